@@ -0,0 +1,11 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto");
+
+    // protox compiles the protos in Rust, so building needs no protoc
+    let file_descriptors = protox::compile(["proto/internal.proto"], ["proto"])?;
+    tonic_build::configure()
+        .build_client(false)
+        .compile_fds(file_descriptors)?;
+
+    Ok(())
+}
@@ -0,0 +1,10 @@
+-- Migration: admin_stats
+-- Description: Admin flag on users and indexes backing the operations dashboard
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS idx_users_created_at ON users(created_at);
+CREATE INDEX IF NOT EXISTS idx_users_last_seen ON users(last_seen_at) WHERE last_seen_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_devices_last_active ON devices(last_active_at);
+CREATE INDEX IF NOT EXISTS idx_messages_created_at ON messages(created_at);
+CREATE INDEX IF NOT EXISTS idx_user_sticker_packs_created_at ON user_sticker_packs(created_at);
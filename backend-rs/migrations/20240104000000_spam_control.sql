@@ -0,0 +1,19 @@
+-- Migration: spam_control
+-- Description: Shadow-limited messages and moderator alerts raised by spam/flood control
+
+-- Messages from shadow-limited senders are stored but only visible to the sender
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS is_shadowed BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS moderation_alerts (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    conversation_id UUID REFERENCES conversations(id) ON DELETE SET NULL,
+    reason VARCHAR(50) NOT NULL,
+    details TEXT,
+    resolved_at TIMESTAMP WITH TIME ZONE,
+    resolved_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_moderation_alerts_open ON moderation_alerts(created_at DESC) WHERE resolved_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_moderation_alerts_user ON moderation_alerts(user_id);
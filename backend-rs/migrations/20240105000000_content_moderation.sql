@@ -0,0 +1,9 @@
+-- Migration: content_moderation
+-- Description: Known-bad content hashes for the moderation pipeline's blocklist matcher
+
+CREATE TABLE IF NOT EXISTS blocked_content_hashes (
+    hash VARCHAR(64) PRIMARY KEY, -- hex SHA-256
+    label VARCHAR(100) NOT NULL,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
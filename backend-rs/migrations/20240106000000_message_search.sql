@@ -0,0 +1,12 @@
+-- Migration: message_search
+-- Description: Full-text index of plaintext messages for in-conversation search
+
+CREATE TABLE IF NOT EXISTS message_search_index (
+    message_id UUID PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+    conversation_id UUID NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    document TSVECTOR NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_search_document ON message_search_index USING GIN(document);
+CREATE INDEX IF NOT EXISTS idx_message_search_conversation ON message_search_index(conversation_id, created_at DESC);
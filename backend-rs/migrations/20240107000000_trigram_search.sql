@@ -0,0 +1,11 @@
+-- Migration: trigram_search
+-- Description: pg_trgm indexes so user and sticker pack search can use indexes and rank by similarity
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS idx_users_username_trgm ON users USING GIN(LOWER(username) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_users_display_name_trgm ON users USING GIN(LOWER(display_name) gin_trgm_ops);
+
+CREATE INDEX IF NOT EXISTS idx_sticker_packs_name_trgm ON sticker_packs USING GIN(LOWER(name) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_sticker_packs_author_trgm ON sticker_packs USING GIN(LOWER(author) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_sticker_packs_description_trgm ON sticker_packs USING GIN(LOWER(COALESCE(description, '')) gin_trgm_ops);
@@ -0,0 +1,12 @@
+-- Migration: conversation_appearance
+-- Description: Per-participant conversation wallpaper, theme color, and quick reaction emoji
+
+CREATE TABLE IF NOT EXISTS conversation_appearance (
+    conversation_id UUID NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    wallpaper_url TEXT,
+    theme_color VARCHAR(7),
+    reaction_emoji VARCHAR(32),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (conversation_id, user_id)
+);
@@ -0,0 +1,5 @@
+-- Migration: group_metadata
+-- Description: Editable description and rules for group conversations
+
+ALTER TABLE conversations ADD COLUMN IF NOT EXISTS description TEXT;
+ALTER TABLE conversations ADD COLUMN IF NOT EXISTS rules TEXT;
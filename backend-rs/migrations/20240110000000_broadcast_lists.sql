@@ -0,0 +1,37 @@
+-- Migration: broadcast_lists
+-- Description: Broadcast lists that fan a message out as separate direct messages
+
+CREATE TABLE IF NOT EXISTS broadcast_lists (
+    id UUID PRIMARY KEY,
+    owner_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_broadcast_lists_owner ON broadcast_lists(owner_id);
+
+CREATE TABLE IF NOT EXISTS broadcast_list_recipients (
+    list_id UUID NOT NULL REFERENCES broadcast_lists(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    added_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (list_id, user_id)
+);
+
+CREATE TABLE IF NOT EXISTS broadcasts (
+    id UUID PRIMARY KEY,
+    list_id UUID NOT NULL REFERENCES broadcast_lists(id) ON DELETE CASCADE,
+    sender_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    type message_type NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_broadcasts_list ON broadcasts(list_id, created_at DESC);
+
+-- One row per recipient; message_id is NULL when the recipient was skipped
+CREATE TABLE IF NOT EXISTS broadcast_messages (
+    broadcast_id UUID NOT NULL REFERENCES broadcasts(id) ON DELETE CASCADE,
+    recipient_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message_id UUID REFERENCES messages(id) ON DELETE SET NULL,
+    PRIMARY KEY (broadcast_id, recipient_id)
+);
@@ -0,0 +1,5 @@
+-- Migration: saved_messages_type
+-- Description: Conversation type for per-user "Saved Messages" notes-to-self
+-- (kept separate because a new enum value cannot be used in the transaction that adds it)
+
+ALTER TYPE conversation_type ADD VALUE IF NOT EXISTS 'saved';
@@ -0,0 +1,5 @@
+-- Migration: saved_messages
+-- Description: At most one Saved Messages conversation per user
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_conversations_saved_owner
+    ON conversations(created_by) WHERE type = 'saved';
@@ -0,0 +1,24 @@
+-- Migration: message_translation
+-- Description: Cached message translations and per-participant auto-translate settings
+
+CREATE TABLE IF NOT EXISTS message_translations (
+    message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    language VARCHAR(16) NOT NULL,
+    text TEXT NOT NULL,
+    source_language VARCHAR(16),
+    provider VARCHAR(32) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (message_id, language)
+);
+
+CREATE TABLE IF NOT EXISTS conversation_translation_settings (
+    conversation_id UUID NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    auto_translate BOOLEAN NOT NULL DEFAULT false,
+    target_language VARCHAR(16),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (conversation_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_translation_settings_auto
+    ON conversation_translation_settings(conversation_id) WHERE auto_translate;
@@ -0,0 +1,7 @@
+-- Migration: message_requests
+-- Description: Direct conversations started by non-contacts wait as message requests
+
+ALTER TABLE participants ADD COLUMN IF NOT EXISTS is_request BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX IF NOT EXISTS idx_participants_requests
+    ON participants(user_id) WHERE is_request AND left_at IS NULL;
@@ -0,0 +1,30 @@
+-- Migration: quiet_hours
+-- Description: Per-user do-not-disturb schedules and pushes held back during them
+
+CREATE TABLE IF NOT EXISTS notification_schedules (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    enabled BOOLEAN NOT NULL DEFAULT false,
+    timezone VARCHAR(64) NOT NULL DEFAULT 'UTC',
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- weekday is days from Monday (0-6); a window ending before it starts runs past midnight
+CREATE TABLE IF NOT EXISTS quiet_hours_windows (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    weekday SMALLINT NOT NULL CHECK (weekday BETWEEN 0 AND 6),
+    start_time TIME NOT NULL,
+    end_time TIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_quiet_hours_windows_user ON quiet_hours_windows(user_id);
+
+CREATE TABLE IF NOT EXISTS queued_notifications (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_queued_notifications_user ON queued_notifications(user_id);
@@ -0,0 +1,9 @@
+-- Migration: device_verification
+-- Description: Devices verified by the user's primary device, and an opt-in to only serve their keys
+
+ALTER TABLE devices ADD COLUMN IF NOT EXISTS verified BOOLEAN NOT NULL DEFAULT false;
+
+-- The device an account was registered on is the primary device and trusted from the start
+UPDATE devices SET verified = true WHERE device_id = 1;
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS verified_devices_only BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,17 @@
+-- Migration: username_unique
+-- Description: Usernames are unique regardless of case
+
+-- Usernames used to be unique only as typed. Where several differ just in
+-- case, the oldest account keeps its name and the others get a suffix from
+-- their ID, staying within 32 characters
+UPDATE users u SET username = LEFT(u.username, 23) || '_' || LEFT(REPLACE(u.id::TEXT, '-', ''), 8)
+FROM (
+    SELECT id, ROW_NUMBER() OVER (
+        PARTITION BY LOWER(username)
+        ORDER BY created_at, id
+    ) AS rank
+    FROM users
+) ranked
+WHERE u.id = ranked.id AND ranked.rank > 1;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_username_lower ON users(LOWER(username));
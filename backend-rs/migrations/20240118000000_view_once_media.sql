@@ -0,0 +1,16 @@
+-- Migration: view_once_media
+-- Description: Image/video messages each recipient can open once
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS view_once BOOLEAN NOT NULL DEFAULT false;
+-- Set when the media was wiped, after every recipient viewed it or it expired
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS media_deleted_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX IF NOT EXISTS idx_messages_view_once_pending ON messages(created_at)
+    WHERE view_once AND media_deleted_at IS NULL;
+
+CREATE TABLE IF NOT EXISTS view_once_views (
+    message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    viewed_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (message_id, user_id)
+);
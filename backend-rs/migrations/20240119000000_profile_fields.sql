@@ -0,0 +1,11 @@
+-- Migration: profile_fields
+-- Description: Emoji status with optional expiry, pronouns, and profile links
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS emoji_status VARCHAR(32);
+-- Cleared by the server once passed; NULL keeps the status until changed
+ALTER TABLE users ADD COLUMN IF NOT EXISTS emoji_status_expires_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS pronouns VARCHAR(32);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS links TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX IF NOT EXISTS idx_users_emoji_status_expires ON users(emoji_status_expires_at)
+    WHERE emoji_status_expires_at IS NOT NULL;
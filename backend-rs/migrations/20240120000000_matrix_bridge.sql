@@ -0,0 +1,22 @@
+-- Migration: matrix_bridge
+-- Description: Conversation/room mapping and Matrix users for the optional Matrix bridge
+
+CREATE TABLE IF NOT EXISTS matrix_rooms (
+    conversation_id UUID PRIMARY KEY REFERENCES conversations(id) ON DELETE CASCADE,
+    room_id TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- Rooms each local user's virtual Matrix user has joined
+CREATE TABLE IF NOT EXISTS matrix_puppet_rooms (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    room_id TEXT NOT NULL REFERENCES matrix_rooms(room_id) ON DELETE CASCADE,
+    joined_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (user_id, room_id)
+);
+
+-- Remote Matrix users are represented by local users without a phone or email
+ALTER TABLE users ADD COLUMN IF NOT EXISTS matrix_user_id TEXT UNIQUE;
+ALTER TABLE users DROP CONSTRAINT IF EXISTS phone_or_email;
+ALTER TABLE users ADD CONSTRAINT phone_or_email
+    CHECK (phone IS NOT NULL OR email IS NOT NULL OR matrix_user_id IS NOT NULL);
@@ -0,0 +1,15 @@
+-- Migration: operator_cli
+-- Description: Account bans and rotating JWT signing keys, managed with the `cli` binary
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS banned_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS ban_reason TEXT;
+
+-- Tokens carry the `kid` of the key that signed them. The newest unretired key
+-- signs; retired keys keep verifying until every token they signed has expired.
+-- With no rows, tokens are signed with JWT_SECRET and carry no `kid`.
+CREATE TABLE IF NOT EXISTS jwt_signing_keys (
+    kid TEXT PRIMARY KEY,
+    secret TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    retired_at TIMESTAMP WITH TIME ZONE
+);
@@ -0,0 +1,17 @@
+-- Migration: message_attachments
+-- Description: Uploaded message media, linked from messages and served through signed URLs
+
+CREATE TABLE IF NOT EXISTS attachments (
+    id UUID PRIMARY KEY,
+    uploader_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    storage_key VARCHAR(255) NOT NULL,
+    content_type VARCHAR(255) NOT NULL,
+    size BIGINT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_attachments_uploader ON attachments(uploader_id);
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS attachment_id UUID REFERENCES attachments(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_messages_attachment ON messages(attachment_id) WHERE attachment_id IS NOT NULL;
@@ -0,0 +1,19 @@
+-- Migration: message_deliveries
+-- Description: Per-device delivery queue with acknowledgements, WebSocket retries and push fallback
+
+CREATE TABLE IF NOT EXISTS message_deliveries (
+    message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id INTEGER NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    pushed_at TIMESTAMP WITH TIME ZONE,
+    acked_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (message_id, user_id, device_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_deliveries_due
+    ON message_deliveries(next_attempt_at) WHERE acked_at IS NULL AND pushed_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_message_deliveries_device
+    ON message_deliveries(user_id, device_id) WHERE acked_at IS NULL;
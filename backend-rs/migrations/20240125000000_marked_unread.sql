@@ -0,0 +1,4 @@
+-- Migration: marked_unread
+-- Description: Per-participant flag for conversations manually marked unread
+
+ALTER TABLE participants ADD COLUMN IF NOT EXISTS marked_unread BOOLEAN NOT NULL DEFAULT false;
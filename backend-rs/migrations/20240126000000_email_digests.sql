@@ -0,0 +1,9 @@
+-- Migration: email_digests
+-- Description: Opt-in unread digest emails for inactive users, with unsubscribe tokens
+
+CREATE TABLE IF NOT EXISTS email_digests (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    unsubscribe_token VARCHAR(64) NOT NULL UNIQUE,
+    last_sent_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
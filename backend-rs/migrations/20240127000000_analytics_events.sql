@@ -0,0 +1,13 @@
+-- Migration: analytics_events
+-- Description: Append-only log of privacy-filtered domain events for analytics export
+
+CREATE TABLE IF NOT EXISTS analytics_events (
+    id UUID PRIMARY KEY,
+    event_type VARCHAR(50) NOT NULL,
+    -- Pseudonymous user ID; never joins back to users without the key
+    actor VARCHAR(64),
+    properties JSONB NOT NULL DEFAULT '{}',
+    occurred_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT clock_timestamp()
+);
+
+CREATE INDEX IF NOT EXISTS idx_analytics_events_occurred ON analytics_events(occurred_at, id);
@@ -0,0 +1,11 @@
+-- Migration: conversation_encryption
+-- Description: Per-conversation encryption mode, fixed at creation
+
+DO $$ BEGIN
+    CREATE TYPE conversation_encryption AS ENUM ('signal', 'none');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+ALTER TABLE conversations
+    ADD COLUMN IF NOT EXISTS encryption conversation_encryption NOT NULL DEFAULT 'none';
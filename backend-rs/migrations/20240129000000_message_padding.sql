@@ -0,0 +1,4 @@
+-- Migration: message_padding
+-- Description: Marks message content stored padded to a length bucket
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS padded BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,17 @@
+-- Migration: sealed_sender
+-- Description: Unidentified access keys and the queue of sealed-sender envelopes
+
+-- SHA-256 of the key senders present to deliver without identifying themselves
+ALTER TABLE users ADD COLUMN IF NOT EXISTS unidentified_access_key BYTEA;
+
+CREATE TABLE IF NOT EXISTS sealed_envelopes (
+    id UUID PRIMARY KEY,
+    recipient_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id INTEGER NOT NULL,
+    -- Encrypted for the device, sender certificate included; the sender is not stored
+    content BYTEA NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_sealed_envelopes_recipient
+    ON sealed_envelopes(recipient_id, device_id, created_at);
@@ -0,0 +1,5 @@
+-- Migration: group_permissions
+-- Description: Per-group overrides of the lowest role allowed each action
+
+-- Keys left out fall back to the defaults, e.g. {"send_media": "admin"}
+ALTER TABLE conversations ADD COLUMN IF NOT EXISTS permissions JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,4 @@
+-- Migration: announcement_mode
+-- Description: Groups where only owners and admins may post
+
+ALTER TABLE conversations ADD COLUMN IF NOT EXISTS announcement_only BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,16 @@
+-- Migration: conversation_events
+-- Description: Per-group history of administrative changes, kept apart from messages
+
+CREATE TABLE IF NOT EXISTS conversation_events (
+    id UUID PRIMARY KEY,
+    conversation_id UUID NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    -- Kept when the actor's account is deleted
+    actor_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    -- Same key and params as the system message posted for the change
+    key VARCHAR(64) NOT NULL,
+    params JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_conversation_events_conversation
+    ON conversation_events(conversation_id, created_at DESC);
@@ -0,0 +1,15 @@
+-- Migration: contact_joined
+-- Description: Hashed phones/emails of unregistered contacts, to announce when they join
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS contact_joined_notices BOOLEAN NOT NULL DEFAULT true;
+
+CREATE TABLE IF NOT EXISTS contact_interests (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Unsalted SHA-256 of the normalized phone or email; phones are recoverable from it
+    identifier_hash BYTEA NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, identifier_hash)
+);
+
+CREATE INDEX IF NOT EXISTS idx_contact_interests_identifier
+    ON contact_interests(identifier_hash);
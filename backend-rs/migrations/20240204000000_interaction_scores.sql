@@ -0,0 +1,6 @@
+-- Migration: interaction_scores
+-- Description: Per-participant decayed count of messages sent, ranking quick conversation targets
+
+ALTER TABLE participants ADD COLUMN IF NOT EXISTS interaction_score DOUBLE PRECISION NOT NULL DEFAULT 0;
+-- When the score was last bumped; it halves every half-life since
+ALTER TABLE participants ADD COLUMN IF NOT EXISTS interacted_at TIMESTAMP WITH TIME ZONE;
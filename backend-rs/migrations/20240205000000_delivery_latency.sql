@@ -0,0 +1,12 @@
+-- Migration: delivery_latency
+-- Description: Sampled per-message timings from storage through fanout to the first device ack
+
+CREATE TABLE IF NOT EXISTS delivery_latency_samples (
+    message_id UUID PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+    received_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    fanout_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    first_ack_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS idx_delivery_latency_samples_received
+    ON delivery_latency_samples(received_at);
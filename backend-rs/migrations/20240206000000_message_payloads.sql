@@ -0,0 +1,11 @@
+-- Migration: message_payloads
+-- Description: Media captions and whether message content is ciphertext, for typed message payloads
+
+ALTER TABLE messages
+    ADD COLUMN IF NOT EXISTS caption TEXT,
+    ADD COLUMN IF NOT EXISTS encrypted BOOLEAN NOT NULL DEFAULT false;
+
+-- Everything but system messages in end-to-end encrypted conversations
+UPDATE messages m SET encrypted = true
+FROM conversations c
+WHERE c.id = m.conversation_id AND c.encryption = 'signal' AND m.type <> 'system';
@@ -0,0 +1,24 @@
+-- Migration: direct_key
+-- Description: One direct conversation per pair of users, keyed by the sorted pair
+
+ALTER TABLE conversations ADD COLUMN IF NOT EXISTS direct_key TEXT;
+
+-- Where a pair already has several, the oldest keeps the key; the others
+-- stay readable but are no longer returned for the pair
+UPDATE conversations c SET direct_key = pairs.direct_key
+FROM (
+    SELECT DISTINCT ON (direct_key) conversation_id, direct_key
+    FROM (
+        SELECT c.id AS conversation_id, c.created_at,
+            LEAST(p1.user_id, p2.user_id)::TEXT || ':' || GREATEST(p1.user_id, p2.user_id)::TEXT
+                AS direct_key
+        FROM conversations c
+        JOIN participants p1 ON p1.conversation_id = c.id
+        JOIN participants p2 ON p2.conversation_id = c.id AND p2.user_id > p1.user_id
+        WHERE c.type = 'direct'
+    ) keyed
+    ORDER BY direct_key, created_at, conversation_id
+) pairs
+WHERE c.id = pairs.conversation_id AND c.direct_key IS NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_conversations_direct_key ON conversations(direct_key);
@@ -0,0 +1,4 @@
+-- Migration: cleared_history
+-- Description: Per-participant history clearing; messages before the watermark are hidden
+
+ALTER TABLE participants ADD COLUMN IF NOT EXISTS cleared_before TIMESTAMPTZ;
@@ -0,0 +1,23 @@
+-- Migration: sticker_analytics
+-- Description: Daily sticker send counts, and the user who created each pack
+
+ALTER TABLE sticker_packs ADD COLUMN IF NOT EXISTS creator_id UUID REFERENCES users(id) ON DELETE SET NULL;
+
+CREATE TABLE IF NOT EXISTS sticker_sends_daily (
+    sticker_id UUID NOT NULL REFERENCES stickers(id) ON DELETE CASCADE,
+    pack_id UUID NOT NULL REFERENCES sticker_packs(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    sends BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (sticker_id, day)
+);
+
+CREATE INDEX IF NOT EXISTS idx_sticker_sends_daily_pack ON sticker_sends_daily(pack_id, day);
+
+-- Count the sticker messages already sent
+INSERT INTO sticker_sends_daily (sticker_id, pack_id, day, sends)
+SELECT m.sticker_id, s.pack_id, (m.created_at AT TIME ZONE 'UTC')::date, COUNT(*)
+FROM messages m
+JOIN stickers s ON s.id = m.sticker_id
+WHERE m.type = 'sticker' AND NOT m.is_shadowed
+GROUP BY m.sticker_id, s.pack_id, (m.created_at AT TIME ZONE 'UTC')::date
+ON CONFLICT (sticker_id, day) DO NOTHING;
@@ -0,0 +1,12 @@
+-- Migration: sticker_curation
+-- Description: Featured packs, tags and a trending score for the sticker catalog
+
+ALTER TABLE sticker_packs ADD COLUMN IF NOT EXISTS featured BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE sticker_packs ADD COLUMN IF NOT EXISTS featured_order INTEGER;
+ALTER TABLE sticker_packs ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE sticker_packs ADD COLUMN IF NOT EXISTS trending_score DOUBLE PRECISION NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS idx_sticker_packs_featured ON sticker_packs(featured_order) WHERE featured;
+CREATE INDEX IF NOT EXISTS idx_sticker_packs_trending ON sticker_packs(trending_score DESC) WHERE trending_score > 0;
+CREATE INDEX IF NOT EXISTS idx_sticker_packs_tags ON sticker_packs USING GIN(tags);
+CREATE INDEX IF NOT EXISTS idx_sticker_packs_created_at ON sticker_packs(created_at DESC);
@@ -0,0 +1,4 @@
+-- Migration: sticker_previews
+-- Description: Static preview frames of animated stickers
+
+ALTER TABLE stickers ADD COLUMN IF NOT EXISTS preview_url TEXT;
@@ -0,0 +1,17 @@
+-- Migration: custom_emoji
+-- Description: Custom emoji available everywhere or in one group
+
+CREATE TABLE IF NOT EXISTS custom_emoji (
+    id UUID PRIMARY KEY,
+    -- NULL for emoji available in every conversation
+    conversation_id UUID REFERENCES conversations(id) ON DELETE CASCADE,
+    name VARCHAR(32) NOT NULL,
+    image_url TEXT NOT NULL,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_custom_emoji_global_name
+    ON custom_emoji(name) WHERE conversation_id IS NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_custom_emoji_conversation_name
+    ON custom_emoji(conversation_id, name) WHERE conversation_id IS NOT NULL;
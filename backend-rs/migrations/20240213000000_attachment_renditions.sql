@@ -0,0 +1,31 @@
+-- Migration: attachment_renditions
+-- Description: Transcoded MP4/WebM renditions and poster frames of GIF and video attachments
+
+DO $$ BEGIN
+    CREATE TYPE transcode_status AS ENUM ('pending', 'ready', 'failed');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE rendition_kind AS ENUM ('mp4', 'webm', 'poster');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+ALTER TABLE attachments ADD COLUMN IF NOT EXISTS transcode_status transcode_status;
+ALTER TABLE attachments ADD COLUMN IF NOT EXISTS transcode_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE attachments ADD COLUMN IF NOT EXISTS transcode_started_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_attachments_transcode_pending
+    ON attachments(created_at) WHERE transcode_status = 'pending';
+
+CREATE TABLE IF NOT EXISTS attachment_renditions (
+    attachment_id UUID NOT NULL REFERENCES attachments(id) ON DELETE CASCADE,
+    kind rendition_kind NOT NULL,
+    storage_key VARCHAR(255) NOT NULL,
+    content_type VARCHAR(255) NOT NULL,
+    size BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (attachment_id, kind)
+);
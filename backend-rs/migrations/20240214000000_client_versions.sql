@@ -0,0 +1,15 @@
+-- Migration: client_versions
+-- Description: App versions devices run and the oldest each platform supports
+
+ALTER TABLE devices ADD COLUMN IF NOT EXISTS app_version VARCHAR(32);
+
+CREATE TABLE IF NOT EXISTS client_version_requirements (
+    platform VARCHAR(32) PRIMARY KEY,
+    -- Older clients are refused with 426 Upgrade Required
+    min_version VARCHAR(32) NOT NULL,
+    latest_version VARCHAR(32),
+    upgrade_url TEXT,
+    message TEXT,
+    updated_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
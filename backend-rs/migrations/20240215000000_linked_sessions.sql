@@ -0,0 +1,5 @@
+-- Migration: linked_sessions
+-- Description: Sessions linked from a signed-in device with restricted scopes
+
+-- NULL for full access; otherwise the scopes the link was approved with
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS scopes TEXT[];
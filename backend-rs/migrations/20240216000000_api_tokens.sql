@@ -0,0 +1,22 @@
+-- Migration: api_tokens
+-- Description: Personal access tokens with named scopes for integrations
+
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    -- SHA-256 of the token; the token itself is only shown once
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    -- Leading characters, so users can tell their tokens apart
+    token_prefix VARCHAR(16) NOT NULL,
+    scopes TEXT[] NOT NULL,
+    -- Requests per minute
+    rate_limit INTEGER NOT NULL,
+    last_used_at TIMESTAMPTZ,
+    last_used_ip VARCHAR(45),
+    expires_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_tokens_user ON api_tokens(user_id, created_at DESC);
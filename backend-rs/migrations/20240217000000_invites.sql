@@ -0,0 +1,22 @@
+-- Migration: invites
+-- Description: Invite codes, per-user invite quotas and referral tracking
+
+CREATE TABLE IF NOT EXISTS invites (
+    id UUID PRIMARY KEY,
+    code VARCHAR(16) NOT NULL UNIQUE,
+    created_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    max_uses INTEGER NOT NULL DEFAULT 1,
+    uses INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_invites_created_by ON invites(created_by, created_at DESC);
+
+-- NULL uses INVITE_QUOTA
+ALTER TABLE users ADD COLUMN IF NOT EXISTS invite_quota INTEGER;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS invited_by UUID REFERENCES users(id) ON DELETE SET NULL;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS invite_id UUID REFERENCES invites(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_users_invited_by ON users(invited_by) WHERE invited_by IS NOT NULL;
@@ -0,0 +1,24 @@
+-- Migration: waitlist
+-- Description: Verified phones and emails waiting for admin approval to sign up
+
+DO $$ BEGIN
+    CREATE TYPE waitlist_status AS ENUM ('pending', 'approved', 'rejected', 'registered');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE IF NOT EXISTS waitlist (
+    id UUID PRIMARY KEY,
+    target VARCHAR(255) NOT NULL,
+    target_type otp_type NOT NULL,
+    status waitlist_status NOT NULL DEFAULT 'pending',
+    ip_address VARCHAR(45),
+    country VARCHAR(2),
+    reviewed_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    reviewed_at TIMESTAMPTZ,
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (target, target_type)
+);
+
+CREATE INDEX IF NOT EXISTS idx_waitlist_status ON waitlist(status, created_at);
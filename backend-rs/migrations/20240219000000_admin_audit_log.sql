@@ -0,0 +1,20 @@
+-- Migration: admin_audit_log
+-- Description: Record of admin-route requests that could change something
+
+CREATE TABLE IF NOT EXISTS admin_audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    actor_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    method VARCHAR(10) NOT NULL,
+    route VARCHAR(255) NOT NULL,
+    path TEXT NOT NULL,
+    -- Query and JSON body, with secrets redacted
+    payload JSONB NOT NULL DEFAULT '{}',
+    status SMALLINT NOT NULL,
+    error TEXT,
+    ip_address VARCHAR(45),
+    duration_ms INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_admin_audit_log_created_at ON admin_audit_log(created_at);
+CREATE INDEX IF NOT EXISTS idx_admin_audit_log_actor ON admin_audit_log(actor_id, id DESC);
@@ -0,0 +1,4 @@
+-- Migration: account_deactivation
+-- Description: Accounts their owners deactivated, hidden until their next login
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deactivated_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,13 @@
+-- Migration: sync_data
+-- Description: Settings kept only for restoring them on a new install, with when they last changed
+
+CREATE TABLE IF NOT EXISTS user_sync_data (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- Layout version of the stored data
+    version SMALLINT NOT NULL,
+    -- Chat folders: [{name, conversation_ids}]
+    folders JSONB NOT NULL DEFAULT '[]',
+    -- When the settings changed on the device that sent them
+    updated_at TIMESTAMPTZ NOT NULL,
+    updated_by_device INTEGER
+);
@@ -0,0 +1,36 @@
+-- Migration: message_reports
+-- Description: Franking tags on encrypted messages, and recipients' reports of abusive messages
+
+-- The sender's commitment to the plaintext and the server's signature over it
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS franking_tag BYTEA;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS franking_signature BYTEA;
+
+DO $$ BEGIN
+    CREATE TYPE report_status AS ENUM ('open', 'actioned', 'dismissed');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+-- Evidence is copied from the message, and the IDs the signature covers kept,
+-- so a report can still be verified after the message is deleted
+CREATE TABLE IF NOT EXISTS message_reports (
+    id UUID PRIMARY KEY,
+    message_id UUID NOT NULL,
+    conversation_id UUID NOT NULL,
+    reporter_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    sender_id UUID NOT NULL,
+    reason VARCHAR(20) NOT NULL,
+    details TEXT,
+    -- What the reporter disclosed, or the server's copy of an unencrypted message
+    content BYTEA,
+    franking_key BYTEA,
+    franking_tag BYTEA,
+    franking_signature BYTEA,
+    status report_status NOT NULL DEFAULT 'open',
+    resolved_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    resolved_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (message_id, reporter_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_reports_status ON message_reports(status, created_at DESC);
@@ -0,0 +1,53 @@
+-- Migration: participant_counts
+-- Description: Current member and admin counts kept on conversations, so listings skip COUNT queries
+
+ALTER TABLE conversations ADD COLUMN IF NOT EXISTS participant_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE conversations ADD COLUMN IF NOT EXISTS admin_count INTEGER NOT NULL DEFAULT 0;
+
+-- Owners count as admins; members who left count as neither
+CREATE OR REPLACE FUNCTION update_participant_counts()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP <> 'INSERT' AND OLD.left_at IS NULL THEN
+        UPDATE conversations
+        SET participant_count = participant_count - 1,
+            admin_count = admin_count - (OLD.role <> 'member')::INTEGER
+        WHERE id = OLD.conversation_id;
+    END IF;
+    IF TG_OP <> 'DELETE' AND NEW.left_at IS NULL THEN
+        UPDATE conversations
+        SET participant_count = participant_count + 1,
+            admin_count = admin_count + (NEW.role <> 'member')::INTEGER
+        WHERE id = NEW.conversation_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS update_participant_counts_on_change ON participants;
+CREATE TRIGGER update_participant_counts_on_change AFTER INSERT OR DELETE ON participants
+    FOR EACH ROW EXECUTE FUNCTION update_participant_counts();
+
+DROP TRIGGER IF EXISTS update_participant_counts_on_update ON participants;
+CREATE TRIGGER update_participant_counts_on_update
+    AFTER UPDATE OF conversation_id, role, left_at ON participants
+    FOR EACH ROW
+    WHEN (OLD.conversation_id IS DISTINCT FROM NEW.conversation_id
+        OR OLD.role IS DISTINCT FROM NEW.role
+        OR (OLD.left_at IS NULL) <> (NEW.left_at IS NULL))
+    EXECUTE FUNCTION update_participant_counts();
+
+-- Backfill without touching updated_at
+ALTER TABLE conversations DISABLE TRIGGER update_conversations_updated_at;
+
+UPDATE conversations c
+SET participant_count = counts.members, admin_count = counts.admins
+FROM (
+    SELECT conversation_id, COUNT(*) AS members, COUNT(*) FILTER (WHERE role <> 'member') AS admins
+    FROM participants
+    WHERE left_at IS NULL
+    GROUP BY conversation_id
+) counts
+WHERE c.id = counts.conversation_id;
+
+ALTER TABLE conversations ENABLE TRIGGER update_conversations_updated_at;
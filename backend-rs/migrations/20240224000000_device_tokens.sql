@@ -0,0 +1,8 @@
+-- Migration: device_tokens
+-- Description: Logins pick an existing device by a server-issued token instead of its name and platform
+
+-- SHA-256 of the token handed to the device when it was created. Devices from
+-- before this have none, so their next login creates a new device.
+ALTER TABLE devices ADD COLUMN IF NOT EXISTS token_hash TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_devices_token_hash ON devices(token_hash);
@@ -0,0 +1,68 @@
+use std::fmt::Display;
+
+use axum::{
+    http::{
+        header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Build a weak ETag from the values that determine a response, typically
+/// IDs and `updated_at` timestamps
+pub fn etag_from<I, T>(parts: I) -> String
+where
+    I: IntoIterator<Item = T>,
+    T: Display,
+{
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.to_string().as_bytes());
+        hasher.update(b"\0");
+    }
+    let digest = format!("{:x}", hasher.finalize());
+
+    format!("W/\"{}\"", &digest[..32])
+}
+
+/// Whether an `If-None-Match` header already covers `etag`
+fn is_fresh(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(value) = headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+
+    value
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+/// Respond with `body` as JSON, or `304 Not Modified` when the client's copy
+/// is current. Both carry the ETag and `Cache-Control: public, max-age=...`.
+pub fn cached_json<T: Serialize>(
+    headers: &HeaderMap,
+    etag: &str,
+    max_age: u64,
+    body: T,
+) -> Response {
+    let mut response = if is_fresh(headers, etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        Json(body).into_response()
+    };
+
+    let response_headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        response_headers.insert(ETAG, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&format!("public, max-age={}", max_age)) {
+        response_headers.insert(CACHE_CONTROL, value);
+    }
+
+    response
+}
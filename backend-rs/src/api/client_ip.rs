@@ -0,0 +1,108 @@
+use std::net::{IpAddr, SocketAddr};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+use ipnet::IpNet;
+
+use crate::{error::AppError, AppState};
+
+/// The client's address. Behind proxies listed in `TRUSTED_PROXIES` it is
+/// taken from `Forwarded` or `X-Forwarded-For`: the hops are walked from the
+/// nearest, and the first one not sent by a trusted proxy is the client.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        // The extractor also accepts `MockConnectInfo` in tests
+        let ConnectInfo(peer) = ConnectInfo::<SocketAddr>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| anyhow::anyhow!("Missing peer address"))?;
+
+        Ok(ClientIp(resolve_client_ip(
+            peer.ip(),
+            &parts.headers,
+            &state.config.server.trusted_proxies,
+        )))
+    }
+}
+
+pub fn resolve_client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(&canonical(*ip)));
+    if !is_trusted(&peer) {
+        return peer;
+    }
+
+    // `Forwarded` wins when a proxy sends both
+    let hops = forwarded_hops(headers).unwrap_or_else(|| x_forwarded_for_hops(headers));
+
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        if !is_trusted(&client) {
+            break;
+        }
+        match hop {
+            Some(ip) => client = ip,
+            // An obfuscated or garbled hop ends the chain we can vouch for
+            None => break,
+        }
+    }
+
+    client
+}
+
+/// IPv4-mapped IPv6 addresses compare as IPv4
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+/// `for=` of each element of RFC 7239 `Forwarded` headers, nearest last
+fn forwarded_hops(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let mut hops = Vec::new();
+    for value in headers.get_all("forwarded") {
+        let value = value.to_str().ok()?;
+        for element in value.split(',') {
+            let node = element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for").then_some(value)
+            });
+            hops.push(node.and_then(parse_node));
+        }
+    }
+
+    (!hops.is_empty()).then_some(hops)
+}
+
+fn x_forwarded_for_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(parse_node)
+        .collect()
+}
+
+/// `192.0.2.1`, `192.0.2.1:4711`, `[2001:db8::1]:4711` or `2001:db8::1`,
+/// optionally quoted
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| {
+            node.trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+        })
+        .ok()
+        .map(canonical)
+}
@@ -0,0 +1,524 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{
+        AdminAuditEntry, AdminStats, AnalyticsEvent, Announcement, BackupManifest,
+        DeliveryLatencyReport, MaintenanceState, MessageReport, MigrationStatus, ModerationAlert,
+        ReportStatus, ReportVerification, SlowRouteStats, VelocityCounter, VelocityKind,
+        WebSocketClientStats,
+    },
+    services::{
+        admin::AdminService, audit::AuditLog, auth::Claims, backups::BackupService, delivery,
+        events::EventLog, migrations::MigrationService,
+        moderation::ModerationService, rate_limit::RegistrationVelocity, reports::ReportService,
+        search::SearchService,
+    },
+    AppState,
+};
+
+use super::super::middleware::get_user_id;
+
+#[derive(Debug, Serialize)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    #[serde(default = "default_days")]
+    pub days: i64,
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+fn default_days() -> i64 {
+    30
+}
+
+pub async fn get_stats(
+    State(state): State<AppState>,
+    Query(query): Query<StatsQuery>,
+) -> AppResult<Json<AdminStats>> {
+    let days = query.days.clamp(1, 90);
+    let websocket = state.ws_hub.stats().await;
+
+    let admin_service = AdminService::new(
+        state.db,
+        state.redis,
+        state.storage,
+        (*state.config).clone(),
+    );
+    let stats = admin_service
+        .get_stats(days, query.refresh, websocket)
+        .await?;
+
+    Ok(Json(stats))
+}
+
+/// Applied, pending and drifted migrations
+pub async fn get_migrations(State(state): State<AppState>) -> AppResult<Json<MigrationStatus>> {
+    Ok(Json(MigrationService::new(state.db).status().await?))
+}
+
+/// Slow statements and requests per route on this instance, worst first
+pub async fn get_slow_routes(
+    State(state): State<AppState>,
+) -> AppResult<Json<Vec<SlowRouteStats>>> {
+    Ok(Json(state.slow_log.snapshot()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LatencyQuery {
+    #[serde(default = "default_latency_hours")]
+    pub hours: u64,
+}
+
+fn default_latency_hours() -> u64 {
+    24
+}
+
+/// Delivery latency percentiles of sampled messages, across all instances
+pub async fn get_delivery_latency(
+    State(state): State<AppState>,
+    Query(query): Query<LatencyQuery>,
+) -> AppResult<Json<DeliveryLatencyReport>> {
+    let window = std::time::Duration::from_secs(query.hours.clamp(1, 168) * 3600);
+    let report = delivery::latency_report(&state.db, window).await?;
+
+    Ok(Json(report))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub actor_id: Option<Uuid>,
+    /// Route prefix, e.g. `/api/v1/admin/moderation`
+    pub route: Option<String>,
+    /// Page back from an entry's `id`
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Admin actions, newest first
+pub async fn get_audit_log(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> AppResult<Json<Vec<AdminAuditEntry>>> {
+    let entries = AuditLog::new(state.db)
+        .list(
+            query.actor_id,
+            query.route.as_deref(),
+            query.before,
+            query.limit.unwrap_or(100),
+        )
+        .await?;
+
+    Ok(Json(entries))
+}
+
+/// Outbound queue metrics for this instance's WebSocket clients, most lagged first
+pub async fn get_websocket_clients(
+    State(state): State<AppState>,
+) -> AppResult<Json<Vec<WebSocketClientStats>>> {
+    Ok(Json(state.ws_hub.client_stats().await))
+}
+
+pub async fn get_maintenance(State(state): State<AppState>) -> AppResult<Json<MaintenanceState>> {
+    let admin_service = AdminService::new(
+        state.db,
+        state.redis,
+        state.storage,
+        (*state.config).clone(),
+    );
+    let maintenance = admin_service.get_maintenance().await?;
+
+    Ok(Json(maintenance))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MaintenanceRequest {
+    pub enabled: bool,
+    pub message: Option<String>,
+    #[serde(default = "default_retry_after")]
+    pub retry_after: u64,
+}
+
+fn default_retry_after() -> u64 {
+    300
+}
+
+pub async fn set_maintenance(
+    State(state): State<AppState>,
+    Json(req): Json<MaintenanceRequest>,
+) -> AppResult<Json<MaintenanceState>> {
+    let admin_service = AdminService::new(
+        state.db,
+        state.redis,
+        state.storage,
+        (*state.config).clone(),
+    );
+    let maintenance = admin_service
+        .set_maintenance(req.enabled, req.message, req.retry_after)
+        .await?;
+
+    Ok(Json(maintenance))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnnouncementRequest {
+    pub title: Option<String>,
+    pub message: String,
+    #[serde(default = "default_level")]
+    pub level: String,
+}
+
+fn default_level() -> String {
+    "info".to_string()
+}
+
+pub async fn broadcast_announcement(
+    State(state): State<AppState>,
+    Json(req): Json<AnnouncementRequest>,
+) -> AppResult<Json<Announcement>> {
+    if req.message.trim().is_empty() {
+        return Err(AppError::BadRequest("Message is required".to_string()));
+    }
+
+    if !matches!(req.level.as_str(), "info" | "warning" | "critical") {
+        return Err(AppError::BadRequest("Invalid announcement level".to_string()));
+    }
+
+    let admin_service = AdminService::new(
+        state.db,
+        state.redis,
+        state.storage,
+        (*state.config).clone(),
+    );
+    let announcement = admin_service
+        .broadcast_announcement(req.title, req.message, req.level)
+        .await?;
+
+    Ok(Json(announcement))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlertsQuery {
+    #[serde(default)]
+    pub include_resolved: bool,
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+    #[serde(default)]
+    pub offset: i32,
+}
+
+fn default_limit() -> i32 {
+    50
+}
+
+pub async fn list_moderation_alerts(
+    State(state): State<AppState>,
+    Query(query): Query<AlertsQuery>,
+) -> AppResult<Json<Vec<ModerationAlert>>> {
+    let moderation_service = ModerationService::new(state.db, state.redis);
+    let alerts = moderation_service
+        .list_alerts(query.include_resolved, query.limit.clamp(1, 200), query.offset.max(0))
+        .await?;
+
+    Ok(Json(alerts))
+}
+
+pub async fn resolve_moderation_alert(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(alert_id): Path<Uuid>,
+) -> AppResult<Json<ModerationAlert>> {
+    let admin_id = get_user_id(&claims)?;
+
+    let moderation_service = ModerationService::new(state.db, state.redis);
+    let alert = moderation_service.resolve_alert(alert_id, admin_id).await?;
+
+    Ok(Json(alert))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportsQuery {
+    pub status: Option<ReportStatus>,
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+    #[serde(default)]
+    pub offset: i32,
+}
+
+pub async fn list_message_reports(
+    State(state): State<AppState>,
+    Query(query): Query<ReportsQuery>,
+) -> AppResult<Json<Vec<MessageReport>>> {
+    let report_service = ReportService::new(state.db, state.redis, &state.config);
+    let reports = report_service
+        .list(query.status, query.limit.clamp(1, 200), query.offset.max(0))
+        .await?;
+
+    Ok(Json(reports))
+}
+
+/// Check that the sender provably sent what was reported
+pub async fn verify_message_report(
+    State(state): State<AppState>,
+    Path(report_id): Path<Uuid>,
+) -> AppResult<Json<ReportVerification>> {
+    let report_service = ReportService::new(state.db, state.redis, &state.config);
+    let verification = report_service.verify(report_id).await?;
+
+    Ok(Json(verification))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResolveReportRequest {
+    /// actioned or dismissed
+    pub status: ReportStatus,
+}
+
+pub async fn resolve_message_report(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(report_id): Path<Uuid>,
+    Json(req): Json<ResolveReportRequest>,
+) -> AppResult<Json<MessageReport>> {
+    let admin_id = get_user_id(&claims)?;
+
+    let report_service = ReportService::new(state.db, state.redis, &state.config);
+    let report = report_service
+        .resolve(report_id, admin_id, req.status)
+        .await?;
+
+    Ok(Json(report))
+}
+
+pub async fn lift_shadow_limit(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> AppResult<Json<MessageResponse>> {
+    let moderation_service = ModerationService::new(state.db, state.redis);
+    moderation_service.lift_shadow_limit(user_id).await?;
+
+    Ok(Json(MessageResponse {
+        message: "Shadow limit lifted".to_string(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BlockedHashRequest {
+    pub hash: String,
+    pub label: String,
+}
+
+pub async fn add_blocked_hash(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<BlockedHashRequest>,
+) -> AppResult<Json<MessageResponse>> {
+    let admin_id = get_user_id(&claims)?;
+
+    let hash = req.hash.trim().to_lowercase();
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AppError::Validation(
+            "Hash must be a hex-encoded SHA-256".to_string(),
+        ));
+    }
+
+    if req.label.trim().is_empty() || req.label.len() > 100 {
+        return Err(AppError::Validation(
+            "Label must be 1-100 characters".to_string(),
+        ));
+    }
+
+    let moderation_service = ModerationService::new(state.db, state.redis);
+    moderation_service
+        .add_blocked_hash(&hash, req.label.trim(), admin_id)
+        .await?;
+
+    Ok(Json(MessageResponse {
+        message: "Hash blocked".to_string(),
+    }))
+}
+
+pub async fn remove_blocked_hash(
+    State(state): State<AppState>,
+    Path(hash): Path<String>,
+) -> AppResult<Json<MessageResponse>> {
+    let moderation_service = ModerationService::new(state.db, state.redis);
+    moderation_service
+        .remove_blocked_hash(&hash.to_lowercase())
+        .await?;
+
+    Ok(Json(MessageResponse {
+        message: "Hash unblocked".to_string(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RebuildSearchRequest {
+    pub conversation_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RebuildSearchResponse {
+    pub indexed: i64,
+}
+
+pub async fn rebuild_search_index(
+    State(state): State<AppState>,
+    Json(req): Json<RebuildSearchRequest>,
+) -> AppResult<Json<RebuildSearchResponse>> {
+    let search_service = SearchService::new(state.db, state.search)?;
+    let indexed = search_service.rebuild(req.conversation_id).await?;
+
+    Ok(Json(RebuildSearchResponse { indexed }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VelocityQuery {
+    pub ip: Option<String>,
+    pub fingerprint: Option<String>,
+}
+
+impl VelocityQuery {
+    fn targets(&self) -> Vec<(VelocityKind, &str)> {
+        [
+            (VelocityKind::Ip, self.ip.as_deref()),
+            (VelocityKind::Device, self.fingerprint.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(kind, value)| Some((kind, value?.trim())))
+        .filter(|(_, value)| !value.is_empty())
+        .collect()
+    }
+}
+
+/// Registration velocity counters for an IP and/or fingerprint, or every live
+/// counter when neither is given
+pub async fn get_registration_velocity(
+    State(state): State<AppState>,
+    Query(query): Query<VelocityQuery>,
+) -> AppResult<Json<Vec<VelocityCounter>>> {
+    let velocity = RegistrationVelocity::new(state.redis);
+    let targets = query.targets();
+    if targets.is_empty() {
+        return Ok(Json(velocity.counters().await?));
+    }
+
+    let mut counters = Vec::new();
+    for (kind, value) in targets {
+        counters.extend(velocity.counter(kind, value).await?);
+    }
+
+    Ok(Json(counters))
+}
+
+pub async fn clear_registration_velocity(
+    State(state): State<AppState>,
+    Query(query): Query<VelocityQuery>,
+) -> AppResult<Json<MessageResponse>> {
+    let targets = query.targets();
+    if targets.is_empty() {
+        return Err(AppError::BadRequest(
+            "ip or fingerprint is required".to_string(),
+        ));
+    }
+
+    let velocity = RegistrationVelocity::new(state.redis);
+    let mut cleared = 0;
+    for (kind, value) in targets {
+        if velocity.clear(kind, value).await? {
+            cleared += 1;
+        }
+    }
+
+    Ok(Json(MessageResponse {
+        message: format!("Cleared {} velocity counter(s)", cleared),
+    }))
+}
+
+fn backup_service(state: &AppState) -> BackupService {
+    BackupService::new(
+        state.db.clone(),
+        state.storage.clone(),
+        state.config.storage.backup_bucket.clone(),
+        state.config.backup.retention,
+    )
+}
+
+/// Completed backups, newest first
+pub async fn list_backups(State(state): State<AppState>) -> AppResult<Json<Vec<BackupManifest>>> {
+    Ok(Json(backup_service(&state).list().await?))
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupStarted {
+    pub id: String,
+}
+
+/// Start a backup in the background; it is listed once complete
+pub async fn create_backup(
+    State(state): State<AppState>,
+) -> AppResult<(StatusCode, Json<BackupStarted>)> {
+    let backups = backup_service(&state);
+    let pending = backups.begin().await?;
+    let id = pending.id.clone();
+
+    tokio::spawn(async move {
+        match backups.run(pending).await {
+            Ok(manifest) => tracing::info!("Backup {} complete", manifest.id),
+            Err(e) => {
+                tracing::error!("Backup failed: {}", e);
+                return;
+            }
+        }
+        if let Err(e) = backups.prune().await {
+            tracing::warn!("Failed to prune old backups: {}", e);
+        }
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(BackupStarted { id })))
+}
+
+pub async fn delete_backup(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<MessageResponse>> {
+    backup_service(&state).delete(&id).await?;
+
+    Ok(Json(MessageResponse {
+        message: "Backup deleted".to_string(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnalyticsExportQuery {
+    /// ID of the last event already read
+    pub after: Option<Uuid>,
+    #[serde(default = "default_export_limit")]
+    pub limit: i64,
+}
+
+fn default_export_limit() -> i64 {
+    500
+}
+
+/// Page through the event log in write order
+pub async fn export_analytics_events(
+    State(state): State<AppState>,
+    Query(query): Query<AnalyticsExportQuery>,
+) -> AppResult<Json<Vec<AnalyticsEvent>>> {
+    let events = EventLog::new(state.db, &state.config.analytics)
+        .export(query.after, query.limit.clamp(1, 5000))
+        .await?;
+
+    Ok(Json(events))
+}
@@ -0,0 +1,110 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::AppResult,
+    models::{ApiToken, CreatedApiToken, Scope},
+    services::{api_tokens::ApiTokenService, auth::Claims},
+    AppState,
+};
+
+use super::super::middleware::get_user_id;
+
+#[derive(Debug, Serialize)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+fn token_service(state: &AppState) -> ApiTokenService {
+    ApiTokenService::new(
+        state.db.clone(),
+        state.redis.clone(),
+        state.config.api_tokens.clone(),
+    )
+}
+
+pub async fn list_tokens(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<Vec<ApiToken>>> {
+    let user_id = get_user_id(&claims)?;
+
+    let tokens = token_service(&state).list(user_id).await?;
+
+    Ok(Json(tokens))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub expires_in_days: Option<i64>,
+    /// Requests per minute; defaults to `API_TOKEN_RATE_LIMIT`
+    pub rate_limit: Option<u32>,
+}
+
+/// Create a token; the response is the only time it is shown
+pub async fn create_token(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<CreateTokenRequest>,
+) -> AppResult<(StatusCode, Json<CreatedApiToken>)> {
+    let user_id = get_user_id(&claims)?;
+
+    let created = token_service(&state)
+        .create(
+            user_id,
+            &req.name,
+            &req.scopes,
+            req.expires_in_days,
+            req.rate_limit,
+        )
+        .await?;
+
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+pub async fn revoke_token(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(token_id): Path<Uuid>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    token_service(&state).revoke(Some(user_id), token_id).await?;
+
+    Ok(Json(MessageResponse {
+        message: "Token revoked".to_string(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminTokensQuery {
+    pub user_id: Option<Uuid>,
+}
+
+/// Every user's tokens, or one user's
+pub async fn admin_list_tokens(
+    State(state): State<AppState>,
+    Query(query): Query<AdminTokensQuery>,
+) -> AppResult<Json<Vec<ApiToken>>> {
+    let tokens = token_service(&state).list_all(query.user_id).await?;
+
+    Ok(Json(tokens))
+}
+
+pub async fn admin_revoke_token(
+    State(state): State<AppState>,
+    Path(token_id): Path<Uuid>,
+) -> AppResult<Json<MessageResponse>> {
+    token_service(&state).revoke(None, token_id).await?;
+
+    Ok(Json(MessageResponse {
+        message: "Token revoked".to_string(),
+    }))
+}
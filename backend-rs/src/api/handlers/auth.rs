@@ -1,20 +1,40 @@
-use axum::{extract::State, Extension, Json};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::{
     error::{AppError, AppResult},
-    models::{OtpType, TokenPair, User},
-    services::auth::{AuthService, Claims},
+    models::{
+        AccountMergeReport, ClientInfo, DeviceLinkStatus, OtpType, Scope, SessionInfo, SignupMode,
+        TokenPair, User,
+    },
+    services::{
+        account_merge::AccountMergeService,
+        auth::{AuthService, Claims},
+        contacts::ContactsService,
+        invites::InviteService,
+        notifications::NotificationService,
+        rate_limit::{RateDecision, RateLimitRule, RateLimiter, RegistrationVelocity},
+    },
     AppState,
 };
 
-use super::super::middleware::{get_device_id, get_user_id};
+use super::super::{
+    client_ip::ClientIp,
+    middleware::{client_info, get_device_id, get_user_id},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct SendOtpRequest {
     pub target: String,
     #[serde(rename = "type")]
     pub otp_type: String,
+    pub captcha_token: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -22,8 +42,53 @@ pub struct MessageResponse {
     pub message: String,
 }
 
+/// Apply abuse rate limits, requiring a CAPTCHA once the limiter flags suspicion
+pub(super) async fn check_abuse(
+    state: &AppState,
+    scope: &str,
+    keys: &[&str],
+    rule: RateLimitRule,
+    captcha_token: Option<&str>,
+    client: &ClientInfo,
+) -> AppResult<()> {
+    let limiter = RateLimiter::new(state.redis.clone());
+    let decision = limiter.hit(scope, keys, rule).await?;
+
+    enforce(state, decision, captcha_token, client).await
+}
+
+async fn enforce(
+    state: &AppState,
+    decision: RateDecision,
+    captcha_token: Option<&str>,
+    client: &ClientInfo,
+) -> AppResult<()> {
+    match decision {
+        RateDecision::Allow => Ok(()),
+        RateDecision::Deny => Err(AppError::TooManyAttempts),
+        RateDecision::Challenge => {
+            // CAPTCHA is optional; without a provider suspicious traffic is only rate limited
+            let Some(provider) = &state.captcha else {
+                return Ok(());
+            };
+
+            let token = captcha_token
+                .filter(|t| !t.is_empty())
+                .ok_or(AppError::CaptchaRequired)?;
+
+            if provider.verify(token, client.ip_address.as_deref()).await? {
+                Ok(())
+            } else {
+                Err(AppError::CaptchaInvalid)
+            }
+        }
+    }
+}
+
 pub async fn send_otp(
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
     Json(req): Json<SendOtpRequest>,
 ) -> AppResult<Json<MessageResponse>> {
     let otp_type = match req.otp_type.as_str() {
@@ -32,6 +97,24 @@ pub async fn send_otp(
         _ => return Err(AppError::BadRequest("Invalid OTP type".to_string())),
     };
 
+    let client = client_info(&headers, ip, &state.config);
+    let limits = &state.config.rate_limit;
+    let rule = RateLimitRule {
+        window: limits.window,
+        challenge_after: limits.otp_challenge_after,
+        max: limits.otp_max,
+    };
+    let ip = client.ip_address.clone().unwrap_or_default();
+    check_abuse(
+        &state,
+        "otp",
+        &[&ip, &req.target],
+        rule,
+        req.captcha_token.as_deref(),
+        &client,
+    )
+    .await?;
+
     let auth_service = AuthService::new(state.db, state.redis, (*state.config).clone());
     auth_service.send_otp(&req.target, otp_type).await?;
 
@@ -77,6 +160,11 @@ pub struct RegisterRequest {
     pub display_name: String,
     pub device_name: String,
     pub platform: String,
+    pub captcha_token: Option<String>,
+    /// Stable per-install identifier, counted for registration velocity
+    pub device_fingerprint: Option<String>,
+    /// Required when the server is invite-only; credits the inviter
+    pub invite_code: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -87,13 +175,58 @@ pub struct AuthResponse {
 
 pub async fn register(
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
     Json(req): Json<RegisterRequest>,
 ) -> AppResult<Json<AuthResponse>> {
     if req.phone.is_none() && req.email.is_none() {
         return Err(AppError::BadRequest("Phone or email is required".to_string()));
     }
 
-    let auth_service = AuthService::new(state.db, state.redis, (*state.config).clone());
+    let client = client_info(&headers, ip, &state.config);
+    let limits = &state.config.rate_limit;
+    let rule = RateLimitRule {
+        window: limits.window,
+        challenge_after: limits.register_challenge_after,
+        max: limits.register_max,
+    };
+    let ip = client.ip_address.clone().unwrap_or_default();
+    let target = req.phone.as_deref().or(req.email.as_deref()).unwrap_or_default();
+    let fingerprint = req
+        .device_fingerprint
+        .as_deref()
+        .map(str::trim)
+        .filter(|f| !f.is_empty());
+    if fingerprint.is_some_and(|f| f.len() > 128) {
+        return Err(AppError::Validation(
+            "Device fingerprint must be at most 128 characters".to_string(),
+        ));
+    }
+
+    // Attempts and completed registrations are limited separately; one
+    // CAPTCHA answers both
+    let limiter = RateLimiter::new(state.redis.clone());
+    let velocity = RegistrationVelocity::new(state.redis.clone());
+    let decision = limiter.hit("register", &[&ip, target], rule).await?.max(
+        velocity
+            .check(
+                &ip,
+                fingerprint,
+                limits.register_velocity_challenge_after,
+                limits.register_velocity_max,
+            )
+            .await?,
+    );
+    if decision == RateDecision::Deny {
+        tracing::warn!(%ip, ?fingerprint, "Registration refused by abuse limits");
+    }
+    enforce(&state, decision, req.captcha_token.as_deref(), &client).await?;
+
+    let auth_service = AuthService::new(
+        state.db.clone(),
+        state.redis.clone(),
+        (*state.config).clone(),
+    );
     let (user, tokens) = auth_service
         .register(
             req.phone.as_deref(),
@@ -102,12 +235,83 @@ pub async fn register(
             &req.display_name,
             &req.device_name,
             &req.platform,
+            req.invite_code.as_deref(),
+            &client,
         )
         .await?;
 
+    if let Err(e) = velocity.record(&ip, fingerprint).await {
+        tracing::warn!("Failed to record registration velocity: {}", e);
+    }
+
+    // Tell people who had the new user in their address book, in the background
+    let contacts_service = ContactsService::new(state.db.clone());
+    let notification_service =
+        NotificationService::new(state.db, state.redis, state.push.clone(), state.email.clone());
+    let joined_user = user.clone();
+    tokio::spawn(async move {
+        let result = async {
+            let recipients = contacts_service.take_interested(&joined_user).await?;
+            notification_service
+                .notify_contact_joined(&joined_user, &recipients)
+                .await
+        };
+        if let Err(e) = result.await {
+            tracing::warn!("Failed to send contact joined notices: {}", e);
+        }
+    });
+
     Ok(Json(AuthResponse { user, tokens }))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct CheckInviteRequest {
+    pub code: String,
+    pub captcha_token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckInviteResponse {
+    pub valid: bool,
+    /// Whether registering needs a code, or a code or the waitlist
+    pub signup_mode: SignupMode,
+}
+
+/// Check an invite code before the OTP step of registration
+pub async fn check_invite(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Json(req): Json<CheckInviteRequest>,
+) -> AppResult<Json<CheckInviteResponse>> {
+    // Unauthenticated, so it shares the OTP abuse limits
+    let client = client_info(&headers, ip, &state.config);
+    let limits = &state.config.rate_limit;
+    let rule = RateLimitRule {
+        window: limits.window,
+        challenge_after: limits.otp_challenge_after,
+        max: limits.otp_max,
+    };
+    let ip = client.ip_address.clone().unwrap_or_default();
+    check_abuse(
+        &state,
+        "invite_check",
+        &[&ip],
+        rule,
+        req.captcha_token.as_deref(),
+        &client,
+    )
+    .await?;
+
+    let invites = InviteService::new(state.db, state.config.invites.clone());
+    let valid = invites.is_valid(&req.code).await?;
+
+    Ok(Json(CheckInviteResponse {
+        valid,
+        signup_mode: SignupMode::from_config(&state.config.signup.mode),
+    }))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
     pub target: String,
@@ -115,10 +319,14 @@ pub struct LoginRequest {
     pub otp_type: String,
     pub device_name: String,
     pub platform: String,
+    /// `tokens.device_token` from this device's first login or registration
+    pub device_token: Option<String>,
 }
 
 pub async fn login(
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
     Json(req): Json<LoginRequest>,
 ) -> AppResult<Json<AuthResponse>> {
     let otp_type = match req.otp_type.as_str() {
@@ -127,11 +335,43 @@ pub async fn login(
         _ => return Err(AppError::BadRequest("Invalid OTP type".to_string())),
     };
 
-    let auth_service = AuthService::new(state.db, state.redis, (*state.config).clone());
-    let (user, tokens) = auth_service
-        .login(&req.target, otp_type, &req.device_name, &req.platform)
+    let client = client_info(&headers, ip, &state.config);
+
+    let auth_service = AuthService::new(
+        state.db.clone(),
+        state.redis.clone(),
+        (*state.config).clone(),
+    );
+    let (user, tokens, alert) = auth_service
+        .login(
+            &req.target,
+            otp_type,
+            &req.device_name,
+            &req.platform,
+            req.device_token.as_deref(),
+            &client,
+        )
         .await?;
 
+    if let Some(alert) = alert {
+        // Deliver in the background so slow providers don't delay the login response
+        let notification_service = NotificationService::new(
+            state.db,
+            state.redis.clone(),
+            state.push.clone(),
+            state.email.clone(),
+        );
+        let alert_user = user.clone();
+        tokio::spawn(async move {
+            if let Err(e) = notification_service
+                .notify_new_login(&alert_user, &alert)
+                .await
+            {
+                tracing::warn!("Failed to send new login notification: {}", e);
+            }
+        });
+    }
+
     Ok(Json(AuthResponse { user, tokens }))
 }
 
@@ -183,3 +423,420 @@ pub async fn logout_all(
         message: "Logged out from all devices".to_string(),
     }))
 }
+
+pub async fn get_sessions(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<Vec<SessionInfo>>> {
+    let user_id = get_user_id(&claims)?;
+    let device_id = get_device_id(&claims)?;
+
+    let auth_service = AuthService::new(state.db, state.redis, (*state.config).clone());
+    let sessions = auth_service.list_sessions(user_id, device_id).await?;
+
+    Ok(Json(sessions))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeIdentifierRequest {
+    #[serde(rename = "type")]
+    pub otp_type: String,
+    pub new_target: String,
+    pub captcha_token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangeIdentifierResponse {
+    /// Where the confirmation code for the current identifier was sent
+    pub confirm_type: OtpType,
+    pub confirm_target: String,
+}
+
+/// Start changing the account's phone or email; codes are sent to the new
+/// target and to the current one
+pub async fn change_identifier(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Json(req): Json<ChangeIdentifierRequest>,
+) -> AppResult<Json<ChangeIdentifierResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    let otp_type = match req.otp_type.as_str() {
+        "phone" => OtpType::Phone,
+        "email" => OtpType::Email,
+        _ => return Err(AppError::BadRequest("Invalid OTP type".to_string())),
+    };
+
+    let new_target = req.new_target.trim();
+    if new_target.is_empty() || new_target.len() > 255 {
+        return Err(AppError::Validation("Invalid target".to_string()));
+    }
+    if otp_type == OtpType::Email && !new_target.contains('@') {
+        return Err(AppError::Validation("Invalid email address".to_string()));
+    }
+
+    // Sends OTPs, so it shares the OTP abuse limits
+    let client = client_info(&headers, ip, &state.config);
+    let limits = &state.config.rate_limit;
+    let rule = RateLimitRule {
+        window: limits.window,
+        challenge_after: limits.otp_challenge_after,
+        max: limits.otp_max,
+    };
+    let user_key = user_id.to_string();
+    check_abuse(
+        &state,
+        "otp",
+        &[&user_key, new_target],
+        rule,
+        req.captcha_token.as_deref(),
+        &client,
+    )
+    .await?;
+
+    let auth_service = AuthService::new(state.db, state.redis, (*state.config).clone());
+    let pending = auth_service
+        .start_identifier_change(user_id, otp_type, new_target)
+        .await?;
+
+    Ok(Json(ChangeIdentifierResponse {
+        confirm_type: pending.confirm_type,
+        confirm_target: pending.confirm_target,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmIdentifierRequest {
+    /// Code sent to the new phone/email
+    pub new_code: String,
+    /// Code sent to the current phone/email
+    pub confirm_code: String,
+}
+
+pub async fn confirm_identifier_change(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<ConfirmIdentifierRequest>,
+) -> AppResult<Json<User>> {
+    let user_id = get_user_id(&claims)?;
+    let device_id = get_device_id(&claims)?;
+
+    let auth_service = AuthService::new(
+        state.db.clone(),
+        state.redis.clone(),
+        (*state.config).clone(),
+    );
+    let (user, pending) = auth_service
+        .confirm_identifier_change(user_id, &req.new_code, &req.confirm_code)
+        .await?;
+
+    let old_email = (pending.confirm_type == OtpType::Email).then_some(pending.confirm_target);
+
+    // Deliver in the background so slow providers don't delay the response
+    let notification_service = NotificationService::new(
+        state.db,
+        state.redis.clone(),
+        state.push.clone(),
+        state.email.clone(),
+    );
+    let notify_user = user.clone();
+    tokio::spawn(async move {
+        if let Err(e) = notification_service
+            .notify_identifier_changed(
+                &notify_user,
+                pending.otp_type,
+                old_email.as_deref(),
+                device_id,
+            )
+            .await
+        {
+            tracing::warn!("Failed to send identifier change notification: {}", e);
+        }
+    });
+
+    Ok(Json(user))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartMergeRequest {
+    #[serde(rename = "type")]
+    pub otp_type: String,
+    /// Phone or email of the account to merge into this one
+    pub target: String,
+    pub captcha_token: Option<String>,
+}
+
+/// Start merging a duplicate account into this one; a code is sent to the
+/// duplicate's phone or email
+pub async fn start_account_merge(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Json(req): Json<StartMergeRequest>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    let otp_type = match req.otp_type.as_str() {
+        "phone" => OtpType::Phone,
+        "email" => OtpType::Email,
+        _ => return Err(AppError::BadRequest("Invalid OTP type".to_string())),
+    };
+    let target = req.target.trim();
+
+    // Sends OTPs, so it shares the OTP abuse limits
+    let client = client_info(&headers, ip, &state.config);
+    let limits = &state.config.rate_limit;
+    let rule = RateLimitRule {
+        window: limits.window,
+        challenge_after: limits.otp_challenge_after,
+        max: limits.otp_max,
+    };
+    let user_key = user_id.to_string();
+    check_abuse(
+        &state,
+        "otp",
+        &[&user_key, target],
+        rule,
+        req.captcha_token.as_deref(),
+        &client,
+    )
+    .await?;
+
+    let merge_service =
+        AccountMergeService::new(state.db, state.redis, (*state.config).clone());
+    merge_service.start(user_id, otp_type, target).await?;
+
+    Ok(Json(MessageResponse {
+        message: "Verification code sent".to_string(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreviewMergeRequest {
+    /// Code sent to the duplicate's phone or email
+    pub code: String,
+}
+
+/// Dry run: what merging the duplicate would move, without moving anything
+pub async fn preview_account_merge(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<PreviewMergeRequest>,
+) -> AppResult<Json<AccountMergeReport>> {
+    let user_id = get_user_id(&claims)?;
+
+    let merge_service =
+        AccountMergeService::new(state.db, state.redis, (*state.config).clone());
+    let report = merge_service.preview(user_id, &req.code).await?;
+
+    Ok(Json(report))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmMergeRequest {
+    pub code: String,
+    /// Must be `true`: the duplicate is deleted and cannot be split out again
+    #[serde(default)]
+    pub irreversible: bool,
+}
+
+/// Merge the previewed duplicate into this account for good
+pub async fn confirm_account_merge(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<ConfirmMergeRequest>,
+) -> AppResult<Json<AccountMergeReport>> {
+    let user_id = get_user_id(&claims)?;
+
+    if !req.irreversible {
+        return Err(AppError::Validation(
+            "Merging cannot be undone; set irreversible to true to confirm".to_string(),
+        ));
+    }
+
+    let merge_service =
+        AccountMergeService::new(state.db, state.redis, (*state.config).clone());
+    let report = merge_service.confirm(user_id, &req.code).await?;
+
+    Ok(Json(report))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartLinkRequest {
+    pub device_name: String,
+    #[serde(default = "default_link_platform")]
+    pub platform: String,
+    pub captcha_token: Option<String>,
+}
+
+fn default_link_platform() -> String {
+    "web".to_string()
+}
+
+#[derive(Debug, Serialize)]
+pub struct StartLinkResponse {
+    pub link_id: Uuid,
+    /// Kept by the client to claim the session; not part of the QR code
+    pub secret: String,
+    /// What the QR code encodes
+    pub qr: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Start linking a web session to an account by QR code
+pub async fn start_link(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Json(req): Json<StartLinkRequest>,
+) -> AppResult<Json<StartLinkResponse>> {
+    let device_name = req.device_name.trim();
+    let platform = req.platform.trim();
+    if device_name.is_empty() || device_name.len() > 100 {
+        return Err(AppError::Validation(
+            "Device name must be 1-100 characters".to_string(),
+        ));
+    }
+    if platform.is_empty() || platform.len() > 50 {
+        return Err(AppError::Validation("Invalid platform".to_string()));
+    }
+
+    // Unauthenticated, so it shares the OTP abuse limits
+    let client = client_info(&headers, ip, &state.config);
+    let limits = &state.config.rate_limit;
+    let rule = RateLimitRule {
+        window: limits.window,
+        challenge_after: limits.otp_challenge_after,
+        max: limits.otp_max,
+    };
+    let ip = client.ip_address.clone().unwrap_or_default();
+    check_abuse(
+        &state,
+        "device_link",
+        &[&ip],
+        rule,
+        req.captcha_token.as_deref(),
+        &client,
+    )
+    .await?;
+
+    let auth_service = AuthService::new(state.db, state.redis, (*state.config).clone());
+    let (link_id, link) = auth_service
+        .start_link(device_name, platform, &client)
+        .await?;
+
+    Ok(Json(StartLinkResponse {
+        link_id,
+        qr: format!("ansible-talk://link?id={}&code={}", link_id, link.code),
+        secret: link.secret,
+        expires_at: link.expires_at,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApproveLinkRequest {
+    pub link_id: Uuid,
+    /// The `code` from the QR code
+    pub code: String,
+    #[serde(default = "default_approved")]
+    pub approved: bool,
+    /// Defaults to every scope a linked session can have
+    pub scopes: Option<Vec<Scope>>,
+}
+
+fn default_approved() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApproveLinkResponse {
+    pub device_name: String,
+    pub platform: String,
+    pub scopes: Vec<Scope>,
+    pub ip_address: Option<String>,
+    pub country: Option<String>,
+}
+
+/// Approve or decline a web session from a signed-in device that scanned its
+/// QR code
+pub async fn approve_link(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<ApproveLinkRequest>,
+) -> AppResult<Json<ApproveLinkResponse>> {
+    let user_id = get_user_id(&claims)?;
+    let device_id = get_device_id(&claims)?;
+
+    let auth_service = AuthService::new(state.db, state.redis, (*state.config).clone());
+    let link = auth_service
+        .approve_link(
+            user_id,
+            device_id,
+            req.link_id,
+            &req.code,
+            req.approved,
+            req.scopes.unwrap_or_else(|| Scope::LINKABLE.to_vec()),
+        )
+        .await?;
+
+    Ok(Json(ApproveLinkResponse {
+        device_name: link.device_name,
+        platform: link.platform,
+        scopes: link.scopes,
+        ip_address: link.ip_address,
+        country: link.country,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClaimLinkRequest {
+    pub link_id: Uuid,
+    pub secret: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClaimLinkResponse {
+    /// `pending` until a device approves the link
+    pub status: DeviceLinkStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<User>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<TokenPair>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<Scope>>,
+}
+
+/// Polled by the web client: `202` while the link waits for approval, then
+/// the linked session's tokens once
+pub async fn claim_link(
+    State(state): State<AppState>,
+    Json(req): Json<ClaimLinkRequest>,
+) -> AppResult<(StatusCode, Json<ClaimLinkResponse>)> {
+    let auth_service = AuthService::new(state.db, state.redis, (*state.config).clone());
+
+    let response = match auth_service.claim_link(req.link_id, &req.secret).await? {
+        None => (
+            StatusCode::ACCEPTED,
+            Json(ClaimLinkResponse {
+                status: DeviceLinkStatus::Pending,
+                user: None,
+                tokens: None,
+                scopes: None,
+            }),
+        ),
+        Some((user, tokens, scopes)) => (
+            StatusCode::OK,
+            Json(ClaimLinkResponse {
+                status: DeviceLinkStatus::Approved,
+                user: Some(user),
+                tokens: Some(tokens),
+                scopes: Some(scopes),
+            }),
+        ),
+    };
+
+    Ok(response)
+}
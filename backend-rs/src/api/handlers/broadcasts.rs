@@ -0,0 +1,212 @@
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{
+        BroadcastDetails, BroadcastListWithRecipients, BroadcastWithSummary, MessagePayload,
+        MessageType,
+    },
+    services::{auth::Claims, broadcast::BroadcastService, messaging::MessagingService},
+    AppState,
+};
+
+use super::super::middleware::get_user_id;
+
+#[derive(Debug, Serialize)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+fn broadcast_service(state: AppState) -> BroadcastService {
+    let messaging = MessagingService::new(state.db.clone(), state.redis, (*state.config).clone())
+        .with_search_index(state.search)
+        .with_translator(state.translator)
+        .with_bridge(state.matrix);
+    BroadcastService::new(state.db, messaging)
+}
+
+fn validate_name(name: &str) -> AppResult<()> {
+    if name.trim().is_empty() || name.chars().count() > 100 {
+        return Err(AppError::Validation(
+            "Name must be 1-100 characters".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub async fn get_broadcast_lists(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<Vec<BroadcastListWithRecipients>>> {
+    let user_id = get_user_id(&claims)?;
+
+    let lists = broadcast_service(state).get_lists(user_id).await?;
+
+    Ok(Json(lists))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateBroadcastListRequest {
+    pub name: String,
+    pub recipient_ids: Vec<Uuid>,
+}
+
+pub async fn create_broadcast_list(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<CreateBroadcastListRequest>,
+) -> AppResult<Json<BroadcastListWithRecipients>> {
+    let user_id = get_user_id(&claims)?;
+
+    validate_name(&req.name)?;
+
+    let list = broadcast_service(state)
+        .create_list(user_id, req.name.trim(), req.recipient_ids)
+        .await?;
+
+    Ok(Json(list))
+}
+
+pub async fn get_broadcast_list(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(list_id): Path<Uuid>,
+) -> AppResult<Json<BroadcastListWithRecipients>> {
+    let user_id = get_user_id(&claims)?;
+
+    let list = broadcast_service(state).get_list(list_id, user_id).await?;
+
+    Ok(Json(list))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateBroadcastListRequest {
+    pub name: Option<String>,
+    /// Replaces the whole recipient set
+    pub recipient_ids: Option<Vec<Uuid>>,
+}
+
+pub async fn update_broadcast_list(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(list_id): Path<Uuid>,
+    Json(req): Json<UpdateBroadcastListRequest>,
+) -> AppResult<Json<BroadcastListWithRecipients>> {
+    let user_id = get_user_id(&claims)?;
+
+    if let Some(name) = &req.name {
+        validate_name(name)?;
+    }
+
+    let list = broadcast_service(state)
+        .update_list(
+            list_id,
+            user_id,
+            req.name.map(|n| n.trim().to_string()),
+            req.recipient_ids,
+        )
+        .await?;
+
+    Ok(Json(list))
+}
+
+pub async fn delete_broadcast_list(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(list_id): Path<Uuid>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    broadcast_service(state)
+        .delete_list(list_id, user_id)
+        .await?;
+
+    Ok(Json(MessageResponse {
+        message: "Broadcast list deleted".to_string(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SendBroadcastRequest {
+    #[serde(rename = "type")]
+    pub message_type: String,
+    #[serde(default)]
+    pub payload: MessagePayload,
+    pub sticker_id: Option<Uuid>,
+}
+
+pub async fn send_broadcast(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(list_id): Path<Uuid>,
+    Json(req): Json<SendBroadcastRequest>,
+) -> AppResult<Json<BroadcastDetails>> {
+    let user_id = get_user_id(&claims)?;
+
+    let message_type = match req.message_type.as_str() {
+        "text" => MessageType::Text,
+        "image" => MessageType::Image,
+        "video" => MessageType::Video,
+        "audio" => MessageType::Audio,
+        "file" => MessageType::File,
+        "sticker" => MessageType::Sticker,
+        other => {
+            return Err(AppError::Validation(format!(
+                "Unknown message type '{}'",
+                other
+            )))
+        }
+    };
+
+    let broadcast = broadcast_service(state)
+        .send(list_id, user_id, message_type, req.payload, req.sticker_id)
+        .await?;
+
+    Ok(Json(broadcast))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PaginationQuery {
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+    #[serde(default)]
+    pub offset: i32,
+}
+
+fn default_limit() -> i32 {
+    20
+}
+
+pub async fn get_broadcasts(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(list_id): Path<Uuid>,
+    Query(query): Query<PaginationQuery>,
+) -> AppResult<Json<Vec<BroadcastWithSummary>>> {
+    let user_id = get_user_id(&claims)?;
+
+    let broadcasts = broadcast_service(state)
+        .get_broadcasts(list_id, user_id, query.limit, query.offset)
+        .await?;
+
+    Ok(Json(broadcasts))
+}
+
+pub async fn get_broadcast(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path((list_id, broadcast_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Json<BroadcastDetails>> {
+    let user_id = get_user_id(&claims)?;
+
+    let broadcast = broadcast_service(state)
+        .get_broadcast(list_id, broadcast_id, user_id)
+        .await?;
+
+    Ok(Json(broadcast))
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Path, State},
+    http::{header::CACHE_CONTROL, HeaderMap},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppResult,
+    models::{ClientConfig, ClientVersionReport, ClientVersionRequirement},
+    services::{
+        auth::Claims,
+        client_versions::{client_version, ClientVersionService},
+    },
+    AppState,
+};
+
+use super::super::middleware::get_user_id;
+
+#[derive(Debug, Serialize)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerTimeResponse {
+    pub server_time: DateTime<Utc>,
+    /// The same instant in milliseconds since the Unix epoch
+    pub unix_ms: i64,
+}
+
+/// The server clock, for clients to work out how far off theirs is. Public
+/// and never cached.
+pub async fn get_server_time() -> impl IntoResponse {
+    let now = Utc::now();
+
+    (
+        [(CACHE_CONTROL, "no-store")],
+        Json(ServerTimeResponse {
+            server_time: now,
+            unix_ms: now.timestamp_millis(),
+        }),
+    )
+}
+
+/// Upgrade state and server features for the calling app. Public and never
+/// refused for being outdated, so old clients can still learn where to upgrade.
+pub async fn get_client_config(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> AppResult<Json<ClientConfig>> {
+    let versions = ClientVersionService::new(state.db, state.redis);
+
+    let upgrade = match client_version(&headers) {
+        Some((platform, version)) => versions.upgrade(&platform, &version).await?,
+        None => None,
+    };
+    let upgrade_urls = versions
+        .requirements()
+        .await?
+        .into_iter()
+        .filter_map(|r| Some((r.platform, r.upgrade_url?)))
+        .collect();
+
+    let config = &state.config;
+    let features = BTreeMap::from([
+        ("search", state.search.is_some()),
+        ("translation", state.translator.is_some()),
+        ("gifs", state.gifs.is_some()),
+        ("web_push", config.notifications.vapid_public_key.is_some()),
+        ("matrix", state.matrix.is_some()),
+        ("captcha", state.captcha.is_some()),
+        ("transcoding", config.transcode.provider != "none"),
+        ("sticker_previews", state.sticker_renderer.is_some()),
+    ]);
+
+    Ok(Json(ClientConfig {
+        upgrade,
+        upgrade_urls,
+        features,
+    }))
+}
+
+/// Minimum versions and the app versions active devices run
+pub async fn get_client_versions(
+    State(state): State<AppState>,
+) -> AppResult<Json<ClientVersionReport>> {
+    let versions = ClientVersionService::new(state.db, state.redis);
+    let report = versions.report().await?;
+
+    Ok(Json(report))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClientVersionRequest {
+    pub min_version: String,
+    pub latest_version: Option<String>,
+    pub upgrade_url: Option<String>,
+    pub message: Option<String>,
+}
+
+pub async fn set_client_version(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(platform): Path<String>,
+    Json(req): Json<ClientVersionRequest>,
+) -> AppResult<Json<ClientVersionRequirement>> {
+    let admin_id = get_user_id(&claims)?;
+
+    let versions = ClientVersionService::new(state.db, state.redis);
+    let requirement = versions
+        .set_requirement(
+            admin_id,
+            &platform,
+            &req.min_version,
+            req.latest_version.as_deref(),
+            req.upgrade_url.as_deref(),
+            req.message.as_deref(),
+        )
+        .await?;
+
+    Ok(Json(requirement))
+}
+
+pub async fn delete_client_version(
+    State(state): State<AppState>,
+    Path(platform): Path<String>,
+) -> AppResult<Json<MessageResponse>> {
+    let versions = ClientVersionService::new(state.db, state.redis);
+    versions.delete_requirement(&platform).await?;
+
+    Ok(Json(MessageResponse {
+        message: "Minimum version removed".to_string(),
+    }))
+}
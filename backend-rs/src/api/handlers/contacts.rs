@@ -1,18 +1,23 @@
 use axum::{
-    extract::{Path, Query, State},
+    extract::{Multipart, Path, Query, State},
     Extension, Json,
 };
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    error::AppResult,
-    models::{ContactWithUser, User},
-    services::{auth::Claims, contacts::ContactsService},
+    error::{AppError, AppResult},
+    models::{
+        ContactImportReport, ContactOperation, ContactOperationResult, ContactWithUser, User,
+    },
+    services::{auth::Claims, contact_import::parse_address_book, contacts::ContactsService},
     AppState,
 };
 
-use super::super::middleware::get_user_id;
+use super::super::{
+    middleware::get_user_id,
+    pagination::{Page, Paginated},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct GetContactsQuery {
@@ -20,19 +25,31 @@ pub struct GetContactsQuery {
     pub include_blocked: bool,
 }
 
+fn default_limit() -> i32 {
+    50
+}
+
 pub async fn get_contacts(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
     Query(query): Query<GetContactsQuery>,
-) -> AppResult<Json<Vec<ContactWithUser>>> {
+    page: Page,
+) -> AppResult<Json<Paginated<ContactWithUser>>> {
     let user_id = get_user_id(&claims)?;
+    let limit = page.limit(default_limit());
+    let offset = page.offset()?;
 
     let contacts_service = ContactsService::new(state.db);
     let contacts = contacts_service
-        .get_contacts(user_id, query.include_blocked)
+        .get_contacts(user_id, query.include_blocked, limit + 1, offset)
+        .await?;
+    let total = contacts_service
+        .count_contacts(user_id, query.include_blocked)
         .await?;
 
-    Ok(Json(contacts))
+    Ok(Json(
+        Paginated::from_offset(contacts, limit, offset).with_total(total),
+    ))
 }
 
 #[derive(Debug, Deserialize)]
@@ -172,3 +189,57 @@ pub async fn sync_contacts(
 
     Ok(Json(users))
 }
+
+/// Multipart form with a `file` field holding a CSV or vCard export
+pub async fn import_contacts(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    mut multipart: Multipart,
+) -> AppResult<Json<ContactImportReport>> {
+    let user_id = get_user_id(&claims)?;
+
+    let mut entries = None;
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        AppError::BadRequest(format!("Failed to read multipart field: {}", e))
+    })? {
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let data = field
+            .bytes()
+            .await
+            .map_err(|e| AppError::BadRequest(format!("Failed to read file: {}", e)))?;
+        let text = String::from_utf8_lossy(&data);
+        entries = Some(parse_address_book(&text)?);
+    }
+    let entries = entries.ok_or(AppError::BadRequest("No file provided".to_string()))?;
+
+    let contacts_service = ContactsService::new(state.db);
+    let report = contacts_service.import_contacts(user_id, entries).await?;
+
+    Ok(Json(report))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BulkContactsRequest {
+    pub operations: Vec<ContactOperation>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkContactsResponse {
+    pub results: Vec<ContactOperationResult>,
+}
+
+pub async fn bulk_contacts(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<BulkContactsRequest>,
+) -> AppResult<Json<BulkContactsResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    let contacts_service = ContactsService::new(state.db);
+    let results = contacts_service.bulk_update(user_id, req.operations).await?;
+
+    Ok(Json(BulkContactsResponse { results }))
+}
@@ -1,25 +1,42 @@
 use axum::{
-    extract::{Path, Query, State},
+    extract::{Multipart, Path, Query, State},
     Extension, Json,
 };
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    error::AppResult,
-    models::{ConversationWithDetails, Message, MessageType},
-    services::{auth::Claims, messaging::MessagingService},
+    error::{AppError, AppResult},
+    models::{
+        ConversationAppearance, ConversationEncryption, ConversationEvent, ConversationFilter,
+        ConversationTranslationSettings, ConversationWithDetails, GroupAction,
+        GroupPermissionsUpdate, Message, MessagePayload, MessageType, ParticipantRole,
+        ParticipantWithUser,
+    },
+    services::{
+        appearance::AppearanceService,
+        auth::Claims,
+        content_moderation::{ContentSource, ContentSubject, ModerationPipeline},
+        emoji::{shortcode_name, EmojiService},
+        media::MediaUrls,
+        messaging::{GroupUpdate, MessagingService},
+        search::SearchService,
+        translation::{is_valid_language, TranslationService},
+    },
     AppState,
 };
 
-use super::super::middleware::get_user_id;
+use super::super::{
+    middleware::get_user_id,
+    pagination::{Page, Paginated},
+};
 
 #[derive(Debug, Deserialize)]
-pub struct PaginationQuery {
-    #[serde(default = "default_limit")]
-    pub limit: i32,
+pub struct ConversationsQuery {
+    /// `inbox` (default) or `requests`
     #[serde(default)]
-    pub offset: i32,
+    pub filter: ConversationFilter,
 }
 
 fn default_limit() -> i32 {
@@ -29,21 +46,155 @@ fn default_limit() -> i32 {
 pub async fn get_conversations(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
-    Query(query): Query<PaginationQuery>,
+    Query(query): Query<ConversationsQuery>,
+    page: Page,
+) -> AppResult<Json<Paginated<ConversationWithDetails>>> {
+    let user_id = get_user_id(&claims)?;
+    let limit = page.limit(default_limit());
+    let offset = page.offset()?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    let conversations = messaging_service
+        .get_user_conversations(user_id, query.filter, limit + 1, offset)
+        .await?;
+
+    Ok(Json(Paginated::from_offset(conversations, limit, offset)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuickQuery {
+    #[serde(default = "default_quick_limit")]
+    pub limit: i32,
+}
+
+fn default_quick_limit() -> i32 {
+    8
+}
+
+/// Conversations ranked by how often and how recently the user sends to them
+pub async fn get_quick_conversations(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<QuickQuery>,
 ) -> AppResult<Json<Vec<ConversationWithDetails>>> {
     let user_id = get_user_id(&claims)?;
 
-    let messaging_service = MessagingService::new(state.db, state.redis);
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
     let conversations = messaging_service
-        .get_user_conversations(user_id, query.limit, query.offset)
+        .get_quick_conversations(user_id, query.limit.clamp(1, 50))
         .await?;
 
     Ok(Json(conversations))
 }
 
+/// The user's Saved Messages conversation, provisioned on first request
+pub async fn get_saved_conversation(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<ConversationWithDetails>> {
+    let user_id = get_user_id(&claims)?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    let conversation = messaging_service.get_saved_conversation(user_id).await?;
+
+    Ok(Json(conversation))
+}
+
+/// Accept a message request from a non-contact
+pub async fn accept_request(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+) -> AppResult<Json<ConversationWithDetails>> {
+    let user_id = get_user_id(&claims)?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    let conversation = messaging_service
+        .accept_request(conversation_id, user_id)
+        .await?;
+
+    Ok(Json(conversation))
+}
+
+/// Flag a conversation to come back to later; cleared once the user reads
+/// a message in it
+pub async fn mark_unread(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+) -> AppResult<Json<ConversationWithDetails>> {
+    let user_id = get_user_id(&claims)?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    let conversation = messaging_service
+        .mark_unread(conversation_id, user_id)
+        .await?;
+
+    Ok(Json(conversation))
+}
+
+/// Decline a message request and block its sender
+pub async fn block_request(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    messaging_service
+        .block_request(conversation_id, user_id)
+        .await?;
+
+    Ok(Json(MessageResponse {
+        message: "Request blocked".to_string(),
+    }))
+}
+
+/// Clear the caller's copy of the history; the other participants keep theirs
+pub async fn clear_history(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone())
+        .with_search_index(state.search);
+    messaging_service
+        .clear_history(conversation_id, user_id)
+        .await?;
+
+    Ok(Json(MessageResponse {
+        message: "History cleared".to_string(),
+    }))
+}
+
+/// Delete a direct conversation for both participants
+pub async fn delete_conversation(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone())
+        .with_search_index(state.search);
+    messaging_service
+        .delete_conversation(conversation_id, user_id)
+        .await?;
+
+    Ok(Json(MessageResponse {
+        message: "Conversation deleted".to_string(),
+    }))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CreateDirectRequest {
     pub user_id: Uuid,
+    /// Applies only if the conversation does not exist yet
+    #[serde(default)]
+    pub encryption: ConversationEncryption,
 }
 
 pub async fn create_direct_conversation(
@@ -53,9 +204,9 @@ pub async fn create_direct_conversation(
 ) -> AppResult<Json<ConversationWithDetails>> {
     let user_id = get_user_id(&claims)?;
 
-    let messaging_service = MessagingService::new(state.db, state.redis);
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
     let conversation = messaging_service
-        .create_direct_conversation(user_id, req.user_id)
+        .create_direct_conversation(user_id, req.user_id, req.encryption)
         .await?;
 
     Ok(Json(conversation))
@@ -65,6 +216,8 @@ pub async fn create_direct_conversation(
 pub struct CreateGroupRequest {
     pub name: String,
     pub member_ids: Vec<Uuid>,
+    #[serde(default)]
+    pub encryption: ConversationEncryption,
 }
 
 pub async fn create_group_conversation(
@@ -74,9 +227,9 @@ pub async fn create_group_conversation(
 ) -> AppResult<Json<ConversationWithDetails>> {
     let user_id = get_user_id(&claims)?;
 
-    let messaging_service = MessagingService::new(state.db, state.redis);
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
     let conversation = messaging_service
-        .create_group_conversation(user_id, &req.name, req.member_ids)
+        .create_group_conversation(user_id, &req.name, req.member_ids, req.encryption)
         .await?;
 
     Ok(Json(conversation))
@@ -89,7 +242,7 @@ pub async fn get_conversation(
 ) -> AppResult<Json<ConversationWithDetails>> {
     let user_id = get_user_id(&claims)?;
 
-    let messaging_service = MessagingService::new(state.db, state.redis);
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
     let conversation = messaging_service
         .get_conversation(conversation_id, user_id)
         .await?;
@@ -97,12 +250,117 @@ pub async fn get_conversation(
     Ok(Json(conversation))
 }
 
+/// Multipart form for `PUT /conversations/:id`: optional `name`,
+/// `description`, and `rules` text fields and an `avatar` file
+pub async fn update_conversation(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+    mut multipart: Multipart,
+) -> AppResult<Json<ConversationWithDetails>> {
+    let user_id = get_user_id(&claims)?;
+
+    let mut update = GroupUpdate::default();
+    let mut avatar = None;
+
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        AppError::BadRequest(format!("Failed to read multipart field: {}", e))
+    })? {
+        let name = field.name().unwrap_or("").to_string();
+
+        match name.as_str() {
+            "name" | "description" | "rules" => {
+                let value = field
+                    .text()
+                    .await
+                    .map_err(|e| AppError::BadRequest(format!("Failed to read {}: {}", name, e)))?;
+                match name.as_str() {
+                    "name" => update.name = Some(value),
+                    "description" => update.description = Some(value),
+                    _ => update.rules = Some(value),
+                }
+            }
+            "avatar" => {
+                let content_type = field
+                    .content_type()
+                    .unwrap_or("application/octet-stream")
+                    .to_string();
+                let data = field
+                    .bytes()
+                    .await
+                    .map_err(|e| AppError::BadRequest(format!("Failed to read file: {}", e)))?;
+                avatar = Some((data, content_type));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(name) = &update.name {
+        if name.trim().is_empty() || name.chars().count() > 100 {
+            return Err(AppError::Validation("Name must be 1-100 characters".to_string()));
+        }
+    }
+    if update.description.as_ref().is_some_and(|d| d.chars().count() > 1000) {
+        return Err(AppError::Validation(
+            "Description must be at most 1000 characters".to_string(),
+        ));
+    }
+    if update.rules.as_ref().is_some_and(|r| r.chars().count() > 4000) {
+        return Err(AppError::Validation(
+            "Rules must be at most 4000 characters".to_string(),
+        ));
+    }
+
+    let messaging_service = MessagingService::new(
+        state.db.clone(),
+        state.redis.clone(),
+        (*state.config).clone(),
+    );
+    messaging_service
+        .require_group_permission(conversation_id, user_id, GroupAction::ChangeInfo)
+        .await?;
+
+    if let Some((data, content_type)) = avatar {
+        ModerationPipeline::new(state.db.clone(), state.redis.clone(), &state.config.moderation)
+            .screen_upload(
+                state.storage.as_ref(),
+                &ContentSubject {
+                    source: ContentSource::GroupAvatar,
+                    uploader_id: user_id,
+                    conversation_id: Some(conversation_id),
+                    content_type: Some(&content_type),
+                    data: &data,
+                },
+            )
+            .await?;
+
+        let extension = match content_type.as_str() {
+            "image/png" => "png",
+            "image/jpeg" | "image/jpg" => "jpg",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            _ => "bin",
+        };
+
+        // The key is reused across uploads, so version the URL
+        let key = format!("groups/{}/avatar.{}", conversation_id, extension);
+        let url = state
+            .storage
+            .upload_file(state.storage.avatars_bucket(), &key, data, &content_type)
+            .await?;
+        update.avatar_url = Some(format!("{}?v={}", url, Utc::now().timestamp()));
+    }
+
+    let conversation = messaging_service
+        .update_group(conversation_id, user_id, update)
+        .await?;
+
+    Ok(Json(conversation))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct MessagesQuery {
-    #[serde(default = "default_message_limit")]
-    pub limit: i32,
-    #[serde(default)]
-    pub offset: i32,
+    /// Start the first page before this message
     pub before: Option<Uuid>,
 }
 
@@ -110,29 +368,40 @@ fn default_message_limit() -> i32 {
     50
 }
 
+/// Newest first. The cursor holds the oldest message of the page, so new
+/// messages arriving don't shift later pages.
 pub async fn get_messages(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
     Path(conversation_id): Path<Uuid>,
     Query(query): Query<MessagesQuery>,
-) -> AppResult<Json<Vec<Message>>> {
+    page: Page,
+) -> AppResult<Json<Paginated<Message>>> {
     let user_id = get_user_id(&claims)?;
+    let limit = page.limit(default_message_limit());
+    let before = page.position::<Uuid>()?.or(query.before);
+    let offset = page.skip()?;
 
-    let messaging_service = MessagingService::new(state.db, state.redis);
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
     let messages = messaging_service
-        .get_messages(conversation_id, user_id, query.limit, query.offset, query.before)
+        .get_messages(conversation_id, user_id, limit + 1, offset, before)
         .await?;
 
-    Ok(Json(messages))
+    Ok(Json(Paginated::new(messages, limit, |last| last.id)))
 }
 
 #[derive(Debug, Deserialize)]
 pub struct SendMessageRequest {
     #[serde(rename = "type")]
     pub message_type: String,
-    pub content: Vec<u8>,
+    #[serde(default)]
+    pub payload: MessagePayload,
     pub sticker_id: Option<Uuid>,
     pub reply_to_id: Option<Uuid>,
+    /// Images and videos only; recipients fetch the media once from
+    /// `/messages/:id/media`
+    #[serde(default)]
+    pub view_once: bool,
 }
 
 pub async fn send_message(
@@ -154,21 +423,217 @@ pub async fn send_message(
         _ => MessageType::Text,
     };
 
-    let messaging_service = MessagingService::new(state.db, state.redis);
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone())
+        .with_search_index(state.search)
+        .with_translator(state.translator)
+        .with_bridge(state.matrix);
     let message = messaging_service
         .send_message(
             conversation_id,
             user_id,
             message_type,
-            req.content,
+            req.payload,
             req.sticker_id,
             req.reply_to_id,
+            req.view_once,
         )
         .await?;
 
     Ok(Json(message))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct MembersQuery {
+    pub role: Option<ParticipantRole>,
+    /// Matches username or display name
+    pub q: Option<String>,
+    #[serde(default = "default_members_limit")]
+    pub limit: i32,
+    #[serde(default)]
+    pub offset: i32,
+}
+
+fn default_members_limit() -> i32 {
+    50
+}
+
+pub async fn get_members(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+    Query(query): Query<MembersQuery>,
+) -> AppResult<Json<Vec<ParticipantWithUser>>> {
+    let user_id = get_user_id(&claims)?;
+
+    let q = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+    let limit = query.limit.clamp(1, 200);
+    let offset = query.offset.max(0);
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    let members = messaging_service
+        .get_members(conversation_id, user_id, query.role, q, limit, offset)
+        .await?;
+
+    Ok(Json(members))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    #[serde(default = "default_members_limit")]
+    pub limit: i32,
+    #[serde(default)]
+    pub offset: i32,
+}
+
+/// Membership and settings changes in a group; owners and admins only
+pub async fn get_events(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+    Query(query): Query<EventsQuery>,
+) -> AppResult<Json<Vec<ConversationEvent>>> {
+    let user_id = get_user_id(&claims)?;
+
+    let limit = query.limit.clamp(1, 200);
+    let offset = query.offset.max(0);
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    let events = messaging_service
+        .get_events(conversation_id, user_id, limit, offset)
+        .await?;
+
+    Ok(Json(events))
+}
+
+/// Set the lowest role allowed each action; owners and admins only
+pub async fn update_permissions(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+    Json(req): Json<GroupPermissionsUpdate>,
+) -> AppResult<Json<ConversationWithDetails>> {
+    let user_id = get_user_id(&claims)?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    let conversation = messaging_service
+        .update_permissions(conversation_id, user_id, req)
+        .await?;
+
+    Ok(Json(conversation))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnnouncementModeRequest {
+    pub enabled: bool,
+}
+
+/// Let only owners and admins post, or everyone again
+pub async fn set_announcement_mode(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+    Json(req): Json<AnnouncementModeRequest>,
+) -> AppResult<Json<ConversationWithDetails>> {
+    let user_id = get_user_id(&claims)?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    let conversation = messaging_service
+        .set_announcement_mode(conversation_id, user_id, req.enabled)
+        .await?;
+
+    Ok(Json(conversation))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddMembersRequest {
+    pub user_ids: Vec<Uuid>,
+}
+
+pub async fn add_members(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+    Json(req): Json<AddMembersRequest>,
+) -> AppResult<Json<ConversationWithDetails>> {
+    let user_id = get_user_id(&claims)?;
+
+    if req.user_ids.is_empty() || req.user_ids.len() > 100 {
+        return Err(AppError::Validation(
+            "Add 1-100 members at a time".to_string(),
+        ));
+    }
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    let conversation = messaging_service
+        .add_members(conversation_id, user_id, req.user_ids)
+        .await?;
+
+    Ok(Json(conversation))
+}
+
+/// Remove a member, or leave the group when the path names the caller
+pub async fn remove_member(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path((conversation_id, member_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    messaging_service
+        .remove_member(conversation_id, user_id, member_id)
+        .await?;
+
+    let message = if member_id == user_id {
+        "Left conversation"
+    } else {
+        "Member removed"
+    };
+
+    Ok(Json(MessageResponse {
+        message: message.to_string(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+    #[serde(default)]
+    pub offset: i32,
+}
+
+pub async fn search_messages(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+    Query(query): Query<SearchQuery>,
+) -> AppResult<Json<Vec<Message>>> {
+    let user_id = get_user_id(&claims)?;
+
+    let q = query.q.trim();
+    if q.is_empty() {
+        return Err(AppError::Validation("Search query is required".to_string()));
+    }
+
+    let limit = query.limit.clamp(1, 100);
+    let offset = query.offset.max(0);
+
+    let search_service = SearchService::new(state.db, state.search)?;
+    let messages = search_service
+        .search_messages(conversation_id, user_id, q, limit, offset)
+        .await?;
+
+    let urls = MediaUrls::new(&state.config);
+    Ok(Json(
+        messages
+            .into_iter()
+            .map(|message| urls.sign_message(message.decode()))
+            .collect(),
+    ))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct TypingRequest {
     pub is_typing: bool,
@@ -187,7 +652,7 @@ pub async fn send_typing(
 ) -> AppResult<Json<MessageResponse>> {
     let user_id = get_user_id(&claims)?;
 
-    let messaging_service = MessagingService::new(state.db, state.redis);
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
     messaging_service
         .broadcast_typing(conversation_id, user_id, req.is_typing)
         .await?;
@@ -196,3 +661,179 @@ pub async fn send_typing(
         message: "ok".to_string(),
     }))
 }
+
+pub async fn get_appearance(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+) -> AppResult<Json<ConversationAppearance>> {
+    let user_id = get_user_id(&claims)?;
+
+    let appearance_service = AppearanceService::new(state.db, state.redis, state.storage);
+    let appearance = appearance_service
+        .get_appearance(conversation_id, user_id)
+        .await?;
+
+    Ok(Json(appearance))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateAppearanceRequest {
+    pub theme_color: Option<String>,
+    pub reaction_emoji: Option<String>,
+}
+
+pub async fn update_appearance(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+    Json(req): Json<UpdateAppearanceRequest>,
+) -> AppResult<Json<ConversationAppearance>> {
+    let user_id = get_user_id(&claims)?;
+
+    if let Some(color) = &req.theme_color {
+        let is_hex_color = color.len() == 7
+            && color.starts_with('#')
+            && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+        if !is_hex_color {
+            return Err(AppError::Validation(
+                "theme_color must be a hex color like #3A7BD5".to_string(),
+            ));
+        }
+    }
+
+    if let Some(emoji) = &req.reaction_emoji {
+        // A `:name:` shortcode must be a custom emoji of the conversation
+        if let Some(name) = shortcode_name(emoji) {
+            EmojiService::new(state.db.clone(), state.storage.clone())
+                .resolve(conversation_id, name)
+                .await?
+                .ok_or_else(|| {
+                    AppError::Validation(format!("No custom emoji :{}: here", name))
+                })?;
+        } else if emoji.trim().is_empty() || emoji.chars().count() > 32 {
+            return Err(AppError::Validation(
+                "reaction_emoji must be 1-32 characters".to_string(),
+            ));
+        }
+    }
+
+    let appearance_service = AppearanceService::new(state.db, state.redis, state.storage);
+    let appearance = appearance_service
+        .update_appearance(conversation_id, user_id, req.theme_color, req.reaction_emoji)
+        .await?;
+
+    Ok(Json(appearance))
+}
+
+pub async fn upload_wallpaper(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+    mut multipart: Multipart,
+) -> AppResult<Json<ConversationAppearance>> {
+    let user_id = get_user_id(&claims)?;
+
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        AppError::BadRequest(format!("Failed to read multipart field: {}", e))
+    })? {
+        let name = field.name().unwrap_or("").to_string();
+        if name != "wallpaper" {
+            continue;
+        }
+
+        let content_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let data = field
+            .bytes()
+            .await
+            .map_err(|e| AppError::BadRequest(format!("Failed to read file: {}", e)))?;
+
+        ModerationPipeline::new(state.db.clone(), state.redis.clone(), &state.config.moderation)
+            .screen_upload(
+                state.storage.as_ref(),
+                &ContentSubject {
+                    source: ContentSource::Wallpaper,
+                    uploader_id: user_id,
+                    conversation_id: Some(conversation_id),
+                    content_type: Some(&content_type),
+                    data: &data,
+                },
+            )
+            .await?;
+
+        let appearance_service = AppearanceService::new(state.db, state.redis, state.storage);
+        let appearance = appearance_service
+            .set_wallpaper(conversation_id, user_id, data, &content_type)
+            .await?;
+
+        return Ok(Json(appearance));
+    }
+
+    Err(AppError::BadRequest("Wallpaper file required".to_string()))
+}
+
+pub async fn reset_appearance(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+) -> AppResult<Json<ConversationAppearance>> {
+    let user_id = get_user_id(&claims)?;
+
+    let appearance_service = AppearanceService::new(state.db, state.redis, state.storage);
+    let appearance = appearance_service
+        .reset_appearance(conversation_id, user_id)
+        .await?;
+
+    Ok(Json(appearance))
+}
+
+pub async fn get_translation_settings(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+) -> AppResult<Json<ConversationTranslationSettings>> {
+    let user_id = get_user_id(&claims)?;
+
+    let translation_service = TranslationService::new(state.db, state.redis, state.translator)?;
+    let settings = translation_service
+        .get_settings(conversation_id, user_id)
+        .await?;
+
+    Ok(Json(settings))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateTranslationSettingsRequest {
+    pub auto_translate: Option<bool>,
+    pub target_language: Option<String>,
+}
+
+pub async fn update_translation_settings(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+    Json(req): Json<UpdateTranslationSettingsRequest>,
+) -> AppResult<Json<ConversationTranslationSettings>> {
+    let user_id = get_user_id(&claims)?;
+
+    if let Some(language) = &req.target_language {
+        if !is_valid_language(language) {
+            return Err(AppError::Validation("Invalid language code".to_string()));
+        }
+    }
+
+    let translation_service = TranslationService::new(state.db, state.redis, state.translator)?;
+    let settings = translation_service
+        .update_settings(
+            conversation_id,
+            user_id,
+            req.auto_translate,
+            req.target_language,
+        )
+        .await?;
+
+    Ok(Json(settings))
+}
@@ -2,36 +2,72 @@ use axum::{
     extract::{Path, State},
     Extension, Json,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    error::AppResult,
-    models::Device,
-    services::auth::Claims,
+    error::{AppError, AppResult},
+    models::{Device, DeviceConnection, DeviceSettings},
+    services::{auth::Claims, devices::DeviceService, web_push::WebPushSubscription},
     AppState,
 };
 
-use super::super::middleware::get_user_id;
+use super::super::{
+    middleware::{get_device_id, get_user_id},
+    pagination::{Page, Paginated},
+};
+
+fn default_limit() -> i32 {
+    50
+}
 
 pub async fn get_devices(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
-) -> AppResult<Json<Vec<Device>>> {
+    page: Page,
+) -> AppResult<Json<Paginated<Device>>> {
     let user_id = get_user_id(&claims)?;
+    let limit = page.limit(default_limit());
+    let offset = page.offset()?;
 
     let devices: Vec<Device> = sqlx::query_as(
         r#"
-        SELECT id, user_id, device_id, name, platform, push_token, last_active_at, created_at
+        SELECT id, user_id, device_id, name, platform, app_version, push_token, verified,
+               last_active_at, created_at
         FROM devices WHERE user_id = $1
-        ORDER BY last_active_at DESC
+        ORDER BY last_active_at DESC, id
+        LIMIT $2 OFFSET $3
         "#,
     )
     .bind(user_id)
+    .bind(limit + 1)
+    .bind(offset)
     .fetch_all(&state.db)
     .await?;
 
-    Ok(Json(devices))
+    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM devices WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(&state.db)
+        .await?;
+
+    Ok(Json(
+        Paginated::from_offset(devices, limit, offset).with_total(total),
+    ))
+}
+
+/// Which of the caller's devices hold a WebSocket, and where; for debugging
+/// a device that is not receiving events
+pub async fn get_connections(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<Vec<DeviceConnection>>> {
+    let user_id = get_user_id(&claims)?;
+
+    let connections = DeviceService::new(state.db, state.redis)
+        .connections(user_id)
+        .await?;
+
+    Ok(Json(connections))
 }
 
 #[derive(Debug, Serialize)]
@@ -56,3 +92,182 @@ pub async fn remove_device(
         message: "Device removed".to_string(),
     }))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct RenameDeviceRequest {
+    pub name: String,
+}
+
+pub async fn rename_device(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(device_uuid): Path<Uuid>,
+    Json(req): Json<RenameDeviceRequest>,
+) -> AppResult<Json<Device>> {
+    let user_id = get_user_id(&claims)?;
+
+    let name = req.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(AppError::Validation(
+            "Name must be 1-100 characters".to_string(),
+        ));
+    }
+
+    let device_service = DeviceService::new(state.db, state.redis);
+    let device = device_service.rename(user_id, device_uuid, name).await?;
+
+    Ok(Json(device))
+}
+
+/// Ask the primary device to verify a device; the result arrives as a
+/// `device_verification_resolved` WebSocket event
+pub async fn request_verification(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(device_uuid): Path<Uuid>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    let device_service = DeviceService::new(state.db, state.redis);
+    device_service
+        .request_verification(user_id, device_uuid)
+        .await?;
+
+    Ok(Json(MessageResponse {
+        message: "Verification requested".to_string(),
+    }))
+}
+
+pub async fn get_device_settings(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<DeviceSettings>> {
+    let user_id = get_user_id(&claims)?;
+
+    let device_service = DeviceService::new(state.db, state.redis);
+    let settings = device_service.get_settings(user_id).await?;
+
+    Ok(Json(settings))
+}
+
+pub async fn update_device_settings(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<DeviceSettings>,
+) -> AppResult<Json<DeviceSettings>> {
+    let user_id = get_user_id(&claims)?;
+
+    let device_service = DeviceService::new(state.db, state.redis);
+    let settings = device_service.update_settings(user_id, &req).await?;
+
+    Ok(Json(settings))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PushTokenRequest {
+    pub push_token: Option<String>,
+}
+
+/// Register (or clear, with `null`) the push token of the calling device
+pub async fn update_push_token(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<PushTokenRequest>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+    let device_id = get_device_id(&claims)?;
+
+    let push_token = req.push_token.filter(|t| !t.is_empty());
+    if push_token.as_ref().is_some_and(|t| t.len() > 4096) {
+        return Err(AppError::Validation("Push token too long".to_string()));
+    }
+
+    sqlx::query("UPDATE devices SET push_token = $1 WHERE user_id = $2 AND device_id = $3")
+        .bind(&push_token)
+        .bind(user_id)
+        .bind(device_id)
+        .execute(&state.db)
+        .await?;
+
+    Ok(Json(MessageResponse {
+        message: "Push token updated".to_string(),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct VapidKeyResponse {
+    /// Pass as `applicationServerKey` to `PushManager.subscribe()`
+    pub public_key: String,
+}
+
+/// The server's VAPID public key, which browsers subscribe with
+pub async fn get_vapid_key(State(state): State<AppState>) -> AppResult<Json<VapidKeyResponse>> {
+    let public_key = state
+        .config
+        .notifications
+        .vapid_public_key
+        .clone()
+        .ok_or(AppError::WebPushDisabled)?;
+
+    Ok(Json(VapidKeyResponse { public_key }))
+}
+
+/// Register the calling device's browser push subscription; it replaces
+/// any push token the device had
+pub async fn register_web_push(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(subscription): Json<WebPushSubscription>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+    let device_id = get_device_id(&claims)?;
+
+    if state.config.notifications.vapid_public_key.is_none() {
+        return Err(AppError::WebPushDisabled);
+    }
+    subscription.validate()?;
+
+    sqlx::query("UPDATE devices SET push_token = $1 WHERE user_id = $2 AND device_id = $3")
+        .bind(serde_json::to_string(&subscription)?)
+        .bind(user_id)
+        .bind(device_id)
+        .execute(&state.db)
+        .await?;
+
+    Ok(Json(MessageResponse {
+        message: "Web Push subscription registered".to_string(),
+    }))
+}
+
+/// Remove the calling device's browser push subscription
+pub async fn unregister_web_push(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+    let device_id = get_device_id(&claims)?;
+
+    let push_token: Option<Option<String>> =
+        sqlx::query_scalar("SELECT push_token FROM devices WHERE user_id = $1 AND device_id = $2")
+            .bind(user_id)
+            .bind(device_id)
+            .fetch_optional(&state.db)
+            .await?;
+
+    // A native push token registered since is left alone
+    let subscribed = push_token
+        .flatten()
+        .and_then(|token| WebPushSubscription::from_push_token(&token))
+        .is_some();
+    if subscribed {
+        sqlx::query("UPDATE devices SET push_token = NULL WHERE user_id = $1 AND device_id = $2")
+            .bind(user_id)
+            .bind(device_id)
+            .execute(&state.db)
+            .await?;
+    }
+
+    Ok(Json(MessageResponse {
+        message: "Web Push subscription removed".to_string(),
+    }))
+}
@@ -0,0 +1,216 @@
+use axum::{
+    extract::{Multipart, Path, State},
+    Extension, Json,
+};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{CustomEmoji, GroupAction},
+    services::{
+        auth::Claims,
+        content_moderation::{ContentSource, ContentSubject, ModerationPipeline},
+        emoji::EmojiService,
+        messaging::MessagingService,
+    },
+    AppState,
+};
+
+use super::super::middleware::get_user_id;
+
+#[derive(Debug, Serialize)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameEmojiRequest {
+    pub name: String,
+}
+
+/// The `name` and `image` fields of an emoji upload
+async fn read_upload(mut multipart: Multipart) -> AppResult<(String, Bytes, String)> {
+    let mut name = None;
+    let mut image = None;
+
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        AppError::BadRequest(format!("Failed to read multipart field: {}", e))
+    })? {
+        match field.name().unwrap_or("") {
+            "name" => {
+                name = Some(
+                    field
+                        .text()
+                        .await
+                        .map_err(|e| AppError::BadRequest(format!("Failed to read name: {}", e)))?,
+                );
+            }
+            "image" => {
+                let content_type = field
+                    .content_type()
+                    .unwrap_or("application/octet-stream")
+                    .to_string();
+                let data = field
+                    .bytes()
+                    .await
+                    .map_err(|e| AppError::BadRequest(format!("Failed to read file: {}", e)))?;
+                image = Some((data, content_type));
+            }
+            _ => {}
+        }
+    }
+
+    let name = name.ok_or_else(|| AppError::BadRequest("Emoji name required".to_string()))?;
+    let (data, content_type) =
+        image.ok_or_else(|| AppError::BadRequest("Emoji image required".to_string()))?;
+    Ok((name, data, content_type))
+}
+
+/// Screen and store an uploaded emoji for a group, or for everyone
+async fn create_emoji(
+    state: AppState,
+    user_id: Uuid,
+    conversation_id: Option<Uuid>,
+    multipart: Multipart,
+) -> AppResult<CustomEmoji> {
+    let (name, data, content_type) = read_upload(multipart).await?;
+
+    ModerationPipeline::new(state.db.clone(), state.redis.clone(), &state.config.moderation)
+        .screen_upload(
+            state.storage.as_ref(),
+            &ContentSubject {
+                source: ContentSource::Emoji,
+                uploader_id: user_id,
+                conversation_id,
+                content_type: Some(&content_type),
+                data: &data,
+            },
+        )
+        .await?;
+
+    EmojiService::new(state.db, state.storage)
+        .create(conversation_id, user_id, &name, data, &content_type)
+        .await
+}
+
+async fn require_change_info(
+    state: &AppState,
+    conversation_id: Uuid,
+    user_id: Uuid,
+) -> AppResult<()> {
+    MessagingService::new(state.db.clone(), state.redis.clone(), (*state.config).clone())
+        .require_group_permission(conversation_id, user_id, GroupAction::ChangeInfo)
+        .await
+}
+
+/// Emoji available in every conversation
+pub async fn list_emoji(State(state): State<AppState>) -> AppResult<Json<Vec<CustomEmoji>>> {
+    let emoji = EmojiService::new(state.db, state.storage).list(None).await?;
+
+    Ok(Json(emoji))
+}
+
+/// Emoji available in a conversation, for rendering `:name:` shortcodes
+pub async fn list_conversation_emoji(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+) -> AppResult<Json<Vec<CustomEmoji>>> {
+    let user_id = get_user_id(&claims)?;
+
+    let emoji = EmojiService::new(state.db, state.storage)
+        .list_for_conversation(conversation_id, user_id)
+        .await?;
+
+    Ok(Json(emoji))
+}
+
+/// Add an emoji to a group; needs the group's `change_info` permission
+pub async fn create_conversation_emoji(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(conversation_id): Path<Uuid>,
+    multipart: Multipart,
+) -> AppResult<Json<CustomEmoji>> {
+    let user_id = get_user_id(&claims)?;
+    require_change_info(&state, conversation_id, user_id).await?;
+
+    let emoji = create_emoji(state, user_id, Some(conversation_id), multipart).await?;
+
+    Ok(Json(emoji))
+}
+
+pub async fn rename_conversation_emoji(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path((conversation_id, emoji_id)): Path<(Uuid, Uuid)>,
+    Json(req): Json<RenameEmojiRequest>,
+) -> AppResult<Json<CustomEmoji>> {
+    let user_id = get_user_id(&claims)?;
+    require_change_info(&state, conversation_id, user_id).await?;
+
+    let emoji = EmojiService::new(state.db, state.storage)
+        .rename(Some(conversation_id), emoji_id, &req.name)
+        .await?;
+
+    Ok(Json(emoji))
+}
+
+pub async fn delete_conversation_emoji(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path((conversation_id, emoji_id)): Path<(Uuid, Uuid)>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+    require_change_info(&state, conversation_id, user_id).await?;
+
+    EmojiService::new(state.db, state.storage)
+        .delete(Some(conversation_id), emoji_id)
+        .await?;
+
+    Ok(Json(MessageResponse {
+        message: "Emoji deleted".to_string(),
+    }))
+}
+
+// Admin endpoints
+
+/// Add an emoji available in every conversation (admin)
+pub async fn create_global_emoji(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    multipart: Multipart,
+) -> AppResult<Json<CustomEmoji>> {
+    let user_id = get_user_id(&claims)?;
+
+    let emoji = create_emoji(state, user_id, None, multipart).await?;
+
+    Ok(Json(emoji))
+}
+
+pub async fn rename_global_emoji(
+    State(state): State<AppState>,
+    Path(emoji_id): Path<Uuid>,
+    Json(req): Json<RenameEmojiRequest>,
+) -> AppResult<Json<CustomEmoji>> {
+    let emoji = EmojiService::new(state.db, state.storage)
+        .rename(None, emoji_id, &req.name)
+        .await?;
+
+    Ok(Json(emoji))
+}
+
+pub async fn delete_global_emoji(
+    State(state): State<AppState>,
+    Path(emoji_id): Path<Uuid>,
+) -> AppResult<Json<MessageResponse>> {
+    EmojiService::new(state.db, state.storage)
+        .delete(None, emoji_id)
+        .await?;
+
+    Ok(Json(MessageResponse {
+        message: "Emoji deleted".to_string(),
+    }))
+}
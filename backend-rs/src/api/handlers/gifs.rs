@@ -0,0 +1,43 @@
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::Deserialize;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::GifSearchResponse,
+    services::gifs::GifService,
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct GifSearchQuery {
+    pub q: String,
+    #[serde(default = "default_limit")]
+    pub limit: u32,
+    /// Cursor from a previous response's `next`
+    pub next: Option<String>,
+}
+
+fn default_limit() -> u32 {
+    20
+}
+
+pub async fn search_gifs(
+    State(state): State<AppState>,
+    Query(query): Query<GifSearchQuery>,
+) -> AppResult<Json<GifSearchResponse>> {
+    if query.q.trim().is_empty() || query.q.chars().count() > 100 {
+        return Err(AppError::Validation(
+            "Query must be 1-100 characters".to_string(),
+        ));
+    }
+
+    let gif_service = GifService::new(state.redis, state.gifs, state.config.gifs.clone())?;
+    let results = gif_service
+        .search(&query.q, query.limit.clamp(1, 50), query.next.as_deref())
+        .await?;
+
+    Ok(Json(results))
+}
@@ -0,0 +1,35 @@
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+
+use crate::{
+    models::{HealthReport, HealthStatus},
+    services::health::HealthService,
+    AppState,
+};
+
+fn health_service(state: AppState) -> HealthService {
+    HealthService::new(state.db, state.redis, state.storage, state.ws_hub, state.slow_log)
+}
+
+/// Readiness probe: 503 while the database or Redis is unreachable; a
+/// degraded instance still answers 200 and lists why
+pub async fn readiness(State(state): State<AppState>) -> (StatusCode, Json<HealthReport>) {
+    let report = health_service(state).report().await;
+    let status = match report.status {
+        HealthStatus::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        HealthStatus::Ready | HealthStatus::Degraded => StatusCode::OK,
+    };
+
+    (status, Json(report))
+}
+
+/// Gauges and counters in the Prometheus text format
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let body = health_service(state).metrics().await;
+
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
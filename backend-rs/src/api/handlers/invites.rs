@@ -0,0 +1,132 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::AppResult,
+    models::{Invite, InviteSummary, Referral},
+    services::{auth::Claims, invites::InviteService},
+    AppState,
+};
+
+use super::super::middleware::get_user_id;
+
+#[derive(Debug, Serialize)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+/// Your invites, what is left of your quota and how many joined
+pub async fn get_invites(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<InviteSummary>> {
+    let user_id = get_user_id(&claims)?;
+
+    let invites = InviteService::new(state.db, state.config.invites.clone());
+    let summary = invites.summary(user_id).await?;
+
+    Ok(Json(summary))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInviteRequest {
+    /// Admins only; invites are single-use otherwise
+    pub max_uses: Option<i32>,
+    /// Defaults to `INVITE_TTL`
+    pub expires_in_days: Option<i64>,
+}
+
+pub async fn create_invite(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<CreateInviteRequest>,
+) -> AppResult<(StatusCode, Json<Invite>)> {
+    let user_id = get_user_id(&claims)?;
+
+    let invites = InviteService::new(state.db, state.config.invites.clone());
+    let invite = invites
+        .create(user_id, req.max_uses, req.expires_in_days)
+        .await?;
+
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+pub async fn revoke_invite(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(invite_id): Path<Uuid>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    let invites = InviteService::new(state.db, state.config.invites.clone());
+    invites.revoke(Some(user_id), invite_id).await?;
+
+    Ok(Json(MessageResponse {
+        message: "Invite revoked".to_string(),
+    }))
+}
+
+/// People who registered with your invites
+pub async fn get_referrals(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<Vec<Referral>>> {
+    let user_id = get_user_id(&claims)?;
+
+    let invites = InviteService::new(state.db, state.config.invites.clone());
+    let referrals = invites.referrals(user_id).await?;
+
+    Ok(Json(referrals))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminInvitesQuery {
+    pub user_id: Option<Uuid>,
+}
+
+/// Every user's invites, or one user's
+pub async fn admin_list_invites(
+    State(state): State<AppState>,
+    Query(query): Query<AdminInvitesQuery>,
+) -> AppResult<Json<Vec<Invite>>> {
+    let invites = InviteService::new(state.db, state.config.invites.clone());
+    let list = invites.list(query.user_id).await?;
+
+    Ok(Json(list))
+}
+
+pub async fn admin_revoke_invite(
+    State(state): State<AppState>,
+    Path(invite_id): Path<Uuid>,
+) -> AppResult<Json<MessageResponse>> {
+    let invites = InviteService::new(state.db, state.config.invites.clone());
+    invites.revoke(None, invite_id).await?;
+
+    Ok(Json(MessageResponse {
+        message: "Invite revoked".to_string(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InviteQuotaRequest {
+    /// `null` goes back to `INVITE_QUOTA`
+    pub quota: Option<i32>,
+}
+
+pub async fn admin_set_invite_quota(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    Json(req): Json<InviteQuotaRequest>,
+) -> AppResult<Json<MessageResponse>> {
+    let invites = InviteService::new(state.db, state.config.invites.clone());
+    invites.set_quota(user_id, req.quota).await?;
+
+    Ok(Json(MessageResponse {
+        message: "Invite quota updated".to_string(),
+    }))
+}
@@ -0,0 +1,135 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header::AUTHORIZATION, HeaderMap},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    services::{matrix::MatrixBridge, messaging::MessagingService},
+    AppState,
+};
+
+/// Transactions already applied, so homeserver retries are no-ops
+const TRANSACTION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Serialize)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AppServiceQuery {
+    /// Legacy way homeservers send the token
+    pub access_token: Option<String>,
+}
+
+/// The bridge, after checking the homeserver's token
+fn authorized_bridge(
+    state: &AppState,
+    headers: &HeaderMap,
+    query: &AppServiceQuery,
+) -> AppResult<Arc<MatrixBridge>> {
+    let bridge = state.matrix.clone().ok_or(AppError::MatrixBridgeDisabled)?;
+
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .or(query.access_token.as_deref());
+    bridge.verify_hs_token(token)?;
+
+    Ok(bridge)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionRequest {
+    #[serde(default)]
+    pub events: Vec<Value>,
+}
+
+pub async fn push_transaction(
+    State(state): State<AppState>,
+    Path(txn_id): Path<String>,
+    Query(query): Query<AppServiceQuery>,
+    headers: HeaderMap,
+    Json(req): Json<TransactionRequest>,
+) -> AppResult<Json<Value>> {
+    let bridge = authorized_bridge(&state, &headers, &query)?;
+
+    let key = format!("matrix:txn:{}", txn_id);
+    if state.redis.get_cached(&key).await?.is_some() {
+        return Ok(Json(json!({})));
+    }
+
+    // No bridge on this service, so messages from Matrix aren't echoed back
+    let messaging = MessagingService::new(state.db, state.redis.clone(), (*state.config).clone())
+        .with_search_index(state.search)
+        .with_translator(state.translator);
+    bridge.handle_transaction(&messaging, &req.events).await?;
+
+    state.redis.set_cached(&key, "1", TRANSACTION_TTL).await?;
+
+    Ok(Json(json!({})))
+}
+
+/// Virtual users are created when a local user first speaks, not on query
+pub async fn query_user(
+    State(state): State<AppState>,
+    Path(_user_id): Path<String>,
+    Query(query): Query<AppServiceQuery>,
+    headers: HeaderMap,
+) -> AppResult<Json<Value>> {
+    authorized_bridge(&state, &headers, &query)?;
+    Err(AppError::UserNotFound)
+}
+
+/// Rooms are linked by an admin, never created from an alias
+pub async fn query_room(
+    State(state): State<AppState>,
+    Path(_alias): Path<String>,
+    Query(query): Query<AppServiceQuery>,
+    headers: HeaderMap,
+) -> AppResult<Json<Value>> {
+    authorized_bridge(&state, &headers, &query)?;
+    Err(AppError::ConversationNotFound)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LinkRoomRequest {
+    pub room_id: String,
+}
+
+pub async fn link_room(
+    State(state): State<AppState>,
+    Path(conversation_id): Path<Uuid>,
+    Json(req): Json<LinkRoomRequest>,
+) -> AppResult<Json<MessageResponse>> {
+    let bridge = state.matrix.ok_or(AppError::MatrixBridgeDisabled)?;
+
+    bridge
+        .link_room(conversation_id, req.room_id.trim())
+        .await?;
+
+    Ok(Json(MessageResponse {
+        message: "Conversation linked".to_string(),
+    }))
+}
+
+pub async fn unlink_room(
+    State(state): State<AppState>,
+    Path(conversation_id): Path<Uuid>,
+) -> AppResult<Json<MessageResponse>> {
+    let bridge = state.matrix.ok_or(AppError::MatrixBridgeDisabled)?;
+
+    bridge.unlink_room(conversation_id).await?;
+
+    Ok(Json(MessageResponse {
+        message: "Conversation unlinked".to_string(),
+    }))
+}
@@ -0,0 +1,131 @@
+use axum::{
+    extract::{Multipart, Path, Query, State},
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{Attachment, AttachmentUrl, RenditionKind},
+    services::{
+        auth::Claims,
+        content_moderation::{ContentSource, ContentSubject, ModerationPipeline},
+        media::MediaService,
+        message_policy,
+    },
+    AppState,
+};
+
+use super::super::middleware::get_user_id;
+
+#[derive(Debug, Serialize)]
+pub struct AttachmentResponse {
+    #[serde(flatten)]
+    pub attachment: Attachment,
+    #[serde(flatten)]
+    pub link: AttachmentUrl,
+}
+
+/// Upload message media ahead of sending it; the returned ID goes in the
+/// message's `attachment_id`
+pub async fn upload_attachment(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    mut multipart: Multipart,
+) -> AppResult<Json<AttachmentResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::BadRequest(format!("Failed to read multipart field: {}", e)))?
+    {
+        let name = field.name().unwrap_or("").to_string();
+        if name != "file" {
+            continue;
+        }
+
+        let content_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let data = field
+            .bytes()
+            .await
+            .map_err(|e| AppError::BadRequest(format!("Failed to read file: {}", e)))?;
+        message_policy::check_upload(&state.config.message_limits, &content_type, data.len())?;
+
+        ModerationPipeline::new(
+            state.db.clone(),
+            state.redis.clone(),
+            &state.config.moderation,
+        )
+        .screen_upload(
+            state.storage.as_ref(),
+            &ContentSubject {
+                source: ContentSource::Attachment,
+                uploader_id: user_id,
+                conversation_id: None,
+                content_type: Some(&content_type),
+                data: &data,
+            },
+        )
+        .await?;
+
+        let media_service = MediaService::new(state.db, state.storage, &state.config);
+        let (attachment, link) = media_service.upload(user_id, data, &content_type).await?;
+
+        return Ok(Json(AttachmentResponse { attachment, link }));
+    }
+
+    Err(AppError::BadRequest("File required".to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignedLinkQuery {
+    pub expires: i64,
+    pub sig: String,
+    /// A transcoded copy instead of the original
+    pub rendition: Option<RenditionKind>,
+}
+
+/// Serve an attachment through a signed link. Public, since links are used
+/// directly as image and video sources; the signature is the credential.
+pub async fn download_media(
+    State(state): State<AppState>,
+    Path(attachment_id): Path<Uuid>,
+    Query(query): Query<SignedLinkQuery>,
+) -> AppResult<impl IntoResponse> {
+    let media_service = MediaService::new(state.db, state.storage, &state.config);
+    let (content_type, data) = media_service
+        .open(attachment_id, query.expires, &query.sig, query.rendition)
+        .await?;
+
+    // Cache no longer than the link is valid
+    let max_age = (query.expires - chrono::Utc::now().timestamp()).max(0);
+
+    Ok((
+        [
+            (CONTENT_TYPE, content_type),
+            (CACHE_CONTROL, format!("private, max-age={}", max_age)),
+        ],
+        data,
+    ))
+}
+
+/// A fresh link for a message's attachment once the one it was sent with expires
+pub async fn refresh_attachment(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(message_id): Path<Uuid>,
+) -> AppResult<Json<AttachmentUrl>> {
+    let user_id = get_user_id(&claims)?;
+
+    let media_service = MediaService::new(state.db, state.storage, &state.config);
+    let link = media_service.refresh(message_id, user_id).await?;
+
+    Ok(Json(link))
+}
@@ -2,22 +2,135 @@ use axum::{
     extract::{Path, State},
     Extension, Json,
 };
-use serde::Serialize;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    error::AppResult,
-    services::{auth::Claims, messaging::MessagingService},
+    error::{AppError, AppResult},
+    models::{Message, MessageDelivery, MessagePayload, MessageReport, MessageTranslation},
+    services::{
+        auth::Claims,
+        delivery::DeliveryService,
+        messaging::MessagingService,
+        notifications::NotificationService,
+        reports::ReportService,
+        translation::{is_valid_language, TranslationService},
+        view_once::ViewOnceService,
+    },
     AppState,
 };
 
-use super::super::middleware::get_user_id;
+use super::super::middleware::{get_device_id, get_user_id};
 
 #[derive(Debug, Serialize)]
 pub struct MessageResponse {
     pub message: String,
 }
 
+/// Upper bound on message IDs per batch receipt request
+const MAX_BATCH_RECEIPTS: usize = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct BatchReceiptRequest {
+    pub message_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchReceiptResponse {
+    pub acknowledged: u64,
+}
+
+fn validate_batch(req: &BatchReceiptRequest) -> AppResult<()> {
+    if req.message_ids.is_empty() {
+        return Err(AppError::Validation("message_ids is required".to_string()));
+    }
+
+    if req.message_ids.len() > MAX_BATCH_RECEIPTS {
+        return Err(AppError::Validation(format!(
+            "At most {} message IDs per request",
+            MAX_BATCH_RECEIPTS
+        )));
+    }
+
+    Ok(())
+}
+
+pub async fn mark_many_delivered(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<BatchReceiptRequest>,
+) -> AppResult<Json<BatchReceiptResponse>> {
+    let user_id = get_user_id(&claims)?;
+    validate_batch(&req)?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    let acknowledged = messaging_service
+        .mark_many_as_delivered(&req.message_ids, user_id)
+        .await?;
+
+    Ok(Json(BatchReceiptResponse { acknowledged }))
+}
+
+pub async fn mark_many_read(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<BatchReceiptRequest>,
+) -> AppResult<Json<BatchReceiptResponse>> {
+    let user_id = get_user_id(&claims)?;
+    validate_batch(&req)?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
+    let acknowledged = messaging_service
+        .mark_many_as_read(&req.message_ids, user_id)
+        .await?;
+
+    Ok(Json(BatchReceiptResponse { acknowledged }))
+}
+
+fn delivery_service(state: AppState) -> DeliveryService {
+    let notifications = NotificationService::new(
+        state.db.clone(),
+        state.redis.clone(),
+        state.push,
+        state.email,
+    );
+    DeliveryService::new(state.db, state.redis, (*state.config).clone(), notifications)
+}
+
+/// Acknowledge messages on this device, e.g. after fetching them following a
+/// push; WebSocket clients send an `ack` event instead
+pub async fn ack_messages(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<BatchReceiptRequest>,
+) -> AppResult<Json<BatchReceiptResponse>> {
+    let user_id = get_user_id(&claims)?;
+    let device_id = get_device_id(&claims)?;
+    validate_batch(&req)?;
+
+    let acknowledged = delivery_service(state)
+        .ack(user_id, device_id, &req.message_ids)
+        .await?;
+
+    Ok(Json(BatchReceiptResponse { acknowledged }))
+}
+
+/// Per-device delivery state of one of your messages
+pub async fn get_deliveries(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(message_id): Path<Uuid>,
+) -> AppResult<Json<Vec<MessageDelivery>>> {
+    let user_id = get_user_id(&claims)?;
+
+    let deliveries = delivery_service(state)
+        .deliveries(message_id, user_id)
+        .await?;
+
+    Ok(Json(deliveries))
+}
+
 pub async fn mark_delivered(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
@@ -25,7 +138,7 @@ pub async fn mark_delivered(
 ) -> AppResult<Json<MessageResponse>> {
     let user_id = get_user_id(&claims)?;
 
-    let messaging_service = MessagingService::new(state.db, state.redis);
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
     messaging_service.mark_as_delivered(message_id, user_id).await?;
 
     Ok(Json(MessageResponse {
@@ -40,7 +153,7 @@ pub async fn mark_read(
 ) -> AppResult<Json<MessageResponse>> {
     let user_id = get_user_id(&claims)?;
 
-    let messaging_service = MessagingService::new(state.db, state.redis);
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone());
     messaging_service.mark_as_read(message_id, user_id).await?;
 
     Ok(Json(MessageResponse {
@@ -55,10 +168,127 @@ pub async fn delete_message(
 ) -> AppResult<Json<MessageResponse>> {
     let user_id = get_user_id(&claims)?;
 
-    let messaging_service = MessagingService::new(state.db, state.redis);
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone())
+        .with_search_index(state.search);
     messaging_service.delete_message(message_id, user_id).await?;
 
     Ok(Json(MessageResponse {
         message: "Message deleted".to_string(),
     }))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct EditMessageRequest {
+    pub payload: MessagePayload,
+}
+
+pub async fn edit_message(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(message_id): Path<Uuid>,
+    Json(req): Json<EditMessageRequest>,
+) -> AppResult<Json<Message>> {
+    let user_id = get_user_id(&claims)?;
+
+    let messaging_service = MessagingService::new(state.db, state.redis, (*state.config).clone())
+        .with_search_index(state.search)
+        .with_translator(state.translator);
+    let message = messaging_service
+        .edit_message(message_id, user_id, req.payload)
+        .await?;
+
+    Ok(Json(message))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TranslateMessageRequest {
+    /// Target language code such as `en` or `pt-BR`
+    pub language: String,
+}
+
+pub async fn translate_message(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(message_id): Path<Uuid>,
+    Json(req): Json<TranslateMessageRequest>,
+) -> AppResult<Json<MessageTranslation>> {
+    let user_id = get_user_id(&claims)?;
+
+    if !is_valid_language(&req.language) {
+        return Err(AppError::Validation("Invalid language code".to_string()));
+    }
+
+    let translation_service = TranslationService::new(state.db, state.redis, state.translator)?;
+    let translation = translation_service
+        .translate_message(message_id, user_id, &req.language)
+        .await?;
+
+    Ok(Json(translation))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportMessageRequest {
+    pub reason: String,
+    pub details: Option<String>,
+    /// Encrypted messages: the decrypted plaintext, base64
+    pub plaintext: Option<String>,
+    /// Encrypted messages: the franking key from inside the ciphertext, base64
+    pub franking_key: Option<String>,
+}
+
+/// Report a received message to the moderators
+pub async fn report_message(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(message_id): Path<Uuid>,
+    Json(req): Json<ReportMessageRequest>,
+) -> AppResult<Json<MessageReport>> {
+    let user_id = get_user_id(&claims)?;
+
+    let decode = |field: &str, value: Option<String>| {
+        value
+            .map(|v| BASE64.decode(v))
+            .transpose()
+            .map_err(|_| AppError::Validation(format!("{} must be base64", field)))
+    };
+    let plaintext = decode("plaintext", req.plaintext)?;
+    let franking_key = decode("franking_key", req.franking_key)?;
+
+    let report_service = ReportService::new(state.db, state.redis, &state.config);
+    let report = report_service
+        .report(
+            user_id,
+            message_id,
+            &req.reason,
+            req.details,
+            plaintext,
+            franking_key,
+        )
+        .await?;
+
+    Ok(Json(report))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ViewOnceMediaResponse {
+    pub message_id: Uuid,
+    /// Base64, like `payload.media`
+    pub media: String,
+}
+
+/// One-time fetch of view-once media; later requests get 410 Gone
+pub async fn open_view_once_media(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(message_id): Path<Uuid>,
+) -> AppResult<Json<ViewOnceMediaResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    let view_once_service = ViewOnceService::new(state.db, state.redis, state.config.view_once.ttl);
+    let content = view_once_service.open(message_id, user_id).await?;
+
+    Ok(Json(ViewOnceMediaResponse {
+        message_id,
+        media: BASE64.encode(content),
+    }))
+}
@@ -1,8 +1,22 @@
+pub mod admin;
+pub mod api_tokens;
 pub mod auth;
+pub mod broadcasts;
+pub mod client;
 pub mod contacts;
 pub mod conversations;
 pub mod devices;
+pub mod emoji;
+pub mod gifs;
+pub mod health;
+pub mod invites;
 pub mod keys;
+pub mod matrix;
+pub mod media;
 pub mod messages;
+pub mod organizations;
+pub mod sealed_sender;
+pub mod search;
 pub mod stickers;
 pub mod users;
+pub mod waitlist;
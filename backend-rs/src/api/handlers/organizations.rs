@@ -0,0 +1,111 @@
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{
+        DirectoryEntry, DirectoryImportEntry, DirectoryImportSummary, Organization,
+        OrganizationWithCounts,
+    },
+    services::{auth::Claims, messaging::MessagingService, organizations::OrganizationService},
+    AppState,
+};
+
+use super::super::middleware::get_user_id;
+
+fn organization_service(state: AppState) -> OrganizationService {
+    let messaging = MessagingService::new(state.db.clone(), state.redis, (*state.config).clone())
+        .with_search_index(state.search)
+        .with_translator(state.translator)
+        .with_bridge(state.matrix);
+    OrganizationService::new(state.db, messaging)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DirectoryQuery {
+    pub q: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+    #[serde(default)]
+    pub offset: i32,
+}
+
+fn default_limit() -> i32 {
+    50
+}
+
+pub async fn get_directory(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<DirectoryQuery>,
+) -> AppResult<Json<Vec<DirectoryEntry>>> {
+    let user_id = get_user_id(&claims)?;
+
+    let search = query.q.as_deref().filter(|q| !q.trim().is_empty());
+    let entries = organization_service(state)
+        .get_directory(
+            user_id,
+            search,
+            query.limit.clamp(1, 200),
+            query.offset.max(0),
+        )
+        .await?;
+
+    Ok(Json(entries))
+}
+
+pub async fn list_organizations(
+    State(state): State<AppState>,
+) -> AppResult<Json<Vec<OrganizationWithCounts>>> {
+    let organizations = organization_service(state).list_organizations().await?;
+
+    Ok(Json(organizations))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateOrganizationRequest {
+    pub name: String,
+}
+
+pub async fn create_organization(
+    State(state): State<AppState>,
+    Json(req): Json<CreateOrganizationRequest>,
+) -> AppResult<Json<Organization>> {
+    let name = req.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err(AppError::Validation(
+            "Name must be 1-100 characters".to_string(),
+        ));
+    }
+
+    let organization = organization_service(state)
+        .create_organization(name)
+        .await?;
+
+    Ok(Json(organization))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportDirectoryRequest {
+    /// The full directory; anyone left out is removed
+    pub members: Vec<DirectoryImportEntry>,
+}
+
+pub async fn import_directory(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(organization_id): Path<Uuid>,
+    Json(req): Json<ImportDirectoryRequest>,
+) -> AppResult<Json<DirectoryImportSummary>> {
+    let user_id = get_user_id(&claims)?;
+
+    let summary = organization_service(state)
+        .import_directory(organization_id, user_id, req.members)
+        .await?;
+
+    Ok(Json(summary))
+}
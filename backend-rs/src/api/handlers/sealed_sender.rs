@@ -0,0 +1,149 @@
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{DeliveryCertificate, OutgoingEnvelope, SealedEnvelope},
+    services::{auth::Claims, sealed_sender::SealedSenderService},
+    AppState,
+};
+
+use super::super::middleware::{get_device_id, get_user_id};
+
+/// Headers a sealed submission authenticates with, in place of a bearer token
+const DELIVERY_TOKEN_HEADER: &str = "delivery-token";
+const ACCESS_KEY_HEADER: &str = "unidentified-access-key";
+
+/// Upper bound on envelope IDs per acknowledgement
+const MAX_BATCH_ACKS: usize = 500;
+
+#[derive(Debug, Serialize)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccessKeyRequest {
+    /// 16 random bytes, base64; shared with contacts in the encrypted
+    /// profile. Null refuses sealed messages.
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SendSealedRequest {
+    pub envelopes: Vec<OutgoingEnvelope>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SendSealedResponse {
+    pub envelope_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AckSealedRequest {
+    pub envelope_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AckSealedResponse {
+    pub acknowledged: u64,
+}
+
+fn sealed_sender_service(state: AppState) -> SealedSenderService {
+    SealedSenderService::new(state.db, state.redis, (*state.config).clone())
+}
+
+pub async fn get_delivery_certificate(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<DeliveryCertificate>> {
+    let user_id = get_user_id(&claims)?;
+    let device_id = get_device_id(&claims)?;
+
+    let certificate = sealed_sender_service(state)
+        .issue_certificate(user_id, device_id)
+        .await?;
+
+    Ok(Json(certificate))
+}
+
+pub async fn update_access_key(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<AccessKeyRequest>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    sealed_sender_service(state)
+        .set_access_key(user_id, req.key.as_deref())
+        .await?;
+
+    let message = if req.key.is_some() {
+        "Sealed messages enabled"
+    } else {
+        "Sealed messages disabled"
+    };
+    Ok(Json(MessageResponse {
+        message: message.to_string(),
+    }))
+}
+
+/// Submit a message without an access token; see `SealedSenderService`
+pub async fn send_sealed_message(
+    State(state): State<AppState>,
+    Path(recipient_id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(req): Json<SendSealedRequest>,
+) -> AppResult<Json<SendSealedResponse>> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let delivery_token = header(DELIVERY_TOKEN_HEADER).ok_or(AppError::DeliveryTokenInvalid)?;
+    let access_key = header(ACCESS_KEY_HEADER).ok_or(AppError::Unauthorized)?;
+
+    let envelope_ids = sealed_sender_service(state)
+        .submit(recipient_id, delivery_token, access_key, req.envelopes)
+        .await?;
+
+    Ok(Json(SendSealedResponse { envelope_ids }))
+}
+
+/// Sealed messages waiting for this device
+pub async fn get_sealed_messages(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<Vec<SealedEnvelope>>> {
+    let user_id = get_user_id(&claims)?;
+    let device_id = get_device_id(&claims)?;
+
+    let envelopes = sealed_sender_service(state)
+        .pending(user_id, device_id)
+        .await?;
+
+    Ok(Json(envelopes))
+}
+
+pub async fn ack_sealed_messages(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<AckSealedRequest>,
+) -> AppResult<Json<AckSealedResponse>> {
+    let user_id = get_user_id(&claims)?;
+    let device_id = get_device_id(&claims)?;
+
+    if req.envelope_ids.is_empty() || req.envelope_ids.len() > MAX_BATCH_ACKS {
+        return Err(AppError::Validation(format!(
+            "Between 1 and {} envelope IDs per request",
+            MAX_BATCH_ACKS
+        )));
+    }
+
+    let acknowledged = sealed_sender_service(state)
+        .ack(user_id, device_id, &req.envelope_ids)
+        .await?;
+
+    Ok(Json(AckSealedResponse { acknowledged }))
+}
@@ -0,0 +1,89 @@
+use axum::{
+    extract::{Query, State},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{ContactWithUser, ConversationWithDetails, Message, Scope},
+    services::{
+        auth::Claims, contacts::ContactsService, media::MediaUrls, messaging::MessagingService,
+        search::SearchService,
+    },
+    AppState,
+};
+
+use super::super::middleware::get_user_id;
+
+/// Most results one group may hold
+const MAX_GROUP_LIMIT: i32 = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    /// Results per group
+    #[serde(default = "default_limit")]
+    pub limit: i32,
+}
+
+fn default_limit() -> i32 {
+    5
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub contacts: Vec<ContactWithUser>,
+    pub conversations: Vec<ConversationWithDetails>,
+    pub messages: Vec<Message>,
+}
+
+/// One search bar over contacts, conversation names and messages. Sessions
+/// without the contacts scope get no contacts, and messages are only
+/// searched when a search index is configured.
+pub async fn search(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<SearchQuery>,
+) -> AppResult<Json<SearchResults>> {
+    let user_id = get_user_id(&claims)?;
+
+    let q = query.q.trim();
+    if q.is_empty() {
+        return Err(AppError::Validation("Search query is required".to_string()));
+    }
+    let limit = query.limit.clamp(1, MAX_GROUP_LIMIT);
+
+    let contacts = if claims.allows(&[Scope::Contacts]) {
+        let contacts_service = ContactsService::new(state.db.clone());
+        contacts_service.search_contacts(user_id, q, limit).await?
+    } else {
+        Vec::new()
+    };
+
+    let messaging_service =
+        MessagingService::new(state.db.clone(), state.redis, (*state.config).clone());
+    let conversations = messaging_service
+        .search_conversations(user_id, q, limit)
+        .await?;
+
+    let messages = match SearchService::new(state.db, state.search) {
+        Ok(search_service) => {
+            search_service
+                .search_all_messages(user_id, q, limit)
+                .await?
+        }
+        Err(AppError::SearchDisabled) => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let urls = MediaUrls::new(&state.config);
+
+    Ok(Json(SearchResults {
+        contacts,
+        conversations,
+        messages: messages
+            .into_iter()
+            .map(|message| urls.sign_message(message.decode()))
+            .collect(),
+    }))
+}
@@ -1,5 +1,7 @@
 use axum::{
     extract::{Multipart, Path, Query, State},
+    http::HeaderMap,
+    response::Response,
     Extension, Json,
 };
 use serde::{Deserialize, Serialize};
@@ -7,20 +9,34 @@ use uuid::Uuid;
 
 use crate::{
     error::{AppError, AppResult},
-    models::{Sticker, StickerPack, StickerPackWithStickers},
-    services::{auth::Claims, stickers::StickersService},
+    models::{
+        CatalogSection, Sticker, StickerPack, StickerPackAnalytics, StickerPackSearchResult,
+        StickerPackWithStickers,
+    },
+    services::{
+        auth::Claims,
+        content_moderation::{ContentSource, ContentSubject, ModerationPipeline},
+        events::{DomainEvent, EventLog},
+        stickers::StickersService,
+    },
     AppState,
 };
 
-use super::super::middleware::get_user_id;
+use super::super::{
+    caching::{cached_json, etag_from},
+    middleware::get_user_id,
+    pagination::{Page, Paginated},
+};
+
+/// Catalog and pack metadata are revalidated with ETags after this many seconds
+const CATALOG_MAX_AGE: u64 = 300;
 
 #[derive(Debug, Deserialize)]
 pub struct CatalogQuery {
-    #[serde(default = "default_limit")]
-    pub limit: i32,
-    #[serde(default)]
-    pub offset: i32,
     pub official: Option<bool>,
+    pub section: Option<CatalogSection>,
+    /// Only packs carrying this tag
+    pub tag: Option<String>,
 }
 
 fn default_limit() -> i32 {
@@ -29,14 +45,49 @@ fn default_limit() -> i32 {
 
 pub async fn get_catalog(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Query(query): Query<CatalogQuery>,
-) -> AppResult<Json<Vec<StickerPack>>> {
-    let stickers_service = StickersService::new(state.db, state.minio);
+    page: Page,
+) -> AppResult<Response> {
+    let limit = page.limit(default_limit());
+    let offset = page.offset()?;
+
+    let stickers_service = StickersService::new(state.db, state.storage);
     let packs = stickers_service
-        .get_catalog(query.limit, query.offset, query.official)
+        .get_catalog(
+            limit + 1,
+            offset,
+            query.official,
+            query.section,
+            query.tag.as_deref(),
+        )
         .await?;
 
-    Ok(Json(packs))
+    let etag = etag_from(
+        [
+            limit.to_string(),
+            offset.to_string(),
+            format!("{:?}", query.official),
+            format!("{:?}", query.section),
+            format!("{:?}", query.tag),
+        ]
+        .into_iter()
+        .chain(packs.iter().map(|p| {
+            format!(
+                "{}:{}:{}",
+                p.id,
+                p.updated_at.timestamp_micros(),
+                p.downloads
+            )
+        })),
+    );
+
+    Ok(cached_json(
+        &headers,
+        &etag,
+        CATALOG_MAX_AGE,
+        Paginated::from_offset(packs, limit, offset),
+    ))
 }
 
 #[derive(Debug, Deserialize)]
@@ -44,30 +95,43 @@ pub struct SearchQuery {
     pub q: String,
     #[serde(default = "default_limit")]
     pub limit: i32,
+    #[serde(default)]
+    pub offset: i32,
 }
 
 pub async fn search_stickers(
     State(state): State<AppState>,
     Query(query): Query<SearchQuery>,
-) -> AppResult<Json<Vec<StickerPack>>> {
+) -> AppResult<Json<Vec<StickerPackSearchResult>>> {
     if query.q.is_empty() {
         return Err(AppError::BadRequest("Search query required".to_string()));
     }
 
-    let stickers_service = StickersService::new(state.db, state.minio);
-    let packs = stickers_service.search_packs(&query.q, query.limit).await?;
+    let stickers_service = StickersService::new(state.db, state.storage);
+    let packs = stickers_service
+        .search_packs(&query.q, query.limit.clamp(1, 100), query.offset.max(0))
+        .await?;
 
     Ok(Json(packs))
 }
 
 pub async fn get_sticker_pack(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Path(pack_id): Path<Uuid>,
-) -> AppResult<Json<StickerPackWithStickers>> {
-    let stickers_service = StickersService::new(state.db, state.minio);
+) -> AppResult<Response> {
+    let stickers_service = StickersService::new(state.db, state.storage);
     let pack = stickers_service.get_pack(pack_id).await?;
 
-    Ok(Json(pack))
+    // Adding stickers bumps the pack's updated_at
+    let etag = etag_from([
+        pack.pack.id.to_string(),
+        pack.pack.updated_at.timestamp_micros().to_string(),
+        pack.pack.downloads.to_string(),
+        pack.stickers.len().to_string(),
+    ]);
+
+    Ok(cached_json(&headers, &etag, CATALOG_MAX_AGE, pack))
 }
 
 #[derive(Debug, Serialize)]
@@ -82,9 +146,13 @@ pub async fn download_sticker_pack(
 ) -> AppResult<Json<MessageResponse>> {
     let user_id = get_user_id(&claims)?;
 
-    let stickers_service = StickersService::new(state.db, state.minio);
+    let stickers_service = StickersService::new(state.db.clone(), state.storage);
     stickers_service.download_pack(user_id, pack_id).await?;
 
+    EventLog::new(state.db, &state.config.analytics)
+        .record(DomainEvent::PackDownloaded { user_id, pack_id })
+        .await;
+
     Ok(Json(MessageResponse {
         message: "Pack downloaded".to_string(),
     }))
@@ -97,7 +165,7 @@ pub async fn remove_sticker_pack(
 ) -> AppResult<Json<MessageResponse>> {
     let user_id = get_user_id(&claims)?;
 
-    let stickers_service = StickersService::new(state.db, state.minio);
+    let stickers_service = StickersService::new(state.db, state.storage);
     stickers_service.remove_pack(user_id, pack_id).await?;
 
     Ok(Json(MessageResponse {
@@ -111,7 +179,7 @@ pub async fn get_user_sticker_packs(
 ) -> AppResult<Json<Vec<StickerPackWithStickers>>> {
     let user_id = get_user_id(&claims)?;
 
-    let stickers_service = StickersService::new(state.db, state.minio);
+    let stickers_service = StickersService::new(state.db, state.storage);
     let packs = stickers_service.get_user_packs(user_id).await?;
 
     Ok(Json(packs))
@@ -129,7 +197,7 @@ pub async fn reorder_sticker_packs(
 ) -> AppResult<Json<MessageResponse>> {
     let user_id = get_user_id(&claims)?;
 
-    let stickers_service = StickersService::new(state.db, state.minio);
+    let stickers_service = StickersService::new(state.db, state.storage);
     stickers_service.reorder_packs(user_id, req.pack_ids).await?;
 
     Ok(Json(MessageResponse {
@@ -152,11 +220,15 @@ pub struct CreatePackRequest {
 
 pub async fn create_sticker_pack(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Json(req): Json<CreatePackRequest>,
 ) -> AppResult<Json<StickerPack>> {
-    let stickers_service = StickersService::new(state.db, state.minio);
+    let user_id = get_user_id(&claims)?;
+
+    let stickers_service = StickersService::new(state.db, state.storage);
     let pack = stickers_service
         .create_pack(
+            Some(user_id),
             &req.name,
             &req.author,
             req.description.as_deref(),
@@ -168,6 +240,68 @@ pub async fn create_sticker_pack(
     Ok(Json(pack))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct AnalyticsQuery {
+    #[serde(default = "default_analytics_days")]
+    pub days: i64,
+}
+
+fn default_analytics_days() -> i64 {
+    30
+}
+
+/// Usage of a pack the caller created
+pub async fn get_my_pack_analytics(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(pack_id): Path<Uuid>,
+    Query(query): Query<AnalyticsQuery>,
+) -> AppResult<Json<StickerPackAnalytics>> {
+    let user_id = get_user_id(&claims)?;
+
+    let analytics = StickersService::new(state.db, state.storage)
+        .creator_pack_analytics(user_id, pack_id, query.days.clamp(1, 365))
+        .await?;
+
+    Ok(Json(analytics))
+}
+
+/// Usage of any pack (admin)
+pub async fn get_pack_analytics(
+    State(state): State<AppState>,
+    Path(pack_id): Path<Uuid>,
+    Query(query): Query<AnalyticsQuery>,
+) -> AppResult<Json<StickerPackAnalytics>> {
+    let analytics = StickersService::new(state.db, state.storage)
+        .pack_analytics(pack_id, query.days.clamp(1, 365))
+        .await?;
+
+    Ok(Json(analytics))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CurationRequest {
+    #[serde(default)]
+    pub featured: bool,
+    /// Position among featured packs, lowest first; ignored unless featured
+    pub featured_order: Option<i32>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Feature a pack in the catalog and set its tags (admin)
+pub async fn curate_sticker_pack(
+    State(state): State<AppState>,
+    Path(pack_id): Path<Uuid>,
+    Json(req): Json<CurationRequest>,
+) -> AppResult<Json<StickerPack>> {
+    let pack = StickersService::new(state.db, state.storage)
+        .curate_pack(pack_id, req.featured, req.featured_order, req.tags)
+        .await?;
+
+    Ok(Json(pack))
+}
+
 #[derive(Debug, Serialize)]
 pub struct CoverResponse {
     pub cover_url: String,
@@ -175,9 +309,12 @@ pub struct CoverResponse {
 
 pub async fn upload_pack_cover(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(pack_id): Path<Uuid>,
     mut multipart: Multipart,
 ) -> AppResult<Json<CoverResponse>> {
+    let user_id = get_user_id(&claims)?;
+
     while let Some(field) = multipart.next_field().await.map_err(|e| {
         AppError::BadRequest(format!("Failed to read multipart field: {}", e))
     })? {
@@ -195,7 +332,20 @@ pub async fn upload_pack_cover(
             .await
             .map_err(|e| AppError::BadRequest(format!("Failed to read file: {}", e)))?;
 
-        let stickers_service = StickersService::new(state.db, state.minio);
+        ModerationPipeline::new(state.db.clone(), state.redis.clone(), &state.config.moderation)
+            .screen_upload(
+                state.storage.as_ref(),
+                &ContentSubject {
+                    source: ContentSource::StickerCover,
+                    uploader_id: user_id,
+                    conversation_id: None,
+                    content_type: Some(&content_type),
+                    data: &data,
+                },
+            )
+            .await?;
+
+        let stickers_service = StickersService::new(state.db, state.storage);
         let cover_url = stickers_service
             .upload_pack_cover(pack_id, data, &content_type)
             .await?;
@@ -208,9 +358,11 @@ pub async fn upload_pack_cover(
 
 pub async fn add_sticker(
     State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
     Path(pack_id): Path<Uuid>,
     mut multipart: Multipart,
 ) -> AppResult<Json<Sticker>> {
+    let user_id = get_user_id(&claims)?;
     let mut emoji = String::new();
     let mut position = 0i32;
     let mut file_data = None;
@@ -253,7 +405,21 @@ pub async fn add_sticker(
 
     let data = file_data.ok_or_else(|| AppError::BadRequest("Sticker file required".to_string()))?;
 
-    let stickers_service = StickersService::new(state.db, state.minio);
+    ModerationPipeline::new(state.db.clone(), state.redis.clone(), &state.config.moderation)
+        .screen_upload(
+            state.storage.as_ref(),
+            &ContentSubject {
+                source: ContentSource::Sticker,
+                uploader_id: user_id,
+                conversation_id: None,
+                content_type: Some(&content_type),
+                data: &data,
+            },
+        )
+        .await?;
+
+    let stickers_service =
+        StickersService::new(state.db, state.storage).with_renderer(state.sticker_renderer);
     let sticker = stickers_service
         .add_sticker(pack_id, &emoji, position, data, &content_type)
         .await?;
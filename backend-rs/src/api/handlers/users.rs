@@ -1,17 +1,59 @@
+use std::time::Duration;
+
 use axum::{
     extract::{Multipart, Query, State},
+    http::HeaderMap,
     Extension, Json,
 };
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     error::{AppError, AppResult},
-    models::User,
-    services::{auth::Claims, contacts::ContactsService},
+    models::{
+        NotificationSchedule, QuietHoursWindow, SyncData, User, UserSearchResult, UserSettings,
+    },
+    services::{
+        auth::{AuthService, Claims},
+        contacts::ContactsService,
+        content_moderation::{ContentSource, ContentSubject, ModerationPipeline},
+        digests::DigestService,
+        notifications::NotificationService,
+        profiles::{normalize_emoji_status, normalize_links, normalize_pronouns, ProfileService},
+        rate_limit::RateLimitRule,
+        stickers::StickersService,
+        sync_data::SyncDataService,
+        usernames::{map_username_conflict, UsernameAvailability, UsernameService},
+    },
     AppState,
 };
 
-use super::super::middleware::get_user_id;
+use super::{
+    super::{
+        client_ip::ClientIp,
+        middleware::{client_info, get_device_id, get_user_id},
+    },
+    auth::check_abuse,
+};
+
+fn notification_service(state: AppState) -> NotificationService {
+    NotificationService::new(state.db, state.redis, state.push, state.email)
+}
+
+fn sync_data_service(state: AppState) -> SyncDataService {
+    SyncDataService::new(
+        state.db.clone(),
+        state.redis.clone(),
+        NotificationService::new(
+            state.db.clone(),
+            state.redis.clone(),
+            state.push.clone(),
+            state.email.clone(),
+        ),
+        DigestService::new(state.db.clone(), state.email, state.config),
+        StickersService::new(state.db, state.storage),
+    )
+}
 
 pub async fn get_current_user(
     State(state): State<AppState>,
@@ -21,7 +63,8 @@ pub async fn get_current_user(
 
     let user: Option<User> = sqlx::query_as(
         r#"
-        SELECT id, phone, email, username, display_name, avatar_url, bio, status, last_seen_at, created_at, updated_at
+        SELECT id, phone, email, username, display_name, avatar_url, bio, status, last_seen_at, created_at, updated_at,
+               emoji_status, emoji_status_expires_at, pronouns, links
         FROM users WHERE id = $1
         "#,
     )
@@ -38,6 +81,14 @@ pub struct UpdateUserRequest {
     pub display_name: Option<String>,
     pub username: Option<String>,
     pub bio: Option<String>,
+    /// An empty string clears the status
+    pub emoji_status: Option<String>,
+    /// Only read with `emoji_status`; omit to keep the status until changed
+    pub emoji_status_expires_at: Option<DateTime<Utc>>,
+    /// An empty string clears the pronouns
+    pub pronouns: Option<String>,
+    /// Replaces the whole list
+    pub links: Option<Vec<String>>,
 }
 
 pub async fn update_current_user(
@@ -47,31 +98,309 @@ pub async fn update_current_user(
 ) -> AppResult<Json<User>> {
     let user_id = get_user_id(&claims)?;
 
-    if req.display_name.is_none() && req.username.is_none() && req.bio.is_none() {
+    if req.display_name.is_none()
+        && req.username.is_none()
+        && req.bio.is_none()
+        && req.emoji_status.is_none()
+        && req.pronouns.is_none()
+        && req.links.is_none()
+    {
         return Err(AppError::BadRequest("No fields to update".to_string()));
     }
 
+    let username = match &req.username {
+        Some(username) => Some(
+            UsernameService::new(state.db.clone(), state.redis.clone())
+                .ensure_available(username, None, Some(user_id))
+                .await?,
+        ),
+        None => None,
+    };
+
+    let emoji_status = req.emoji_status.as_deref().map(normalize_emoji_status).transpose()?;
+    let emoji_status_expires_at = match (&emoji_status, req.emoji_status_expires_at) {
+        (Some(status), Some(expires_at)) if !status.is_empty() => {
+            if expires_at <= Utc::now() {
+                return Err(AppError::Validation(
+                    "emoji_status_expires_at must be in the future".to_string(),
+                ));
+            }
+            Some(expires_at)
+        }
+        _ => None,
+    };
+    let pronouns = req.pronouns.as_deref().map(normalize_pronouns).transpose()?;
+    let links = req.links.map(normalize_links).transpose()?;
+
+    // For emoji status and pronouns, NULL keeps the current value and '' clears it
     let user: User = sqlx::query_as(
         r#"
         UPDATE users
         SET display_name = COALESCE($1, display_name),
             username = COALESCE($2, username),
             bio = COALESCE($3, bio),
+            emoji_status = NULLIF(COALESCE($4, emoji_status), ''),
+            emoji_status_expires_at = CASE
+                WHEN $4::text IS NULL THEN emoji_status_expires_at
+                ELSE $5
+            END,
+            pronouns = NULLIF(COALESCE($6, pronouns), ''),
+            links = COALESCE($7, links),
             updated_at = NOW()
-        WHERE id = $4
+        WHERE id = $8
         RETURNING *
         "#,
     )
     .bind(&req.display_name)
-    .bind(&req.username)
+    .bind(&username)
     .bind(&req.bio)
+    .bind(&emoji_status)
+    .bind(emoji_status_expires_at)
+    .bind(&pronouns)
+    .bind(&links)
     .bind(user_id)
     .fetch_one(&state.db)
-    .await?;
+    .await
+    .map_err(map_username_conflict)?;
+
+    ProfileService::new(state.db, state.redis)
+        .publish_update(&user)
+        .await?;
 
     Ok(Json(user))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct UsernameAvailableQuery {
+    pub u: String,
+    /// Phone or email of a registration in progress; with a verified OTP the
+    /// username is held for it for a few minutes
+    pub target: Option<String>,
+    pub captcha_token: Option<String>,
+}
+
+pub async fn username_available(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Query(query): Query<UsernameAvailableQuery>,
+) -> AppResult<Json<UsernameAvailability>> {
+    // Unauthenticated and able to hold names, so limited per IP and per
+    // registration
+    let client = client_info(&headers, ip, &state.config);
+    let limits = &state.config.rate_limit;
+    let rule = RateLimitRule {
+        window: limits.window,
+        challenge_after: limits.username_challenge_after,
+        max: limits.username_max,
+    };
+    let ip = client.ip_address.clone().unwrap_or_default();
+    let mut keys = vec![ip.as_str()];
+    keys.extend(query.target.as_deref());
+    check_abuse(
+        &state,
+        "username_check",
+        &keys,
+        rule,
+        query.captcha_token.as_deref(),
+        &client,
+    )
+    .await?;
+
+    let username_service = UsernameService::new(state.db, state.redis);
+    let availability = username_service
+        .check_availability(&query.u, query.target.as_deref())
+        .await?;
+
+    Ok(Json(availability))
+}
+
+pub async fn get_notification_schedule(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<NotificationSchedule>> {
+    let user_id = get_user_id(&claims)?;
+
+    let schedule = notification_service(state).get_schedule(user_id).await?;
+
+    Ok(Json(schedule))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateNotificationScheduleRequest {
+    pub enabled: bool,
+    pub timezone: String,
+    #[serde(default)]
+    pub windows: Vec<QuietHoursWindow>,
+}
+
+pub async fn update_notification_schedule(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<UpdateNotificationScheduleRequest>,
+) -> AppResult<Json<NotificationSchedule>> {
+    let user_id = get_user_id(&claims)?;
+
+    let schedule = notification_service(state)
+        .update_schedule(user_id, req.enabled, &req.timezone, &req.windows)
+        .await?;
+
+    Ok(Json(schedule))
+}
+
+/// Notification settings, including any running push snooze
+pub async fn get_settings(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<UserSettings>> {
+    let user_id = get_user_id(&claims)?;
+
+    let settings = notification_service(state).get_settings(user_id).await?;
+
+    Ok(Json(settings))
+}
+
+#[derive(Debug, Serialize)]
+pub struct BadgeResponse {
+    pub badge: i64,
+}
+
+/// Total unread for the app icon badge, the same count pushes carry
+pub async fn get_badge(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<BadgeResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    let badge = notification_service(state).badge_count(user_id).await?;
+
+    Ok(Json(BadgeResponse { badge }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SnoozeRequest {
+    /// Seconds from now
+    pub duration: u64,
+}
+
+/// Suppress every push until the snooze runs out; WebSocket events still
+/// arrive. Snoozing again replaces the running snooze.
+pub async fn snooze_notifications(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<SnoozeRequest>,
+) -> AppResult<Json<UserSettings>> {
+    let user_id = get_user_id(&claims)?;
+
+    let settings = notification_service(state)
+        .snooze(user_id, Duration::from_secs(req.duration))
+        .await?;
+
+    Ok(Json(settings))
+}
+
+/// End a push snooze early
+pub async fn clear_snooze(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<UserSettings>> {
+    let user_id = get_user_id(&claims)?;
+
+    let settings = notification_service(state).clear_snooze(user_id).await?;
+
+    Ok(Json(settings))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailDigestRequest {
+    pub enabled: bool,
+}
+
+/// Opt in to or out of unread digest emails while away
+pub async fn update_email_digest(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<EmailDigestRequest>,
+) -> AppResult<Json<UserSettings>> {
+    let user_id = get_user_id(&claims)?;
+
+    let digest_service =
+        DigestService::new(state.db.clone(), state.email.clone(), state.config.clone());
+    if req.enabled {
+        digest_service.enable(user_id).await?;
+    } else {
+        digest_service.disable(user_id).await?;
+    }
+
+    let settings = notification_service(state).get_settings(user_id).await?;
+
+    Ok(Json(settings))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContactJoinedRequest {
+    pub enabled: bool,
+}
+
+/// Opt in to or out of hearing when address book contacts join
+pub async fn update_contact_joined(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<ContactJoinedRequest>,
+) -> AppResult<Json<UserSettings>> {
+    let user_id = get_user_id(&claims)?;
+
+    ContactsService::new(state.db.clone())
+        .set_contact_joined_notices(user_id, req.enabled)
+        .await?;
+
+    let settings = notification_service(state).get_settings(user_id).await?;
+
+    Ok(Json(settings))
+}
+
+/// Settings to restore on a fresh install
+pub async fn get_sync_data(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<SyncData>> {
+    let user_id = get_user_id(&claims)?;
+
+    let data = sync_data_service(state).get(user_id).await?;
+
+    Ok(Json(data))
+}
+
+/// Save this device's settings; a 409 carries newer ones saved elsewhere
+pub async fn update_sync_data(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<SyncData>,
+) -> AppResult<Json<SyncData>> {
+    let user_id = get_user_id(&claims)?;
+    let device_id = get_device_id(&claims)?;
+
+    let data = sync_data_service(state).put(user_id, device_id, req).await?;
+
+    Ok(Json(data))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnsubscribeQuery {
+    pub token: String,
+}
+
+/// Target of the link in every digest email; works without signing in
+pub async fn unsubscribe_email_digest(
+    State(state): State<AppState>,
+    Query(query): Query<UnsubscribeQuery>,
+) -> AppResult<&'static str> {
+    let digest_service = DigestService::new(state.db, state.email, state.config);
+    digest_service.unsubscribe(&query.token).await?;
+
+    Ok("You will no longer receive digest emails from Ansible Talk.")
+}
+
 #[derive(Debug, Serialize)]
 pub struct AvatarResponse {
     pub avatar_url: String,
@@ -101,6 +430,19 @@ pub async fn upload_avatar(
             .await
             .map_err(|e| AppError::BadRequest(format!("Failed to read file: {}", e)))?;
 
+        ModerationPipeline::new(state.db.clone(), state.redis.clone(), &state.config.moderation)
+            .screen_upload(
+                state.storage.as_ref(),
+                &ContentSubject {
+                    source: ContentSource::Avatar,
+                    uploader_id: user_id,
+                    conversation_id: None,
+                    content_type: Some(&content_type),
+                    data: &data,
+                },
+            )
+            .await?;
+
         let extension = match content_type.as_str() {
             "image/png" => "png",
             "image/jpeg" | "image/jpg" => "jpg",
@@ -110,16 +452,28 @@ pub async fn upload_avatar(
         };
 
         let key = format!("avatars/{}/avatar.{}", user_id, extension);
-        let avatar_url = state
-            .minio
-            .upload_file(state.minio.avatars_bucket(), &key, data, &content_type)
+        let url = state
+            .storage
+            .upload_file(state.storage.avatars_bucket(), &key, data, &content_type)
             .await?;
 
+        // The key is reused across uploads, so version the URL by update time
+        // to let clients and CDNs cache each avatar indefinitely
+        let updated_at = Utc::now();
+        let avatar_url = format!("{}?v={}", url, updated_at.timestamp());
+
         // Update user
-        sqlx::query("UPDATE users SET avatar_url = $1, updated_at = NOW() WHERE id = $2")
-            .bind(&avatar_url)
-            .bind(user_id)
-            .execute(&state.db)
+        let user: User = sqlx::query_as(
+            "UPDATE users SET avatar_url = $1, updated_at = $2 WHERE id = $3 RETURNING *",
+        )
+        .bind(&avatar_url)
+        .bind(updated_at)
+        .bind(user_id)
+        .fetch_one(&state.db)
+        .await?;
+
+        ProfileService::new(state.db, state.redis)
+            .publish_update(&user)
             .await?;
 
         return Ok(Json(AvatarResponse { avatar_url }));
@@ -133,6 +487,8 @@ pub struct SearchQuery {
     pub q: String,
     #[serde(default = "default_limit")]
     pub limit: i32,
+    #[serde(default)]
+    pub offset: i32,
 }
 
 fn default_limit() -> i32 {
@@ -143,7 +499,7 @@ pub async fn search_users(
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
     Query(query): Query<SearchQuery>,
-) -> AppResult<Json<Vec<User>>> {
+) -> AppResult<Json<Vec<UserSearchResult>>> {
     let user_id = get_user_id(&claims)?;
 
     if query.q.is_empty() {
@@ -151,10 +507,29 @@ pub async fn search_users(
     }
 
     let contacts_service = ContactsService::new(state.db.clone());
-    let mut users = contacts_service.search_users(&query.q, query.limit).await?;
-
-    // Filter out current user
-    users.retain(|u| u.id != user_id);
+    let users = contacts_service
+        .search_users(&query.q, user_id, query.limit.clamp(1, 100), query.offset.max(0))
+        .await?;
 
     Ok(Json(users))
 }
+
+#[derive(Debug, Serialize)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+/// Hide the account until its next login, ending every session
+pub async fn deactivate_account(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> AppResult<Json<MessageResponse>> {
+    let user_id = get_user_id(&claims)?;
+
+    let auth_service = AuthService::new(state.db, state.redis, (*state.config).clone());
+    auth_service.deactivate(user_id).await?;
+
+    Ok(Json(MessageResponse {
+        message: "Account deactivated; log in again to reactivate it".to_string(),
+    }))
+}
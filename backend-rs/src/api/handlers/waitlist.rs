@@ -0,0 +1,116 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    Extension, Json,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{OtpType, WaitlistEntry, WaitlistPosition, WaitlistStatus},
+    services::{auth::Claims, waitlist::WaitlistService},
+    AppState,
+};
+
+use super::super::{
+    client_ip::ClientIp,
+    middleware::{client_info, get_user_id},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct JoinWaitlistRequest {
+    pub target: String,
+    #[serde(rename = "type")]
+    pub otp_type: String,
+}
+
+/// Join the waitlist after verifying an OTP; calling it again with a
+/// verified OTP reports where the entry stands
+pub async fn join_waitlist(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Json(req): Json<JoinWaitlistRequest>,
+) -> AppResult<Json<WaitlistPosition>> {
+    let otp_type = match req.otp_type.as_str() {
+        "phone" => OtpType::Phone,
+        "email" => OtpType::Email,
+        _ => return Err(AppError::BadRequest("Invalid OTP type".to_string())),
+    };
+    let client = client_info(&headers, ip, &state.config);
+
+    let waitlist = WaitlistService::new(state.db, state.redis, (*state.config).clone());
+    let position = waitlist.join(&req.target, otp_type, &client).await?;
+
+    Ok(Json(position))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WaitlistQuery {
+    pub status: Option<WaitlistStatus>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Waitlist entries, oldest first (`?status=pending`)
+pub async fn get_waitlist(
+    State(state): State<AppState>,
+    Query(query): Query<WaitlistQuery>,
+) -> AppResult<Json<Vec<WaitlistEntry>>> {
+    let waitlist = WaitlistService::new(state.db, state.redis, (*state.config).clone());
+    let entries = waitlist
+        .list(
+            query.status,
+            query.limit.unwrap_or(100),
+            query.offset.unwrap_or(0),
+        )
+        .await?;
+
+    Ok(Json(entries))
+}
+
+pub async fn approve_waitlist_entry(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(entry_id): Path<Uuid>,
+) -> AppResult<Json<WaitlistEntry>> {
+    let admin_id = get_user_id(&claims)?;
+
+    let waitlist = WaitlistService::new(state.db, state.redis, (*state.config).clone());
+    let entry = waitlist.approve(admin_id, entry_id).await?;
+
+    Ok(Json(entry))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApproveNextRequest {
+    pub count: i64,
+}
+
+/// Let in the longest-waiting entries
+pub async fn approve_next_waitlist(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<ApproveNextRequest>,
+) -> AppResult<Json<Vec<WaitlistEntry>>> {
+    let admin_id = get_user_id(&claims)?;
+
+    let waitlist = WaitlistService::new(state.db, state.redis, (*state.config).clone());
+    let entries = waitlist.approve_next(admin_id, req.count).await?;
+
+    Ok(Json(entries))
+}
+
+pub async fn reject_waitlist_entry(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(entry_id): Path<Uuid>,
+) -> AppResult<Json<WaitlistEntry>> {
+    let admin_id = get_user_id(&claims)?;
+
+    let waitlist = WaitlistService::new(state.db, state.redis, (*state.config).clone());
+    let entry = waitlist.reject(admin_id, entry_id).await?;
+
+    Ok(Json(entry))
+}
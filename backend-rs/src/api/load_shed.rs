@@ -0,0 +1,168 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{
+    config::LoadShedConfig,
+    error::{AppError, AppResult},
+    logging::SAMPLED,
+};
+
+/// Which requests go first when the server is over its limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Catalogs and searches, shed once half the limit is in use
+    Low,
+    /// Shed at four fifths of the limit
+    Normal,
+    /// Signing in and sending messages, shed only at the full limit
+    Critical,
+}
+
+impl Priority {
+    pub fn of(method: &Method, route: &str) -> Self {
+        if route.starts_with("/api/v1/auth")
+            || (method == Method::POST && route == "/api/v1/conversations/:id/messages")
+        {
+            Priority::Critical
+        } else if route.starts_with("/api/v1/stickers")
+            || route.starts_with("/api/v1/gifs")
+            || route.starts_with("/api/v1/directory")
+            || route.ends_with("/search")
+        {
+            Priority::Low
+        } else {
+            Priority::Normal
+        }
+    }
+
+    /// Requests in flight at which this class is turned away
+    fn threshold(self, limit: usize) -> usize {
+        match self {
+            Priority::Low => limit / 2,
+            Priority::Normal => limit * 4 / 5,
+            Priority::Critical => limit,
+        }
+        .max(1)
+    }
+}
+
+/// Concurrency limit for API requests that adapts to latency: each request
+/// finishing under the target raises it by one, and a slow one cuts it by a
+/// tenth, at most once per target interval so a single burst cuts it once.
+pub struct LoadShedder {
+    config: LoadShedConfig,
+    in_flight: AtomicUsize,
+    limit: AtomicUsize,
+    last_cut: Mutex<Instant>,
+}
+
+/// A request counted against the limit until dropped, including when the
+/// request is cancelled
+pub struct Permit {
+    shedder: Arc<LoadShedder>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.shedder.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl LoadShedder {
+    pub fn new(config: LoadShedConfig) -> Self {
+        let limit = config.max_concurrency;
+        Self {
+            config,
+            in_flight: AtomicUsize::new(0),
+            limit: AtomicUsize::new(limit),
+            last_cut: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn try_acquire(self: &Arc<Self>, priority: Priority) -> Option<Permit> {
+        let threshold = priority.threshold(self.limit.load(Ordering::Acquire));
+        let in_flight = self.in_flight.fetch_add(1, Ordering::AcqRel);
+        let permit = Permit {
+            shedder: self.clone(),
+        };
+
+        (in_flight < threshold).then_some(permit)
+    }
+
+    fn observe(&self, latency: Duration) {
+        let target = self.config.target_latency;
+        if latency <= target {
+            let _ = self
+                .limit
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |limit| {
+                    (limit < self.config.max_concurrency).then_some(limit + 1)
+                });
+            return;
+        }
+
+        let Ok(mut last_cut) = self.last_cut.lock() else {
+            return;
+        };
+        if last_cut.elapsed() < target {
+            return;
+        }
+        *last_cut = Instant::now();
+        let _ = self
+            .limit
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |limit| {
+                Some((limit - limit / 10).max(self.config.min_concurrency))
+            });
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Acquire)
+    }
+}
+
+/// Turn away API requests over the limit, lowest priority first, with a 503
+/// and `Retry-After`. Health and metrics routes are never shed.
+pub async fn load_shed_middleware(
+    State(shedder): State<Arc<LoadShedder>>,
+    request: Request,
+    next: Next,
+) -> AppResult<Response> {
+    let Some(route) = request
+        .extensions()
+        .get::<MatchedPath>()
+        .filter(|path| path.as_str().starts_with("/api/v1"))
+    else {
+        return Ok(next.run(request).await);
+    };
+
+    let priority = Priority::of(request.method(), route.as_str());
+    let Some(_permit) = shedder.try_acquire(priority) else {
+        tracing::warn!(
+            target: SAMPLED,
+            route = route.as_str(),
+            ?priority,
+            limit = shedder.limit(),
+            "request shed"
+        );
+        return Err(AppError::Overloaded {
+            retry_after: shedder.config.retry_after,
+        });
+    };
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    shedder.observe(started.elapsed());
+
+    Ok(response)
+}
@@ -1,14 +1,30 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Instant,
+};
+
 use axum::{
-    extract::{Request, State},
-    http::header::AUTHORIZATION,
+    extract::{ConnectInfo, MatchedPath, OriginalUri, Query, Request, State},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE, USER_AGENT},
+        HeaderMap, Method,
+    },
     middleware::Next,
     response::Response,
 };
 use uuid::Uuid;
 
+use super::client_ip::resolve_client_ip;
 use crate::{
+    config::Config,
     error::{AppError, AppResult},
-    services::auth::Claims,
+    models::{ClientInfo, MaintenanceState, Scope},
+    services::{
+        api_tokens::{ApiTokenService, TOKEN_PREFIX},
+        audit::{summarize_payload, AuditLog, AuditRecord},
+        auth::Claims,
+        client_versions::{client_version, ClientVersionService},
+    },
     AppState,
 };
 
@@ -25,13 +41,43 @@ pub async fn auth_middleware(
         .and_then(|h| h.strip_prefix("Bearer "))
         .ok_or(AppError::Unauthorized)?;
 
+    // API tokens act for their user without a session
+    if token.starts_with(TOKEN_PREFIX) {
+        let ip = request_ip(&request, &state.config);
+        let tokens = ApiTokenService::new(
+            state.db.clone(),
+            state.redis.clone(),
+            state.config.api_tokens.clone(),
+        );
+        let claims = tokens.authenticate(token, ip.as_deref()).await?;
+        tracing::Span::current().record("user_id", claims.sub.as_str());
+        request.extensions_mut().insert(claims);
+
+        return Ok(next.run(request).await);
+    }
+
     let auth_service = crate::services::auth::AuthService::new(
         state.db.clone(),
         state.redis.clone(),
         (*state.config).clone(),
     );
 
-    let claims = auth_service.validate_token(token)?;
+    let claims = auth_service.validate_token(token).await?;
+    tracing::Span::current().record("user_id", claims.sub.as_str());
+
+    // Keep the session's last activity and the device's app version current
+    // without delaying the request
+    if let (Ok(user_id), Ok(device_id)) = (get_user_id(&claims), get_device_id(&claims)) {
+        let app_version = client_version(request.headers()).map(|(_, version)| version);
+        tokio::spawn(async move {
+            if let Err(e) = auth_service
+                .touch_session(user_id, device_id, app_version.as_deref())
+                .await
+            {
+                tracing::warn!("Failed to record session activity: {}", e);
+            }
+        });
+    }
 
     // Insert claims into request extensions
     request.extensions_mut().insert(claims);
@@ -39,6 +85,308 @@ pub async fn auth_middleware(
     Ok(next.run(request).await)
 }
 
+/// Admin middleware, layered after `auth_middleware` on admin-only routes
+pub async fn admin_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let claims = request
+        .extensions()
+        .get::<Claims>()
+        .ok_or(AppError::Unauthorized)?;
+    let user_id = get_user_id(claims)?;
+
+    let is_admin: Option<bool> = sqlx::query_scalar("SELECT is_admin FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.db)
+        .await?;
+
+    if is_admin != Some(true) {
+        return Err(AppError::AdminRequired);
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// JSON bodies larger than this are refused on audited routes; `Json`
+/// would refuse them anyway
+const MAX_AUDITED_BODY: usize = 2 * 1024 * 1024;
+
+/// Audit middleware, layered around `admin_middleware`: records every
+/// admin request other than reads, with who sent it, a summary of its
+/// payload and how it ended, including requests refused to non-admins
+pub async fn admin_audit_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let method = request.method().clone();
+    if method == Method::GET || method == Method::HEAD || method == Method::OPTIONS {
+        return Ok(next.run(request).await);
+    }
+
+    let actor_id = request
+        .extensions()
+        .get::<Claims>()
+        .and_then(|claims| get_user_id(claims).ok());
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+    // Nested routers see the path without their prefix
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(request.uri().path(), |uri| uri.path())
+        .to_string();
+    let ip_address = request_ip(&request, &state.config);
+
+    let mut payload = serde_json::Map::new();
+    if let Ok(Query(params)) = Query::<serde_json::Map<_, _>>::try_from_uri(request.uri()) {
+        if !params.is_empty() {
+            payload.insert("query".to_string(), summarize_payload(&params.into()));
+        }
+    }
+    let content_type = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let request = if content_type.starts_with("application/json") {
+        let (parts, body) = request.into_parts();
+        let bytes = axum::body::to_bytes(body, MAX_AUDITED_BODY)
+            .await
+            .map_err(|_| AppError::BadRequest("Request body too large".to_string()))?;
+        let body = serde_json::from_slice(&bytes)
+            .map(|body| summarize_payload(&body))
+            .unwrap_or_else(|_| "[invalid JSON]".into());
+        payload.insert("body".to_string(), body);
+        Request::from_parts(parts, bytes.into())
+    } else {
+        // Uploads are described, not stored
+        if !content_type.is_empty() {
+            payload.insert("content_type".to_string(), content_type.into());
+        }
+        request
+    };
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let duration = started.elapsed();
+
+    // Keep the message of a failed request; error bodies are small JSON
+    let status = response.status();
+    let (response, error) = if status.is_client_error() || status.is_server_error() {
+        let (parts, body) = response.into_parts();
+        let bytes = axum::body::to_bytes(body, MAX_AUDITED_BODY)
+            .await
+            .unwrap_or_default();
+        let error = serde_json::from_slice::<serde_json::Value>(&bytes)
+            .ok()
+            .and_then(|body| body.get("error")?.as_str().map(str::to_string));
+        (Response::from_parts(parts, bytes.into()), error)
+    } else {
+        (response, None)
+    };
+
+    let record = AuditRecord {
+        actor_id,
+        method: method.as_str(),
+        route: &route,
+        path: &path,
+        payload: payload.into(),
+        status: status.as_u16(),
+        error,
+        ip_address,
+        duration,
+    };
+    if let Err(e) = AuditLog::new(state.db.clone()).record(record).await {
+        tracing::error!("Failed to record admin action {} {}: {}", method, path, e);
+    }
+
+    Ok(response)
+}
+
+/// Scope middleware, layered after `auth_middleware`; refuses linked
+/// sessions and API tokens without any of the route's scopes
+pub async fn scope_middleware(
+    State(scopes): State<&'static [Scope]>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let claims = request
+        .extensions()
+        .get::<Claims>()
+        .ok_or(AppError::Unauthorized)?;
+
+    if !claims.allows(scopes) {
+        return Err(AppError::ScopeNotGranted);
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Maintenance middleware, rejects writes with 503 while maintenance mode is on
+pub async fn maintenance_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let method = request.method();
+    if method == Method::GET || method == Method::HEAD || method == Method::OPTIONS {
+        return Ok(next.run(request).await);
+    }
+
+    match state.redis.get_maintenance().await {
+        Ok(Some(raw)) => {
+            if let Ok(maintenance) = serde_json::from_str::<MaintenanceState>(&raw) {
+                if maintenance.enabled {
+                    return Err(AppError::Maintenance {
+                        message: maintenance.message,
+                        retry_after: maintenance.retry_after,
+                    });
+                }
+            }
+        }
+        Ok(None) => {}
+        // Fail open: a Redis outage should not turn into a write outage
+        Err(e) => tracing::warn!("Failed to read maintenance state: {}", e),
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Client version middleware, refuses apps older than their platform's
+/// minimum with 426. Requests without version headers pass.
+pub async fn client_version_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if let Some((platform, version)) = client_version(request.headers()) {
+        let versions = ClientVersionService::new(state.db.clone(), state.redis.clone());
+        match versions.check(&platform, &version).await {
+            Ok(()) => {}
+            Err(e @ AppError::UpgradeRequired { .. }) => return Err(e),
+            // Fail open, like maintenance mode
+            Err(e) => tracing::warn!("Failed to check client version: {}", e),
+        }
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Cancel a request that outlives its budget: longer for admin routes and
+/// multipart uploads. Dropping the handler drops its in-flight queries; Postgres
+/// stops them at `DB_STATEMENT_TIMEOUT`.
+pub async fn timeout_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let timeouts = &state.config.timeouts;
+    let is_upload = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("multipart/"));
+    let is_admin = request
+        .extensions()
+        .get::<MatchedPath>()
+        .is_some_and(|path| path.as_str().starts_with("/api/v1/admin"));
+    let budget = if is_admin {
+        timeouts.admin
+    } else if is_upload {
+        timeouts.upload
+    } else {
+        timeouts.request
+    };
+    if budget.is_zero() {
+        return Ok(next.run(request).await);
+    }
+
+    match tokio::time::timeout(budget, next.run(request)).await {
+        Ok(response) => Ok(response),
+        Err(_) => {
+            tracing::warn!(budget_secs = budget.as_secs(), "request timed out");
+            Err(AppError::Timeout)
+        }
+    }
+}
+
+/// Log and count requests that take longer than `SLOW_REQUEST_MS`, with the
+/// names (never the values) of their query parameters
+pub async fn slow_request_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let threshold = state.config.logging.slow_request;
+    let route = request.extensions().get::<MatchedPath>().cloned();
+    let params: Vec<String> = request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split('=').next())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let latency = started.elapsed();
+
+    if let Some(route) = route.filter(|_| !threshold.is_zero() && latency >= threshold) {
+        tracing::warn!(
+            route = route.as_str(),
+            params = %params.join(","),
+            latency_ms = latency.as_millis() as u64,
+            "slow request"
+        );
+        state.slow_log.record_request(route.as_str(), latency);
+    }
+
+    response
+}
+
+/// Collect client IP, user agent, and country for session metadata
+pub fn client_info(headers: &HeaderMap, ip: IpAddr, config: &Config) -> ClientInfo {
+    let user_agent = headers
+        .get(USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .map(|ua| ua.chars().take(512).collect());
+
+    let country = config
+        .server
+        .geo_country_header
+        .as_deref()
+        .and_then(|name| headers.get(name))
+        .and_then(|h| h.to_str().ok())
+        .map(|c| c.trim().to_uppercase())
+        .filter(|c| c.len() == 2 && c.chars().all(|ch| ch.is_ascii_alphabetic()));
+
+    ClientInfo {
+        ip_address: Some(ip.to_string()),
+        user_agent,
+        country,
+    }
+}
+
+/// The client's address, for middleware that cannot use the `ClientIp` extractor
+fn request_ip(request: &Request, config: &Config) -> Option<String> {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| {
+            resolve_client_ip(peer.ip(), request.headers(), &config.server.trusted_proxies)
+                .to_string()
+        })
+}
+
 /// Extract user_id from request extensions
 pub fn get_user_id(claims: &Claims) -> AppResult<Uuid> {
     Uuid::parse_str(&claims.sub).map_err(|_| AppError::InvalidToken)
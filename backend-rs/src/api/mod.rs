@@ -1,4 +1,11 @@
+pub mod caching;
+pub mod client_ip;
 pub mod handlers;
+pub mod load_shed;
 pub mod middleware;
+pub mod pagination;
 pub mod router;
 pub mod websocket;
+pub mod ws_deflate;
+pub mod ws_queue;
+pub mod ws_resume;
@@ -0,0 +1,127 @@
+use std::{fmt::Display, str::FromStr};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, AppResult};
+
+/// Most items one page holds, whatever `limit` asks for
+pub const MAX_PAGE_SIZE: i32 = 100;
+
+/// One page of a list. `next_cursor` fetches the page after it and is null
+/// on the last one; `total` is only sent where it is cheap to count.
+#[derive(Debug, Serialize)]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+}
+
+impl<T> Paginated<T> {
+    /// A page fetched with one item more than `limit`, the extra one only
+    /// showing that another page follows. The next cursor holds what
+    /// `position` gives for the last item kept.
+    pub fn new<P: Display>(mut items: Vec<T>, limit: i32, position: impl FnOnce(&T) -> P) -> Self {
+        let mut next_cursor = None;
+        if items.len() > limit as usize {
+            items.truncate(limit as usize);
+            next_cursor = items
+                .last()
+                .map(|last| URL_SAFE_NO_PAD.encode(position(last).to_string()));
+        }
+
+        Self {
+            items,
+            next_cursor,
+            total: None,
+        }
+    }
+
+    /// A page of a list paged by offset
+    pub fn from_offset(items: Vec<T>, limit: i32, offset: i32) -> Self {
+        Self::new(items, limit, |_| offset + limit)
+    }
+
+    pub fn with_total(mut self, total: i64) -> Self {
+        self.total = Some(total);
+        self
+    }
+}
+
+/// `?limit=&cursor=` on a list endpoint. Cursors are opaque to clients, so
+/// an endpoint can change what its cursor holds without breaking them.
+/// Clients from before cursors may still send `?offset=` instead, which
+/// starts the page that many items in.
+#[derive(Debug, Deserialize)]
+pub struct Page {
+    limit: Option<i32>,
+    cursor: Option<String>,
+    offset: Option<i32>,
+}
+
+impl Page {
+    /// The page size, `default` when the client gave none
+    pub fn limit(&self, default: i32) -> i32 {
+        self.limit.unwrap_or(default).clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// Where the cursor says the page starts, if one was given
+    pub fn position<P: FromStr>(&self) -> AppResult<Option<P>> {
+        let Some(cursor) = &self.cursor else {
+            return Ok(None);
+        };
+        if self.offset.is_some() {
+            return Err(AppError::Validation(
+                "Pass either cursor or offset, not both".to_string(),
+            ));
+        }
+
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .and_then(|position| position.parse().ok())
+            .map(Some)
+            .ok_or_else(|| AppError::Validation("Invalid cursor".to_string()))
+    }
+
+    /// How many items to skip, for lists paged by offset: what the cursor
+    /// holds, or else `offset`
+    pub fn offset(&self) -> AppResult<i32> {
+        match self.position::<i32>()? {
+            Some(offset) if offset < 0 => Err(AppError::Validation("Invalid cursor".to_string())),
+            Some(offset) => Ok(offset),
+            None => self.skip(),
+        }
+    }
+
+    /// `offset`, for lists whose cursor holds something else; the cursor
+    /// the page returns continues after the skipped items
+    pub fn skip(&self) -> AppResult<i32> {
+        match self.offset {
+            Some(offset) if offset < 0 => {
+                Err(AppError::Validation("Offset must not be negative".to_string()))
+            }
+            offset => Ok(offset.unwrap_or(0)),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Page {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(page) = Query::<Page>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| AppError::Validation(e.body_text()))?;
+
+        Ok(page)
+    }
+}
@@ -1,11 +1,38 @@
 use axum::{
+    extract::DefaultBodyLimit,
     middleware,
     routing::{delete, get, post, put},
-    Router,
+    Extension, Router,
 };
 
-use super::{handlers, middleware::auth_middleware, websocket::handle_websocket};
-use crate::AppState;
+use super::{
+    handlers,
+    middleware::{
+        admin_audit_middleware, admin_middleware, auth_middleware, client_version_middleware,
+        maintenance_middleware, scope_middleware,
+    },
+    websocket::handle_websocket,
+};
+use crate::{graphql, models::Scope, services::message_policy, AppState};
+
+// Scopes each group of routes accepts, any one of them being enough
+const MESSAGES: &[Scope] = &[Scope::Messages];
+const READ_MESSAGES: &[Scope] = &[Scope::Messages, Scope::ReadMessages];
+const SEND_MESSAGES: &[Scope] = &[Scope::Messages, Scope::SendMessages];
+const MANAGE_STICKERS: &[Scope] = &[Scope::Messages, Scope::ManageStickers];
+const CONTACTS: &[Scope] = &[Scope::Contacts];
+const PROFILE: &[Scope] = &[Scope::Profile];
+const DEVICES: &[Scope] = &[Scope::Devices];
+const ACCOUNT: &[Scope] = &[Scope::Account];
+const ADMIN: &[Scope] = &[Scope::Admin];
+/// Any login or linked session, but not API tokens, which only hold token scopes
+const SESSIONS: &[Scope] = &[
+    Scope::Messages,
+    Scope::Contacts,
+    Scope::Profile,
+    Scope::Devices,
+    Scope::Account,
+];
 
 pub fn create_router(state: AppState) -> Router<AppState> {
     // Public auth routes
@@ -14,26 +41,102 @@ pub fn create_router(state: AppState) -> Router<AppState> {
         .route("/otp/verify", post(handlers::auth::verify_otp))
         .route("/register", post(handlers::auth::register))
         .route("/login", post(handlers::auth::login))
-        .route("/refresh", post(handlers::auth::refresh_token));
+        .route("/refresh", post(handlers::auth::refresh_token))
+        .route("/link/start", post(handlers::auth::start_link))
+        .route("/link/claim", post(handlers::auth::claim_link))
+        .route("/invite/check", post(handlers::auth::check_invite))
+        .route("/waitlist", post(handlers::waitlist::join_waitlist));
 
-    // Protected auth routes
+    // Protected auth routes; linked sessions cannot change the account or
+    // link further devices
     let auth_protected = Router::new()
-        .route("/logout", post(handlers::auth::logout))
         .route("/logout-all", post(handlers::auth::logout_all))
+        .route("/identifier/change", post(handlers::auth::change_identifier))
+        .route(
+            "/identifier/confirm",
+            post(handlers::auth::confirm_identifier_change),
+        )
+        .route("/merge/start", post(handlers::auth::start_account_merge))
+        .route("/merge/preview", post(handlers::auth::preview_account_merge))
+        .route("/merge/confirm", post(handlers::auth::confirm_account_merge))
+        .layer(middleware::from_fn_with_state(ACCOUNT, scope_middleware))
+        .merge(
+            Router::new()
+                .route("/link/approve", post(handlers::auth::approve_link))
+                .layer(middleware::from_fn_with_state(DEVICES, scope_middleware)),
+        )
+        .merge(
+            Router::new()
+                .route("/logout", post(handlers::auth::logout))
+                .route("/sessions", get(handlers::auth::get_sessions))
+                .layer(middleware::from_fn_with_state(SESSIONS, scope_middleware)),
+        )
+        .merge(
+            Router::new()
+                .route(
+                    "/delivery-certificate",
+                    get(handlers::sealed_sender::get_delivery_certificate),
+                )
+                .layer(middleware::from_fn_with_state(SEND_MESSAGES, scope_middleware)),
+        )
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
 
     // User routes (protected)
+    let user_public_routes = Router::new()
+        .route(
+            "/username-available",
+            get(handlers::users::username_available),
+        )
+        .route(
+            "/email-digest/unsubscribe",
+            get(handlers::users::unsubscribe_email_digest),
+        );
+
     let user_routes = Router::new()
         .route("/me", get(handlers::users::get_current_user))
         .route("/me", put(handlers::users::update_current_user))
         .route("/me/avatar", post(handlers::users::upload_avatar))
+        .route(
+            "/me/notification-schedule",
+            get(handlers::users::get_notification_schedule),
+        )
+        .route(
+            "/me/notification-schedule",
+            put(handlers::users::update_notification_schedule),
+        )
+        .route("/me/settings", get(handlers::users::get_settings))
+        .route("/me/badge", get(handlers::users::get_badge))
+        .route("/me/connections", get(handlers::devices::get_connections))
+        .route("/me/email-digest", put(handlers::users::update_email_digest))
+        .route("/me/contact-joined", put(handlers::users::update_contact_joined))
+        .route("/me/snooze", post(handlers::users::snooze_notifications))
+        .route("/me/snooze", delete(handlers::users::clear_snooze))
+        .route("/me/sync-data", get(handlers::users::get_sync_data))
+        .route("/me/sync-data", put(handlers::users::update_sync_data))
         .route("/search", get(handlers::users::search_users))
+        .layer(middleware::from_fn_with_state(PROFILE, scope_middleware))
+        .merge(
+            Router::new()
+                .route("/me/deactivate", post(handlers::users::deactivate_account))
+                .layer(middleware::from_fn_with_state(ACCOUNT, scope_middleware)),
+        )
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
 
-    // Device routes (protected)
+    // Device routes (protected); every session manages its own push
+    // registration, but only full sessions manage devices
     let device_routes = Router::new()
-        .route("/", get(handlers::devices::get_devices))
+        .route("/settings", put(handlers::devices::update_device_settings))
+        .route("/:id", put(handlers::devices::rename_device))
         .route("/:id", delete(handlers::devices::remove_device))
+        .route("/:id/verify", post(handlers::devices::request_verification))
+        .layer(middleware::from_fn_with_state(DEVICES, scope_middleware))
+        .route("/", get(handlers::devices::get_devices))
+        .route("/push-token", put(handlers::devices::update_push_token))
+        .route("/web-push", put(handlers::devices::register_web_push))
+        .route("/web-push", delete(handlers::devices::unregister_web_push))
+        .route("/web-push/key", get(handlers::devices::get_vapid_key))
+        .route("/settings", get(handlers::devices::get_device_settings))
+        .layer(middleware::from_fn_with_state(SESSIONS, scope_middleware))
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
 
     // Key routes (protected)
@@ -43,6 +146,11 @@ pub fn create_router(state: AppState) -> Router<AppState> {
         .route("/count", get(handlers::keys::get_pre_key_count))
         .route("/prekeys", post(handlers::keys::refresh_pre_keys))
         .route("/signed-prekey", put(handlers::keys::update_signed_pre_key))
+        .route(
+            "/unidentified-access-key",
+            put(handlers::sealed_sender::update_access_key),
+        )
+        .layer(middleware::from_fn_with_state(MESSAGES, scope_middleware))
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
 
     // Contact routes (protected)
@@ -56,24 +164,139 @@ pub fn create_router(state: AppState) -> Router<AppState> {
         .route("/:id/unblock", post(handlers::contacts::unblock_contact))
         .route("/blocked", get(handlers::contacts::get_blocked_contacts))
         .route("/sync", post(handlers::contacts::sync_contacts))
+        .route("/import", post(handlers::contacts::import_contacts))
+        .route("/bulk", post(handlers::contacts::bulk_contacts))
+        .layer(middleware::from_fn_with_state(CONTACTS, scope_middleware))
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
 
-    // Conversation routes (protected)
+    // Conversation routes (protected); API tokens may read and send messages
     let conversation_routes = Router::new()
-        .route("/", get(handlers::conversations::get_conversations))
+        .route("/self", get(handlers::conversations::get_saved_conversation))
+        .route("/quick", get(handlers::conversations::get_quick_conversations))
         .route("/direct", post(handlers::conversations::create_direct_conversation))
         .route("/group", post(handlers::conversations::create_group_conversation))
-        .route("/:id", get(handlers::conversations::get_conversation))
-        .route("/:id/messages", get(handlers::conversations::get_messages))
-        .route("/:id/messages", post(handlers::conversations::send_message))
+        .route("/:id", put(handlers::conversations::update_conversation))
+        .route("/:id", delete(handlers::conversations::delete_conversation))
+        .route("/:id/accept", post(handlers::conversations::accept_request))
+        .route("/:id/block", post(handlers::conversations::block_request))
+        .route("/:id/mark-unread", post(handlers::conversations::mark_unread))
+        .route("/:id/history", delete(handlers::conversations::clear_history))
+        .route("/:id/members", post(handlers::conversations::add_members))
+        .route(
+            "/:id/permissions",
+            put(handlers::conversations::update_permissions),
+        )
+        .route("/:id/events", get(handlers::conversations::get_events))
+        .route(
+            "/:id/announcement-mode",
+            put(handlers::conversations::set_announcement_mode),
+        )
+        .route(
+            "/:id/members/:user_id",
+            delete(handlers::conversations::remove_member),
+        )
         .route("/:id/typing", post(handlers::conversations::send_typing))
+        .route("/:id/emoji", get(handlers::emoji::list_conversation_emoji))
+        .route("/:id/emoji", post(handlers::emoji::create_conversation_emoji))
+        .route("/:id/emoji/:emoji_id", put(handlers::emoji::rename_conversation_emoji))
+        .route("/:id/emoji/:emoji_id", delete(handlers::emoji::delete_conversation_emoji))
+        .route("/:id/appearance", get(handlers::conversations::get_appearance))
+        .route("/:id/appearance", put(handlers::conversations::update_appearance))
+        .route("/:id/appearance", delete(handlers::conversations::reset_appearance))
+        .route(
+            "/:id/appearance/wallpaper",
+            post(handlers::conversations::upload_wallpaper),
+        )
+        .route(
+            "/:id/translation",
+            get(handlers::conversations::get_translation_settings),
+        )
+        .route(
+            "/:id/translation",
+            put(handlers::conversations::update_translation_settings),
+        )
+        .layer(middleware::from_fn_with_state(MESSAGES, scope_middleware))
+        .merge(
+            Router::new()
+                .route("/", get(handlers::conversations::get_conversations))
+                .route("/:id", get(handlers::conversations::get_conversation))
+                .route("/:id/members", get(handlers::conversations::get_members))
+                .route("/:id/messages", get(handlers::conversations::get_messages))
+                .route("/:id/search", get(handlers::conversations::search_messages))
+                .layer(middleware::from_fn_with_state(READ_MESSAGES, scope_middleware)),
+        )
+        .merge(
+            Router::new()
+                .route("/:id/messages", post(handlers::conversations::send_message))
+                .layer(middleware::from_fn_with_state(SEND_MESSAGES, scope_middleware)),
+        )
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
 
     // Message routes (protected)
     let message_routes = Router::new()
+        .route("/delivered", post(handlers::messages::mark_many_delivered))
+        .route("/read", post(handlers::messages::mark_many_read))
+        .route("/ack", post(handlers::messages::ack_messages))
         .route("/:id/delivered", post(handlers::messages::mark_delivered))
         .route("/:id/read", post(handlers::messages::mark_read))
+        .route("/:id/translate", post(handlers::messages::translate_message))
+        .route("/:id/media", get(handlers::messages::open_view_once_media))
+        .route("/:id/deliveries", get(handlers::messages::get_deliveries))
+        .route("/:id/report", post(handlers::messages::report_message))
+        .route("/:id", put(handlers::messages::edit_message))
         .route("/:id", delete(handlers::messages::delete_message))
+        .layer(middleware::from_fn_with_state(MESSAGES, scope_middleware))
+        .merge(
+            Router::new()
+                .route("/:id/attachment", get(handlers::media::refresh_attachment))
+                .layer(middleware::from_fn_with_state(READ_MESSAGES, scope_middleware)),
+        )
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
+
+    // Message attachment uploads (protected)
+    let attachment_routes = Router::new()
+        .route("/", post(handlers::media::upload_attachment))
+        .layer(DefaultBodyLimit::max(message_policy::max_upload_bytes(
+            &state.config.message_limits,
+        )))
+        .layer(middleware::from_fn_with_state(SEND_MESSAGES, scope_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
+
+    // Attachment downloads (public, authorized by the link's signature)
+    let media_routes = Router::new().route("/:id", get(handlers::media::download_media));
+
+    // Broadcast list routes (protected)
+    let broadcast_routes = Router::new()
+        .route("/", get(handlers::broadcasts::get_broadcast_lists))
+        .route("/", post(handlers::broadcasts::create_broadcast_list))
+        .route("/:id", get(handlers::broadcasts::get_broadcast_list))
+        .route("/:id", put(handlers::broadcasts::update_broadcast_list))
+        .route("/:id", delete(handlers::broadcasts::delete_broadcast_list))
+        .route("/:id/messages", get(handlers::broadcasts::get_broadcasts))
+        .route("/:id/messages", post(handlers::broadcasts::send_broadcast))
+        .route(
+            "/:id/messages/:broadcast_id",
+            get(handlers::broadcasts::get_broadcast),
+        )
+        .layer(middleware::from_fn_with_state(MESSAGES, scope_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
+
+    // Organization directory (protected)
+    let directory_routes = Router::new()
+        .route("/", get(handlers::organizations::get_directory))
+        .layer(middleware::from_fn_with_state(CONTACTS, scope_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
+
+    // GIF search proxy (protected so the server's API quota isn't public)
+    let gif_routes = Router::new()
+        .route("/search", get(handlers::gifs::search_gifs))
+        .layer(middleware::from_fn_with_state(MESSAGES, scope_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
+
+    // Custom emoji available everywhere (protected)
+    let emoji_routes = Router::new()
+        .route("/", get(handlers::emoji::list_emoji))
+        .layer(middleware::from_fn_with_state(MESSAGES, scope_middleware))
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
 
     // Sticker routes (public catalog, protected for user actions)
@@ -87,31 +310,201 @@ pub fn create_router(state: AppState) -> Router<AppState> {
         .route("/packs/:id", delete(handlers::stickers::remove_sticker_pack))
         .route("/my-packs", get(handlers::stickers::get_user_sticker_packs))
         .route("/my-packs/reorder", put(handlers::stickers::reorder_sticker_packs))
+        .layer(middleware::from_fn_with_state(MESSAGES, scope_middleware))
+        .merge(
+            Router::new()
+                .route("/packs/:id/analytics", get(handlers::stickers::get_my_pack_analytics))
+                .layer(middleware::from_fn_with_state(MANAGE_STICKERS, scope_middleware)),
+        )
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
 
     // Admin sticker routes (protected - would need admin check in production)
     let admin_sticker_routes = Router::new()
+        .route("/packs/:id/analytics", get(handlers::stickers::get_pack_analytics))
+        .route("/packs/:id/curation", put(handlers::stickers::curate_sticker_pack))
+        .layer(middleware::from_fn_with_state(state.clone(), admin_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), admin_audit_middleware))
+        .layer(middleware::from_fn_with_state(ADMIN, scope_middleware))
         .route("/packs", post(handlers::stickers::create_sticker_pack))
         .route("/packs/:id/cover", post(handlers::stickers::upload_pack_cover))
         .route("/packs/:id/stickers", post(handlers::stickers::add_sticker))
+        .layer(middleware::from_fn_with_state(MANAGE_STICKERS, scope_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
+
+    // Invite routes (protected)
+    let invite_routes = Router::new()
+        .route("/", get(handlers::invites::get_invites))
+        .route("/", post(handlers::invites::create_invite))
+        .route("/referrals", get(handlers::invites::get_referrals))
+        .route("/:id", delete(handlers::invites::revoke_invite))
+        .layer(middleware::from_fn_with_state(PROFILE, scope_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
+
+    // API token routes (protected); tokens cannot manage tokens
+    let token_routes = Router::new()
+        .route("/", get(handlers::api_tokens::list_tokens))
+        .route("/", post(handlers::api_tokens::create_token))
+        .route("/:id", delete(handlers::api_tokens::revoke_token))
+        .layer(middleware::from_fn_with_state(ACCOUNT, scope_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
+
+    // Admin routes (protected, admin only)
+    let admin_routes = Router::new()
+        .route("/stats", get(handlers::admin::get_stats))
+        .route("/websocket/clients", get(handlers::admin::get_websocket_clients))
+        .route("/stats/slow", get(handlers::admin::get_slow_routes))
+        .route("/stats/latency", get(handlers::admin::get_delivery_latency))
+        .route("/migrations", get(handlers::admin::get_migrations))
+        .route("/maintenance", get(handlers::admin::get_maintenance))
+        .route("/maintenance", put(handlers::admin::set_maintenance))
+        .route("/client-versions", get(handlers::client::get_client_versions))
+        .route(
+            "/client-versions/:platform",
+            put(handlers::client::set_client_version),
+        )
+        .route(
+            "/client-versions/:platform",
+            delete(handlers::client::delete_client_version),
+        )
+        .route("/waitlist", get(handlers::waitlist::get_waitlist))
+        .route("/waitlist/approve", post(handlers::waitlist::approve_next_waitlist))
+        .route(
+            "/waitlist/:id/approve",
+            post(handlers::waitlist::approve_waitlist_entry),
+        )
+        .route("/waitlist/:id/reject", post(handlers::waitlist::reject_waitlist_entry))
+        .route("/invites", get(handlers::invites::admin_list_invites))
+        .route("/invites/:id", delete(handlers::invites::admin_revoke_invite))
+        .route(
+            "/users/:id/invite-quota",
+            put(handlers::invites::admin_set_invite_quota),
+        )
+        .route("/tokens", get(handlers::api_tokens::admin_list_tokens))
+        .route("/tokens/:id", delete(handlers::api_tokens::admin_revoke_token))
+        .route("/audit", get(handlers::admin::get_audit_log))
+        .route("/announcements", post(handlers::admin::broadcast_announcement))
+        .route("/moderation/alerts", get(handlers::admin::list_moderation_alerts))
+        .route(
+            "/moderation/alerts/:id/resolve",
+            post(handlers::admin::resolve_moderation_alert),
+        )
+        .route("/moderation/reports", get(handlers::admin::list_message_reports))
+        .route(
+            "/moderation/reports/:id/verify",
+            get(handlers::admin::verify_message_report),
+        )
+        .route(
+            "/moderation/reports/:id/resolve",
+            post(handlers::admin::resolve_message_report),
+        )
+        .route(
+            "/moderation/shadow-limits/:user_id",
+            delete(handlers::admin::lift_shadow_limit),
+        )
+        .route("/moderation/hashes", post(handlers::admin::add_blocked_hash))
+        .route(
+            "/moderation/hashes/:hash",
+            delete(handlers::admin::remove_blocked_hash),
+        )
+        .route("/search/rebuild", post(handlers::admin::rebuild_search_index))
+        .route("/analytics/events", get(handlers::admin::export_analytics_events))
+        .route("/backups", get(handlers::admin::list_backups))
+        .route("/backups", post(handlers::admin::create_backup))
+        .route("/backups/:id", delete(handlers::admin::delete_backup))
+        .route(
+            "/velocity/registrations",
+            get(handlers::admin::get_registration_velocity),
+        )
+        .route(
+            "/velocity/registrations",
+            delete(handlers::admin::clear_registration_velocity),
+        )
+        .route("/matrix/rooms/:conversation_id", put(handlers::matrix::link_room))
+        .route("/matrix/rooms/:conversation_id", delete(handlers::matrix::unlink_room))
+        .route("/emoji", post(handlers::emoji::create_global_emoji))
+        .route("/emoji/:id", put(handlers::emoji::rename_global_emoji))
+        .route("/emoji/:id", delete(handlers::emoji::delete_global_emoji))
+        .route("/organizations", get(handlers::organizations::list_organizations))
+        .route("/organizations", post(handlers::organizations::create_organization))
+        .route(
+            "/organizations/:id/directory",
+            put(handlers::organizations::import_directory),
+        )
+        .layer(middleware::from_fn_with_state(state.clone(), admin_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), admin_audit_middleware))
+        .layer(middleware::from_fn_with_state(ADMIN, scope_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
+
+    // Sealed-sender submission authenticates with a delivery token instead
+    let sealed_public_routes = Router::new().route(
+        "/:user_id/messages",
+        post(handlers::sealed_sender::send_sealed_message),
+    );
+
+    let sealed_routes = Router::new()
+        .route("/messages", get(handlers::sealed_sender::get_sealed_messages))
+        .route("/ack", post(handlers::sealed_sender::ack_sealed_messages))
+        .layer(middleware::from_fn_with_state(MESSAGES, scope_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
+
+    // Search across contacts, conversations and messages (protected)
+    let search_route = Router::new()
+        .route("/search", get(handlers::search::search))
+        .layer(middleware::from_fn_with_state(READ_MESSAGES, scope_middleware))
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
 
     // WebSocket route (protected)
     let ws_route = Router::new()
         .route("/ws", get(handle_websocket))
+        .layer(middleware::from_fn_with_state(MESSAGES, scope_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
+
+    // GraphQL gateway (protected)
+    let graphql_routes = Router::new()
+        .route("/graphql", post(graphql::graphql_handler))
+        .route("/graphql/ws", get(graphql::graphql_ws_handler))
+        .layer(Extension(graphql::build_schema(state.clone())))
+        .layer(middleware::from_fn_with_state(MESSAGES, scope_middleware))
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
 
-    // Combine all routes
+    // Matrix application service API, called by the homeserver with its own token
+    let matrix_routes = Router::new()
+        .route(
+            "/_matrix/app/v1/transactions/:txn_id",
+            put(handlers::matrix::push_transaction),
+        )
+        .route("/_matrix/app/v1/users/:user_id", get(handlers::matrix::query_user))
+        .route("/_matrix/app/v1/rooms/:alias", get(handlers::matrix::query_room));
+
+    // Combine all routes; admin routes stay writable during maintenance
     Router::new()
         .nest("/auth", auth_routes.merge(auth_protected))
-        .nest("/users", user_routes)
+        .nest("/users", user_public_routes.merge(user_routes))
         .nest("/devices", device_routes)
+        .nest("/invites", invite_routes)
+        .nest("/tokens", token_routes)
         .nest("/keys", key_routes)
         .nest("/contacts", contact_routes)
         .nest("/conversations", conversation_routes)
         .nest("/messages", message_routes)
+        .nest("/attachments", attachment_routes)
+        .nest("/media", media_routes)
+        .nest("/broadcasts", broadcast_routes)
+        .nest("/sealed", sealed_public_routes.merge(sealed_routes))
+        .nest("/directory", directory_routes)
+        .nest("/gifs", gif_routes)
+        .nest("/emoji", emoji_routes)
         .nest("/stickers", sticker_public_routes.merge(sticker_protected_routes))
-        .nest("/admin/stickers", admin_sticker_routes)
+        .merge(search_route)
         .merge(ws_route)
+        .merge(graphql_routes)
+        .layer(middleware::from_fn_with_state(state.clone(), maintenance_middleware))
+        .layer(middleware::from_fn_with_state(state.clone(), client_version_middleware))
+        // Outdated clients can still learn where to upgrade and check their clock
+        .route("/client-config", get(handlers::client::get_client_config))
+        .route("/time", get(handlers::client::get_server_time))
+        .nest("/matrix", matrix_routes)
+        .nest("/admin/stickers", admin_sticker_routes)
+        .nest("/admin", admin_routes)
         .with_state(state)
 }
@@ -1,28 +1,56 @@
 use std::{
-    collections::HashMap,
-    sync::Arc,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
     time::Duration,
 };
 
 use axum::{
-    extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
-    },
+    extract::{Query, State},
     response::Response,
     Extension,
 };
+use chrono::{DateTime, Utc};
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc, RwLock};
+use sqlx::PgPool;
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::{
+    protocol::{frame::coding::Data as OpData, CloseFrame},
+    Message,
+};
+use tracing::Instrument;
+use uuid::Uuid;
 
 use crate::{
-    services::auth::Claims,
-    storage::redis::RedisClient,
+    config::{Config, WebSocketConfig},
+    logging::SAMPLED,
+    models::{WebSocketClientStats, WebSocketStats},
+    services::{
+        auth::Claims,
+        delivery::DeliveryService,
+        devices::DeviceService,
+        messaging::MessagingService,
+        notifications::NotificationService,
+        presence::{PresenceService, PRESENCE_TTL},
+    },
+    storage::redis::{RedisClient, RoutedEvent, INSTANCE_TTL},
     AppState,
 };
 
-use super::middleware::{get_device_id, get_user_id};
+use super::{
+    middleware::{get_device_id, get_user_id},
+    ws_deflate::{compressed_frame, DeflateSocket, WsUpgrade},
+    ws_queue::{OutboundQueue, QUEUE_CAPACITY},
+    ws_resume::ResumeSessions,
+};
+
+/// A device's route to this instance, which delivery retries follow, lasts
+/// until this expires; pings refresh it
+const DEVICE_CONNECTED_TTL: Duration = Duration::from_secs(300);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WsIncomingMessage {
@@ -36,117 +64,664 @@ pub struct WsOutgoingMessage {
     #[serde(rename = "type")]
     pub msg_type: String,
     pub payload: serde_json::Value,
+    /// Position in the user's event stream; ephemeral events have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+/// Reconnecting with the previous connection's `resume` token replays the
+/// events missed since the device's last acknowledged `seq`. `last_seq`
+/// moves the start forward for clients that track what they received.
+#[derive(Debug, Deserialize)]
+pub struct WsConnectQuery {
+    pub resume: Option<String>,
+    pub last_seq: Option<u64>,
+}
+
+/// The device on the other end of a connection
+struct WsClient {
+    user_id: String,
+    device_id: i32,
+    resume_token: String,
+    connection_id: u64,
+    connected_at: DateTime<Utc>,
+    queue: Arc<OutboundQueue>,
+    /// Users whose presence this connection follows
+    watching: Arc<Mutex<HashSet<String>>>,
+}
+
+/// Close code sent when a connection is refused because the user or device
+/// is at its connection limit
+pub const CLOSE_TOO_MANY_CONNECTIONS: u16 = 4429;
+
+/// Close code sent to the oldest connection when a newer one takes its place
+pub const CLOSE_REPLACED: u16 = 4409;
+
+struct WsConnection {
+    id: u64,
+    queue: Arc<OutboundQueue>,
+    evicted: Arc<Notify>,
+}
+
+/// A connection's place in the hub
+pub struct WsRegistration {
+    pub id: u64,
+    /// Notified when a newer connection replaces this one; the connection
+    /// should close with `CLOSE_REPLACED`
+    pub evicted: Arc<Notify>,
 }
 
+/// Client map shards; a user's connections all live in the shard their ID
+/// hashes to
+const HUB_SHARDS: usize = 64;
+
+/// One user's connections by client ID (`user:device`), oldest first
+type UserClients = HashMap<String, Vec<WsConnection>>;
+
+/// Connections following one user's presence, by connection ID
+type PresenceWatchers = HashMap<u64, Arc<OutboundQueue>>;
+
+/// Users whose presence one connection may follow at once
+pub const PRESENCE_SUBSCRIPTION_LIMIT: usize = 200;
+
+/// Connections on this instance. The client map is split into shards by
+/// user, each behind its own short-lived lock that is never held across an
+/// `.await`, so registrations and fanouts for different users don't contend.
 pub struct WsHub {
-    clients: RwLock<HashMap<String, mpsc::Sender<WsOutgoingMessage>>>,
+    shards: Vec<RwLock<HashMap<String, UserClients>>>,
+    /// Presence subscriptions by watched user, sharded the same way
+    watchers: Vec<RwLock<HashMap<String, PresenceWatchers>>>,
+    next_id: AtomicU64,
+    limits: WebSocketConfig,
     redis: RedisClient,
+    /// Names this instance in the device routes of its connections
+    instance_id: String,
 }
 
 impl WsHub {
-    pub fn new(redis: RedisClient) -> Self {
+    pub fn new(redis: RedisClient, limits: WebSocketConfig) -> Self {
         Self {
-            clients: RwLock::new(HashMap::new()),
+            shards: (0..HUB_SHARDS).map(|_| RwLock::default()).collect(),
+            watchers: (0..HUB_SHARDS).map(|_| RwLock::default()).collect(),
+            next_id: AtomicU64::new(1),
+            limits,
             redis,
+            instance_id: Uuid::new_v4().simple().to_string(),
         }
     }
 
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
     pub async fn run(&self) {
-        // This is a placeholder for any hub-level background tasks
-        // In production, you might want to implement heartbeat checking here
+        tokio::join!(
+            self.heartbeat(),
+            self.relay_routed(),
+            self.relay_broadcasts(),
+            self.relay_presence()
+        );
+    }
+
+    /// Keep routes to this instance's devices alive
+    async fn heartbeat(&self) {
+        let mut interval = tokio::time::interval(INSTANCE_TTL / 3);
         loop {
-            tokio::time::sleep(Duration::from_secs(60)).await;
+            interval.tick().await;
+            if let Err(e) = self.redis.heartbeat_instance(&self.instance_id).await {
+                tracing::warn!("Failed to send instance heartbeat: {}", e);
+            }
+        }
+    }
+
+    /// Deliver events other instances routed to devices connected here
+    async fn relay_routed(&self) {
+        loop {
+            match self.redis.subscribe_instance(&self.instance_id).await {
+                Ok(mut stream) => {
+                    while let Some(msg) = stream.next().await {
+                        let Ok(routed) = serde_json::from_str::<RoutedEvent>(&msg.payload) else {
+                            continue;
+                        };
+                        if let Ok(ws_msg) = serde_json::from_str::<WsOutgoingMessage>(&routed.event)
+                        {
+                            self.push_to_device(&routed.user_id, routed.device_id, ws_msg);
+                        }
+                    }
+                    tracing::warn!("Instance subscription closed, resubscribing");
+                }
+                Err(e) => {
+                    tracing::error!("Failed to subscribe to routed events: {}", e);
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    /// Relay server-wide broadcasts (e.g. announcements) published by any instance
+    async fn relay_broadcasts(&self) {
+        loop {
+            match self.redis.subscribe_broadcast().await {
+                Ok(mut stream) => {
+                    while let Some(msg) = stream.next().await {
+                        if let Ok(ws_msg) = serde_json::from_str::<WsOutgoingMessage>(&msg.payload)
+                        {
+                            self.broadcast(ws_msg).await;
+                        }
+                    }
+                    tracing::warn!("Broadcast subscription closed, resubscribing");
+                }
+                Err(e) => {
+                    tracing::error!("Failed to subscribe to broadcasts: {}", e);
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
         }
     }
 
-    pub async fn register(&self, client_id: &str, sender: mpsc::Sender<WsOutgoingMessage>) {
-        let mut clients = self.clients.write().await;
-        clients.insert(client_id.to_string(), sender);
+    /// Pass presence changes from any instance to the connections following
+    /// that user here
+    async fn relay_presence(&self) {
+        loop {
+            match self.redis.subscribe_presence().await {
+                Ok(mut stream) => {
+                    while let Some(msg) = stream.next().await {
+                        if let Ok(ws_msg) = serde_json::from_str::<WsOutgoingMessage>(&msg.payload)
+                        {
+                            self.send_presence(ws_msg).await;
+                        }
+                    }
+                    tracing::warn!("Presence subscription closed, resubscribing");
+                }
+                Err(e) => {
+                    tracing::error!("Failed to subscribe to presence: {}", e);
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    fn shard(&self, user_id: &str) -> &RwLock<HashMap<String, UserClients>> {
+        &self.shards[shard_index(user_id)]
+    }
+
+    fn watcher_shard(&self, user_id: &str) -> &RwLock<HashMap<String, PresenceWatchers>> {
+        &self.watchers[shard_index(user_id)]
+    }
+
+    /// Start passing the users' presence changes to one connection
+    pub async fn watch_presence(
+        &self,
+        connection_id: u64,
+        queue: &Arc<OutboundQueue>,
+        user_ids: &[String],
+    ) {
+        for user_id in user_ids {
+            let mut shard = self
+                .watcher_shard(user_id)
+                .write()
+                .unwrap_or_else(|e| e.into_inner());
+            shard
+                .entry(user_id.clone())
+                .or_default()
+                .insert(connection_id, queue.clone());
+        }
+    }
+
+    /// Stop passing the users' presence changes to one connection
+    pub async fn unwatch_presence(&self, connection_id: u64, user_ids: &[String]) {
+        for user_id in user_ids {
+            let mut shard = self
+                .watcher_shard(user_id)
+                .write()
+                .unwrap_or_else(|e| e.into_inner());
+            if let Some(watchers) = shard.get_mut(user_id) {
+                watchers.remove(&connection_id);
+                if watchers.is_empty() {
+                    shard.remove(user_id);
+                }
+            }
+        }
+    }
+
+    /// Hand a `presence` event to the connections following its user
+    async fn send_presence(&self, message: WsOutgoingMessage) {
+        let Some(user_id) = message.payload.get("user_id").and_then(|id| id.as_str()) else {
+            return;
+        };
+
+        let shard = self
+            .watcher_shard(user_id)
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        for queue in shard.get(user_id).into_iter().flat_map(|watchers| watchers.values()) {
+            queue.push(message.clone());
+        }
+    }
+
+    /// Add a connection, enforcing the per-device and per-user limits. Over a
+    /// limit, the oldest connections are evicted to make room, or with
+    /// `reject_over_limit` the new one is refused and `None` returned.
+    pub async fn register(
+        &self,
+        client_id: &str,
+        queue: Arc<OutboundQueue>,
+    ) -> Option<WsRegistration> {
+        let user_id = client_user_id(client_id);
+        let mut shard = self.shard(user_id).write().unwrap_or_else(|e| e.into_inner());
+        let user = shard.entry(user_id.to_string()).or_default();
+
+        let device_count = user.get(client_id).map_or(0, Vec::len);
+        let user_count: usize = user.values().map(Vec::len).sum();
+        if device_count >= self.limits.max_connections_per_device
+            || user_count >= self.limits.max_connections_per_user
+        {
+            if self.limits.reject_over_limit {
+                if user.is_empty() {
+                    shard.remove(user_id);
+                }
+                tracing::warn!("Rejected connection over limit: {}", client_id);
+                return None;
+            }
+
+            while user.get(client_id).map_or(0, Vec::len) >= self.limits.max_connections_per_device
+            {
+                evict_oldest(user, Some(client_id));
+            }
+            while user.values().map(Vec::len).sum::<usize>()
+                >= self.limits.max_connections_per_user
+            {
+                evict_oldest(user, None);
+            }
+        }
+
+        let registration = WsRegistration {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            evicted: Arc::new(Notify::new()),
+        };
+        user.entry(client_id.to_string())
+            .or_default()
+            .push(WsConnection {
+                id: registration.id,
+                queue,
+                evicted: registration.evicted.clone(),
+            });
         tracing::info!("Client registered: {}", client_id);
+
+        Some(registration)
     }
 
-    pub async fn unregister(&self, client_id: &str) {
-        let mut clients = self.clients.write().await;
-        clients.remove(client_id);
+    /// Remove one connection; returns whether the client still has others
+    pub async fn unregister(&self, client_id: &str, connection_id: u64) -> bool {
+        let user_id = client_user_id(client_id);
+        let mut shard = self.shard(user_id).write().unwrap_or_else(|e| e.into_inner());
+        let Some(user) = shard.get_mut(user_id) else {
+            return false;
+        };
+
+        let remaining = match user.get_mut(client_id) {
+            Some(connections) => {
+                connections.retain(|c| c.id != connection_id);
+                connections.len()
+            }
+            None => 0,
+        };
+        if remaining == 0 {
+            user.remove(client_id);
+        }
+        if user.is_empty() {
+            shard.remove(user_id);
+        }
         tracing::info!("Client unregistered: {}", client_id);
+
+        remaining > 0
     }
 
-    pub async fn send_to_user(&self, user_id: &str, message: WsOutgoingMessage) {
-        let clients = self.clients.read().await;
+    /// Send a message to every client connected to this instance, one shard
+    /// at a time
+    pub async fn broadcast(&self, message: WsOutgoingMessage) {
+        for shard in &self.shards {
+            let shard = shard.read().unwrap_or_else(|e| e.into_inner());
+            for connection in shard.values().flat_map(|user| user.values().flatten()) {
+                connection.queue.push(message.clone());
+            }
+        }
+    }
+
+    /// Connection counts for this instance
+    pub async fn stats(&self) -> WebSocketStats {
+        let mut stats = WebSocketStats::default();
 
-        // Find all clients for this user (could be multiple devices)
-        for (client_id, sender) in clients.iter() {
-            if client_id.starts_with(&format!("{}:", user_id)) {
-                let _ = sender.send(message.clone()).await;
+        for shard in &self.shards {
+            let shard = shard.read().unwrap_or_else(|e| e.into_inner());
+            stats.connected_users += shard.len();
+            for connection in shard.values().flat_map(|user| user.values().flatten()) {
+                let queued = connection.queue.len();
+                stats.connections += 1;
+                stats.queued_events += queued;
+                if queued * 2 > connection.queue.capacity() {
+                    stats.lagging_clients += 1;
+                }
             }
         }
 
-        // Also publish to Redis for other server instances
+        stats
+    }
+
+    /// Outbound queue metrics for each connection, most lagged first
+    pub async fn client_stats(&self) -> Vec<WebSocketClientStats> {
+        let mut stats = Vec::new();
+
+        for shard in &self.shards {
+            let shard = shard.read().unwrap_or_else(|e| e.into_inner());
+            for (client_id, connections) in shard.values().flatten() {
+                for connection in connections {
+                    let queue = connection.queue.stats();
+                    stats.push(WebSocketClientStats {
+                        client_id: client_id.clone(),
+                        connection_id: connection.id,
+                        queued: queue.queued,
+                        max_queued: queue.max_queued,
+                        lag_ms: queue.lag_ms,
+                        sent: queue.sent,
+                        dropped: queue.dropped,
+                        overflowed: queue.overflowed,
+                    });
+                }
+            }
+        }
+
+        stats.sort_by(|a, b| b.lag_ms.cmp(&a.lag_ms).then(b.queued.cmp(&a.queued)));
+        stats
+    }
+
+    pub async fn send_to_user(&self, user_id: &str, message: WsOutgoingMessage) {
+        // All of this user's clients on this instance (could be multiple devices);
+        // the shard lock is released before publishing
+        {
+            let shard = self.shard(user_id).read().unwrap_or_else(|e| e.into_inner());
+            let connections = shard.get(user_id).into_iter().flat_map(|user| user.values());
+            for connection in connections.flatten() {
+                connection.queue.push(message.clone());
+            }
+        }
+
+        // Also publish for other server instances
         if let Ok(msg_str) = serde_json::to_string(&message) {
             let _ = self.redis.publish_message(user_id, &msg_str).await;
         }
     }
 
-    pub async fn send_to_device(&self, user_id: &str, device_id: &str, message: WsOutgoingMessage) {
-        let clients = self.clients.read().await;
+    /// Send to one device, wherever it is connected: directly when here,
+    /// otherwise through the instance its route names
+    pub async fn send_to_device(&self, user_id: &str, device_id: i32, message: WsOutgoingMessage) {
+        if self.push_to_device(user_id, device_id, message.clone()) {
+            return;
+        }
+
+        if let Ok(msg_str) = serde_json::to_string(&message) {
+            let _ = self
+                .redis
+                .publish_device_message(user_id, device_id, &msg_str)
+                .await;
+        }
+    }
+
+    /// Queue for the device's connections on this instance. Returns whether
+    /// it has any.
+    fn push_to_device(&self, user_id: &str, device_id: i32, message: WsOutgoingMessage) -> bool {
         let client_id = format!("{}:{}", user_id, device_id);
+        let shard = self.shard(user_id).read().unwrap_or_else(|e| e.into_inner());
+
+        let connections = shard.get(user_id).and_then(|user| user.get(&client_id));
+        let mut delivered = false;
+        for connection in connections.into_iter().flatten() {
+            connection.queue.push(message.clone());
+            delivered = true;
+        }
+        delivered
+    }
+}
+
+fn shard_index(user_id: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    user_id.hash(&mut hasher);
+    hasher.finish() as usize % HUB_SHARDS
+}
+
+/// The user part of a client ID (`user:device` or `user:graphql:id`)
+fn client_user_id(client_id: &str) -> &str {
+    client_id.split(':').next().unwrap_or_default()
+}
+
+/// Drop the user's oldest connection, or the oldest of one client's, and tell
+/// it to close
+fn evict_oldest(user: &mut UserClients, only: Option<&str>) {
+    let oldest = user
+        .iter()
+        .filter(|(client_id, _)| only.is_none() || only == Some(client_id.as_str()))
+        .filter_map(|(client_id, connections)| Some((connections.first()?.id, client_id.clone())))
+        .min();
+    let Some((id, client_id)) = oldest else {
+        return;
+    };
 
-        if let Some(sender) = clients.get(&client_id) {
-            let _ = sender.send(message).await;
+    if let Some(connections) = user.get_mut(&client_id) {
+        if let Some(index) = connections.iter().position(|c| c.id == id) {
+            let connection = connections.remove(index);
+            connection.evicted.notify_one();
+            tracing::info!("Evicted oldest connection: {}", client_id);
+        }
+        if connections.is_empty() {
+            user.remove(&client_id);
         }
     }
 }
 
 pub async fn handle_websocket(
-    ws: WebSocketUpgrade,
+    ws: WsUpgrade,
     State(state): State<AppState>,
     Extension(claims): Extension<Claims>,
+    Query(query): Query<WsConnectQuery>,
 ) -> Response {
     let user_id = get_user_id(&claims).unwrap_or_default();
     let device_id = get_device_id(&claims).unwrap_or(1);
 
-    ws.on_upgrade(move |socket| handle_socket(socket, state, user_id.to_string(), device_id))
+    // Clients offering permessage-deflate get large events compressed
+    let compression = state.config.websocket.compression;
+
+    // The connection keeps logging under the upgrade request's span
+    let span = tracing::Span::current();
+    ws.on_upgrade(compression, move |socket, deflate| {
+        handle_socket(socket, deflate, state, user_id.to_string(), device_id, query)
+            .instrument(span)
+    })
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState, user_id: String, device_id: i32) {
+/// Serialize an event as a text frame, compressed with permessage-deflate
+/// once it reaches `compress_from` bytes
+fn encode_frame(message: &WsOutgoingMessage, compress_from: Option<usize>) -> Option<Message> {
+    let json = serde_json::to_string(message).ok()?;
+
+    match compress_from {
+        Some(threshold) if json.len() >= threshold => {
+            compressed_frame(json.as_bytes(), OpData::Text).map(Message::Frame)
+        }
+        _ => Some(Message::Text(json)),
+    }
+}
+
+async fn handle_socket(
+    socket: DeflateSocket,
+    deflate: bool,
+    state: AppState,
+    user_id: String,
+    device_id: i32,
+    query: WsConnectQuery,
+) {
     let client_id = format!("{}:{}", user_id, device_id);
-    let (mut ws_sender, mut ws_receiver) = socket.split();
+    let compress_from = deflate.then_some(state.config.websocket.compression_threshold);
 
-    // Create channel for sending messages to this client
-    let (tx, mut rx) = mpsc::channel::<WsOutgoingMessage>(256);
+    // Bounded queue of events waiting to be written to this client
+    let queue = Arc::new(OutboundQueue::new(QUEUE_CAPACITY));
 
     // Register client
-    state.ws_hub.register(&client_id, tx.clone()).await;
+    let Some(registration) = state.ws_hub.register(&client_id, queue.clone()).await else {
+        let mut socket = socket;
+        let _ = socket
+            .send(Message::Close(Some(CloseFrame {
+                code: CLOSE_TOO_MANY_CONNECTIONS.into(),
+                reason: "Too many connections".into(),
+            })))
+            .await;
+        return;
+    };
+    let (mut ws_sender, mut ws_receiver) = socket.split();
 
     // Set user presence to online
+    let presence = PresenceService::new(state.db.clone(), state.redis.clone());
+    let _ = presence.set_status(&user_id, "online", PRESENCE_TTL).await;
+    let connected_at = Utc::now();
     let _ = state
         .redis
-        .set_user_presence(&user_id, "online", Duration::from_secs(300))
+        .set_device_connected(
+            &user_id,
+            device_id,
+            state.ws_hub.instance_id(),
+            connected_at,
+            DEVICE_CONNECTED_TTL,
+        )
         .await;
 
-    // Subscribe to Redis for this user
-    let redis_client = state.redis.clone();
-    let user_id_clone = user_id.clone();
-    let tx_clone = tx.clone();
+    let delivery = DeliveryService::new(
+        state.db.clone(),
+        state.redis.clone(),
+        (*state.config).clone(),
+        NotificationService::new(
+            state.db.clone(),
+            state.redis.clone(),
+            state.push.clone(),
+            state.email.clone(),
+        ),
+    );
 
-    let redis_task = tokio::spawn(async move {
-        if let Ok(mut pubsub) = redis_client.subscribe_messages(&user_id_clone).await {
-            while let Some(msg) = pubsub.on_message().next().await {
-                if let Ok(payload) = msg.get_payload::<String>() {
-                    if let Ok(ws_msg) = serde_json::from_str::<WsOutgoingMessage>(&payload) {
-                        let _ = tx_clone.send(ws_msg).await;
+    // Task to send messages to WebSocket, until a newer connection replaces
+    // this one
+    let evicted = registration.evicted.clone();
+    let outbound = queue.clone();
+    let send_task = tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                msg = outbound.recv() => {
+                    if let Some(frame) = encode_frame(&msg, compress_from) {
+                        if ws_sender.send(frame).await.is_err() {
+                            break;
+                        }
                     }
                 }
+                _ = evicted.notified() => {
+                    let _ = ws_sender
+                        .send(Message::Close(Some(CloseFrame {
+                            code: CLOSE_REPLACED.into(),
+                            reason: "Replaced by a newer connection".into(),
+                        })))
+                        .await;
+                    break;
+                }
             }
         }
     });
 
-    // Task to send messages to WebSocket
-    let send_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if let Ok(json) = serde_json::to_string(&msg) {
-                if ws_sender.send(Message::Text(json)).await.is_err() {
-                    break;
+    // Replay messages this device has not acknowledged yet, at the pace the
+    // client reads them
+    let pending = match Uuid::parse_str(&user_id) {
+        Ok(user_uuid) => delivery.pending(user_uuid, device_id).await,
+        Err(_) => Ok(Vec::new()),
+    };
+    let replay = queue.clone();
+    let replay_task = tokio::spawn(async move {
+        match pending {
+            Ok(messages) => {
+                for message in messages {
+                    if let Ok(payload) = serde_json::to_value(&message) {
+                        replay
+                            .send(WsOutgoingMessage {
+                                msg_type: "new_message".to_string(),
+                                payload,
+                                seq: None,
+                            })
+                            .await;
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Failed to replay pending messages: {}", e),
+        }
+    });
+
+    // Subscribe to this user's events before reading the stream's position,
+    // so nothing published in between is lost. Events for this device alone
+    // arrive through the hub.
+    let subscription = state.redis.subscribe_messages(&user_id).await;
+    let current_seq = state
+        .redis
+        .current_event_seq(&user_id)
+        .await
+        .unwrap_or(0);
+
+    // A reconnect with a valid resume token replays what the device missed;
+    // otherwise the client resyncs as usual
+    let sessions = ResumeSessions::new(state.redis.clone());
+    let resume_from = match &query.resume {
+        Some(token) => sessions
+            .take(token, &user_id, device_id)
+            .await
+            .ok()
+            .flatten()
+            .map(|acked| acked.max(query.last_seq.unwrap_or(0))),
+        None => None,
+    };
+    let missed = match resume_from {
+        Some(after) => sessions
+            .missed_events(&user_id, after, current_seq)
+            .await
+            .ok()
+            .flatten(),
+        None => None,
+    };
+    let resumed = missed.is_some();
+
+    let acked_seq = resume_from.filter(|_| resumed).unwrap_or(current_seq);
+    let resume_token = sessions
+        .open(&user_id, device_id, acked_seq)
+        .await
+        .unwrap_or_default();
+    queue.push(WsOutgoingMessage {
+        msg_type: "session".to_string(),
+        payload: serde_json::json!({
+            "resume_token": resume_token,
+            "seq": current_seq,
+            "resumed": resumed,
+            "server_time": Utc::now(),
+        }),
+        seq: None,
+    });
+    for event in missed.unwrap_or_default() {
+        queue.send(event).await;
+    }
+
+    // Events for a lagging client follow the queue's overflow policies.
+    // Those already replayed can arrive again live and are skipped.
+    let replayed_through = if resumed { current_seq } else { 0 };
+    let inbound = queue.clone();
+
+    let redis_task = tokio::spawn(async move {
+        if let Ok(mut stream) = subscription {
+            while let Some(msg) = stream.next().await {
+                if let Ok(ws_msg) = serde_json::from_str::<WsOutgoingMessage>(&msg.payload) {
+                    if ws_msg.seq.is_some_and(|seq| seq <= replayed_through) {
+                        continue;
+                    }
+                    inbound.push(ws_msg);
                 }
             }
         }
@@ -154,20 +729,39 @@ async fn handle_socket(socket: WebSocket, state: AppState, user_id: String, devi
 
     // Task to receive messages from WebSocket
     let hub = state.ws_hub.clone();
+    let db = state.db.clone();
     let redis = state.redis.clone();
-    let user_id_for_recv = user_id.clone();
+    let config = state.config.clone();
+    let watching = Arc::new(Mutex::new(HashSet::new()));
+    let mut client = WsClient {
+        user_id: user_id.clone(),
+        device_id,
+        resume_token: resume_token.clone(),
+        connection_id: registration.id,
+        connected_at,
+        queue: queue.clone(),
+        watching: watching.clone(),
+    };
 
     let recv_task = tokio::spawn(async move {
         while let Some(result) = ws_receiver.next().await {
             match result {
                 Ok(Message::Text(text)) => {
                     if let Ok(msg) = serde_json::from_str::<WsIncomingMessage>(&text) {
-                        handle_incoming_message(&hub, &redis, &user_id_for_recv, device_id, msg)
-                            .await;
+                        handle_incoming_message(
+                            &hub,
+                            &db,
+                            &redis,
+                            &config,
+                            &delivery,
+                            &mut client,
+                            msg,
+                        )
+                        .await;
                     }
                 }
                 Ok(Message::Ping(data)) => {
-                    // Pong is handled automatically by axum
+                    // Pong is handled automatically by tungstenite
                     let _ = data;
                 }
                 Ok(Message::Close(_)) => break,
@@ -177,64 +771,261 @@ async fn handle_socket(socket: WebSocket, state: AppState, user_id: String, devi
         }
     });
 
-    // Wait for any task to complete
+    // Wait for any task to complete, then stop the rest so the event bus
+    // subscription is released with the connection
+    let (mut send_task, mut recv_task, mut redis_task) = (send_task, recv_task, redis_task);
     tokio::select! {
-        _ = send_task => {},
-        _ = recv_task => {},
-        _ = redis_task => {},
+        _ = &mut send_task => {},
+        _ = &mut recv_task => {},
+        _ = &mut redis_task => {},
     }
+    send_task.abort();
+    recv_task.abort();
+    redis_task.abort();
+    replay_task.abort();
 
-    // Cleanup
-    state.ws_hub.unregister(&client_id).await;
+    // The device can resume from here for a while
+    let _ = sessions.touch(&resume_token).await;
 
-    // Set user presence to offline
+    // Cleanup; another connection from the same device keeps it connected
+    let watched: Vec<String> = watching
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .drain()
+        .collect();
+    state
+        .ws_hub
+        .unwatch_presence(registration.id, &watched)
+        .await;
+    let still_connected = state
+        .ws_hub
+        .unregister(&client_id, registration.id)
+        .await;
+    if still_connected {
+        return;
+    }
     let _ = state
         .redis
-        .set_user_presence(&user_id, "offline", Duration::from_secs(1))
+        .clear_device_connected(&user_id, device_id, state.ws_hub.instance_id())
+        .await;
+
+    // Set user presence to offline
+    let _ = presence
+        .set_status(&user_id, "offline", Duration::from_secs(1))
         .await;
 }
 
 async fn handle_incoming_message(
     hub: &Arc<WsHub>,
+    db: &PgPool,
     redis: &RedisClient,
-    user_id: &str,
-    _device_id: i32,
+    config: &Config,
+    delivery: &DeliveryService,
+    client: &mut WsClient,
     msg: WsIncomingMessage,
 ) {
+    let (user_id, device_id) = (client.user_id.as_str(), client.device_id);
+
     match msg.msg_type.as_str() {
         "ping" => {
+            tracing::debug!(target: SAMPLED, device_id, "WebSocket ping");
+            let _ = redis
+                .set_device_connected(
+                    user_id,
+                    device_id,
+                    hub.instance_id(),
+                    client.connected_at,
+                    DEVICE_CONNECTED_TTL,
+                )
+                .await;
+            // An open connection keeps its resume token alive
+            let _ = ResumeSessions::new(redis.clone())
+                .touch(&client.resume_token)
+                .await;
+
             // Respond with pong
             let pong = WsOutgoingMessage {
                 msg_type: "pong".to_string(),
-                payload: serde_json::json!({}),
+                payload: serde_json::json!({ "server_time": Utc::now() }),
+                seq: None,
             };
             hub.send_to_user(user_id, pong).await;
         }
         "typing" => {
-            // Forward typing indicator to conversation participants
-            // This would need conversation_id from payload
-            if let Some(conversation_id) = msg.payload.get("conversation_id") {
+            // Sent on to the conversation as part of its `typing_users`
+            let conversation_id = msg
+                .payload
+                .get("conversation_id")
+                .and_then(|c| c.as_str())
+                .and_then(|c| Uuid::parse_str(c).ok());
+            let is_typing = msg
+                .payload
+                .get("is_typing")
+                .and_then(|t| t.as_bool())
+                .unwrap_or(true);
+
+            if let (Ok(user_uuid), Some(conversation_id)) =
+                (Uuid::parse_str(user_id), conversation_id)
+            {
                 tracing::debug!(
+                    target: SAMPLED,
                     "User {} typing in conversation {}",
                     user_id,
                     conversation_id
                 );
+                let messaging = MessagingService::new(db.clone(), redis.clone(), config.clone());
+                if let Err(e) = messaging
+                    .broadcast_typing(conversation_id, user_uuid, is_typing)
+                    .await
+                {
+                    tracing::warn!("Typing from {} failed: {}", user_id, e);
+                }
             }
         }
         "presence" => {
             // Update user presence
             if let Some(status) = msg.payload.get("status").and_then(|s| s.as_str()) {
-                let _ = redis
-                    .set_user_presence(user_id, status, Duration::from_secs(300))
-                    .await;
+                if matches!(status, "online" | "away" | "offline") {
+                    let presence = PresenceService::new(db.clone(), redis.clone());
+                    let _ = presence.set_status(user_id, status, PRESENCE_TTL).await;
+                }
+            }
+        }
+        "subscribe_presence" => {
+            // Follow the presence of users the client is displaying
+            let requested = presence_user_ids(&msg.payload);
+            let Ok(user_uuid) = Uuid::parse_str(user_id) else {
+                return;
+            };
+            let presence = PresenceService::new(db.clone(), redis.clone());
+            let visible = match presence.visible_to(user_uuid, &requested).await {
+                Ok(visible) => visible,
+                Err(e) => {
+                    tracing::warn!("Presence subscription by {} failed: {}", user_id, e);
+                    return;
+                }
+            };
+
+            let added: Vec<Uuid> = {
+                let mut watching = client.watching.lock().unwrap_or_else(|e| e.into_inner());
+                let mut added = Vec::new();
+                for id in visible {
+                    if watching.len() >= PRESENCE_SUBSCRIPTION_LIMIT {
+                        break;
+                    }
+                    if watching.insert(id.to_string()) {
+                        added.push(id);
+                    }
+                }
+                added
+            };
+            let added_ids: Vec<String> = added.iter().map(Uuid::to_string).collect();
+            hub.watch_presence(client.connection_id, &client.queue, &added_ids)
+                .await;
+
+            // Current statuses, so the client starts from the right state
+            for (id, status) in presence.statuses(&added).await.unwrap_or_default() {
+                client.queue.push(WsOutgoingMessage {
+                    msg_type: "presence".to_string(),
+                    payload: serde_json::json!({ "user_id": id, "status": status }),
+                    seq: None,
+                });
+            }
+            send_presence_subscriptions(client);
+        }
+        "unsubscribe_presence" => {
+            let removed: Vec<String> = {
+                let mut watching = client.watching.lock().unwrap_or_else(|e| e.into_inner());
+                presence_user_ids(&msg.payload)
+                    .iter()
+                    .map(Uuid::to_string)
+                    .filter(|id| watching.remove(id))
+                    .collect()
+            };
+            hub.unwatch_presence(client.connection_id, &removed).await;
+            send_presence_subscriptions(client);
+        }
+        "device_verification" => {
+            // The primary device approving or rejecting one of the user's devices
+            let target = msg
+                .payload
+                .get("device_id")
+                .and_then(|d| d.as_str())
+                .and_then(|d| Uuid::parse_str(d).ok());
+            let approved = msg.payload.get("approved").and_then(|a| a.as_bool());
+
+            if let (Ok(user_uuid), Some(target), Some(approved)) =
+                (Uuid::parse_str(user_id), target, approved)
+            {
+                let device_service = DeviceService::new(db.clone(), redis.clone());
+                if let Err(e) = device_service
+                    .resolve_verification(user_uuid, device_id, target, approved)
+                    .await
+                {
+                    tracing::warn!("Device verification by {} failed: {}", user_id, e);
+                }
             }
         }
         "ack" => {
-            // Handle message acknowledgment
-            tracing::debug!("User {} ack: {:?}", user_id, msg.payload);
+            // Events up to `seq` need no replay when the device resumes
+            if let Some(seq) = msg.payload.get("seq").and_then(|s| s.as_u64()) {
+                let sessions = ResumeSessions::new(redis.clone());
+                if let Err(e) = sessions.ack(&client.resume_token, seq).await {
+                    tracing::warn!("Ack from {}:{} failed: {}", user_id, device_id, e);
+                }
+            }
+
+            // This device received the messages; stops their redelivery
+            let message_ids: Vec<Uuid> = msg
+                .payload
+                .get("message_ids")
+                .and_then(|ids| serde_json::from_value(ids.clone()).ok())
+                .or_else(|| {
+                    msg.payload
+                        .get("message_id")
+                        .and_then(|id| id.as_str())
+                        .and_then(|id| Uuid::parse_str(id).ok())
+                        .map(|id| vec![id])
+                })
+                .unwrap_or_default();
+
+            if let (Ok(user_uuid), false) = (Uuid::parse_str(user_id), message_ids.is_empty()) {
+                if let Err(e) = delivery.ack(user_uuid, device_id, &message_ids).await {
+                    tracing::warn!("Ack from {}:{} failed: {}", user_id, device_id, e);
+                }
+            }
         }
         _ => {
             tracing::warn!("Unknown message type: {}", msg.msg_type);
         }
     }
 }
+
+/// The `user_ids` of a presence subscription request
+fn presence_user_ids(payload: &serde_json::Value) -> Vec<Uuid> {
+    payload
+        .get("user_ids")
+        .and_then(|ids| serde_json::from_value(ids.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// Tell the client which users it now follows, so it can see what did not
+/// fit under the limit or was not visible
+fn send_presence_subscriptions(client: &WsClient) {
+    let user_ids: Vec<String> = client
+        .watching
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .cloned()
+        .collect();
+
+    client.queue.push(WsOutgoingMessage {
+        msg_type: "presence_subscriptions".to_string(),
+        payload: serde_json::json!({
+            "user_ids": user_ids,
+            "limit": PRESENCE_SUBSCRIPTION_LIMIT,
+        }),
+        seq: None,
+    });
+}
@@ -0,0 +1,397 @@
+use std::{
+    future::Future,
+    io::{self, Cursor},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use axum::{
+    async_trait,
+    body::Body,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper_util::rt::TokioIo;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::{
+    tungstenite::protocol::{
+        frame::{
+            coding::{Data as OpData, OpCode},
+            Frame, FrameHeader,
+        },
+        Role,
+    },
+    WebSocketStream,
+};
+
+use crate::error::AppError;
+
+/// What the server answers a permessage-deflate offer with. It compresses
+/// every message on its own, so clients keep no window for it.
+const DEFLATE_RESPONSE: &str = "permessage-deflate; server_no_context_takeover";
+
+/// Every deflate block flushed with `Z_SYNC_FLUSH` ends in these bytes,
+/// which permessage-deflate leaves off the wire (RFC 7692, section 7.2.1)
+const SYNC_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Largest client message accepted, compressed or inflated. Clients only
+/// send small control events.
+const MAX_CLIENT_MESSAGE: usize = 1024 * 1024;
+
+/// Bytes read from the client at a time
+const READ_CHUNK: usize = 8 * 1024;
+
+pub type DeflateSocket = WebSocketStream<InflateIo<TokioIo<Upgraded>>>;
+
+/// The WebSocket handshake, with permessage-deflate (RFC 7692) negotiated
+/// when the client offers it. axum's upgrade can't negotiate extensions.
+pub struct WsUpgrade {
+    key: HeaderValue,
+    on_upgrade: OnUpgrade,
+    deflate_offered: bool,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for WsUpgrade {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let headers = &parts.headers;
+        let upgrade = header_str(headers, header::UPGRADE).eq_ignore_ascii_case("websocket");
+        let connection = header_str(headers, header::CONNECTION)
+            .to_ascii_lowercase()
+            .contains("upgrade");
+        if !upgrade || !connection {
+            return Err(AppError::BadRequest(
+                "Expected a WebSocket upgrade".to_string(),
+            ));
+        }
+        if header_str(headers, header::SEC_WEBSOCKET_VERSION) != "13" {
+            return Err(AppError::BadRequest(
+                "WebSocket version must be 13".to_string(),
+            ));
+        }
+
+        let key = headers
+            .get(header::SEC_WEBSOCKET_KEY)
+            .cloned()
+            .ok_or_else(|| AppError::BadRequest("Missing Sec-WebSocket-Key".to_string()))?;
+        let deflate_offered = headers
+            .get_all(header::SEC_WEBSOCKET_EXTENSIONS)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(acceptable_offer);
+        let on_upgrade = parts
+            .extensions
+            .remove::<OnUpgrade>()
+            .ok_or_else(|| AppError::BadRequest("Connection can't be upgraded".to_string()))?;
+
+        Ok(Self {
+            key,
+            on_upgrade,
+            deflate_offered,
+        })
+    }
+}
+
+impl WsUpgrade {
+    /// Answer the handshake and hand the socket to `callback`, with whether
+    /// permessage-deflate was negotiated; it only is if `compression` allows
+    pub fn on_upgrade<C, Fut>(self, compression: bool, callback: C) -> Response
+    where
+        C: FnOnce(DeflateSocket, bool) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let deflate = compression && self.deflate_offered;
+        let on_upgrade = self.on_upgrade;
+        tokio::spawn(async move {
+            let upgraded = match on_upgrade.await {
+                Ok(upgraded) => upgraded,
+                Err(e) => {
+                    tracing::debug!("WebSocket upgrade failed: {}", e);
+                    return;
+                }
+            };
+            let io = InflateIo::new(TokioIo::new(upgraded), deflate);
+            let socket = WebSocketStream::from_raw_socket(io, Role::Server, None).await;
+            callback(socket, deflate).await;
+        });
+
+        let mut accept = Sha1::new();
+        accept.update(self.key.as_bytes());
+        accept.update(b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11");
+
+        let mut response = Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(header::CONNECTION, "upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(
+                header::SEC_WEBSOCKET_ACCEPT,
+                BASE64.encode(accept.finalize()),
+            );
+        if deflate {
+            response = response.header(header::SEC_WEBSOCKET_EXTENSIONS, DEFLATE_RESPONSE);
+        }
+
+        response.body(Body::empty()).unwrap_or_default()
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> &str {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// Whether a permessage-deflate offer can be accepted as answered by
+/// `DEFLATE_RESPONSE`. Offers limiting the server's window below the
+/// default can't be honoured by the compressor and are declined.
+fn acceptable_offer(offer: &str) -> bool {
+    let mut params = offer.split(';').map(str::trim);
+    if params.next() != Some("permessage-deflate") {
+        return false;
+    }
+
+    params.all(|param| {
+        let (name, value) = match param.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (param, None),
+        };
+        match (name, value) {
+            ("server_no_context_takeover" | "client_no_context_takeover", None) => true,
+            ("client_max_window_bits", None) => true,
+            ("client_max_window_bits", Some(bits)) => bits.parse::<u8>().is_ok(),
+            ("server_max_window_bits", Some(bits)) => bits == "15",
+            _ => false,
+        }
+    })
+}
+
+/// A message as permessage-deflate sends it: a raw deflate stream flushed
+/// with `Z_SYNC_FLUSH`, without the trailing empty block
+pub fn compressed_frame(data: &[u8], opcode: OpData) -> Option<Frame> {
+    let mut compress = Compress::new(Compression::default(), false);
+    let mut out = Vec::with_capacity(data.len() / 2 + 64);
+    loop {
+        let consumed = compress.total_in() as usize;
+        compress
+            .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+            .ok()?;
+        if compress.total_in() as usize == data.len() && out.len() < out.capacity() {
+            break;
+        }
+        out.reserve(out.capacity());
+    }
+    if !out.ends_with(&SYNC_TAIL) {
+        return None;
+    }
+    out.truncate(out.len() - SYNC_TAIL.len());
+
+    let mut frame = Frame::message(out, OpCode::Data(opcode), true);
+    frame.header_mut().rsv1 = true;
+    Some(frame)
+}
+
+/// The client's side of the stream with its compressed messages inflated,
+/// so tungstenite, which knows no extensions, reads them as plain ones.
+/// Other frames pass through untouched; a reserved bit set where it
+/// shouldn't be reaches tungstenite, which fails the connection.
+pub struct InflateIo<T> {
+    inner: T,
+    deflate: bool,
+    /// Read from the client, not yet a whole frame
+    raw: Vec<u8>,
+    /// Frames for tungstenite to read, from `ready_pos` on
+    ready: Vec<u8>,
+    ready_pos: usize,
+    /// A compressed message whose final fragment hasn't arrived
+    message: Option<(OpData, Vec<u8>)>,
+    /// Kept across messages, in case the client takes over its window
+    inflater: Decompress,
+}
+
+impl<T> InflateIo<T> {
+    fn new(inner: T, deflate: bool) -> Self {
+        Self {
+            inner,
+            deflate,
+            raw: Vec::new(),
+            ready: Vec::new(),
+            ready_pos: 0,
+            message: None,
+            inflater: Decompress::new(false),
+        }
+    }
+
+    /// Move the whole frames at the front of `raw` to `ready`, inflating
+    /// compressed messages. Returns whether any frame was taken.
+    fn take_frames(&mut self) -> io::Result<bool> {
+        let mut taken = false;
+        loop {
+            let mut cursor = Cursor::new(&self.raw[..]);
+            let Some((header, len)) = FrameHeader::parse(&mut cursor).map_err(invalid_data)? else {
+                return Ok(taken);
+            };
+            let start = cursor.position() as usize;
+            if len > MAX_CLIENT_MESSAGE as u64 {
+                return Err(invalid_data("Client message too large"));
+            }
+            let end = start + len as usize;
+            if self.raw.len() < end {
+                return Ok(taken);
+            }
+
+            let frame: Vec<u8> = self.raw.drain(..end).collect();
+            taken = true;
+            self.take_frame(header, frame, start)?;
+        }
+    }
+
+    fn take_frame(&mut self, header: FrameHeader, frame: Vec<u8>, start: usize) -> io::Result<()> {
+        let masked = header.mask.is_some();
+        let opcode = match header.opcode {
+            OpCode::Data(OpData::Continue) if self.message.is_some() && !header.rsv1 && masked => {
+                None
+            }
+            OpCode::Data(_) if self.message.is_some() => {
+                return Err(invalid_data("Unexpected frame inside a compressed message"));
+            }
+            OpCode::Data(opcode @ (OpData::Text | OpData::Binary))
+                if self.deflate && header.rsv1 && !header.rsv2 && !header.rsv3 && masked =>
+            {
+                Some(opcode)
+            }
+            _ => {
+                self.ready.extend_from_slice(&frame);
+                return Ok(());
+            }
+        };
+
+        let mut payload = frame[start..].to_vec();
+        if let Some(mask) = header.mask {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+        let (opcode, mut data) = match opcode {
+            Some(opcode) => (opcode, payload),
+            None => {
+                let (opcode, mut data) =
+                    self.message.take().unwrap_or((OpData::Binary, Vec::new()));
+                data.extend_from_slice(&payload);
+                (opcode, data)
+            }
+        };
+        if data.len() > MAX_CLIENT_MESSAGE {
+            return Err(invalid_data("Client message too large"));
+        }
+        if !header.is_final {
+            self.message = Some((opcode, data));
+            return Ok(());
+        }
+
+        data.extend_from_slice(&SYNC_TAIL);
+        let inflated = self.inflate(&data)?;
+        // Masked with zeros, which leaves the payload as it is: tungstenite
+        // still sees a masked client frame
+        let header = FrameHeader {
+            is_final: true,
+            opcode: OpCode::Data(opcode),
+            mask: Some([0; 4]),
+            ..FrameHeader::default()
+        };
+        header
+            .format(inflated.len() as u64, &mut self.ready)
+            .map_err(invalid_data)?;
+        self.ready.extend_from_slice(&inflated);
+        Ok(())
+    }
+
+    fn inflate(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let start = self.inflater.total_in();
+        let mut out = Vec::with_capacity(data.len() * 4);
+        loop {
+            let consumed = (self.inflater.total_in() - start) as usize;
+            let produced = out.len();
+            self.inflater
+                .decompress_vec(&data[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(invalid_data)?;
+            if out.len() > MAX_CLIENT_MESSAGE {
+                return Err(invalid_data("Client message too large"));
+            }
+            let consumed_all = (self.inflater.total_in() - start) as usize == data.len();
+            if consumed_all && out.len() < out.capacity() {
+                return Ok(out);
+            }
+            let progressed =
+                out.len() > produced || (self.inflater.total_in() - start) as usize > consumed;
+            if !progressed && out.len() < out.capacity() {
+                return Err(invalid_data("Truncated compressed message"));
+            }
+            out.reserve(out.capacity());
+        }
+    }
+}
+
+fn invalid_data<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for InflateIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.ready_pos < this.ready.len() {
+                let n = buf.remaining().min(this.ready.len() - this.ready_pos);
+                buf.put_slice(&this.ready[this.ready_pos..this.ready_pos + n]);
+                this.ready_pos += n;
+                if this.ready_pos == this.ready.len() {
+                    this.ready.clear();
+                    this.ready_pos = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            if this.take_frames()? {
+                continue;
+            }
+
+            let mut chunk = [0; READ_CHUNK];
+            let mut read = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+            if read.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            this.raw.extend_from_slice(read.filled());
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for InflateIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
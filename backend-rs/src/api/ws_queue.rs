@@ -0,0 +1,198 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use tokio::sync::Notify;
+
+use super::websocket::WsOutgoingMessage;
+
+/// Events a connection may have waiting before overflow policies apply
+pub const QUEUE_CAPACITY: usize = 256;
+
+/// How an event is treated when the client's queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Overflow {
+    /// Superseded by the next one anyway; the oldest queued is dropped
+    DropOldest,
+    /// Never dropped; if no ephemeral event can make room it is left
+    /// pending in the delivery queue, which redelivers or pushes it later
+    OfflineQueue,
+    /// Makes room by dropping an ephemeral event, else is dropped
+    Drop,
+}
+
+fn overflow_policy(msg_type: &str) -> Overflow {
+    match msg_type {
+        "typing_users" | "presence" | "pong" => Overflow::DropOldest,
+        "new_message" => Overflow::OfflineQueue,
+        _ => Overflow::Drop,
+    }
+}
+
+/// What became of a pushed event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enqueued {
+    Queued,
+    /// Queued after dropping the oldest ephemeral event
+    ReplacedOldest,
+    Dropped,
+    /// Left to the delivery queue
+    Overflowed,
+}
+
+/// Lag metrics for one connection's queue
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueueStats {
+    pub queued: usize,
+    /// Most events ever waiting at once
+    pub max_queued: usize,
+    /// Milliseconds the oldest waiting event has been queued
+    pub lag_ms: u64,
+    pub sent: u64,
+    pub dropped: u64,
+    pub overflowed: u64,
+}
+
+struct QueueState {
+    events: VecDeque<(Instant, WsOutgoingMessage)>,
+    max_queued: usize,
+}
+
+/// A connection's bounded outbound queue. Producers never wait on a slow
+/// client: when it is full, each event follows its overflow policy.
+pub struct OutboundQueue {
+    state: Mutex<QueueState>,
+    ready: Notify,
+    room: Notify,
+    capacity: usize,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    overflowed: AtomicU64,
+}
+
+impl OutboundQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                events: VecDeque::with_capacity(capacity),
+                max_queued: 0,
+            }),
+            ready: Notify::new(),
+            room: Notify::new(),
+            capacity,
+            sent: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            overflowed: AtomicU64::new(0),
+        }
+    }
+
+    pub fn push(&self, message: WsOutgoingMessage) -> Enqueued {
+        let policy = overflow_policy(&message.msg_type);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        let outcome = if state.events.len() < self.capacity {
+            Enqueued::Queued
+        } else {
+            let oldest_ephemeral = state.events.iter().position(|(_, queued)| {
+                overflow_policy(&queued.msg_type) == Overflow::DropOldest
+            });
+
+            match (policy, oldest_ephemeral) {
+                (_, Some(index)) => {
+                    state.events.remove(index);
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    Enqueued::ReplacedOldest
+                }
+                (Overflow::OfflineQueue, None) => {
+                    self.overflowed.fetch_add(1, Ordering::Relaxed);
+                    return Enqueued::Overflowed;
+                }
+                (_, None) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    if policy == Overflow::Drop {
+                        tracing::warn!("Dropped {} event for a lagging client", message.msg_type);
+                    }
+                    return Enqueued::Dropped;
+                }
+            }
+        };
+
+        state.events.push_back((Instant::now(), message));
+        state.max_queued = state.max_queued.max(state.events.len());
+        drop(state);
+
+        self.ready.notify_one();
+        outcome
+    }
+
+    /// Wait for room, then queue. For producers that should slow down with
+    /// the client, such as replaying a device's pending messages.
+    pub async fn send(&self, message: WsOutgoingMessage) {
+        loop {
+            let room = self.room.notified();
+            if self.len() < self.capacity {
+                self.push(message);
+                return;
+            }
+            room.await;
+        }
+    }
+
+    /// The next event to write to the socket; waits while the queue is empty
+    pub async fn recv(&self) -> WsOutgoingMessage {
+        loop {
+            let ready = self.ready.notified();
+            let next = self
+                .state
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .events
+                .pop_front();
+
+            if let Some((_, message)) = next {
+                self.sent.fetch_add(1, Ordering::Relaxed);
+                self.room.notify_waiters();
+                return message;
+            }
+            ready.await;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .events
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        QueueStats {
+            queued: state.events.len(),
+            max_queued: state.max_queued,
+            lag_ms: state
+                .events
+                .front()
+                .map(|(queued_at, _)| queued_at.elapsed().as_millis() as u64)
+                .unwrap_or(0),
+            sent: self.sent.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            overflowed: self.overflowed.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AppResult, storage::redis::RedisClient};
+
+use super::websocket::WsOutgoingMessage;
+
+/// Most missed events a reconnect replays; a device further behind resyncs
+pub const RESUME_LIMIT: u64 = 1000;
+
+/// What a resume token stands for; a replay starts after `acked_seq`
+#[derive(Debug, Serialize, Deserialize)]
+struct ResumeSession {
+    user_id: String,
+    device_id: i32,
+    acked_seq: u64,
+}
+
+/// Resume tokens. Each connection gets one; reconnecting with it within the
+/// resume window replays the user events the device missed instead of
+/// requiring a full `/sync`.
+pub struct ResumeSessions {
+    redis: RedisClient,
+}
+
+impl ResumeSessions {
+    pub fn new(redis: RedisClient) -> Self {
+        Self { redis }
+    }
+
+    /// Issue a token for a new connection whose device has everything up to
+    /// `acked_seq`
+    pub async fn open(&self, user_id: &str, device_id: i32, acked_seq: u64) -> AppResult<String> {
+        let token = URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 32]>());
+        let session = ResumeSession {
+            user_id: user_id.to_string(),
+            device_id,
+            acked_seq,
+        };
+
+        self.redis
+            .set_resume_session(&token, &serde_json::to_string(&session)?)
+            .await?;
+        Ok(token)
+    }
+
+    /// Record that the device has received every event up to `seq`
+    pub async fn ack(&self, token: &str, seq: u64) -> AppResult<()> {
+        let Some(mut session) = self.load(token).await? else {
+            return Ok(());
+        };
+        if seq <= session.acked_seq {
+            return Ok(());
+        }
+
+        session.acked_seq = seq;
+        self.redis
+            .set_resume_session(token, &serde_json::to_string(&session)?)
+            .await
+    }
+
+    /// Retire a token presented on reconnect. Returns where its device left
+    /// off, or `None` if the token expired or belongs to another device.
+    pub async fn take(&self, token: &str, user_id: &str, device_id: i32) -> AppResult<Option<u64>> {
+        let Some(session) = self.load(token).await? else {
+            return Ok(None);
+        };
+        if session.user_id != user_id || session.device_id != device_id {
+            return Ok(None);
+        }
+
+        self.redis.delete_resume_session(token).await?;
+        Ok(Some(session.acked_seq))
+    }
+
+    /// Keep the token usable for a full resume window from now; pings call
+    /// this so the window is still open when the connection drops
+    pub async fn touch(&self, token: &str) -> AppResult<()> {
+        self.redis.touch_resume_session(token).await
+    }
+
+    /// The user's events after `after` up to `through`, or `None` when the
+    /// gap is too wide or part of it has expired
+    pub async fn missed_events(
+        &self,
+        user_id: &str,
+        after: u64,
+        through: u64,
+    ) -> AppResult<Option<Vec<WsOutgoingMessage>>> {
+        if through.saturating_sub(after) > RESUME_LIMIT {
+            return Ok(None);
+        }
+
+        let logged = self
+            .redis
+            .get_logged_events(user_id, after, through)
+            .await?;
+        let events = logged
+            .into_iter()
+            .map(|event| event.and_then(|event| serde_json::from_str(&event).ok()))
+            .collect();
+
+        Ok(events)
+    }
+
+    async fn load(&self, token: &str) -> AppResult<Option<ResumeSession>> {
+        let session = self.redis.get_resume_session(token).await?;
+        Ok(session.and_then(|session| serde_json::from_str(&session).ok()))
+    }
+}
@@ -0,0 +1,268 @@
+//! Operator tasks run directly against Postgres and Redis, without the API.
+//!
+//! Reads the same environment as the server; run `cli --help` for commands.
+
+use clap::{Parser, Subcommand};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use uuid::Uuid;
+
+use ansible_talk_backend::{
+    config::Config,
+    error::{AppError, AppResult},
+    services::{
+        auth::AuthService, backups::BackupService, delivery::DeliveryService,
+        email::build_email_provider, messaging::MessagingService,
+        notifications::NotificationService, profiles::ProfileService, push::build_push_provider,
+        view_once::ViewOnceService,
+    },
+    storage::{blob::build_blob_storage, event_bus::build_event_bus, redis::RedisClient},
+};
+
+#[derive(Parser)]
+#[command(name = "cli", about = "Ansible Talk operator tools")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create an admin account, or promote an existing user to admin
+    CreateAdmin {
+        username: String,
+        /// Defaults to the username
+        #[arg(long)]
+        display_name: Option<String>,
+        #[arg(long)]
+        email: Option<String>,
+        #[arg(long)]
+        phone: Option<String>,
+    },
+    /// Suspend an account and sign it out of every device
+    Ban {
+        /// Username or user ID
+        user: String,
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Lift a suspension
+    Unban {
+        /// Username or user ID
+        user: String,
+    },
+    /// Sign new tokens with a fresh JWT key; earlier tokens stay valid until they expire
+    RotateJwtKeys,
+    /// Run the cleanup jobs once
+    Gc,
+    /// List an account's sessions
+    Sessions {
+        /// Username or user ID
+        user: String,
+    },
+    /// Recompute an account's unread counts from read receipts
+    RecomputeUnread {
+        /// Username or user ID
+        user: String,
+    },
+    /// Take a backup of every table now
+    Backup,
+    /// List completed backups, newest first
+    Backups,
+    /// Replace the contents of every table with a backup. Stop the servers first.
+    RestoreBackup {
+        /// Backup ID, as listed by `backups`
+        id: String,
+        /// Confirm that current data will be overwritten
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "ansible_talk_backend=warn".into()),
+        )
+        .init();
+
+    let cli = Cli::parse();
+    let config = Config::load();
+
+    let db = PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&config.database_url())
+        .await?;
+    sqlx::migrate!("./migrations").run(&db).await?;
+
+    let redis = RedisClient::new(&config.redis_url()).await?;
+    let redis = match build_event_bus(&config).await? {
+        Some(bus) => redis.with_event_bus(bus),
+        None => redis,
+    };
+    let auth = AuthService::new(db.clone(), redis.clone(), config.clone());
+
+    match cli.command {
+        Command::CreateAdmin {
+            username,
+            display_name,
+            email,
+            phone,
+        } => {
+            let display_name = display_name.unwrap_or_else(|| username.clone());
+            let (user, created) = auth
+                .create_admin_user(&username, &display_name, email.as_deref(), phone.as_deref())
+                .await?;
+
+            let action = if created { "Created" } else { "Promoted" };
+            println!("{} admin {} ({})", action, user.username, user.id);
+        }
+        Command::Ban { user, reason } => {
+            let user_id = resolve_user(&db, &user).await?;
+            auth.ban_user(user_id, reason.as_deref()).await?;
+            println!("Banned {} ({}) and ended its sessions", user, user_id);
+        }
+        Command::Unban { user } => {
+            let user_id = resolve_user(&db, &user).await?;
+            auth.unban_user(user_id).await?;
+            println!("Unbanned {} ({})", user, user_id);
+        }
+        Command::RotateJwtKeys => {
+            let kid = auth.rotate_signing_key().await?;
+            println!("New tokens are signed with key {}", kid);
+        }
+        Command::Gc => {
+            let sessions = auth.expire_idle_sessions().await?;
+            let otps = auth.purge_expired_otps().await?;
+            let keys = auth.purge_retired_signing_keys().await?;
+            let view_once = ViewOnceService::new(db.clone(), redis.clone(), config.view_once.ttl)
+                .purge_expired()
+                .await?;
+            let statuses = ProfileService::new(db.clone(), redis.clone())
+                .expire_emoji_statuses()
+                .await?;
+            let notifications = NotificationService::new(
+                db.clone(),
+                redis.clone(),
+                build_push_provider(&config.notifications),
+                build_email_provider(&config.notifications),
+            );
+            let deliveries =
+                DeliveryService::new(db.clone(), redis.clone(), config.clone(), notifications)
+                    .purge_expired()
+                    .await?;
+
+            println!("Expired {} idle sessions", sessions);
+            println!("Deleted {} expired OTPs", otps);
+            println!("Deleted {} retired JWT keys", keys);
+            println!("Purged {} expired view-once messages", view_once);
+            println!("Cleared {} expired emoji statuses", statuses);
+            println!("Deleted {} old delivery records", deliveries);
+        }
+        Command::Sessions { user } => {
+            let user_id = resolve_user(&db, &user).await?;
+            let sessions = auth.list_sessions(user_id, 0).await?;
+
+            if sessions.is_empty() {
+                println!("No sessions for {}", user);
+            }
+            for session in sessions {
+                println!(
+                    "device {}: {} ({}), ip {}, country {}",
+                    session.device_id,
+                    session.device_name.as_deref().unwrap_or("-"),
+                    session.platform.as_deref().unwrap_or("-"),
+                    session.ip_address.as_deref().unwrap_or("-"),
+                    session.country.as_deref().unwrap_or("-"),
+                );
+                println!(
+                    "  created {}, last used {}, idle expiry {}",
+                    session.created_at.to_rfc3339(),
+                    session.last_used_at.to_rfc3339(),
+                    session
+                        .idle_expires_at
+                        .map(|at| at.to_rfc3339())
+                        .unwrap_or_else(|| "-".to_string()),
+                );
+            }
+        }
+        Command::RecomputeUnread { user } => {
+            let user_id = resolve_user(&db, &user).await?;
+            let counts = MessagingService::new(db.clone(), redis.clone(), config.clone())
+                .unread_counts(user_id)
+                .await?;
+
+            for (conversation_id, unread) in &counts {
+                println!("{}  {}", conversation_id, unread);
+            }
+            let total: i64 = counts.iter().map(|(_, unread)| unread).sum();
+            println!("{} unread across {} conversations", total, counts.len());
+        }
+        Command::Backup => {
+            let manifest = backup_service(&db, &config).await?.create().await?;
+            let rows: i64 = manifest.tables.iter().map(|t| t.rows).sum();
+            println!(
+                "Backup {}: {} rows in {} tables",
+                manifest.id,
+                rows,
+                manifest.tables.len()
+            );
+        }
+        Command::Backups => {
+            let backups = backup_service(&db, &config).await?.list().await?;
+
+            if backups.is_empty() {
+                println!("No backups");
+            }
+            for manifest in backups {
+                let rows: i64 = manifest.tables.iter().map(|t| t.rows).sum();
+                let bytes: u64 = manifest.tables.iter().map(|t| t.bytes).sum();
+                println!(
+                    "{}  schema {}  {} rows  {} KiB",
+                    manifest.id,
+                    manifest.schema_version,
+                    rows,
+                    bytes / 1024
+                );
+            }
+        }
+        Command::RestoreBackup { id, yes } => {
+            if !yes {
+                anyhow::bail!("Restoring replaces all current data; pass --yes to confirm");
+            }
+            let manifest = backup_service(&db, &config).await?.restore(&id).await?;
+            println!(
+                "Restored {} tables from backup {}",
+                manifest.tables.len(),
+                manifest.id
+            );
+            println!("Flush Redis and restart the servers so sessions and caches match");
+        }
+    }
+
+    Ok(())
+}
+
+async fn backup_service(db: &PgPool, config: &Config) -> AppResult<BackupService> {
+    let storage = build_blob_storage(config).await?;
+    Ok(BackupService::new(
+        db.clone(),
+        storage,
+        config.storage.backup_bucket.clone(),
+        config.backup.retention,
+    ))
+}
+
+/// Accept either a user ID or a username
+async fn resolve_user(db: &PgPool, user: &str) -> AppResult<Uuid> {
+    if let Ok(id) = Uuid::parse_str(user) {
+        return Ok(id);
+    }
+
+    sqlx::query_scalar("SELECT id FROM users WHERE LOWER(username) = LOWER($1)")
+        .bind(user.trim_start_matches('@'))
+        .fetch_optional(db)
+        .await?
+        .ok_or(AppError::UserNotFound)
+}
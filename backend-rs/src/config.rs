@@ -1,14 +1,50 @@
 use std::env;
+use std::net::IpAddr;
+use std::path::PathBuf;
 use std::time::Duration;
 
+use ipnet::IpNet;
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub server: ServerConfig,
+    pub logging: LoggingConfig,
+    pub timeouts: TimeoutConfig,
+    pub load_shed: LoadShedConfig,
     pub database: DatabaseConfig,
     pub redis: RedisConfig,
+    pub event_bus: EventBusConfig,
     pub minio: MinioConfig,
+    pub storage: StorageConfig,
     pub jwt: JwtConfig,
+    pub session: SessionConfig,
+    pub api_tokens: ApiTokenConfig,
+    pub signup: SignupConfig,
+    pub invites: InviteConfig,
     pub otp: OtpConfig,
+    pub admin: AdminConfig,
+    pub notifications: NotificationConfig,
+    pub rate_limit: RateLimitConfig,
+    pub captcha: CaptchaConfig,
+    pub spam: SpamConfig,
+    pub message_limits: MessageLimitsConfig,
+    pub moderation: ModerationConfig,
+    pub search: SearchConfig,
+    pub translation: TranslationConfig,
+    pub gifs: GifConfig,
+    pub stickers: StickerConfig,
+    pub view_once: ViewOnceConfig,
+    pub media: MediaConfig,
+    pub transcode: TranscodeConfig,
+    pub delivery: DeliveryConfig,
+    pub digest: DigestConfig,
+    pub backup: BackupConfig,
+    pub analytics: AnalyticsConfig,
+    pub privacy: PrivacyConfig,
+    pub sealed_sender: SealedSenderConfig,
+    pub websocket: WebSocketConfig,
+    pub grpc: GrpcConfig,
+    pub matrix: MatrixConfig,
 }
 
 #[derive(Debug, Clone)]
@@ -16,6 +52,50 @@ pub struct ServerConfig {
     pub host: String,
     pub port: u16,
     pub environment: String,
+    /// Header set by the edge proxy/CDN with the client's ISO country code
+    pub geo_country_header: Option<String>,
+    /// Proxies and load balancers whose `Forwarded` / `X-Forwarded-For`
+    /// entries are believed
+    pub trusted_proxies: Vec<IpNet>,
+    /// How far apart server clocks may be when checking token and OTP times
+    pub clock_skew: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoggingConfig {
+    /// One JSON object per line instead of human-readable text
+    pub json: bool,
+    /// Only one in this many high-volume debug events (WebSocket pings,
+    /// typing) is logged; `1` logs them all
+    pub sample_rate: u64,
+    /// Statements slower than this are logged and counted; zero disables
+    pub slow_query: Duration,
+    /// Requests slower than this are logged and counted; zero disables
+    pub slow_request: Duration,
+}
+
+/// Budgets after which a request is cancelled with a 504; zero disables
+#[derive(Debug, Clone)]
+pub struct TimeoutConfig {
+    pub request: Duration,
+    /// Multipart uploads
+    pub upload: Duration,
+    /// Routes under `/api/v1/admin`
+    pub admin: Duration,
+    /// Postgres `statement_timeout` for the server's connections
+    pub statement: Duration,
+}
+
+/// Adaptive limit on concurrent API requests. The limit shrinks while
+/// requests run slower than `target_latency` and grows back when they don't.
+#[derive(Debug, Clone)]
+pub struct LoadShedConfig {
+    /// Upper bound of the limit; zero disables shedding
+    pub max_concurrency: usize,
+    pub min_concurrency: usize,
+    pub target_latency: Duration,
+    /// Seconds clients are told to wait after a shed request
+    pub retry_after: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -27,6 +107,10 @@ pub struct DatabaseConfig {
     pub database: String,
     pub ssl_mode: String,
     pub max_connections: u32,
+    /// Apply pending migrations on startup
+    pub auto_migrate: bool,
+    /// Refuse to start when applied migrations differ from the binary's
+    pub refuse_migration_drift: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -37,6 +121,19 @@ pub struct RedisConfig {
     pub db: i64,
 }
 
+/// Carries WebSocket, presence and broadcast fanout between instances
+#[derive(Debug, Clone)]
+pub struct EventBusConfig {
+    /// redis, nats or kafka; the latter two need the matching cargo feature
+    pub backend: String,
+    pub nats_url: String,
+    /// Prepended to every NATS subject, e.g. `ansible_talk.messages.{user}`
+    pub nats_subject_prefix: String,
+    pub kafka_brokers: Vec<String>,
+    /// Must exist; more partitions spread the publish load
+    pub kafka_topic: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct MinioConfig {
     pub endpoint: String,
@@ -44,10 +141,22 @@ pub struct MinioConfig {
     pub secret_key: String,
     pub use_ssl: bool,
     pub region: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+    /// minio, s3, gcs or local
+    pub provider: String,
     pub stickers_bucket: String,
     pub avatars_bucket: String,
     pub attachments_bucket: String,
+    /// Private bucket for database backups, kept apart from the buckets that
+    /// serve public files; backups refuse to run without it
+    pub backup_bucket: Option<String>,
+    /// Base URL objects are served from, e.g. a CDN in front of the buckets
     pub public_url: Option<String>,
+    /// Root directory of the local backend
+    pub local_dir: PathBuf,
 }
 
 #[derive(Debug, Clone)]
@@ -58,6 +167,40 @@ pub struct JwtConfig {
     pub issuer: String,
 }
 
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// Minimum time between `last_used_at` writes for one session
+    pub activity_throttle: Duration,
+    /// Sessions unused for this long are deleted; zero disables idle expiry
+    pub idle_timeout: Duration,
+    /// How long a web session's link QR code can be scanned and claimed
+    pub link_ttl: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiTokenConfig {
+    /// Requests per minute for tokens created without a limit
+    pub default_rate_limit: u32,
+    /// Highest per-minute limit users can give their own tokens
+    pub max_rate_limit: u32,
+    pub max_per_user: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SignupConfig {
+    /// `open`, `invite` (registration needs an invite code) or `waitlist`
+    /// (an invite code or approval from the waitlist)
+    pub mode: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct InviteConfig {
+    /// Invites a user can create unless an admin set their own quota
+    pub default_quota: i32,
+    /// How long invites stay valid unless created with another expiry
+    pub ttl: Duration,
+}
+
 #[derive(Debug, Clone)]
 pub struct OtpConfig {
     pub length: usize,
@@ -65,6 +208,284 @@ pub struct OtpConfig {
     pub max_attempts: u32,
 }
 
+#[derive(Debug, Clone)]
+pub struct AdminConfig {
+    pub stats_cache_ttl: Duration,
+    /// Audit log entries older than this are deleted; zero keeps them
+    pub audit_retention: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct NotificationConfig {
+    pub push_provider: String,
+    pub fcm_server_key: Option<String>,
+    pub email_provider: String,
+    pub sendgrid_api_key: Option<String>,
+    pub email_from: String,
+    /// VAPID key pair for Web Push, base64url: the uncompressed P-256 public
+    /// key and the raw private scalar. Web Push is off unless both are set.
+    pub vapid_public_key: Option<String>,
+    pub vapid_private_key: Option<String>,
+    /// Contact URL push services can reach the operator at
+    pub vapid_subject: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub window: Duration,
+    /// Requests per window after which a CAPTCHA is required
+    pub otp_challenge_after: u32,
+    pub otp_max: u32,
+    pub register_challenge_after: u32,
+    pub register_max: u32,
+    /// Completed registrations per hour from one IP or device fingerprint
+    /// before a CAPTCHA is required / before registration is refused
+    pub register_velocity_challenge_after: u32,
+    pub register_velocity_max: u32,
+    /// Username availability checks per window from one IP or registration
+    pub username_challenge_after: u32,
+    pub username_max: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptchaConfig {
+    /// none, hcaptcha, or turnstile
+    pub provider: String,
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpamConfig {
+    /// Messages a single sender may send per window before being throttled
+    pub message_window: Duration,
+    pub message_max: u32,
+    /// Identical content posted to more than this many conversations within
+    /// the duplicate window is treated as spam
+    pub duplicate_window: Duration,
+    pub duplicate_max_conversations: u32,
+    /// Shorter content ("ok", "thanks") is never considered a duplicate
+    pub duplicate_min_bytes: usize,
+    /// Throttled windows after which the sender is shadow-limited
+    pub strikes_before_shadow: u32,
+    pub shadow_duration: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct MessageLimitsConfig {
+    /// Largest text or sticker message content, as sent (ciphertext included)
+    pub max_text_bytes: usize,
+    /// Largest image, video, audio and other file, inline or uploaded
+    pub max_image_bytes: usize,
+    pub max_video_bytes: usize,
+    pub max_audio_bytes: usize,
+    pub max_file_bytes: usize,
+    /// Largest attachment upload of any type; uploads are held in memory
+    /// until stored
+    pub max_upload_bytes: usize,
+    /// Attachment content types accepted; `type/*` accepts a whole family
+    /// and `*` anything
+    pub allowed_attachment_types: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModerationConfig {
+    /// none or blocklist
+    pub hash_provider: String,
+    /// Action on a hash match: reject, quarantine, or flag
+    pub hash_match_action: String,
+    pub max_upload_bytes: usize,
+    pub allowed_media_types: Vec<String>,
+    /// Action on a size/type policy violation: reject, quarantine, or flag
+    pub policy_action: String,
+    /// Also run message content through the pipeline (only useful for
+    /// deployments whose clients send unencrypted content)
+    pub scan_messages: bool,
+    /// Key the server signs franking tags with; defaults to the JWT secret
+    pub franking_secret: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchConfig {
+    /// none, postgres, or meilisearch. Only plaintext text messages are
+    /// indexed, so this is off by default for end-to-end encrypted clients.
+    pub provider: String,
+    pub meilisearch_url: Option<String>,
+    pub meilisearch_api_key: Option<String>,
+    pub meilisearch_index: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct TranslationConfig {
+    /// none, deepl, google, or nllb (a self-hosted NLLB server)
+    pub provider: String,
+    pub api_key: Option<String>,
+    /// API base URL; required for nllb, optional for deepl (defaults to the free API)
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GifConfig {
+    /// none, tenor, or giphy
+    pub provider: String,
+    pub api_key: Option<String>,
+    /// Maximum content rating: g, pg, pg-13, or r
+    pub rating: String,
+    pub cache_ttl: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct StickerConfig {
+    /// Base URL of a Lottie renderer that returns a PNG of one frame; unset
+    /// leaves Lottie stickers without previews
+    pub renderer_url: Option<String>,
+    pub renderer_api_key: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GrpcConfig {
+    /// Listen address for the internal gRPC API; unset disables it
+    pub addr: Option<String>,
+    /// Bearer token internal consumers send with every call
+    pub auth_token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatrixConfig {
+    /// Client-server API base URL of the homeserver; unset disables the bridge
+    pub homeserver_url: Option<String>,
+    /// Server name in Matrix IDs, e.g. example.org
+    pub server_name: String,
+    /// Token the bridge sends to the homeserver
+    pub as_token: String,
+    /// Token the homeserver sends to the bridge
+    pub hs_token: String,
+    /// Localpart prefix of local users' virtual Matrix IDs
+    pub user_prefix: String,
+    /// Localpart of the bridge's own Matrix user
+    pub bot_localpart: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ViewOnceConfig {
+    /// Unopened view-once media is deleted after this long
+    pub ttl: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct MediaConfig {
+    /// Key signing attachment links; defaults to the JWT secret
+    pub url_secret: Option<String>,
+    /// How long a signed attachment link stays valid
+    pub url_ttl: Duration,
+    /// Public base URL of this server; defaults to `http://localhost:{port}`
+    pub base_url: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscodeConfig {
+    /// none or ffmpeg
+    pub provider: String,
+    pub ffmpeg_path: String,
+    /// Longest side of renditions and posters, in pixels
+    pub max_dimension: u32,
+    /// Renditions larger than this are not kept
+    pub max_bytes: usize,
+    /// Videos at least this large are transcoded; GIFs always are
+    pub video_min_bytes: usize,
+    /// Longest a single rendition may take
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeliveryConfig {
+    /// First WebSocket redelivery delay; doubles on each retry
+    pub retry_base: Duration,
+    /// Longest delay between redeliveries
+    pub retry_max: Duration,
+    /// Unacknowledged messages are pushed to the device after this long
+    pub push_after: Duration,
+    /// A conversation's pushes to a device replace each other, counting up,
+    /// until none is sent for this long
+    pub group_window: Duration,
+    /// Pushes to a device within one window after which a single summary
+    /// replaces them; 0 disables summaries
+    pub summary_after: i64,
+    /// Share of messages whose delivery timings are recorded, 0 to 1
+    pub latency_sample_rate: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DigestConfig {
+    /// Days offline before an opted-in user is sent an unread digest; also the
+    /// minimum gap between two digests
+    pub inactive_days: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackupConfig {
+    /// How often to take a scheduled backup; zero disables the schedule
+    pub interval: Duration,
+    /// Completed backups kept; older ones are deleted after each backup
+    pub retention: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnalyticsConfig {
+    /// none, table (kept in `analytics_events` for export) or http (relayed
+    /// to `http_url` as NDJSON, then deleted)
+    pub sink: String,
+    pub http_url: Option<String>,
+    /// Bearer token for the HTTP sink
+    pub http_token: Option<String>,
+    /// Key user and conversation IDs are pseudonymized with
+    pub pseudonym_key: String,
+    /// Events older than this are deleted whether or not they were exported
+    pub retention: Duration,
+}
+
+/// Metadata minimization; `PRIVACY_MODE` turns every option on
+#[derive(Debug, Clone)]
+pub struct PrivacyConfig {
+    /// Pad stored Signal ciphertext to a length bucket
+    pub pad_messages: bool,
+    /// Leave client IP and user agent out of request logs for message routes
+    pub minimize_logs: bool,
+    /// Hide when recipients received and acknowledged a message from its
+    /// sender; only the delivery state is shown
+    pub coarse_receipts: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SealedSenderConfig {
+    /// Key sender certificates and delivery tokens are derived from;
+    /// defaults to the JWT secret
+    pub secret: Option<String>,
+    /// How long a sender certificate and its delivery token stay valid
+    pub certificate_ttl: Duration,
+    /// Certificates one user may be issued per day
+    pub certificates_per_day: u32,
+}
+
+/// Limits are per instance and cover GraphQL subscriptions as well as `/ws`
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    /// Concurrent connections one device may hold
+    pub max_connections_per_device: usize,
+    /// Concurrent connections one user may hold across devices
+    pub max_connections_per_user: usize,
+    /// Refuse connections over a limit instead of closing the oldest
+    pub reject_over_limit: bool,
+    /// Accept permessage-deflate from clients that offer it
+    pub compression: bool,
+    /// Events smaller than this many bytes are sent uncompressed
+    pub compression_threshold: usize,
+    /// A typer is dropped from `typing_users` unless they send typing again
+    /// within this long
+    pub typing_ttl: Duration,
+    /// Each conversation's `typing_users` is sent at most this often
+    pub typing_update_interval: Duration,
+}
+
 impl Config {
     pub fn load() -> Self {
         dotenvy::dotenv().ok();
@@ -77,6 +498,91 @@ impl Config {
                     .and_then(|p| p.parse().ok())
                     .unwrap_or(8080),
                 environment: env::var("ENVIRONMENT").unwrap_or_else(|_| "development".to_string()),
+                geo_country_header: env::var("GEO_COUNTRY_HEADER").ok(),
+                trusted_proxies: env::var("TRUSTED_PROXIES")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .filter_map(|s| {
+                        s.parse::<IpNet>()
+                            .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+                            .ok()
+                    })
+                    .collect(),
+                clock_skew: Duration::from_secs(
+                    env::var("CLOCK_SKEW_TOLERANCE")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(60),
+                ),
+            },
+            logging: LoggingConfig {
+                json: env::var("LOG_FORMAT").is_ok_and(|f| f.eq_ignore_ascii_case("json")),
+                sample_rate: env::var("LOG_SAMPLE_RATE")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(100_u64)
+                    .max(1),
+                slow_query: Duration::from_millis(
+                    env::var("SLOW_QUERY_MS")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(250),
+                ),
+                slow_request: Duration::from_millis(
+                    env::var("SLOW_REQUEST_MS")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(1000),
+                ),
+            },
+            timeouts: TimeoutConfig {
+                request: Duration::from_secs(
+                    env::var("REQUEST_TIMEOUT")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(30),
+                ),
+                upload: Duration::from_secs(
+                    env::var("UPLOAD_TIMEOUT")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(120),
+                ),
+                admin: Duration::from_secs(
+                    env::var("ADMIN_REQUEST_TIMEOUT")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(300),
+                ),
+                statement: Duration::from_secs(
+                    env::var("DB_STATEMENT_TIMEOUT")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(25),
+                ),
+            },
+            load_shed: LoadShedConfig {
+                max_concurrency: env::var("LOAD_SHED_MAX_CONCURRENCY")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(512),
+                min_concurrency: env::var("LOAD_SHED_MIN_CONCURRENCY")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(32_usize)
+                    .max(1),
+                target_latency: Duration::from_millis(
+                    env::var("LOAD_SHED_TARGET_LATENCY_MS")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(500),
+                ),
+                retry_after: env::var("LOAD_SHED_RETRY_AFTER")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(5),
             },
             database: DatabaseConfig {
                 host: env::var("DB_HOST").unwrap_or_else(|_| "localhost".to_string()),
@@ -92,6 +598,14 @@ impl Config {
                     .ok()
                     .and_then(|p| p.parse().ok())
                     .unwrap_or(25),
+                auto_migrate: env::var("DB_AUTO_MIGRATE")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(true),
+                refuse_migration_drift: env::var("DB_REFUSE_MIGRATION_DRIFT")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(true),
             },
             redis: RedisConfig {
                 host: env::var("REDIS_HOST").unwrap_or_else(|_| "localhost".to_string()),
@@ -105,6 +619,24 @@ impl Config {
                     .and_then(|p| p.parse().ok())
                     .unwrap_or(0),
             },
+            event_bus: EventBusConfig {
+                backend: env::var("EVENT_BUS")
+                    .unwrap_or_else(|_| "redis".to_string())
+                    .to_lowercase(),
+                nats_url: env::var("NATS_URL")
+                    .unwrap_or_else(|_| "nats://localhost:4222".to_string()),
+                nats_subject_prefix: env::var("NATS_SUBJECT_PREFIX")
+                    .unwrap_or_else(|_| "ansible_talk".to_string()),
+                kafka_brokers: env::var("KAFKA_BROKERS")
+                    .unwrap_or_else(|_| "localhost:9092".to_string())
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect(),
+                kafka_topic: env::var("KAFKA_TOPIC")
+                    .unwrap_or_else(|_| "ansible_talk.events".to_string()),
+            },
             minio: MinioConfig {
                 endpoint: env::var("MINIO_ENDPOINT")
                     .unwrap_or_else(|_| "http://localhost:9000".to_string()),
@@ -117,10 +649,32 @@ impl Config {
                     .and_then(|s| s.parse().ok())
                     .unwrap_or(false),
                 region: env::var("MINIO_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
-                stickers_bucket: "stickers".to_string(),
-                avatars_bucket: "avatars".to_string(),
-                attachments_bucket: "attachments".to_string(),
-                public_url: env::var("MINIO_PUBLIC_URL").ok(),
+            },
+            storage: {
+                // S3 and GCS bucket names are global, so deployments there need a prefix
+                let prefix = env::var("STORAGE_BUCKET_PREFIX").unwrap_or_default();
+                let provider = env::var("STORAGE_PROVIDER")
+                    .unwrap_or_else(|_| "minio".to_string())
+                    .to_lowercase();
+                // The local backend serves its own files, so the MinIO URL does not apply
+                let public_url = env::var("STORAGE_PUBLIC_URL").ok().or_else(|| {
+                    if provider == "local" {
+                        None
+                    } else {
+                        env::var("MINIO_PUBLIC_URL").ok()
+                    }
+                });
+                StorageConfig {
+                    provider,
+                    stickers_bucket: format!("{}stickers", prefix),
+                    avatars_bucket: format!("{}avatars", prefix),
+                    attachments_bucket: format!("{}attachments", prefix),
+                    backup_bucket: env::var("BACKUP_BUCKET").ok().filter(|b| !b.is_empty()),
+                    public_url,
+                    local_dir: env::var("STORAGE_LOCAL_DIR")
+                        .unwrap_or_else(|_| "./data/storage".to_string())
+                        .into(),
+                }
             },
             jwt: JwtConfig {
                 secret: env::var("JWT_SECRET")
@@ -139,6 +693,63 @@ impl Config {
                 ),
                 issuer: env::var("JWT_ISSUER").unwrap_or_else(|_| "ansible-talk".to_string()),
             },
+            session: SessionConfig {
+                activity_throttle: Duration::from_secs(
+                    env::var("SESSION_ACTIVITY_THROTTLE")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(60),
+                ),
+                idle_timeout: Duration::from_secs(
+                    env::var("SESSION_IDLE_TIMEOUT")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(30 * 24 * 60 * 60), // 30 days
+                ),
+                link_ttl: Duration::from_secs(
+                    env::var("SESSION_LINK_TTL")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(300),
+                ),
+            },
+            api_tokens: ApiTokenConfig {
+                default_rate_limit: env::var("API_TOKEN_RATE_LIMIT")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(60),
+                max_rate_limit: env::var("API_TOKEN_MAX_RATE_LIMIT")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(600),
+                max_per_user: env::var("API_TOKEN_MAX_PER_USER")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(20),
+            },
+            signup: SignupConfig {
+                // `INVITE_ONLY=true` predates `SIGNUP_MODE`
+                mode: env::var("SIGNUP_MODE")
+                    .map(|m| m.trim().to_lowercase())
+                    .unwrap_or_else(|_| {
+                        let invite_only =
+                            env::var("INVITE_ONLY").is_ok_and(|v| v == "true" || v == "1");
+                        let mode = if invite_only { "invite" } else { "open" };
+                        mode.to_string()
+                    }),
+            },
+            invites: InviteConfig {
+                default_quota: env::var("INVITE_QUOTA")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(5),
+                ttl: Duration::from_secs(
+                    env::var("INVITE_TTL")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(2592000),
+                ),
+            },
             otp: OtpConfig {
                 length: env::var("OTP_LENGTH")
                     .ok()
@@ -155,6 +766,401 @@ impl Config {
                     .and_then(|p| p.parse().ok())
                     .unwrap_or(3),
             },
+            admin: AdminConfig {
+                stats_cache_ttl: Duration::from_secs(
+                    env::var("ADMIN_STATS_CACHE_TTL")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(60), // 1 minute
+                ),
+                audit_retention: Duration::from_secs(
+                    env::var("ADMIN_AUDIT_RETENTION_DAYS")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(365)
+                        * 24
+                        * 3600,
+                ),
+            },
+            notifications: NotificationConfig {
+                push_provider: env::var("PUSH_PROVIDER").unwrap_or_else(|_| "log".to_string()),
+                fcm_server_key: env::var("FCM_SERVER_KEY").ok().filter(|k| !k.is_empty()),
+                email_provider: env::var("EMAIL_PROVIDER").unwrap_or_else(|_| "log".to_string()),
+                sendgrid_api_key: env::var("SENDGRID_API_KEY").ok().filter(|k| !k.is_empty()),
+                email_from: env::var("EMAIL_FROM")
+                    .unwrap_or_else(|_| "no-reply@ansible-talk.local".to_string()),
+                vapid_public_key: env::var("VAPID_PUBLIC_KEY").ok().filter(|k| !k.is_empty()),
+                vapid_private_key: env::var("VAPID_PRIVATE_KEY").ok().filter(|k| !k.is_empty()),
+                vapid_subject: env::var("VAPID_SUBJECT")
+                    .unwrap_or_else(|_| "mailto:admin@ansible-talk.local".to_string()),
+            },
+            rate_limit: RateLimitConfig {
+                window: Duration::from_secs(
+                    env::var("RATE_LIMIT_WINDOW")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(60 * 60), // 1 hour
+                ),
+                otp_challenge_after: env::var("RATE_LIMIT_OTP_CHALLENGE_AFTER")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(3),
+                otp_max: env::var("RATE_LIMIT_OTP_MAX")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(10),
+                register_challenge_after: env::var("RATE_LIMIT_REGISTER_CHALLENGE_AFTER")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(2),
+                register_max: env::var("RATE_LIMIT_REGISTER_MAX")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(5),
+                register_velocity_challenge_after: env::var(
+                    "RATE_LIMIT_REGISTER_VELOCITY_CHALLENGE_AFTER",
+                )
+                .ok()
+                .and_then(|p| p.parse().ok())
+                .unwrap_or(3),
+                register_velocity_max: env::var("RATE_LIMIT_REGISTER_VELOCITY_MAX")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(10),
+                username_challenge_after: env::var("RATE_LIMIT_USERNAME_CHALLENGE_AFTER")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(60),
+                username_max: env::var("RATE_LIMIT_USERNAME_MAX")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(200),
+            },
+            captcha: CaptchaConfig {
+                provider: env::var("CAPTCHA_PROVIDER").unwrap_or_else(|_| "none".to_string()),
+                secret: env::var("CAPTCHA_SECRET").ok().filter(|s| !s.is_empty()),
+            },
+            spam: SpamConfig {
+                message_window: Duration::from_secs(
+                    env::var("SPAM_MESSAGE_WINDOW")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(60),
+                ),
+                message_max: env::var("SPAM_MESSAGE_MAX")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(30),
+                duplicate_window: Duration::from_secs(
+                    env::var("SPAM_DUPLICATE_WINDOW")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(10 * 60), // 10 minutes
+                ),
+                duplicate_max_conversations: env::var("SPAM_DUPLICATE_MAX_CONVERSATIONS")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(5),
+                duplicate_min_bytes: env::var("SPAM_DUPLICATE_MIN_BYTES")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(16),
+                strikes_before_shadow: env::var("SPAM_STRIKES_BEFORE_SHADOW")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(3),
+                shadow_duration: Duration::from_secs(
+                    env::var("SPAM_SHADOW_DURATION")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(24 * 60 * 60), // 24 hours
+                ),
+            },
+            message_limits: MessageLimitsConfig {
+                max_text_bytes: env::var("MESSAGE_MAX_TEXT_BYTES")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(64 * 1024), // 64 KiB
+                max_image_bytes: env::var("MESSAGE_MAX_IMAGE_BYTES")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(10 * 1024 * 1024), // 10 MiB
+                max_video_bytes: env::var("MESSAGE_MAX_VIDEO_BYTES")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(25 * 1024 * 1024), // 25 MiB
+                max_audio_bytes: env::var("MESSAGE_MAX_AUDIO_BYTES")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(25 * 1024 * 1024), // 25 MiB
+                max_file_bytes: env::var("MESSAGE_MAX_FILE_BYTES")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(25 * 1024 * 1024), // 25 MiB
+                max_upload_bytes: env::var("MESSAGE_MAX_UPLOAD_BYTES")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(25 * 1024 * 1024), // 25 MiB
+                allowed_attachment_types: env::var("MESSAGE_ALLOWED_ATTACHMENT_TYPES")
+                    .unwrap_or_else(|_| {
+                        "image/*,video/*,audio/*,text/plain,application/pdf,application/zip,\
+                         application/octet-stream"
+                            .to_string()
+                    })
+                    .split(',')
+                    .map(|t| t.trim().to_lowercase())
+                    .filter(|t| !t.is_empty())
+                    .collect(),
+            },
+            moderation: ModerationConfig {
+                hash_provider: env::var("MODERATION_HASH_PROVIDER")
+                    .unwrap_or_else(|_| "none".to_string()),
+                hash_match_action: env::var("MODERATION_HASH_MATCH_ACTION")
+                    .unwrap_or_else(|_| "quarantine".to_string()),
+                max_upload_bytes: env::var("MODERATION_MAX_UPLOAD_BYTES")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(10 * 1024 * 1024), // 10 MiB
+                allowed_media_types: env::var("MODERATION_ALLOWED_MEDIA_TYPES")
+                    .unwrap_or_else(|_| "image/png,image/jpeg,image/gif,image/webp".to_string())
+                    .split(',')
+                    .map(|t| t.trim().to_lowercase())
+                    .filter(|t| !t.is_empty())
+                    .collect(),
+                policy_action: env::var("MODERATION_POLICY_ACTION")
+                    .unwrap_or_else(|_| "reject".to_string()),
+                scan_messages: env::var("MODERATION_SCAN_MESSAGES")
+                    .map(|v| v == "true" || v == "1")
+                    .unwrap_or(false),
+                franking_secret: env::var("FRANKING_SECRET").ok().filter(|s| !s.is_empty()),
+            },
+            search: SearchConfig {
+                provider: env::var("SEARCH_PROVIDER").unwrap_or_else(|_| "none".to_string()),
+                meilisearch_url: env::var("MEILISEARCH_URL").ok().filter(|u| !u.is_empty()),
+                meilisearch_api_key: env::var("MEILISEARCH_API_KEY")
+                    .ok()
+                    .filter(|k| !k.is_empty()),
+                meilisearch_index: env::var("MEILISEARCH_INDEX")
+                    .unwrap_or_else(|_| "messages".to_string()),
+            },
+            translation: TranslationConfig {
+                provider: env::var("TRANSLATION_PROVIDER").unwrap_or_else(|_| "none".to_string()),
+                api_key: env::var("TRANSLATION_API_KEY").ok().filter(|k| !k.is_empty()),
+                url: env::var("TRANSLATION_URL").ok().filter(|u| !u.is_empty()),
+            },
+            gifs: GifConfig {
+                provider: env::var("GIF_PROVIDER").unwrap_or_else(|_| "none".to_string()),
+                api_key: env::var("GIF_API_KEY").ok().filter(|k| !k.is_empty()),
+                rating: env::var("GIF_RATING").unwrap_or_else(|_| "pg-13".to_string()),
+                cache_ttl: Duration::from_secs(
+                    env::var("GIF_CACHE_TTL")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(3600), // 1 hour
+                ),
+            },
+            stickers: StickerConfig {
+                renderer_url: env::var("STICKER_RENDERER_URL").ok().filter(|u| !u.is_empty()),
+                renderer_api_key: env::var("STICKER_RENDERER_API_KEY")
+                    .ok()
+                    .filter(|k| !k.is_empty()),
+            },
+            view_once: ViewOnceConfig {
+                ttl: Duration::from_secs(
+                    env::var("VIEW_ONCE_TTL")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(14 * 24 * 60 * 60), // 14 days
+                ),
+            },
+            media: MediaConfig {
+                url_secret: env::var("MEDIA_URL_SECRET").ok().filter(|s| !s.is_empty()),
+                url_ttl: Duration::from_secs(
+                    env::var("MEDIA_URL_TTL")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(60 * 60), // 1 hour
+                ),
+                base_url: env::var("MEDIA_BASE_URL").ok().filter(|u| !u.is_empty()),
+            },
+            transcode: TranscodeConfig {
+                provider: env::var("TRANSCODE_PROVIDER").unwrap_or_else(|_| "none".to_string()),
+                ffmpeg_path: env::var("TRANSCODE_FFMPEG_PATH")
+                    .unwrap_or_else(|_| "ffmpeg".to_string()),
+                max_dimension: env::var("TRANSCODE_MAX_DIMENSION")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(720),
+                max_bytes: env::var("TRANSCODE_MAX_BYTES")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(8 * 1024 * 1024), // 8 MB
+                video_min_bytes: env::var("TRANSCODE_VIDEO_MIN_BYTES")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(10 * 1024 * 1024), // 10 MB
+                timeout: Duration::from_secs(
+                    env::var("TRANSCODE_TIMEOUT")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(300), // 5 minutes
+                ),
+            },
+            delivery: DeliveryConfig {
+                retry_base: Duration::from_secs(
+                    env::var("DELIVERY_RETRY_BASE")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(2),
+                ),
+                retry_max: Duration::from_secs(
+                    env::var("DELIVERY_RETRY_MAX")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(60),
+                ),
+                push_after: Duration::from_secs(
+                    env::var("DELIVERY_PUSH_AFTER")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(30),
+                ),
+                group_window: Duration::from_secs(
+                    env::var("DELIVERY_GROUP_WINDOW")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(600),
+                ),
+                summary_after: env::var("DELIVERY_SUMMARY_AFTER")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(5),
+                latency_sample_rate: env::var("DELIVERY_LATENCY_SAMPLE_RATE")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(0.01_f64)
+                    .clamp(0.0, 1.0),
+            },
+            digest: DigestConfig {
+                inactive_days: env::var("EMAIL_DIGEST_INACTIVE_DAYS")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(3_i32)
+                    .max(1),
+            },
+            analytics: AnalyticsConfig {
+                sink: env::var("ANALYTICS_SINK")
+                    .unwrap_or_else(|_| "none".to_string())
+                    .to_lowercase(),
+                http_url: env::var("ANALYTICS_HTTP_URL").ok().filter(|u| !u.is_empty()),
+                http_token: env::var("ANALYTICS_HTTP_TOKEN").ok().filter(|t| !t.is_empty()),
+                // Falls back to the JWT secret so pseudonyms are stable without extra setup
+                pseudonym_key: env::var("ANALYTICS_PSEUDONYM_KEY")
+                    .ok()
+                    .filter(|k| !k.is_empty())
+                    .or_else(|| env::var("JWT_SECRET").ok())
+                    .unwrap_or_else(|| "super-secret-jwt-key-change-in-production".to_string()),
+                retention: Duration::from_secs(
+                    env::var("ANALYTICS_RETENTION_DAYS")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(90)
+                        * 24
+                        * 3600,
+                ),
+            },
+            privacy: {
+                let enabled = env::var("PRIVACY_MODE")
+                    .map(|v| v == "true" || v == "1")
+                    .unwrap_or(false);
+                let option = |name: &str| {
+                    env::var(name)
+                        .map(|v| v == "true" || v == "1")
+                        .unwrap_or(enabled)
+                };
+                PrivacyConfig {
+                    pad_messages: option("PRIVACY_PAD_MESSAGES"),
+                    minimize_logs: option("PRIVACY_MINIMIZE_LOGS"),
+                    coarse_receipts: option("PRIVACY_COARSE_RECEIPTS"),
+                }
+            },
+            sealed_sender: SealedSenderConfig {
+                secret: env::var("SEALED_SENDER_SECRET").ok().filter(|s| !s.is_empty()),
+                certificate_ttl: Duration::from_secs(
+                    env::var("SEALED_SENDER_CERTIFICATE_TTL")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(24 * 60 * 60), // 1 day
+                ),
+                certificates_per_day: env::var("SEALED_SENDER_CERTIFICATES_PER_DAY")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(20),
+            },
+            backup: BackupConfig {
+                interval: Duration::from_secs(
+                    env::var("BACKUP_INTERVAL_HOURS")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(0)
+                        * 3600,
+                ),
+                retention: env::var("BACKUP_RETENTION")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(7_usize)
+                    .max(1),
+            },
+            websocket: WebSocketConfig {
+                max_connections_per_device: env::var("WS_MAX_CONNECTIONS_PER_DEVICE")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(1_usize)
+                    .max(1),
+                max_connections_per_user: env::var("WS_MAX_CONNECTIONS_PER_USER")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(10_usize)
+                    .max(1),
+                reject_over_limit: env::var("WS_REJECT_OVER_LIMIT")
+                    .map(|v| v == "true" || v == "1")
+                    .unwrap_or(false),
+                compression: env::var("WS_COMPRESSION")
+                    .map(|v| v == "true" || v == "1")
+                    .unwrap_or(true),
+                compression_threshold: env::var("WS_COMPRESSION_THRESHOLD")
+                    .ok()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(1024),
+                typing_ttl: Duration::from_secs(
+                    env::var("WS_TYPING_TTL")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(6_u64)
+                        .max(1),
+                ),
+                typing_update_interval: Duration::from_secs(
+                    env::var("WS_TYPING_UPDATE_INTERVAL")
+                        .ok()
+                        .and_then(|p| p.parse().ok())
+                        .unwrap_or(2_u64)
+                        .max(1),
+                ),
+            },
+            grpc: GrpcConfig {
+                addr: env::var("GRPC_ADDR").ok().filter(|a| !a.is_empty()),
+                auth_token: env::var("GRPC_AUTH_TOKEN").ok().filter(|t| !t.is_empty()),
+            },
+            matrix: MatrixConfig {
+                homeserver_url: env::var("MATRIX_HOMESERVER_URL").ok().filter(|u| !u.is_empty()),
+                server_name: env::var("MATRIX_SERVER_NAME").unwrap_or_default(),
+                as_token: env::var("MATRIX_AS_TOKEN").unwrap_or_default(),
+                hs_token: env::var("MATRIX_HS_TOKEN").unwrap_or_default(),
+                user_prefix: env::var("MATRIX_USER_PREFIX")
+                    .unwrap_or_else(|_| "_ansible_".to_string()),
+                bot_localpart: env::var("MATRIX_BOT_LOCALPART")
+                    .unwrap_or_else(|_| "ansible_bridge".to_string()),
+            },
         }
     }
 
@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -17,12 +17,34 @@ pub enum AppError {
     TokenExpired,
     #[error("Unauthorized")]
     Unauthorized,
+    #[error("Not available in this session")]
+    ScopeNotGranted,
+    #[error("Link request declined")]
+    DeviceLinkDeclined,
+    #[error("Admin access required")]
+    AdminRequired,
+    #[error("An invite code is required to register")]
+    InviteRequired,
+    #[error("Invalid or expired invite code")]
+    InviteInvalid,
+    #[error("No invites left")]
+    InviteQuotaExceeded,
+    #[error("Not yet approved from the waitlist")]
+    WaitlistNotApproved,
+    #[error("Signups are not waitlisted")]
+    WaitlistClosed,
+    #[error("Account suspended")]
+    UserBanned,
+    #[error("Invalid or expired delivery token")]
+    DeliveryTokenInvalid,
 
     // User errors
     #[error("User not found")]
     UserNotFound,
     #[error("User already exists")]
     UserAlreadyExists,
+    #[error("Username is taken")]
+    UsernameTaken,
 
     // OTP errors
     #[error("Invalid OTP")]
@@ -34,6 +56,12 @@ pub enum AppError {
     #[error("OTP not verified")]
     OtpNotVerified,
 
+    // Abuse prevention errors
+    #[error("CAPTCHA required")]
+    CaptchaRequired,
+    #[error("CAPTCHA verification failed")]
+    CaptchaInvalid,
+
     // Contact errors
     #[error("Contact not found")]
     ContactNotFound,
@@ -47,10 +75,52 @@ pub enum AppError {
     ConversationNotFound,
     #[error("Not a participant")]
     NotParticipant,
+    #[error("Insufficient permissions")]
+    InsufficientPermissions,
+    #[error("Only owners and admins can post in this conversation")]
+    AnnouncementOnly,
 
     // Message errors
     #[error("Message not found")]
     MessageNotFound,
+    #[error("Sending messages too fast")]
+    MessageRateLimited,
+    #[error("API token rate limit exceeded")]
+    ApiTokenRateLimited,
+    #[error("View-once media was already viewed or has expired")]
+    MediaGone,
+    #[error("Attachment not found")]
+    AttachmentNotFound,
+    #[error("Media link has expired")]
+    MediaLinkExpired,
+
+    // Broadcast errors
+    #[error("Broadcast list not found")]
+    BroadcastListNotFound,
+    #[error("Broadcast not found")]
+    BroadcastNotFound,
+
+    // Moderation errors
+    #[error("Moderation alert not found")]
+    ModerationAlertNotFound,
+    #[error("Report not found")]
+    MessageReportNotFound,
+    #[error("You already reported this message")]
+    MessageAlreadyReported,
+    #[error("Content rejected: {0}")]
+    ContentRejected(String),
+    #[error("Content held for review")]
+    ContentQuarantined,
+    #[error("Message exceeds the {0}-byte limit")]
+    MessageTooLarge(usize),
+    #[error("Content type '{0}' is not allowed")]
+    UnsupportedMediaType(String),
+
+    // Device errors
+    #[error("Device not found")]
+    DeviceNotFound,
+    #[error("Device not verified")]
+    DeviceNotVerified,
 
     // Signal key errors
     #[error("Identity key not found")]
@@ -58,6 +128,10 @@ pub enum AppError {
     #[error("Pre-key not found")]
     PreKeyNotFound,
 
+    // Push errors
+    #[error("Push subscription expired")]
+    PushSubscriptionExpired,
+
     // Sticker errors
     #[error("Sticker pack not found")]
     StickerPackNotFound,
@@ -66,6 +140,64 @@ pub enum AppError {
     #[error("Sticker pack not owned")]
     StickerPackNotOwned,
 
+    // Custom emoji errors
+    #[error("Custom emoji not found")]
+    EmojiNotFound,
+    #[error("A custom emoji with this name already exists")]
+    EmojiNameTaken,
+
+    // Organization errors
+    #[error("Organization not found")]
+    OrganizationNotFound,
+
+    // Backup errors
+    #[error("Backup not found")]
+    BackupNotFound,
+    #[error("Link request not found or expired")]
+    DeviceLinkNotFound,
+    #[error("No minimum version set for this platform")]
+    ClientVersionRequirementNotFound,
+    #[error("API token not found")]
+    ApiTokenNotFound,
+    #[error("Invite not found")]
+    InviteNotFound,
+    #[error("Waitlist entry not found")]
+    WaitlistEntryNotFound,
+    #[error("A backup or restore is already running")]
+    BackupInProgress,
+    #[error("Settings were changed more recently on another device")]
+    SyncDataConflict { current: serde_json::Value },
+
+    // Service availability errors
+    #[error("Message search is not enabled")]
+    SearchDisabled,
+    #[error("Backups need BACKUP_BUCKET to be set")]
+    BackupsDisabled,
+    #[error("Message translation is not enabled")]
+    TranslationDisabled,
+    #[error("GIF search is not enabled")]
+    GifSearchDisabled,
+    #[error("Matrix bridge is not enabled")]
+    MatrixBridgeDisabled,
+    #[error("Web Push is not enabled")]
+    WebPushDisabled,
+    #[error("Request timed out")]
+    Timeout,
+    #[error("Server is overloaded, try again later")]
+    Overloaded { retry_after: u64 },
+    #[error("This app version is no longer supported, please upgrade")]
+    UpgradeRequired {
+        platform: String,
+        min_version: String,
+        upgrade_url: Option<String>,
+        message: Option<String>,
+    },
+    #[error("Service under maintenance")]
+    Maintenance {
+        message: Option<String>,
+        retry_after: u64,
+    },
+
     // Validation errors
     #[error("Validation error: {0}")]
     Validation(String),
@@ -80,6 +212,10 @@ pub enum AppError {
     #[error("Redis error: {0}")]
     Redis(#[from] redis::RedisError),
 
+    // Serialization errors
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+
     // JWT errors
     #[error("JWT error: {0}")]
     Jwt(#[from] jsonwebtoken::errors::Error),
@@ -94,6 +230,8 @@ impl IntoResponse for AppError {
         let (status, message) = match &self {
             // 400 Bad Request
             AppError::Validation(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
+            AppError::InviteInvalid => (StatusCode::BAD_REQUEST, self.to_string()),
+            AppError::WaitlistClosed => (StatusCode::BAD_REQUEST, self.to_string()),
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
             AppError::InvalidOtp => (StatusCode::BAD_REQUEST, self.to_string()),
             AppError::OtpExpired => (StatusCode::BAD_REQUEST, self.to_string()),
@@ -104,29 +242,142 @@ impl IntoResponse for AppError {
             AppError::InvalidToken => (StatusCode::UNAUTHORIZED, self.to_string()),
             AppError::TokenExpired => (StatusCode::UNAUTHORIZED, self.to_string()),
             AppError::Unauthorized => (StatusCode::UNAUTHORIZED, self.to_string()),
+            AppError::DeliveryTokenInvalid => (StatusCode::UNAUTHORIZED, self.to_string()),
             AppError::Jwt(_) => (StatusCode::UNAUTHORIZED, "Invalid token".to_string()),
 
             // 403 Forbidden
             AppError::NotParticipant => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::InsufficientPermissions => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::AnnouncementOnly => (StatusCode::FORBIDDEN, self.to_string()),
             AppError::OtpNotVerified => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::AdminRequired => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::InviteRequired => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::InviteQuotaExceeded => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::WaitlistNotApproved => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::ScopeNotGranted => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::DeviceLinkDeclined => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::UserBanned => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::CaptchaInvalid => (StatusCode::FORBIDDEN, self.to_string()),
+            AppError::DeviceNotVerified => (StatusCode::FORBIDDEN, self.to_string()),
+
+            // 428 Precondition Required
+            AppError::CaptchaRequired => (StatusCode::PRECONDITION_REQUIRED, self.to_string()),
 
             // 404 Not Found
             AppError::UserNotFound => (StatusCode::NOT_FOUND, self.to_string()),
             AppError::ContactNotFound => (StatusCode::NOT_FOUND, self.to_string()),
             AppError::ConversationNotFound => (StatusCode::NOT_FOUND, self.to_string()),
             AppError::MessageNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::AttachmentNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::BroadcastListNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::BroadcastNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::ModerationAlertNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::MessageReportNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::DeviceNotFound => (StatusCode::NOT_FOUND, self.to_string()),
             AppError::IdentityKeyNotFound => (StatusCode::NOT_FOUND, self.to_string()),
             AppError::PreKeyNotFound => (StatusCode::NOT_FOUND, self.to_string()),
             AppError::StickerPackNotFound => (StatusCode::NOT_FOUND, self.to_string()),
             AppError::StickerPackNotOwned => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::EmojiNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::OrganizationNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::BackupNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::DeviceLinkNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::ClientVersionRequirementNotFound => {
+                (StatusCode::NOT_FOUND, self.to_string())
+            }
+            AppError::ApiTokenNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::InviteNotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::WaitlistEntryNotFound => (StatusCode::NOT_FOUND, self.to_string()),
 
             // 409 Conflict
             AppError::UserAlreadyExists => (StatusCode::CONFLICT, self.to_string()),
+            AppError::UsernameTaken => (StatusCode::CONFLICT, self.to_string()),
             AppError::ContactAlreadyExists => (StatusCode::CONFLICT, self.to_string()),
+            AppError::MessageAlreadyReported => (StatusCode::CONFLICT, self.to_string()),
             AppError::StickerPackAlreadyOwned => (StatusCode::CONFLICT, self.to_string()),
+            AppError::EmojiNameTaken => (StatusCode::CONFLICT, self.to_string()),
+            AppError::BackupInProgress => (StatusCode::CONFLICT, self.to_string()),
+            AppError::SyncDataConflict { current } => {
+                let body = Json(json!({
+                    "error": self.to_string(),
+                    "current": current,
+                }));
+                return (StatusCode::CONFLICT, body).into_response();
+            }
 
             // 429 Too Many Requests
             AppError::TooManyAttempts => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
+            AppError::MessageRateLimited => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
+            AppError::ApiTokenRateLimited => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
+
+            // 410 Gone
+            AppError::MediaGone => (StatusCode::GONE, self.to_string()),
+            AppError::MediaLinkExpired => (StatusCode::GONE, self.to_string()),
+            AppError::PushSubscriptionExpired => (StatusCode::GONE, self.to_string()),
+
+            // 422 Unprocessable Entity
+            AppError::ContentRejected(_) => (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()),
+            AppError::ContentQuarantined => (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()),
+
+            // 413 Payload Too Large
+            AppError::MessageTooLarge(_) => (StatusCode::PAYLOAD_TOO_LARGE, self.to_string()),
+
+            // 415 Unsupported Media Type
+            AppError::UnsupportedMediaType(_) => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, self.to_string())
+            }
+
+            // 426 Upgrade Required
+            AppError::UpgradeRequired {
+                platform,
+                min_version,
+                upgrade_url,
+                message,
+            } => {
+                let body = Json(json!({
+                    "error": message.clone().unwrap_or_else(|| self.to_string()),
+                    "upgrade_required": true,
+                    "platform": platform,
+                    "min_version": min_version,
+                    "upgrade_url": upgrade_url,
+                }));
+                return (StatusCode::UPGRADE_REQUIRED, body).into_response();
+            }
+
+            // 503 Service Unavailable
+            AppError::SearchDisabled => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::BackupsDisabled => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::TranslationDisabled => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::GifSearchDisabled => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::MatrixBridgeDisabled => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::WebPushDisabled => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
+            AppError::Maintenance { message, retry_after } => {
+                let body = Json(json!({
+                    "error": message.clone().unwrap_or_else(|| self.to_string())
+                }));
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(RETRY_AFTER, retry_after.to_string())],
+                    body,
+                )
+                    .into_response();
+            }
+            AppError::Overloaded { retry_after } => {
+                let body = Json(json!({ "error": self.to_string() }));
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(RETRY_AFTER, retry_after.to_string())],
+                    body,
+                )
+                    .into_response();
+            }
+
+            // 504 Gateway Timeout
+            AppError::Timeout => (StatusCode::GATEWAY_TIMEOUT, self.to_string()),
+            AppError::Database(e) if is_statement_timeout(e) => {
+                tracing::warn!("Statement timed out: {}", e);
+                (StatusCode::GATEWAY_TIMEOUT, AppError::Timeout.to_string())
+            }
 
             // 500 Internal Server Error
             AppError::Database(e) => {
@@ -140,6 +391,13 @@ impl IntoResponse for AppError {
                 tracing::error!("Redis error: {}", e);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Cache error".to_string())
             }
+            AppError::Serialization(e) => {
+                tracing::error!("Serialization error: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal server error".to_string(),
+                )
+            }
             AppError::Internal(e) => {
                 tracing::error!("Internal error: {}", e);
                 (
@@ -157,4 +415,11 @@ impl IntoResponse for AppError {
     }
 }
 
+/// Postgres cancelled the statement for running past `statement_timeout`
+fn is_statement_timeout(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| code == "57014")
+}
+
 pub type AppResult<T> = Result<T, AppError>;
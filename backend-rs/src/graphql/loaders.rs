@@ -0,0 +1,172 @@
+//! Batch loaders, created per request so nothing is cached across viewers
+
+use std::collections::HashMap;
+
+use async_graphql::dataloader::Loader;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    models::{Message, Participant, Sticker, User},
+    services::social_graph::SocialGraphService,
+};
+
+use super::to_graphql_error;
+
+fn db_error(e: sqlx::Error) -> async_graphql::Error {
+    to_graphql_error(AppError::from(e))
+}
+
+/// Users as the viewer sees them, without the presence of those who may not
+/// interact with the viewer
+pub struct UserLoader {
+    pub db: PgPool,
+    pub viewer_id: Uuid,
+}
+
+impl Loader<Uuid> for UserLoader {
+    type Value = User;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, User>, Self::Error> {
+        let mut users: Vec<User> = sqlx::query_as("SELECT * FROM users WHERE id = ANY($1)")
+            .bind(keys)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        SocialGraphService::new(self.db.clone())
+            .redact_presence(self.viewer_id, users.iter_mut())
+            .await
+            .map_err(to_graphql_error)?;
+
+        Ok(users.into_iter().map(|user| (user.id, user)).collect())
+    }
+}
+
+/// Current members of each conversation, owners and admins first
+pub struct MembersLoader {
+    pub db: PgPool,
+}
+
+impl Loader<Uuid> for MembersLoader {
+    type Value = Vec<Participant>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Vec<Participant>>, Self::Error> {
+        let participants: Vec<Participant> = sqlx::query_as(
+            r#"
+            SELECT * FROM participants
+            WHERE conversation_id = ANY($1) AND left_at IS NULL
+            ORDER BY CASE role WHEN 'owner' THEN 0 WHEN 'admin' THEN 1 ELSE 2 END, joined_at
+            "#,
+        )
+        .bind(keys)
+        .fetch_all(&self.db)
+        .await
+        .map_err(db_error)?;
+
+        let mut members: HashMap<Uuid, Vec<Participant>> = HashMap::new();
+        for participant in participants {
+            members
+                .entry(participant.conversation_id)
+                .or_default()
+                .push(participant);
+        }
+
+        Ok(members)
+    }
+}
+
+/// Latest message of each conversation as the viewer sees it
+pub struct LastMessageLoader {
+    pub db: PgPool,
+    pub viewer_id: Uuid,
+}
+
+impl Loader<Uuid> for LastMessageLoader {
+    type Value = Message;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Message>, Self::Error> {
+        let messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT DISTINCT ON (m.conversation_id) m.* FROM messages m
+            LEFT JOIN participants p ON p.conversation_id = m.conversation_id AND p.user_id = $2
+            WHERE m.conversation_id = ANY($1) AND m.deleted_at IS NULL
+            AND (NOT m.is_shadowed OR m.sender_id = $2)
+            AND (p.cleared_before IS NULL OR m.created_at > p.cleared_before)
+            ORDER BY m.conversation_id, m.created_at DESC
+            "#,
+        )
+        .bind(keys)
+        .bind(self.viewer_id)
+        .fetch_all(&self.db)
+        .await
+        .map_err(db_error)?;
+
+        Ok(messages
+            .into_iter()
+            .map(|message| (message.conversation_id, message.decode().redact_view_once()))
+            .collect())
+    }
+}
+
+/// Messages from others the viewer hasn't read, per conversation
+pub struct UnreadCountLoader {
+    pub db: PgPool,
+    pub viewer_id: Uuid,
+}
+
+impl Loader<Uuid> for UnreadCountLoader {
+    type Value = i64;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, i64>, Self::Error> {
+        let counts: Vec<(Uuid, i64)> = sqlx::query_as(
+            r#"
+            SELECT m.conversation_id, COUNT(*) FROM messages m
+            LEFT JOIN receipts r ON m.id = r.message_id AND r.user_id = $2 AND r.type = 'read'
+            LEFT JOIN participants p ON p.conversation_id = m.conversation_id AND p.user_id = $2
+            WHERE m.conversation_id = ANY($1) AND m.sender_id != $2 AND r.id IS NULL
+            AND m.deleted_at IS NULL AND NOT m.is_shadowed
+            AND (p.cleared_before IS NULL OR m.created_at > p.cleared_before)
+            GROUP BY m.conversation_id
+            "#,
+        )
+        .bind(keys)
+        .bind(self.viewer_id)
+        .fetch_all(&self.db)
+        .await
+        .map_err(db_error)?;
+
+        Ok(counts.into_iter().collect())
+    }
+}
+
+/// Stickers of each pack in display order
+pub struct StickersLoader {
+    pub db: PgPool,
+}
+
+impl Loader<Uuid> for StickersLoader {
+    type Value = Vec<Sticker>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Vec<Sticker>>, Self::Error> {
+        let stickers: Vec<Sticker> =
+            sqlx::query_as("SELECT * FROM stickers WHERE pack_id = ANY($1) ORDER BY position")
+                .bind(keys)
+                .fetch_all(&self.db)
+                .await
+                .map_err(db_error)?;
+
+        let mut packs: HashMap<Uuid, Vec<Sticker>> = HashMap::new();
+        for sticker in stickers {
+            packs.entry(sticker.pack_id).or_default().push(sticker);
+        }
+
+        Ok(packs)
+    }
+}
@@ -0,0 +1,348 @@
+//! GraphQL gateway over conversations, messages, contacts, and stickers.
+//! Nested fields are batched through dataloaders so the conversation list
+//! costs a fixed number of queries however many conversations it shows.
+
+mod loaders;
+mod types;
+
+use std::sync::Arc;
+
+use async_graphql::{
+    dataloader::DataLoader, Context, Data, EmptyMutation, ErrorExtensions, Json, Object, Schema,
+    SimpleObject, Subscription, ID,
+};
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
+use axum::{
+    extract::{State, WebSocketUpgrade},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use futures::{Stream, StreamExt};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        middleware::get_user_id,
+        websocket::{WsHub, WsOutgoingMessage, WsRegistration},
+        ws_queue::{OutboundQueue, QUEUE_CAPACITY},
+    },
+    error::{AppError, AppResult},
+    models::{ConversationFilter, User},
+    services::{
+        auth::Claims, contacts::ContactsService, media::MediaUrls, messaging::MessagingService,
+        stickers::StickersService,
+    },
+    AppState,
+};
+
+use loaders::{LastMessageLoader, MembersLoader, StickersLoader, UnreadCountLoader, UserLoader};
+use types::{ContactObject, ConversationObject, StickerPackObject, UserObject};
+
+pub type AppSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+/// The authenticated user a request runs as
+pub struct Viewer {
+    pub user_id: Uuid,
+}
+
+pub fn build_schema(state: AppState) -> AppSchema {
+    Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+        .data(state)
+        .limit_depth(10)
+        .finish()
+}
+
+fn state<'a>(ctx: &Context<'a>) -> &'a AppState {
+    ctx.data_unchecked::<AppState>()
+}
+
+/// Map an error to a GraphQL error carrying its HTTP status as `code`
+fn to_graphql_error(error: AppError) -> async_graphql::Error {
+    let message = error.to_string();
+    let status = error.into_response().status();
+
+    // Details of server errors were logged when the error became a response
+    let message = if status.is_server_error() && status.as_u16() != 503 {
+        "Internal server error".to_string()
+    } else {
+        message
+    };
+
+    async_graphql::Error::new(message).extend_with(|_, e| e.set("code", status.as_u16()))
+}
+
+/// Per-request context: the viewer and fresh dataloaders
+fn request_data(state: &AppState, user_id: Uuid) -> Data {
+    let mut data = Data::default();
+    data.insert(Viewer { user_id });
+    data.insert(MediaUrls::new(&state.config));
+    data.insert(DataLoader::new(
+        UserLoader {
+            db: state.db.clone(),
+            viewer_id: user_id,
+        },
+        tokio::spawn,
+    ));
+    data.insert(DataLoader::new(
+        MembersLoader {
+            db: state.db.clone(),
+        },
+        tokio::spawn,
+    ));
+    data.insert(DataLoader::new(
+        LastMessageLoader {
+            db: state.db.clone(),
+            viewer_id: user_id,
+        },
+        tokio::spawn,
+    ));
+    data.insert(DataLoader::new(
+        UnreadCountLoader {
+            db: state.db.clone(),
+            viewer_id: user_id,
+        },
+        tokio::spawn,
+    ));
+    data.insert(DataLoader::new(
+        StickersLoader {
+            db: state.db.clone(),
+        },
+        tokio::spawn,
+    ));
+    data
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn me(&self, ctx: &Context<'_>) -> async_graphql::Result<UserObject> {
+        let viewer = ctx.data_unchecked::<Viewer>();
+        let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+            .bind(viewer.user_id)
+            .fetch_optional(&state(ctx).db)
+            .await
+            .map_err(|e| to_graphql_error(e.into()))?
+            .ok_or_else(|| to_graphql_error(AppError::UserNotFound))?;
+
+        Ok(UserObject(user))
+    }
+
+    /// Your conversations, most recently active first
+    async fn conversations(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] requests: bool,
+        #[graphql(default = 20)] limit: i32,
+        #[graphql(default)] offset: i32,
+    ) -> async_graphql::Result<Vec<ConversationObject>> {
+        let state = state(ctx);
+        let viewer = ctx.data_unchecked::<Viewer>();
+        let filter = if requests {
+            ConversationFilter::Requests
+        } else {
+            ConversationFilter::Inbox
+        };
+
+        let messaging = MessagingService::new(
+            state.db.clone(),
+            state.redis.clone(),
+            (*state.config).clone(),
+        );
+        let conversations = messaging
+            .list_conversations(viewer.user_id, filter, limit.clamp(1, 100), offset.max(0))
+            .await
+            .map_err(to_graphql_error)?;
+
+        Ok(conversations.into_iter().map(ConversationObject).collect())
+    }
+
+    async fn conversation(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+    ) -> async_graphql::Result<ConversationObject> {
+        let viewer = ctx.data_unchecked::<Viewer>();
+        let conversation_id = Uuid::parse_str(&id)?;
+
+        let conversation = sqlx::query_as(
+            r#"
+            SELECT c.* FROM conversations c
+            JOIN participants p ON p.conversation_id = c.id
+            WHERE c.id = $1 AND p.user_id = $2 AND p.left_at IS NULL
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(viewer.user_id)
+        .fetch_optional(&state(ctx).db)
+        .await
+        .map_err(|e| to_graphql_error(e.into()))?
+        .ok_or_else(|| to_graphql_error(AppError::ConversationNotFound))?;
+
+        Ok(ConversationObject(conversation))
+    }
+
+    async fn contacts(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] include_blocked: bool,
+    ) -> async_graphql::Result<Vec<ContactObject>> {
+        let viewer = ctx.data_unchecked::<Viewer>();
+        let contacts = ContactsService::new(state(ctx).db.clone())
+            .list_contacts(viewer.user_id, include_blocked)
+            .await
+            .map_err(to_graphql_error)?;
+
+        Ok(contacts.into_iter().map(ContactObject).collect())
+    }
+
+    /// Your downloaded sticker packs in your chosen order
+    async fn sticker_packs(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<Vec<StickerPackObject>> {
+        let state = state(ctx);
+        let viewer = ctx.data_unchecked::<Viewer>();
+        let packs = StickersService::new(state.db.clone(), state.storage.clone())
+            .list_user_packs(viewer.user_id)
+            .await
+            .map_err(to_graphql_error)?;
+
+        Ok(packs.into_iter().map(StickerPackObject).collect())
+    }
+}
+
+/// A real-time event, the same as sent over `/ws`
+#[derive(SimpleObject)]
+pub struct Event {
+    #[graphql(name = "type")]
+    pub event_type: String,
+    pub payload: Json<serde_json::Value>,
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Your real-time events, optionally only those of the given types
+    async fn events(
+        &self,
+        ctx: &Context<'_>,
+        types: Option<Vec<String>>,
+    ) -> async_graphql::Result<impl Stream<Item = Event>> {
+        let state = state(ctx);
+        let viewer = ctx.data_unchecked::<Viewer>();
+        let subscription = HubSubscription::start(state, viewer.user_id)
+            .await
+            .ok_or_else(|| async_graphql::Error::new("Too many connections"))?;
+
+        Ok(futures::stream::unfold(subscription, |mut subscription| async move {
+            let message = subscription.next().await?;
+            Some((message, subscription))
+        })
+        .filter(move |message| {
+            let wanted = match &types {
+                Some(types) => types.contains(&message.msg_type),
+                None => true,
+            };
+            async move { wanted }
+        })
+        .map(|message| Event {
+            event_type: message.msg_type,
+            payload: Json(message.payload),
+        }))
+    }
+}
+
+/// A subscription registered with the `WsHub` like a WebSocket client, so it
+/// receives the same direct sends, per-user Redis events, and broadcasts
+struct HubSubscription {
+    hub: Arc<WsHub>,
+    client_id: String,
+    registration: WsRegistration,
+    queue: Arc<OutboundQueue>,
+    redis_task: JoinHandle<()>,
+}
+
+impl HubSubscription {
+    /// `None` when the user is at their connection limit
+    async fn start(state: &AppState, user_id: Uuid) -> Option<Self> {
+        let client_id = format!("{}:graphql:{}", user_id, Uuid::new_v4());
+        let queue = Arc::new(OutboundQueue::new(QUEUE_CAPACITY));
+        let registration = state.ws_hub.register(&client_id, queue.clone()).await?;
+
+        let redis = state.redis.clone();
+        let inbound = queue.clone();
+        let redis_task = tokio::spawn(async move {
+            if let Ok(mut stream) = redis.subscribe_messages(&user_id.to_string()).await {
+                while let Some(msg) = stream.next().await {
+                    if let Ok(ws_msg) = serde_json::from_str::<WsOutgoingMessage>(&msg.payload) {
+                        inbound.push(ws_msg);
+                    }
+                }
+            }
+        });
+
+        Some(Self {
+            hub: state.ws_hub.clone(),
+            client_id,
+            registration,
+            queue,
+            redis_task,
+        })
+    }
+
+    /// The next event, or `None` once a newer connection replaces this one
+    async fn next(&mut self) -> Option<WsOutgoingMessage> {
+        tokio::select! {
+            message = self.queue.recv() => Some(message),
+            _ = self.registration.evicted.notified() => None,
+        }
+    }
+}
+
+impl Drop for HubSubscription {
+    fn drop(&mut self) {
+        self.redis_task.abort();
+
+        let hub = self.hub.clone();
+        let client_id = std::mem::take(&mut self.client_id);
+        let connection_id = self.registration.id;
+        tokio::spawn(async move { hub.unregister(&client_id, connection_id).await });
+    }
+}
+
+pub async fn graphql_handler(
+    State(state): State<AppState>,
+    Extension(schema): Extension<AppSchema>,
+    Extension(claims): Extension<Claims>,
+    request: GraphQLRequest,
+) -> AppResult<GraphQLResponse> {
+    let user_id = get_user_id(&claims)?;
+
+    let mut request = request.into_inner();
+    request.data = request_data(&state, user_id);
+
+    Ok(schema.execute(request).await.into())
+}
+
+/// GraphQL subscriptions over WebSocket (`graphql-transport-ws` or `graphql-ws`)
+pub async fn graphql_ws_handler(
+    State(state): State<AppState>,
+    Extension(schema): Extension<AppSchema>,
+    Extension(claims): Extension<Claims>,
+    protocol: GraphQLProtocol,
+    ws: WebSocketUpgrade,
+) -> AppResult<Response> {
+    let user_id = get_user_id(&claims)?;
+    let data = request_data(&state, user_id);
+
+    Ok(ws
+        .protocols(async_graphql::http::ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| {
+            GraphQLWebSocket::new(socket, schema, protocol)
+                .with_data(data)
+                .serve()
+        }))
+}
@@ -0,0 +1,420 @@
+use async_graphql::{dataloader::DataLoader, Context, Object, Result, ID};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        Contact, Conversation, GroupPermissions, Message, Participant, Sticker, StickerPack, User,
+    },
+    services::{media::MediaUrls, messaging::MessagingService},
+};
+
+use super::{
+    loaders::{LastMessageLoader, MembersLoader, StickersLoader, UnreadCountLoader, UserLoader},
+    state, to_graphql_error, Viewer,
+};
+
+/// The lowercase API name of an enum
+fn enum_name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+async fn load_user(ctx: &Context<'_>, user_id: Uuid) -> Result<Option<UserObject>> {
+    let user = ctx
+        .data_unchecked::<DataLoader<UserLoader>>()
+        .load_one(user_id)
+        .await?;
+
+    Ok(user.map(UserObject))
+}
+
+pub struct UserObject(pub User);
+
+#[Object(name = "User")]
+impl UserObject {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn username(&self) -> &str {
+        &self.0.username
+    }
+
+    async fn display_name(&self) -> &str {
+        &self.0.display_name
+    }
+
+    async fn avatar_url(&self) -> Option<&str> {
+        self.0.avatar_url.as_deref()
+    }
+
+    async fn bio(&self) -> Option<&str> {
+        self.0.bio.as_deref()
+    }
+
+    /// Only visible on your own profile
+    async fn phone(&self, ctx: &Context<'_>) -> Option<&str> {
+        if ctx.data_unchecked::<Viewer>().user_id == self.0.id {
+            self.0.phone.as_deref()
+        } else {
+            None
+        }
+    }
+
+    /// Only visible on your own profile
+    async fn email(&self, ctx: &Context<'_>) -> Option<&str> {
+        if ctx.data_unchecked::<Viewer>().user_id == self.0.id {
+            self.0.email.as_deref()
+        } else {
+            None
+        }
+    }
+
+    /// online, offline, or away
+    async fn status(&self) -> String {
+        enum_name(&self.0.status)
+    }
+
+    async fn last_seen_at(&self) -> Option<DateTime<Utc>> {
+        self.0.last_seen_at
+    }
+
+    async fn emoji_status(&self) -> Option<&str> {
+        self.0.emoji_status.as_deref()
+    }
+
+    async fn emoji_status_expires_at(&self) -> Option<DateTime<Utc>> {
+        self.0.emoji_status_expires_at
+    }
+
+    async fn pronouns(&self) -> Option<&str> {
+        self.0.pronouns.as_deref()
+    }
+
+    async fn links(&self) -> &[String] {
+        &self.0.links
+    }
+}
+
+pub struct ConversationObject(pub Conversation);
+
+#[Object(name = "Conversation")]
+impl ConversationObject {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    /// direct, group, or saved
+    #[graphql(name = "type")]
+    async fn conversation_type(&self) -> String {
+        enum_name(&self.0.conversation_type)
+    }
+
+    /// signal (text is Signal ciphertext) or none
+    async fn encryption(&self) -> String {
+        enum_name(&self.0.encryption)
+    }
+
+    async fn name(&self) -> Option<&str> {
+        self.0.name.as_deref()
+    }
+
+    /// Only owners and admins may post
+    async fn announcement_only(&self) -> bool {
+        self.0.announcement_only
+    }
+
+    /// Who may do what; only meaningful for groups
+    async fn permissions(&self) -> GroupPermissionsObject {
+        GroupPermissionsObject(self.0.permissions)
+    }
+
+    async fn avatar_url(&self) -> Option<&str> {
+        self.0.avatar_url.as_deref()
+    }
+
+    async fn description(&self) -> Option<&str> {
+        self.0.description.as_deref()
+    }
+
+    async fn rules(&self) -> Option<&str> {
+        self.0.rules.as_deref()
+    }
+
+    async fn last_message_at(&self) -> Option<DateTime<Utc>> {
+        self.0.last_message_at
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    /// Current members, owners and admins first
+    async fn members(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 20)] limit: usize,
+    ) -> Result<Vec<MemberObject>> {
+        let members = ctx
+            .data_unchecked::<DataLoader<MembersLoader>>()
+            .load_one(self.0.id)
+            .await?
+            .unwrap_or_default();
+
+        Ok(members.into_iter().take(limit).map(MemberObject).collect())
+    }
+
+    async fn member_count(&self) -> i32 {
+        self.0.participant_count
+    }
+
+    /// Current owners and admins
+    async fn admin_count(&self) -> i32 {
+        self.0.admin_count
+    }
+
+    async fn unread_count(&self, ctx: &Context<'_>) -> Result<i64> {
+        let count = ctx
+            .data_unchecked::<DataLoader<UnreadCountLoader>>()
+            .load_one(self.0.id)
+            .await?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    async fn last_message(&self, ctx: &Context<'_>) -> Result<Option<MessageObject>> {
+        let message = ctx
+            .data_unchecked::<DataLoader<LastMessageLoader>>()
+            .load_one(self.0.id)
+            .await?;
+
+        Ok(message.map(MessageObject))
+    }
+
+    /// Newest first; page backwards with `before`
+    async fn messages(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 50)] limit: i32,
+        before: Option<ID>,
+    ) -> Result<Vec<MessageObject>> {
+        let state = state(ctx);
+        let viewer = ctx.data_unchecked::<Viewer>();
+        let before = before.map(|id| Uuid::parse_str(&id)).transpose()?;
+
+        let messaging = MessagingService::new(
+            state.db.clone(),
+            state.redis.clone(),
+            (*state.config).clone(),
+        );
+        let messages = messaging
+            .get_messages(self.0.id, viewer.user_id, limit.clamp(1, 100), 0, before)
+            .await
+            .map_err(to_graphql_error)?;
+
+        Ok(messages.into_iter().map(MessageObject).collect())
+    }
+}
+
+/// The lowest role (owner, admin, or member) allowed each action
+pub struct GroupPermissionsObject(pub GroupPermissions);
+
+#[Object(name = "GroupPermissions")]
+impl GroupPermissionsObject {
+    async fn send_messages(&self) -> String {
+        enum_name(&self.0.send_messages)
+    }
+
+    /// Images, videos, audio and files
+    async fn send_media(&self) -> String {
+        enum_name(&self.0.send_media)
+    }
+
+    async fn send_stickers(&self) -> String {
+        enum_name(&self.0.send_stickers)
+    }
+
+    async fn add_members(&self) -> String {
+        enum_name(&self.0.add_members)
+    }
+
+    /// Name, description, rules and avatar
+    async fn change_info(&self) -> String {
+        enum_name(&self.0.change_info)
+    }
+}
+
+pub struct MemberObject(pub Participant);
+
+#[Object(name = "Member")]
+impl MemberObject {
+    /// owner, admin, or member
+    async fn role(&self) -> String {
+        enum_name(&self.0.role)
+    }
+
+    async fn joined_at(&self) -> DateTime<Utc> {
+        self.0.joined_at
+    }
+
+    async fn user(&self, ctx: &Context<'_>) -> Result<Option<UserObject>> {
+        load_user(ctx, self.0.user_id).await
+    }
+}
+
+pub struct MessageObject(pub Message);
+
+#[Object(name = "Message")]
+impl MessageObject {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn conversation_id(&self) -> ID {
+        ID(self.0.conversation_id.to_string())
+    }
+
+    /// text, image, video, audio, file, sticker, or system
+    #[graphql(name = "type")]
+    async fn message_type(&self) -> String {
+        enum_name(&self.0.message_type)
+    }
+
+    /// Base64-encoded content
+    async fn content(&self) -> String {
+        STANDARD.encode(&self.0.content)
+    }
+
+    async fn sticker_id(&self) -> Option<ID> {
+        self.0.sticker_id.map(|id| ID(id.to_string()))
+    }
+
+    async fn reply_to_id(&self) -> Option<ID> {
+        self.0.reply_to_id.map(|id| ID(id.to_string()))
+    }
+
+    /// sent, delivered, or read
+    async fn status(&self) -> String {
+        enum_name(&self.0.status)
+    }
+
+    async fn view_once(&self) -> bool {
+        self.0.view_once
+    }
+
+    async fn attachment_id(&self) -> Option<ID> {
+        self.0.attachment_id.map(|id| ID(id.to_string()))
+    }
+
+    /// Short-lived download link for the attachment, signed per request
+    async fn attachment_url(&self, ctx: &Context<'_>) -> Option<String> {
+        let urls = ctx.data_unchecked::<MediaUrls>();
+        self.0.attachment_id.map(|id| urls.sign(id).url)
+    }
+
+    async fn edited_at(&self) -> Option<DateTime<Utc>> {
+        self.0.edited_at
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    async fn sender(&self, ctx: &Context<'_>) -> Result<Option<UserObject>> {
+        load_user(ctx, self.0.sender_id).await
+    }
+}
+
+pub struct ContactObject(pub Contact);
+
+#[Object(name = "Contact")]
+impl ContactObject {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn nickname(&self) -> Option<&str> {
+        self.0.nickname.as_deref()
+    }
+
+    async fn is_blocked(&self) -> bool {
+        self.0.is_blocked
+    }
+
+    async fn is_favorite(&self) -> bool {
+        self.0.is_favorite
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    async fn user(&self, ctx: &Context<'_>) -> Result<Option<UserObject>> {
+        load_user(ctx, self.0.contact_id).await
+    }
+}
+
+pub struct StickerPackObject(pub StickerPack);
+
+#[Object(name = "StickerPack")]
+impl StickerPackObject {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn author(&self) -> &str {
+        &self.0.author
+    }
+
+    async fn description(&self) -> Option<&str> {
+        self.0.description.as_deref()
+    }
+
+    async fn cover_url(&self) -> Option<&str> {
+        self.0.cover_url.as_deref()
+    }
+
+    async fn is_animated(&self) -> bool {
+        self.0.is_animated
+    }
+
+    async fn stickers(&self, ctx: &Context<'_>) -> Result<Vec<StickerObject>> {
+        let stickers = ctx
+            .data_unchecked::<DataLoader<StickersLoader>>()
+            .load_one(self.0.id)
+            .await?
+            .unwrap_or_default();
+
+        Ok(stickers.into_iter().map(StickerObject).collect())
+    }
+}
+
+pub struct StickerObject(pub Sticker);
+
+#[Object(name = "Sticker")]
+impl StickerObject {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn emoji(&self) -> &str {
+        &self.0.emoji
+    }
+
+    async fn image_url(&self) -> &str {
+        &self.0.image_url
+    }
+
+    async fn preview_url(&self) -> Option<&str> {
+        self.0.preview_url.as_deref()
+    }
+}
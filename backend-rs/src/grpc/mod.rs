@@ -0,0 +1,236 @@
+//! Internal gRPC API for trusted backend consumers, served next to the HTTP
+//! API and backed by the same services
+
+// tonic's API is built around `Result<_, Status>`
+#![allow(clippy::result_large_err)]
+
+use std::{net::SocketAddr, pin::Pin};
+
+use axum::response::IntoResponse;
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use tonic::{metadata::MetadataValue, transport::Server, Request, Response, Status};
+use uuid::Uuid;
+
+use crate::{
+    config::GrpcConfig, error::AppError, models, services::messaging::MessagingService, AppState,
+};
+
+pub mod proto {
+    tonic::include_proto!("ansible.internal.v1");
+}
+
+use proto::{
+    get_user_request::Lookup,
+    internal_api_server::{InternalApi, InternalApiServer},
+    Event, GetUserRequest, Message, SendMessageRequest, SubscribeEventsRequest, User,
+};
+
+/// Serve the internal API until the process exits. Returns immediately when
+/// `GRPC_ADDR` is unset.
+pub async fn serve(state: AppState, config: GrpcConfig) {
+    let addr = match &config.addr {
+        Some(addr) => addr,
+        None => return,
+    };
+    let auth_token = match config.auth_token {
+        Some(token) => token,
+        None => {
+            tracing::warn!("GRPC_ADDR is set but GRPC_AUTH_TOKEN is not; gRPC API disabled");
+            return;
+        }
+    };
+    let addr: SocketAddr = match addr.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            tracing::error!("Invalid GRPC_ADDR {}: {}", addr, e);
+            return;
+        }
+    };
+
+    let expected: MetadataValue<_> = match format!("Bearer {}", auth_token).parse() {
+        Ok(value) => value,
+        Err(_) => {
+            tracing::error!("GRPC_AUTH_TOKEN is not a valid header value");
+            return;
+        }
+    };
+    let check_token = move |request: Request<()>| match request.metadata().get("authorization") {
+        Some(token) if token == expected => Ok(request),
+        _ => Err(Status::unauthenticated("Invalid token")),
+    };
+
+    tracing::info!("gRPC API listening on {}", addr);
+    let service = InternalApiServer::with_interceptor(InternalService { state }, check_token);
+    if let Err(e) = Server::builder().add_service(service).serve(addr).await {
+        tracing::error!("gRPC server failed: {}", e);
+    }
+}
+
+struct InternalService {
+    state: AppState,
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<Event, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl InternalApi for InternalService {
+    async fn get_user(&self, request: Request<GetUserRequest>) -> Result<Response<User>, Status> {
+        let lookup = request
+            .into_inner()
+            .lookup
+            .ok_or_else(|| Status::invalid_argument("A lookup field is required"))?;
+
+        let (condition, value) = match lookup {
+            Lookup::Id(id) => ("id = $1::uuid", parse_uuid(&id)?.to_string()),
+            Lookup::Username(username) => ("LOWER(username) = LOWER($1)", username),
+            Lookup::Phone(phone) => ("phone = $1", phone),
+            Lookup::Email(email) => ("email = $1", email),
+        };
+
+        let user: models::User =
+            sqlx::query_as(&format!("SELECT * FROM users WHERE {}", condition))
+                .bind(value)
+                .fetch_optional(&self.state.db)
+                .await
+                .map_err(AppError::from)
+                .map_err(to_status)?
+                .ok_or_else(|| to_status(AppError::UserNotFound))?;
+
+        Ok(Response::new(User {
+            id: user.id.to_string(),
+            username: user.username,
+            display_name: user.display_name,
+            phone: user.phone,
+            email: user.email,
+            avatar_url: user.avatar_url,
+            status: enum_name(&user.status),
+            last_seen_at: user.last_seen_at.map(timestamp),
+            created_at: Some(timestamp(user.created_at)),
+        }))
+    }
+
+    async fn send_message(
+        &self,
+        request: Request<SendMessageRequest>,
+    ) -> Result<Response<Message>, Status> {
+        let req = request.into_inner();
+        let message_type: models::MessageType = parse_enum(&req.r#type)?;
+
+        let messaging = MessagingService::new(
+            self.state.db.clone(),
+            self.state.redis.clone(),
+            (*self.state.config).clone(),
+        )
+        .with_search_index(self.state.search.clone())
+        .with_translator(self.state.translator.clone())
+        .with_bridge(self.state.matrix.clone());
+
+        let message = messaging
+            .send_message(
+                parse_uuid(&req.conversation_id)?,
+                parse_uuid(&req.sender_id)?,
+                message_type,
+                models::MessagePayload::from_content(message_type, req.content),
+                req.sticker_id.as_deref().map(parse_uuid).transpose()?,
+                req.reply_to_id.as_deref().map(parse_uuid).transpose()?,
+                false,
+            )
+            .await
+            .map_err(to_status)?;
+
+        Ok(Response::new(Message {
+            id: message.id.to_string(),
+            conversation_id: message.conversation_id.to_string(),
+            sender_id: message.sender_id.to_string(),
+            r#type: enum_name(&message.message_type),
+            content: message.content,
+            sticker_id: message.sticker_id.map(|id| id.to_string()),
+            reply_to_id: message.reply_to_id.map(|id| id.to_string()),
+            status: enum_name(&message.status),
+            created_at: Some(timestamp(message.created_at)),
+        }))
+    }
+
+    type SubscribeEventsStream = EventStream;
+
+    async fn subscribe_events(
+        &self,
+        request: Request<SubscribeEventsRequest>,
+    ) -> Result<Response<Self::SubscribeEventsStream>, Status> {
+        let user_ids = request
+            .into_inner()
+            .user_ids
+            .iter()
+            .map(|id| parse_uuid(id).map(|id| id.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let stream = self
+            .state
+            .redis
+            .subscribe_events(&user_ids)
+            .await
+            .map_err(to_status)?;
+
+        let events = stream.filter_map(|msg| async move {
+            let user_id = msg
+                .channel
+                .strip_prefix("messages:")
+                .unwrap_or_default()
+                .to_string();
+            let event: serde_json::Value = serde_json::from_str(&msg.payload).ok()?;
+
+            Some(Ok(Event {
+                user_id,
+                r#type: event["type"].as_str().unwrap_or_default().to_string(),
+                payload: event["payload"].to_string(),
+            }))
+        });
+
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+/// Map an error to the gRPC status matching its HTTP status
+fn to_status(error: AppError) -> Status {
+    let message = error.to_string();
+
+    match error.into_response().status().as_u16() {
+        400 | 422 => Status::invalid_argument(message),
+        401 => Status::unauthenticated(message),
+        403 => Status::permission_denied(message),
+        404 => Status::not_found(message),
+        409 => Status::already_exists(message),
+        410 | 428 => Status::failed_precondition(message),
+        429 => Status::resource_exhausted(message),
+        503 => Status::unavailable(message),
+        // Details were logged when the error became a response
+        _ => Status::internal("Internal server error"),
+    }
+}
+
+fn parse_uuid(value: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(value).map_err(|_| Status::invalid_argument(format!("Invalid ID: {}", value)))
+}
+
+/// Parse an enum from its lowercase API name
+fn parse_enum<T: DeserializeOwned>(value: &str) -> Result<T, Status> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| Status::invalid_argument(format!("Invalid value: {}", value)))
+}
+
+/// The lowercase API name of an enum
+fn enum_name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn timestamp(time: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use axum::{extract::Request, middleware, routing::get, Router};
+use tower_http::{
+    cors::{Any, CorsLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    services::ServeDir,
+    trace::TraceLayer,
+};
+
+pub mod api;
+pub mod config;
+pub mod error;
+pub mod graphql;
+pub mod grpc;
+pub mod logging;
+pub mod models;
+pub mod services;
+pub mod storage;
+#[cfg(feature = "test-harness")]
+pub mod testing;
+
+use api::load_shed::{load_shed_middleware, LoadShedder};
+use config::Config;
+use services::{
+    captcha::CaptchaProvider, email::EmailProvider, gifs::GifProvider, matrix::MatrixBridge,
+    push::PushProvider, search::SearchIndex, sticker_preview::LottieRenderer,
+    translation::TranslationProvider,
+};
+use storage::{
+    blob::BlobStorage,
+    local::{LocalStorage, LOCAL_FILES_ROUTE},
+    redis::RedisClient,
+};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub db: sqlx::PgPool,
+    pub redis: RedisClient,
+    pub storage: Arc<dyn BlobStorage>,
+    pub config: Arc<Config>,
+    pub ws_hub: Arc<api::websocket::WsHub>,
+    pub push: Arc<dyn PushProvider>,
+    pub email: Arc<dyn EmailProvider>,
+    pub captcha: Option<Arc<dyn CaptchaProvider>>,
+    pub search: Option<Arc<dyn SearchIndex>>,
+    pub translator: Option<Arc<dyn TranslationProvider>>,
+    pub gifs: Option<Arc<dyn GifProvider>>,
+    pub sticker_renderer: Option<Arc<LottieRenderer>>,
+    pub matrix: Option<Arc<MatrixBridge>>,
+    pub slow_log: Arc<logging::SlowLog>,
+}
+
+/// The full HTTP app: health check, versioned API and, for the local storage
+/// backend, its static files
+pub fn build_app(state: AppState) -> Router {
+    let mut app = Router::new()
+        .route("/health", get(health_check))
+        .route("/ready", get(api::handlers::health::readiness))
+        .route("/metrics", get(api::handlers::health::metrics))
+        .nest("/api/v1", api::router::create_router(state.clone()));
+
+    // The local storage backend serves its public files itself
+    if state.config.storage.provider == "local" {
+        app = app.nest_service(
+            LOCAL_FILES_ROUTE,
+            ServeDir::new(LocalStorage::public_dir(&state.config.storage.local_dir)),
+        );
+    }
+
+    app = app.layer(middleware::from_fn_with_state(
+        state.clone(),
+        api::middleware::timeout_middleware,
+    ));
+
+    // Outside the timeout, so requests it cancels still count as slow
+    if state.config.load_shed.max_concurrency > 0 {
+        let shedder = Arc::new(LoadShedder::new(state.config.load_shed.clone()));
+        app = app.layer(middleware::from_fn_with_state(shedder, load_shed_middleware));
+    }
+
+    app.layer(middleware::from_fn_with_state(
+        state.clone(),
+        api::middleware::slow_request_middleware,
+    ))
+    .layer(
+        CorsLayer::new()
+            .allow_origin(Any)
+            .allow_methods(Any)
+            .allow_headers(Any),
+    )
+    .layer(PropagateRequestIdLayer::x_request_id())
+    .layer(
+        TraceLayer::new_for_http()
+            .make_span_with({
+                let config = state.config.clone();
+                move |request: &Request| logging::request_span(request, &config)
+            })
+            .on_response(logging::on_response),
+    )
+    .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+    .with_state(state)
+}
+
+async fn health_check() -> &'static str {
+    "OK"
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request},
+    http::{header::USER_AGENT, Response},
+};
+use tower_http::request_id::RequestId;
+use tracing::{
+    callsite::Identifier,
+    field::{self, Field, Visit},
+    span, Event, Span, Subscriber,
+};
+use tracing_subscriber::{
+    fmt, layer::Context, layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+use crate::{
+    api::client_ip::resolve_client_ip,
+    config::{Config, LoggingConfig},
+    models::SlowRouteStats,
+};
+
+/// Target for high-volume events such as WebSocket pings or shed requests;
+/// only one in `LOG_SAMPLE_RATE` of them is logged, counted per call site
+pub const SAMPLED: &str = "ansible_talk_backend::sampled";
+
+/// Route that statements run outside any request are counted under
+const BACKGROUND: &str = "background";
+
+/// Install the global subscriber: text or JSON lines, filtered by `RUST_LOG`.
+/// Slow statements reported by sqlx are counted into `slow_log`.
+pub fn init(config: &LoggingConfig, slow_log: Arc<SlowLog>) {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| "ansible_talk_backend=debug,tower_http=debug,sqlx::query=warn".into());
+
+    // Span fields (request_id, user_id, route) are flattened into each line
+    let json = config.json.then(|| {
+        fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+    });
+    let text = (!config.json).then(fmt::layer);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(Sampler::new(config.sample_rate))
+        .with(SlowQueries { slow_log })
+        .with(json)
+        .with(text)
+        .init();
+}
+
+/// Span wrapping each HTTP request. `user_id` is filled in once the request
+/// is authenticated. With `PRIVACY_MINIMIZE_LOGS` the client IP and user
+/// agent are left out for routes that carry messages.
+pub fn request_span<B>(request: &Request<B>, config: &Config) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or_else(|| request.uri().path());
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default();
+
+    let identify = !(config.privacy.minimize_logs && is_message_route(route));
+    let client_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .filter(|_| identify)
+        .map(|ConnectInfo(peer)| {
+            resolve_client_ip(peer.ip(), request.headers(), &config.server.trusted_proxies)
+        });
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .filter(|_| identify)
+        .and_then(|ua| ua.to_str().ok());
+
+    tracing::info_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        route,
+        client_ip = client_ip.map(field::display),
+        user_agent,
+        user_id = field::Empty,
+    )
+}
+
+/// Routes that send, fetch or stream messages, where who connected from
+/// where is worth the most
+fn is_message_route(route: &str) -> bool {
+    route.contains("/messages")
+        || route.ends_with("/search")
+        || route.ends_with("/ws")
+        || route.ends_with("/graphql")
+}
+
+/// Logged once a response is ready, inside the request span
+pub fn on_response<B>(response: &Response<B>, latency: Duration, _span: &Span) {
+    tracing::info!(
+        status = response.status().as_u16(),
+        latency_ms = latency.as_millis() as u64,
+        "request finished"
+    );
+}
+
+/// Per-route counts of slow statements and requests on this instance
+#[derive(Default)]
+pub struct SlowLog {
+    routes: Mutex<HashMap<String, SlowRouteStats>>,
+}
+
+impl SlowLog {
+    fn update(&self, route: &str, apply: impl FnOnce(&mut SlowRouteStats)) {
+        let Ok(mut routes) = self.routes.lock() else {
+            return;
+        };
+        let stats = routes
+            .entry(route.to_string())
+            .or_insert_with(|| SlowRouteStats {
+                route: route.to_string(),
+                ..Default::default()
+            });
+        apply(stats);
+    }
+
+    pub fn record_query(&self, route: &str) {
+        self.update(route, |stats| stats.slow_queries += 1);
+    }
+
+    pub fn record_request(&self, route: &str, latency: Duration) {
+        self.update(route, |stats| {
+            stats.slow_requests += 1;
+            stats.max_latency_ms = stats.max_latency_ms.max(latency.as_millis() as u64);
+        });
+    }
+
+    /// All routes, the most slow statements and requests first
+    pub fn snapshot(&self) -> Vec<SlowRouteStats> {
+        let mut stats: Vec<SlowRouteStats> = self
+            .routes
+            .lock()
+            .map(|routes| routes.values().cloned().collect())
+            .unwrap_or_default();
+        stats.sort_by_key(|s| std::cmp::Reverse(s.slow_queries + s.slow_requests));
+        stats
+    }
+}
+
+/// The `route` field of a request span, kept for events inside it
+struct Route(String);
+
+impl Visit for Route {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "route" {
+            self.0 = value.to_string();
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+/// Counts sqlx's slow-statement events under the route of the request that
+/// ran them
+struct SlowQueries {
+    slow_log: Arc<SlowLog>,
+}
+
+impl<S> Layer<S> for SlowQueries
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != "request" {
+            return;
+        }
+        let mut route = Route(String::new());
+        attrs.record(&mut route);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(route);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        // Only the slow-statement event carries the threshold
+        if metadata.target() != "sqlx::query" || metadata.fields().field("slow_threshold").is_none()
+        {
+            return;
+        }
+
+        let route = ctx.event_span(event).and_then(|span| {
+            span.scope()
+                .find_map(|s| s.extensions().get::<Route>().map(|r| r.0.clone()))
+        });
+        self.slow_log
+            .record_query(route.as_deref().unwrap_or(BACKGROUND));
+    }
+}
+
+/// Drops all but one in `rate` events targeted at [`SAMPLED`]
+struct Sampler {
+    rate: u64,
+    seen: Mutex<HashMap<Identifier, u64>>,
+}
+
+impl Sampler {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for Sampler {
+    fn event_enabled(&self, event: &Event<'_>, _ctx: Context<'_, S>) -> bool {
+        let metadata = event.metadata();
+        if self.rate <= 1 || metadata.target() != SAMPLED {
+            return true;
+        }
+
+        let Ok(mut seen) = self.seen.lock() else {
+            return true;
+        };
+        let count = seen.entry(metadata.callsite()).or_insert(0);
+        *count += 1;
+        (*count - 1) % self.rate == 0
+    }
+}
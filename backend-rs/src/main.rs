@@ -1,69 +1,173 @@
-use std::sync::Arc;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use axum::{routing::get, Router};
-use sqlx::postgres::PgPoolOptions;
-use tower_http::{
-    cors::{Any, CorsLayer},
-    trace::TraceLayer,
+use log::LevelFilter;
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    ConnectOptions,
+};
+
+use ansible_talk_backend::{
+    api,
+    build_app,
+    config::Config,
+    error::AppError,
+    grpc,
+    logging,
+    services::{
+        audit::AuditLog,
+        auth::AuthService,
+        backups::BackupService,
+        captcha::build_captcha_provider,
+        crypto::CryptoService,
+        delivery::DeliveryService,
+        digests::DigestService,
+        email::build_email_provider,
+        events::{build_event_sink, EventLog},
+        gifs::build_gif_provider,
+        media::MediaService,
+        matrix::build_matrix_bridge,
+        messaging::MessagingService,
+        migrations::{MigrationService, MIGRATOR},
+        notifications::NotificationService,
+        profiles::ProfileService,
+        push::build_push_provider,
+        sealed_sender::SealedSenderService,
+        search::build_search_index,
+        seed::SeedService,
+        sticker_preview::build_lottie_renderer,
+        stickers::StickersService,
+        transcoding::{build_transcoder, TranscodeService},
+        translation::build_translation_provider,
+        view_once::ViewOnceService,
+    },
+    storage::{blob::build_blob_storage, event_bus::build_event_bus, redis::RedisClient},
+    AppState,
 };
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-mod api;
-mod config;
-mod error;
-mod models;
-mod services;
-mod storage;
-
-use config::Config;
-use storage::{minio::MinioClient, redis::RedisClient};
-
-#[derive(Clone)]
-pub struct AppState {
-    pub db: sqlx::PgPool,
-    pub redis: RedisClient,
-    pub minio: MinioClient,
-    pub config: Arc<Config>,
-    pub ws_hub: Arc<api::websocket::WsHub>,
-}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "ansible_talk_backend=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
     // Load configuration
     let config = Config::load();
+
+    // Initialize tracing
+    let slow_log = Arc::new(logging::SlowLog::default());
+    logging::init(&config.logging, slow_log.clone());
     tracing::info!("Starting server in {} mode", config.server.environment);
 
-    // Initialize database pool
+    // Initialize database pool; statements over the slow threshold are logged
+    let slow_query_level = if config.logging.slow_query.is_zero() {
+        LevelFilter::Off
+    } else {
+        LevelFilter::Warn
+    };
     let db = PgPoolOptions::new()
         .max_connections(config.database.max_connections)
-        .connect(&config.database_url())
+        .connect_with(
+            config
+                .database_url()
+                .parse::<PgConnectOptions>()?
+                .log_slow_statements(slow_query_level, config.logging.slow_query)
+                .options([(
+                    "statement_timeout",
+                    config.timeouts.statement.as_millis().to_string(),
+                )]),
+        )
         .await?;
     tracing::info!("Connected to PostgreSQL");
 
-    // Run migrations
-    sqlx::migrate!("./migrations").run(&db).await?;
-    tracing::info!("Database migrations completed");
+    // Check for drift before touching the schema
+    let migrations = MigrationService::new(db.clone()).status().await?;
+    if migrations.drift {
+        for (problem, records) in [
+            ("checksum mismatch", &migrations.checksum_mismatches),
+            ("not in this binary", &migrations.unknown),
+            ("failed", &migrations.failed),
+        ] {
+            for record in records {
+                tracing::error!(
+                    "Migration {} ({}): {}",
+                    record.version,
+                    record.description,
+                    problem
+                );
+            }
+        }
+        if config.database.refuse_migration_drift {
+            anyhow::bail!(
+                "Database schema has drifted from this binary's migrations; \
+                 set DB_REFUSE_MIGRATION_DRIFT=false to start anyway"
+            );
+        }
+        tracing::warn!("Starting despite schema drift; pending migrations are not applied");
+    } else if !config.database.auto_migrate {
+        if !migrations.pending.is_empty() {
+            tracing::warn!(
+                "{} pending migrations not applied (DB_AUTO_MIGRATE=false)",
+                migrations.pending.len()
+            );
+        }
+    } else {
+        // Run migrations, free of the statement timeout
+        let mut conn = db.acquire().await?;
+        sqlx::query("SET statement_timeout = 0").execute(&mut *conn).await?;
+        MIGRATOR.run(&mut *conn).await?;
+        sqlx::query("RESET statement_timeout").execute(&mut *conn).await?;
+        drop(conn);
+        tracing::info!("Database migrations completed");
+    }
 
     // Initialize Redis
     let redis = RedisClient::new(&config.redis_url()).await?;
     tracing::info!("Connected to Redis");
+    let redis = match build_event_bus(&config).await? {
+        Some(bus) => {
+            tracing::info!("Fanning out events over {}", bus.name());
+            redis.with_event_bus(bus)
+        }
+        None => redis,
+    };
+
+    // Initialize object storage
+    let storage = build_blob_storage(&config).await?;
+    storage.ensure_buckets().await?;
+    tracing::info!("Connected to {} object storage", storage.name());
+
+    // `--seed <fixture>` loads demo data and exits instead of serving
+    if let Some(fixture_path) = seed_fixture_arg()? {
+        let fixture = SeedService::load(&fixture_path)?;
+        let search = build_search_index(&db, &config.search);
+        if let Some(index) = &search {
+            index.prepare().await?;
+        }
 
-    // Initialize MinIO
-    let minio = MinioClient::new(&config.minio).await?;
-    minio.ensure_buckets().await?;
-    tracing::info!("Connected to MinIO");
+        let messaging = MessagingService::new(db.clone(), redis.clone(), config.clone())
+            .with_search_index(search);
+        let stickers = StickersService::new(db.clone(), storage.clone())
+            .with_renderer(build_lottie_renderer(&config.stickers));
+        let base_dir = fixture_path.parent().unwrap_or(Path::new("."));
+
+        let summary = SeedService::new(db.clone(), messaging, stickers)
+            .apply(&fixture, base_dir)
+            .await?;
+        tracing::info!(
+            "Seeded {} users, {} contacts, {} conversations, {} messages, {} sticker packs from {}",
+            summary.users,
+            summary.contacts,
+            summary.conversations,
+            summary.messages,
+            summary.sticker_packs,
+            fixture_path.display()
+        );
+        return Ok(());
+    }
 
     // Initialize WebSocket hub
-    let ws_hub = Arc::new(api::websocket::WsHub::new(redis.clone()));
+    let ws_hub = Arc::new(api::websocket::WsHub::new(redis.clone(), config.websocket.clone()));
 
     // Spawn hub runner
     let hub_clone = ws_hub.clone();
@@ -71,38 +175,351 @@ async fn main() -> anyhow::Result<()> {
         hub_clone.run().await;
     });
 
+    // Initialize notification providers
+    let push = build_push_provider(&config.notifications);
+    let email = build_email_provider(&config.notifications);
+    let captcha = build_captcha_provider(&config.captcha);
+
+    // Delete sessions that have been idle past the configured timeout
+    let auth_service = AuthService::new(db.clone(), redis.clone(), config.clone());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(600));
+        loop {
+            interval.tick().await;
+            match auth_service.expire_idle_sessions().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Expired {} idle sessions", count),
+                Err(e) => tracing::warn!("Failed to expire idle sessions: {}", e),
+            }
+        }
+    });
+
+    // Wipe view-once media that was never opened
+    let view_once = ViewOnceService::new(db.clone(), redis.clone(), config.view_once.ttl);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(600));
+        loop {
+            interval.tick().await;
+            match view_once.purge_expired().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Purged {} expired view-once messages", count),
+                Err(e) => tracing::warn!("Failed to purge view-once media: {}", e),
+            }
+        }
+    });
+
+    // Clear emoji statuses once they expire
+    let profiles = ProfileService::new(db.clone(), redis.clone());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = profiles.expire_emoji_statuses().await {
+                tracing::warn!("Failed to expire emoji statuses: {}", e);
+            }
+        }
+    });
+
+    // Send summaries for pushes held back during quiet hours
+    let notifications =
+        NotificationService::new(db.clone(), redis.clone(), push.clone(), email.clone());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = notifications.flush_quiet_hours().await {
+                tracing::warn!("Failed to flush quiet-hours notifications: {}", e);
+            }
+        }
+    });
+
+    // Expire old admin audit log entries
+    let audit_log = AuditLog::new(db.clone());
+    let audit_retention = config.admin.audit_retention;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match audit_log.purge_expired(audit_retention).await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Deleted {} expired audit log entries", count),
+                Err(e) => tracing::warn!("Failed to delete expired audit log entries: {}", e),
+            }
+        }
+    });
+
+    // Email unread digests to opted-in users who have been away
+    let digests = DigestService::new(db.clone(), email.clone(), Arc::new(config.clone()));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match digests.send_due().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Sent {} email digests", count),
+                Err(e) => tracing::warn!("Failed to send email digests: {}", e),
+            }
+        }
+    });
+
+    // Relay analytics events to the external sink, and expire old ones
+    let event_log = Arc::new(EventLog::new(db.clone(), &config.analytics));
+    if let Some(sink) = build_event_sink(&config.analytics) {
+        let event_log = event_log.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(10));
+            loop {
+                interval.tick().await;
+                match event_log.relay(sink.as_ref()).await {
+                    Ok(0) => {}
+                    Ok(count) => tracing::debug!("Relayed {} events to {}", count, sink.name()),
+                    Err(e) => tracing::warn!("Failed to relay events to {}: {}", sink.name(), e),
+                }
+            }
+        });
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match event_log.purge_expired().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Deleted {} expired analytics events", count),
+                Err(e) => tracing::warn!("Failed to delete expired analytics events: {}", e),
+            }
+        }
+    });
+
+    // Scheduled backups; the advisory lock keeps instances from overlapping
+    if !config.backup.interval.is_zero() && config.storage.backup_bucket.is_none() {
+        tracing::warn!("BACKUP_INTERVAL_HOURS is set but BACKUP_BUCKET is not; skipping backups");
+    } else if !config.backup.interval.is_zero() {
+        let backups = BackupService::new(
+            db.clone(),
+            storage.clone(),
+            config.storage.backup_bucket.clone(),
+            config.backup.retention,
+        );
+        let every = config.backup.interval;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            // The first tick is immediate; wait a full interval after startup
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match backups.create().await {
+                    Ok(manifest) => tracing::info!("Backup {} complete", manifest.id),
+                    Err(AppError::BackupInProgress) => continue,
+                    Err(e) => {
+                        tracing::warn!("Scheduled backup failed: {}", e);
+                        continue;
+                    }
+                }
+                match backups.prune().await {
+                    Ok(0) => {}
+                    Ok(count) => tracing::info!("Deleted {} old backups", count),
+                    Err(e) => tracing::warn!("Failed to prune old backups: {}", e),
+                }
+            }
+        });
+    }
+
+    // Redeliver unacknowledged messages, falling back to push
+    let delivery_service = || {
+        DeliveryService::new(
+            db.clone(),
+            redis.clone(),
+            config.clone(),
+            NotificationService::new(db.clone(), redis.clone(), push.clone(), email.clone()),
+        )
+    };
+    let delivery = delivery_service();
+    let retry_interval = config.delivery.retry_base.max(Duration::from_secs(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(retry_interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = delivery.process_due().await {
+                tracing::warn!("Failed to process message deliveries: {}", e);
+            }
+        }
+    });
+
+    // Drop delivery records past their retention
+    let delivery = delivery_service();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(600));
+        loop {
+            interval.tick().await;
+            match delivery.purge_expired().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Purged {} old delivery records", count),
+                Err(e) => tracing::warn!("Failed to purge delivery records: {}", e),
+            }
+        }
+    });
+
+    // Send typing updates that were held back, and typers lapsing
+    let typing = MessagingService::new(db.clone(), redis.clone(), config.clone());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            if let Err(e) = typing.flush_typing().await {
+                tracing::warn!("Failed to send typing updates: {}", e);
+            }
+        }
+    });
+
+    // Drop sealed envelopes their device never fetched
+    let sealed_sender = SealedSenderService::new(db.clone(), redis.clone(), config.clone());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match sealed_sender.purge_expired().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Purged {} unfetched sealed envelopes", count),
+                Err(e) => tracing::warn!("Failed to purge sealed envelopes: {}", e),
+            }
+        }
+    });
+
+    // Keep only each device's recent signed pre-keys
+    let crypto = CryptoService::new(db.clone());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match crypto.purge_old_signed_pre_keys().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Purged {} old signed pre-keys", count),
+                Err(e) => tracing::warn!("Failed to purge old signed pre-keys: {}", e),
+            }
+        }
+    });
+
+    // Delete attachments whose messages are gone
+    let media = MediaService::new(db.clone(), storage.clone(), &config);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match media.purge_orphaned().await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!("Purged {} orphaned attachments", count),
+                Err(e) => tracing::warn!("Failed to purge orphaned attachments: {}", e),
+            }
+        }
+    });
+
+    // Transcode queued GIF and video attachments
+    if let Some(transcoder) = build_transcoder(&config.transcode) {
+        tracing::info!("Transcoding attachments with {}", transcoder.name());
+        let transcode = TranscodeService::new(
+            db.clone(),
+            redis.clone(),
+            storage.clone(),
+            transcoder,
+            config.clone(),
+        );
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(5));
+            loop {
+                interval.tick().await;
+                match transcode.run_pending().await {
+                    Ok(0) => {}
+                    Ok(count) => tracing::info!("Transcoded {} attachments", count),
+                    Err(e) => tracing::warn!("Failed to transcode attachments: {}", e),
+                }
+            }
+        });
+    }
+
+    // Recompute trending sticker packs for the catalog
+    let stickers = StickersService::new(db.clone(), storage.clone());
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            if let Err(e) = stickers.refresh_trending().await {
+                tracing::warn!("Failed to refresh trending sticker packs: {}", e);
+            }
+        }
+    });
+
+    // Initialize message search index
+    let search = build_search_index(&db, &config.search);
+    if let Some(index) = &search {
+        if let Err(e) = index.prepare().await {
+            tracing::warn!("Failed to prepare search index: {}", e);
+        }
+    }
+
+    // Initialize message translation
+    let translator = build_translation_provider(&config.translation);
+
+    // Initialize GIF search proxy
+    let gifs = build_gif_provider(&config.gifs);
+
+    // Initialize Lottie sticker preview renderer
+    let sticker_renderer = build_lottie_renderer(&config.stickers);
+
+    // Initialize Matrix bridge
+    let matrix = build_matrix_bridge(&db, &config.matrix);
+
     // Create app state
     let state = AppState {
         db,
         redis,
-        minio,
+        storage,
         config: Arc::new(config.clone()),
         ws_hub,
+        push,
+        email,
+        captcha,
+        search,
+        translator,
+        gifs,
+        sticker_renderer,
+        matrix,
+        slow_log,
     };
 
+    // Start the internal gRPC API, if configured
+    tokio::spawn(grpc::serve(state.clone(), config.grpc.clone()));
+
     // Build router
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .nest("/api/v1", api::router::create_router(state.clone()))
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods(Any)
-                .allow_headers(Any),
-        )
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
+    let app = build_app(state);
 
     // Start server
     let addr = format!("{}:{}", config.server.host, config.server.port);
     let listener = tokio::net::TcpListener::bind(&addr).await?;
     tracing::info!("Server listening on {}", addr);
 
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
 
-async fn health_check() -> &'static str {
-    "OK"
+/// The fixture path passed as `--seed <path>` or `--seed=<path>`, if any
+fn seed_fixture_arg() -> anyhow::Result<Option<PathBuf>> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            let path = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("--seed requires a fixture path"))?;
+            return Ok(Some(PathBuf::from(path)));
+        }
+        if let Some(path) = arg.strip_prefix("--seed=") {
+            return Ok(Some(PathBuf::from(path)));
+        }
+    }
+
+    Ok(None)
 }
@@ -0,0 +1,228 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminStats {
+    pub generated_at: DateTime<Utc>,
+    pub active_users: ActiveUserStats,
+    pub users: UserStats,
+    pub messages_per_day: Vec<DailyCount>,
+    pub sticker_downloads: StickerDownloadStats,
+    pub storage: Vec<BucketUsage>,
+    pub websocket: WebSocketStats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveUserStats {
+    pub daily: i64,
+    pub weekly: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserStats {
+    pub total: i64,
+    pub registrations_per_day: Vec<DailyCount>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StickerDownloadStats {
+    pub total: i64,
+    pub per_day: Vec<DailyCount>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DailyCount {
+    pub day: NaiveDate,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BucketUsage {
+    pub bucket: String,
+    pub objects: i64,
+    pub bytes: i64,
+}
+
+/// Connection counts for this server instance; never cached
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WebSocketStats {
+    pub connections: usize,
+    pub connected_users: usize,
+    /// Events waiting in outbound queues
+    pub queued_events: usize,
+    /// Connections whose queue is over half full
+    pub lagging_clients: usize,
+}
+
+/// Slow statements and requests this instance has seen for one route since it
+/// started. Statements run outside a request are counted under `background`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SlowRouteStats {
+    pub route: String,
+    pub slow_requests: u64,
+    pub slow_queries: u64,
+    pub max_latency_ms: u64,
+}
+
+/// Delivery timings of sampled messages over a recent window
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeliveryLatencyReport {
+    pub since: DateTime<Utc>,
+    pub samples: i64,
+    /// Samples at least one recipient device acknowledged
+    pub acked: i64,
+    /// From storing the message to handing it to recipients' connections
+    pub fanout: LatencyPercentiles,
+    /// From storing the message to the first device acknowledgement
+    pub first_ack: LatencyPercentiles,
+}
+
+/// Milliseconds; `None` without samples
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencyPercentiles {
+    pub p50_ms: Option<f64>,
+    pub p90_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+}
+
+/// One migration as known to the binary, the database or both
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationRecord {
+    pub version: i64,
+    pub description: String,
+    /// When it was applied; empty for pending migrations
+    pub installed_on: Option<DateTime<Utc>>,
+    pub execution_ms: Option<i64>,
+}
+
+/// Migrations shipped in this binary compared with those recorded in the
+/// database. Mismatched checksums, applied migrations the binary does not
+/// know and failed ones count as drift.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MigrationStatus {
+    pub drift: bool,
+    pub applied: Vec<MigrationRecord>,
+    pub pending: Vec<MigrationRecord>,
+    /// Applied migrations whose file has changed since
+    pub checksum_mismatches: Vec<MigrationRecord>,
+    /// Applied by a newer binary, or from a file since removed
+    pub unknown: Vec<MigrationRecord>,
+    /// Recorded as failed part-way
+    pub failed: Vec<MigrationRecord>,
+}
+
+/// A logical backup: one gzipped CSV per table, written before the manifest
+/// so only complete backups are listed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    /// Latest migration applied when the backup was taken; a restore needs
+    /// the database at the same version
+    pub schema_version: i64,
+    pub tables: Vec<BackupTable>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupTable {
+    pub name: String,
+    /// Column order of the CSV
+    pub columns: Vec<String>,
+    pub rows: i64,
+    /// Compressed size
+    pub bytes: u64,
+    /// Objects the compressed CSV is split across
+    #[serde(default = "default_parts")]
+    pub parts: u32,
+}
+
+fn default_parts() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VelocityKind {
+    Ip,
+    /// A client-supplied device fingerprint
+    Device,
+}
+
+impl VelocityKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VelocityKind::Ip => "ip",
+            VelocityKind::Device => "device",
+        }
+    }
+}
+
+/// Registrations counted against one IP or device fingerprint in the current
+/// hourly window
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VelocityCounter {
+    pub kind: VelocityKind,
+    pub value: String,
+    pub count: i64,
+    /// Seconds until the window resets
+    pub expires_in: i64,
+}
+
+/// One connection's outbound queue on this instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebSocketClientStats {
+    /// `user:device`, or `user:graphql:id` for a GraphQL subscription
+    pub client_id: String,
+    pub connection_id: u64,
+    pub queued: usize,
+    /// Most events ever waiting at once
+    pub max_queued: usize,
+    /// Milliseconds the oldest waiting event has been queued
+    pub lag_ms: u64,
+    pub sent: u64,
+    /// Typing, presence and other events dropped while the queue was full
+    pub dropped: u64,
+    /// Messages left to redelivery or push while the queue was full
+    pub overflowed: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MaintenanceState {
+    pub enabled: bool,
+    pub message: Option<String>,
+    /// Seconds clients should wait before retrying writes
+    pub retry_after: u64,
+    pub started_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Announcement {
+    pub id: Uuid,
+    pub title: Option<String>,
+    pub message: String,
+    pub level: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An admin-route request that could change something, recorded by
+/// `admin_audit_middleware`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AdminAuditEntry {
+    pub id: i64,
+    pub actor_id: Option<Uuid>,
+    pub method: String,
+    /// The route template, e.g. `/api/v1/admin/invites/:id`
+    pub route: String,
+    pub path: String,
+    /// Query and JSON body, with secrets redacted and long values cut short
+    pub payload: serde_json::Value,
+    pub status: i16,
+    /// The error message of a failed request
+    pub error: Option<String>,
+    pub ip_address: Option<String>,
+    pub duration_ms: i32,
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,17 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A domain event as exported for analytics. Carries no message content,
+/// contact details or IP addresses; user and conversation IDs are replaced
+/// by keyed pseudonyms.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AnalyticsEvent {
+    pub id: Uuid,
+    /// `user_registered`, `message_sent` or `pack_downloaded`
+    pub event_type: String,
+    pub actor: Option<String>,
+    pub properties: serde_json::Value,
+    pub occurred_at: DateTime<Utc>,
+}
@@ -0,0 +1,68 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use super::{MessageStatus, MessageType};
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct BroadcastList {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastListWithRecipients {
+    #[serde(flatten)]
+    pub list: BroadcastList,
+    pub recipient_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Broadcast {
+    pub id: Uuid,
+    pub list_id: Uuid,
+    pub sender_id: Uuid,
+    #[serde(rename = "type")]
+    #[sqlx(rename = "type")]
+    pub message_type: MessageType,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Delivery counts across a broadcast's individual messages
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow)]
+pub struct BroadcastDeliverySummary {
+    pub total: i64,
+    pub sent: i64,
+    pub delivered: i64,
+    pub read: i64,
+    /// Recipients who had blocked the sender when it went out
+    pub skipped: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastWithSummary {
+    #[serde(flatten)]
+    pub broadcast: Broadcast,
+    pub summary: BroadcastDeliverySummary,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct BroadcastRecipientStatus {
+    pub recipient_id: Uuid,
+    pub conversation_id: Option<Uuid>,
+    pub message_id: Option<Uuid>,
+    /// None when the recipient was skipped
+    pub status: Option<MessageStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastDetails {
+    #[serde(flatten)]
+    pub broadcast: Broadcast,
+    pub summary: BroadcastDeliverySummary,
+    pub recipients: Vec<BroadcastRecipientStatus>,
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// The oldest app version a platform supports, set by admins
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ClientVersionRequirement {
+    /// As sent in `X-Client-Platform`, lowercase
+    pub platform: String,
+    pub min_version: String,
+    /// Offered as an optional upgrade to clients older than this
+    pub latest_version: Option<String>,
+    /// App store or download page
+    pub upgrade_url: Option<String>,
+    /// Shown to users asked to upgrade
+    pub message: Option<String>,
+    pub updated_by: Option<Uuid>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Devices active in the last 30 days on one app version
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ClientVersionAdoption {
+    pub platform: String,
+    pub app_version: String,
+    pub devices: i64,
+}
+
+/// Minimums and how many devices each would cut off
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientVersionReport {
+    pub requirements: Vec<ClientVersionRequirement>,
+    pub adoption: Vec<ClientVersionAdoption>,
+}
+
+/// Where the calling client stands, from its version headers
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientUpgrade {
+    /// Older than the platform's minimum; other requests get 426
+    pub required: bool,
+    /// Older than the platform's latest version
+    pub available: bool,
+    pub min_version: String,
+    pub latest_version: Option<String>,
+    pub upgrade_url: Option<String>,
+    pub message: Option<String>,
+}
+
+/// What a client needs before signing in: upgrade state, per-platform
+/// upgrade links and which optional server features are on
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientConfig {
+    /// `None` when the client sent no version headers or its platform has
+    /// no minimum
+    pub upgrade: Option<ClientUpgrade>,
+    pub upgrade_urls: BTreeMap<String, String>,
+    pub features: BTreeMap<&'static str, bool>,
+}
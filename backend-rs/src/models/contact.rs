@@ -23,3 +23,71 @@ pub struct ContactWithUser {
     pub contact: Contact,
     pub user: Option<User>,
 }
+
+/// One person from an imported address book, phones and emails normalized
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportedContact {
+    pub name: Option<String>,
+    pub phones: Vec<String>,
+    pub emails: Vec<String>,
+}
+
+/// An imported entry that belongs to a registered user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportMatch {
+    /// The name in the address book, kept as the contact's nickname
+    pub name: Option<String>,
+    /// The phone or email that matched
+    pub identifier: String,
+    pub user: User,
+    /// False when the user was already a contact
+    pub added: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContactImportReport {
+    pub matched: Vec<ImportMatch>,
+    /// Entries with a phone or email no account uses
+    pub unmatched: Vec<ImportedContact>,
+    /// Entries with no usable phone or email
+    pub skipped: usize,
+}
+
+/// One change in a bulk contacts request, applied in order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum ContactOperation {
+    Add {
+        contact_id: Uuid,
+        nickname: Option<String>,
+    },
+    Update {
+        contact_id: Uuid,
+        nickname: Option<String>,
+        is_favorite: Option<bool>,
+    },
+    Delete {
+        contact_id: Uuid,
+    },
+}
+
+impl ContactOperation {
+    pub fn contact_id(&self) -> Uuid {
+        match self {
+            ContactOperation::Add { contact_id, .. }
+            | ContactOperation::Update { contact_id, .. }
+            | ContactOperation::Delete { contact_id } => *contact_id,
+        }
+    }
+}
+
+/// The outcome of one bulk operation, at the same position as in the request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContactOperationResult {
+    pub contact_id: Uuid,
+    pub ok: bool,
+    /// The contact as it is now; null after a delete or a failure
+    pub contact: Option<ContactWithUser>,
+    /// Why the operation was not applied
+    pub error: Option<String>,
+}
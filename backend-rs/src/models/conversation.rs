@@ -7,13 +7,29 @@ use uuid::Uuid;
 pub struct Conversation {
     pub id: Uuid,
     #[serde(rename = "type")]
+    #[sqlx(rename = "type")]
     pub conversation_type: ConversationType,
     pub name: Option<String>,
     pub avatar_url: Option<String>,
+    pub description: Option<String>,
+    pub rules: Option<String>,
     pub created_by: Uuid,
     pub last_message_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Set on an organization team's group, whose members follow the directory
+    pub organization_team_id: Option<Uuid>,
+    /// Fixed at creation; tells clients which pipeline to send through
+    pub encryption: ConversationEncryption,
+    /// Who may do what in a group; ignored for direct and saved conversations
+    #[sqlx(json)]
+    pub permissions: GroupPermissions,
+    /// Only owners and admins may post; members still read and react
+    pub announcement_only: bool,
+    /// Current members, kept up to date as people join and leave
+    pub participant_count: i32,
+    /// Current owners and admins
+    pub admin_count: i32,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
@@ -22,6 +38,19 @@ pub struct Conversation {
 pub enum ConversationType {
     Direct,
     Group,
+    /// A user's notes-to-self conversation, with the user as sole participant
+    Saved,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "conversation_encryption", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ConversationEncryption {
+    /// Text messages must be Signal ciphertext
+    Signal,
+    /// Content is stored as sent, so it can be searched, translated and bridged
+    #[default]
+    None,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -33,6 +62,26 @@ pub struct Participant {
     pub joined_at: DateTime<Utc>,
     pub left_at: Option<DateTime<Utc>>,
     pub muted_until: Option<DateTime<Utc>>,
+    /// A direct conversation started by a non-contact, awaiting acceptance
+    pub is_request: bool,
+    /// Marked unread by this participant; private to them, so surfaced on
+    /// `ConversationWithDetails` instead
+    #[serde(skip)]
+    pub marked_unread: bool,
+    /// Messages sent before this were cleared from this participant's copy
+    #[serde(skip)]
+    pub cleared_before: Option<DateTime<Utc>>,
+}
+
+/// Which of the user's conversations to list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConversationFilter {
+    /// Everything except pending message requests
+    #[default]
+    Inbox,
+    /// Pending message requests from non-contacts
+    Requests,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
@@ -44,13 +93,102 @@ pub enum ParticipantRole {
     Member,
 }
 
+impl ParticipantRole {
+    fn rank(self) -> u8 {
+        match self {
+            ParticipantRole::Owner => 2,
+            ParticipantRole::Admin => 1,
+            ParticipantRole::Member => 0,
+        }
+    }
+}
+
+/// Something a group restricts by role
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupAction {
+    SendMessages,
+    /// Images, videos, audio and files
+    SendMedia,
+    SendStickers,
+    AddMembers,
+    /// Name, description, rules and avatar
+    ChangeInfo,
+}
+
+/// The lowest role allowed each action in a group. Only overrides are
+/// stored, so groups follow the defaults for anything never changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GroupPermissions {
+    pub send_messages: ParticipantRole,
+    pub send_media: ParticipantRole,
+    pub send_stickers: ParticipantRole,
+    pub add_members: ParticipantRole,
+    pub change_info: ParticipantRole,
+}
+
+impl Default for GroupPermissions {
+    fn default() -> Self {
+        Self {
+            send_messages: ParticipantRole::Member,
+            send_media: ParticipantRole::Member,
+            send_stickers: ParticipantRole::Member,
+            add_members: ParticipantRole::Admin,
+            change_info: ParticipantRole::Admin,
+        }
+    }
+}
+
+impl GroupPermissions {
+    pub fn min_role(&self, action: GroupAction) -> ParticipantRole {
+        match action {
+            GroupAction::SendMessages => self.send_messages,
+            GroupAction::SendMedia => self.send_media,
+            GroupAction::SendStickers => self.send_stickers,
+            GroupAction::AddMembers => self.add_members,
+            GroupAction::ChangeInfo => self.change_info,
+        }
+    }
+
+    pub fn allows(&self, role: ParticipantRole, action: GroupAction) -> bool {
+        role.rank() >= self.min_role(action).rank()
+    }
+}
+
+/// Permissions to change; `None` keeps the current setting
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GroupPermissionsUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_messages: Option<ParticipantRole>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_media: Option<ParticipantRole>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_stickers: Option<ParticipantRole>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add_members: Option<ParticipantRole>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change_info: Option<ParticipantRole>,
+}
+
+/// How many participants `ConversationWithDetails` carries inline
+pub const PARTICIPANT_PREVIEW_LIMIT: i64 = 20;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConversationWithDetails {
     #[serde(flatten)]
     pub conversation: Conversation,
+    /// Up to `PARTICIPANT_PREVIEW_LIMIT` participants, owners and admins
+    /// first; page through `/members` for the rest
     pub participants: Vec<ParticipantWithUser>,
+    pub member_count: i64,
     pub unread_count: i64,
+    /// The viewer marked the conversation unread to come back to it; cleared
+    /// once they read a message in it
+    pub marked_unread: bool,
     pub last_message: Option<super::Message>,
+    /// A direct conversation whose other participant deactivated their
+    /// account; messages reach them once they log in again
+    pub account_deactivated: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,4 +196,33 @@ pub struct ParticipantWithUser {
     #[serde(flatten)]
     pub participant: Participant,
     pub user: Option<super::User>,
+    /// The participant deactivated their account
+    pub deactivated: bool,
+}
+
+/// An entry in a group's administrative history. Outlives the system
+/// message posted for the same change.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ConversationEvent {
+    pub id: Uuid,
+    pub conversation_id: Uuid,
+    /// `None` once the actor's account is deleted
+    pub actor_id: Option<Uuid>,
+    /// A `SystemEvent` key such as `member_removed`
+    pub key: String,
+    pub params: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A participant's own appearance settings for a conversation, synced
+/// across their devices
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow)]
+pub struct ConversationAppearance {
+    pub conversation_id: Uuid,
+    pub wallpaper_url: Option<String>,
+    /// Hex color such as `#3A7BD5`
+    pub theme_color: Option<String>,
+    /// Emoji sent when double-tapping a message
+    pub reaction_emoji: Option<String>,
+    pub updated_at: Option<DateTime<Utc>>,
 }
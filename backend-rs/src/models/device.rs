@@ -10,7 +10,35 @@ pub struct Device {
     pub device_id: i32,
     pub name: String,
     pub platform: String,
+    /// From the `X-Client-Version` header of its latest request
+    #[sqlx(default)]
+    pub app_version: Option<String>,
     pub push_token: Option<String>,
+    /// Confirmed by the primary device
+    pub verified: bool,
     pub last_active_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
 }
+
+/// The primary device, the one the account was registered on
+pub const PRIMARY_DEVICE_ID: i32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceSettings {
+    /// Only hand out key bundles for verified devices
+    pub verified_devices_only: bool,
+}
+
+/// One of the user's devices and its WebSocket connection, if it has one
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceConnection {
+    pub device_id: i32,
+    pub name: String,
+    pub platform: String,
+    /// Holds a WebSocket on an instance that is still alive
+    pub connected: bool,
+    /// The instance last holding the connection; may have gone away
+    pub instance_id: Option<String>,
+    pub connected_at: Option<DateTime<Utc>>,
+    pub last_ping_at: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,17 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// An image used as `:name:` in messages and as a conversation's reaction emoji
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct CustomEmoji {
+    pub id: Uuid,
+    /// The group it belongs to; `None` for emoji available everywhere
+    pub conversation_id: Option<Uuid>,
+    /// Lowercase letters, digits, `-` and `_`, without the colons
+    pub name: String,
+    pub image_url: String,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
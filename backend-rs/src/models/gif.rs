@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// A GIF search hit normalized across providers, ready to send as an image message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GifResult {
+    pub id: String,
+    pub title: String,
+    /// Full-size animated GIF
+    pub url: String,
+    /// Smaller rendition for pickers
+    pub preview_url: String,
+    pub width: u32,
+    pub height: u32,
+    pub content_type: String,
+    /// tenor or giphy, for attribution
+    pub provider: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GifSearchResponse {
+    pub results: Vec<GifResult>,
+    /// Opaque cursor for the next page, if there is one
+    pub next: Option<String>,
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+use super::WebSocketStats;
+
+/// `ready` and `degraded` instances keep receiving traffic; `unavailable`
+/// ones fail the readiness probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ready,
+    Degraded,
+    Unavailable,
+}
+
+/// State of this instance and the services it depends on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    /// Why the instance is degraded or unavailable
+    pub reasons: Vec<String>,
+    pub database: DatabasePoolStats,
+    pub redis: RedisHealth,
+    pub storage: StorageCallStats,
+    pub websocket: WebSocketStats,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DatabasePoolStats {
+    pub up: bool,
+    pub in_use: u32,
+    pub idle: u32,
+    pub max: u32,
+    /// Time the probe waited for a pooled connection
+    pub acquire_ms: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RedisHealth {
+    pub up: bool,
+    pub ping_ms: f64,
+}
+
+/// Calls to the object storage backend since startup
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StorageCallStats {
+    pub backend: String,
+    pub requests: u64,
+    pub errors: u64,
+    /// Share of the most recent calls that failed
+    pub recent_error_rate: f64,
+}
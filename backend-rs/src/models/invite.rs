@@ -0,0 +1,39 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A code that lets people register, and credits them to its creator
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Invite {
+    pub id: Uuid,
+    pub code: String,
+    pub created_by: Uuid,
+    /// One for invites users create; admins can make reusable ones
+    pub max_uses: i32,
+    pub uses: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Someone who registered with one of the user's invites
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Referral {
+    pub user_id: Uuid,
+    pub username: String,
+    pub display_name: String,
+    pub avatar_url: Option<String>,
+    pub invite_id: Option<Uuid>,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// A user's invites and what is left of their quota
+#[derive(Debug, Clone, Serialize)]
+pub struct InviteSummary {
+    pub invites: Vec<Invite>,
+    /// `None` for admins, who have no quota
+    pub quota: Option<i32>,
+    pub remaining: Option<i32>,
+    pub referrals: i64,
+}
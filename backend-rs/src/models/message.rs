@@ -9,20 +9,299 @@ pub struct Message {
     pub conversation_id: Uuid,
     pub sender_id: Uuid,
     #[serde(rename = "type")]
+    #[sqlx(rename = "type")]
     pub message_type: MessageType,
+    /// Body text, ciphertext, inline media or system event, as stored;
+    /// clients see it as `payload`
+    #[serde(skip)]
     pub content: Vec<u8>,
+    /// Plaintext caption of a media message
+    #[serde(skip)]
+    pub caption: Option<String>,
+    /// `content` is ciphertext the server cannot read
+    #[serde(skip)]
+    pub encrypted: bool,
+    /// Filled from the columns above by `decode`
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub payload: MessagePayload,
     pub sticker_id: Option<Uuid>,
     pub reply_to_id: Option<Uuid>,
     pub status: MessageStatus,
     pub edited_at: Option<DateTime<Utc>>,
     pub deleted_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
+    /// Media opened through `/messages/:id/media`, once per recipient;
+    /// `content` is always empty elsewhere
+    pub view_once: bool,
+    /// Uploaded media the message carries, linked through `attachment`; sent
+    /// to clients in `payload`
+    #[serde(skip)]
+    pub attachment_id: Option<Uuid>,
+    /// Short-lived download link, signed whenever the message is returned
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<AttachmentUrl>,
+    /// `content` is stored padded; clients only ever see it unpadded
+    #[sqlx(default)]
+    #[serde(skip)]
+    pub padded: bool,
+    /// The sender's commitment to the plaintext of an encrypted message;
+    /// sent to clients in `payload`
+    #[sqlx(default)]
+    #[serde(skip)]
+    pub franking_tag: Option<Vec<u8>>,
+    /// The server's signature over the commitment, message and sender
+    #[sqlx(default)]
+    #[serde(skip)]
+    pub franking_signature: Option<Vec<u8>>,
+}
+
+/// Ends the content of a padded message; only zeros follow it
+pub const PADDING_MARKER: u8 = 0x80;
+
+/// Version of the `MessagePayload` format this server reads and writes
+pub const PAYLOAD_VERSION: i16 = 1;
+
+/// What a message carries, as clients send and receive it. Binary fields
+/// are base64. Text messages have a `body` or, in end-to-end encrypted
+/// conversations, a `ciphertext`; media messages an `attachment_id` or
+/// inline `media` (`ciphertext` when encrypted) and an optional `caption`;
+/// system messages an `event`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessagePayload {
+    /// Format version; payloads newer than `PAYLOAD_VERSION` are refused
+    pub v: i16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    /// Media uploaded through `POST /attachments`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attachment_id: Option<Uuid>,
+    /// Signal message, as produced by the sender's session
+    #[serde(skip_serializing_if = "Option::is_none", with = "base64_bytes")]
+    pub ciphertext: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none", with = "base64_bytes")]
+    pub media: Option<Vec<u8>>,
+    /// With `ciphertext`: HMAC-SHA256 of the plaintext under a franking key
+    /// sent inside the ciphertext, so recipients can report the message
+    #[serde(skip_serializing_if = "Option::is_none", with = "base64_bytes")]
+    pub franking_tag: Option<Vec<u8>>,
+    /// Set by the server on messages with a `franking_tag`
+    #[serde(skip_serializing_if = "Option::is_none", with = "base64_bytes")]
+    pub franking_signature: Option<Vec<u8>>,
+    /// `{key, params, actor_id}`; see `SystemEvent`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<serde_json::Value>,
+}
+
+impl Default for MessagePayload {
+    fn default() -> Self {
+        Self {
+            v: PAYLOAD_VERSION,
+            body: None,
+            caption: None,
+            attachment_id: None,
+            ciphertext: None,
+            media: None,
+            franking_tag: None,
+            franking_signature: None,
+            event: None,
+        }
+    }
+}
+
+impl MessagePayload {
+    pub fn text(body: impl Into<String>) -> Self {
+        Self {
+            body: Some(body.into()),
+            ..Default::default()
+        }
+    }
+
+    /// The payload raw content stands for: UTF-8 text is a body, anything
+    /// else ciphertext. For clients that only send bytes, such as gRPC.
+    pub fn from_content(message_type: MessageType, content: Vec<u8>) -> Self {
+        let mut payload = Self::default();
+        if content.is_empty() {
+            return payload;
+        }
+
+        match message_type {
+            MessageType::Text => match String::from_utf8(content) {
+                Ok(body) => payload.body = Some(body),
+                Err(e) => payload.ciphertext = Some(e.into_bytes()),
+            },
+            MessageType::System => payload.event = serde_json::from_slice(&content).ok(),
+            MessageType::Sticker => payload.ciphertext = Some(content),
+            _ => payload.media = Some(content),
+        }
+        payload
+    }
+}
+
+/// Base64 strings for optional binary payload fields
+pub(crate) mod base64_bytes {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => s.serialize_str(&STANDARD.encode(bytes)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|encoded| STANDARD.decode(encoded).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+impl Message {
+    /// Strip view-once media so it is only ever served by the one-time fetch
+    pub fn redact_view_once(mut self) -> Self {
+        if self.view_once {
+            self.content = Vec::new();
+            self.payload.media = None;
+            self.payload.ciphertext = None;
+        }
+        self
+    }
+
+    /// Drop the padding and fill `payload` from what was stored
+    pub fn decode(self) -> Self {
+        let mut message = self.unpad();
+        let content = message.content.clone();
+        let mut payload = if message.encrypted {
+            MessagePayload {
+                ciphertext: (!content.is_empty()).then_some(content),
+                ..Default::default()
+            }
+        } else {
+            MessagePayload::from_content(message.message_type, content)
+        };
+        payload.caption = message.caption.clone();
+        payload.attachment_id = message.attachment_id;
+        payload.franking_tag = message.franking_tag.clone();
+        payload.franking_signature = message.franking_signature.clone();
+        message.payload = payload;
+        message
+    }
+
+    /// Drop the padding content was stored with
+    pub fn unpad(mut self) -> Self {
+        if self.padded {
+            let end = self.content.iter().rposition(|&b| b != 0);
+            if let Some(end) = end.filter(|&end| self.content[end] == PADDING_MARKER) {
+                self.content.truncate(end);
+            }
+            self.padded = false;
+        }
+        self
+    }
+}
+
+/// Media uploaded ahead of the message that carries it
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub uploader_id: Uuid,
+    #[serde(skip)]
+    pub storage_key: String,
+    pub content_type: String,
+    pub size: i64,
+    pub created_at: DateTime<Utc>,
+    /// Set on GIFs and large videos queued for transcoding
+    pub transcode_status: Option<TranscodeStatus>,
+    #[serde(skip)]
+    pub transcode_attempts: i32,
+    #[serde(skip)]
+    pub transcode_started_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "transcode_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TranscodeStatus {
+    Pending,
+    /// At least one rendition was made
+    Ready,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "rendition_kind", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum RenditionKind {
+    /// H.264, for every platform
+    Mp4,
+    /// VP9, usually smaller
+    Webm,
+    /// JPEG of the first frame
+    Poster,
+}
+
+impl RenditionKind {
+    pub const ALL: [RenditionKind; 3] =
+        [RenditionKind::Mp4, RenditionKind::Webm, RenditionKind::Poster];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RenditionKind::Mp4 => "mp4",
+            RenditionKind::Webm => "webm",
+            RenditionKind::Poster => "poster",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            RenditionKind::Mp4 => "video/mp4",
+            RenditionKind::Webm => "video/webm",
+            RenditionKind::Poster => "image/jpeg",
+        }
+    }
+}
+
+/// A transcoded copy of an attachment, stored next to the original
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AttachmentRendition {
+    pub attachment_id: Uuid,
+    pub kind: RenditionKind,
+    #[serde(skip)]
+    pub storage_key: String,
+    pub content_type: String,
+    pub size: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A signed attachment link; fetch a fresh one once it expires
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachmentUrl {
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+    /// Transcoded copies, signed alike; only listed by
+    /// `/messages/:id/attachment` and `attachment_transcoded` events
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub renditions: Vec<RenditionUrl>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenditionUrl {
+    pub kind: RenditionKind,
+    pub content_type: String,
+    pub size: i64,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "message_type", rename_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
 pub enum MessageType {
+    #[default]
     Text,
     Image,
     Video,
@@ -32,35 +311,43 @@ pub enum MessageType {
     System,
 }
 
-impl Default for MessageType {
-    fn default() -> Self {
-        Self::Text
-    }
+/// Content of a `System` message: a `key` and its `params`, which clients
+/// turn into localized text, plus the `actor_id` who caused it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "key", content = "params", rename_all = "snake_case")]
+pub enum SystemEvent {
+    GroupCreated { name: String },
+    MembersAdded { user_ids: Vec<Uuid> },
+    MemberRemoved { user_id: Uuid },
+    MemberLeft {},
+    GroupNameChanged { name: Option<String> },
+    GroupDescriptionChanged { description: Option<String> },
+    GroupRulesChanged { rules: Option<String> },
+    GroupAvatarChanged { avatar_url: Option<String> },
+    GroupPermissionsChanged { permissions: super::GroupPermissions },
+    AnnouncementModeChanged { enabled: bool },
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "message_status", rename_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
 pub enum MessageStatus {
     Sending,
+    #[default]
     Sent,
     Delivered,
     Read,
     Failed,
 }
 
-impl Default for MessageStatus {
-    fn default() -> Self {
-        Self::Sent
-    }
-}
-
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Receipt {
     pub id: Uuid,
     pub message_id: Uuid,
     pub user_id: Uuid,
     #[serde(rename = "type")]
+    #[sqlx(rename = "type")]
     pub receipt_type: ReceiptType,
     pub created_at: DateTime<Utc>,
 }
@@ -73,6 +360,21 @@ pub enum ReceiptType {
     Read,
 }
 
+/// A message's delivery to one recipient device
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct MessageDelivery {
+    pub user_id: Uuid,
+    pub device_id: i32,
+    /// `pending` (retrying over WebSocket), `pushed` (fell back to push,
+    /// awaiting the device) or `acked`
+    pub state: String,
+    pub attempts: i32,
+    pub pushed_at: Option<DateTime<Utc>>,
+    pub acked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageWithSender {
     #[serde(flatten)]
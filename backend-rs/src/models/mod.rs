@@ -4,7 +4,23 @@ pub mod contact;
 pub mod conversation;
 pub mod message;
 pub mod sticker;
+pub mod emoji;
 pub mod signal_keys;
+pub mod admin;
+pub mod moderation;
+pub mod broadcast;
+pub mod translation;
+pub mod gif;
+pub mod notification;
+pub mod organization;
+pub mod health;
+pub mod analytics;
+pub mod sealed_sender;
+pub mod client;
+pub mod api_token;
+pub mod invite;
+pub mod waitlist;
+pub mod sync_data;
 
 pub use user::*;
 pub use device::*;
@@ -12,4 +28,20 @@ pub use contact::*;
 pub use conversation::*;
 pub use message::*;
 pub use sticker::*;
+pub use emoji::*;
 pub use signal_keys::*;
+pub use admin::*;
+pub use moderation::*;
+pub use broadcast::*;
+pub use translation::*;
+pub use gif::*;
+pub use notification::*;
+pub use organization::*;
+pub use health::*;
+pub use analytics::*;
+pub use sealed_sender::*;
+pub use client::*;
+pub use api_token::*;
+pub use invite::*;
+pub use waitlist::*;
+pub use sync_data::*;
@@ -0,0 +1,71 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ModerationAlert {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub conversation_id: Option<Uuid>,
+    /// flood, duplicate_content or message_report
+    pub reason: String,
+    pub details: Option<String>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolved_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "report_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ReportStatus {
+    Open,
+    /// A moderator acted on the sender
+    Actioned,
+    Dismissed,
+}
+
+/// A recipient's report of a message. Binary fields are base64. For an end-
+/// to-end encrypted message, `content` is the plaintext the reporter
+/// disclosed and `franking_key` opens the sender's commitment to it.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct MessageReport {
+    pub id: Uuid,
+    /// May no longer exist; the evidence stays
+    pub message_id: Uuid,
+    pub conversation_id: Uuid,
+    pub reporter_id: Option<Uuid>,
+    /// As when the message was sent, like the signature
+    pub sender_id: Uuid,
+    /// spam, harassment, hate, violence, sexual, self_harm, illegal or other
+    pub reason: String,
+    pub details: Option<String>,
+    #[serde(with = "super::message::base64_bytes")]
+    pub content: Option<Vec<u8>>,
+    #[serde(with = "super::message::base64_bytes")]
+    pub franking_key: Option<Vec<u8>>,
+    #[serde(with = "super::message::base64_bytes")]
+    pub franking_tag: Option<Vec<u8>>,
+    #[serde(with = "super::message::base64_bytes")]
+    pub franking_signature: Option<Vec<u8>>,
+    pub status: ReportStatus,
+    pub resolved_by: Option<Uuid>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A check of a report's evidence, run again whenever a moderator asks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportVerification {
+    pub report_id: Uuid,
+    /// The message was end-to-end encrypted and sent with a franking tag
+    pub franked: bool,
+    /// The disclosed plaintext and key open the sender's commitment
+    pub commitment_opens: bool,
+    /// This server signed the commitment for that message and sender
+    pub signature_valid: bool,
+    /// The sender provably sent `content`; always true for unencrypted
+    /// messages, whose content is the server's own copy
+    pub verified: bool,
+}
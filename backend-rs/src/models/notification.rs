@@ -0,0 +1,44 @@
+use chrono::{DateTime, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// A do-not-disturb window starting on `weekday`. When `end` is not after
+/// `start` the window runs past midnight into the next day.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuietHoursWindow {
+    pub weekday: Weekday,
+    /// Local time, `HH:MM`
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationSchedule {
+    pub enabled: bool,
+    /// IANA time zone such as `Asia/Taipei`
+    pub timezone: String,
+    pub windows: Vec<QuietHoursWindow>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl Default for NotificationSchedule {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timezone: "UTC".to_string(),
+            windows: Vec::new(),
+            updated_at: None,
+        }
+    }
+}
+
+/// The user's notification settings as a whole
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserSettings {
+    /// Pushes are suppressed until then; WebSocket events still arrive
+    pub snoozed_until: Option<DateTime<Utc>>,
+    pub notification_schedule: NotificationSchedule,
+    /// Unread digests by email while away
+    pub email_digest: bool,
+    /// Hear when someone from your synced address book joins
+    pub contact_joined: bool,
+}
@@ -0,0 +1,77 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use super::{PublicProfile, User};
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Organization {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct OrganizationWithCounts {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub organization: Organization,
+    pub member_count: i64,
+    pub team_count: i64,
+}
+
+/// One person in an imported directory, matched to an account by username,
+/// then email, then phone
+#[derive(Debug, Clone, Deserialize)]
+pub struct DirectoryImportEntry {
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub title: Option<String>,
+    /// Team names; each team gets a managed group
+    #[serde(default)]
+    pub teams: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DirectoryImportSummary {
+    pub members: usize,
+    /// Entries that matched no account, by the identifier given
+    pub unmatched: Vec<String>,
+    /// Members no longer in the directory
+    pub removed: usize,
+    pub teams: usize,
+    pub team_groups_created: usize,
+}
+
+/// Database row behind a `DirectoryEntry`
+#[derive(Debug, Clone, FromRow)]
+pub struct DirectoryRow {
+    #[sqlx(flatten)]
+    pub user: User,
+    pub organization_id: Uuid,
+    pub title: Option<String>,
+    pub teams: Vec<String>,
+}
+
+/// A colleague as listed in the directory tab
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryEntry {
+    pub organization_id: Uuid,
+    pub user: PublicProfile,
+    pub title: Option<String>,
+    pub teams: Vec<String>,
+}
+
+impl From<DirectoryRow> for DirectoryEntry {
+    fn from(row: DirectoryRow) -> Self {
+        Self {
+            organization_id: row.organization_id,
+            user: PublicProfile::from(&row.user),
+            title: row.title,
+            teams: row.teams,
+        }
+    }
+}
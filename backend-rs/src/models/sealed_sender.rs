@@ -0,0 +1,45 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// What a device needs to send sealed messages until `expires_at`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeliveryCertificate {
+    /// Base64 of the `SenderCertificate` JSON; goes inside the envelope so
+    /// only the recipient learns who sent it
+    pub certificate: String,
+    /// Base64 Ed25519 signature over the certificate's bytes
+    pub signature: String,
+    /// Base64 Ed25519 public key recipients verify certificates with
+    pub server_key: String,
+    /// Presented when submitting; counts toward rate limits without naming
+    /// the sender
+    pub delivery_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The sender as vouched for by the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SenderCertificate {
+    pub sender_id: Uuid,
+    pub sender_device: i32,
+    /// Unix seconds
+    pub expires: i64,
+}
+
+/// A sealed message waiting for one of the recipient's devices
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SealedEnvelope {
+    pub id: Uuid,
+    pub device_id: i32,
+    pub content: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One device's copy of a sealed message
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutgoingEnvelope {
+    pub device_id: i32,
+    pub content: Vec<u8>,
+}
@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct SignalIdentityKey {
     pub id: Uuid,
@@ -14,6 +15,7 @@ pub struct SignalIdentityKey {
     pub updated_at: DateTime<Utc>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct SignalSignedPreKey {
     pub id: Uuid,
@@ -26,6 +28,7 @@ pub struct SignalSignedPreKey {
     pub updated_at: DateTime<Utc>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct SignalPreKey {
     pub id: Uuid,
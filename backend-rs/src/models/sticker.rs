@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
+use super::DailyCount;
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct StickerPack {
     pub id: Uuid,
@@ -14,16 +16,46 @@ pub struct StickerPack {
     pub is_animated: bool,
     pub price: i32,
     pub downloads: i64,
+    /// Picked by the editors for the catalog's featured section
+    pub featured: bool,
+    /// Position in the featured section, lowest first
+    pub featured_order: Option<i32>,
+    /// Lowercase categories the catalog can be filtered by
+    pub tags: Vec<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// A curated slice of the catalog; without one it is ordered by downloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CatalogSection {
+    /// Featured packs in their editorial order
+    Featured,
+    /// Packs most sent and downloaded lately, recent use weighing more
+    Trending,
+    /// Newest first
+    New,
+}
+
+/// A sticker pack search hit with its trigram similarity to the query (0.0-1.0)
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct StickerPackSearchResult {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub pack: StickerPack,
+    pub score: f32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Sticker {
     pub id: Uuid,
     pub pack_id: Uuid,
     pub emoji: String,
     pub image_url: String,
+    /// Static PNG of an animated sticker's first frame, for clients and link
+    /// previews without an animation runtime
+    pub preview_url: Option<String>,
     pub position: i32,
     pub created_at: DateTime<Utc>,
 }
@@ -43,3 +75,27 @@ pub struct StickerPackWithStickers {
     pub pack: StickerPack,
     pub stickers: Vec<Sticker>,
 }
+
+/// How much one pack was used over the last `days` days, for its creator
+/// and admins
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StickerPackAnalytics {
+    pub pack_id: Uuid,
+    pub days: i64,
+    /// All-time downloads
+    pub downloads: i64,
+    /// Sticker messages sent from the pack within the window
+    pub sends: i64,
+    pub sends_per_day: Vec<DailyCount>,
+    /// Users who added the pack within the window and still have it
+    pub installs_per_day: Vec<DailyCount>,
+    /// Every sticker in the pack, most sent first
+    pub stickers: Vec<StickerSends>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct StickerSends {
+    pub sticker_id: Uuid,
+    pub emoji: String,
+    pub sends: i64,
+}
@@ -0,0 +1,41 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::NotificationSchedule;
+
+/// Layout version of [`SyncData`] this server reads and writes
+pub const SYNC_DATA_VERSION: i16 = 1;
+
+/// A chat folder. The server only keeps folders so a new install can restore
+/// them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatFolder {
+    pub name: String,
+    /// In display order; conversations the user has left are dropped
+    pub conversation_ids: Vec<Uuid>,
+}
+
+/// Notification preferences carried in the sync data. A running snooze is
+/// left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncedNotifications {
+    pub notification_schedule: NotificationSchedule,
+    pub email_digest: bool,
+    pub contact_joined: bool,
+}
+
+/// The user's setup as one versioned blob, for restoring it on a fresh
+/// install
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncData {
+    pub version: i16,
+    /// When the settings last changed on a device; `None` until first saved.
+    /// A save older than the stored one is rejected.
+    pub updated_at: Option<DateTime<Utc>>,
+    pub updated_by_device: Option<i32>,
+    pub notifications: SyncedNotifications,
+    /// Installed sticker packs in display order
+    pub sticker_packs: Vec<Uuid>,
+    pub folders: Vec<ChatFolder>,
+}
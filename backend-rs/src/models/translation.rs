@@ -0,0 +1,25 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct MessageTranslation {
+    pub message_id: Uuid,
+    /// Target language code such as `en` or `pt-BR`
+    pub language: String,
+    pub text: String,
+    /// Detected source language, when the provider reports it
+    pub source_language: Option<String>,
+    pub provider: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A participant's auto-translate preference for a conversation
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow)]
+pub struct ConversationTranslationSettings {
+    pub conversation_id: Uuid,
+    pub auto_translate: bool,
+    pub target_language: Option<String>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
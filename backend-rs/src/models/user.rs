@@ -16,28 +16,80 @@ pub struct User {
     pub last_seen_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub emoji_status: Option<String>,
+    pub emoji_status_expires_at: Option<DateTime<Utc>>,
+    pub pronouns: Option<String>,
+    /// Profile URLs, in display order
+    pub links: Vec<String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+impl User {
+    /// Show the user as offline, with no last seen time
+    pub fn hide_presence(&mut self) {
+        self.status = UserStatus::Offline;
+        self.last_seen_at = None;
+    }
+}
+
+/// The public part of a profile, sent to contacts and conversation members
+/// in `profile_updated` events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicProfile {
+    pub user_id: Uuid,
+    pub username: String,
+    pub display_name: String,
+    pub avatar_url: Option<String>,
+    pub bio: Option<String>,
+    pub emoji_status: Option<String>,
+    pub emoji_status_expires_at: Option<DateTime<Utc>>,
+    pub pronouns: Option<String>,
+    pub links: Vec<String>,
+}
+
+impl From<&User> for PublicProfile {
+    fn from(user: &User) -> Self {
+        Self {
+            user_id: user.id,
+            username: user.username.clone(),
+            display_name: user.display_name.clone(),
+            avatar_url: user.avatar_url.clone(),
+            bio: user.bio.clone(),
+            emoji_status: user.emoji_status.clone(),
+            emoji_status_expires_at: user.emoji_status_expires_at,
+            pronouns: user.pronouns.clone(),
+            links: user.links.clone(),
+        }
+    }
+}
+
+/// A user search hit with its trigram similarity to the query (0.0-1.0)
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UserSearchResult {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub user: User,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "user_status", rename_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
 pub enum UserStatus {
     Online,
+    #[default]
     Offline,
     Away,
 }
 
-impl Default for UserStatus {
-    fn default() -> Self {
-        Self::Offline
-    }
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenPair {
     pub access_token: String,
     pub refresh_token: String,
     pub expires_at: DateTime<Utc>,
+    /// Set when a login or registration created the device; later logins
+    /// send it back to sign in as the same device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_token: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -50,6 +102,52 @@ pub struct Session {
     pub expires_at: DateTime<Utc>,
     pub last_used_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub country: Option<String>,
+    /// What a linked session may do; `None` for full access
+    pub scopes: Option<Vec<String>>,
+}
+
+/// Session as shown to its owner, without token hashes
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub device_id: i32,
+    pub device_name: Option<String>,
+    pub platform: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub country: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    /// Last authenticated request, updated at most once per throttle interval
+    pub last_used_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    /// What a linked session may do; `None` for full access
+    pub scopes: Option<Vec<String>>,
+    /// When the session will be deleted if it stays unused
+    #[sqlx(default)]
+    pub idle_expires_at: Option<DateTime<Utc>>,
+    #[sqlx(default)]
+    pub current: bool,
+}
+
+/// Request metadata captured at login
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub country: Option<String>,
+}
+
+/// A login from a device or location the user has not used before
+#[derive(Debug, Clone)]
+pub struct NewLoginAlert {
+    pub device_id: i32,
+    pub device_name: String,
+    pub platform: String,
+    pub new_device: bool,
+    pub client: ClientInfo,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -57,6 +155,7 @@ pub struct Otp {
     pub id: Uuid,
     pub target: String,
     #[serde(rename = "type")]
+    #[sqlx(rename = "type")]
     pub otp_type: OtpType,
     pub code: String,
     pub expires_at: DateTime<Utc>,
@@ -72,3 +171,157 @@ pub enum OtpType {
     Phone,
     Email,
 }
+
+/// A phone/email change waiting for codes from both the new target and the
+/// account's current one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingIdentifierChange {
+    #[serde(rename = "type")]
+    pub otp_type: OtpType,
+    pub new_target: String,
+    pub confirm_type: OtpType,
+    pub confirm_target: String,
+}
+
+/// A merge of the account holding `target` into the signed-in one, waiting
+/// for the code sent to `target`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingAccountMerge {
+    #[serde(rename = "type")]
+    pub otp_type: OtpType,
+    pub target: String,
+    pub merged_id: Uuid,
+    /// Sent to `target` for this merge only, never through the login OTPs
+    pub code: String,
+    /// Wrong codes entered so far
+    #[serde(default)]
+    pub attempts: u32,
+    /// Set once the dry run was shown; confirming needs it
+    pub previewed: bool,
+}
+
+/// What merging another account moves into this one. A dry run reports what
+/// would move; the confirmed merge what did.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountMergeReport {
+    pub merged_id: Uuid,
+    pub merged_username: String,
+    pub merged_display_name: String,
+    /// The identifiers the account has after the merge
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    /// Devices moved over; they need verifying again from the primary device
+    pub devices_moved: i64,
+    /// Devices with the same name and platform as one of yours, dropped
+    pub devices_dropped: i64,
+    /// Identity keys and pre-keys of the moved devices
+    pub keys_moved: i64,
+    pub contacts_moved: i64,
+    /// Contacts you already have, folded into yours
+    pub contacts_dropped: i64,
+    /// People with the merged account in their contacts
+    pub contact_of: i64,
+    pub conversations_moved: i64,
+    /// Conversations both accounts are in; yours stays, with the higher role
+    pub conversations_shared: i64,
+    pub messages_moved: i64,
+    pub sessions_ended: i64,
+    pub api_tokens_revoked: i64,
+    pub dry_run: bool,
+}
+
+/// What a session may do. Sessions from a login or registration have every
+/// scope; linked web sessions only those they were approved with, and API
+/// tokens only those they were created with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Conversations, messages, attachments, stickers and keys
+    Messages,
+    Contacts,
+    /// The user's own profile and settings
+    Profile,
+    /// Managing other devices and linking new ones
+    Devices,
+    /// Changing the phone or email, merging accounts, signing out everywhere
+    /// and API tokens
+    Account,
+    Admin,
+    /// API tokens: list conversations and read their messages
+    #[serde(rename = "read:messages")]
+    ReadMessages,
+    /// API tokens: send messages and upload attachments
+    #[serde(rename = "send:messages")]
+    SendMessages,
+    /// API tokens: create sticker packs and see their analytics
+    #[serde(rename = "manage:stickers")]
+    ManageStickers,
+}
+
+impl Scope {
+    /// Scopes a linked session can be approved with
+    pub const LINKABLE: [Scope; 3] = [Scope::Messages, Scope::Contacts, Scope::Profile];
+
+    /// Scopes an API token can be created with
+    pub const TOKEN: [Scope; 3] = [
+        Scope::ReadMessages,
+        Scope::SendMessages,
+        Scope::ManageStickers,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Messages => "messages",
+            Scope::Contacts => "contacts",
+            Scope::Profile => "profile",
+            Scope::Devices => "devices",
+            Scope::Account => "account",
+            Scope::Admin => "admin",
+            Scope::ReadMessages => "read:messages",
+            Scope::SendMessages => "send:messages",
+            Scope::ManageStickers => "manage:stickers",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "messages" => Some(Scope::Messages),
+            "contacts" => Some(Scope::Contacts),
+            "profile" => Some(Scope::Profile),
+            "devices" => Some(Scope::Devices),
+            "account" => Some(Scope::Account),
+            "admin" => Some(Scope::Admin),
+            "read:messages" => Some(Scope::ReadMessages),
+            "send:messages" => Some(Scope::SendMessages),
+            "manage:stickers" => Some(Scope::ManageStickers),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceLinkStatus {
+    Pending,
+    Approved,
+    Declined,
+}
+
+/// A web session waiting for the user to scan its QR code from a signed-in
+/// device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceLink {
+    pub device_name: String,
+    pub platform: String,
+    /// In the QR code; approving the link takes it
+    pub code: String,
+    /// Kept by the web client; claiming the session takes it
+    pub secret: String,
+    pub status: DeviceLinkStatus,
+    pub user_id: Option<Uuid>,
+    pub scopes: Vec<Scope>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub country: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
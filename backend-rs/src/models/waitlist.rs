@@ -0,0 +1,68 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use super::OtpType;
+
+/// Who may register, from `SIGNUP_MODE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignupMode {
+    Open,
+    /// Registration needs an invite code
+    Invite,
+    /// Registration needs an invite code or approval from the waitlist
+    Waitlist,
+}
+
+impl SignupMode {
+    pub fn from_config(value: &str) -> Self {
+        match value {
+            "open" => SignupMode::Open,
+            "invite" => SignupMode::Invite,
+            "waitlist" => SignupMode::Waitlist,
+            other => {
+                tracing::warn!("Unknown signup mode '{}', requiring invites instead", other);
+                SignupMode::Invite
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "waitlist_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum WaitlistStatus {
+    Pending,
+    /// May register; an OTP was sent when approved
+    Approved,
+    Rejected,
+    Registered,
+}
+
+/// A verified phone or email waiting to be let in
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct WaitlistEntry {
+    pub id: Uuid,
+    pub target: String,
+    pub target_type: OtpType,
+    pub status: WaitlistStatus,
+    pub ip_address: Option<String>,
+    pub country: Option<String>,
+    /// The admin who approved or rejected the entry
+    pub reviewed_by: Option<Uuid>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    /// Set once the entry registered
+    pub user_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Where someone stands on the waitlist, as shown to them
+#[derive(Debug, Clone, Serialize)]
+pub struct WaitlistPosition {
+    pub status: WaitlistStatus,
+    /// Pending entries ahead of theirs, plus one; only while pending
+    pub position: Option<i64>,
+    pub joined_at: DateTime<Utc>,
+}
@@ -0,0 +1,628 @@
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, AppResult},
+    models::{AccountMergeReport, OtpType, PendingAccountMerge},
+    services::{auth::AuthService, messaging::direct_key},
+    storage::redis::RedisClient,
+};
+
+/// Columns pointing at a user that move over as they are
+const USER_COLUMNS: [(&str, &str); 18] = [
+    ("messages", "sender_id"),
+    ("sticker_packs", "creator_id"),
+    ("moderation_alerts", "user_id"),
+    ("moderation_alerts", "resolved_by"),
+    ("blocked_content_hashes", "created_by"),
+    ("broadcast_lists", "owner_id"),
+    ("broadcasts", "sender_id"),
+    ("queued_notifications", "user_id"),
+    ("attachments", "uploader_id"),
+    ("conversation_events", "actor_id"),
+    ("custom_emoji", "created_by"),
+    ("client_version_requirements", "updated_by"),
+    ("invites", "created_by"),
+    ("users", "invited_by"),
+    ("waitlist", "reviewed_by"),
+    ("waitlist", "user_id"),
+    ("admin_audit_log", "actor_id"),
+    ("message_reports", "resolved_by"),
+];
+
+/// Columns pointing at a user that are unique together with another column.
+/// Rows the surviving account already has a match for are left behind and
+/// deleted with the merged account.
+const KEYED_USER_COLUMNS: [(&str, &str, &str); 14] = [
+    ("participants", "user_id", "conversation_id"),
+    ("receipts", "user_id", "message_id, type"),
+    ("user_sticker_packs", "user_id", "pack_id"),
+    ("conversation_appearance", "user_id", "conversation_id"),
+    ("conversation_translation_settings", "user_id", "conversation_id"),
+    ("broadcast_list_recipients", "user_id", "list_id"),
+    ("broadcast_messages", "recipient_id", "broadcast_id"),
+    ("view_once_views", "user_id", "message_id"),
+    ("matrix_puppet_rooms", "user_id", "room_id"),
+    ("organization_members", "user_id", "organization_id"),
+    ("organization_team_members", "user_id", "team_id"),
+    ("contact_interests", "user_id", "identifier_hash"),
+    ("contacts", "contact_id", "user_id"),
+    ("message_reports", "reporter_id", "message_id"),
+];
+
+/// Columns pointing at one of a user's devices. Sessions stay behind: their
+/// tokens were issued for the merged account.
+const DEVICE_COLUMNS: [(&str, &str); 7] = [
+    ("devices", "user_id"),
+    ("signal_identity_keys", "user_id"),
+    ("signal_signed_prekeys", "user_id"),
+    ("signal_prekeys", "user_id"),
+    ("sealed_envelopes", "recipient_id"),
+    ("message_deliveries", "user_id"),
+    ("login_events", "user_id"),
+];
+
+/// Which of the merged account's devices move over, and the device ids they
+/// get next to the surviving account's own
+struct DevicePlan {
+    from: Vec<i32>,
+    to: Vec<i32>,
+    dropped: i64,
+}
+
+/// Folds a duplicate account, typically one registered with an email next to
+/// one registered with a phone, into the signed-in account. The user proves
+/// they hold the duplicate with a code sent to its phone or email, sees a dry
+/// run, and confirms; the duplicate is deleted and cannot be restored.
+pub struct AccountMergeService {
+    db: PgPool,
+    redis: RedisClient,
+    config: Config,
+}
+
+impl AccountMergeService {
+    pub fn new(db: PgPool, redis: RedisClient, config: Config) -> Self {
+        Self { db, redis, config }
+    }
+
+    fn auth(&self) -> AuthService {
+        AuthService::new(self.db.clone(), self.redis.clone(), self.config.clone())
+    }
+
+    /// Send a code to the phone or email of the account to merge
+    pub async fn start(&self, user_id: Uuid, otp_type: OtpType, target: &str) -> AppResult<()> {
+        let column = match otp_type {
+            OtpType::Phone => "phone",
+            OtpType::Email => "email",
+        };
+        let (merged_id, is_admin, banned): (Uuid, bool, bool) = sqlx::query_as(&format!(
+            "SELECT id, is_admin, banned_at IS NOT NULL FROM users WHERE {} = $1",
+            column
+        ))
+        .bind(target)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
+
+        if merged_id == user_id {
+            return Err(AppError::BadRequest(
+                "This is the signed-in account".to_string(),
+            ));
+        }
+        if is_admin {
+            return Err(AppError::BadRequest(
+                "Admin accounts cannot be merged".to_string(),
+            ));
+        }
+        if banned {
+            return Err(AppError::BadRequest(
+                "Suspended accounts cannot be merged".to_string(),
+            ));
+        }
+
+        let code = self.auth().send_code(target, otp_type).await?;
+        let pending = PendingAccountMerge {
+            otp_type,
+            target: target.to_string(),
+            merged_id,
+            code,
+            attempts: 0,
+            previewed: false,
+        };
+        self.save(user_id, &pending).await
+    }
+
+    /// Check the code and report what the merge would move, without moving
+    /// anything
+    pub async fn preview(&self, user_id: Uuid, code: &str) -> AppResult<AccountMergeReport> {
+        let mut pending = self.pending(user_id, code).await?;
+
+        let mut conn = self.db.acquire().await?;
+        let devices = plan_devices(&mut conn, pending.merged_id, user_id).await?;
+        let report = report(&mut conn, pending.merged_id, user_id, &devices).await?;
+
+        pending.previewed = true;
+        self.save(user_id, &pending).await?;
+
+        Ok(report)
+    }
+
+    /// Merge for good. The merged account's sessions end and its API tokens
+    /// stop working; its devices have to sign in to this account again.
+    pub async fn confirm(&self, user_id: Uuid, code: &str) -> AppResult<AccountMergeReport> {
+        let pending = self.pending(user_id, code).await?;
+        if !pending.previewed {
+            return Err(AppError::BadRequest(
+                "Preview the merge before confirming it".to_string(),
+            ));
+        }
+        let merged_id = pending.merged_id;
+
+        let mut tx = self.db.begin().await?;
+
+        // The duplicate may have changed hands, or been suspended, since the
+        // code was sent
+        let locked: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT id FROM users
+            WHERE id = $1 OR (
+                id = $2 AND (phone = $3 OR email = $3) AND NOT is_admin AND banned_at IS NULL
+            )
+            ORDER BY id
+            FOR UPDATE
+            "#,
+        )
+        .bind(user_id)
+        .bind(merged_id)
+        .bind(&pending.target)
+        .fetch_all(&mut *tx)
+        .await?;
+        if locked.len() != 2 {
+            return Err(AppError::UserNotFound);
+        }
+
+        let devices = plan_devices(&mut tx, merged_id, user_id).await?;
+        let mut report = report(&mut tx, merged_id, user_id, &devices).await?;
+
+        merge_conversations(&mut tx, merged_id, user_id).await?;
+        merge_contacts(&mut tx, merged_id, user_id).await?;
+
+        for (table, column) in DEVICE_COLUMNS {
+            sqlx::query(&format!(
+                r#"
+                UPDATE {table} SET {column} = $2, device_id = m.device_id
+                FROM UNNEST($3::INT[], $4::INT[]) AS m(old_device_id, device_id)
+                WHERE {table}.{column} = $1 AND {table}.device_id = m.old_device_id
+                "#,
+                table = table,
+                column = column,
+            ))
+            .bind(merged_id)
+            .bind(user_id)
+            .bind(&devices.from)
+            .bind(&devices.to)
+            .execute(&mut *tx)
+            .await?;
+        }
+        // Moved devices need the primary device's approval again
+        sqlx::query(
+            "UPDATE devices SET verified = false WHERE user_id = $1 AND device_id = ANY($2)",
+        )
+        .bind(user_id)
+        .bind(&devices.to)
+        .execute(&mut *tx)
+        .await?;
+
+        for (table, column) in USER_COLUMNS {
+            sqlx::query(&format!(
+                "UPDATE {} SET {} = $2 WHERE {} = $1",
+                table, column, column
+            ))
+            .bind(merged_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for (table, column, keys) in KEYED_USER_COLUMNS {
+            let matches = keys
+                .split(", ")
+                .map(|key| format!("k.{key} = {table}.{key}", key = key, table = table))
+                .collect::<Vec<_>>()
+                .join(" AND ");
+            sqlx::query(&format!(
+                r#"
+                UPDATE {table} SET {column} = $2
+                WHERE {column} = $1
+                AND NOT EXISTS (SELECT 1 FROM {table} k WHERE k.{column} = $2 AND {matches})
+                "#,
+                table = table,
+                column = column,
+                matches = matches,
+            ))
+            .bind(merged_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        // Whatever was left behind goes with it
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(merged_id)
+            .execute(&mut *tx)
+            .await?;
+
+        // The account takes over the phone or email it lacks
+        sqlx::query(
+            "UPDATE users SET phone = $2, email = $3, updated_at = NOW() WHERE id = $1",
+        )
+        .bind(user_id)
+        .bind(&report.phone)
+        .bind(&report.email)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        self.redis.delete_cached(&pending_key(user_id)).await?;
+        self.redis
+            .delete_all_user_sessions(&merged_id.to_string())
+            .await?;
+
+        report.dry_run = false;
+        self.publish(merged_id, user_id, &report).await?;
+
+        Ok(report)
+    }
+
+    /// The pending merge, once the code sent for it checks out. Codes
+    /// verified for logins or other flows don't count.
+    async fn pending(&self, user_id: Uuid, code: &str) -> AppResult<PendingAccountMerge> {
+        let mut pending: PendingAccountMerge =
+            match self.redis.get_cached(&pending_key(user_id)).await? {
+                Some(raw) => serde_json::from_str(&raw)?,
+                None => return Err(AppError::OtpExpired),
+            };
+
+        if pending.attempts >= self.config.otp.max_attempts {
+            return Err(AppError::TooManyAttempts);
+        }
+        if pending.code != code {
+            pending.attempts += 1;
+            self.save(user_id, &pending).await?;
+            return Err(AppError::InvalidOtp);
+        }
+
+        Ok(pending)
+    }
+
+    async fn save(&self, user_id: Uuid, pending: &PendingAccountMerge) -> AppResult<()> {
+        self.redis
+            .set_cached(
+                &pending_key(user_id),
+                &serde_json::to_string(pending)?,
+                self.config.otp.ttl,
+            )
+            .await
+    }
+
+    /// Sign out the merged account's devices, and have this account's devices
+    /// and the people who now have it as a contact refresh
+    async fn publish(
+        &self,
+        merged_id: Uuid,
+        user_id: Uuid,
+        report: &AccountMergeReport,
+    ) -> AppResult<()> {
+        let merged = json!({
+            "type": "account_merged",
+            "payload": { "merged_id": merged_id, "user_id": user_id },
+        })
+        .to_string();
+        self.redis
+            .publish_message(&merged_id.to_string(), &merged)
+            .await?;
+
+        let own = json!({ "type": "account_merged", "payload": report }).to_string();
+        self.redis
+            .publish_message(&user_id.to_string(), &own)
+            .await?;
+
+        let watcher_ids: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT user_id FROM contacts WHERE contact_id = $1 AND is_blocked = false",
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let contact_updated = json!({
+            "type": "contact_updated",
+            "payload": { "user_id": user_id },
+        })
+        .to_string();
+        for watcher_id in watcher_ids {
+            self.redis
+                .publish_message(&watcher_id.to_string(), &contact_updated)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+fn pending_key(user_id: Uuid) -> String {
+    format!("account_merge:{}", user_id)
+}
+
+/// Devices with the same name and platform as one the account already has
+/// are dropped, since signing in from them would pick the account's own.
+/// The rest are numbered after the account's devices.
+async fn plan_devices(
+    conn: &mut PgConnection,
+    merged_id: Uuid,
+    user_id: Uuid,
+) -> AppResult<DevicePlan> {
+    let devices: Vec<(i32, bool)> = sqlx::query_as(
+        r#"
+        SELECT d.device_id, EXISTS(
+            SELECT 1 FROM devices s
+            WHERE s.user_id = $2 AND s.name = d.name AND s.platform = d.platform
+        )
+        FROM devices d
+        WHERE d.user_id = $1
+        ORDER BY d.device_id
+        "#,
+    )
+    .bind(merged_id)
+    .bind(user_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let last_device_id: i32 =
+        sqlx::query_scalar("SELECT COALESCE(MAX(device_id), 0) FROM devices WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&mut *conn)
+            .await?;
+
+    let from: Vec<i32> = devices
+        .iter()
+        .filter(|(_, taken)| !taken)
+        .map(|(device_id, _)| *device_id)
+        .collect();
+    let to = (1..=from.len() as i32).map(|n| last_device_id + n).collect();
+
+    Ok(DevicePlan {
+        dropped: (devices.len() - from.len()) as i64,
+        from,
+        to,
+    })
+}
+
+async fn report(
+    conn: &mut PgConnection,
+    merged_id: Uuid,
+    user_id: Uuid,
+    devices: &DevicePlan,
+) -> AppResult<AccountMergeReport> {
+    let (phone, email): (Option<String>, Option<String>) =
+        sqlx::query_as("SELECT phone, email FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+    type Merged = (Option<String>, Option<String>, String, String);
+    let (merged_phone, merged_email, merged_username, merged_display_name): Merged =
+        sqlx::query_as("SELECT phone, email, username, display_name FROM users WHERE id = $1")
+            .bind(merged_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+
+    let (keys_moved, contacts_moved, contacts_dropped, contact_of): (i64, i64, i64, i64) =
+        sqlx::query_as(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM (
+                    SELECT device_id FROM signal_identity_keys WHERE user_id = $1
+                    UNION ALL SELECT device_id FROM signal_signed_prekeys WHERE user_id = $1
+                    UNION ALL SELECT device_id FROM signal_prekeys WHERE user_id = $1
+                ) k WHERE k.device_id = ANY($3)),
+                (SELECT COUNT(*) FROM contacts d
+                    WHERE d.user_id = $1 AND d.contact_id != $2
+                    AND NOT EXISTS (
+                        SELECT 1 FROM contacts s
+                        WHERE s.user_id = $2 AND s.contact_id = d.contact_id
+                    )),
+                (SELECT COUNT(*) FROM contacts d
+                    WHERE d.user_id = $1 AND (d.contact_id = $2 OR EXISTS (
+                        SELECT 1 FROM contacts s
+                        WHERE s.user_id = $2 AND s.contact_id = d.contact_id
+                    ))),
+                (SELECT COUNT(*) FROM contacts WHERE contact_id = $1 AND user_id != $2)
+            "#,
+        )
+        .bind(merged_id)
+        .bind(user_id)
+        .bind(&devices.from)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let (conversations_moved, conversations_shared, messages_moved, sessions_ended, tokens): (
+        i64,
+        i64,
+        i64,
+        i64,
+        i64,
+    ) = sqlx::query_as(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM participants d WHERE d.user_id = $1 AND NOT EXISTS (
+                SELECT 1 FROM participants s
+                WHERE s.user_id = $2 AND s.conversation_id = d.conversation_id
+            )),
+            (SELECT COUNT(*) FROM participants d WHERE d.user_id = $1 AND EXISTS (
+                SELECT 1 FROM participants s
+                WHERE s.user_id = $2 AND s.conversation_id = d.conversation_id
+            )),
+            (SELECT COUNT(*) FROM messages WHERE sender_id = $1),
+            (SELECT COUNT(*) FROM sessions WHERE user_id = $1),
+            (SELECT COUNT(*) FROM api_tokens WHERE user_id = $1 AND revoked_at IS NULL)
+        "#,
+    )
+    .bind(merged_id)
+    .bind(user_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(AccountMergeReport {
+        merged_id,
+        merged_username,
+        merged_display_name,
+        phone: phone.or(merged_phone),
+        email: email.or(merged_email),
+        devices_moved: devices.from.len() as i64,
+        devices_dropped: devices.dropped,
+        keys_moved,
+        contacts_moved,
+        contacts_dropped,
+        contact_of,
+        conversations_moved,
+        conversations_shared,
+        messages_moved,
+        sessions_ended,
+        api_tokens_revoked: tokens,
+        dry_run: true,
+    })
+}
+
+/// Saved Messages fold into the account's own, shared conversations keep the
+/// higher of the two roles, and direct conversations are keyed to the
+/// account. Where the account already has a direct conversation with the same
+/// person, or the conversation was between the two accounts, it stays
+/// readable but is no longer returned for the pair.
+async fn merge_conversations(
+    conn: &mut PgConnection,
+    merged_id: Uuid,
+    user_id: Uuid,
+) -> AppResult<()> {
+    let saved: Vec<(Uuid, Uuid)> = sqlx::query_as(
+        r#"
+        SELECT id, created_by FROM conversations
+        WHERE type = 'saved' AND created_by IN ($1, $2)
+        "#,
+    )
+    .bind(merged_id)
+    .bind(user_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    let merged_saved = saved.iter().find(|(_, owner)| *owner == merged_id);
+    let own_saved = saved.iter().find(|(_, owner)| *owner == user_id);
+    if let (Some((merged_saved, _)), Some((own_saved, _))) = (merged_saved, own_saved) {
+        sqlx::query("UPDATE messages SET conversation_id = $2 WHERE conversation_id = $1")
+            .bind(merged_saved)
+            .bind(own_saved)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("DELETE FROM conversations WHERE id = $1")
+            .bind(merged_saved)
+            .execute(&mut *conn)
+            .await?;
+    }
+    sqlx::query("UPDATE conversations SET created_by = $2 WHERE created_by = $1")
+        .bind(merged_id)
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE participants s SET role = LEAST(s.role, d.role),
+            left_at = CASE WHEN d.left_at IS NULL THEN NULL ELSE s.left_at END
+        FROM participants d
+        WHERE s.user_id = $2 AND d.user_id = $1 AND d.conversation_id = s.conversation_id
+        "#,
+    )
+    .bind(merged_id)
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+
+    let direct: Vec<(Uuid, Uuid)> = sqlx::query_as(
+        r#"
+        SELECT c.id, p.user_id FROM conversations c
+        JOIN participants d ON d.conversation_id = c.id AND d.user_id = $1
+        JOIN participants p ON p.conversation_id = c.id AND p.user_id != $1
+        WHERE c.type = 'direct' AND c.direct_key IS NOT NULL
+        "#,
+    )
+    .bind(merged_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    for (conversation_id, other_id) in direct {
+        let key = (other_id != user_id).then(|| direct_key(user_id, other_id));
+        sqlx::query(
+            r#"
+            UPDATE conversations SET direct_key = CASE
+                WHEN EXISTS(SELECT 1 FROM conversations WHERE direct_key = $2) THEN NULL
+                ELSE $2
+            END
+            WHERE id = $1
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(key)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Where both accounts had the same contact, or were listed by the same
+/// person, the account's own entry stays; a block on either entry carries
+/// over. Entries between the two accounts are dropped.
+async fn merge_contacts(conn: &mut PgConnection, merged_id: Uuid, user_id: Uuid) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE contacts s SET is_blocked = true
+        FROM contacts d
+        WHERE d.is_blocked AND (
+            (d.user_id = $1 AND s.user_id = $2 AND s.contact_id = d.contact_id)
+            OR (d.contact_id = $1 AND s.contact_id = $2 AND s.user_id = d.user_id)
+        )
+        "#,
+    )
+    .bind(merged_id)
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM contacts
+        WHERE (user_id = $1 AND contact_id = $2) OR (user_id = $2 AND contact_id = $1)
+        "#,
+    )
+    .bind(merged_id)
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE contacts SET user_id = $2
+        WHERE user_id = $1
+        AND NOT EXISTS (
+            SELECT 1 FROM contacts k WHERE k.user_id = $2 AND k.contact_id = contacts.contact_id
+        )
+        "#,
+    )
+    .bind(merged_id)
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
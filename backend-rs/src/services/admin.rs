@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::AppResult,
+    models::{
+        ActiveUserStats, AdminStats, Announcement, BucketUsage, DailyCount, MaintenanceState,
+        StickerDownloadStats, UserStats, WebSocketStats,
+    },
+    services::messaging::WsMessage,
+    storage::{blob::BlobStorage, redis::RedisClient},
+};
+
+pub struct AdminService {
+    db: PgPool,
+    redis: RedisClient,
+    storage: Arc<dyn BlobStorage>,
+    config: Config,
+}
+
+impl AdminService {
+    pub fn new(
+        db: PgPool,
+        redis: RedisClient,
+        storage: Arc<dyn BlobStorage>,
+        config: Config,
+    ) -> Self {
+        Self {
+            db,
+            redis,
+            storage,
+            config,
+        }
+    }
+
+    /// Get dashboard statistics, served from cache unless `refresh` is set
+    pub async fn get_stats(
+        &self,
+        days: i64,
+        refresh: bool,
+        websocket: WebSocketStats,
+    ) -> AppResult<AdminStats> {
+        let cache_key = format!("admin:stats:{}", days);
+
+        if !refresh {
+            if let Some(cached) = self.redis.get_cached(&cache_key).await? {
+                if let Ok(mut stats) = serde_json::from_str::<AdminStats>(&cached) {
+                    stats.websocket = websocket;
+                    return Ok(stats);
+                }
+            }
+        }
+
+        let mut stats = self.compute_stats(days).await?;
+
+        let ttl = self.config.admin.stats_cache_ttl;
+        if !ttl.is_zero() {
+            let serialized = serde_json::to_string(&stats)?;
+            self.redis.set_cached(&cache_key, &serialized, ttl).await?;
+        }
+
+        stats.websocket = websocket;
+        Ok(stats)
+    }
+
+    /// Get current maintenance mode state
+    pub async fn get_maintenance(&self) -> AppResult<MaintenanceState> {
+        let state = self
+            .redis
+            .get_maintenance()
+            .await?
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+
+        Ok(state)
+    }
+
+    /// Enable or disable read-only maintenance mode
+    pub async fn set_maintenance(
+        &self,
+        enabled: bool,
+        message: Option<String>,
+        retry_after: u64,
+    ) -> AppResult<MaintenanceState> {
+        if !enabled {
+            self.redis.clear_maintenance().await?;
+            return Ok(MaintenanceState::default());
+        }
+
+        let state = MaintenanceState {
+            enabled,
+            message,
+            retry_after,
+            started_at: Some(Utc::now()),
+        };
+        self.redis
+            .set_maintenance(&serde_json::to_string(&state)?)
+            .await?;
+
+        Ok(state)
+    }
+
+    /// Publish an announcement to every connected client on all instances
+    pub async fn broadcast_announcement(
+        &self,
+        title: Option<String>,
+        message: String,
+        level: String,
+    ) -> AppResult<Announcement> {
+        let announcement = Announcement {
+            id: Uuid::new_v4(),
+            title,
+            message,
+            level,
+            created_at: Utc::now(),
+        };
+
+        let ws_message = WsMessage {
+            msg_type: "announcement".to_string(),
+            payload: serde_json::to_value(&announcement)?,
+        };
+
+        self.redis
+            .publish_broadcast(&serde_json::to_string(&ws_message)?)
+            .await?;
+
+        Ok(announcement)
+    }
+
+    async fn compute_stats(&self, days: i64) -> AppResult<AdminStats> {
+        let now = Utc::now();
+        let since = now - Duration::days(days);
+
+        let active_users = ActiveUserStats {
+            daily: self.count_active_users(now - Duration::days(1)).await?,
+            weekly: self.count_active_users(now - Duration::days(7)).await?,
+        };
+
+        let total_users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&self.db)
+            .await?;
+
+        let users = UserStats {
+            total: total_users,
+            registrations_per_day: self.daily_counts("users", since).await?,
+        };
+
+        let messages_per_day = self.daily_counts("messages", since).await?;
+
+        let total_downloads: Option<i64> =
+            sqlx::query_scalar("SELECT SUM(downloads)::BIGINT FROM sticker_packs")
+                .fetch_one(&self.db)
+                .await?;
+
+        let sticker_downloads = StickerDownloadStats {
+            total: total_downloads.unwrap_or(0),
+            per_day: self.daily_counts("user_sticker_packs", since).await?,
+        };
+
+        let mut storage = Vec::new();
+        for bucket in [
+            self.storage.stickers_bucket(),
+            self.storage.avatars_bucket(),
+            self.storage.attachments_bucket(),
+        ] {
+            let (objects, bytes) = self.storage.bucket_usage(bucket).await?;
+            storage.push(BucketUsage {
+                bucket: bucket.to_string(),
+                objects,
+                bytes,
+            });
+        }
+
+        Ok(AdminStats {
+            generated_at: now,
+            active_users,
+            users,
+            messages_per_day,
+            sticker_downloads,
+            storage,
+            websocket: WebSocketStats::default(),
+        })
+    }
+
+    /// Count users seen, logged in, or sending messages since the given time
+    async fn count_active_users(&self, since: DateTime<Utc>) -> AppResult<i64> {
+        let count: i64 = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM (
+                SELECT id AS user_id FROM users WHERE last_seen_at >= $1
+                UNION
+                SELECT user_id FROM devices WHERE last_active_at >= $1
+                UNION
+                SELECT sender_id FROM messages WHERE created_at >= $1
+            ) active
+            "#,
+        )
+        .bind(since)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(count)
+    }
+
+    /// Row counts per day by `created_at` for one of the fixed tables above
+    async fn daily_counts(&self, table: &str, since: DateTime<Utc>) -> AppResult<Vec<DailyCount>> {
+        let query = format!(
+            r#"
+            SELECT (created_at AT TIME ZONE 'UTC')::date AS day, COUNT(*) AS count
+            FROM {}
+            WHERE created_at >= $1
+            GROUP BY day
+            ORDER BY day ASC
+            "#,
+            table
+        );
+
+        let counts: Vec<DailyCount> = sqlx::query_as(&query)
+            .bind(since)
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(counts)
+    }
+}
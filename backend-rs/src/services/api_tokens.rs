@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::ApiTokenConfig,
+    error::{AppError, AppResult},
+    models::{ApiToken, CreatedApiToken, Scope},
+    services::auth::Claims,
+    storage::redis::RedisClient,
+};
+
+/// Marks a bearer token as an API token rather than a session JWT
+pub const TOKEN_PREFIX: &str = "atk_";
+
+/// Characters of a token kept to tell tokens apart
+const DISPLAY_PREFIX_LEN: usize = 12;
+
+/// Window of the per-token rate limit
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// How often a token's last use is written
+const LAST_USED_THROTTLE: Duration = Duration::from_secs(60);
+
+/// Longest a token can be created for
+const MAX_EXPIRY_DAYS: i64 = 365;
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+pub struct ApiTokenService {
+    db: PgPool,
+    redis: RedisClient,
+    config: ApiTokenConfig,
+}
+
+impl ApiTokenService {
+    pub fn new(db: PgPool, redis: RedisClient, config: ApiTokenConfig) -> Self {
+        Self { db, redis, config }
+    }
+
+    /// Create a token; the returned token is not stored and cannot be shown again
+    pub async fn create(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        scopes: &[Scope],
+        expires_in_days: Option<i64>,
+        rate_limit: Option<u32>,
+    ) -> AppResult<CreatedApiToken> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > 64 {
+            return Err(AppError::Validation(
+                "Token names are 1-64 characters".to_string(),
+            ));
+        }
+        if scopes.is_empty() {
+            return Err(AppError::Validation(
+                "Tokens need at least one scope".to_string(),
+            ));
+        }
+        if let Some(scope) = scopes.iter().find(|s| !Scope::TOKEN.contains(s)) {
+            return Err(AppError::Validation(format!(
+                "Scope not available to API tokens: {}",
+                scope.as_str()
+            )));
+        }
+        if expires_in_days.is_some_and(|days| !(1..=MAX_EXPIRY_DAYS).contains(&days)) {
+            return Err(AppError::Validation(format!(
+                "Tokens expire within 1-{} days",
+                MAX_EXPIRY_DAYS
+            )));
+        }
+        let rate_limit = rate_limit.unwrap_or(self.config.default_rate_limit);
+        if rate_limit == 0 || rate_limit > self.config.max_rate_limit {
+            return Err(AppError::Validation(format!(
+                "Rate limits are 1-{} requests per minute",
+                self.config.max_rate_limit
+            )));
+        }
+
+        let count: i64 = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM api_tokens
+            WHERE user_id = $1 AND revoked_at IS NULL
+            AND (expires_at IS NULL OR expires_at > NOW())
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(&self.db)
+        .await?;
+        if count >= self.config.max_per_user {
+            return Err(AppError::Validation(format!(
+                "At most {} active API tokens per account",
+                self.config.max_per_user
+            )));
+        }
+
+        let token = {
+            let mut bytes = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            format!("{}{}", TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(bytes))
+        };
+        let mut scopes: Vec<&str> = scopes.iter().map(Scope::as_str).collect();
+        scopes.sort_unstable();
+        scopes.dedup();
+        let expires_at = expires_in_days.map(|days| Utc::now() + chrono::Duration::days(days));
+
+        let api_token = sqlx::query_as(
+            r#"
+            INSERT INTO api_tokens
+                (id, user_id, name, token_hash, token_prefix, scopes, rate_limit, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(name)
+        .bind(hash_token(&token))
+        .bind(&token[..DISPLAY_PREFIX_LEN])
+        .bind(&scopes)
+        .bind(rate_limit as i32)
+        .bind(expires_at)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(CreatedApiToken { api_token, token })
+    }
+
+    /// A user's tokens, revoked and expired ones included
+    pub async fn list(&self, user_id: Uuid) -> AppResult<Vec<ApiToken>> {
+        self.list_all(Some(user_id)).await
+    }
+
+    /// Tokens of one user, or of everyone, newest first
+    pub async fn list_all(&self, user_id: Option<Uuid>) -> AppResult<Vec<ApiToken>> {
+        let tokens = sqlx::query_as(
+            r#"
+            SELECT * FROM api_tokens
+            WHERE $1::UUID IS NULL OR user_id = $1
+            ORDER BY created_at DESC
+            LIMIT 500
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    /// Revoke a token; only the owner's when `user_id` is given
+    pub async fn revoke(&self, user_id: Option<Uuid>, token_id: Uuid) -> AppResult<()> {
+        let result = sqlx::query(
+            r#"
+            UPDATE api_tokens SET revoked_at = NOW()
+            WHERE id = $1 AND ($2::UUID IS NULL OR user_id = $2) AND revoked_at IS NULL
+            "#,
+        )
+        .bind(token_id)
+        .bind(user_id)
+        .execute(&self.db)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::ApiTokenNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Claims for a live token, counting the request against its rate limit
+    pub async fn authenticate(&self, token: &str, ip: Option<&str>) -> AppResult<Claims> {
+        let api_token: ApiToken = sqlx::query_as(
+            r#"
+            SELECT t.* FROM api_tokens t
+            JOIN users u ON u.id = t.user_id
+            WHERE t.token_hash = $1 AND t.revoked_at IS NULL
+            AND (t.expires_at IS NULL OR t.expires_at > NOW())
+            AND u.banned_at IS NULL AND u.deactivated_at IS NULL
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::InvalidToken)?;
+
+        let count = self
+            .redis
+            .incr_rate_limit(&format!("api_token:{}", api_token.id), RATE_LIMIT_WINDOW)
+            .await?;
+        if count > api_token.rate_limit as i64 {
+            return Err(AppError::ApiTokenRateLimited);
+        }
+
+        let key = format!("api_token_used:{}", api_token.id);
+        if self.redis.try_throttle(&key, LAST_USED_THROTTLE).await? {
+            sqlx::query(
+                "UPDATE api_tokens SET last_used_at = NOW(), last_used_ip = $2 WHERE id = $1",
+            )
+            .bind(api_token.id)
+            .bind(ip)
+            .execute(&self.db)
+            .await?;
+        }
+
+        let now = Utc::now();
+        Ok(Claims {
+            sub: api_token.user_id.to_string(),
+            // API tokens belong to no device
+            device_id: "0".to_string(),
+            iss: TOKEN_PREFIX.to_string(),
+            exp: api_token
+                .expires_at
+                .map_or(i64::MAX, |expires_at| expires_at.timestamp()),
+            iat: now.timestamp(),
+            scopes: Some(
+                api_token
+                    .scopes
+                    .iter()
+                    .filter_map(|s| Scope::parse(s))
+                    .collect(),
+            ),
+        })
+    }
+}
@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::ConversationAppearance,
+    services::messaging::WsMessage,
+    storage::{blob::BlobStorage, redis::RedisClient},
+};
+
+pub struct AppearanceService {
+    db: PgPool,
+    redis: RedisClient,
+    storage: Arc<dyn BlobStorage>,
+}
+
+impl AppearanceService {
+    pub fn new(db: PgPool, redis: RedisClient, storage: Arc<dyn BlobStorage>) -> Self {
+        Self { db, redis, storage }
+    }
+
+    /// Get the user's appearance settings for a conversation (defaults if unset)
+    pub async fn get_appearance(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+    ) -> AppResult<ConversationAppearance> {
+        self.ensure_participant(conversation_id, user_id).await?;
+
+        let appearance: Option<ConversationAppearance> = sqlx::query_as(
+            r#"
+            SELECT conversation_id, wallpaper_url, theme_color, reaction_emoji, updated_at
+            FROM conversation_appearance
+            WHERE conversation_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(appearance.unwrap_or(ConversationAppearance {
+            conversation_id,
+            ..Default::default()
+        }))
+    }
+
+    /// Update theme color and reaction emoji; `None` leaves a field unchanged
+    pub async fn update_appearance(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        theme_color: Option<String>,
+        reaction_emoji: Option<String>,
+    ) -> AppResult<ConversationAppearance> {
+        self.ensure_participant(conversation_id, user_id).await?;
+
+        let appearance: ConversationAppearance = sqlx::query_as(
+            r#"
+            INSERT INTO conversation_appearance (conversation_id, user_id, theme_color, reaction_emoji)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (conversation_id, user_id) DO UPDATE SET
+                theme_color = COALESCE($3, conversation_appearance.theme_color),
+                reaction_emoji = COALESCE($4, conversation_appearance.reaction_emoji),
+                updated_at = NOW()
+            RETURNING conversation_id, wallpaper_url, theme_color, reaction_emoji, updated_at
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .bind(theme_color)
+        .bind(reaction_emoji)
+        .fetch_one(&self.db)
+        .await?;
+
+        self.sync_to_devices(user_id, &appearance).await?;
+
+        Ok(appearance)
+    }
+
+    /// Upload a wallpaper image and attach it to the user's settings
+    pub async fn set_wallpaper(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        data: Bytes,
+        content_type: &str,
+    ) -> AppResult<ConversationAppearance> {
+        self.ensure_participant(conversation_id, user_id).await?;
+
+        let extension = match content_type {
+            "image/png" => "png",
+            "image/jpeg" | "image/jpg" => "jpg",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            _ => "bin",
+        };
+
+        // Versioned below since the key is reused across uploads
+        let key = format!("wallpapers/{}/{}.{}", user_id, conversation_id, extension);
+        let url = self
+            .storage
+            .upload_file(self.storage.attachments_bucket(), &key, data, content_type)
+            .await?;
+        let wallpaper_url = format!("{}?v={}", url, Utc::now().timestamp());
+
+        let appearance: ConversationAppearance = sqlx::query_as(
+            r#"
+            INSERT INTO conversation_appearance (conversation_id, user_id, wallpaper_url)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (conversation_id, user_id) DO UPDATE SET
+                wallpaper_url = EXCLUDED.wallpaper_url,
+                updated_at = NOW()
+            RETURNING conversation_id, wallpaper_url, theme_color, reaction_emoji, updated_at
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .bind(&wallpaper_url)
+        .fetch_one(&self.db)
+        .await?;
+
+        self.sync_to_devices(user_id, &appearance).await?;
+
+        Ok(appearance)
+    }
+
+    /// Reset the user's settings for a conversation to the defaults
+    pub async fn reset_appearance(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+    ) -> AppResult<ConversationAppearance> {
+        self.ensure_participant(conversation_id, user_id).await?;
+
+        sqlx::query(
+            "DELETE FROM conversation_appearance WHERE conversation_id = $1 AND user_id = $2",
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .execute(&self.db)
+        .await?;
+
+        let appearance = ConversationAppearance {
+            conversation_id,
+            updated_at: Some(Utc::now()),
+            ..Default::default()
+        };
+
+        self.sync_to_devices(user_id, &appearance).await?;
+
+        Ok(appearance)
+    }
+
+    async fn ensure_participant(&self, conversation_id: Uuid, user_id: Uuid) -> AppResult<()> {
+        let is_participant: Option<(i32,)> = sqlx::query_as(
+            "SELECT 1 FROM participants WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL",
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        if is_participant.is_none() {
+            return Err(AppError::NotParticipant);
+        }
+
+        Ok(())
+    }
+
+    /// Let the user's other devices pick up the change
+    async fn sync_to_devices(
+        &self,
+        user_id: Uuid,
+        appearance: &ConversationAppearance,
+    ) -> AppResult<()> {
+        let ws_message = WsMessage {
+            msg_type: "appearance_updated".to_string(),
+            payload: serde_json::to_value(appearance)?,
+        };
+
+        self.redis
+            .publish_message(&user_id.to_string(), &serde_json::to_string(&ws_message)?)
+            .await
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{error::AppResult, models::AdminAuditEntry};
+
+/// Fields whose values are never stored, matched anywhere in the name
+const REDACTED_FIELDS: [&str; 6] = ["password", "secret", "token", "key", "otp", "code"];
+
+/// Strings longer than this are cut short
+const MAX_STRING_CHARS: usize = 200;
+
+/// Array items kept; the rest are counted
+const MAX_ARRAY_ITEMS: usize = 20;
+
+/// Nesting kept; deeper values are replaced by their type
+const MAX_DEPTH: usize = 4;
+
+/// A request's payload as stored in the audit log
+pub fn summarize_payload(value: &Value) -> Value {
+    summarize(value, 0)
+}
+
+fn summarize(value: &Value, depth: usize) -> Value {
+    match value {
+        Value::String(s) if s.chars().count() > MAX_STRING_CHARS => {
+            let cut: String = s.chars().take(MAX_STRING_CHARS).collect();
+            Value::String(format!("{}… ({} chars)", cut, s.chars().count()))
+        }
+        Value::Array(_) | Value::Object(_) if depth >= MAX_DEPTH => {
+            Value::String(if value.is_array() { "[…]" } else { "{…}" }.to_string())
+        }
+        Value::Array(items) => {
+            let mut kept: Vec<Value> = items
+                .iter()
+                .take(MAX_ARRAY_ITEMS)
+                .map(|item| summarize(item, depth + 1))
+                .collect();
+            if items.len() > MAX_ARRAY_ITEMS {
+                kept.push(Value::String(format!(
+                    "… {} more",
+                    items.len() - MAX_ARRAY_ITEMS
+                )));
+            }
+            Value::Array(kept)
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| {
+                    let lower = name.to_lowercase();
+                    let value = if REDACTED_FIELDS.iter().any(|f| lower.contains(f)) {
+                        Value::String("[redacted]".to_string())
+                    } else {
+                        summarize(value, depth + 1)
+                    };
+                    (name.clone(), value)
+                })
+                .collect::<Map<_, _>>(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// One admin request, before it is stored
+pub struct AuditRecord<'a> {
+    pub actor_id: Option<Uuid>,
+    pub method: &'a str,
+    pub route: &'a str,
+    pub path: &'a str,
+    pub payload: Value,
+    pub status: u16,
+    pub error: Option<String>,
+    pub ip_address: Option<String>,
+    pub duration: Duration,
+}
+
+/// Who did what through the admin API
+pub struct AuditLog {
+    db: PgPool,
+}
+
+impl AuditLog {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    pub async fn record(&self, record: AuditRecord<'_>) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO admin_audit_log
+                (actor_id, method, route, path, payload, status, error, ip_address, duration_ms)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(record.actor_id)
+        .bind(record.method)
+        .bind(record.route)
+        .bind(record.path)
+        .bind(&record.payload)
+        .bind(record.status as i16)
+        .bind(&record.error)
+        .bind(&record.ip_address)
+        .bind(record.duration.as_millis().min(i32::MAX as u128) as i32)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Entries newest first, optionally by one admin or on routes starting
+    /// with `route`; `before` pages back from an entry's `id`
+    pub async fn list(
+        &self,
+        actor_id: Option<Uuid>,
+        route: Option<&str>,
+        before: Option<i64>,
+        limit: i64,
+    ) -> AppResult<Vec<AdminAuditEntry>> {
+        let entries = sqlx::query_as(
+            r#"
+            SELECT * FROM admin_audit_log
+            WHERE ($1::UUID IS NULL OR actor_id = $1)
+            AND ($2::TEXT IS NULL OR starts_with(route, $2))
+            AND ($3::BIGINT IS NULL OR id < $3)
+            ORDER BY id DESC
+            LIMIT $4
+            "#,
+        )
+        .bind(actor_id)
+        .bind(route)
+        .bind(before)
+        .bind(limit.clamp(1, 500))
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(entries)
+    }
+
+    /// Delete entries older than the retention; zero keeps everything
+    pub async fn purge_expired(&self, retention: Duration) -> AppResult<u64> {
+        if retention.is_zero() {
+            return Ok(0);
+        }
+
+        let result = sqlx::query(
+            "DELETE FROM admin_audit_log WHERE created_at < NOW() - make_interval(secs => $1)",
+        )
+        .bind(retention.as_secs_f64())
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
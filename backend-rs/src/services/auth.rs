@@ -1,15 +1,28 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use bcrypt::{hash, verify, DEFAULT_COST};
-use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode, decode_header, encode, DecodingKey, EncodingKey, Header, Validation};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::{
     config::Config,
     error::{AppError, AppResult},
-    models::{Device, Otp, OtpType, Session, TokenPair, User, UserStatus},
+    models::{
+        ClientInfo, Device, DeviceLink, DeviceLinkStatus, NewLoginAlert, Otp, OtpType,
+        PendingIdentifierChange, Scope, Session, SessionInfo, SignupMode, TokenPair, User,
+        UserStatus,
+    },
+    services::{
+        events::{DomainEvent, EventLog},
+        invites::InviteService,
+        profiles::ProfileService,
+        usernames::{map_username_conflict, normalize_username, UsernameService},
+    },
     storage::redis::RedisClient,
 };
 
@@ -20,6 +33,34 @@ pub struct Claims {
     pub iss: String,       // issuer
     pub exp: i64,          // expiry
     pub iat: i64,          // issued at
+    /// Set on linked sessions and API tokens; others may do everything
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<Scope>>,
+}
+
+impl Claims {
+    /// Whether the session may use routes needing any of `scopes`
+    pub fn allows(&self, scopes: &[Scope]) -> bool {
+        self.scopes
+            .as_ref()
+            .is_none_or(|granted| scopes.iter().any(|s| granted.contains(s)))
+    }
+}
+
+fn link_key(link_id: Uuid) -> String {
+    format!("device_link:{}", link_id)
+}
+
+/// A secret handed to a device when it is created, for signing the same
+/// device back in later. Only its SHA-256 is stored.
+fn new_device_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn hash_device_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
 }
 
 pub struct AuthService {
@@ -59,13 +100,24 @@ impl AuthService {
             .set_otp(target, &code, self.config.otp.ttl)
             .await?;
 
-        // Send OTP via SMS or Email
+        self.deliver_otp(target, otp_type, &code).await
+    }
+
+    /// Send a fresh code to `target` without storing it, for flows that keep
+    /// and check their own code
+    pub async fn send_code(&self, target: &str, otp_type: OtpType) -> AppResult<String> {
+        let code = self.generate_otp();
+        self.deliver_otp(target, otp_type, &code).await?;
+
+        Ok(code)
+    }
+
+    /// Send OTP via SMS or Email
+    async fn deliver_otp(&self, target: &str, otp_type: OtpType, code: &str) -> AppResult<()> {
         match otp_type {
-            OtpType::Phone => self.send_sms(target, &code).await?,
-            OtpType::Email => self.send_email(target, &code).await?,
+            OtpType::Phone => self.send_sms(target, code).await,
+            OtpType::Email => self.send_email(target, code).await,
         }
-
-        Ok(())
     }
 
     pub async fn verify_otp(&self, target: &str, otp_type: OtpType, code: &str) -> AppResult<()> {
@@ -95,7 +147,7 @@ impl AuthService {
 
         let otp = otp.ok_or(AppError::InvalidOtp)?;
 
-        if otp.expires_at < Utc::now() {
+        if otp.expires_at + self.clock_skew() < Utc::now() {
             return Err(AppError::OtpExpired);
         }
 
@@ -121,7 +173,200 @@ impl AuthService {
         Ok(())
     }
 
+    // Phone/email change
+    /// Start changing the account's phone or email. Codes go to the new target
+    /// and to the current one (or, when adding a first phone/email, to the
+    /// identifier the account already has).
+    pub async fn start_identifier_change(
+        &self,
+        user_id: Uuid,
+        otp_type: OtpType,
+        new_target: &str,
+    ) -> AppResult<PendingIdentifierChange> {
+        let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+
+        let (current, other) = match otp_type {
+            OtpType::Phone => (user.phone.clone(), user.email.clone().map(|e| (OtpType::Email, e))),
+            OtpType::Email => (user.email.clone(), user.phone.clone().map(|p| (OtpType::Phone, p))),
+        };
+
+        if current.as_deref() == Some(new_target) {
+            return Err(AppError::BadRequest(
+                "New target is the same as the current one".to_string(),
+            ));
+        }
+
+        self.ensure_identifier_available(user_id, new_target).await?;
+
+        let (confirm_type, confirm_target) = current
+            .map(|c| (otp_type, c))
+            .or(other)
+            .ok_or(AppError::BadRequest("Account has no phone or email".to_string()))?;
+
+        let pending = PendingIdentifierChange {
+            otp_type,
+            new_target: new_target.to_string(),
+            confirm_type,
+            confirm_target,
+        };
+
+        self.redis
+            .set_cached(
+                &format!("identifier_change:{}", user_id),
+                &serde_json::to_string(&pending)?,
+                self.config.otp.ttl,
+            )
+            .await?;
+
+        self.send_otp(&pending.new_target, pending.otp_type).await?;
+        self.send_otp(&pending.confirm_target, pending.confirm_type)
+            .await?;
+
+        Ok(pending)
+    }
+
+    /// Finish a phone/email change with the codes sent to the new and current targets
+    pub async fn confirm_identifier_change(
+        &self,
+        user_id: Uuid,
+        new_code: &str,
+        confirm_code: &str,
+    ) -> AppResult<(User, PendingIdentifierChange)> {
+        let key = format!("identifier_change:{}", user_id);
+        let pending: PendingIdentifierChange = match self.redis.get_cached(&key).await? {
+            Some(raw) => serde_json::from_str(&raw)?,
+            None => return Err(AppError::OtpExpired),
+        };
+
+        self.verify_otp_once(&pending.new_target, pending.otp_type, new_code)
+            .await?;
+        self.verify_otp_once(&pending.confirm_target, pending.confirm_type, confirm_code)
+            .await?;
+
+        // The target may have been claimed while the codes were in flight
+        self.ensure_identifier_available(user_id, &pending.new_target)
+            .await?;
+
+        let mut tx = self.db.begin().await?;
+
+        let query = match pending.otp_type {
+            OtpType::Phone => {
+                "UPDATE users SET phone = $1, updated_at = NOW() WHERE id = $2 RETURNING *"
+            }
+            OtpType::Email => {
+                "UPDATE users SET email = $1, updated_at = NOW() WHERE id = $2 RETURNING *"
+            }
+        };
+        let user: User = sqlx::query_as(query)
+            .bind(&pending.new_target)
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            "DELETE FROM otps WHERE (target = $1 AND type = $2) OR (target = $3 AND type = $4)",
+        )
+        .bind(&pending.new_target)
+        .bind(pending.otp_type)
+        .bind(&pending.confirm_target)
+        .bind(pending.confirm_type)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        self.redis.delete_cached(&key).await?;
+        self.publish_identifier_change(&user, &pending).await?;
+
+        Ok((user, pending))
+    }
+
+    /// Tell the user's devices about the new identifier, and the people who
+    /// have the user as a contact to refresh their discovery data
+    async fn publish_identifier_change(
+        &self,
+        user: &User,
+        pending: &PendingIdentifierChange,
+    ) -> AppResult<()> {
+        let own = json!({
+            "type": "identifier_changed",
+            "payload": { "type": pending.otp_type, "user": user },
+        });
+        self.redis
+            .publish_message(&user.id.to_string(), &own.to_string())
+            .await?;
+
+        let watcher_ids: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT user_id FROM contacts WHERE contact_id = $1 AND is_blocked = false",
+        )
+        .bind(user.id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let contact_updated = json!({
+            "type": "contact_updated",
+            "payload": { "user_id": user.id },
+        })
+        .to_string();
+        for watcher_id in watcher_ids {
+            self.redis
+                .publish_message(&watcher_id.to_string(), &contact_updated)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Like `verify_otp`, but a code already verified stays accepted so one
+    /// wrong code doesn't force resending both
+    pub async fn verify_otp_once(
+        &self,
+        target: &str,
+        otp_type: OtpType,
+        code: &str,
+    ) -> AppResult<()> {
+        let verified: Option<bool> = sqlx::query_scalar(
+            r#"
+            SELECT verified FROM otps
+            WHERE target = $1 AND type = $2
+            AND expires_at > NOW() - make_interval(secs => $3)
+            "#,
+        )
+        .bind(target)
+        .bind(otp_type)
+        .bind(self.config.server.clock_skew.as_secs_f64())
+        .fetch_optional(&self.db)
+        .await?;
+
+        if verified == Some(true) {
+            return Ok(());
+        }
+
+        self.verify_otp(target, otp_type, code).await
+    }
+
+    async fn ensure_identifier_available(&self, user_id: Uuid, target: &str) -> AppResult<()> {
+        let taken: Option<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM users WHERE (phone = $1 OR email = $1) AND id != $2 LIMIT 1",
+        )
+        .bind(target)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        if taken.is_some() {
+            return Err(AppError::UserAlreadyExists);
+        }
+
+        Ok(())
+    }
+
     // User Registration
+    #[allow(clippy::too_many_arguments)]
     pub async fn register(
         &self,
         phone: Option<&str>,
@@ -130,7 +375,15 @@ impl AuthService {
         display_name: &str,
         device_name: &str,
         platform: &str,
+        invite_code: Option<&str>,
+        client: &ClientInfo,
     ) -> AppResult<(User, TokenPair)> {
+        let invite_code = invite_code.map(str::trim).filter(|c| !c.is_empty());
+        let signup_mode = SignupMode::from_config(&self.config.signup.mode);
+        if signup_mode == SignupMode::Invite && invite_code.is_none() {
+            return Err(AppError::InviteRequired);
+        }
+
         // Check if OTP was verified
         let target = phone.or(email).ok_or(AppError::BadRequest(
             "Phone or email required".to_string(),
@@ -165,6 +418,30 @@ impl AuthService {
             return Err(AppError::UserAlreadyExists);
         }
 
+        // Without an invite, waitlisted signups need an admin's approval
+        if signup_mode == SignupMode::Waitlist && invite_code.is_none() {
+            let approved: bool = sqlx::query_scalar(
+                r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM waitlist
+                    WHERE target = $1 AND target_type = $2 AND status = 'approved'
+                )
+                "#,
+            )
+            .bind(target)
+            .bind(otp_type)
+            .fetch_one(&self.db)
+            .await?;
+            if !approved {
+                return Err(AppError::WaitlistNotApproved);
+            }
+        }
+
+        let usernames = UsernameService::new(self.db.clone(), self.redis.clone());
+        let username = usernames
+            .ensure_available(username, Some(target), None)
+            .await?;
+
         // Create user in transaction
         let mut tx = self.db.begin().await?;
 
@@ -179,18 +456,35 @@ impl AuthService {
         .bind(user_id)
         .bind(phone)
         .bind(email)
-        .bind(username)
+        .bind(&username)
         .bind(display_name)
         .bind(UserStatus::Online)
         .fetch_one(&mut *tx)
+        .await
+        .map_err(map_username_conflict)?;
+
+        if let Some(code) = invite_code {
+            InviteService::redeem(&mut tx, code, user_id).await?;
+        }
+        sqlx::query(
+            r#"
+            UPDATE waitlist SET status = 'registered', user_id = $3
+            WHERE target = $1 AND target_type = $2
+            "#,
+        )
+        .bind(target)
+        .bind(otp_type)
+        .bind(user_id)
+        .execute(&mut *tx)
         .await?;
 
         // Create device
         let device_id = 1;
+        let device_token = new_device_token();
         let _device: Device = sqlx::query_as(
             r#"
-            INSERT INTO devices (id, user_id, device_id, name, platform, last_active_at)
-            VALUES ($1, $2, $3, $4, $5, NOW())
+            INSERT INTO devices (id, user_id, device_id, name, platform, verified, token_hash, last_active_at)
+            VALUES ($1, $2, $3, $4, $5, true, $6, NOW())
             RETURNING *
             "#,
         )
@@ -199,11 +493,15 @@ impl AuthService {
         .bind(device_id)
         .bind(device_name)
         .bind(platform)
+        .bind(hash_device_token(&device_token))
         .fetch_one(&mut *tx)
         .await?;
 
         // Generate tokens
-        let tokens = self.generate_token_pair(&user_id.to_string(), &device_id.to_string())?;
+        let mut tokens = self
+            .generate_token_pair(&user_id.to_string(), &device_id.to_string(), None)
+            .await?;
+        tokens.device_token = Some(device_token);
 
         // Store session
         let token_hash = hash(&tokens.access_token, DEFAULT_COST)
@@ -213,8 +511,8 @@ impl AuthService {
 
         sqlx::query(
             r#"
-            INSERT INTO sessions (id, user_id, device_id, token_hash, refresh_token_hash, expires_at, last_used_at)
-            VALUES ($1, $2, $3, $4, $5, $6, NOW())
+            INSERT INTO sessions (id, user_id, device_id, token_hash, refresh_token_hash, expires_at, last_used_at, ip_address, user_agent, country)
+            VALUES ($1, $2, $3, $4, $5, $6, NOW(), $7, $8, $9)
             "#,
         )
         .bind(Uuid::new_v4())
@@ -223,9 +521,15 @@ impl AuthService {
         .bind(token_hash)
         .bind(refresh_hash)
         .bind(tokens.expires_at)
+        .bind(&client.ip_address)
+        .bind(&client.user_agent)
+        .bind(&client.country)
         .execute(&mut *tx)
         .await?;
 
+        self.record_login_event(&mut *tx, user_id, device_id, client)
+            .await?;
+
         // Delete OTP
         sqlx::query("DELETE FROM otps WHERE target = $1 AND type = $2")
             .bind(target)
@@ -235,6 +539,16 @@ impl AuthService {
 
         tx.commit().await?;
 
+        usernames.release(&username).await?;
+
+        EventLog::new(self.db.clone(), &self.config.analytics)
+            .record(DomainEvent::UserRegistered {
+                user_id,
+                platform,
+                method: if phone.is_some() { "phone" } else { "email" },
+            })
+            .await;
+
         Ok((user, tokens))
     }
 
@@ -245,7 +559,9 @@ impl AuthService {
         otp_type: OtpType,
         device_name: &str,
         platform: &str,
-    ) -> AppResult<(User, TokenPair)> {
+        device_token: Option<&str>,
+        client: &ClientInfo,
+    ) -> AppResult<(User, TokenPair, Option<NewLoginAlert>)> {
         // Check if OTP was verified
         let otp: Option<Otp> = sqlx::query_as(
             "SELECT * FROM otps WHERE target = $1 AND type = $2 AND verified = true",
@@ -276,65 +592,88 @@ impl AuthService {
         }
         .ok_or(AppError::UserNotFound)?;
 
-        // Get or create device
-        let device: Device = sqlx::query_as(
-            r#"
-            SELECT * FROM devices WHERE user_id = $1 AND name = $2 AND platform = $3
-            "#,
+        let banned: bool =
+            sqlx::query_scalar("SELECT banned_at IS NOT NULL FROM users WHERE id = $1")
+                .bind(user.id)
+                .fetch_one(&self.db)
+                .await?;
+        if banned {
+            return Err(AppError::UserBanned);
+        }
+
+        // Logging in is how a deactivated account comes back
+        let reactivated = sqlx::query(
+            "UPDATE users SET deactivated_at = NULL WHERE id = $1 AND deactivated_at IS NOT NULL",
         )
         .bind(user.id)
-        .bind(device_name)
-        .bind(platform)
-        .fetch_optional(&self.db)
-        .await?
-        .unwrap_or_else(|| Device {
-            id: Uuid::new_v4(),
-            user_id: user.id,
-            device_id: 0, // Will be set below
-            name: device_name.to_string(),
-            platform: platform.to_string(),
-            push_token: None,
-            last_active_at: Utc::now(),
-            created_at: Utc::now(),
-        });
-
-        let device_id = if device.device_id == 0 {
-            // Get next device_id
-            let max_device_id: Option<i32> = sqlx::query_scalar(
-                "SELECT MAX(device_id) FROM devices WHERE user_id = $1",
-            )
-            .bind(user.id)
-            .fetch_one(&self.db)
-            .await?;
+        .execute(&self.db)
+        .await?;
+        if reactivated.rows_affected() > 0 {
+            ProfileService::new(self.db.clone(), self.redis.clone())
+                .publish_deactivation(user.id, false)
+                .await?;
+        }
 
-            let new_device_id = max_device_id.unwrap_or(0) + 1;
+        // A device signs back in with the token it was given when created.
+        // Name and platform are whatever the client says, so they never pick
+        // an existing device and its verified or primary status.
+        let device: Option<Device> = match device_token {
+            Some(token) => {
+                sqlx::query_as("SELECT * FROM devices WHERE user_id = $1 AND token_hash = $2")
+                    .bind(user.id)
+                    .bind(hash_device_token(token))
+                    .fetch_optional(&self.db)
+                    .await?
+            }
+            None => None,
+        };
 
-            sqlx::query(
-                r#"
-                INSERT INTO devices (id, user_id, device_id, name, platform, last_active_at)
-                VALUES ($1, $2, $3, $4, $5, NOW())
-                "#,
-            )
-            .bind(device.id)
-            .bind(user.id)
-            .bind(new_device_id)
-            .bind(device_name)
-            .bind(platform)
-            .execute(&self.db)
-            .await?;
+        let (device_id, device_token) = match device {
+            Some(device) => {
+                // Update last active
+                sqlx::query("UPDATE devices SET last_active_at = NOW() WHERE id = $1")
+                    .bind(device.id)
+                    .execute(&self.db)
+                    .await?;
+                (device.device_id, None)
+            }
+            None => {
+                // Get next device_id
+                let max_device_id: Option<i32> = sqlx::query_scalar(
+                    "SELECT MAX(device_id) FROM devices WHERE user_id = $1",
+                )
+                .bind(user.id)
+                .fetch_one(&self.db)
+                .await?;
 
-            new_device_id
-        } else {
-            // Update last active
-            sqlx::query("UPDATE devices SET last_active_at = NOW() WHERE id = $1")
-                .bind(device.id)
+                let new_device_id = max_device_id.unwrap_or(0) + 1;
+                let device_token = new_device_token();
+
+                sqlx::query(
+                    r#"
+                    INSERT INTO devices (id, user_id, device_id, name, platform, token_hash, last_active_at)
+                    VALUES ($1, $2, $3, $4, $5, $6, NOW())
+                    "#,
+                )
+                .bind(Uuid::new_v4())
+                .bind(user.id)
+                .bind(new_device_id)
+                .bind(device_name)
+                .bind(platform)
+                .bind(hash_device_token(&device_token))
                 .execute(&self.db)
                 .await?;
-            device.device_id
+
+                (new_device_id, Some(device_token))
+            }
         };
+        let new_device = device_token.is_some();
 
         // Generate tokens
-        let tokens = self.generate_token_pair(&user.id.to_string(), &device_id.to_string())?;
+        let mut tokens = self
+            .generate_token_pair(&user.id.to_string(), &device_id.to_string(), None)
+            .await?;
+        tokens.device_token = device_token;
 
         // Store session
         let token_hash = hash(&tokens.access_token, DEFAULT_COST)
@@ -344,10 +683,11 @@ impl AuthService {
 
         sqlx::query(
             r#"
-            INSERT INTO sessions (id, user_id, device_id, token_hash, refresh_token_hash, expires_at, last_used_at)
-            VALUES ($1, $2, $3, $4, $5, $6, NOW())
+            INSERT INTO sessions (id, user_id, device_id, token_hash, refresh_token_hash, expires_at, last_used_at, ip_address, user_agent, country)
+            VALUES ($1, $2, $3, $4, $5, $6, NOW(), $7, $8, $9)
             ON CONFLICT (user_id, device_id)
-            DO UPDATE SET token_hash = $4, refresh_token_hash = $5, expires_at = $6, last_used_at = NOW()
+            DO UPDATE SET token_hash = $4, refresh_token_hash = $5, expires_at = $6, last_used_at = NOW(),
+                ip_address = $7, user_agent = $8, country = $9, scopes = NULL
             "#,
         )
         .bind(Uuid::new_v4())
@@ -356,9 +696,25 @@ impl AuthService {
         .bind(token_hash)
         .bind(refresh_hash)
         .bind(tokens.expires_at)
+        .bind(&client.ip_address)
+        .bind(&client.user_agent)
+        .bind(&client.country)
         .execute(&self.db)
         .await?;
 
+        // Compare against login history before recording this login
+        let new_location = self.is_new_location(user.id, client).await?;
+        self.record_login_event(&self.db, user.id, device_id, client)
+            .await?;
+
+        let alert = (new_device || new_location).then(|| NewLoginAlert {
+            device_id,
+            device_name: device_name.to_string(),
+            platform: platform.to_string(),
+            new_device,
+            client: client.clone(),
+        });
+
         // Delete OTP
         sqlx::query("DELETE FROM otps WHERE target = $1 AND type = $2")
             .bind(target)
@@ -373,21 +729,143 @@ impl AuthService {
             .execute(&self.db)
             .await?;
 
-        Ok((user, tokens))
+        Ok((user, tokens, alert))
+    }
+
+    /// List the user's active sessions, flagging the one making the request
+    pub async fn list_sessions(
+        &self,
+        user_id: Uuid,
+        current_device_id: i32,
+    ) -> AppResult<Vec<SessionInfo>> {
+        let mut sessions: Vec<SessionInfo> = sqlx::query_as(
+            r#"
+            SELECT s.id, s.device_id, d.name AS device_name, d.platform, s.ip_address,
+                   s.user_agent, s.country, s.expires_at, s.last_used_at, s.created_at,
+                   s.scopes
+            FROM sessions s
+            LEFT JOIN devices d ON d.user_id = s.user_id AND d.device_id = s.device_id
+            WHERE s.user_id = $1
+            ORDER BY s.last_used_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let idle_timeout = chrono::Duration::from_std(self.config.session.idle_timeout).ok();
+        for session in &mut sessions {
+            session.current = session.device_id == current_device_id;
+            session.idle_expires_at = idle_timeout
+                .filter(|t| !t.is_zero())
+                .map(|t| session.last_used_at + t);
+        }
+
+        Ok(sessions)
+    }
+
+    /// Record activity on a session and the app version its device runs,
+    /// writing at most once per throttle interval
+    pub async fn touch_session(
+        &self,
+        user_id: Uuid,
+        device_id: i32,
+        app_version: Option<&str>,
+    ) -> AppResult<()> {
+        let key = format!("session_activity:{}:{}", user_id, device_id);
+        if !self
+            .redis
+            .try_throttle(&key, self.config.session.activity_throttle)
+            .await?
+        {
+            return Ok(());
+        }
+
+        sqlx::query(
+            "UPDATE sessions SET last_used_at = NOW() WHERE user_id = $1 AND device_id = $2",
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .execute(&self.db)
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE devices SET last_active_at = NOW(), app_version = COALESCE($3, app_version)
+            WHERE user_id = $1 AND device_id = $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .bind(app_version)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Delete sessions unused for longer than the idle timeout. Their refresh
+    /// tokens stop working; access tokens run out on their own shortly after.
+    pub async fn expire_idle_sessions(&self) -> AppResult<u64> {
+        let idle_timeout = self.config.session.idle_timeout;
+        if idle_timeout.is_zero() {
+            return Ok(0);
+        }
+
+        let result = sqlx::query(
+            "DELETE FROM sessions WHERE last_used_at < NOW() - make_interval(secs => $1)",
+        )
+        .bind(idle_timeout.as_secs() as f64)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
     }
 
     // Token validation
-    pub fn validate_token(&self, token: &str) -> AppResult<Claims> {
-        let key = DecodingKey::from_secret(self.config.jwt.secret.as_bytes());
-        let validation = Validation::default();
+    pub async fn validate_token(&self, token: &str) -> AppResult<Claims> {
+        let secret = match decode_header(token)?.kid {
+            Some(kid) => sqlx::query_scalar("SELECT secret FROM jwt_signing_keys WHERE kid = $1")
+                .bind(kid)
+                .fetch_optional(&self.db)
+                .await?
+                .ok_or(AppError::InvalidToken)?,
+            None => {
+                // JWT_SECRET stops verifying one access-token lifetime after
+                // the first rotation, so rotating retires a leaked secret
+                let first_rotation: Option<DateTime<Utc>> =
+                    sqlx::query_scalar("SELECT MIN(created_at) FROM jwt_signing_keys")
+                        .fetch_one(&self.db)
+                        .await?;
+                if let Some(first_rotation) = first_rotation {
+                    let access_ttl =
+                        Duration::seconds(self.config.jwt.access_token_ttl.as_secs() as i64);
+                    if Utc::now() > first_rotation + access_ttl {
+                        return Err(AppError::InvalidToken);
+                    }
+                }
+                self.config.jwt.secret.clone()
+            }
+        };
+        let key = DecodingKey::from_secret(secret.as_bytes());
+        let mut validation = Validation::default();
+        validation.leeway = self.config.server.clock_skew.as_secs();
 
         let token_data = decode::<Claims>(token, &key, &validation)?;
+        // Issued in the future by more than another instance's clock can be off
+        if token_data.claims.iat > (Utc::now() + self.clock_skew()).timestamp() {
+            return Err(AppError::InvalidToken);
+        }
         Ok(token_data.claims)
     }
 
+    fn clock_skew(&self) -> Duration {
+        Duration::seconds(self.config.server.clock_skew.as_secs() as i64)
+    }
+
     // Refresh token
     pub async fn refresh_token(&self, refresh_token: &str) -> AppResult<TokenPair> {
-        let claims = self.validate_token(refresh_token)?;
+        let claims = self.validate_token(refresh_token).await?;
 
         // Check session exists
         let session: Option<Session> = sqlx::query_as(
@@ -407,8 +885,13 @@ impl AuthService {
             return Err(AppError::InvalidToken);
         }
 
-        // Generate new tokens
-        let tokens = self.generate_token_pair(&claims.sub, &claims.device_id)?;
+        // Generate new tokens, keeping the scopes the session was created with
+        let scopes = session
+            .scopes
+            .map(|scopes| scopes.iter().filter_map(|s| Scope::parse(s)).collect::<Vec<_>>());
+        let tokens = self
+            .generate_token_pair(&claims.sub, &claims.device_id, scopes.as_deref())
+            .await?;
 
         // Update session
         let token_hash = hash(&tokens.access_token, DEFAULT_COST)
@@ -466,7 +949,427 @@ impl AuthService {
         Ok(())
     }
 
+    /// Deactivate the user's own account: hide it and stop its pushes, and
+    /// sign it out everywhere. The next OTP login reactivates it.
+    pub async fn deactivate(&self, user_id: Uuid) -> AppResult<()> {
+        let result = sqlx::query(
+            "UPDATE users SET deactivated_at = NOW() WHERE id = $1 AND deactivated_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&self.db)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::BadRequest("Account is already deactivated".to_string()));
+        }
+
+        self.logout_all(user_id).await?;
+
+        ProfileService::new(self.db.clone(), self.redis.clone())
+            .publish_deactivation(user_id, true)
+            .await
+    }
+
+    /// Start linking a web session. The client shows `code` in a QR code and
+    /// polls with `secret` until a signed-in device approves it.
+    pub async fn start_link(
+        &self,
+        device_name: &str,
+        platform: &str,
+        client: &ClientInfo,
+    ) -> AppResult<(Uuid, DeviceLink)> {
+        let ttl = self.config.session.link_ttl;
+        let (code, secret) = {
+            let mut rng = rand::thread_rng();
+            (rng.gen::<[u8; 16]>(), rng.gen::<[u8; 32]>())
+        };
+        let link_id = Uuid::new_v4();
+        let link = DeviceLink {
+            device_name: device_name.to_string(),
+            platform: platform.to_string(),
+            code: URL_SAFE_NO_PAD.encode(code),
+            secret: URL_SAFE_NO_PAD.encode(secret),
+            status: DeviceLinkStatus::Pending,
+            user_id: None,
+            scopes: Vec::new(),
+            ip_address: client.ip_address.clone(),
+            user_agent: client.user_agent.clone(),
+            country: client.country.clone(),
+            expires_at: Utc::now() + Duration::seconds(ttl.as_secs() as i64),
+        };
+
+        self.redis
+            .set_cached(&link_key(link_id), &serde_json::to_string(&link)?, ttl)
+            .await?;
+
+        Ok((link_id, link))
+    }
+
+    /// Approve or decline a link from a device that scanned its QR code. The
+    /// linked session gets `scopes`, at most `Scope::LINKABLE`.
+    pub async fn approve_link(
+        &self,
+        user_id: Uuid,
+        approver_device_id: i32,
+        link_id: Uuid,
+        code: &str,
+        approved: bool,
+        scopes: Vec<Scope>,
+    ) -> AppResult<DeviceLink> {
+        if let Some(scope) = scopes.iter().find(|s| !Scope::LINKABLE.contains(s)) {
+            return Err(AppError::Validation(format!(
+                "Linked sessions cannot have the {} scope",
+                scope.as_str()
+            )));
+        }
+        if approved && scopes.is_empty() {
+            return Err(AppError::Validation("At least one scope required".to_string()));
+        }
+
+        let verified: bool = sqlx::query_scalar(
+            "SELECT verified FROM devices WHERE user_id = $1 AND device_id = $2",
+        )
+        .bind(user_id)
+        .bind(approver_device_id)
+        .fetch_optional(&self.db)
+        .await?
+        .unwrap_or(false);
+        if !verified {
+            return Err(AppError::DeviceNotVerified);
+        }
+
+        let key = link_key(link_id);
+        let raw = self
+            .redis
+            .get_cached(&key)
+            .await?
+            .ok_or(AppError::DeviceLinkNotFound)?;
+        let mut link: DeviceLink = serde_json::from_str(&raw)?;
+        let remaining = (link.expires_at - Utc::now()).to_std().unwrap_or_default();
+        if link.code != code || link.status != DeviceLinkStatus::Pending || remaining.is_zero() {
+            return Err(AppError::DeviceLinkNotFound);
+        }
+
+        link.status = if approved {
+            DeviceLinkStatus::Approved
+        } else {
+            DeviceLinkStatus::Declined
+        };
+        link.user_id = Some(user_id);
+        link.scopes = Scope::LINKABLE
+            .into_iter()
+            .filter(|s| scopes.contains(s))
+            .collect();
+        // Another approval or decline may have landed since the read
+        if !self
+            .redis
+            .replace_cached(&key, &raw, &serde_json::to_string(&link)?)
+            .await?
+        {
+            return Err(AppError::DeviceLinkNotFound);
+        }
+
+        Ok(link)
+    }
+
+    /// Finish linking once approved: creates the device and its scoped
+    /// session. `None` while the link is still waiting for approval.
+    pub async fn claim_link(
+        &self,
+        link_id: Uuid,
+        secret: &str,
+    ) -> AppResult<Option<(User, TokenPair, Vec<Scope>)>> {
+        let key = link_key(link_id);
+        let link: DeviceLink = match self.redis.get_cached(&key).await? {
+            Some(raw) => serde_json::from_str(&raw)?,
+            None => return Err(AppError::DeviceLinkNotFound),
+        };
+        if link.secret != secret {
+            return Err(AppError::DeviceLinkNotFound);
+        }
+
+        if link.status == DeviceLinkStatus::Pending {
+            return Ok(None);
+        }
+
+        // Single use: of two claims at once, only the one that takes it goes on
+        let link: DeviceLink = match self.redis.take_cached(&key).await? {
+            Some(raw) => serde_json::from_str(&raw)?,
+            None => return Err(AppError::DeviceLinkNotFound),
+        };
+        if link.secret != secret {
+            return Err(AppError::DeviceLinkNotFound);
+        }
+        let user_id = match (link.status, link.user_id) {
+            (DeviceLinkStatus::Approved, Some(user_id)) => user_id,
+            _ => return Err(AppError::DeviceLinkDeclined),
+        };
+
+        let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1 AND banned_at IS NULL")
+            .bind(user_id)
+            .fetch_optional(&self.db)
+            .await?
+            .ok_or(AppError::UserBanned)?;
+
+        let mut tx = self.db.begin().await?;
+
+        let device_id: i32 = sqlx::query_scalar(
+            r#"
+            INSERT INTO devices (id, user_id, device_id, name, platform, verified, last_active_at)
+            SELECT $1, $2, COALESCE(MAX(device_id), 0) + 1, $3, $4, true, NOW()
+            FROM devices WHERE user_id = $2
+            RETURNING device_id
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(&link.device_name)
+        .bind(&link.platform)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let tokens = self
+            .generate_token_pair(
+                &user_id.to_string(),
+                &device_id.to_string(),
+                Some(&link.scopes),
+            )
+            .await?;
+
+        let token_hash = hash(&tokens.access_token, DEFAULT_COST)
+            .map_err(|e| anyhow::anyhow!("Hash error: {}", e))?;
+        let refresh_hash = hash(&tokens.refresh_token, DEFAULT_COST)
+            .map_err(|e| anyhow::anyhow!("Hash error: {}", e))?;
+        let scopes: Vec<&str> = link.scopes.iter().map(Scope::as_str).collect();
+
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, user_id, device_id, token_hash, refresh_token_hash, expires_at, last_used_at, ip_address, user_agent, country, scopes)
+            VALUES ($1, $2, $3, $4, $5, $6, NOW(), $7, $8, $9, $10)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(device_id)
+        .bind(token_hash)
+        .bind(refresh_hash)
+        .bind(tokens.expires_at)
+        .bind(&link.ip_address)
+        .bind(&link.user_agent)
+        .bind(&link.country)
+        .bind(&scopes)
+        .execute(&mut *tx)
+        .await?;
+
+        let client = ClientInfo {
+            ip_address: link.ip_address,
+            user_agent: link.user_agent,
+            country: link.country,
+        };
+        self.record_login_event(&mut *tx, user_id, device_id, &client)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(Some((user, tokens, link.scopes)))
+    }
+
+    /// Create an admin account, or promote the existing user with that
+    /// username. Operators may use reserved names such as `admin`. The account
+    /// signs in through the normal OTP login. Returns the user and whether it
+    /// was created.
+    pub async fn create_admin_user(
+        &self,
+        username: &str,
+        display_name: &str,
+        email: Option<&str>,
+        phone: Option<&str>,
+    ) -> AppResult<(User, bool)> {
+        let username = normalize_username(username)?;
+
+        let promoted: Option<User> = sqlx::query_as(
+            r#"
+            UPDATE users SET is_admin = true, updated_at = NOW()
+            WHERE LOWER(username) = $1
+            RETURNING *
+            "#,
+        )
+        .bind(&username)
+        .fetch_optional(&self.db)
+        .await?;
+
+        if let Some(user) = promoted {
+            return Ok((user, false));
+        }
+
+        if email.is_none() && phone.is_none() {
+            return Err(AppError::BadRequest("Phone or email required".to_string()));
+        }
+
+        let existing: Option<Uuid> =
+            sqlx::query_scalar("SELECT id FROM users WHERE phone = $1 OR email = $2")
+                .bind(phone)
+                .bind(email)
+                .fetch_optional(&self.db)
+                .await?;
+
+        if existing.is_some() {
+            return Err(AppError::UserAlreadyExists);
+        }
+
+        let user: User = sqlx::query_as(
+            r#"
+            INSERT INTO users (id, phone, email, username, display_name, status, is_admin)
+            VALUES ($1, $2, $3, $4, $5, $6, true)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(phone)
+        .bind(email)
+        .bind(&username)
+        .bind(display_name)
+        .bind(UserStatus::Offline)
+        .fetch_one(&self.db)
+        .await
+        .map_err(map_username_conflict)?;
+
+        Ok((user, true))
+    }
+
+    /// Suspend an account: sign it out everywhere and refuse new logins.
+    /// Access tokens already issued run out within `JWT_ACCESS_TOKEN_TTL`.
+    pub async fn ban_user(&self, user_id: Uuid, reason: Option<&str>) -> AppResult<()> {
+        let result =
+            sqlx::query("UPDATE users SET banned_at = NOW(), ban_reason = $1 WHERE id = $2")
+                .bind(reason)
+                .bind(user_id)
+                .execute(&self.db)
+                .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::UserNotFound);
+        }
+
+        self.logout_all(user_id).await
+    }
+
+    pub async fn unban_user(&self, user_id: Uuid) -> AppResult<()> {
+        let result =
+            sqlx::query("UPDATE users SET banned_at = NULL, ban_reason = NULL WHERE id = $1")
+                .bind(user_id)
+                .execute(&self.db)
+                .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Start signing tokens with a fresh key and retire the current one.
+    /// Tokens signed with an earlier rotated key stay valid until they expire;
+    /// ones signed with `JWT_SECRET` only for one access-token lifetime after
+    /// the first rotation. Returns the new `kid`.
+    pub async fn rotate_signing_key(&self) -> AppResult<String> {
+        let mut rng = rand::thread_rng();
+        let kid = format!("{:016x}", rng.gen::<u64>());
+        let secret = URL_SAFE_NO_PAD.encode(rng.gen::<[u8; 32]>());
+
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query("UPDATE jwt_signing_keys SET retired_at = NOW() WHERE retired_at IS NULL")
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("INSERT INTO jwt_signing_keys (kid, secret) VALUES ($1, $2)")
+            .bind(&kid)
+            .bind(&secret)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(kid)
+    }
+
+    /// Delete retired signing keys once every token they signed has expired
+    pub async fn purge_retired_signing_keys(&self) -> AppResult<u64> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM jwt_signing_keys
+            WHERE retired_at < NOW() - make_interval(secs => $1)
+            "#,
+        )
+        .bind(self.config.jwt.refresh_token_ttl.as_secs() as f64)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Delete OTPs that expired more than a day ago
+    pub async fn purge_expired_otps(&self) -> AppResult<u64> {
+        let result = sqlx::query("DELETE FROM otps WHERE expires_at < NOW() - INTERVAL '1 day'")
+            .execute(&self.db)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
     // Helper methods
+    async fn record_login_event<'e, E>(
+        &self,
+        executor: E,
+        user_id: Uuid,
+        device_id: i32,
+        client: &ClientInfo,
+    ) -> AppResult<()>
+    where
+        E: sqlx::PgExecutor<'e>,
+    {
+        sqlx::query(
+            r#"
+            INSERT INTO login_events (id, user_id, device_id, ip_address, user_agent, country)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(device_id)
+        .bind(&client.ip_address)
+        .bind(&client.user_agent)
+        .bind(&client.country)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    /// A location is new if the user has login history but none from this country
+    /// (or this IP, when no country is known)
+    async fn is_new_location(&self, user_id: Uuid, client: &ClientInfo) -> AppResult<bool> {
+        let (column, value) = match (&client.country, &client.ip_address) {
+            (Some(country), _) => ("country", country),
+            (None, Some(ip)) => ("ip_address", ip),
+            (None, None) => return Ok(false),
+        };
+
+        let (has_history, seen): (bool, bool) = sqlx::query_as(&format!(
+            r#"
+            SELECT EXISTS(SELECT 1 FROM login_events WHERE user_id = $1),
+                   EXISTS(SELECT 1 FROM login_events WHERE user_id = $1 AND {} = $2)
+            "#,
+            column
+        ))
+        .bind(user_id)
+        .bind(value)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(has_history && !seen)
+    }
+
     fn generate_otp(&self) -> String {
         let mut rng = rand::thread_rng();
         let max = 10_u32.pow(self.config.otp.length as u32);
@@ -474,7 +1377,12 @@ impl AuthService {
         format!("{:0>width$}", code, width = self.config.otp.length)
     }
 
-    fn generate_token_pair(&self, user_id: &str, device_id: &str) -> AppResult<TokenPair> {
+    async fn generate_token_pair(
+        &self,
+        user_id: &str,
+        device_id: &str,
+        scopes: Option<&[Scope]>,
+    ) -> AppResult<TokenPair> {
         let now = Utc::now();
         let access_exp = now + Duration::seconds(self.config.jwt.access_token_ttl.as_secs() as i64);
         let refresh_exp =
@@ -486,6 +1394,7 @@ impl AuthService {
             iss: self.config.jwt.issuer.clone(),
             exp: access_exp.timestamp(),
             iat: now.timestamp(),
+            scopes: scopes.map(<[Scope]>::to_vec),
         };
 
         let refresh_claims = Claims {
@@ -494,17 +1403,37 @@ impl AuthService {
             iss: self.config.jwt.issuer.clone(),
             exp: refresh_exp.timestamp(),
             iat: now.timestamp(),
+            scopes: scopes.map(<[Scope]>::to_vec),
         };
 
-        let key = EncodingKey::from_secret(self.config.jwt.secret.as_bytes());
+        // The newest rotated key signs; before the first rotation, JWT_SECRET does
+        let signing_key: Option<(String, String)> = sqlx::query_as(
+            r#"
+            SELECT kid, secret FROM jwt_signing_keys
+            WHERE retired_at IS NULL
+            ORDER BY created_at DESC LIMIT 1
+            "#,
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        let mut header = Header::default();
+        let key = match signing_key {
+            Some((kid, secret)) => {
+                header.kid = Some(kid);
+                EncodingKey::from_secret(secret.as_bytes())
+            }
+            None => EncodingKey::from_secret(self.config.jwt.secret.as_bytes()),
+        };
 
-        let access_token = encode(&Header::default(), &access_claims, &key)?;
-        let refresh_token = encode(&Header::default(), &refresh_claims, &key)?;
+        let access_token = encode(&header, &access_claims, &key)?;
+        let refresh_token = encode(&header, &refresh_claims, &key)?;
 
         Ok(TokenPair {
             access_token,
             refresh_token,
             expires_at: access_exp,
+            device_token: None,
         })
     }
 
@@ -0,0 +1,390 @@
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use chrono::Utc;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::TryStreamExt;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{BackupManifest, BackupTable},
+    storage::blob::BlobStorage,
+};
+
+/// Backups are objects in the backup bucket under this prefix
+const PREFIX: &str = "backups/";
+
+/// Advisory lock held by the transaction of a running backup or restore
+const BACKUP_LOCK: i64 = 0x6261_636b_7570;
+
+/// Compressed size at which a table's export moves on to its next object,
+/// bounding the memory one table takes
+const PART_BYTES: usize = 64 * 1024 * 1024;
+
+/// Uncompressed bytes sent to `COPY FROM STDIN` at a time during a restore
+const RESTORE_CHUNK_BYTES: usize = 1024 * 1024;
+
+/// Logical backups of every table through `COPY`, stored in object storage.
+/// A backup reads one consistent snapshot; a restore replaces the contents
+/// of every table in one transaction.
+pub struct BackupService {
+    db: PgPool,
+    storage: Arc<dyn BlobStorage>,
+    bucket: Option<String>,
+    retention: usize,
+}
+
+/// A backup that holds the lock and its snapshot, ready to be written
+pub struct PendingBackup {
+    pub id: String,
+    tx: Transaction<'static, Postgres>,
+}
+
+impl BackupService {
+    pub fn new(
+        db: PgPool,
+        storage: Arc<dyn BlobStorage>,
+        bucket: Option<String>,
+        retention: usize,
+    ) -> Self {
+        Self {
+            db,
+            storage,
+            bucket,
+            retention,
+        }
+    }
+
+    /// The private bucket backups go to. The other buckets serve public
+    /// files on S3 and GCS, so there is no fallback to them.
+    fn bucket(&self) -> AppResult<&str> {
+        self.bucket.as_deref().ok_or(AppError::BackupsDisabled)
+    }
+
+    /// Take the lock and the snapshot. Fails with `BackupInProgress` while
+    /// another backup or restore runs on any instance.
+    pub async fn begin(&self) -> AppResult<PendingBackup> {
+        self.bucket()?;
+        let mut tx = self.db.begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+        no_statement_timeout(&mut tx).await?;
+        lock(&mut tx).await?;
+
+        Ok(PendingBackup {
+            id: Utc::now().format("%Y%m%dT%H%M%SZ").to_string(),
+            tx,
+        })
+    }
+
+    pub async fn create(&self) -> AppResult<BackupManifest> {
+        let pending = self.begin().await?;
+        self.run(pending).await
+    }
+
+    /// Export every table of the snapshot, then write the manifest
+    pub async fn run(&self, pending: PendingBackup) -> AppResult<BackupManifest> {
+        let PendingBackup { id, mut tx } = pending;
+        let bucket = self.bucket()?;
+        let schema_version = schema_version(&mut tx).await?;
+
+        let names: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT tablename::TEXT FROM pg_tables
+            WHERE schemaname = 'public' AND tablename != '_sqlx_migrations'
+            ORDER BY tablename
+            "#,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut tables = Vec::with_capacity(names.len());
+        for name in names {
+            let columns: Vec<String> = sqlx::query_scalar(
+                r#"
+                SELECT column_name::TEXT FROM information_schema.columns
+                WHERE table_schema = 'public' AND table_name = $1 AND is_generated = 'NEVER'
+                ORDER BY ordinal_position
+                "#,
+            )
+            .bind(&name)
+            .fetch_all(&mut *tx)
+            .await?;
+            let rows: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", quote(&name)))
+                .fetch_one(&mut *tx)
+                .await?;
+
+            let statement = format!(
+                "COPY {} ({}) TO STDOUT WITH (FORMAT csv, HEADER)",
+                quote(&name),
+                column_list(&columns)
+            );
+            // Each part is uploaded as it fills, so only one is ever in memory
+            let mut parts = 0;
+            let mut bytes = 0;
+            let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+            let mut stream = tx.copy_out_raw(&statement).await?;
+            while let Some(chunk) = stream.try_next().await? {
+                gz.write_all(&chunk)
+                    .map_err(|e| anyhow::anyhow!("Failed to compress {}: {}", name, e))?;
+                if gz.get_ref().len() >= PART_BYTES {
+                    let full = std::mem::replace(
+                        &mut gz,
+                        GzEncoder::new(Vec::new(), Compression::default()),
+                    );
+                    bytes += self.upload_part(&id, &name, parts, full).await?;
+                    parts += 1;
+                }
+            }
+            drop(stream);
+            bytes += self.upload_part(&id, &name, parts, gz).await?;
+            parts += 1;
+
+            tables.push(BackupTable {
+                name,
+                columns,
+                rows,
+                bytes,
+                parts,
+            });
+        }
+        tx.commit().await?;
+
+        let manifest = BackupManifest {
+            id,
+            created_at: Utc::now(),
+            schema_version,
+            tables,
+        };
+        self.storage
+            .upload_private_file(
+                bucket,
+                &manifest_key(&manifest.id),
+                Bytes::from(serde_json::to_vec_pretty(&manifest)?),
+                "application/json",
+            )
+            .await?;
+
+        Ok(manifest)
+    }
+
+    /// Finish one part of a table's export and upload it. Returns its size.
+    async fn upload_part(
+        &self,
+        id: &str,
+        table: &str,
+        part: u32,
+        gz: GzEncoder<Vec<u8>>,
+    ) -> AppResult<u64> {
+        let data = gz
+            .finish()
+            .map_err(|e| anyhow::anyhow!("Failed to compress {}: {}", table, e))?;
+        let bytes = data.len() as u64;
+        self.storage
+            .upload_private_file(
+                self.bucket()?,
+                &table_key(id, table, part),
+                data.into(),
+                "application/gzip",
+            )
+            .await?;
+
+        Ok(bytes)
+    }
+
+    /// Completed backups, newest first
+    pub async fn list(&self) -> AppResult<Vec<BackupManifest>> {
+        let bucket = self.bucket()?;
+        let mut manifests = Vec::new();
+        for key in self.storage.list_files(bucket, PREFIX).await? {
+            if !key.ends_with("/manifest.json") {
+                continue;
+            }
+            let data = self.storage.download_file(bucket, &key).await?;
+            match serde_json::from_slice::<BackupManifest>(&data) {
+                Ok(manifest) => manifests.push(manifest),
+                Err(e) => tracing::warn!("Skipping unreadable backup manifest {}: {}", key, e),
+            }
+        }
+        manifests.sort_by(|a, b| b.id.cmp(&a.id));
+
+        Ok(manifests)
+    }
+
+    pub async fn get(&self, id: &str) -> AppResult<BackupManifest> {
+        validate_id(id)?;
+        let bucket = self.bucket()?;
+        let key = manifest_key(id);
+        if !self.storage.file_exists(bucket, &key).await? {
+            return Err(AppError::BackupNotFound);
+        }
+
+        let data = self.storage.download_file(bucket, &key).await?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    /// Delete a backup, including one left incomplete
+    pub async fn delete(&self, id: &str) -> AppResult<()> {
+        validate_id(id)?;
+        let bucket = self.bucket()?;
+        let mut keys = self
+            .storage
+            .list_files(bucket, &format!("{}{}/", PREFIX, id))
+            .await?;
+        if keys.is_empty() {
+            return Err(AppError::BackupNotFound);
+        }
+
+        // The manifest goes first so a partly deleted backup is no longer listed
+        keys.sort_by_key(|key| !key.ends_with("/manifest.json"));
+        for key in keys {
+            self.storage.delete_file(bucket, &key).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete completed backups beyond the retention count. Returns the
+    /// number deleted.
+    pub async fn prune(&self) -> AppResult<usize> {
+        let expired: Vec<String> = self
+            .list()
+            .await?
+            .into_iter()
+            .skip(self.retention)
+            .map(|manifest| manifest.id)
+            .collect();
+
+        for id in &expired {
+            self.delete(id).await?;
+        }
+
+        Ok(expired.len())
+    }
+
+    /// Replace the contents of every table with a backup. The database must
+    /// be at the backup's schema version, and the role must be allowed to set
+    /// `session_replication_role`, which lets rows load in any table order.
+    pub async fn restore(&self, id: &str) -> AppResult<BackupManifest> {
+        let manifest = self.get(id).await?;
+        let bucket = self.bucket()?;
+
+        let mut tx = self.db.begin().await?;
+        no_statement_timeout(&mut tx).await?;
+        lock(&mut tx).await?;
+        let current = schema_version(&mut tx).await?;
+        if current != manifest.schema_version {
+            return Err(AppError::Validation(format!(
+                "Backup {} is at schema version {}, the database is at {}",
+                manifest.id, manifest.schema_version, current
+            )));
+        }
+
+        sqlx::query("SET LOCAL session_replication_role = replica")
+            .execute(&mut *tx)
+            .await?;
+        let names: Vec<String> = manifest.tables.iter().map(|t| quote(&t.name)).collect();
+        sqlx::query(&format!("TRUNCATE {}", names.join(", ")))
+            .execute(&mut *tx)
+            .await?;
+
+        let mut buf = vec![0; RESTORE_CHUNK_BYTES];
+        for table in &manifest.tables {
+            let statement = format!(
+                "COPY {} ({}) FROM STDIN WITH (FORMAT csv, HEADER)",
+                quote(&table.name),
+                column_list(&table.columns)
+            );
+            let mut copy = tx.copy_in_raw(&statement).await?;
+            // Parts are consecutive pieces of one CSV, so they load as one
+            for part in 0..table.parts {
+                let compressed = self
+                    .storage
+                    .download_file(bucket, &table_key(&manifest.id, &table.name, part))
+                    .await?;
+                let mut gz = GzDecoder::new(&compressed[..]);
+                loop {
+                    let n = gz.read(&mut buf).map_err(|e| {
+                        anyhow::anyhow!("Failed to decompress {}: {}", table.name, e)
+                    })?;
+                    if n == 0 {
+                        break;
+                    }
+                    copy.send(&buf[..n]).await?;
+                }
+            }
+            copy.finish().await?;
+        }
+        tx.commit().await?;
+
+        Ok(manifest)
+    }
+}
+
+async fn lock(tx: &mut Transaction<'static, Postgres>) -> AppResult<()> {
+    let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_xact_lock($1)")
+        .bind(BACKUP_LOCK)
+        .fetch_one(&mut **tx)
+        .await?;
+    if !locked {
+        return Err(AppError::BackupInProgress);
+    }
+
+    Ok(())
+}
+
+/// Exports and loads run far longer than the pool's `statement_timeout`
+async fn no_statement_timeout(tx: &mut Transaction<'static, Postgres>) -> AppResult<()> {
+    sqlx::query("SET LOCAL statement_timeout = 0")
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+async fn schema_version(tx: &mut Transaction<'static, Postgres>) -> AppResult<i64> {
+    let version: i64 =
+        sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM _sqlx_migrations WHERE success")
+            .fetch_one(&mut **tx)
+            .await?;
+
+    Ok(version)
+}
+
+/// Backup IDs are UTC timestamps, e.g. `20240101T030000Z`
+fn validate_id(id: &str) -> AppResult<()> {
+    if id.is_empty() || id.len() > 32 || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(AppError::BackupNotFound);
+    }
+
+    Ok(())
+}
+
+fn manifest_key(id: &str) -> String {
+    format!("{}{}/manifest.json", PREFIX, id)
+}
+
+/// The first part keeps the name backups had before tables were split
+fn table_key(id: &str, table: &str, part: u32) -> String {
+    match part {
+        0 => format!("{}{}/{}.csv.gz", PREFIX, id, table),
+        _ => format!("{}{}/{}.{}.csv.gz", PREFIX, id, table, part),
+    }
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn column_list(columns: &[String]) -> String {
+    columns
+        .iter()
+        .map(|c| quote(c))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
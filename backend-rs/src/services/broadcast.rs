@@ -0,0 +1,442 @@
+use std::collections::HashSet;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{
+        Broadcast, BroadcastDeliverySummary, BroadcastDetails, BroadcastList,
+        BroadcastListWithRecipients, BroadcastRecipientStatus, BroadcastWithSummary,
+        ConversationEncryption, MessagePayload, MessageType,
+    },
+    services::{
+        message_policy,
+        messaging::{check_encryption, MessagingService},
+    },
+};
+
+pub const MAX_BROADCAST_RECIPIENTS: usize = 256;
+
+/// Broadcast lists: a saved recipient set whose messages are delivered as
+/// separate direct messages, so replies come back one-to-one
+pub struct BroadcastService {
+    db: PgPool,
+    messaging: MessagingService,
+}
+
+impl BroadcastService {
+    pub fn new(db: PgPool, messaging: MessagingService) -> Self {
+        Self { db, messaging }
+    }
+
+    pub async fn get_lists(&self, owner_id: Uuid) -> AppResult<Vec<BroadcastListWithRecipients>> {
+        let lists: Vec<BroadcastList> = sqlx::query_as(
+            "SELECT * FROM broadcast_lists WHERE owner_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(owner_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut result = Vec::with_capacity(lists.len());
+        for list in lists {
+            let recipient_ids = self.recipient_ids(list.id).await?;
+            result.push(BroadcastListWithRecipients {
+                list,
+                recipient_ids,
+            });
+        }
+
+        Ok(result)
+    }
+
+    pub async fn get_list(
+        &self,
+        list_id: Uuid,
+        owner_id: Uuid,
+    ) -> AppResult<BroadcastListWithRecipients> {
+        let list = self.find_list(list_id, owner_id).await?;
+        let recipient_ids = self.recipient_ids(list.id).await?;
+
+        Ok(BroadcastListWithRecipients {
+            list,
+            recipient_ids,
+        })
+    }
+
+    pub async fn create_list(
+        &self,
+        owner_id: Uuid,
+        name: &str,
+        recipient_ids: Vec<Uuid>,
+    ) -> AppResult<BroadcastListWithRecipients> {
+        let recipient_ids = self.validate_recipients(owner_id, recipient_ids).await?;
+
+        let mut tx = self.db.begin().await?;
+
+        let list_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO broadcast_lists (id, owner_id, name) VALUES ($1, $2, $3)")
+            .bind(list_id)
+            .bind(owner_id)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO broadcast_list_recipients (list_id, user_id) SELECT $1, UNNEST($2::uuid[])",
+        )
+        .bind(list_id)
+        .bind(&recipient_ids)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        self.get_list(list_id, owner_id).await
+    }
+
+    /// Rename a list and/or replace its recipients; `None` leaves a field unchanged
+    pub async fn update_list(
+        &self,
+        list_id: Uuid,
+        owner_id: Uuid,
+        name: Option<String>,
+        recipient_ids: Option<Vec<Uuid>>,
+    ) -> AppResult<BroadcastListWithRecipients> {
+        self.find_list(list_id, owner_id).await?;
+
+        let recipient_ids = match recipient_ids {
+            Some(ids) => Some(self.validate_recipients(owner_id, ids).await?),
+            None => None,
+        };
+
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            "UPDATE broadcast_lists SET name = COALESCE($2, name), updated_at = NOW() WHERE id = $1",
+        )
+        .bind(list_id)
+        .bind(name)
+        .execute(&mut *tx)
+        .await?;
+
+        if let Some(recipient_ids) = recipient_ids {
+            sqlx::query("DELETE FROM broadcast_list_recipients WHERE list_id = $1")
+                .bind(list_id)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query(
+                "INSERT INTO broadcast_list_recipients (list_id, user_id) SELECT $1, UNNEST($2::uuid[])",
+            )
+            .bind(list_id)
+            .bind(&recipient_ids)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        self.get_list(list_id, owner_id).await
+    }
+
+    pub async fn delete_list(&self, list_id: Uuid, owner_id: Uuid) -> AppResult<()> {
+        let result = sqlx::query("DELETE FROM broadcast_lists WHERE id = $1 AND owner_id = $2")
+            .bind(list_id)
+            .bind(owner_id)
+            .execute(&self.db)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::BroadcastListNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Send a message to every recipient as its own direct message.
+    ///
+    /// The content is screened once for the whole broadcast, so fanning out
+    /// does not trip duplicate-content detection. Recipients who have blocked
+    /// the sender are skipped.
+    pub async fn send(
+        &self,
+        list_id: Uuid,
+        sender_id: Uuid,
+        message_type: MessageType,
+        payload: MessagePayload,
+        sticker_id: Option<Uuid>,
+    ) -> AppResult<BroadcastDetails> {
+        self.find_list(list_id, sender_id).await?;
+        let payload = message_policy::encode_payload(message_type, payload)?;
+        if payload.attachment_id.is_some() {
+            return Err(AppError::Validation(
+                "Broadcasts cannot carry attachments".to_string(),
+            ));
+        }
+        self.messaging.check_content(message_type, &payload)?;
+
+        let recipients: Vec<(Uuid, bool)> = sqlx::query_as(
+            r#"
+            SELECT r.user_id, EXISTS(
+                SELECT 1 FROM contacts c
+                WHERE c.user_id = r.user_id AND c.contact_id = $2 AND c.is_blocked = true
+            )
+            FROM broadcast_list_recipients r
+            WHERE r.list_id = $1
+            "#,
+        )
+        .bind(list_id)
+        .bind(sender_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        if recipients.is_empty() {
+            return Err(AppError::Validation(
+                "Broadcast list has no recipients".to_string(),
+            ));
+        }
+
+        // Resolve every conversation first, so content one of them would
+        // refuse fails the whole broadcast
+        let mut targets = Vec::with_capacity(recipients.len());
+        for (recipient_id, blocked) in recipients {
+            let conversation_id = if blocked {
+                None
+            } else {
+                let conversation_id = self
+                    .messaging
+                    .direct_conversation_id(sender_id, recipient_id, ConversationEncryption::None)
+                    .await?;
+                Some(conversation_id)
+            };
+            targets.push((recipient_id, conversation_id));
+        }
+
+        let conversation_ids: Vec<Uuid> = targets.iter().filter_map(|(_, id)| *id).collect();
+        let modes: Vec<ConversationEncryption> = sqlx::query_scalar(
+            "SELECT DISTINCT encryption FROM conversations WHERE id = ANY($1)",
+        )
+        .bind(&conversation_ids)
+        .fetch_all(&self.db)
+        .await?;
+        for encryption in modes {
+            check_encryption(encryption, message_type, &payload)?;
+        }
+
+        let shadowed = self
+            .messaging
+            .screen_message(sender_id, list_id, &payload.content)
+            .await?;
+
+        // Every recipient gets the broadcast or none does
+        let mut tx = self.db.begin().await?;
+        let broadcast: Broadcast = sqlx::query_as(
+            r#"
+            INSERT INTO broadcasts (id, list_id, sender_id, type)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(list_id)
+        .bind(sender_id)
+        .bind(message_type)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let mut messages = Vec::with_capacity(targets.len());
+        for (recipient_id, conversation_id) in targets {
+            let message_id = if let Some(conversation_id) = conversation_id {
+                let message = self
+                    .messaging
+                    .insert_message(
+                        &mut tx,
+                        conversation_id,
+                        sender_id,
+                        message_type,
+                        payload.clone(),
+                        sticker_id,
+                        None,
+                        shadowed,
+                        false,
+                    )
+                    .await?;
+                let message_id = message.id;
+                messages.push(message);
+                Some(message_id)
+            } else {
+                None
+            };
+
+            sqlx::query(
+                r#"
+                INSERT INTO broadcast_messages (broadcast_id, recipient_id, message_id)
+                VALUES ($1, $2, $3)
+                "#,
+            )
+            .bind(broadcast.id)
+            .bind(recipient_id)
+            .bind(message_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        for message in &messages {
+            self.messaging.announce_message(message, shadowed).await?;
+        }
+
+        self.get_broadcast(list_id, broadcast.id, sender_id).await
+    }
+
+    /// Past broadcasts of a list, newest first, with delivery counts
+    pub async fn get_broadcasts(
+        &self,
+        list_id: Uuid,
+        owner_id: Uuid,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<BroadcastWithSummary>> {
+        self.find_list(list_id, owner_id).await?;
+
+        let broadcasts: Vec<Broadcast> = sqlx::query_as(
+            r#"
+            SELECT * FROM broadcasts
+            WHERE list_id = $1
+            ORDER BY created_at DESC
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(list_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut result = Vec::with_capacity(broadcasts.len());
+        for broadcast in broadcasts {
+            let summary = self.summarize(broadcast.id).await?;
+            result.push(BroadcastWithSummary { broadcast, summary });
+        }
+
+        Ok(result)
+    }
+
+    /// One broadcast with per-recipient delivery status
+    pub async fn get_broadcast(
+        &self,
+        list_id: Uuid,
+        broadcast_id: Uuid,
+        owner_id: Uuid,
+    ) -> AppResult<BroadcastDetails> {
+        self.find_list(list_id, owner_id).await?;
+
+        let broadcast: Broadcast =
+            sqlx::query_as("SELECT * FROM broadcasts WHERE id = $1 AND list_id = $2")
+                .bind(broadcast_id)
+                .bind(list_id)
+                .fetch_optional(&self.db)
+                .await?
+                .ok_or(AppError::BroadcastNotFound)?;
+
+        let recipients: Vec<BroadcastRecipientStatus> = sqlx::query_as(
+            r#"
+            SELECT bm.recipient_id, m.conversation_id, bm.message_id, m.status
+            FROM broadcast_messages bm
+            LEFT JOIN messages m ON m.id = bm.message_id
+            WHERE bm.broadcast_id = $1
+            ORDER BY bm.recipient_id
+            "#,
+        )
+        .bind(broadcast_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let summary = self.summarize(broadcast_id).await?;
+
+        Ok(BroadcastDetails {
+            broadcast,
+            summary,
+            recipients,
+        })
+    }
+
+    async fn summarize(&self, broadcast_id: Uuid) -> AppResult<BroadcastDeliverySummary> {
+        let summary: BroadcastDeliverySummary = sqlx::query_as(
+            r#"
+            SELECT
+                COUNT(*) AS total,
+                COUNT(*) FILTER (WHERE m.status = 'sent') AS sent,
+                COUNT(*) FILTER (WHERE m.status = 'delivered') AS delivered,
+                COUNT(*) FILTER (WHERE m.status = 'read') AS read,
+                COUNT(*) FILTER (WHERE bm.message_id IS NULL) AS skipped
+            FROM broadcast_messages bm
+            LEFT JOIN messages m ON m.id = bm.message_id
+            WHERE bm.broadcast_id = $1
+            "#,
+        )
+        .bind(broadcast_id)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(summary)
+    }
+
+    async fn find_list(&self, list_id: Uuid, owner_id: Uuid) -> AppResult<BroadcastList> {
+        sqlx::query_as("SELECT * FROM broadcast_lists WHERE id = $1 AND owner_id = $2")
+            .bind(list_id)
+            .bind(owner_id)
+            .fetch_optional(&self.db)
+            .await?
+            .ok_or(AppError::BroadcastListNotFound)
+    }
+
+    async fn recipient_ids(&self, list_id: Uuid) -> AppResult<Vec<Uuid>> {
+        let ids: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT user_id FROM broadcast_list_recipients WHERE list_id = $1 ORDER BY added_at",
+        )
+        .bind(list_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(ids)
+    }
+
+    /// De-duplicate recipients and check they are existing users other than the owner
+    async fn validate_recipients(
+        &self,
+        owner_id: Uuid,
+        recipient_ids: Vec<Uuid>,
+    ) -> AppResult<Vec<Uuid>> {
+        let mut seen = HashSet::new();
+        let recipient_ids: Vec<Uuid> = recipient_ids
+            .into_iter()
+            .filter(|id| seen.insert(*id))
+            .collect();
+
+        if recipient_ids.is_empty() || recipient_ids.len() > MAX_BROADCAST_RECIPIENTS {
+            return Err(AppError::Validation(format!(
+                "A broadcast list needs 1-{} recipients",
+                MAX_BROADCAST_RECIPIENTS
+            )));
+        }
+
+        if recipient_ids.contains(&owner_id) {
+            return Err(AppError::Validation(
+                "Cannot add yourself to a broadcast list".to_string(),
+            ));
+        }
+
+        let existing: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE id = ANY($1)")
+            .bind(&recipient_ids)
+            .fetch_one(&self.db)
+            .await?;
+
+        if existing != recipient_ids.len() as i64 {
+            return Err(AppError::UserNotFound);
+        }
+
+        Ok(recipient_ids)
+    }
+}
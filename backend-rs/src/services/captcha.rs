@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::{config::CaptchaConfig, error::AppResult};
+
+/// Verifies a client-solved CAPTCHA token
+#[async_trait]
+pub trait CaptchaProvider: Send + Sync {
+    async fn verify(&self, token: &str, remote_ip: Option<&str>) -> AppResult<bool>;
+}
+
+#[derive(Debug, Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+/// hCaptcha and Cloudflare Turnstile share the same siteverify contract
+pub struct SiteVerifyCaptchaProvider {
+    http: reqwest::Client,
+    verify_url: &'static str,
+    secret: String,
+}
+
+impl SiteVerifyCaptchaProvider {
+    pub fn hcaptcha(secret: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            verify_url: "https://api.hcaptcha.com/siteverify",
+            secret,
+        }
+    }
+
+    pub fn turnstile(secret: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            verify_url: "https://challenges.cloudflare.com/turnstile/v0/siteverify",
+            secret,
+        }
+    }
+}
+
+#[async_trait]
+impl CaptchaProvider for SiteVerifyCaptchaProvider {
+    async fn verify(&self, token: &str, remote_ip: Option<&str>) -> AppResult<bool> {
+        let mut form = vec![("secret", self.secret.as_str()), ("response", token)];
+        if let Some(ip) = remote_ip {
+            form.push(("remoteip", ip));
+        }
+
+        let response: SiteVerifyResponse = self
+            .http
+            .post(self.verify_url)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to verify CAPTCHA: {}", e))?
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("Invalid CAPTCHA response: {}", e))?;
+
+        Ok(response.success)
+    }
+}
+
+/// Returns `None` when CAPTCHA is disabled
+pub fn build_captcha_provider(config: &CaptchaConfig) -> Option<Arc<dyn CaptchaProvider>> {
+    let secret = match (config.provider.as_str(), &config.secret) {
+        ("none", _) => return None,
+        (_, Some(secret)) => secret.clone(),
+        (provider, None) => {
+            tracing::warn!("CAPTCHA_PROVIDER={} but CAPTCHA_SECRET is not set", provider);
+            return None;
+        }
+    };
+
+    match config.provider.as_str() {
+        "hcaptcha" => Some(Arc::new(SiteVerifyCaptchaProvider::hcaptcha(secret))),
+        "turnstile" => Some(Arc::new(SiteVerifyCaptchaProvider::turnstile(secret))),
+        other => {
+            tracing::warn!("Unknown CAPTCHA provider: {}", other);
+            None
+        }
+    }
+}
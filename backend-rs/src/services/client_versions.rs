@@ -0,0 +1,256 @@
+use std::{cmp::Ordering, time::Duration};
+
+use axum::http::HeaderMap;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{ClientUpgrade, ClientVersionReport, ClientVersionRequirement},
+    storage::redis::RedisClient,
+};
+
+/// The client app's platform, e.g. `ios`, `android`, `desktop` or `web`
+pub const PLATFORM_HEADER: &str = "x-client-platform";
+
+/// The client app's version, e.g. `2.4.1`
+pub const VERSION_HEADER: &str = "x-client-version";
+
+const REQUIREMENTS_CACHE_KEY: &str = "client_version_requirements";
+
+/// How long instances may keep enforcing a minimum after it changes
+const REQUIREMENTS_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Longest platform name and version accepted
+const MAX_FIELD_LEN: usize = 32;
+
+/// Numeric components of a version, ignoring any `-beta` or `+build` suffix
+pub fn parse_version(version: &str) -> Option<Vec<u64>> {
+    let core = version.trim().split(['-', '+']).next()?;
+    let parts: Vec<u64> = core
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+
+    (!parts.is_empty() && parts.len() <= 4).then_some(parts)
+}
+
+/// Compare two parsed versions, treating missing components as zero
+fn compare_versions(a: &[u64], b: &[u64]) -> Ordering {
+    let len = a.len().max(b.len());
+    (0..len)
+        .map(|i| {
+            let x = a.get(i).copied().unwrap_or(0);
+            let y = b.get(i).copied().unwrap_or(0);
+            x.cmp(&y)
+        })
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Whether `version` is older than `than`; unparseable versions never are
+fn is_older(version: &str, than: &str) -> bool {
+    match (parse_version(version), parse_version(than)) {
+        (Some(v), Some(t)) => compare_versions(&v, &t).is_lt(),
+        _ => false,
+    }
+}
+
+/// The lowercase platform and version a request was sent with, if it sent
+/// both and the version parses
+pub fn client_version(headers: &HeaderMap) -> Option<(String, String)> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty() && v.len() <= MAX_FIELD_LEN)
+    };
+    let platform = header(PLATFORM_HEADER)?.to_lowercase();
+    let version = header(VERSION_HEADER)?;
+    parse_version(version)?;
+
+    Some((platform, version.to_string()))
+}
+
+pub struct ClientVersionService {
+    db: PgPool,
+    redis: RedisClient,
+}
+
+impl ClientVersionService {
+    pub fn new(db: PgPool, redis: RedisClient) -> Self {
+        Self { db, redis }
+    }
+
+    /// Every platform's minimum, cached briefly since each request checks them
+    pub async fn requirements(&self) -> AppResult<Vec<ClientVersionRequirement>> {
+        if let Some(cached) = self.redis.get_cached(REQUIREMENTS_CACHE_KEY).await? {
+            if let Ok(requirements) = serde_json::from_str(&cached) {
+                return Ok(requirements);
+            }
+        }
+
+        let requirements: Vec<ClientVersionRequirement> =
+            sqlx::query_as("SELECT * FROM client_version_requirements ORDER BY platform")
+                .fetch_all(&self.db)
+                .await?;
+        self.redis
+            .set_cached(
+                REQUIREMENTS_CACHE_KEY,
+                &serde_json::to_string(&requirements)?,
+                REQUIREMENTS_CACHE_TTL,
+            )
+            .await?;
+
+        Ok(requirements)
+    }
+
+    /// Where a client on `platform` at `version` stands; `None` when the
+    /// platform has no minimum
+    pub async fn upgrade(&self, platform: &str, version: &str) -> AppResult<Option<ClientUpgrade>> {
+        let upgrade = self
+            .requirements()
+            .await?
+            .into_iter()
+            .find(|r| r.platform == platform)
+            .map(|r| ClientUpgrade {
+                required: is_older(version, &r.min_version),
+                available: r
+                    .latest_version
+                    .as_deref()
+                    .is_some_and(|latest| is_older(version, latest)),
+                min_version: r.min_version,
+                latest_version: r.latest_version,
+                upgrade_url: r.upgrade_url,
+                message: r.message,
+            });
+
+        Ok(upgrade)
+    }
+
+    /// Refuse clients older than their platform's minimum
+    pub async fn check(&self, platform: &str, version: &str) -> AppResult<()> {
+        match self.upgrade(platform, version).await? {
+            Some(upgrade) if upgrade.required => Err(AppError::UpgradeRequired {
+                platform: platform.to_string(),
+                min_version: upgrade.min_version,
+                upgrade_url: upgrade.upgrade_url,
+                message: upgrade.message,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Minimums, and the app versions devices active in the last 30 days run
+    pub async fn report(&self) -> AppResult<ClientVersionReport> {
+        let requirements =
+            sqlx::query_as("SELECT * FROM client_version_requirements ORDER BY platform")
+                .fetch_all(&self.db)
+                .await?;
+        let adoption = sqlx::query_as(
+            r#"
+            SELECT platform, app_version, COUNT(*) AS devices FROM devices
+            WHERE app_version IS NOT NULL AND last_active_at > NOW() - INTERVAL '30 days'
+            GROUP BY platform, app_version
+            ORDER BY platform, devices DESC
+            "#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(ClientVersionReport {
+            requirements,
+            adoption,
+        })
+    }
+
+    /// Set a platform's minimum, replacing any it had
+    pub async fn set_requirement(
+        &self,
+        admin_id: Uuid,
+        platform: &str,
+        min_version: &str,
+        latest_version: Option<&str>,
+        upgrade_url: Option<&str>,
+        message: Option<&str>,
+    ) -> AppResult<ClientVersionRequirement> {
+        let platform = platform.trim().to_lowercase();
+        let valid_platform = (1..=MAX_FIELD_LEN).contains(&platform.len())
+            && platform
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if !valid_platform {
+            return Err(AppError::Validation(
+                "Platforms are 1-32 lowercase letters, digits, - and _".to_string(),
+            ));
+        }
+        for version in std::iter::once(min_version).chain(latest_version) {
+            if version.len() > MAX_FIELD_LEN || parse_version(version).is_none() {
+                return Err(AppError::Validation(format!(
+                    "Invalid version: {}",
+                    version
+                )));
+            }
+        }
+        if latest_version.is_some_and(|latest| is_older(latest, min_version)) {
+            return Err(AppError::Validation(
+                "Latest version cannot be older than the minimum".to_string(),
+            ));
+        }
+        // Store links may use app schemes such as `itms-apps://` or `market://`
+        let valid_url = |url: &str| {
+            url.len() <= 2048
+                && !url.contains(char::is_whitespace)
+                && url.split_once("://").is_some_and(|(scheme, rest)| {
+                    !scheme.is_empty()
+                        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                        && !rest.is_empty()
+                })
+        };
+        if upgrade_url.is_some_and(|url| !valid_url(url)) {
+            return Err(AppError::Validation("Invalid upgrade URL".to_string()));
+        }
+
+        let requirement = sqlx::query_as(
+            r#"
+            INSERT INTO client_version_requirements
+                (platform, min_version, latest_version, upgrade_url, message, updated_by)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (platform) DO UPDATE SET
+                min_version = EXCLUDED.min_version,
+                latest_version = EXCLUDED.latest_version,
+                upgrade_url = EXCLUDED.upgrade_url,
+                message = EXCLUDED.message,
+                updated_by = EXCLUDED.updated_by,
+                updated_at = NOW()
+            RETURNING *
+            "#,
+        )
+        .bind(&platform)
+        .bind(min_version.trim())
+        .bind(latest_version.map(str::trim))
+        .bind(upgrade_url)
+        .bind(message)
+        .bind(admin_id)
+        .fetch_one(&self.db)
+        .await?;
+        self.redis.delete_cached(REQUIREMENTS_CACHE_KEY).await?;
+
+        Ok(requirement)
+    }
+
+    /// Stop enforcing a minimum on a platform
+    pub async fn delete_requirement(&self, platform: &str) -> AppResult<()> {
+        let result = sqlx::query("DELETE FROM client_version_requirements WHERE platform = $1")
+            .bind(platform.to_lowercase())
+            .execute(&self.db)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::ClientVersionRequirementNotFound);
+        }
+        self.redis.delete_cached(REQUIREMENTS_CACHE_KEY).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+use crate::{
+    error::{AppError, AppResult},
+    models::ImportedContact,
+    services::contacts::MAX_NICKNAME_CHARS,
+};
+
+/// Entries per import; larger address books are split by the client
+pub const MAX_IMPORT_ENTRIES: usize = 5000;
+
+/// Parse an address book export: vCard when it starts with `BEGIN:VCARD`,
+/// CSV with a header row otherwise. Entries without a phone or email are
+/// kept so the report can list them.
+pub fn parse_address_book(data: &str) -> AppResult<Vec<ImportedContact>> {
+    let data = data.trim_start_matches('\u{feff}');
+    let entries = if data
+        .trim_start()
+        .to_ascii_uppercase()
+        .starts_with("BEGIN:VCARD")
+    {
+        parse_vcard(data)
+    } else {
+        parse_csv(data)?
+    };
+
+    if entries.len() > MAX_IMPORT_ENTRIES {
+        return Err(AppError::Validation(format!(
+            "At most {} contacts per import",
+            MAX_IMPORT_ENTRIES
+        )));
+    }
+
+    Ok(entries)
+}
+
+/// Phone numbers as stored on accounts: digits with an optional leading `+`
+pub fn normalize_phone(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    let digits: String = raw.chars().filter(char::is_ascii_digit).collect();
+    if digits.len() < 6 {
+        return None;
+    }
+
+    Some(if raw.starts_with('+') {
+        format!("+{}", digits)
+    } else {
+        digits
+    })
+}
+
+pub fn normalize_email(raw: &str) -> Option<String> {
+    let email = raw.trim().trim_start_matches("mailto:").to_lowercase();
+    email.contains('@').then_some(email)
+}
+
+/// CSV as exported by Google, Outlook and most spreadsheets. Columns are
+/// found by header name, so any that mention a phone or email are read.
+fn parse_csv(data: &str) -> AppResult<Vec<ImportedContact>> {
+    let mut rows = csv_records(data).into_iter();
+    let header: Vec<String> = rows
+        .next()
+        .unwrap_or_default()
+        .iter()
+        .map(|h| h.trim().to_lowercase())
+        .collect();
+
+    let find = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+    let name_column = find(&["name", "full name", "display name", "display_name"]);
+    let first_column = find(&["first name", "given name", "first_name"]);
+    let last_column = find(&["last name", "family name", "last_name"]);
+    let phone_columns: Vec<usize> = header
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| ["phone", "mobile", "tel"].iter().any(|k| h.contains(k)))
+        .filter(|(_, h)| !h.contains("type") && !h.contains("label"))
+        .map(|(i, _)| i)
+        .collect();
+    let email_columns: Vec<usize> = header
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| h.contains("mail"))
+        .filter(|(_, h)| !h.contains("type") && !h.contains("label"))
+        .map(|(i, _)| i)
+        .collect();
+
+    if phone_columns.is_empty() && email_columns.is_empty() {
+        return Err(AppError::Validation(
+            "CSV needs a header row with a phone or email column".to_string(),
+        ));
+    }
+
+    let entries = rows
+        .filter(|row| row.iter().any(|field| !field.trim().is_empty()))
+        .map(|row| {
+            let cell = |i: Option<usize>| {
+                i.and_then(|i| row.get(i))
+                    .map(|v| v.trim())
+                    .filter(|v| !v.is_empty())
+            };
+            let name = cell(name_column).map(str::to_string).or_else(|| {
+                let parts: Vec<&str> = [cell(first_column), cell(last_column)]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                (!parts.is_empty()).then(|| parts.join(" "))
+            });
+
+            // Google joins several numbers in one cell with " ::: "
+            let values = |columns: &[usize]| -> Vec<String> {
+                columns
+                    .iter()
+                    .filter_map(|&i| cell(Some(i)))
+                    .flat_map(|v| v.split(":::").map(str::to_string).collect::<Vec<_>>())
+                    .collect()
+            };
+
+            entry(name, values(&phone_columns), values(&email_columns))
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+/// RFC 4180 records: quoted fields may hold commas, newlines and `""`
+fn csv_records(data: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    records
+}
+
+/// The properties read from one vCard
+#[derive(Default)]
+struct Card {
+    full_name: Option<String>,
+    name: Option<String>,
+    phones: Vec<String>,
+    emails: Vec<String>,
+}
+
+/// vCard 2.1 to 4.0: `FN` (or `N`), `TEL` and `EMAIL` of each card
+fn parse_vcard(data: &str) -> Vec<ImportedContact> {
+    // Lines starting with whitespace continue the previous one
+    let mut lines: Vec<String> = Vec::new();
+    for line in data.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut entries = Vec::new();
+    let mut card: Option<Card> = None;
+    for line in lines {
+        let Some((property, value)) = line.split_once(':') else {
+            continue;
+        };
+        // `item1.TEL;TYPE=CELL` names the property `TEL`
+        let property = property.split(';').next().unwrap_or_default();
+        let property = property
+            .rsplit('.')
+            .next()
+            .unwrap_or_default()
+            .to_uppercase();
+        let value = unescape_vcard(value.trim());
+
+        match (property.as_str(), card.as_mut()) {
+            ("BEGIN", _) if value.eq_ignore_ascii_case("VCARD") => {
+                card = Some(Card::default());
+            }
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VCARD") => {
+                if let Some(card) = card.take() {
+                    entries.push(entry(
+                        card.full_name.or(card.name),
+                        card.phones,
+                        card.emails,
+                    ));
+                }
+            }
+            ("FN", Some(card)) if !value.is_empty() => card.full_name = Some(value),
+            ("N", Some(card)) => {
+                // Family;Given;Additional;Prefix;Suffix
+                let parts: Vec<&str> = value.split(';').collect();
+                let name = [parts.get(1), parts.first()]
+                    .into_iter()
+                    .flatten()
+                    .map(|p| p.trim())
+                    .filter(|p| !p.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                if !name.is_empty() {
+                    card.name = Some(name);
+                }
+            }
+            ("TEL", Some(card)) => card
+                .phones
+                .push(value.trim_start_matches("tel:").to_string()),
+            ("EMAIL", Some(card)) => card.emails.push(value),
+            _ => {}
+        }
+    }
+
+    entries
+}
+
+/// An entry with its phones and emails normalized and deduplicated, and its
+/// name cut to fit a contact nickname
+fn entry(name: Option<String>, phones: Vec<String>, emails: Vec<String>) -> ImportedContact {
+    let mut entry = ImportedContact {
+        name: name.map(|n| n.chars().take(MAX_NICKNAME_CHARS).collect()),
+        phones: Vec::new(),
+        emails: Vec::new(),
+    };
+    for phone in phones.iter().filter_map(|p| normalize_phone(p)) {
+        if !entry.phones.contains(&phone) {
+            entry.phones.push(phone);
+        }
+    }
+    for email in emails.iter().filter_map(|e| normalize_email(e)) {
+        if !entry.emails.contains(&email) {
+            entry.emails.push(email);
+        }
+    }
+    entry
+}
+
+fn unescape_vcard(value: &str) -> String {
+    value
+        .replace("\\n", " ")
+        .replace("\\N", " ")
+        .replace("\\,", ",")
+        .replace("\\;", ";")
+        .replace("\\\\", "\\")
+}
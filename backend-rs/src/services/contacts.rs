@@ -1,11 +1,31 @@
-use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgExecutor, PgPool};
 use uuid::Uuid;
 
 use crate::{
     error::{AppError, AppResult},
-    models::{Contact, ContactWithUser, User},
+    models::{
+        Contact, ContactImportReport, ContactOperation, ContactOperationResult, ContactWithUser,
+        ImportMatch, ImportedContact, User, UserSearchResult,
+    },
+    services::{
+        contact_import::{normalize_email, normalize_phone},
+        search::escape_like,
+        social_graph::SocialGraphService,
+    },
 };
 
+/// Unregistered phones and emails remembered per user for join notices
+const MAX_CONTACT_INTERESTS: i64 = 10_000;
+
+/// Most operations one bulk contacts request may carry
+pub const MAX_BULK_OPERATIONS: usize = 500;
+
+/// Longest nickname `contacts.nickname` holds
+pub const MAX_NICKNAME_CHARS: usize = 100;
+
 pub struct ContactsService {
     db: PgPool,
 }
@@ -15,25 +35,29 @@ impl ContactsService {
         Self { db }
     }
 
-    /// Get all contacts for a user
+    /// A page of a user's contacts with their profiles, newest first
     pub async fn get_contacts(
         &self,
         user_id: Uuid,
         include_blocked: bool,
+        limit: i32,
+        offset: i32,
     ) -> AppResult<Vec<ContactWithUser>> {
-        let contacts: Vec<Contact> = if include_blocked {
-            sqlx::query_as("SELECT * FROM contacts WHERE user_id = $1 ORDER BY created_at DESC")
-                .bind(user_id)
-                .fetch_all(&self.db)
-                .await?
-        } else {
-            sqlx::query_as(
-                "SELECT * FROM contacts WHERE user_id = $1 AND is_blocked = false ORDER BY created_at DESC",
-            )
-            .bind(user_id)
-            .fetch_all(&self.db)
-            .await?
-        };
+        let contacts: Vec<Contact> = sqlx::query_as(
+            r#"
+            SELECT c.* FROM contacts c
+            JOIN users u ON u.id = c.contact_id
+            WHERE c.user_id = $1 AND ($2 OR c.is_blocked = false) AND u.deactivated_at IS NULL
+            ORDER BY c.created_at DESC, c.id
+            LIMIT $3 OFFSET $4
+            "#,
+        )
+        .bind(user_id)
+        .bind(include_blocked)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db)
+        .await?;
 
         let mut result = Vec::with_capacity(contacts.len());
         for contact in contacts {
@@ -45,9 +69,53 @@ impl ContactsService {
             result.push(ContactWithUser { contact, user });
         }
 
+        SocialGraphService::new(self.db.clone())
+            .redact_presence(user_id, result.iter_mut().filter_map(|c| c.user.as_mut()))
+            .await?;
+
         Ok(result)
     }
 
+    /// How many contacts `get_contacts` pages through
+    pub async fn count_contacts(&self, user_id: Uuid, include_blocked: bool) -> AppResult<i64> {
+        let count = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM contacts c
+            JOIN users u ON u.id = c.contact_id
+            WHERE c.user_id = $1 AND ($2 OR c.is_blocked = false) AND u.deactivated_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(include_blocked)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(count)
+    }
+
+    /// A user's contacts, newest first, without the contacts' profiles.
+    /// Deactivated accounts are left out until they come back.
+    pub async fn list_contacts(
+        &self,
+        user_id: Uuid,
+        include_blocked: bool,
+    ) -> AppResult<Vec<Contact>> {
+        let contacts: Vec<Contact> = sqlx::query_as(
+            r#"
+            SELECT c.* FROM contacts c
+            JOIN users u ON u.id = c.contact_id
+            WHERE c.user_id = $1 AND ($2 OR c.is_blocked = false) AND u.deactivated_at IS NULL
+            ORDER BY c.created_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(include_blocked)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(contacts)
+    }
+
     /// Add a new contact
     pub async fn add_contact(
         &self,
@@ -59,12 +127,16 @@ impl ContactsService {
         if user_id == contact_id {
             return Err(AppError::CannotAddSelf);
         }
+        if nickname.is_some_and(is_too_long) {
+            return Err(nickname_too_long());
+        }
 
         // Check if contact user exists
-        let contact_user: Option<User> = sqlx::query_as("SELECT * FROM users WHERE id = $1")
-            .bind(contact_id)
-            .fetch_optional(&self.db)
-            .await?;
+        let contact_user: Option<User> =
+            sqlx::query_as("SELECT * FROM users WHERE id = $1 AND deactivated_at IS NULL")
+                .bind(contact_id)
+                .fetch_optional(&self.db)
+                .await?;
 
         if contact_user.is_none() {
             return Err(AppError::UserNotFound);
@@ -98,10 +170,14 @@ impl ContactsService {
         .fetch_one(&self.db)
         .await?;
 
-        Ok(ContactWithUser {
-            contact,
-            user: contact_user,
-        })
+        accept_requests_from(&self.db, user_id, contact_id).await?;
+
+        let mut user = contact_user;
+        SocialGraphService::new(self.db.clone())
+            .redact_presence(user_id, user.as_mut())
+            .await?;
+
+        Ok(ContactWithUser { contact, user })
     }
 
     /// Get a specific contact
@@ -116,11 +192,15 @@ impl ContactsService {
 
         let contact = contact.ok_or(AppError::ContactNotFound)?;
 
-        let user: Option<User> = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+        let mut user: Option<User> = sqlx::query_as("SELECT * FROM users WHERE id = $1")
             .bind(contact.contact_id)
             .fetch_optional(&self.db)
             .await?;
 
+        SocialGraphService::new(self.db.clone())
+            .redact_presence(user_id, user.as_mut())
+            .await?;
+
         Ok(ContactWithUser { contact, user })
     }
 
@@ -132,6 +212,10 @@ impl ContactsService {
         nickname: Option<&str>,
         is_favorite: Option<bool>,
     ) -> AppResult<ContactWithUser> {
+        if nickname.is_some_and(is_too_long) {
+            return Err(nickname_too_long());
+        }
+
         let contact: Option<Contact> = sqlx::query_as(
             r#"
             UPDATE contacts
@@ -151,11 +235,15 @@ impl ContactsService {
 
         let contact = contact.ok_or(AppError::ContactNotFound)?;
 
-        let user: Option<User> = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+        let mut user: Option<User> = sqlx::query_as("SELECT * FROM users WHERE id = $1")
             .bind(contact.contact_id)
             .fetch_optional(&self.db)
             .await?;
 
+        SocialGraphService::new(self.db.clone())
+            .redact_presence(user_id, user.as_mut())
+            .await?;
+
         Ok(ContactWithUser { contact, user })
     }
 
@@ -174,6 +262,157 @@ impl ContactsService {
         Ok(())
     }
 
+    /// Add, update and delete many contacts in one transaction, as after an
+    /// address book sync. An operation that can't apply (adding yourself, a
+    /// missing user, a duplicate, or changing a contact you don't have) is
+    /// reported in its result without affecting the others.
+    pub async fn bulk_update(
+        &self,
+        user_id: Uuid,
+        operations: Vec<ContactOperation>,
+    ) -> AppResult<Vec<ContactOperationResult>> {
+        if operations.len() > MAX_BULK_OPERATIONS {
+            return Err(AppError::Validation(format!(
+                "At most {} operations per request",
+                MAX_BULK_OPERATIONS
+            )));
+        }
+
+        let add_ids: Vec<Uuid> = operations
+            .iter()
+            .filter(|op| matches!(op, ContactOperation::Add { .. }))
+            .map(ContactOperation::contact_id)
+            .collect();
+        let addable: HashSet<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM users WHERE id = ANY($1) AND deactivated_at IS NULL",
+        )
+        .bind(&add_ids)
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .collect();
+
+        let mut outcomes: Vec<(Uuid, Result<Option<Contact>, AppError>)> =
+            Vec::with_capacity(operations.len());
+        let mut tx = self.db.begin().await?;
+        for operation in operations {
+            let contact_id = operation.contact_id();
+            let outcome = match operation {
+                ContactOperation::Add { .. } if contact_id == user_id => {
+                    Err(AppError::CannotAddSelf)
+                }
+                ContactOperation::Add { .. } if !addable.contains(&contact_id) => {
+                    Err(AppError::UserNotFound)
+                }
+                ContactOperation::Add {
+                    nickname: Some(ref nickname),
+                    ..
+                }
+                | ContactOperation::Update {
+                    nickname: Some(ref nickname),
+                    ..
+                } if is_too_long(nickname) => Err(nickname_too_long()),
+                ContactOperation::Add { nickname, .. } => {
+                    let contact: Option<Contact> = sqlx::query_as(
+                        r#"
+                        INSERT INTO contacts
+                            (id, user_id, contact_id, nickname, is_blocked, is_favorite)
+                        VALUES ($1, $2, $3, $4, false, false)
+                        ON CONFLICT (user_id, contact_id) DO NOTHING
+                        RETURNING *
+                        "#,
+                    )
+                    .bind(Uuid::new_v4())
+                    .bind(user_id)
+                    .bind(contact_id)
+                    .bind(nickname)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+
+                    if contact.is_some() {
+                        accept_requests_from(&mut *tx, user_id, contact_id).await?;
+                    }
+                    contact.map(Some).ok_or(AppError::ContactAlreadyExists)
+                }
+                ContactOperation::Update {
+                    nickname,
+                    is_favorite,
+                    ..
+                } => {
+                    let contact: Option<Contact> = sqlx::query_as(
+                        r#"
+                        UPDATE contacts
+                        SET nickname = COALESCE($3, nickname),
+                            is_favorite = COALESCE($4, is_favorite),
+                            updated_at = NOW()
+                        WHERE user_id = $1 AND contact_id = $2
+                        RETURNING *
+                        "#,
+                    )
+                    .bind(user_id)
+                    .bind(contact_id)
+                    .bind(nickname)
+                    .bind(is_favorite)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+
+                    contact.map(Some).ok_or(AppError::ContactNotFound)
+                }
+                ContactOperation::Delete { .. } => {
+                    let result =
+                        sqlx::query("DELETE FROM contacts WHERE user_id = $1 AND contact_id = $2")
+                            .bind(user_id)
+                            .bind(contact_id)
+                            .execute(&mut *tx)
+                            .await?;
+
+                    if result.rows_affected() == 0 {
+                        Err(AppError::ContactNotFound)
+                    } else {
+                        Ok(None)
+                    }
+                }
+            };
+            outcomes.push((contact_id, outcome));
+        }
+        tx.commit().await?;
+
+        let user_ids: Vec<Uuid> = outcomes
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, Ok(Some(_))))
+            .map(|(contact_id, _)| *contact_id)
+            .collect();
+        let mut users: Vec<User> = sqlx::query_as("SELECT * FROM users WHERE id = ANY($1)")
+            .bind(&user_ids)
+            .fetch_all(&self.db)
+            .await?;
+        SocialGraphService::new(self.db.clone())
+            .redact_presence(user_id, users.iter_mut())
+            .await?;
+        let users: HashMap<Uuid, User> = users.into_iter().map(|u| (u.id, u)).collect();
+
+        Ok(outcomes
+            .into_iter()
+            .map(|(contact_id, outcome)| match outcome {
+                Ok(contact) => ContactOperationResult {
+                    contact_id,
+                    ok: true,
+                    contact: contact.map(|contact| ContactWithUser {
+                        user: users.get(&contact_id).cloned(),
+                        contact,
+                    }),
+                    error: None,
+                },
+                Err(e) => ContactOperationResult {
+                    contact_id,
+                    ok: false,
+                    contact: None,
+                    error: Some(e.to_string()),
+                },
+            })
+            .collect())
+    }
+
     /// Block a contact
     pub async fn block_contact(&self, user_id: Uuid, contact_id: Uuid) -> AppResult<()> {
         // First ensure contact exists or create it as blocked
@@ -226,32 +465,212 @@ impl ContactsService {
             result.push(ContactWithUser { contact, user });
         }
 
+        SocialGraphService::new(self.db.clone())
+            .redact_presence(user_id, result.iter_mut().filter_map(|c| c.user.as_mut()))
+            .await?;
+
         Ok(result)
     }
 
     /// Search users by username or display name
-    pub async fn search_users(&self, query: &str, limit: i32) -> AppResult<Vec<User>> {
-        let search_pattern = format!("%{}%", query.to_lowercase());
-
-        let users: Vec<User> = sqlx::query_as(
+    pub async fn search_users(
+        &self,
+        query: &str,
+        exclude_user_id: Uuid,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<UserSearchResult>> {
+        let query = query.to_lowercase();
+        let search_pattern = format!("%{}%", escape_like(&query));
+
+        // Substring and fuzzy matches are both served by the trigram indexes
+        let mut users: Vec<UserSearchResult> = sqlx::query_as(
             r#"
-            SELECT * FROM users
-            WHERE LOWER(username) LIKE $1 OR LOWER(display_name) LIKE $1
-            LIMIT $2
+            SELECT u.*,
+                GREATEST(similarity(LOWER(u.username), $1), similarity(LOWER(u.display_name), $1)) AS score
+            FROM users u
+            WHERE u.id != $3 AND u.deactivated_at IS NULL
+            AND (
+                LOWER(u.username) LIKE $2 OR LOWER(u.display_name) LIKE $2
+                OR LOWER(u.username) % $1 OR LOWER(u.display_name) % $1
+            )
+            ORDER BY score DESC, u.username
+            LIMIT $4 OFFSET $5
             "#,
         )
+        .bind(&query)
         .bind(&search_pattern)
+        .bind(exclude_user_id)
         .bind(limit)
+        .bind(offset)
         .fetch_all(&self.db)
         .await?;
 
+        SocialGraphService::new(self.db.clone())
+            .redact_presence(exclude_user_id, users.iter_mut().map(|u| &mut u.user))
+            .await?;
+
         Ok(users)
     }
 
+    /// The user's contacts whose nickname, username or display name match,
+    /// best match first. Blocked contacts are left out.
+    pub async fn search_contacts(
+        &self,
+        user_id: Uuid,
+        query: &str,
+        limit: i32,
+    ) -> AppResult<Vec<ContactWithUser>> {
+        let query = query.to_lowercase();
+        let search_pattern = format!("%{}%", escape_like(&query));
+
+        let contacts: Vec<Contact> = sqlx::query_as(
+            r#"
+            SELECT c.* FROM contacts c
+            JOIN users u ON u.id = c.contact_id
+            WHERE c.user_id = $1 AND NOT c.is_blocked AND u.deactivated_at IS NULL
+            AND (
+                LOWER(c.nickname) LIKE $3 OR LOWER(u.username) LIKE $3
+                OR LOWER(u.display_name) LIKE $3
+                OR LOWER(c.nickname) % $2 OR LOWER(u.username) % $2
+                OR LOWER(u.display_name) % $2
+            )
+            ORDER BY GREATEST(
+                similarity(LOWER(c.nickname), $2),
+                similarity(LOWER(u.username), $2),
+                similarity(LOWER(u.display_name), $2)
+            ) DESC, c.is_favorite DESC, c.id
+            LIMIT $4
+            "#,
+        )
+        .bind(user_id)
+        .bind(&query)
+        .bind(&search_pattern)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?;
+
+        let user_ids: Vec<Uuid> = contacts.iter().map(|c| c.contact_id).collect();
+        let mut users: Vec<User> = sqlx::query_as("SELECT * FROM users WHERE id = ANY($1)")
+            .bind(&user_ids)
+            .fetch_all(&self.db)
+            .await?;
+        SocialGraphService::new(self.db.clone())
+            .redact_presence(user_id, users.iter_mut())
+            .await?;
+        let mut users: HashMap<Uuid, User> = users.into_iter().map(|u| (u.id, u)).collect();
+
+        Ok(contacts
+            .into_iter()
+            .map(|contact| ContactWithUser {
+                user: users.remove(&contact.contact_id),
+                contact,
+            })
+            .collect())
+    }
+
+    /// Add the registered users among imported address book entries as
+    /// contacts, nicknamed as in the address book, and report the rest
+    pub async fn import_contacts(
+        &self,
+        user_id: Uuid,
+        entries: Vec<ImportedContact>,
+    ) -> AppResult<ContactImportReport> {
+        let (entries, skipped): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|e| !e.phones.is_empty() || !e.emails.is_empty());
+
+        let identifiers: Vec<&String> = entries
+            .iter()
+            .flat_map(|e| e.phones.iter().chain(&e.emails))
+            .collect();
+        let users: Vec<User> = sqlx::query_as(
+            r#"
+            SELECT * FROM users
+            WHERE (phone = ANY($1) OR LOWER(email) = ANY($1)) AND id != $2
+            AND deactivated_at IS NULL
+            "#,
+        )
+        .bind(&identifiers)
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut by_identifier = HashMap::new();
+        for user in &users {
+            if let Some(phone) = &user.phone {
+                by_identifier.insert(phone.clone(), user);
+            }
+            if let Some(email) = &user.email {
+                by_identifier.insert(email.to_lowercase(), user);
+            }
+        }
+
+        let mut report = ContactImportReport {
+            matched: Vec::new(),
+            unmatched: Vec::new(),
+            skipped: skipped.len(),
+        };
+        let mut seen = HashSet::new();
+        let mut tx = self.db.begin().await?;
+        for entry in entries {
+            let found = entry
+                .phones
+                .iter()
+                .chain(&entry.emails)
+                .find_map(|id| by_identifier.get(id).map(|user| (id.clone(), *user)));
+            let Some((identifier, user)) = found else {
+                report.unmatched.push(entry);
+                continue;
+            };
+
+            // The same person listed twice is added once
+            let mut added = false;
+            if seen.insert(user.id) {
+                let result = sqlx::query(
+                    r#"
+                    INSERT INTO contacts
+                        (id, user_id, contact_id, nickname, is_blocked, is_favorite)
+                    VALUES ($1, $2, $3, $4, false, false)
+                    ON CONFLICT (user_id, contact_id) DO NOTHING
+                    "#,
+                )
+                .bind(Uuid::new_v4())
+                .bind(user_id)
+                .bind(user.id)
+                .bind(&entry.name)
+                .execute(&mut *tx)
+                .await?;
+                added = result.rows_affected() > 0;
+                if added {
+                    accept_requests_from(&mut *tx, user_id, user.id).await?;
+                }
+            }
+
+            report.matched.push(ImportMatch {
+                name: entry.name,
+                identifier,
+                user: user.clone(),
+                added,
+            });
+        }
+        tx.commit().await?;
+
+        let unmatched: Vec<&str> = report
+            .unmatched
+            .iter()
+            .flat_map(|e| e.phones.iter().chain(&e.emails))
+            .map(String::as_str)
+            .collect();
+        self.record_interests(user_id, &unmatched).await?;
+
+        Ok(report)
+    }
+
     /// Sync contacts from phone identifiers (phone numbers or emails)
     pub async fn sync_contacts(
         &self,
-        _user_id: Uuid,
+        user_id: Uuid,
         identifiers: Vec<String>,
     ) -> AppResult<Vec<User>> {
         if identifiers.is_empty() {
@@ -259,12 +678,199 @@ impl ContactsService {
         }
 
         let users: Vec<User> = sqlx::query_as(
-            "SELECT * FROM users WHERE phone = ANY($1) OR email = ANY($1)",
+            r#"
+            SELECT * FROM users
+            WHERE (phone = ANY($1) OR email = ANY($1)) AND deactivated_at IS NULL
+            "#,
         )
         .bind(&identifiers)
         .fetch_all(&self.db)
         .await?;
 
+        let unregistered: Vec<&str> = identifiers
+            .iter()
+            .filter(|id| {
+                !users
+                    .iter()
+                    .any(|u| u.phone.as_ref() == Some(id) || u.email.as_ref() == Some(id))
+            })
+            .map(String::as_str)
+            .collect();
+        self.record_interests(user_id, &unregistered).await?;
+
         Ok(users)
     }
+
+    /// Remember phones and emails from the user's address book that no one
+    /// has registered with, so they can be told when someone does. Nothing
+    /// is kept for users who turned join notices off.
+    pub async fn record_interests(&self, user_id: Uuid, identifiers: &[&str]) -> AppResult<()> {
+        let mut hashes: Vec<Vec<u8>> = identifiers
+            .iter()
+            .filter_map(|id| identifier_hash(id))
+            .collect();
+        hashes.sort();
+        hashes.dedup();
+        if hashes.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = self.db.begin().await?;
+
+        // Locking the user keeps concurrent syncs from both filling the last room
+        let notices: Option<bool> =
+            sqlx::query_scalar("SELECT contact_joined_notices FROM users WHERE id = $1 FOR UPDATE")
+                .bind(user_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if notices != Some(true) {
+            return Ok(());
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE contact_interests SET created_at = NOW()
+            WHERE user_id = $1 AND identifier_hash = ANY($2)
+            "#,
+        )
+        .bind(user_id)
+        .bind(&hashes)
+        .execute(&mut *tx)
+        .await?;
+
+        // New entries only take what room is left under the cap
+        sqlx::query(
+            r#"
+            INSERT INTO contact_interests (user_id, identifier_hash)
+            SELECT $1, hash FROM UNNEST($2::BYTEA[]) AS hash
+            WHERE NOT EXISTS (
+                SELECT 1 FROM contact_interests WHERE user_id = $1 AND identifier_hash = hash
+            )
+            LIMIT GREATEST($3 - (SELECT COUNT(*) FROM contact_interests WHERE user_id = $1), 0)
+            ON CONFLICT (user_id, identifier_hash) DO NOTHING
+            "#,
+        )
+        .bind(user_id)
+        .bind(&hashes)
+        .bind(MAX_CONTACT_INTERESTS)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Users who had the new user's phone or email in their address book and
+    /// want to hear about it. Their interest is used up. Only the identifier
+    /// registration verified counts: the phone when there is one, else the
+    /// email, so an unverified address can't claim someone else's notices.
+    pub async fn take_interested(&self, user: &User) -> AppResult<Vec<Uuid>> {
+        let hashes: Vec<Vec<u8>> = user
+            .phone
+            .as_ref()
+            .or(user.email.as_ref())
+            .and_then(|id| identifier_hash(id))
+            .into_iter()
+            .collect();
+        if hashes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut interested: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            DELETE FROM contact_interests i
+            USING users u
+            WHERE i.identifier_hash = ANY($1) AND u.id = i.user_id
+            AND u.contact_joined_notices AND u.banned_at IS NULL AND u.id != $2
+            RETURNING i.user_id
+            "#,
+        )
+        .bind(&hashes)
+        .bind(user.id)
+        .fetch_all(&self.db)
+        .await?;
+
+        interested.sort();
+        interested.dedup();
+        Ok(interested)
+    }
+
+    /// Turn "joined Ansible Talk" notices on or off. Turning them off also
+    /// forgets the stored phones and emails.
+    pub async fn set_contact_joined_notices(&self, user_id: Uuid, enabled: bool) -> AppResult<()> {
+        let mut tx = self.db.begin().await?;
+        Self::save_contact_joined_notices(&mut tx, user_id, enabled).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Set join notices within the caller's transaction
+    pub async fn save_contact_joined_notices(
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        enabled: bool,
+    ) -> AppResult<()> {
+        sqlx::query("UPDATE users SET contact_joined_notices = $2 WHERE id = $1")
+            .bind(user_id)
+            .bind(enabled)
+            .execute(&mut *conn)
+            .await?;
+
+        if !enabled {
+            sqlx::query("DELETE FROM contact_interests WHERE user_id = $1")
+                .bind(user_id)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Adding someone as a contact accepts their pending message requests
+async fn accept_requests_from<'e, E: PgExecutor<'e>>(
+    executor: E,
+    user_id: Uuid,
+    contact_id: Uuid,
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE participants p SET is_request = false
+        FROM conversations c, participants other
+        WHERE p.user_id = $1 AND p.is_request
+        AND c.id = p.conversation_id AND c.type = 'direct'
+        AND other.conversation_id = p.conversation_id AND other.user_id = $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(contact_id)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Phones and emails hash alike however the address book wrote them. The
+/// hash is unsalted so it can be matched at registration, which also means
+/// a phone number is easily recovered from it by trying every number.
+fn identifier_hash(identifier: &str) -> Option<Vec<u8>> {
+    let normalized = if identifier.contains('@') {
+        normalize_email(identifier)
+    } else {
+        normalize_phone(identifier)
+    };
+    normalized.map(|id| Sha256::digest(id.as_bytes()).to_vec())
+}
+
+fn is_too_long(nickname: &str) -> bool {
+    nickname.chars().count() > MAX_NICKNAME_CHARS
+}
+
+fn nickname_too_long() -> AppError {
+    AppError::Validation(format!(
+        "Nicknames are at most {} characters",
+        MAX_NICKNAME_CHARS
+    ))
 }
@@ -0,0 +1,316 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::ModerationConfig,
+    error::{AppError, AppResult},
+    services::moderation::ModerationService,
+    storage::{blob::BlobStorage, redis::RedisClient},
+};
+
+/// What to do with content, ordered from most to least permissive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ModerationAction {
+    Allow,
+    /// Accept the content but raise an alert for review
+    Flag,
+    /// Hold the content out of view until a moderator reviews it
+    Quarantine,
+    Reject,
+}
+
+impl ModerationAction {
+    fn from_config(value: &str) -> Self {
+        match value {
+            "reject" => ModerationAction::Reject,
+            "quarantine" => ModerationAction::Quarantine,
+            "flag" => ModerationAction::Flag,
+            other => {
+                tracing::warn!("Unknown moderation action '{}', flagging instead", other);
+                ModerationAction::Flag
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentSource {
+    Avatar,
+    GroupAvatar,
+    StickerCover,
+    Sticker,
+    Emoji,
+    Wallpaper,
+    Message,
+    Attachment,
+}
+
+impl ContentSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentSource::Avatar => "avatar",
+            ContentSource::GroupAvatar => "group_avatar",
+            ContentSource::StickerCover => "sticker_cover",
+            ContentSource::Sticker => "sticker",
+            ContentSource::Emoji => "emoji",
+            ContentSource::Wallpaper => "wallpaper",
+            ContentSource::Message => "message",
+            ContentSource::Attachment => "attachment",
+        }
+    }
+}
+
+/// A piece of user-supplied content entering the system
+pub struct ContentSubject<'a> {
+    pub source: ContentSource,
+    pub uploader_id: Uuid,
+    pub conversation_id: Option<Uuid>,
+    pub content_type: Option<&'a str>,
+    pub data: &'a [u8],
+}
+
+/// A single check's opinion on a subject
+#[derive(Debug, Clone)]
+pub struct ModerationFinding {
+    pub check: &'static str,
+    pub action: ModerationAction,
+    pub reason: String,
+    /// Whether moderators should be alerted about this finding
+    pub alert: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModerationVerdict {
+    pub action: ModerationAction,
+    pub findings: Vec<ModerationFinding>,
+}
+
+impl ModerationVerdict {
+    fn summary(&self) -> String {
+        self.findings
+            .iter()
+            .map(|f| format!("{}: {}", f.check, f.reason))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// A hook in the moderation pipeline
+#[async_trait]
+pub trait ContentCheck: Send + Sync {
+    async fn check(&self, subject: &ContentSubject<'_>) -> AppResult<Option<ModerationFinding>>;
+}
+
+/// Matches content against a list of known-bad hashes (PhotoDNA-style).
+/// Returns the matched list label, if any.
+#[async_trait]
+pub trait HashMatcher: Send + Sync {
+    async fn find_match(&self, data: &[u8]) -> AppResult<Option<String>>;
+}
+
+/// Exact SHA-256 matching against the `blocked_content_hashes` table
+pub struct BlocklistHashMatcher {
+    db: PgPool,
+}
+
+impl BlocklistHashMatcher {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl HashMatcher for BlocklistHashMatcher {
+    async fn find_match(&self, data: &[u8]) -> AppResult<Option<String>> {
+        let label: Option<String> =
+            sqlx::query_scalar("SELECT label FROM blocked_content_hashes WHERE hash = $1")
+                .bind(content_hash(data))
+                .fetch_optional(&self.db)
+                .await?;
+
+        Ok(label)
+    }
+}
+
+pub struct HashMatchCheck {
+    matcher: Box<dyn HashMatcher>,
+    action: ModerationAction,
+}
+
+#[async_trait]
+impl ContentCheck for HashMatchCheck {
+    async fn check(&self, subject: &ContentSubject<'_>) -> AppResult<Option<ModerationFinding>> {
+        let finding = self
+            .matcher
+            .find_match(subject.data)
+            .await?
+            .map(|label| ModerationFinding {
+                check: "hash_match",
+                action: self.action,
+                reason: format!("matched hash list '{}'", label),
+                alert: true,
+            });
+
+        Ok(finding)
+    }
+}
+
+/// Size and content-type limits for avatars, stickers and wallpapers
+pub struct SizeTypePolicy {
+    max_bytes: usize,
+    allowed_types: Vec<String>,
+    action: ModerationAction,
+}
+
+#[async_trait]
+impl ContentCheck for SizeTypePolicy {
+    async fn check(&self, subject: &ContentSubject<'_>) -> AppResult<Option<ModerationFinding>> {
+        // Messages and attachments have their own limits, see `message_policy`
+        if matches!(subject.source, ContentSource::Message | ContentSource::Attachment) {
+            return Ok(None);
+        }
+
+        let reason = if subject.data.len() > self.max_bytes {
+            Some(format!("exceeds {} bytes", self.max_bytes))
+        } else {
+            let content_type = subject.content_type.unwrap_or("").to_lowercase();
+            (!self.allowed_types.contains(&content_type))
+                .then(|| format!("content type '{}' not allowed", content_type))
+        };
+
+        Ok(reason.map(|reason| ModerationFinding {
+            check: "size_type_policy",
+            action: self.action,
+            reason,
+            alert: false,
+        }))
+    }
+}
+
+pub struct ModerationPipeline {
+    db: PgPool,
+    redis: RedisClient,
+    checks: Vec<Box<dyn ContentCheck>>,
+}
+
+impl ModerationPipeline {
+    pub fn new(db: PgPool, redis: RedisClient, config: &ModerationConfig) -> Self {
+        let mut checks: Vec<Box<dyn ContentCheck>> = vec![Box::new(SizeTypePolicy {
+            max_bytes: config.max_upload_bytes,
+            allowed_types: config.allowed_media_types.clone(),
+            action: ModerationAction::from_config(&config.policy_action),
+        })];
+
+        match config.hash_provider.as_str() {
+            "blocklist" => checks.push(Box::new(HashMatchCheck {
+                matcher: Box::new(BlocklistHashMatcher::new(db.clone())),
+                action: ModerationAction::from_config(&config.hash_match_action),
+            })),
+            "none" => {}
+            other => {
+                tracing::warn!("Unknown moderation hash provider '{}', skipping", other);
+            }
+        }
+
+        Self { db, redis, checks }
+    }
+
+    /// Run every check and apply the strictest action.
+    ///
+    /// Rejections are returned as `ContentRejected`; anything else is returned
+    /// to the caller to handle. Findings that warrant review (hash matches)
+    /// raise a moderator alert whatever the action.
+    pub async fn review(&self, subject: &ContentSubject<'_>) -> AppResult<ModerationVerdict> {
+        let mut findings = Vec::new();
+        for check in &self.checks {
+            if let Some(finding) = check.check(subject).await? {
+                findings.push(finding);
+            }
+        }
+
+        let verdict = ModerationVerdict {
+            action: findings
+                .iter()
+                .map(|f| f.action)
+                .max()
+                .unwrap_or(ModerationAction::Allow),
+            findings,
+        };
+
+        if verdict.action != ModerationAction::Allow {
+            tracing::info!(
+                "Moderation {:?} for {} from {}: {}",
+                verdict.action,
+                subject.source.as_str(),
+                subject.uploader_id,
+                verdict.summary()
+            );
+        }
+
+        if verdict.findings.iter().any(|f| f.alert) {
+            let reason = match verdict.action {
+                ModerationAction::Reject => "content_rejected",
+                ModerationAction::Quarantine => "content_quarantined",
+                _ => "content_flagged",
+            };
+            ModerationService::new(self.db.clone(), self.redis.clone())
+                .create_alert(
+                    subject.uploader_id,
+                    subject.conversation_id,
+                    reason,
+                    Some(format!(
+                        "{} sha256={}: {}",
+                        subject.source.as_str(),
+                        content_hash(subject.data),
+                        verdict.summary()
+                    )),
+                )
+                .await?;
+        }
+
+        if verdict.action == ModerationAction::Reject {
+            return Err(AppError::ContentRejected(verdict.summary()));
+        }
+
+        Ok(verdict)
+    }
+
+    /// Review a media upload. Quarantined uploads are stored privately for
+    /// moderators and refused to the uploader; flagged uploads go through.
+    pub async fn screen_upload(
+        &self,
+        storage: &dyn BlobStorage,
+        subject: &ContentSubject<'_>,
+    ) -> AppResult<()> {
+        let verdict = self.review(subject).await?;
+
+        if verdict.action == ModerationAction::Quarantine {
+            // Keyed by hash so moderators can find it from the alert details
+            let key = format!(
+                "quarantine/{}/{}",
+                subject.source.as_str(),
+                content_hash(subject.data)
+            );
+            storage
+                .upload_private_file(
+                    storage.attachments_bucket(),
+                    &key,
+                    Bytes::copy_from_slice(subject.data),
+                    subject.content_type.unwrap_or("application/octet-stream"),
+                )
+                .await?;
+
+            return Err(AppError::ContentQuarantined);
+        }
+
+        Ok(())
+    }
+}
+
+/// Hex-encoded SHA-256, the format stored in `blocked_content_hashes`
+pub fn content_hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
@@ -1,15 +1,33 @@
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use rand::Rng;
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use uuid::Uuid;
 
 use crate::{
     error::{AppError, AppResult},
-    models::{
-        KeyBundle, PreKeyBundle, RegisterKeysRequest, SignedPreKeyBundle,
-    },
+    models::{KeyBundle, PreKeyBundle, RegisterKeysRequest, SignedPreKeyBundle, PADDING_MARKER},
 };
 
+/// Signal wire messages open with the protocol version in both nibbles
+const CIPHERTEXT_VERSION: u8 = 0x33;
+
+/// The version byte plus the truncated MAC every Signal message ends with
+const MIN_CIPHERTEXT_LEN: usize = 1 + 8;
+
+/// Padded content is never shorter than this, so short messages all look
+/// alike
+const MIN_PADDED_LEN: usize = 256;
+
+/// Most one-time pre-keys one upload may carry
+const MAX_PRE_KEY_BATCH: usize = 100;
+
+/// Most one-time pre-keys stored per device
+const MAX_STORED_PRE_KEYS: i64 = 200;
+
+/// Signed pre-keys kept per device after rotation, so messages still in
+/// flight under a replaced key can be decrypted
+const SIGNED_PRE_KEY_HISTORY: i64 = 5;
+
 pub struct CryptoService {
     db: PgPool,
 }
@@ -20,6 +38,7 @@ impl CryptoService {
     }
 
     /// Generate a registration ID (14-bit random number)
+    #[allow(dead_code)]
     pub fn generate_registration_id() -> i32 {
         let mut rng = rand::thread_rng();
         rng.gen_range(1..16381)
@@ -76,26 +95,7 @@ impl CryptoService {
         .await?;
 
         // Store pre-keys
-        for pre_key in &req.pre_keys {
-            let pre_key_public = BASE64
-                .decode(&pre_key.public_key)
-                .map_err(|_| AppError::BadRequest("Invalid pre-key encoding".to_string()))?;
-
-            sqlx::query(
-                r#"
-                INSERT INTO signal_prekeys (id, user_id, device_id, key_id, public_key)
-                VALUES ($1, $2, $3, $4, $5)
-                ON CONFLICT (user_id, device_id, key_id) DO NOTHING
-                "#,
-            )
-            .bind(Uuid::new_v4())
-            .bind(user_id)
-            .bind(req.device_id)
-            .bind(pre_key.key_id)
-            .bind(&pre_key_public)
-            .execute(&mut *tx)
-            .await?;
-        }
+        store_pre_keys(&mut tx, user_id, req.device_id, &req.pre_keys).await?;
 
         tx.commit().await?;
         Ok(())
@@ -103,6 +103,26 @@ impl CryptoService {
 
     /// Get key bundle for establishing a session
     pub async fn get_key_bundle(&self, user_id: Uuid, device_id: i32) -> AppResult<KeyBundle> {
+        // Users who opted in only expose keys of devices their primary device verified
+        let trust: Option<(bool, Option<bool>)> = sqlx::query_as(
+            r#"
+            SELECT u.verified_devices_only, d.verified
+            FROM users u
+            LEFT JOIN devices d ON d.user_id = u.id AND d.device_id = $2
+            WHERE u.id = $1
+            "#,
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        if let Some((true, verified)) = trust {
+            if verified != Some(true) {
+                return Err(AppError::DeviceNotVerified);
+            }
+        }
+
         // Get identity key
         let identity: Option<(Vec<u8>, i32)> = sqlx::query_as(
             "SELECT public_key, registration_id FROM signal_identity_keys WHERE user_id = $1 AND device_id = $2",
@@ -116,7 +136,11 @@ impl CryptoService {
 
         // Get signed pre-key
         let signed_pre_key: Option<(i32, Vec<u8>, Vec<u8>)> = sqlx::query_as(
-            "SELECT key_id, public_key, signature FROM signal_signed_prekeys WHERE user_id = $1 AND device_id = $2 ORDER BY key_id DESC LIMIT 1",
+            r#"
+            SELECT key_id, public_key, signature FROM signal_signed_prekeys
+            WHERE user_id = $1 AND device_id = $2
+            ORDER BY updated_at DESC NULLS LAST, key_id DESC LIMIT 1
+            "#,
         )
         .bind(user_id)
         .bind(device_id)
@@ -184,26 +208,9 @@ impl CryptoService {
         device_id: i32,
         pre_keys: Vec<PreKeyBundle>,
     ) -> AppResult<()> {
-        for pre_key in pre_keys {
-            let public_key = BASE64
-                .decode(&pre_key.public_key)
-                .map_err(|_| AppError::BadRequest("Invalid pre-key encoding".to_string()))?;
-
-            sqlx::query(
-                r#"
-                INSERT INTO signal_prekeys (id, user_id, device_id, key_id, public_key)
-                VALUES ($1, $2, $3, $4, $5)
-                ON CONFLICT (user_id, device_id, key_id) DO NOTHING
-                "#,
-            )
-            .bind(Uuid::new_v4())
-            .bind(user_id)
-            .bind(device_id)
-            .bind(pre_key.key_id)
-            .bind(&public_key)
-            .execute(&self.db)
-            .await?;
-        }
+        let mut tx = self.db.begin().await?;
+        store_pre_keys(&mut tx, user_id, device_id, &pre_keys).await?;
+        tx.commit().await?;
 
         Ok(())
     }
@@ -242,7 +249,32 @@ impl CryptoService {
         Ok(())
     }
 
+    /// Delete each device's signed pre-keys beyond its newest
+    /// `SIGNED_PRE_KEY_HISTORY`
+    pub async fn purge_old_signed_pre_keys(&self) -> AppResult<u64> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM signal_signed_prekeys WHERE id IN (
+                SELECT id FROM (
+                    SELECT id, ROW_NUMBER() OVER (
+                        PARTITION BY user_id, device_id
+                        ORDER BY updated_at DESC NULLS LAST, key_id DESC
+                    ) AS rank
+                    FROM signal_signed_prekeys
+                ) ranked
+                WHERE rank > $1
+            )
+            "#,
+        )
+        .bind(SIGNED_PRE_KEY_HISTORY)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
     /// Get all devices for a user
+    #[allow(dead_code)]
     pub async fn get_user_devices(&self, user_id: Uuid) -> AppResult<Vec<i32>> {
         let devices: Vec<(i32,)> = sqlx::query_as(
             "SELECT DISTINCT device_id FROM signal_identity_keys WHERE user_id = $1",
@@ -254,3 +286,86 @@ impl CryptoService {
         Ok(devices.into_iter().map(|(d,)| d).collect())
     }
 }
+
+/// Store a batch of one-time pre-keys in one insert, skipping key IDs the
+/// device already has, and refuse uploads that would leave it with more than
+/// `MAX_STORED_PRE_KEYS`
+async fn store_pre_keys(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    device_id: i32,
+    pre_keys: &[PreKeyBundle],
+) -> AppResult<()> {
+    if pre_keys.len() > MAX_PRE_KEY_BATCH {
+        return Err(AppError::Validation(format!(
+            "At most {} pre-keys per upload",
+            MAX_PRE_KEY_BATCH
+        )));
+    }
+    if pre_keys.is_empty() {
+        return Ok(());
+    }
+
+    let key_ids: Vec<i32> = pre_keys.iter().map(|k| k.key_id).collect();
+    let public_keys = pre_keys
+        .iter()
+        .map(|k| BASE64.decode(&k.public_key))
+        .collect::<Result<Vec<Vec<u8>>, _>>()
+        .map_err(|_| AppError::BadRequest("Invalid pre-key encoding".to_string()))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO signal_prekeys (user_id, device_id, key_id, public_key)
+        SELECT $1, $2, k.key_id, k.public_key
+        FROM UNNEST($3::INT[], $4::BYTEA[]) AS k(key_id, public_key)
+        ON CONFLICT (user_id, device_id, key_id) DO NOTHING
+        "#,
+    )
+    .bind(user_id)
+    .bind(device_id)
+    .bind(&key_ids)
+    .bind(&public_keys)
+    .execute(&mut *conn)
+    .await?;
+
+    let stored: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM signal_prekeys WHERE user_id = $1 AND device_id = $2",
+    )
+    .bind(user_id)
+    .bind(device_id)
+    .fetch_one(&mut *conn)
+    .await?;
+    if stored > MAX_STORED_PRE_KEYS {
+        return Err(AppError::Validation(format!(
+            "At most {} pre-keys may be stored per device",
+            MAX_STORED_PRE_KEYS
+        )));
+    }
+
+    Ok(())
+}
+
+/// Whether content is framed as a Signal message: the version byte, and not
+/// readable as text. Ciphertext that happens to be valid UTF-8 throughout is
+/// vanishingly unlikely past the MAC.
+pub fn is_signal_ciphertext(content: &[u8]) -> bool {
+    content.len() >= MIN_CIPHERTEXT_LEN
+        && content[0] == CIPHERTEXT_VERSION
+        && std::str::from_utf8(content).is_err()
+}
+
+/// Pad content with `PADDING_MARKER` and zeros to its Padmé bucket, which
+/// leaves only the top few bits of its length visible (at most 12% larger)
+pub fn pad_content(mut content: Vec<u8>) -> Vec<u8> {
+    let len = padded_len(content.len() + 1);
+    content.push(PADDING_MARKER);
+    content.resize(len, 0);
+    content
+}
+
+fn padded_len(len: usize) -> usize {
+    let len = len.max(MIN_PADDED_LEN);
+    let exponent = len.ilog2();
+    let mask = (1_usize << (exponent - exponent.ilog2() - 1)) - 1;
+    (len + mask) & !mask
+}
@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, AppResult},
+    models::{DeliveryLatencyReport, LatencyPercentiles, Message, MessageDelivery},
+    services::{
+        media::MediaUrls,
+        messaging::{MessagingService, WsMessage},
+        notifications::NotificationService,
+        push::PushNotification,
+    },
+    storage::redis::RedisClient,
+};
+
+/// Due deliveries handled per retry pass
+const RETRY_BATCH: i64 = 500;
+
+/// Messages replayed to a device when it connects
+const REPLAY_LIMIT: i64 = 500;
+
+/// Delivery records are dropped after this many days, acknowledged or not
+const RETENTION_DAYS: i32 = 7;
+
+/// Message pushes, summaries included, share one slot on the device so each
+/// replaces the last; `thread_id` keeps them grouped by conversation
+const MESSAGE_COLLAPSE_KEY: &str = "messages";
+
+/// Per-device delivery. Every recipient device acknowledges each message;
+/// connected devices that don't get WebSocket redeliveries with backoff, and
+/// devices that are offline or stay silent past `push_after` get a push.
+pub struct DeliveryService {
+    db: PgPool,
+    redis: RedisClient,
+    config: Config,
+    messaging: MessagingService,
+    notifications: NotificationService,
+}
+
+impl DeliveryService {
+    pub fn new(
+        db: PgPool,
+        redis: RedisClient,
+        config: Config,
+        notifications: NotificationService,
+    ) -> Self {
+        Self {
+            messaging: MessagingService::new(db.clone(), redis.clone(), config.clone()),
+            db,
+            redis,
+            config,
+            notifications,
+        }
+    }
+
+    /// Record a device's acknowledgement. The message also counts as
+    /// delivered to the user, so senders see the usual receipt.
+    pub async fn ack(&self, user_id: Uuid, device_id: i32, message_ids: &[Uuid]) -> AppResult<u64> {
+        let acked = sqlx::query(
+            r#"
+            UPDATE message_deliveries SET acked_at = NOW()
+            WHERE message_id = ANY($1) AND user_id = $2 AND device_id = $3 AND acked_at IS NULL
+            "#,
+        )
+        .bind(message_ids)
+        .bind(user_id)
+        .bind(device_id)
+        .execute(&self.db)
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE delivery_latency_samples SET first_ack_at = NOW()
+            WHERE message_id = ANY($1) AND first_ack_at IS NULL
+            "#,
+        )
+        .bind(message_ids)
+        .execute(&self.db)
+        .await?;
+
+        // The device has caught up on these conversations, so their grouped
+        // pushes start counting from one again
+        let conversation_ids: Vec<String> = sqlx::query_scalar(
+            "SELECT DISTINCT conversation_id::text FROM messages WHERE id = ANY($1)",
+        )
+        .bind(message_ids)
+        .fetch_all(&self.db)
+        .await?;
+        self.redis
+            .clear_message_pushes(&user_id.to_string(), device_id, &conversation_ids)
+            .await?;
+
+        self.messaging
+            .mark_many_as_delivered(message_ids, user_id)
+            .await?;
+
+        Ok(acked.rows_affected())
+    }
+
+    /// Messages the device has not acknowledged, oldest first, to replay
+    /// when it connects
+    pub async fn pending(&self, user_id: Uuid, device_id: i32) -> AppResult<Vec<Message>> {
+        let messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT m.* FROM message_deliveries d
+            JOIN messages m ON m.id = d.message_id
+            WHERE d.user_id = $1 AND d.device_id = $2 AND d.acked_at IS NULL
+            AND m.deleted_at IS NULL
+            ORDER BY m.created_at
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .bind(REPLAY_LIMIT)
+        .fetch_all(&self.db)
+        .await?;
+
+        let urls = MediaUrls::new(&self.config);
+        Ok(messages
+            .into_iter()
+            .map(|message| urls.sign_message(message.decode().redact_view_once()))
+            .collect())
+    }
+
+    /// Delivery state of one of the sender's messages on each recipient device
+    pub async fn deliveries(
+        &self,
+        message_id: Uuid,
+        sender_id: Uuid,
+    ) -> AppResult<Vec<MessageDelivery>> {
+        let is_sender: bool = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM messages WHERE id = $1 AND sender_id = $2 AND deleted_at IS NULL
+            )
+            "#,
+        )
+        .bind(message_id)
+        .bind(sender_id)
+        .fetch_one(&self.db)
+        .await?;
+
+        if !is_sender {
+            return Err(AppError::MessageNotFound);
+        }
+
+        let mut deliveries: Vec<MessageDelivery> = sqlx::query_as(
+            r#"
+            SELECT user_id, device_id,
+                CASE
+                    WHEN acked_at IS NOT NULL THEN 'acked'
+                    WHEN pushed_at IS NOT NULL THEN 'pushed'
+                    ELSE 'pending'
+                END AS state,
+                attempts, pushed_at, acked_at, created_at
+            FROM message_deliveries
+            WHERE message_id = $1
+            ORDER BY user_id, device_id
+            "#,
+        )
+        .bind(message_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        // When a device came online is left to `state`
+        if self.config.privacy.coarse_receipts {
+            for delivery in &mut deliveries {
+                delivery.pushed_at = None;
+                delivery.acked_at = None;
+            }
+        }
+
+        Ok(deliveries)
+    }
+
+    /// Redeliver or push every delivery whose retry is due; returns how many
+    /// were handled. Rows are claimed with SKIP LOCKED, so every instance can
+    /// run this.
+    pub async fn process_due(&self) -> AppResult<usize> {
+        let delivery = &self.config.delivery;
+        let due: Vec<(Uuid, Uuid, i32, DateTime<Utc>)> = sqlx::query_as(
+            r#"
+            WITH due AS (
+                SELECT message_id, user_id, device_id FROM message_deliveries
+                WHERE acked_at IS NULL AND pushed_at IS NULL AND next_attempt_at <= NOW()
+                ORDER BY next_attempt_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            UPDATE message_deliveries d
+            SET attempts = d.attempts + 1,
+                next_attempt_at = NOW()
+                    + make_interval(secs => LEAST($2 * POWER(2, d.attempts), $3))
+            FROM due
+            WHERE d.message_id = due.message_id
+            AND d.user_id = due.user_id AND d.device_id = due.device_id
+            RETURNING d.message_id, d.user_id, d.device_id, d.created_at
+            "#,
+        )
+        .bind(RETRY_BATCH)
+        .bind(delivery.retry_base.as_secs_f64())
+        .bind(delivery.retry_max.as_secs_f64())
+        .fetch_all(&self.db)
+        .await?;
+
+        if due.is_empty() {
+            return Ok(0);
+        }
+
+        let message_ids: Vec<Uuid> = due.iter().map(|(id, ..)| *id).collect();
+        let messages: Vec<Message> =
+            sqlx::query_as("SELECT * FROM messages WHERE id = ANY($1) AND deleted_at IS NULL")
+                .bind(&message_ids)
+                .fetch_all(&self.db)
+                .await?;
+
+        let sender_ids: Vec<Uuid> = messages.iter().map(|m| m.sender_id).collect();
+        let senders: HashMap<Uuid, String> = sqlx::query_as::<_, (Uuid, String)>(
+            "SELECT id, display_name FROM users WHERE id = ANY($1)",
+        )
+        .bind(&sender_ids)
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .collect();
+
+        let urls = MediaUrls::new(&self.config);
+        let messages: HashMap<Uuid, Message> = messages
+            .into_iter()
+            .map(|message| (message.id, urls.sign_message(message.decode().redact_view_once())))
+            .collect();
+
+        let push_after = Duration::seconds(delivery.push_after.as_secs() as i64);
+        let now = Utc::now();
+
+        for (message_id, user_id, device_id, created_at) in &due {
+            let Some(message) = messages.get(message_id) else {
+                // Deleted since it was sent; nothing left to deliver
+                sqlx::query("DELETE FROM message_deliveries WHERE message_id = $1")
+                    .bind(message_id)
+                    .execute(&self.db)
+                    .await?;
+                continue;
+            };
+
+            let connected = self
+                .redis
+                .is_device_connected(&user_id.to_string(), *device_id)
+                .await?;
+
+            if connected && now - *created_at < push_after {
+                self.redeliver(*user_id, *device_id, message).await?;
+            } else {
+                let sender = senders.get(&message.sender_id).map(String::as_str);
+                self.push_fallback(*user_id, *device_id, message, sender)
+                    .await?;
+            }
+        }
+
+        Ok(due.len())
+    }
+
+    /// Delete delivery records and latency samples past the retention period
+    pub async fn purge_expired(&self) -> AppResult<u64> {
+        let result = sqlx::query(
+            "DELETE FROM message_deliveries WHERE created_at < NOW() - make_interval(days => $1)",
+        )
+        .bind(RETENTION_DAYS)
+        .execute(&self.db)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM delivery_latency_samples
+            WHERE received_at < NOW() - make_interval(days => $1)
+            "#,
+        )
+        .bind(RETENTION_DAYS)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn redeliver(&self, user_id: Uuid, device_id: i32, message: &Message) -> AppResult<()> {
+        let ws_message = WsMessage {
+            msg_type: "new_message".to_string(),
+            payload: serde_json::to_value(message)?,
+        };
+
+        self.redis
+            .publish_device_message(
+                &user_id.to_string(),
+                device_id,
+                &serde_json::to_string(&ws_message)?,
+            )
+            .await
+    }
+
+    /// Stop WebSocket retries and push instead. The delivery stays pending
+    /// until the device fetches the message and acknowledges it.
+    async fn push_fallback(
+        &self,
+        user_id: Uuid,
+        device_id: i32,
+        message: &Message,
+        sender: Option<&str>,
+    ) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            UPDATE message_deliveries SET pushed_at = NOW()
+            WHERE message_id = $1 AND user_id = $2 AND device_id = $3
+            "#,
+        )
+        .bind(message.id)
+        .bind(user_id)
+        .bind(device_id)
+        .execute(&self.db)
+        .await?;
+
+        // Nothing is sent while snoozed or in quiet hours, so nothing is
+        // counted toward grouping either
+        if self.notifications.pushes_paused(user_id).await? {
+            return Ok(());
+        }
+        let notification = self
+            .message_push(user_id, device_id, message, sender)
+            .await?;
+
+        // One failing device should not hold up the rest of the batch
+        if let Err(e) = self
+            .notifications
+            .push_to_device(user_id, device_id, &notification)
+            .await
+        {
+            tracing::warn!("Push to {}:{} failed: {}", user_id, device_id, e);
+        }
+
+        Ok(())
+    }
+
+    /// The push for a message. It replaces the device's earlier message push,
+    /// counting up within its conversation, and once `summary_after` pushes
+    /// reach the device within a window, a summary takes its place.
+    async fn message_push(
+        &self,
+        user_id: Uuid,
+        device_id: i32,
+        message: &Message,
+        sender: Option<&str>,
+    ) -> AppResult<PushNotification> {
+        let delivery = &self.config.delivery;
+        let (pushes, conversations, in_conversation) = self
+            .redis
+            .track_message_push(
+                &user_id.to_string(),
+                device_id,
+                &message.conversation_id.to_string(),
+                delivery.group_window,
+            )
+            .await?;
+
+        if delivery.summary_after > 0 && pushes >= delivery.summary_after {
+            return Ok(PushNotification {
+                title: "Ansible Talk".to_string(),
+                body: format!(
+                    "{} new messages in {} {}",
+                    pushes,
+                    conversations,
+                    if conversations == 1 { "chat" } else { "chats" }
+                ),
+                data: json!({
+                    "type": "message_summary",
+                    "count": pushes,
+                    "conversations": conversations,
+                }),
+                collapse_key: Some(MESSAGE_COLLAPSE_KEY.to_string()),
+                thread_id: None,
+                badge: None,
+            });
+        }
+
+        // Content may be end-to-end encrypted, so the push only says who wrote
+        let body = match in_conversation {
+            1 => "New message".to_string(),
+            n => format!("{} new messages", n),
+        };
+        let thread_id = format!("conversation:{}", message.conversation_id);
+
+        Ok(PushNotification {
+            title: sender.unwrap_or("Ansible Talk").to_string(),
+            body,
+            data: json!({
+                "type": "new_message",
+                "conversation_id": message.conversation_id,
+                "message_id": message.id,
+                "count": in_conversation,
+                "thread_id": thread_id,
+            }),
+            collapse_key: Some(MESSAGE_COLLAPSE_KEY.to_string()),
+            thread_id: Some(thread_id),
+            badge: None,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct LatencyRow {
+    samples: i64,
+    acked: i64,
+    fanout: Option<Vec<f64>>,
+    fanout_max: Option<f64>,
+    first_ack: Option<Vec<f64>>,
+    first_ack_max: Option<f64>,
+}
+
+/// Percentiles of the latency samples taken over the last `window`
+pub async fn latency_report(
+    db: &PgPool,
+    window: std::time::Duration,
+) -> AppResult<DeliveryLatencyReport> {
+    let since = Utc::now() - Duration::from_std(window).unwrap_or(Duration::hours(1));
+    let row: LatencyRow = sqlx::query_as(
+        r#"
+        WITH timings AS (
+            SELECT EXTRACT(EPOCH FROM fanout_at - received_at)::FLOAT8 * 1000 AS fanout,
+                EXTRACT(EPOCH FROM first_ack_at - received_at)::FLOAT8 * 1000 AS first_ack
+            FROM delivery_latency_samples
+            WHERE received_at >= $1
+        )
+        SELECT COUNT(*) AS samples, COUNT(first_ack) AS acked,
+            percentile_cont(ARRAY[0.5, 0.9, 0.99]) WITHIN GROUP (ORDER BY fanout) AS fanout,
+            MAX(fanout) AS fanout_max,
+            percentile_cont(ARRAY[0.5, 0.9, 0.99]) WITHIN GROUP (ORDER BY first_ack)
+                AS first_ack,
+            MAX(first_ack) AS first_ack_max
+        FROM timings
+        "#,
+    )
+    .bind(since)
+    .fetch_one(db)
+    .await?;
+
+    let percentiles = |values: Option<Vec<f64>>, max: Option<f64>| {
+        let values = values.unwrap_or_default();
+        LatencyPercentiles {
+            p50_ms: values.first().copied(),
+            p90_ms: values.get(1).copied(),
+            p99_ms: values.get(2).copied(),
+            max_ms: max,
+        }
+    };
+
+    Ok(DeliveryLatencyReport {
+        since,
+        samples: row.samples,
+        acked: row.acked,
+        fanout: percentiles(row.fanout, row.fanout_max),
+        first_ack: percentiles(row.first_ack, row.first_ack_max),
+    })
+}
@@ -0,0 +1,187 @@
+use std::time::Duration;
+
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{Device, DeviceConnection, DeviceSettings, PRIMARY_DEVICE_ID},
+    services::messaging::WsMessage,
+    storage::redis::RedisClient,
+};
+
+/// How long the primary device has to answer a verification request
+const VERIFICATION_TTL: Duration = Duration::from_secs(600);
+
+/// Device naming and trust: new devices start unverified until the user's
+/// primary device approves them
+pub struct DeviceService {
+    db: PgPool,
+    redis: RedisClient,
+}
+
+impl DeviceService {
+    pub fn new(db: PgPool, redis: RedisClient) -> Self {
+        Self { db, redis }
+    }
+
+    pub async fn rename(&self, user_id: Uuid, device_uuid: Uuid, name: &str) -> AppResult<Device> {
+        sqlx::query_as("UPDATE devices SET name = $1 WHERE id = $2 AND user_id = $3 RETURNING *")
+            .bind(name)
+            .bind(device_uuid)
+            .bind(user_id)
+            .fetch_optional(&self.db)
+            .await?
+            .ok_or(AppError::DeviceNotFound)
+    }
+
+    /// Ask the primary device to approve a device. The primary device answers
+    /// with a `device_verification` message over its WebSocket.
+    pub async fn request_verification(&self, user_id: Uuid, device_uuid: Uuid) -> AppResult<()> {
+        let device = self.find(user_id, device_uuid).await?;
+        if device.verified {
+            return Ok(());
+        }
+
+        self.redis
+            .set_cached(&pending_key(user_id, device_uuid), "1", VERIFICATION_TTL)
+            .await?;
+
+        self.notify(
+            user_id,
+            "device_verification_requested",
+            json!({
+                "device": device,
+                "primary_device_id": PRIMARY_DEVICE_ID,
+                "expires_in": VERIFICATION_TTL.as_secs(),
+            }),
+        )
+        .await
+    }
+
+    /// Apply the primary device's answer to a pending verification request
+    pub async fn resolve_verification(
+        &self,
+        user_id: Uuid,
+        approver_device_id: i32,
+        device_uuid: Uuid,
+        approved: bool,
+    ) -> AppResult<()> {
+        if approver_device_id != PRIMARY_DEVICE_ID {
+            return Err(AppError::InsufficientPermissions);
+        }
+
+        let key = pending_key(user_id, device_uuid);
+        if self.redis.get_cached(&key).await?.is_none() {
+            return Err(AppError::BadRequest(
+                "No pending verification for this device".to_string(),
+            ));
+        }
+        self.redis.delete_cached(&key).await?;
+
+        if approved {
+            sqlx::query("UPDATE devices SET verified = true WHERE id = $1 AND user_id = $2")
+                .bind(device_uuid)
+                .bind(user_id)
+                .execute(&self.db)
+                .await?;
+        }
+
+        self.notify(
+            user_id,
+            "device_verification_resolved",
+            json!({ "device_id": device_uuid, "verified": approved }),
+        )
+        .await
+    }
+
+    pub async fn get_settings(&self, user_id: Uuid) -> AppResult<DeviceSettings> {
+        let verified_devices_only: bool =
+            sqlx::query_scalar("SELECT verified_devices_only FROM users WHERE id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.db)
+                .await?
+                .ok_or(AppError::UserNotFound)?;
+
+        Ok(DeviceSettings {
+            verified_devices_only,
+        })
+    }
+
+    pub async fn update_settings(
+        &self,
+        user_id: Uuid,
+        settings: &DeviceSettings,
+    ) -> AppResult<DeviceSettings> {
+        sqlx::query(
+            "UPDATE users SET verified_devices_only = $1, updated_at = NOW() WHERE id = $2",
+        )
+        .bind(settings.verified_devices_only)
+        .bind(user_id)
+        .execute(&self.db)
+        .await?;
+
+        self.get_settings(user_id).await
+    }
+
+    /// Every device of the user and its WebSocket connection as registered
+    /// in Redis; connections refresh on each ping and lapse five minutes
+    /// after the last
+    pub async fn connections(&self, user_id: Uuid) -> AppResult<Vec<DeviceConnection>> {
+        let devices: Vec<(i32, String, String)> = sqlx::query_as(
+            "SELECT device_id, name, platform FROM devices WHERE user_id = $1 ORDER BY device_id",
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let user = user_id.to_string();
+        let mut connections = Vec::with_capacity(devices.len());
+        for (device_id, name, platform) in devices {
+            let record = self.redis.get_device_connection(&user, device_id).await?;
+            let connected = record.as_ref().is_some_and(|(_, alive)| *alive);
+            let record = record.map(|(record, _)| record);
+            connections.push(DeviceConnection {
+                device_id,
+                name,
+                platform,
+                connected,
+                instance_id: record.as_ref().map(|r| r.instance_id.clone()),
+                connected_at: record.as_ref().map(|r| r.connected_at),
+                last_ping_at: record.map(|r| r.last_ping_at),
+            });
+        }
+
+        Ok(connections)
+    }
+
+    async fn find(&self, user_id: Uuid, device_uuid: Uuid) -> AppResult<Device> {
+        sqlx::query_as("SELECT * FROM devices WHERE id = $1 AND user_id = $2")
+            .bind(device_uuid)
+            .bind(user_id)
+            .fetch_optional(&self.db)
+            .await?
+            .ok_or(AppError::DeviceNotFound)
+    }
+
+    async fn notify(
+        &self,
+        user_id: Uuid,
+        msg_type: &str,
+        payload: serde_json::Value,
+    ) -> AppResult<()> {
+        let ws_message = WsMessage {
+            msg_type: msg_type.to_string(),
+            payload,
+        };
+
+        self.redis
+            .publish_message(&user_id.to_string(), &serde_json::to_string(&ws_message)?)
+            .await
+    }
+}
+
+fn pending_key(user_id: Uuid, device_uuid: Uuid) -> String {
+    format!("device_verification:{}:{}", user_id, device_uuid)
+}
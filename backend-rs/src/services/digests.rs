@@ -0,0 +1,198 @@
+use std::sync::Arc;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::Rng;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, AppResult},
+    services::email::EmailProvider,
+};
+
+/// Opt-in digest emails for users who have been offline for a while. A
+/// digest carries unread counts only, never message content, and links to a
+/// token that turns digests off without signing in.
+pub struct DigestService {
+    db: PgPool,
+    email: Arc<dyn EmailProvider>,
+    config: Arc<Config>,
+}
+
+impl DigestService {
+    pub fn new(db: PgPool, email: Arc<dyn EmailProvider>, config: Arc<Config>) -> Self {
+        Self { db, email, config }
+    }
+
+    /// Opt in to digests. Requires an email address on the account.
+    pub async fn enable(&self, user_id: Uuid) -> AppResult<()> {
+        self.set_enabled(&mut *self.db.acquire().await?, user_id, true)
+            .await
+    }
+
+    pub async fn disable(&self, user_id: Uuid) -> AppResult<()> {
+        self.set_enabled(&mut *self.db.acquire().await?, user_id, false)
+            .await
+    }
+
+    /// Opt in or out within the caller's transaction
+    pub async fn set_enabled(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        enabled: bool,
+    ) -> AppResult<()> {
+        if !enabled {
+            sqlx::query("DELETE FROM email_digests WHERE user_id = $1")
+                .bind(user_id)
+                .execute(&mut *conn)
+                .await?;
+            return Ok(());
+        }
+
+        let email: Option<String> = sqlx::query_scalar("SELECT email FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+        if email.is_none() {
+            return Err(AppError::Validation(
+                "Add an email address before enabling digests".to_string(),
+            ));
+        }
+
+        let token = URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 32]>());
+        sqlx::query(
+            r#"
+            INSERT INTO email_digests (user_id, unsubscribe_token) VALUES ($1, $2)
+            ON CONFLICT (user_id) DO NOTHING
+            "#,
+        )
+        .bind(user_id)
+        .bind(token)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Opt out via the link in a digest. Unknown tokens are ignored so the
+    /// link can be followed more than once.
+    pub async fn unsubscribe(&self, token: &str) -> AppResult<()> {
+        sqlx::query("DELETE FROM email_digests WHERE unsubscribe_token = $1")
+            .bind(token)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Email every opted-in user who has been offline for the configured
+    /// number of days, has unread messages and has not had a digest in as
+    /// long. Returns the number of digests sent.
+    pub async fn send_due(&self) -> AppResult<usize> {
+        let days = self.config.digest.inactive_days;
+
+        // Claim the batch first so several instances never mail the same user
+        let due: Vec<(Uuid, String, String)> = sqlx::query_as(
+            r#"
+            WITH due AS (
+                SELECT d.user_id FROM email_digests d
+                JOIN users u ON u.id = d.user_id
+                WHERE u.email IS NOT NULL AND u.banned_at IS NULL AND u.deactivated_at IS NULL
+                AND u.last_seen_at < NOW() - make_interval(days => $1)
+                AND (d.last_sent_at IS NULL OR d.last_sent_at < NOW() - make_interval(days => $1))
+                AND EXISTS (
+                    SELECT 1 FROM participants p
+                    JOIN messages m ON m.conversation_id = p.conversation_id
+                    WHERE p.user_id = d.user_id AND p.left_at IS NULL AND NOT p.is_request
+                    AND (p.muted_until IS NULL OR p.muted_until <= NOW())
+                    AND m.sender_id != d.user_id AND m.deleted_at IS NULL AND NOT m.is_shadowed
+                    AND (p.cleared_before IS NULL OR m.created_at > p.cleared_before)
+                    AND NOT EXISTS (
+                        SELECT 1 FROM receipts r
+                        WHERE r.message_id = m.id AND r.user_id = d.user_id AND r.type = 'read'
+                    )
+                )
+                FOR UPDATE OF d SKIP LOCKED
+                LIMIT 500
+            )
+            UPDATE email_digests d SET last_sent_at = NOW()
+            FROM due, users u
+            WHERE d.user_id = due.user_id AND u.id = d.user_id
+            RETURNING d.user_id, d.unsubscribe_token, u.email
+            "#,
+        )
+        .bind(days)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut sent = 0;
+        for (user_id, token, email) in due {
+            let (messages, conversations) = self.unread_counts(user_id).await?;
+            if messages == 0 {
+                continue;
+            }
+
+            let body = format!(
+                "You have {} unread message{} in {} conversation{} on Ansible Talk.\n\n\
+                 Open the app to catch up.\n\n\
+                 To stop these emails, visit {}",
+                messages,
+                if messages == 1 { "" } else { "s" },
+                conversations,
+                if conversations == 1 { "" } else { "s" },
+                self.unsubscribe_url(&token),
+            );
+            match self
+                .email
+                .send(&email, "You have unread messages on Ansible Talk", &body)
+                .await
+            {
+                Ok(()) => sent += 1,
+                Err(e) => tracing::warn!("Failed to send email digest to {}: {}", user_id, e),
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Unread messages and the conversations holding them, counted the same
+    /// way as the push badge
+    async fn unread_counts(&self, user_id: Uuid) -> AppResult<(i64, i64)> {
+        let counts: (i64, i64) = sqlx::query_as(
+            r#"
+            SELECT COUNT(m.id), COUNT(DISTINCT m.conversation_id) FROM participants p
+            JOIN messages m ON m.conversation_id = p.conversation_id
+            WHERE p.user_id = $1 AND p.left_at IS NULL AND NOT p.is_request
+            AND (p.muted_until IS NULL OR p.muted_until <= NOW())
+            AND m.sender_id != $1 AND m.deleted_at IS NULL AND NOT m.is_shadowed
+            AND (p.cleared_before IS NULL OR m.created_at > p.cleared_before)
+            AND NOT EXISTS (
+                SELECT 1 FROM receipts r
+                WHERE r.message_id = m.id AND r.user_id = $1 AND r.type = 'read'
+            )
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(counts)
+    }
+
+    fn unsubscribe_url(&self, token: &str) -> String {
+        let base = self
+            .config
+            .media
+            .base_url
+            .clone()
+            .unwrap_or_else(|| format!("http://localhost:{}", self.config.server.port));
+        format!(
+            "{}/api/v1/users/email-digest/unsubscribe?token={}",
+            base.trim_end_matches('/'),
+            token
+        )
+    }
+}
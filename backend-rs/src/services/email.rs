@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::json;
+
+use crate::{config::NotificationConfig, error::AppResult};
+
+/// Sends transactional email
+#[async_trait]
+pub trait EmailProvider: Send + Sync {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> AppResult<()>;
+}
+
+/// Development provider that only logs emails
+pub struct LogEmailProvider;
+
+#[async_trait]
+impl EmailProvider for LogEmailProvider {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> AppResult<()> {
+        tracing::info!("Email to {}: {}\n{}", to, subject, body);
+        Ok(())
+    }
+}
+
+pub struct SendGridEmailProvider {
+    http: reqwest::Client,
+    api_key: String,
+    from: String,
+}
+
+impl SendGridEmailProvider {
+    pub fn new(api_key: String, from: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            api_key,
+            from,
+        }
+    }
+}
+
+#[async_trait]
+impl EmailProvider for SendGridEmailProvider {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> AppResult<()> {
+        let response = self
+            .http
+            .post("https://api.sendgrid.com/v3/mail/send")
+            .bearer_auth(&self.api_key)
+            .json(&json!({
+                "personalizations": [{ "to": [{ "email": to }] }],
+                "from": { "email": self.from },
+                "subject": subject,
+                "content": [{ "type": "text/plain", "value": body }],
+            }))
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send email: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("SendGrid returned {}", response.status()).into());
+        }
+
+        Ok(())
+    }
+}
+
+pub fn build_email_provider(config: &NotificationConfig) -> Arc<dyn EmailProvider> {
+    match (config.email_provider.as_str(), &config.sendgrid_api_key) {
+        ("sendgrid", Some(key)) => Arc::new(SendGridEmailProvider::new(
+            key.clone(),
+            config.email_from.clone(),
+        )),
+        ("sendgrid", None) => {
+            tracing::warn!("EMAIL_PROVIDER=sendgrid but SENDGRID_API_KEY is not set, logging emails");
+            Arc::new(LogEmailProvider)
+        }
+        _ => Arc::new(LogEmailProvider),
+    }
+}
@@ -0,0 +1,254 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::CustomEmoji,
+    storage::blob::BlobStorage,
+};
+
+/// Largest custom emoji image; they are shown at text size
+pub const MAX_EMOJI_BYTES: usize = 256 * 1024;
+
+/// Custom emoji per group on top of the global ones
+const MAX_GROUP_EMOJI: i64 = 200;
+
+/// A shortcode's name: 2 to 32 lowercase letters, digits, `-` and `_`,
+/// with any surrounding colons dropped
+pub fn normalize_name(raw: &str) -> AppResult<String> {
+    let name = raw.trim().trim_matches(':').to_lowercase();
+    let valid = (2..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if !valid {
+        return Err(AppError::Validation(
+            "Emoji names are 2-32 lowercase letters, digits, - and _".to_string(),
+        ));
+    }
+
+    Ok(name)
+}
+
+/// The name in a `:name:` shortcode, if `text` is one
+pub fn shortcode_name(text: &str) -> Option<&str> {
+    text.strip_prefix(':')?.strip_suffix(':')
+}
+
+/// Map a unique-index violation on emoji names to `EmojiNameTaken`
+fn map_name_conflict(e: sqlx::Error) -> AppError {
+    match &e {
+        sqlx::Error::Database(db)
+            if matches!(
+                db.constraint(),
+                Some("idx_custom_emoji_global_name" | "idx_custom_emoji_conversation_name")
+            ) =>
+        {
+            AppError::EmojiNameTaken
+        }
+        _ => e.into(),
+    }
+}
+
+pub struct EmojiService {
+    db: PgPool,
+    storage: Arc<dyn BlobStorage>,
+}
+
+impl EmojiService {
+    pub fn new(db: PgPool, storage: Arc<dyn BlobStorage>) -> Self {
+        Self { db, storage }
+    }
+
+    /// Emoji usable in a conversation the user is in
+    pub async fn list_for_conversation(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+    ) -> AppResult<Vec<CustomEmoji>> {
+        let is_participant: bool = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM participants
+                WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL
+            )
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .fetch_one(&self.db)
+        .await?;
+        if !is_participant {
+            return Err(AppError::NotParticipant);
+        }
+
+        self.list(Some(conversation_id)).await
+    }
+
+    /// Emoji usable in a conversation: the global ones and, for a group, its
+    /// own, which win over a global emoji of the same name. Without a
+    /// conversation, only the global ones.
+    pub async fn list(&self, conversation_id: Option<Uuid>) -> AppResult<Vec<CustomEmoji>> {
+        let emoji = sqlx::query_as(
+            r#"
+            SELECT DISTINCT ON (name) * FROM custom_emoji
+            WHERE conversation_id IS NULL OR conversation_id = $1
+            ORDER BY name, conversation_id NULLS LAST
+            "#,
+        )
+        .bind(conversation_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(emoji)
+    }
+
+    /// The emoji a shortcode name stands for in a conversation
+    pub async fn resolve(
+        &self,
+        conversation_id: Uuid,
+        name: &str,
+    ) -> AppResult<Option<CustomEmoji>> {
+        let emoji = sqlx::query_as(
+            r#"
+            SELECT * FROM custom_emoji
+            WHERE name = $2 AND (conversation_id IS NULL OR conversation_id = $1)
+            ORDER BY conversation_id NULLS LAST
+            LIMIT 1
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(name)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(emoji)
+    }
+
+    /// Upload an emoji for one group, or for everyone without one. Callers
+    /// check who may.
+    pub async fn create(
+        &self,
+        conversation_id: Option<Uuid>,
+        user_id: Uuid,
+        name: &str,
+        data: Bytes,
+        content_type: &str,
+    ) -> AppResult<CustomEmoji> {
+        let name = normalize_name(name)?;
+        if data.len() > MAX_EMOJI_BYTES {
+            return Err(AppError::Validation(format!(
+                "Emoji images are at most {} KB",
+                MAX_EMOJI_BYTES / 1024
+            )));
+        }
+        let extension = match content_type {
+            "image/png" => "png",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            _ => return Err(AppError::UnsupportedMediaType(content_type.to_string())),
+        };
+
+        if let Some(conversation_id) = conversation_id {
+            let count: i64 =
+                sqlx::query_scalar("SELECT COUNT(*) FROM custom_emoji WHERE conversation_id = $1")
+                    .bind(conversation_id)
+                    .fetch_one(&self.db)
+                    .await?;
+            if count >= MAX_GROUP_EMOJI {
+                return Err(AppError::Validation(format!(
+                    "At most {} custom emoji per group",
+                    MAX_GROUP_EMOJI
+                )));
+            }
+        }
+
+        let id = Uuid::new_v4();
+        let key = format!("emoji/{}.{}", id, extension);
+        let image_url = self
+            .storage
+            .upload_file(self.storage.stickers_bucket(), &key, data, content_type)
+            .await?;
+
+        let created = sqlx::query_as(
+            r#"
+            INSERT INTO custom_emoji (id, conversation_id, name, image_url, created_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(conversation_id)
+        .bind(&name)
+        .bind(&image_url)
+        .bind(user_id)
+        .fetch_one(&self.db)
+        .await;
+
+        match created {
+            Ok(emoji) => Ok(emoji),
+            Err(e) => {
+                if let Err(e) = self.storage.delete_file(self.storage.stickers_bucket(), &key).await
+                {
+                    tracing::warn!("Failed to delete unused emoji image {}: {}", key, e);
+                }
+                Err(map_name_conflict(e))
+            }
+        }
+    }
+
+    /// Rename an emoji of the given scope
+    pub async fn rename(
+        &self,
+        conversation_id: Option<Uuid>,
+        emoji_id: Uuid,
+        name: &str,
+    ) -> AppResult<CustomEmoji> {
+        let name = normalize_name(name)?;
+
+        let emoji = sqlx::query_as(
+            r#"
+            UPDATE custom_emoji SET name = $3
+            WHERE id = $1 AND conversation_id IS NOT DISTINCT FROM $2
+            RETURNING *
+            "#,
+        )
+        .bind(emoji_id)
+        .bind(conversation_id)
+        .bind(&name)
+        .fetch_optional(&self.db)
+        .await
+        .map_err(map_name_conflict)?
+        .ok_or(AppError::EmojiNotFound)?;
+
+        Ok(emoji)
+    }
+
+    /// Delete an emoji of the given scope and its image. Messages that used
+    /// it keep the `:name:` text.
+    pub async fn delete(&self, conversation_id: Option<Uuid>, emoji_id: Uuid) -> AppResult<()> {
+        let image_url: String = sqlx::query_scalar(
+            r#"
+            DELETE FROM custom_emoji
+            WHERE id = $1 AND conversation_id IS NOT DISTINCT FROM $2
+            RETURNING image_url
+            "#,
+        )
+        .bind(emoji_id)
+        .bind(conversation_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::EmojiNotFound)?;
+
+        if let Some(key) = image_url.rsplit_once("/emoji/").map(|(_, f)| format!("emoji/{}", f)) {
+            if let Err(e) = self.storage.delete_file(self.storage.stickers_bucket(), &key).await {
+                tracing::warn!("Failed to delete emoji image {}: {}", key, e);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::AnalyticsConfig,
+    error::AppResult,
+    models::{AnalyticsEvent, MessageType},
+};
+
+/// Events relayed to an external sink per round
+const RELAY_BATCH: i64 = 500;
+
+/// What happened, in terms analytics can use. Only counts, kinds and
+/// platforms are kept; IDs are pseudonymized when the event is written.
+pub enum DomainEvent<'a> {
+    UserRegistered {
+        user_id: Uuid,
+        platform: &'a str,
+        /// `phone` or `email`
+        method: &'a str,
+    },
+    MessageSent {
+        sender_id: Uuid,
+        conversation_id: Uuid,
+        message_type: MessageType,
+        has_attachment: bool,
+        is_reply: bool,
+        view_once: bool,
+    },
+    PackDownloaded {
+        user_id: Uuid,
+        pack_id: Uuid,
+    },
+}
+
+/// Where events go after `analytics_events`, e.g. a collector in front of
+/// Kafka or NATS
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn publish(&self, events: &[AnalyticsEvent]) -> AppResult<()>;
+}
+
+/// POSTs each batch as newline-delimited JSON
+pub struct HttpEventSink {
+    http: reqwest::Client,
+    url: String,
+    token: Option<String>,
+}
+
+impl HttpEventSink {
+    pub fn new(url: String, token: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url,
+            token,
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for HttpEventSink {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn publish(&self, events: &[AnalyticsEvent]) -> AppResult<()> {
+        let mut body = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut body, event)?;
+            body.push(b'\n');
+        }
+
+        let mut request = self
+            .http
+            .post(&self.url)
+            .header("Content-Type", "application/x-ndjson")
+            .body(body);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to publish events: {}", e))?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("Event sink returned {}", response.status()).into());
+        }
+
+        Ok(())
+    }
+}
+
+/// The external sink for `ANALYTICS_SINK=http`; with `table` events stay in
+/// the database for export
+pub fn build_event_sink(config: &AnalyticsConfig) -> Option<Arc<dyn EventSink>> {
+    match config.sink.as_str() {
+        "http" => {
+            let Some(url) = config.http_url.clone() else {
+                tracing::warn!("ANALYTICS_SINK=http without ANALYTICS_HTTP_URL; keeping events");
+                return None;
+            };
+            Some(Arc::new(HttpEventSink::new(url, config.http_token.clone())))
+        }
+        _ => None,
+    }
+}
+
+/// Append-only log of domain events for analytics. Writing never fails the
+/// action that caused the event.
+pub struct EventLog {
+    db: PgPool,
+    config: AnalyticsConfig,
+}
+
+impl EventLog {
+    pub fn new(db: PgPool, config: &AnalyticsConfig) -> Self {
+        Self {
+            db,
+            config: config.clone(),
+        }
+    }
+
+    pub async fn record(&self, event: DomainEvent<'_>) {
+        if self.config.sink == "none" {
+            return;
+        }
+
+        let (event_type, actor, properties) = match event {
+            DomainEvent::UserRegistered {
+                user_id,
+                platform,
+                method,
+            } => (
+                "user_registered",
+                user_id,
+                json!({ "platform": platform, "method": method }),
+            ),
+            DomainEvent::MessageSent {
+                sender_id,
+                conversation_id,
+                message_type,
+                has_attachment,
+                is_reply,
+                view_once,
+            } => (
+                "message_sent",
+                sender_id,
+                json!({
+                    "conversation": self.pseudonym(conversation_id),
+                    "message_type": message_type,
+                    "has_attachment": has_attachment,
+                    "is_reply": is_reply,
+                    "view_once": view_once,
+                }),
+            ),
+            DomainEvent::PackDownloaded { user_id, pack_id } => (
+                "pack_downloaded",
+                user_id,
+                // Packs are public catalog entries, so their IDs are kept
+                json!({ "pack_id": pack_id }),
+            ),
+        };
+
+        let result = sqlx::query(
+            "INSERT INTO analytics_events (id, event_type, actor, properties) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(Uuid::new_v4())
+        .bind(event_type)
+        .bind(self.pseudonym(actor))
+        .bind(properties)
+        .execute(&self.db)
+        .await;
+        if let Err(e) = result {
+            tracing::warn!("Failed to record {} event: {}", event_type, e);
+        }
+    }
+
+    /// Events in the order they were written, after the event `after`
+    pub async fn export(&self, after: Option<Uuid>, limit: i64) -> AppResult<Vec<AnalyticsEvent>> {
+        let events = sqlx::query_as(
+            r#"
+            SELECT * FROM analytics_events
+            WHERE $1::UUID IS NULL OR (occurred_at, id) > (
+                SELECT occurred_at, id FROM analytics_events WHERE id = $1
+            )
+            ORDER BY occurred_at, id
+            LIMIT $2
+            "#,
+        )
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(events)
+    }
+
+    /// Hand the oldest events to the sink and delete them once it accepts
+    /// them. Returns the number relayed.
+    pub async fn relay(&self, sink: &dyn EventSink) -> AppResult<usize> {
+        let mut tx = self.db.begin().await?;
+        let events: Vec<AnalyticsEvent> = sqlx::query_as(
+            r#"
+            SELECT * FROM analytics_events
+            ORDER BY occurred_at, id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#,
+        )
+        .bind(RELAY_BATCH)
+        .fetch_all(&mut *tx)
+        .await?;
+        if events.is_empty() {
+            return Ok(0);
+        }
+
+        sink.publish(&events).await?;
+
+        let ids: Vec<Uuid> = events.iter().map(|e| e.id).collect();
+        sqlx::query("DELETE FROM analytics_events WHERE id = ANY($1)")
+            .bind(&ids)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(events.len())
+    }
+
+    pub async fn purge_expired(&self) -> AppResult<u64> {
+        let result = sqlx::query(
+            "DELETE FROM analytics_events WHERE occurred_at < NOW() - make_interval(secs => $1)",
+        )
+        .bind(self.config.retention.as_secs_f64())
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Stable per key, and not reversible without it
+    fn pseudonym(&self, id: Uuid) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.config.pseudonym_key.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(id.as_bytes());
+        mac.finalize().into_bytes()[..16]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
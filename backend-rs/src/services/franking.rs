@@ -0,0 +1,89 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::config::Config;
+
+/// Length of a franking tag, an HMAC-SHA256
+pub const FRANKING_TAG_LEN: usize = 32;
+
+/// Message franking for end-to-end encrypted conversations. The sender picks
+/// a random franking key, commits to the plaintext with
+/// `HMAC-SHA256(key, plaintext)` and sends the commitment as `franking_tag`
+/// next to the ciphertext, with the key inside it. The server signs the tag
+/// together with the message, conversation and sender, so a recipient can
+/// later disclose the plaintext and key to prove who sent what. The server
+/// never sees either unless a message is reported.
+#[derive(Clone)]
+pub struct Franking {
+    secret: Vec<u8>,
+}
+
+impl Franking {
+    pub fn new(config: &Config) -> Self {
+        let secret = config
+            .moderation
+            .franking_secret
+            .clone()
+            .unwrap_or_else(|| config.jwt.secret.clone());
+
+        Self {
+            secret: secret.into_bytes(),
+        }
+    }
+
+    /// The server's signature, stored with the message at send time
+    pub fn sign(
+        &self,
+        message_id: Uuid,
+        conversation_id: Uuid,
+        sender_id: Uuid,
+        franking_tag: &[u8],
+    ) -> Vec<u8> {
+        self.mac(message_id, conversation_id, sender_id, franking_tag)
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+
+    /// Whether this server signed the tag for that message and sender
+    pub fn verify_signature(
+        &self,
+        message_id: Uuid,
+        conversation_id: Uuid,
+        sender_id: Uuid,
+        franking_tag: &[u8],
+        signature: &[u8],
+    ) -> bool {
+        self.mac(message_id, conversation_id, sender_id, franking_tag)
+            .verify_slice(signature)
+            .is_ok()
+    }
+
+    fn mac(
+        &self,
+        message_id: Uuid,
+        conversation_id: Uuid,
+        sender_id: Uuid,
+        franking_tag: &[u8],
+    ) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(b"franking-v1");
+        mac.update(message_id.as_bytes());
+        mac.update(conversation_id.as_bytes());
+        mac.update(sender_id.as_bytes());
+        mac.update(franking_tag);
+        mac
+    }
+}
+
+/// Whether the disclosed plaintext and franking key open the sender's
+/// commitment
+pub fn opens_commitment(franking_tag: &[u8], franking_key: &[u8], plaintext: &[u8]) -> bool {
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(franking_key) else {
+        return false;
+    };
+    mac.update(plaintext);
+    mac.verify_slice(franking_tag).is_ok()
+}
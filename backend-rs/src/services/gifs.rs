@@ -0,0 +1,332 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::{
+    config::GifConfig,
+    error::{AppError, AppResult},
+    models::{GifResult, GifSearchResponse},
+    services::http::send_json,
+    storage::redis::RedisClient,
+};
+
+/// A GIF search API queried with the server's key
+#[async_trait]
+pub trait GifProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn search(
+        &self,
+        query: &str,
+        limit: u32,
+        cursor: Option<&str>,
+    ) -> AppResult<GifSearchResponse>;
+}
+
+#[derive(Debug, Deserialize)]
+struct TenorMedia {
+    url: String,
+    #[serde(default)]
+    dims: Vec<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TenorMediaFormats {
+    gif: Option<TenorMedia>,
+    tinygif: Option<TenorMedia>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TenorResult {
+    id: String,
+    #[serde(default)]
+    content_description: String,
+    media_formats: TenorMediaFormats,
+}
+
+#[derive(Debug, Deserialize)]
+struct TenorResponse {
+    results: Vec<TenorResult>,
+    #[serde(default)]
+    next: String,
+}
+
+/// Tenor API v2
+pub struct TenorProvider {
+    http: reqwest::Client,
+    api_key: String,
+    content_filter: &'static str,
+}
+
+impl TenorProvider {
+    pub fn new(api_key: String, rating: &str) -> Self {
+        // Tenor filters by strictness rather than by rating
+        let content_filter = match rating {
+            "g" => "high",
+            "pg" => "medium",
+            "r" => "off",
+            _ => "low",
+        };
+
+        Self {
+            http: reqwest::Client::new(),
+            api_key,
+            content_filter,
+        }
+    }
+}
+
+#[async_trait]
+impl GifProvider for TenorProvider {
+    fn name(&self) -> &'static str {
+        "tenor"
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        limit: u32,
+        cursor: Option<&str>,
+    ) -> AppResult<GifSearchResponse> {
+        let limit = limit.to_string();
+        let mut params = vec![
+            ("q", query),
+            ("key", self.api_key.as_str()),
+            ("client_key", "ansible-talk"),
+            ("limit", limit.as_str()),
+            ("contentfilter", self.content_filter),
+            ("media_filter", "gif,tinygif"),
+        ];
+        if let Some(pos) = cursor {
+            params.push(("pos", pos));
+        }
+
+        let response: TenorResponse = send_json(
+            "Tenor",
+            self.http
+                .get("https://tenor.googleapis.com/v2/search")
+                .query(&params),
+        )
+        .await?;
+
+        let results = response
+            .results
+            .into_iter()
+            .filter_map(|result| {
+                let gif = result.media_formats.gif?;
+                let preview_url = result
+                    .media_formats
+                    .tinygif
+                    .map(|m| m.url)
+                    .unwrap_or_else(|| gif.url.clone());
+
+                Some(GifResult {
+                    id: result.id,
+                    title: result.content_description,
+                    width: gif.dims.first().copied().unwrap_or(0),
+                    height: gif.dims.get(1).copied().unwrap_or(0),
+                    url: gif.url,
+                    preview_url,
+                    content_type: "image/gif".to_string(),
+                    provider: self.name().to_string(),
+                })
+            })
+            .collect();
+
+        Ok(GifSearchResponse {
+            results,
+            next: Some(response.next).filter(|n| !n.is_empty()),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GiphyImage {
+    url: String,
+    #[serde(default)]
+    width: String,
+    #[serde(default)]
+    height: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiphyImages {
+    original: Option<GiphyImage>,
+    fixed_width_small: Option<GiphyImage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiphyGif {
+    id: String,
+    #[serde(default)]
+    title: String,
+    images: GiphyImages,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiphyPagination {
+    #[serde(default)]
+    total_count: u64,
+    count: u64,
+    offset: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiphyResponse {
+    data: Vec<GiphyGif>,
+    pagination: GiphyPagination,
+}
+
+pub struct GiphyProvider {
+    http: reqwest::Client,
+    api_key: String,
+    rating: String,
+}
+
+impl GiphyProvider {
+    pub fn new(api_key: String, rating: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            api_key,
+            rating: rating.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl GifProvider for GiphyProvider {
+    fn name(&self) -> &'static str {
+        "giphy"
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        limit: u32,
+        cursor: Option<&str>,
+    ) -> AppResult<GifSearchResponse> {
+        // Giphy pages by offset; the cursor is the next offset
+        let offset: u64 = cursor.and_then(|c| c.parse().ok()).unwrap_or(0);
+
+        let response: GiphyResponse = send_json(
+            "Giphy",
+            self.http
+                .get("https://api.giphy.com/v1/gifs/search")
+                .query(&[
+                    ("api_key", self.api_key.as_str()),
+                    ("q", query),
+                    ("limit", &limit.to_string()),
+                    ("offset", &offset.to_string()),
+                    ("rating", self.rating.as_str()),
+                ]),
+        )
+        .await?;
+
+        let results = response
+            .data
+            .into_iter()
+            .filter_map(|gif| {
+                let original = gif.images.original?;
+                let preview_url = gif
+                    .images
+                    .fixed_width_small
+                    .map(|m| m.url)
+                    .unwrap_or_else(|| original.url.clone());
+
+                Some(GifResult {
+                    id: gif.id,
+                    title: gif.title,
+                    width: original.width.parse().unwrap_or(0),
+                    height: original.height.parse().unwrap_or(0),
+                    url: original.url,
+                    preview_url,
+                    content_type: "image/gif".to_string(),
+                    provider: self.name().to_string(),
+                })
+            })
+            .collect();
+
+        let next_offset = response.pagination.offset + response.pagination.count;
+        let next = (response.pagination.count > 0 && next_offset < response.pagination.total_count)
+            .then(|| next_offset.to_string());
+
+        Ok(GifSearchResponse { results, next })
+    }
+}
+
+/// Returns `None` when GIF search is disabled or has no API key
+pub fn build_gif_provider(config: &GifConfig) -> Option<Arc<dyn GifProvider>> {
+    let api_key = match (config.provider.as_str(), &config.api_key) {
+        ("none", _) => return None,
+        (_, Some(key)) => key.clone(),
+        (provider, None) => {
+            tracing::warn!("GIF_PROVIDER={} but GIF_API_KEY is not set", provider);
+            return None;
+        }
+    };
+
+    match config.provider.as_str() {
+        "tenor" => Some(Arc::new(TenorProvider::new(api_key, &config.rating))),
+        "giphy" => Some(Arc::new(GiphyProvider::new(api_key, &config.rating))),
+        other => {
+            tracing::warn!("Unknown GIF provider: {}", other);
+            None
+        }
+    }
+}
+
+/// GIF search proxied through the server so clients never hold the API key
+pub struct GifService {
+    redis: RedisClient,
+    provider: Arc<dyn GifProvider>,
+    config: GifConfig,
+}
+
+impl GifService {
+    pub fn new(
+        redis: RedisClient,
+        provider: Option<Arc<dyn GifProvider>>,
+        config: GifConfig,
+    ) -> AppResult<Self> {
+        let provider = provider.ok_or(AppError::GifSearchDisabled)?;
+        Ok(Self {
+            redis,
+            provider,
+            config,
+        })
+    }
+
+    /// Search GIFs, served from cache when the same page was fetched recently
+    pub async fn search(
+        &self,
+        query: &str,
+        limit: u32,
+        cursor: Option<&str>,
+    ) -> AppResult<GifSearchResponse> {
+        let query = query.trim().to_lowercase();
+        let cache_key = format!(
+            "gifs:{}:{}:{}:{}",
+            self.provider.name(),
+            limit,
+            cursor.unwrap_or(""),
+            query
+        );
+
+        if let Some(cached) = self.redis.get_cached(&cache_key).await? {
+            if let Ok(response) = serde_json::from_str::<GifSearchResponse>(&cached) {
+                return Ok(response);
+            }
+        }
+
+        let response = self.provider.search(&query, limit, cursor).await?;
+
+        let ttl = self.config.cache_ttl;
+        if !ttl.is_zero() {
+            let serialized = serde_json::to_string(&response)?;
+            self.redis.set_cached(&cache_key, &serialized, ttl).await?;
+        }
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,376 @@
+use std::{
+    fmt::Write,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use sqlx::PgPool;
+
+use crate::{
+    api::websocket::WsHub,
+    logging::SlowLog,
+    models::{DatabasePoolStats, HealthReport, HealthStatus, LatencyPercentiles, RedisHealth},
+    services::delivery::latency_report,
+    storage::{blob::BlobStorage, redis::RedisClient},
+};
+
+/// How long a dependency may take to answer a probe before it counts as down
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Waiting this long for a pooled connection marks the instance degraded
+const SLOW_ACQUIRE: Duration = Duration::from_millis(500);
+
+/// Storage error rate over recent calls that marks the instance degraded
+const STORAGE_ERROR_RATE: f64 = 0.5;
+
+/// Delivery latency percentiles in `/metrics` cover this much recent traffic
+const LATENCY_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Probes the database and Redis, and gathers pool, storage and WebSocket
+/// figures for the readiness probe and `/metrics`
+pub struct HealthService {
+    db: PgPool,
+    redis: RedisClient,
+    storage: Arc<dyn BlobStorage>,
+    ws_hub: Arc<WsHub>,
+    slow_log: Arc<SlowLog>,
+}
+
+impl HealthService {
+    pub fn new(
+        db: PgPool,
+        redis: RedisClient,
+        storage: Arc<dyn BlobStorage>,
+        ws_hub: Arc<WsHub>,
+        slow_log: Arc<SlowLog>,
+    ) -> Self {
+        Self {
+            db,
+            redis,
+            storage,
+            ws_hub,
+            slow_log,
+        }
+    }
+
+    /// Check every dependency. Without the database or Redis the instance is
+    /// unavailable; a saturated pool, failing storage or lagging WebSocket
+    /// clients only degrade it.
+    pub async fn report(&self) -> HealthReport {
+        let database = self.probe_database().await;
+        let redis = self.probe_redis().await;
+        let storage = self.storage.call_stats();
+        let websocket = self.ws_hub.stats().await;
+
+        let mut unavailable = Vec::new();
+        let mut degraded = Vec::new();
+        if !database.up {
+            unavailable.push("database unreachable".to_string());
+        }
+        if !redis.up {
+            unavailable.push("redis unreachable".to_string());
+        }
+        if database.up && database.in_use >= database.max {
+            degraded.push(format!(
+                "database pool exhausted ({} connections)",
+                database.max
+            ));
+        }
+        if database.acquire_ms >= SLOW_ACQUIRE.as_secs_f64() * 1000.0 {
+            degraded.push(format!("database pool wait {:.0} ms", database.acquire_ms));
+        }
+        if storage.recent_error_rate >= STORAGE_ERROR_RATE {
+            degraded.push(format!(
+                "{} storage failing {:.0}% of calls",
+                storage.backend,
+                storage.recent_error_rate * 100.0
+            ));
+        }
+        if websocket.lagging_clients * 2 > websocket.connections {
+            degraded.push(format!(
+                "{} of {} WebSocket clients lagging",
+                websocket.lagging_clients, websocket.connections
+            ));
+        }
+
+        let status = if !unavailable.is_empty() {
+            HealthStatus::Unavailable
+        } else if !degraded.is_empty() {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Ready
+        };
+        unavailable.extend(degraded);
+
+        HealthReport {
+            status,
+            reasons: unavailable,
+            database,
+            redis,
+            storage,
+            websocket,
+        }
+    }
+
+    /// The report and per-route slow counts in the Prometheus text format
+    pub async fn metrics(&self) -> String {
+        let report = self.report().await;
+        let mut out = String::new();
+
+        let status = match report.status {
+            HealthStatus::Ready => 1.0,
+            HealthStatus::Degraded => 0.5,
+            HealthStatus::Unavailable => 0.0,
+        };
+        gauge(
+            &mut out,
+            "ready",
+            "1 ready, 0.5 degraded, 0 unavailable",
+            status,
+        );
+
+        let db = &report.database;
+        write_help(
+            &mut out,
+            "db_pool_connections",
+            "gauge",
+            "Pooled database connections",
+        );
+        let _ = writeln!(
+            out,
+            "ansible_talk_db_pool_connections{{state=\"in_use\"}} {}",
+            db.in_use
+        );
+        let _ = writeln!(
+            out,
+            "ansible_talk_db_pool_connections{{state=\"idle\"}} {}",
+            db.idle
+        );
+        gauge(
+            &mut out,
+            "db_pool_max_connections",
+            "Pool size limit",
+            db.max as f64,
+        );
+        gauge(
+            &mut out,
+            "db_pool_acquire_seconds",
+            "Time the last probe waited for a pooled connection",
+            db.acquire_ms / 1000.0,
+        );
+        gauge(
+            &mut out,
+            "db_up",
+            "Whether the database answered the last probe",
+            flag(db.up),
+        );
+
+        gauge(
+            &mut out,
+            "redis_up",
+            "Whether Redis answered the last probe",
+            flag(report.redis.up),
+        );
+        gauge(
+            &mut out,
+            "redis_ping_seconds",
+            "Round trip of the last Redis probe",
+            report.redis.ping_ms / 1000.0,
+        );
+
+        let storage = &report.storage;
+        let backend = escape(&storage.backend);
+        write_help(
+            &mut out,
+            "storage_requests_total",
+            "counter",
+            "Object storage calls",
+        );
+        let _ = writeln!(
+            out,
+            "ansible_talk_storage_requests_total{{backend=\"{}\"}} {}",
+            backend, storage.requests
+        );
+        write_help(
+            &mut out,
+            "storage_errors_total",
+            "counter",
+            "Failed object storage calls",
+        );
+        let _ = writeln!(
+            out,
+            "ansible_talk_storage_errors_total{{backend=\"{}\"}} {}",
+            backend, storage.errors
+        );
+        write_help(
+            &mut out,
+            "storage_recent_error_ratio",
+            "gauge",
+            "Share of recent object storage calls that failed",
+        );
+        let _ = writeln!(
+            out,
+            "ansible_talk_storage_recent_error_ratio{{backend=\"{}\"}} {}",
+            backend, storage.recent_error_rate
+        );
+
+        let ws = &report.websocket;
+        gauge(
+            &mut out,
+            "ws_connections",
+            "Open WebSocket connections",
+            ws.connections as f64,
+        );
+        gauge(
+            &mut out,
+            "ws_connected_users",
+            "Users with a connection",
+            ws.connected_users as f64,
+        );
+        gauge(
+            &mut out,
+            "ws_queued_events",
+            "Events waiting in outbound queues",
+            ws.queued_events as f64,
+        );
+        gauge(
+            &mut out,
+            "ws_lagging_clients",
+            "Connections whose queue is over half full",
+            ws.lagging_clients as f64,
+        );
+
+        let slow = self.slow_log.snapshot();
+        write_help(
+            &mut out,
+            "slow_queries_total",
+            "counter",
+            "Statements over SLOW_QUERY_MS",
+        );
+        for route in &slow {
+            let _ = writeln!(
+                out,
+                "ansible_talk_slow_queries_total{{route=\"{}\"}} {}",
+                escape(&route.route),
+                route.slow_queries
+            );
+        }
+        write_help(
+            &mut out,
+            "slow_requests_total",
+            "counter",
+            "Requests over SLOW_REQUEST_MS",
+        );
+        for route in &slow {
+            let _ = writeln!(
+                out,
+                "ansible_talk_slow_requests_total{{route=\"{}\"}} {}",
+                escape(&route.route),
+                route.slow_requests
+            );
+        }
+
+        // Shared across instances, so every instance reports the same figures
+        if let Ok(latency) = latency_report(&self.db, LATENCY_WINDOW).await {
+            gauge(
+                &mut out,
+                "delivery_latency_samples",
+                "Messages sampled for delivery latency in the last hour",
+                latency.samples as f64,
+            );
+            write_help(
+                &mut out,
+                "delivery_latency_seconds",
+                "gauge",
+                "Sampled delivery latency over the last hour, from storing a message",
+            );
+            write_percentiles(&mut out, "fanout", &latency.fanout);
+            write_percentiles(&mut out, "first_ack", &latency.first_ack);
+        }
+
+        out
+    }
+
+    async fn probe_database(&self) -> DatabasePoolStats {
+        // Taken before the probe borrows a connection of its own
+        let size = self.db.size();
+        let idle = self.db.num_idle() as u32;
+        let mut stats = DatabasePoolStats {
+            up: false,
+            in_use: size.saturating_sub(idle),
+            idle,
+            max: self.db.options().get_max_connections(),
+            acquire_ms: 0.0,
+        };
+
+        let started = Instant::now();
+        let Ok(Ok(mut conn)) = tokio::time::timeout(PROBE_TIMEOUT, self.db.acquire()).await else {
+            stats.acquire_ms = started.elapsed().as_secs_f64() * 1000.0;
+            return stats;
+        };
+        stats.acquire_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+        let probe = sqlx::query("SELECT 1").execute(&mut *conn);
+        stats.up = matches!(tokio::time::timeout(PROBE_TIMEOUT, probe).await, Ok(Ok(_)));
+        stats
+    }
+
+    async fn probe_redis(&self) -> RedisHealth {
+        let started = Instant::now();
+        let up = matches!(
+            tokio::time::timeout(PROBE_TIMEOUT, self.redis.ping()).await,
+            Ok(Ok(()))
+        );
+
+        RedisHealth {
+            up,
+            ping_ms: started.elapsed().as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+fn write_help(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP ansible_talk_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE ansible_talk_{} {}", name, kind);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    write_help(out, name, "gauge", help);
+    let _ = writeln!(out, "ansible_talk_{} {}", name, value);
+}
+
+fn write_percentiles(out: &mut String, phase: &str, percentiles: &LatencyPercentiles) {
+    let quantiles = [
+        ("0.5", percentiles.p50_ms),
+        ("0.9", percentiles.p90_ms),
+        ("0.99", percentiles.p99_ms),
+        ("1", percentiles.max_ms),
+    ];
+    for (quantile, ms) in quantiles {
+        if let Some(ms) = ms {
+            let _ = writeln!(
+                out,
+                "ansible_talk_delivery_latency_seconds{{phase=\"{}\",quantile=\"{}\"}} {}",
+                phase,
+                quantile,
+                ms / 1000.0
+            );
+        }
+    }
+}
+
+fn flag(up: bool) -> f64 {
+    if up {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Escape a Prometheus label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,26 @@
+use serde::Deserialize;
+
+use crate::error::AppResult;
+
+/// Send a request to a third-party API and decode its JSON reply; errors
+/// name the `provider`
+pub async fn send_json<T: for<'de> Deserialize<'de>>(
+    provider: &str,
+    request: reqwest::RequestBuilder,
+) -> AppResult<T> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| anyhow::anyhow!("{} request failed: {}", provider, e))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("{} returned {}", provider, response.status()).into());
+    }
+
+    let body = response
+        .json()
+        .await
+        .map_err(|e| anyhow::anyhow!("Invalid {} response: {}", provider, e))?;
+
+    Ok(body)
+}
@@ -0,0 +1,284 @@
+use chrono::Utc;
+use rand::Rng;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    config::InviteConfig,
+    error::{AppError, AppResult},
+    models::{Invite, InviteSummary, Referral},
+};
+
+/// Invite code characters, without ones easily misread (0/O, 1/I)
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+const CODE_LEN: usize = 10;
+
+/// Most uses an admin can give one invite
+const MAX_USES: i32 = 10_000;
+
+/// Longest an invite can be created for
+const MAX_EXPIRY_DAYS: i64 = 365;
+
+fn generate_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..CODE_LEN)
+        .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Codes as people type them: any case, with spaces or dashes
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_uppercase()
+}
+
+pub struct InviteService {
+    db: PgPool,
+    config: InviteConfig,
+}
+
+impl InviteService {
+    pub fn new(db: PgPool, config: InviteConfig) -> Self {
+        Self { db, config }
+    }
+
+    /// The user's quota, or `None` for admins, who have none
+    async fn quota(&self, user_id: Uuid) -> AppResult<Option<i32>> {
+        let (is_admin, quota): (bool, Option<i32>) =
+            sqlx::query_as("SELECT is_admin, invite_quota FROM users WHERE id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.db)
+                .await?
+                .ok_or(AppError::UserNotFound)?;
+
+        Ok((!is_admin).then(|| quota.unwrap_or(self.config.default_quota)))
+    }
+
+    /// Invites counted against the quota: all but revoked ones nobody used
+    async fn used_quota(&self, user_id: Uuid) -> AppResult<i64> {
+        let used = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM invites
+            WHERE created_by = $1 AND (revoked_at IS NULL OR uses > 0)
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(used)
+    }
+
+    /// Create an invite. Only admins can make one usable more than once.
+    pub async fn create(
+        &self,
+        user_id: Uuid,
+        max_uses: Option<i32>,
+        expires_in_days: Option<i64>,
+    ) -> AppResult<Invite> {
+        let quota = self.quota(user_id).await?;
+        if let Some(quota) = quota {
+            if max_uses.is_some_and(|uses| uses != 1) {
+                return Err(AppError::Validation(
+                    "Only admins can create reusable invites".to_string(),
+                ));
+            }
+            if self.used_quota(user_id).await? >= quota as i64 {
+                return Err(AppError::InviteQuotaExceeded);
+            }
+        }
+        let max_uses = max_uses.unwrap_or(1);
+        if !(1..=MAX_USES).contains(&max_uses) {
+            return Err(AppError::Validation(format!(
+                "Invites can be used 1-{} times",
+                MAX_USES
+            )));
+        }
+        if expires_in_days.is_some_and(|days| !(1..=MAX_EXPIRY_DAYS).contains(&days)) {
+            return Err(AppError::Validation(format!(
+                "Invites expire within 1-{} days",
+                MAX_EXPIRY_DAYS
+            )));
+        }
+        let expires_at = match expires_in_days {
+            Some(days) => Utc::now() + chrono::Duration::days(days),
+            None => Utc::now() + chrono::Duration::seconds(self.config.ttl.as_secs() as i64),
+        };
+
+        // Codes are random enough that a collision means a retry, not a bug
+        for _ in 0..3 {
+            let invite = sqlx::query_as(
+                r#"
+                INSERT INTO invites (id, code, created_by, max_uses, expires_at)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (code) DO NOTHING
+                RETURNING *
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(generate_code())
+            .bind(user_id)
+            .bind(max_uses)
+            .bind(expires_at)
+            .fetch_optional(&self.db)
+            .await?;
+            if let Some(invite) = invite {
+                return Ok(invite);
+            }
+        }
+
+        Err(anyhow::anyhow!("Failed to generate a unique invite code").into())
+    }
+
+    /// A user's invites, quota and how many people joined through them
+    pub async fn summary(&self, user_id: Uuid) -> AppResult<InviteSummary> {
+        let invites = self.list(Some(user_id)).await?;
+        let quota = self.quota(user_id).await?;
+        let remaining = match quota {
+            Some(quota) => {
+                let used = self.used_quota(user_id).await?;
+                Some((quota as i64 - used).max(0) as i32)
+            }
+            None => None,
+        };
+        let referrals = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE invited_by = $1")
+            .bind(user_id)
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(InviteSummary {
+            invites,
+            quota,
+            remaining,
+            referrals,
+        })
+    }
+
+    /// Invites of one user, or of everyone, newest first
+    pub async fn list(&self, user_id: Option<Uuid>) -> AppResult<Vec<Invite>> {
+        let invites = sqlx::query_as(
+            r#"
+            SELECT * FROM invites
+            WHERE $1::UUID IS NULL OR created_by = $1
+            ORDER BY created_at DESC
+            LIMIT 500
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(invites)
+    }
+
+    /// People who registered with the user's invites, newest first
+    pub async fn referrals(&self, user_id: Uuid) -> AppResult<Vec<Referral>> {
+        let referrals = sqlx::query_as(
+            r#"
+            SELECT id AS user_id, username, display_name, avatar_url, invite_id,
+                created_at AS joined_at
+            FROM users
+            WHERE invited_by = $1
+            ORDER BY created_at DESC
+            LIMIT 500
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(referrals)
+    }
+
+    /// Revoke an invite; only the creator's when `user_id` is given. Uses so
+    /// far stay credited.
+    pub async fn revoke(&self, user_id: Option<Uuid>, invite_id: Uuid) -> AppResult<()> {
+        let result = sqlx::query(
+            r#"
+            UPDATE invites SET revoked_at = NOW()
+            WHERE id = $1 AND ($2::UUID IS NULL OR created_by = $2) AND revoked_at IS NULL
+            "#,
+        )
+        .bind(invite_id)
+        .bind(user_id)
+        .execute(&self.db)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::InviteNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Set how many invites a user can create; `None` goes back to `INVITE_QUOTA`
+    pub async fn set_quota(&self, user_id: Uuid, quota: Option<i32>) -> AppResult<()> {
+        if quota.is_some_and(|quota| quota < 0) {
+            return Err(AppError::Validation(
+                "Invite quota cannot be negative".to_string(),
+            ));
+        }
+
+        let result = sqlx::query("UPDATE users SET invite_quota = $2 WHERE id = $1")
+            .bind(user_id)
+            .bind(quota)
+            .execute(&self.db)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::UserNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Whether a code can still be used to register
+    pub async fn is_valid(&self, code: &str) -> AppResult<bool> {
+        let valid = sqlx::query_scalar(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM invites i
+                JOIN users u ON u.id = i.created_by
+                WHERE i.code = $1 AND i.revoked_at IS NULL AND i.uses < i.max_uses
+                AND (i.expires_at IS NULL OR i.expires_at > NOW())
+                AND u.banned_at IS NULL
+            )
+            "#,
+        )
+        .bind(normalize_code(code))
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(valid)
+    }
+
+    /// Use up an invite for a new user and credit its creator, within the
+    /// registration's transaction
+    pub async fn redeem(conn: &mut PgConnection, code: &str, user_id: Uuid) -> AppResult<()> {
+        let (invite_id, created_by): (Uuid, Uuid) = sqlx::query_as(
+            r#"
+            UPDATE invites i SET uses = uses + 1
+            FROM users u
+            WHERE i.code = $1 AND u.id = i.created_by
+            AND i.revoked_at IS NULL AND i.uses < i.max_uses
+            AND (i.expires_at IS NULL OR i.expires_at > NOW())
+            AND u.banned_at IS NULL
+            RETURNING i.id, i.created_by
+            "#,
+        )
+        .bind(normalize_code(code))
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(AppError::InviteInvalid)?;
+
+        sqlx::query("UPDATE users SET invited_by = $2, invite_id = $3 WHERE id = $1")
+            .bind(user_id)
+            .bind(created_by)
+            .bind(invite_id)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+}
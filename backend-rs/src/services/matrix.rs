@@ -0,0 +1,457 @@
+use std::sync::Arc;
+
+use reqwest::{Method, StatusCode, Url};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::MatrixConfig,
+    error::{AppError, AppResult},
+    models::{ConversationEncryption, ConversationType, Message, MessageType, User},
+    services::{message_policy::StoredPayload, messaging::MessagingService},
+};
+
+/// Returns `None` when the bridge is disabled or missing required settings
+pub fn build_matrix_bridge(db: &PgPool, config: &MatrixConfig) -> Option<Arc<MatrixBridge>> {
+    let homeserver_url = config.homeserver_url.as_ref()?;
+
+    if config.server_name.is_empty() || config.as_token.is_empty() || config.hs_token.is_empty() {
+        tracing::warn!(
+            "MATRIX_HOMESERVER_URL is set but MATRIX_SERVER_NAME, MATRIX_AS_TOKEN or \
+             MATRIX_HS_TOKEN is not; Matrix bridge disabled"
+        );
+        return None;
+    }
+
+    let homeserver_url = match Url::parse(homeserver_url) {
+        Ok(url) => url,
+        Err(e) => {
+            tracing::warn!("Invalid MATRIX_HOMESERVER_URL {}: {}", homeserver_url, e);
+            return None;
+        }
+    };
+
+    Some(Arc::new(MatrixBridge {
+        db: db.clone(),
+        http: reqwest::Client::new(),
+        homeserver_url,
+        config: config.clone(),
+    }))
+}
+
+/// Matrix application service bridging linked conversations and rooms.
+///
+/// Local users appear in Matrix as virtual users under `MATRIX_USER_PREFIX`;
+/// Matrix users appear locally as users without a phone or email. Only text
+/// is bridged, so it is only useful where message content is not end-to-end
+/// encrypted.
+pub struct MatrixBridge {
+    db: PgPool,
+    http: reqwest::Client,
+    homeserver_url: Url,
+    config: MatrixConfig,
+}
+
+impl MatrixBridge {
+    pub fn bot_user_id(&self) -> String {
+        format!("@{}:{}", self.config.bot_localpart, self.config.server_name)
+    }
+
+    /// The virtual Matrix ID of a local user
+    pub fn puppet_user_id(&self, username: &str) -> String {
+        format!(
+            "@{}{}:{}",
+            self.config.user_prefix, username, self.config.server_name
+        )
+    }
+
+    /// Whether the bridge itself controls a Matrix user
+    fn is_bridge_user(&self, matrix_user_id: &str) -> bool {
+        let local_prefix = format!("@{}", self.config.user_prefix);
+        let server_suffix = format!(":{}", self.config.server_name);
+
+        matrix_user_id == self.bot_user_id()
+            || (matrix_user_id.starts_with(&local_prefix)
+                && matrix_user_id.ends_with(&server_suffix))
+    }
+
+    /// Check the token the homeserver sent with an application service call
+    pub fn verify_hs_token(&self, token: Option<&str>) -> AppResult<()> {
+        if token != Some(self.config.hs_token.as_str()) {
+            return Err(AppError::Unauthorized);
+        }
+        Ok(())
+    }
+
+    /// Link a group to an existing Matrix room the bridge bot can join
+    pub async fn link_room(&self, conversation_id: Uuid, room_id: &str) -> AppResult<()> {
+        if !room_id.starts_with('!') || !room_id.contains(':') {
+            return Err(AppError::Validation(
+                "room_id must be a Matrix room ID like !abc:example.org".to_string(),
+            ));
+        }
+
+        let conversation: Option<(ConversationType, ConversationEncryption)> =
+            sqlx::query_as("SELECT type, encryption FROM conversations WHERE id = $1")
+                .bind(conversation_id)
+                .fetch_optional(&self.db)
+                .await?;
+        match conversation {
+            None => return Err(AppError::ConversationNotFound),
+            Some((ConversationType::Group, ConversationEncryption::None)) => {}
+            Some((ConversationType::Group, ConversationEncryption::Signal)) => {
+                return Err(AppError::Validation(
+                    "Encrypted groups cannot be linked to Matrix rooms".to_string(),
+                ))
+            }
+            Some(_) => {
+                return Err(AppError::Validation(
+                    "Only groups can be linked to Matrix rooms".to_string(),
+                ))
+            }
+        }
+
+        self.call(Method::POST, &["join", room_id], None, json!({}))
+            .await?;
+
+        let result = sqlx::query(
+            r#"
+            INSERT INTO matrix_rooms (conversation_id, room_id) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(room_id)
+        .execute(&self.db)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::Validation(
+                "Conversation or room is already linked".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub async fn unlink_room(&self, conversation_id: Uuid) -> AppResult<()> {
+        let room_id: Option<String> = sqlx::query_scalar(
+            "DELETE FROM matrix_rooms WHERE conversation_id = $1 RETURNING room_id",
+        )
+        .bind(conversation_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        let room_id = room_id.ok_or(AppError::ConversationNotFound)?;
+
+        // Remote members stay as they are; the bot just stops relaying
+        if let Err(e) = self
+            .call(Method::POST, &["rooms", &room_id, "leave"], None, json!({}))
+            .await
+        {
+            tracing::warn!("Failed to leave Matrix room {}: {}", room_id, e);
+        }
+
+        Ok(())
+    }
+
+    /// Relay a new message from a linked conversation to its Matrix room
+    pub async fn relay_message(&self, message: &Message) -> AppResult<()> {
+        let Some(body) = message.payload.body.as_deref() else {
+            return Ok(());
+        };
+
+        let room_id: Option<String> =
+            sqlx::query_scalar("SELECT room_id FROM matrix_rooms WHERE conversation_id = $1")
+                .bind(message.conversation_id)
+                .fetch_optional(&self.db)
+                .await?;
+        let Some(room_id) = room_id else {
+            return Ok(());
+        };
+
+        // Messages from Matrix users came from the room already
+        let sender: Option<User> =
+            sqlx::query_as("SELECT * FROM users WHERE id = $1 AND matrix_user_id IS NULL")
+                .bind(message.sender_id)
+                .fetch_optional(&self.db)
+                .await?;
+        let Some(sender) = sender else {
+            return Ok(());
+        };
+
+        let puppet_id = self.ensure_puppet_joined(&sender, &room_id).await?;
+
+        // The message ID as transaction ID makes retries idempotent
+        self.call(
+            Method::PUT,
+            &[
+                "rooms",
+                &room_id,
+                "send",
+                "m.room.message",
+                &message.id.to_string(),
+            ],
+            Some(&puppet_id),
+            json!({
+                "msgtype": "m.text",
+                "body": body,
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Apply the events of an application service transaction: messages in
+    /// linked rooms are stored as sent by the remote user, and leaves remove
+    /// them from the conversation
+    pub async fn handle_transaction(
+        &self,
+        messaging: &MessagingService,
+        events: &[Value],
+    ) -> AppResult<()> {
+        for event in events {
+            let (Some(event_type), Some(room_id), Some(sender)) = (
+                event["type"].as_str(),
+                event["room_id"].as_str(),
+                event["sender"].as_str(),
+            ) else {
+                continue;
+            };
+
+            if self.is_bridge_user(sender) {
+                continue;
+            }
+
+            let conversation_id: Option<Uuid> =
+                sqlx::query_scalar("SELECT conversation_id FROM matrix_rooms WHERE room_id = $1")
+                    .bind(room_id)
+                    .fetch_optional(&self.db)
+                    .await?;
+            let Some(conversation_id) = conversation_id else {
+                continue;
+            };
+
+            match event_type {
+                "m.room.message" => {
+                    let content = &event["content"];
+                    let Some(body) = content["body"].as_str() else {
+                        continue;
+                    };
+                    if !matches!(
+                        content["msgtype"].as_str(),
+                        Some("m.text" | "m.notice" | "m.emote")
+                    ) {
+                        continue;
+                    }
+
+                    let user_id = self.remote_user(sender).await?;
+                    self.join_conversation(conversation_id, user_id).await?;
+
+                    messaging
+                        .store_message(
+                            conversation_id,
+                            user_id,
+                            MessageType::Text,
+                            StoredPayload::text(body.to_string()),
+                            None,
+                            None,
+                            false,
+                            false,
+                        )
+                        .await?;
+                }
+                "m.room.member" => {
+                    if matches!(
+                        event["content"]["membership"].as_str(),
+                        Some("leave" | "ban")
+                    ) {
+                        sqlx::query(
+                            r#"
+                            UPDATE participants SET left_at = NOW()
+                            WHERE conversation_id = $1 AND left_at IS NULL
+                            AND user_id = (SELECT id FROM users WHERE matrix_user_id = $2)
+                            "#,
+                        )
+                        .bind(conversation_id)
+                        .bind(event["state_key"].as_str().unwrap_or(sender))
+                        .execute(&self.db)
+                        .await?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The local user standing in for a Matrix user, created on first use
+    async fn remote_user(&self, matrix_user_id: &str) -> AppResult<Uuid> {
+        let digest = Sha256::digest(matrix_user_id.as_bytes());
+        let username: String = std::iter::once("mx_".to_string())
+            .chain(digest[..6].iter().map(|b| format!("{:02x}", b)))
+            .collect();
+        let display_name: String = matrix_user_id
+            .trim_start_matches('@')
+            .split(':')
+            .next()
+            .unwrap_or(matrix_user_id)
+            .chars()
+            .take(100)
+            .collect();
+
+        let user_id: Uuid = sqlx::query_scalar(
+            r#"
+            INSERT INTO users (id, username, display_name, matrix_user_id)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (matrix_user_id) DO UPDATE SET matrix_user_id = EXCLUDED.matrix_user_id
+            RETURNING id
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(&username)
+        .bind(&display_name)
+        .bind(matrix_user_id)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(user_id)
+    }
+
+    async fn join_conversation(&self, conversation_id: Uuid, user_id: Uuid) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO participants (id, conversation_id, user_id, role)
+            VALUES ($1, $2, $3, 'member')
+            ON CONFLICT (conversation_id, user_id) DO UPDATE SET left_at = NULL
+            WHERE participants.left_at IS NOT NULL
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(conversation_id)
+        .bind(user_id)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Register a local user's virtual Matrix user and join it to the room,
+    /// once per room
+    async fn ensure_puppet_joined(&self, user: &User, room_id: &str) -> AppResult<String> {
+        let puppet_id = self.puppet_user_id(&user.username);
+
+        let joined: Option<i32> = sqlx::query_scalar(
+            "SELECT 1 FROM matrix_puppet_rooms WHERE user_id = $1 AND room_id = $2",
+        )
+        .bind(user.id)
+        .bind(room_id)
+        .fetch_optional(&self.db)
+        .await?;
+        if joined.is_some() {
+            return Ok(puppet_id);
+        }
+
+        let localpart = format!("{}{}", self.config.user_prefix, user.username);
+        let (status, body) = self
+            .call_raw(
+                Method::POST,
+                &["register"],
+                None,
+                json!({ "type": "m.login.application_service", "username": localpart }),
+            )
+            .await?;
+        if !status.is_success() && body["errcode"] != "M_USER_IN_USE" {
+            return Err(anyhow::anyhow!("Matrix register returned {}: {}", status, body).into());
+        }
+
+        self.call(
+            Method::PUT,
+            &["profile", &puppet_id, "displayname"],
+            Some(&puppet_id),
+            json!({ "displayname": user.display_name }),
+        )
+        .await?;
+
+        // Invite-only rooms need the bot's invite; public ones don't
+        let _ = self
+            .call_raw(
+                Method::POST,
+                &["rooms", room_id, "invite"],
+                None,
+                json!({ "user_id": puppet_id }),
+            )
+            .await;
+        self.call(
+            Method::POST,
+            &["join", room_id],
+            Some(&puppet_id),
+            json!({}),
+        )
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO matrix_puppet_rooms (user_id, room_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(user.id)
+        .bind(room_id)
+        .execute(&self.db)
+        .await?;
+
+        Ok(puppet_id)
+    }
+
+    async fn call(
+        &self,
+        method: Method,
+        path: &[&str],
+        as_user: Option<&str>,
+        body: Value,
+    ) -> AppResult<Value> {
+        let (status, body) = self.call_raw(method, path, as_user, body).await?;
+
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("Matrix homeserver returned {}: {}", status, body).into());
+        }
+
+        Ok(body)
+    }
+
+    /// Call the client-server API as the bot, or as one of its virtual users
+    async fn call_raw(
+        &self,
+        method: Method,
+        path: &[&str],
+        as_user: Option<&str>,
+        body: Value,
+    ) -> AppResult<(StatusCode, Value)> {
+        let mut url = self.homeserver_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("MATRIX_HOMESERVER_URL cannot be a base URL"))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(path);
+
+        let mut request = self
+            .http
+            .request(method, url)
+            .bearer_auth(&self.config.as_token)
+            .json(&body);
+        if let Some(user_id) = as_user {
+            request = request.query(&[("user_id", user_id)]);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Matrix request failed: {}", e))?;
+        let status = response.status();
+        let body = response.json().await.unwrap_or(Value::Null);
+
+        Ok((status, body))
+    }
+}
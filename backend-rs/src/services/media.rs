@@ -0,0 +1,291 @@
+use std::{sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::{Config, TranscodeConfig},
+    error::{AppError, AppResult},
+    models::{
+        Attachment, AttachmentRendition, AttachmentUrl, Message, RenditionKind, RenditionUrl,
+        TranscodeStatus,
+    },
+    services::transcoding::wants_transcode,
+    storage::blob::BlobStorage,
+};
+
+/// Route signed attachment links point at, relative to the server root
+pub const MEDIA_ROUTE: &str = "/api/v1/media";
+
+/// Unreferenced attachments younger than this may still be about to be sent
+const ORPHAN_GRACE_HOURS: i32 = 24;
+
+/// Orphaned attachments removed per purge run
+const ORPHAN_PURGE_BATCH: i64 = 500;
+
+/// Signs attachment links at response time. A link carries its expiry and an
+/// HMAC over the attachment ID and expiry, so nothing permanent is stored.
+#[derive(Clone)]
+pub struct MediaUrls {
+    secret: Vec<u8>,
+    ttl: Duration,
+    base_url: String,
+}
+
+impl MediaUrls {
+    pub fn new(config: &Config) -> Self {
+        let secret = config
+            .media
+            .url_secret
+            .clone()
+            .unwrap_or_else(|| config.jwt.secret.clone());
+        let base_url = config
+            .media
+            .base_url
+            .clone()
+            .unwrap_or_else(|| format!("http://localhost:{}", config.server.port));
+
+        Self {
+            secret: secret.into_bytes(),
+            ttl: config.media.url_ttl,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn sign(&self, attachment_id: Uuid) -> AttachmentUrl {
+        let expires = Utc::now().timestamp() + self.ttl.as_secs() as i64;
+        let signature =
+            URL_SAFE_NO_PAD.encode(self.mac(attachment_id, expires).finalize().into_bytes());
+
+        AttachmentUrl {
+            url: format!(
+                "{}{}/{}?expires={}&sig={}",
+                self.base_url, MEDIA_ROUTE, attachment_id, expires, signature
+            ),
+            expires_at: DateTime::from_timestamp(expires, 0).unwrap_or_default(),
+            renditions: Vec::new(),
+        }
+    }
+
+    /// A link to the attachment and, under the same signature, its renditions
+    pub fn sign_with_renditions(
+        &self,
+        attachment_id: Uuid,
+        renditions: Vec<AttachmentRendition>,
+    ) -> AttachmentUrl {
+        let mut link = self.sign(attachment_id);
+        link.renditions = renditions
+            .into_iter()
+            .map(|r| RenditionUrl {
+                url: format!("{}&rendition={}", link.url, r.kind.as_str()),
+                kind: r.kind,
+                content_type: r.content_type,
+                size: r.size,
+            })
+            .collect();
+        link
+    }
+
+    /// Fill in the message's attachment link, if it has one
+    pub fn sign_message(&self, mut message: Message) -> Message {
+        message.attachment = message.attachment_id.map(|id| self.sign(id));
+        message
+    }
+
+    /// Check a link's signature, then its expiry
+    pub fn verify(&self, attachment_id: Uuid, expires: i64, signature: &str) -> AppResult<()> {
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| AppError::AttachmentNotFound)?;
+        self.mac(attachment_id, expires)
+            .verify_slice(&signature)
+            .map_err(|_| AppError::AttachmentNotFound)?;
+
+        if expires < Utc::now().timestamp() {
+            return Err(AppError::MediaLinkExpired);
+        }
+
+        Ok(())
+    }
+
+    fn mac(&self, attachment_id: Uuid, expires: i64) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}.{}", attachment_id, expires).as_bytes());
+        mac
+    }
+}
+
+/// Message attachments: uploaded privately, then served through signed links
+pub struct MediaService {
+    db: PgPool,
+    storage: Arc<dyn BlobStorage>,
+    urls: MediaUrls,
+    transcode: TranscodeConfig,
+}
+
+impl MediaService {
+    pub fn new(db: PgPool, storage: Arc<dyn BlobStorage>, config: &Config) -> Self {
+        Self {
+            db,
+            storage,
+            urls: MediaUrls::new(config),
+            transcode: config.transcode.clone(),
+        }
+    }
+
+    /// Store an already screened upload; it is sent by passing its ID as a
+    /// message's `attachment_id`. GIFs and large videos are queued for
+    /// transcoding.
+    pub async fn upload(
+        &self,
+        uploader_id: Uuid,
+        data: Bytes,
+        content_type: &str,
+    ) -> AppResult<(Attachment, AttachmentUrl)> {
+        let id = Uuid::new_v4();
+        let key = format!("messages/{}/{}", uploader_id, id);
+        let size = data.len() as i64;
+        let transcode_status = wants_transcode(&self.transcode, content_type, data.len())
+            .then_some(TranscodeStatus::Pending);
+
+        self.storage
+            .upload_private_file(self.storage.attachments_bucket(), &key, data, content_type)
+            .await?;
+
+        let attachment: Attachment = sqlx::query_as(
+            r#"
+            INSERT INTO attachments (id, uploader_id, storage_key, content_type, size,
+                transcode_status)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(uploader_id)
+        .bind(&key)
+        .bind(content_type)
+        .bind(size)
+        .bind(transcode_status)
+        .fetch_one(&self.db)
+        .await?;
+
+        let url = self.urls.sign(id);
+        Ok((attachment, url))
+    }
+
+    /// Serve a signed link, or one of its renditions; returns the content
+    /// type and data
+    pub async fn open(
+        &self,
+        attachment_id: Uuid,
+        expires: i64,
+        signature: &str,
+        rendition: Option<RenditionKind>,
+    ) -> AppResult<(String, Bytes)> {
+        self.urls.verify(attachment_id, expires, signature)?;
+
+        let (storage_key, content_type): (String, String) = match rendition {
+            None => sqlx::query_as(
+                "SELECT storage_key, content_type FROM attachments WHERE id = $1",
+            )
+            .bind(attachment_id)
+            .fetch_optional(&self.db)
+            .await?,
+            Some(kind) => sqlx::query_as(
+                r#"
+                SELECT storage_key, content_type FROM attachment_renditions
+                WHERE attachment_id = $1 AND kind = $2
+                "#,
+            )
+            .bind(attachment_id)
+            .bind(kind)
+            .fetch_optional(&self.db)
+            .await?,
+        }
+        .ok_or(AppError::AttachmentNotFound)?;
+
+        let data = self
+            .storage
+            .download_file(self.storage.attachments_bucket(), &storage_key)
+            .await?;
+
+        Ok((content_type, data))
+    }
+
+    /// A fresh link for a message's attachment and its renditions, for
+    /// clients holding an expired one
+    pub async fn refresh(&self, message_id: Uuid, user_id: Uuid) -> AppResult<AttachmentUrl> {
+        let attachment_id: Option<Option<Uuid>> = sqlx::query_scalar(
+            r#"
+            SELECT m.attachment_id FROM messages m
+            JOIN participants p ON p.conversation_id = m.conversation_id
+            WHERE m.id = $1 AND p.user_id = $2 AND p.left_at IS NULL
+            AND m.deleted_at IS NULL AND (NOT m.is_shadowed OR m.sender_id = $2)
+            AND (p.cleared_before IS NULL OR m.created_at > p.cleared_before)
+            "#,
+        )
+        .bind(message_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        let attachment_id = attachment_id
+            .ok_or(AppError::MessageNotFound)?
+            .ok_or(AppError::AttachmentNotFound)?;
+
+        let renditions: Vec<AttachmentRendition> = sqlx::query_as(
+            "SELECT * FROM attachment_renditions WHERE attachment_id = $1 ORDER BY kind",
+        )
+        .bind(attachment_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(self.urls.sign_with_renditions(attachment_id, renditions))
+    }
+
+    /// Delete attachments no message refers to anymore, such as those of
+    /// messages whose history every participant cleared. The blob goes first
+    /// so a failed delete is retried on the next run.
+    pub async fn purge_orphaned(&self) -> AppResult<u64> {
+        let orphaned: Vec<(Uuid, String)> = sqlx::query_as(
+            r#"
+            SELECT a.id, a.storage_key FROM attachments a
+            WHERE a.created_at < NOW() - make_interval(hours => $1)
+            AND NOT EXISTS (SELECT 1 FROM messages m WHERE m.attachment_id = a.id)
+            LIMIT $2
+            "#,
+        )
+        .bind(ORPHAN_GRACE_HOURS)
+        .bind(ORPHAN_PURGE_BATCH)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut purged = 0;
+        for (id, key) in orphaned {
+            let renditions: Vec<String> = sqlx::query_scalar(
+                "SELECT storage_key FROM attachment_renditions WHERE attachment_id = $1",
+            )
+            .bind(id)
+            .fetch_all(&self.db)
+            .await?;
+            for key in renditions.iter().chain([&key]) {
+                self.storage
+                    .delete_file(self.storage.attachments_bucket(), key)
+                    .await?;
+            }
+            purged += sqlx::query("DELETE FROM attachments WHERE id = $1")
+                .bind(id)
+                .execute(&self.db)
+                .await?
+                .rows_affected();
+        }
+
+        Ok(purged)
+    }
+}
@@ -0,0 +1,212 @@
+use uuid::Uuid;
+
+use crate::{
+    config::MessageLimitsConfig,
+    error::{AppError, AppResult},
+    models::{MessagePayload, MessageType, PAYLOAD_VERSION},
+    services::franking::FRANKING_TAG_LEN,
+};
+
+/// Encrypted clients upload every attachment as opaque bytes, so its type
+/// cannot be checked against the message's
+const OPAQUE_TYPE: &str = "application/octet-stream";
+
+/// A payload flattened into the message columns it is stored in
+#[derive(Debug, Clone, Default)]
+pub struct StoredPayload {
+    pub content: Vec<u8>,
+    pub caption: Option<String>,
+    pub encrypted: bool,
+    pub attachment_id: Option<Uuid>,
+    /// The sender's commitment to the plaintext, for encrypted content only
+    pub franking_tag: Option<Vec<u8>>,
+}
+
+impl StoredPayload {
+    pub fn text(body: String) -> Self {
+        Self {
+            content: body.into_bytes(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Check a client's payload has the fields its message type takes, then
+/// flatten it for storage
+pub fn encode_payload(
+    message_type: MessageType,
+    payload: MessagePayload,
+) -> AppResult<StoredPayload> {
+    if !(1..=PAYLOAD_VERSION).contains(&payload.v) {
+        return Err(AppError::Validation(format!(
+            "Unsupported payload version {}; this server reads up to {}",
+            payload.v, PAYLOAD_VERSION
+        )));
+    }
+
+    let is_media = matches!(
+        message_type,
+        MessageType::Image | MessageType::Video | MessageType::Audio | MessageType::File
+    );
+    if !is_media && (payload.caption.is_some() || payload.attachment_id.is_some()) {
+        return Err(AppError::Validation(
+            "Only media messages have a caption or attachment".to_string(),
+        ));
+    }
+
+    let MessagePayload {
+        body,
+        caption,
+        attachment_id,
+        ciphertext,
+        media,
+        event,
+        franking_tag,
+        ..
+    } = payload;
+    let (content, encrypted) = match (message_type, body, ciphertext, media, event) {
+        (MessageType::Text, Some(body), None, None, None) => (body.into_bytes(), false),
+        (MessageType::System, None, None, None, Some(event)) => {
+            (serde_json::to_vec(&event)?, false)
+        }
+        (MessageType::System, ..) => {
+            return Err(AppError::Validation(
+                "System messages carry an event".to_string(),
+            ))
+        }
+        (_, None, Some(ciphertext), None, None) => (ciphertext, true),
+        (_, None, None, Some(media), None) if is_media => (media, false),
+        (MessageType::Text, ..) => {
+            return Err(AppError::Validation(
+                "Text messages carry a body or ciphertext".to_string(),
+            ))
+        }
+        (_, None, None, None, None) => (Vec::new(), false),
+        _ => {
+            return Err(AppError::Validation(
+                "Media and sticker messages carry at most one of media or ciphertext".to_string(),
+            ))
+        }
+    };
+
+    if let Some(tag) = &franking_tag {
+        if !encrypted {
+            return Err(AppError::Validation(
+                "Only encrypted messages carry a franking_tag".to_string(),
+            ));
+        }
+        if tag.len() != FRANKING_TAG_LEN {
+            return Err(AppError::Validation(format!(
+                "franking_tag must be {} bytes",
+                FRANKING_TAG_LEN
+            )));
+        }
+    }
+
+    Ok(StoredPayload {
+        content,
+        caption,
+        encrypted,
+        attachment_id,
+        franking_tag,
+    })
+}
+
+/// Size limits of a message's content, inline media included, and caption
+pub fn check_content(
+    limits: &MessageLimitsConfig,
+    message_type: MessageType,
+    payload: &StoredPayload,
+) -> AppResult<()> {
+    check_size(limit(limits, message_type), payload.content.len())?;
+    if let Some(caption) = &payload.caption {
+        check_size(Some(limits.max_text_bytes), caption.len())?;
+    }
+
+    Ok(())
+}
+
+/// Type and size limits of an upload, by the family of its content type
+pub fn check_upload(
+    limits: &MessageLimitsConfig,
+    content_type: &str,
+    size: usize,
+) -> AppResult<()> {
+    let content_type = content_type.to_lowercase();
+    let allowed = limits.allowed_attachment_types.iter().any(|allowed| {
+        allowed == "*"
+            || *allowed == content_type
+            || allowed
+                .strip_suffix('*')
+                .is_some_and(|family| family.ends_with('/') && content_type.starts_with(family))
+    });
+    if !allowed {
+        return Err(AppError::UnsupportedMediaType(content_type));
+    }
+
+    let message_type = family(&content_type).unwrap_or(MessageType::File);
+    check_size(limit(limits, message_type), size)
+}
+
+/// An attachment sent as an image, video or audio message must be one, and
+/// within that type's limit; files may be anything that could be uploaded
+pub fn check_attachment(
+    limits: &MessageLimitsConfig,
+    message_type: MessageType,
+    content_type: &str,
+    size: usize,
+) -> AppResult<()> {
+    let content_type = content_type.to_lowercase();
+    if message_type != MessageType::File
+        && content_type != OPAQUE_TYPE
+        && family(&content_type) != Some(message_type)
+    {
+        return Err(AppError::UnsupportedMediaType(content_type));
+    }
+
+    check_size(limit(limits, message_type), size)
+}
+
+/// The largest upload of any type, but never past `max_upload_bytes` since
+/// uploads are buffered; the attachment route's body limit
+pub fn max_upload_bytes(limits: &MessageLimitsConfig) -> usize {
+    [
+        limits.max_image_bytes,
+        limits.max_video_bytes,
+        limits.max_audio_bytes,
+        limits.max_file_bytes,
+    ]
+    .into_iter()
+    .max()
+    .unwrap_or_default()
+    .min(limits.max_upload_bytes)
+}
+
+/// System messages are written by the server and not limited
+fn limit(limits: &MessageLimitsConfig, message_type: MessageType) -> Option<usize> {
+    match message_type {
+        MessageType::Text | MessageType::Sticker => Some(limits.max_text_bytes),
+        MessageType::Image => Some(limits.max_image_bytes),
+        MessageType::Video => Some(limits.max_video_bytes),
+        MessageType::Audio => Some(limits.max_audio_bytes),
+        MessageType::File => Some(limits.max_file_bytes),
+        MessageType::System => None,
+    }
+}
+
+fn check_size(limit: Option<usize>, size: usize) -> AppResult<()> {
+    match limit {
+        Some(limit) if size > limit => Err(AppError::MessageTooLarge(limit)),
+        _ => Ok(()),
+    }
+}
+
+/// The media message type a content type belongs to
+fn family(content_type: &str) -> Option<MessageType> {
+    match content_type.split('/').next() {
+        Some("image") => Some(MessageType::Image),
+        Some("video") => Some(MessageType::Video),
+        Some("audio") => Some(MessageType::Audio),
+        _ => None,
+    }
+}
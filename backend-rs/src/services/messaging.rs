@@ -1,17 +1,43 @@
-use chrono::Utc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use sqlx::{types::Json, PgConnection, PgPool};
 use uuid::Uuid;
 
 use crate::{
+    config::Config,
     error::{AppError, AppResult},
     models::{
-        Conversation, ConversationType, ConversationWithDetails, Message, MessageStatus,
-        MessageType, Participant, ParticipantRole, ParticipantWithUser, ReceiptType, User,
+        Conversation, ConversationEncryption, ConversationEvent, ConversationFilter,
+        ConversationType, ConversationWithDetails, GroupAction, GroupPermissions,
+        GroupPermissionsUpdate, Message, MessagePayload, MessageStatus, MessageType, Participant,
+        ParticipantRole, ParticipantWithUser, ReceiptType, SystemEvent, User,
+        PARTICIPANT_PREVIEW_LIMIT,
+    },
+    services::{
+        contacts::ContactsService,
+        content_moderation::{ContentSource, ContentSubject, ModerationAction, ModerationPipeline},
+        crypto::{is_signal_ciphertext, pad_content},
+        events::{DomainEvent, EventLog},
+        franking::Franking,
+        matrix::MatrixBridge,
+        media::MediaUrls,
+        message_policy::{self, StoredPayload},
+        search::{escape_like, SearchDocument, SearchIndex},
+        social_graph::SocialGraphService,
+        spam::{SpamGuard, SpamVerdict},
+        translation::{TranslationProvider, TranslationService},
     },
     storage::redis::RedisClient,
 };
 
+/// Seconds for a conversation's interaction score to halve
+const INTERACTION_HALF_LIFE: f64 = 14.0 * 24.0 * 60.0 * 60.0;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WsMessage {
     #[serde(rename = "type")]
@@ -19,14 +45,53 @@ pub struct WsMessage {
     pub payload: serde_json::Value,
 }
 
+/// Group metadata changes; `None` leaves a field unchanged and an empty
+/// description or rules clears it
+#[derive(Debug, Default)]
+pub struct GroupUpdate {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub rules: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
 pub struct MessagingService {
     db: PgPool,
     redis: RedisClient,
+    config: Config,
+    search: Option<Arc<dyn SearchIndex>>,
+    translator: Option<Arc<dyn TranslationProvider>>,
+    bridge: Option<Arc<MatrixBridge>>,
 }
 
 impl MessagingService {
-    pub fn new(db: PgPool, redis: RedisClient) -> Self {
-        Self { db, redis }
+    pub fn new(db: PgPool, redis: RedisClient, config: Config) -> Self {
+        Self {
+            db,
+            redis,
+            config,
+            search: None,
+            translator: None,
+            bridge: None,
+        }
+    }
+
+    /// Keep the given search index up to date on message writes
+    pub fn with_search_index(mut self, search: Option<Arc<dyn SearchIndex>>) -> Self {
+        self.search = search;
+        self
+    }
+
+    /// Auto-translate new and edited messages for participants who asked for it
+    pub fn with_translator(mut self, translator: Option<Arc<dyn TranslationProvider>>) -> Self {
+        self.translator = translator;
+        self
+    }
+
+    /// Relay new messages in linked conversations to Matrix
+    pub fn with_bridge(mut self, bridge: Option<Arc<MatrixBridge>>) -> Self {
+        self.bridge = bridge;
+        self
     }
 
     /// Create or get existing direct conversation
@@ -34,106 +99,177 @@ impl MessagingService {
         &self,
         user_id: Uuid,
         other_user_id: Uuid,
+        encryption: ConversationEncryption,
     ) -> AppResult<ConversationWithDetails> {
-        // Check if conversation already exists
-        let existing: Option<Conversation> = sqlx::query_as(
+        // A chat with yourself is your Saved Messages
+        if other_user_id == user_id {
+            return self.get_saved_conversation(user_id).await;
+        }
+
+        let conversation_id = self
+            .direct_conversation_id(user_id, other_user_id, encryption)
+            .await?;
+
+        self.get_conversation(conversation_id, user_id).await
+    }
+
+    /// ID of the direct conversation between two users, creating it with
+    /// `encryption` if needed; an existing one keeps its own mode. The pair's
+    /// `direct_key` is unique, so concurrent requests agree on one.
+    pub async fn direct_conversation_id(
+        &self,
+        user_id: Uuid,
+        other_user_id: Uuid,
+        encryption: ConversationEncryption,
+    ) -> AppResult<Uuid> {
+        let key = direct_key(user_id, other_user_id);
+
+        let existing: Option<Uuid> =
+            sqlx::query_scalar("SELECT id FROM conversations WHERE direct_key = $1")
+                .bind(&key)
+                .fetch_optional(&self.db)
+                .await?;
+        if let Some(conversation_id) = existing {
+            return self.reopen_direct(conversation_id, user_id).await;
+        }
+
+        // Non-contacts land in the recipient's message requests; if the
+        // recipient has blocked the user, they silently never see it
+        let blocked: Option<bool> = sqlx::query_scalar(
+            "SELECT is_blocked FROM contacts WHERE user_id = $1 AND contact_id = $2",
+        )
+        .bind(other_user_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        // Colleagues in a shared organization directory count as contacts
+        let colleagues: bool = sqlx::query_scalar(
             r#"
-            SELECT c.* FROM conversations c
-            JOIN participants p1 ON c.id = p1.conversation_id
-            JOIN participants p2 ON c.id = p2.conversation_id
-            WHERE c.type = 'direct'
-            AND p1.user_id = $1 AND p2.user_id = $2
-            AND p1.left_at IS NULL AND p2.left_at IS NULL
+            SELECT EXISTS(
+                SELECT 1 FROM organization_members a
+                JOIN organization_members b ON a.organization_id = b.organization_id
+                WHERE a.user_id = $1 AND b.user_id = $2
+            )
             "#,
         )
         .bind(user_id)
         .bind(other_user_id)
-        .fetch_optional(&self.db)
+        .fetch_one(&self.db)
         .await?;
 
-        if let Some(conv) = existing {
-            return self.get_conversation(conv.id, user_id).await;
-        }
-
         // Create new conversation
         let mut tx = self.db.begin().await?;
 
-        let conv_id = Uuid::new_v4();
-        let conversation: Conversation = sqlx::query_as(
+        // A concurrent request may have created it in the meantime
+        let created: Option<Uuid> = sqlx::query_scalar(
             r#"
-            INSERT INTO conversations (id, type, created_by)
-            VALUES ($1, $2, $3)
-            RETURNING *
+            INSERT INTO conversations (id, type, created_by, encryption, direct_key)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (direct_key) DO NOTHING
+            RETURNING id
             "#,
         )
-        .bind(conv_id)
+        .bind(Uuid::new_v4())
         .bind(ConversationType::Direct)
         .bind(user_id)
-        .fetch_one(&mut *tx)
+        .bind(encryption)
+        .bind(&key)
+        .fetch_optional(&mut *tx)
         .await?;
 
+        let Some(conv_id) = created else {
+            tx.rollback().await?;
+            let conversation_id: Uuid =
+                sqlx::query_scalar("SELECT id FROM conversations WHERE direct_key = $1")
+                    .bind(&key)
+                    .fetch_one(&self.db)
+                    .await?;
+            return self.reopen_direct(conversation_id, user_id).await;
+        };
+
         // Add both participants
-        for uid in [user_id, other_user_id] {
+        for (uid, is_request, left) in [
+            (user_id, false, false),
+            (
+                other_user_id,
+                blocked.is_none() && !colleagues,
+                blocked == Some(true),
+            ),
+        ] {
             sqlx::query(
                 r#"
-                INSERT INTO participants (id, conversation_id, user_id, role, joined_at)
-                VALUES ($1, $2, $3, $4, NOW())
+                INSERT INTO participants (id, conversation_id, user_id, role, joined_at, is_request, left_at)
+                VALUES ($1, $2, $3, $4, NOW(), $5, CASE WHEN $6 THEN NOW() END)
                 "#,
             )
             .bind(Uuid::new_v4())
             .bind(conv_id)
             .bind(uid)
             .bind(ParticipantRole::Member)
+            .bind(is_request)
+            .bind(left)
             .execute(&mut *tx)
             .await?;
         }
 
         tx.commit().await?;
 
-        self.get_conversation(conversation.id, user_id).await
+        Ok(conv_id)
     }
 
-    /// Create a group conversation
-    pub async fn create_group_conversation(
+    /// Opening a direct conversation brings the opener back into it, e.g.
+    /// after it was created while they had the other user blocked
+    async fn reopen_direct(&self, conversation_id: Uuid, user_id: Uuid) -> AppResult<Uuid> {
+        sqlx::query(
+            r#"
+            UPDATE participants SET left_at = NULL
+            WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NOT NULL
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .execute(&self.db)
+        .await?;
+
+        Ok(conversation_id)
+    }
+
+    /// Get the user's Saved Messages conversation, creating it on first use
+    pub async fn get_saved_conversation(
         &self,
         user_id: Uuid,
-        name: &str,
-        member_ids: Vec<Uuid>,
     ) -> AppResult<ConversationWithDetails> {
-        let mut tx = self.db.begin().await?;
-
-        let conv_id = Uuid::new_v4();
-        let conversation: Conversation = sqlx::query_as(
-            r#"
-            INSERT INTO conversations (id, type, name, created_by)
-            VALUES ($1, $2, $3, $4)
-            RETURNING *
-            "#,
+        let existing: Option<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM conversations WHERE type = 'saved' AND created_by = $1",
         )
-        .bind(conv_id)
-        .bind(ConversationType::Group)
-        .bind(name)
         .bind(user_id)
-        .fetch_one(&mut *tx)
+        .fetch_optional(&self.db)
         .await?;
 
-        // Add creator as owner
-        sqlx::query(
+        if let Some(conversation_id) = existing {
+            return self.get_conversation(conversation_id, user_id).await;
+        }
+
+        let mut tx = self.db.begin().await?;
+
+        // A concurrent request may have provisioned it in the meantime
+        let created: Option<Uuid> = sqlx::query_scalar(
             r#"
-            INSERT INTO participants (id, conversation_id, user_id, role, joined_at)
-            VALUES ($1, $2, $3, $4, NOW())
+            INSERT INTO conversations (id, type, created_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (created_by) WHERE type = 'saved' DO NOTHING
+            RETURNING id
             "#,
         )
         .bind(Uuid::new_v4())
-        .bind(conv_id)
+        .bind(ConversationType::Saved)
         .bind(user_id)
-        .bind(ParticipantRole::Owner)
-        .execute(&mut *tx)
+        .fetch_optional(&mut *tx)
         .await?;
 
-        // Add members
-        for member_id in member_ids {
-            if member_id != user_id {
+        let conversation_id = match created {
+            Some(conversation_id) => {
                 sqlx::query(
                     r#"
                     INSERT INTO participants (id, conversation_id, user_id, role, joined_at)
@@ -141,176 +277,1614 @@ impl MessagingService {
                     "#,
                 )
                 .bind(Uuid::new_v4())
-                .bind(conv_id)
-                .bind(member_id)
-                .bind(ParticipantRole::Member)
+                .bind(conversation_id)
+                .bind(user_id)
+                .bind(ParticipantRole::Owner)
                 .execute(&mut *tx)
                 .await?;
-            }
-        }
 
-        tx.commit().await?;
+                tx.commit().await?;
+                conversation_id
+            }
+            None => {
+                tx.rollback().await?;
+                sqlx::query_scalar(
+                    "SELECT id FROM conversations WHERE type = 'saved' AND created_by = $1",
+                )
+                .bind(user_id)
+                .fetch_one(&self.db)
+                .await?
+            }
+        };
 
-        self.get_conversation(conversation.id, user_id).await
+        self.get_conversation(conversation_id, user_id).await
     }
 
-    /// Get conversation with details
-    pub async fn get_conversation(
+    /// Accept a pending message request, revealing read state and presence
+    /// to the sender
+    pub async fn accept_request(
         &self,
         conversation_id: Uuid,
         user_id: Uuid,
     ) -> AppResult<ConversationWithDetails> {
-        // Check if user is participant
-        let is_participant: Option<(i64,)> = sqlx::query_as(
-            "SELECT 1 FROM participants WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL",
+        let result = sqlx::query(
+            r#"
+            UPDATE participants SET is_request = false
+            WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL AND is_request
+            "#,
         )
         .bind(conversation_id)
         .bind(user_id)
-        .fetch_optional(&self.db)
+        .execute(&self.db)
         .await?;
 
-        if is_participant.is_none() {
-            return Err(AppError::NotParticipant);
-        }
+        if result.rows_affected() > 0 {
+            // Apply read receipts held back while the request was pending
+            sqlx::query(
+                r#"
+                UPDATE messages m SET status = 'read'
+                WHERE m.conversation_id = $1 AND m.sender_id != $2 AND m.status IN ('sent', 'delivered')
+                AND EXISTS (
+                    SELECT 1 FROM receipts r
+                    WHERE r.message_id = m.id AND r.user_id = $2 AND r.type = 'read'
+                )
+                "#,
+            )
+            .bind(conversation_id)
+            .bind(user_id)
+            .execute(&self.db)
+            .await?;
 
-        let conversation: Option<Conversation> =
-            sqlx::query_as("SELECT * FROM conversations WHERE id = $1")
-                .bind(conversation_id)
-                .fetch_optional(&self.db)
+            let ws_message = WsMessage {
+                msg_type: "request_accepted".to_string(),
+                payload: serde_json::json!({
+                    "conversation_id": conversation_id,
+                    "user_id": user_id,
+                }),
+            };
+            self.publish_to_participants(conversation_id, user_id, &ws_message)
                 .await?;
+        }
 
-        let conversation = conversation.ok_or(AppError::ConversationNotFound)?;
+        self.get_conversation(conversation_id, user_id).await
+    }
 
-        // Get participants
-        let participants: Vec<Participant> = sqlx::query_as(
-            "SELECT * FROM participants WHERE conversation_id = $1 AND left_at IS NULL",
+    /// Flag the conversation unread for the user, to come back to later
+    /// without un-reading any messages. Reading a message in it clears the flag.
+    pub async fn mark_unread(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+    ) -> AppResult<ConversationWithDetails> {
+        let result = sqlx::query(
+            r#"
+            UPDATE participants SET marked_unread = true
+            WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL
+            "#,
         )
         .bind(conversation_id)
-        .fetch_all(&self.db)
+        .bind(user_id)
+        .execute(&self.db)
         .await?;
 
-        let mut participants_with_users = Vec::with_capacity(participants.len());
-        for participant in participants {
-            let user: Option<User> = sqlx::query_as("SELECT * FROM users WHERE id = $1")
-                .bind(participant.user_id)
-                .fetch_optional(&self.db)
-                .await?;
-            participants_with_users.push(ParticipantWithUser { participant, user });
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotParticipant);
         }
 
-        // Get unread count
-        let unread_count: (i64,) = sqlx::query_as(
+        // Let the user's other devices pick up the change
+        let ws_message = WsMessage {
+            msg_type: "conversation_marked_unread".to_string(),
+            payload: serde_json::json!({ "conversation_id": conversation_id }),
+        };
+        self.redis
+            .publish_message(&user_id.to_string(), &serde_json::to_string(&ws_message)?)
+            .await?;
+
+        self.get_conversation(conversation_id, user_id).await
+    }
+
+    /// Clear the user's copy of the history; the other participants keep
+    /// theirs. Messages every participant has cleared are deleted, and their
+    /// attachments left for `MediaService::purge_orphaned`.
+    pub async fn clear_history(&self, conversation_id: Uuid, user_id: Uuid) -> AppResult<()> {
+        let cleared_before: Option<DateTime<Utc>> = sqlx::query_scalar(
             r#"
-            SELECT COUNT(*) FROM messages m
-            LEFT JOIN receipts r ON m.id = r.message_id AND r.user_id = $2 AND r.type = 'read'
-            WHERE m.conversation_id = $1 AND m.sender_id != $2 AND r.id IS NULL AND m.deleted_at IS NULL
+            UPDATE participants SET cleared_before = NOW(), marked_unread = false
+            WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL
+            RETURNING cleared_before
             "#,
         )
         .bind(conversation_id)
         .bind(user_id)
-        .fetch_one(&self.db)
-        .await?;
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::NotParticipant)?;
 
-        // Get last message
-        let last_message: Option<Message> = sqlx::query_as(
-            "SELECT * FROM messages WHERE conversation_id = $1 AND deleted_at IS NULL ORDER BY created_at DESC LIMIT 1",
+        let deleted: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            DELETE FROM messages m
+            WHERE m.conversation_id = $1 AND NOT EXISTS (
+                SELECT 1 FROM participants p
+                WHERE p.conversation_id = m.conversation_id AND p.left_at IS NULL
+                AND (p.cleared_before IS NULL OR p.cleared_before < m.created_at)
+            )
+            RETURNING m.id
+            "#,
         )
         .bind(conversation_id)
-        .fetch_optional(&self.db)
+        .fetch_all(&self.db)
         .await?;
 
-        Ok(ConversationWithDetails {
-            conversation,
-            participants: participants_with_users,
-            unread_count: unread_count.0,
-            last_message,
-        })
+        for message_id in &deleted {
+            self.remove_from_search_index(*message_id).await;
+        }
+
+        // Let the user's other devices drop the cleared messages too
+        let ws_message = WsMessage {
+            msg_type: "history_cleared".to_string(),
+            payload: serde_json::json!({
+                "conversation_id": conversation_id,
+                "cleared_before": cleared_before,
+            }),
+        };
+        self.redis
+            .publish_message(&user_id.to_string(), &serde_json::to_string(&ws_message)?)
+            .await?;
+
+        Ok(())
     }
 
-    /// Get user's conversations
-    pub async fn get_user_conversations(
-        &self,
-        user_id: Uuid,
-        limit: i32,
-        offset: i32,
-    ) -> AppResult<Vec<ConversationWithDetails>> {
-        let conversations: Vec<Conversation> = sqlx::query_as(
+    /// Delete a direct conversation and its messages for both participants.
+    /// Groups are left instead; Saved Messages is cleared.
+    pub async fn delete_conversation(&self, conversation_id: Uuid, user_id: Uuid) -> AppResult<()> {
+        let conversation_type: Option<ConversationType> = sqlx::query_scalar(
             r#"
-            SELECT c.* FROM conversations c
-            JOIN participants p ON c.id = p.conversation_id
-            WHERE p.user_id = $1 AND p.left_at IS NULL
-            ORDER BY COALESCE(c.last_message_at, c.created_at) DESC
-            LIMIT $2 OFFSET $3
+            SELECT c.type FROM conversations c
+            JOIN participants p ON p.conversation_id = c.id
+            WHERE c.id = $1 AND p.user_id = $2 AND p.left_at IS NULL
             "#,
         )
+        .bind(conversation_id)
         .bind(user_id)
-        .bind(limit)
-        .bind(offset)
-        .fetch_all(&self.db)
+        .fetch_optional(&self.db)
         .await?;
 
-        let mut result = Vec::with_capacity(conversations.len());
-        for conv in conversations {
-            let details = self.get_conversation(conv.id, user_id).await?;
-            result.push(details);
+        match conversation_type {
+            None => return Err(AppError::NotParticipant),
+            Some(ConversationType::Direct) => {}
+            Some(_) => {
+                return Err(AppError::Validation(
+                    "Only direct conversations can be deleted".to_string(),
+                ))
+            }
         }
 
-        Ok(result)
+        // Everyone who had it, including those who left, so their lists update
+        let participants: Vec<Uuid> =
+            sqlx::query_scalar("SELECT user_id FROM participants WHERE conversation_id = $1")
+                .bind(conversation_id)
+                .fetch_all(&self.db)
+                .await?;
+
+        // Messages, receipts and participants go with it
+        sqlx::query("DELETE FROM conversations WHERE id = $1")
+            .bind(conversation_id)
+            .execute(&self.db)
+            .await?;
+
+        if let Some(index) = &self.search {
+            if let Err(e) = index.clear(Some(conversation_id)).await {
+                tracing::warn!(
+                    "Failed to remove conversation {} from search index: {}",
+                    conversation_id,
+                    e
+                );
+            }
+        }
+
+        let ws_message = WsMessage {
+            msg_type: "conversation_deleted".to_string(),
+            payload: serde_json::json!({
+                "conversation_id": conversation_id,
+                "deleted_by": user_id,
+            }),
+        };
+        let msg_str = serde_json::to_string(&ws_message)?;
+        for participant_id in participants {
+            self.redis
+                .publish_message(&participant_id.to_string(), &msg_str)
+                .await?;
+        }
+
+        Ok(())
     }
 
-    /// Send a message
-    pub async fn send_message(
-        &self,
-        conversation_id: Uuid,
-        sender_id: Uuid,
-        message_type: MessageType,
-        content: Vec<u8>,
-        sticker_id: Option<Uuid>,
-        reply_to_id: Option<Uuid>,
-    ) -> AppResult<Message> {
-        // Check if sender is participant
-        let is_participant: Option<(i64,)> = sqlx::query_as(
-            "SELECT 1 FROM participants WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL",
+    /// Decline a pending message request by blocking its sender and leaving
+    /// the conversation. The sender is not told.
+    pub async fn block_request(&self, conversation_id: Uuid, user_id: Uuid) -> AppResult<()> {
+        let is_request: Option<bool> = sqlx::query_scalar(
+            "SELECT is_request FROM participants WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL",
         )
         .bind(conversation_id)
-        .bind(sender_id)
+        .bind(user_id)
         .fetch_optional(&self.db)
         .await?;
 
-        if is_participant.is_none() {
-            return Err(AppError::NotParticipant);
+        match is_request {
+            None => return Err(AppError::NotParticipant),
+            Some(false) => {
+                return Err(AppError::Validation(
+                    "Not a pending message request".to_string(),
+                ))
+            }
+            Some(true) => {}
         }
 
-        // Create message
-        let message: Message = sqlx::query_as(
-            r#"
-            INSERT INTO messages (id, conversation_id, sender_id, type, content, sticker_id, reply_to_id, status)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-            RETURNING *
-            "#,
+        let senders: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT user_id FROM participants WHERE conversation_id = $1 AND user_id != $2",
         )
-        .bind(Uuid::new_v4())
         .bind(conversation_id)
-        .bind(sender_id)
-        .bind(message_type)
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let contacts_service = ContactsService::new(self.db.clone());
+        for sender_id in senders {
+            contacts_service.block_contact(user_id, sender_id).await?;
+        }
+
+        sqlx::query(
+            "UPDATE participants SET left_at = NOW() WHERE conversation_id = $1 AND user_id = $2",
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Create a group conversation
+    pub async fn create_group_conversation(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        member_ids: Vec<Uuid>,
+        encryption: ConversationEncryption,
+    ) -> AppResult<ConversationWithDetails> {
+        let mut tx = self.db.begin().await?;
+
+        let conv_id = Uuid::new_v4();
+        let conversation: Conversation = sqlx::query_as(
+            r#"
+            INSERT INTO conversations (id, type, name, created_by, encryption)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#,
+        )
+        .bind(conv_id)
+        .bind(ConversationType::Group)
+        .bind(name)
+        .bind(user_id)
+        .bind(encryption)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Add creator as owner
+        sqlx::query(
+            r#"
+            INSERT INTO participants (id, conversation_id, user_id, role, joined_at)
+            VALUES ($1, $2, $3, $4, NOW())
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(conv_id)
+        .bind(user_id)
+        .bind(ParticipantRole::Owner)
+        .execute(&mut *tx)
+        .await?;
+
+        // Add members
+        let mut added = Vec::new();
+        for member_id in member_ids {
+            if member_id != user_id && !added.contains(&member_id) {
+                sqlx::query(
+                    r#"
+                    INSERT INTO participants (id, conversation_id, user_id, role, joined_at)
+                    VALUES ($1, $2, $3, $4, NOW())
+                    "#,
+                )
+                .bind(Uuid::new_v4())
+                .bind(conv_id)
+                .bind(member_id)
+                .bind(ParticipantRole::Member)
+                .execute(&mut *tx)
+                .await?;
+                added.push(member_id);
+            }
+        }
+
+        let mut events = vec![SystemEvent::GroupCreated {
+            name: name.to_string(),
+        }];
+        if !added.is_empty() {
+            events.push(SystemEvent::MembersAdded { user_ids: added });
+        }
+
+        let mut system_messages = Vec::with_capacity(events.len());
+        for event in events {
+            let message = self
+                .insert_system_message(&mut tx, conv_id, user_id, event)
+                .await?;
+            system_messages.push(message);
+        }
+
+        sqlx::query("UPDATE conversations SET last_message_at = NOW() WHERE id = $1")
+            .bind(conv_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        for message in &system_messages {
+            self.notify_participants(conv_id, user_id, message).await?;
+        }
+
+        self.get_conversation(conversation.id, user_id).await
+    }
+
+    /// Get conversation with details
+    pub async fn get_conversation(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+    ) -> AppResult<ConversationWithDetails> {
+        // Check if user is participant
+        let participant: Option<(bool, Option<DateTime<Utc>>)> = sqlx::query_as(
+            r#"
+            SELECT marked_unread, cleared_before FROM participants
+            WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        let (marked_unread, cleared_before) = participant.ok_or(AppError::NotParticipant)?;
+
+        let conversation: Option<Conversation> =
+            sqlx::query_as("SELECT * FROM conversations WHERE id = $1")
+                .bind(conversation_id)
+                .fetch_optional(&self.db)
+                .await?;
+
+        let conversation = conversation.ok_or(AppError::ConversationNotFound)?;
+
+        // Get a preview of the participants; large groups page through get_members
+        let participants: Vec<Participant> = sqlx::query_as(
+            r#"
+            SELECT * FROM participants
+            WHERE conversation_id = $1 AND left_at IS NULL
+            ORDER BY (role = 'member'), joined_at
+            LIMIT $2
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(PARTICIPANT_PREVIEW_LIMIT)
+        .fetch_all(&self.db)
+        .await?;
+        let participants_with_users = self.attach_users(participants, user_id).await?;
+
+        // Get unread count
+        let unread_count: (i64,) = sqlx::query_as(
+            r#"
+            SELECT COUNT(*) FROM messages m
+            LEFT JOIN receipts r ON m.id = r.message_id AND r.user_id = $2 AND r.type = 'read'
+            WHERE m.conversation_id = $1 AND m.sender_id != $2 AND r.id IS NULL AND m.deleted_at IS NULL
+            AND NOT m.is_shadowed AND ($3::timestamptz IS NULL OR m.created_at > $3)
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .bind(cleared_before)
+        .fetch_one(&self.db)
+        .await?;
+
+        // Get last message
+        let last_message: Option<Message> = sqlx::query_as(
+            r#"
+            SELECT * FROM messages
+            WHERE conversation_id = $1 AND deleted_at IS NULL
+            AND (NOT is_shadowed OR sender_id = $2)
+            AND ($3::timestamptz IS NULL OR created_at > $3)
+            ORDER BY created_at DESC LIMIT 1
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .bind(cleared_before)
+        .fetch_optional(&self.db)
+        .await?
+        .map(|message| self.present(message));
+
+        let account_deactivated = conversation.conversation_type == ConversationType::Direct
+            && participants_with_users
+                .iter()
+                .any(|p| p.participant.user_id != user_id && p.deactivated);
+
+        Ok(ConversationWithDetails {
+            member_count: conversation.participant_count.into(),
+            conversation,
+            participants: participants_with_users,
+            unread_count: unread_count.0,
+            marked_unread,
+            last_message,
+            account_deactivated,
+        })
+    }
+
+    /// Unread counts across every conversation the user is in, worked out from
+    /// read receipts the same way `get_conversation` does
+    pub async fn unread_counts(&self, user_id: Uuid) -> AppResult<Vec<(Uuid, i64)>> {
+        let counts: Vec<(Uuid, i64)> = sqlx::query_as(
+            r#"
+            SELECT p.conversation_id, COUNT(m.id) FROM participants p
+            LEFT JOIN messages m ON m.conversation_id = p.conversation_id
+                AND m.sender_id != $1 AND m.deleted_at IS NULL AND NOT m.is_shadowed
+                AND NOT EXISTS (
+                    SELECT 1 FROM receipts r
+                    WHERE r.message_id = m.id AND r.user_id = $1 AND r.type = 'read'
+                )
+                AND (p.cleared_before IS NULL OR m.created_at > p.cleared_before)
+            WHERE p.user_id = $1 AND p.left_at IS NULL
+            GROUP BY p.conversation_id
+            ORDER BY COUNT(m.id) DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// Page through a conversation's current members, optionally filtered by
+    /// role and by a username/display name search
+    pub async fn get_members(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        role: Option<ParticipantRole>,
+        query: Option<&str>,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<ParticipantWithUser>> {
+        let is_participant: Option<(i32,)> = sqlx::query_as(
+            "SELECT 1 FROM participants WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL",
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        if is_participant.is_none() {
+            return Err(AppError::NotParticipant);
+        }
+
+        let pattern = query.map(|q| format!("%{}%", escape_like(&q.to_lowercase())));
+
+        let participants: Vec<Participant> = sqlx::query_as(
+            r#"
+            SELECT p.* FROM participants p
+            JOIN users u ON u.id = p.user_id
+            WHERE p.conversation_id = $1 AND p.left_at IS NULL
+            AND ($2::participant_role IS NULL OR p.role = $2)
+            AND ($3::text IS NULL OR LOWER(u.username) LIKE $3 OR LOWER(u.display_name) LIKE $3)
+            ORDER BY (p.role = 'member'), p.joined_at, p.id
+            LIMIT $4 OFFSET $5
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(role)
+        .bind(pattern)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db)
+        .await?;
+
+        self.attach_users(participants, user_id).await
+    }
+
+    /// Load the users behind participants, as seen by `viewer_id`
+    async fn attach_users(
+        &self,
+        participants: Vec<Participant>,
+        viewer_id: Uuid,
+    ) -> AppResult<Vec<ParticipantWithUser>> {
+        let user_ids: Vec<Uuid> = participants.iter().map(|p| p.user_id).collect();
+        let users: Vec<User> = sqlx::query_as("SELECT * FROM users WHERE id = ANY($1)")
+            .bind(&user_ids)
+            .fetch_all(&self.db)
+            .await?;
+        let mut users: HashMap<Uuid, User> = users.into_iter().map(|u| (u.id, u)).collect();
+        let deactivated: HashSet<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM users WHERE id = ANY($1) AND deactivated_at IS NOT NULL",
+        )
+        .bind(&user_ids)
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .collect();
+
+        SocialGraphService::new(self.db.clone())
+            .redact_presence(viewer_id, users.values_mut())
+            .await?;
+
+        Ok(participants
+            .into_iter()
+            .map(|participant| ParticipantWithUser {
+                user: users.remove(&participant.user_id),
+                deactivated: deactivated.contains(&participant.user_id),
+                participant,
+            })
+            .collect())
+    }
+
+    /// Fail unless the user is an owner or admin of a group conversation
+    pub async fn require_group_admin(&self, conversation_id: Uuid, user_id: Uuid) -> AppResult<()> {
+        let membership: Option<(ConversationType, ParticipantRole)> = sqlx::query_as(
+            r#"
+            SELECT c.type, p.role FROM conversations c
+            JOIN participants p ON p.conversation_id = c.id
+            WHERE c.id = $1 AND p.user_id = $2 AND p.left_at IS NULL
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        match membership {
+            None => Err(AppError::NotParticipant),
+            Some((ConversationType::Direct | ConversationType::Saved, _)) => Err(
+                AppError::Validation("Not a group conversation".to_string()),
+            ),
+            Some((_, ParticipantRole::Member)) => Err(AppError::InsufficientPermissions),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Fail unless the user's role in a group allows `action`
+    pub async fn require_group_permission(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        action: GroupAction,
+    ) -> AppResult<()> {
+        let membership: Option<(ConversationType, ParticipantRole, Json<GroupPermissions>)> =
+            sqlx::query_as(
+                r#"
+                SELECT c.type, p.role, c.permissions FROM conversations c
+                JOIN participants p ON p.conversation_id = c.id
+                WHERE c.id = $1 AND p.user_id = $2 AND p.left_at IS NULL
+                "#,
+            )
+            .bind(conversation_id)
+            .bind(user_id)
+            .fetch_optional(&self.db)
+            .await?;
+
+        match membership {
+            None => Err(AppError::NotParticipant),
+            Some((ConversationType::Direct | ConversationType::Saved, _, _)) => Err(
+                AppError::Validation("Not a group conversation".to_string()),
+            ),
+            Some((_, role, Json(permissions))) if !permissions.allows(role, action) => {
+                Err(AppError::InsufficientPermissions)
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Change who may do what in a group (owners/admins only), posting a
+    /// system message with the resulting permissions
+    pub async fn update_permissions(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        update: GroupPermissionsUpdate,
+    ) -> AppResult<ConversationWithDetails> {
+        self.require_group_admin(conversation_id, user_id).await?;
+
+        let mut tx = self.db.begin().await?;
+
+        // Only the given keys are merged in, so the rest keep following the
+        // defaults
+        let (Json(previous), Json(permissions)): (Json<GroupPermissions>, Json<GroupPermissions>) =
+            sqlx::query_as(
+                r#"
+                UPDATE conversations c SET permissions = c.permissions || $2, updated_at = NOW()
+                FROM conversations old
+                WHERE c.id = $1 AND old.id = c.id
+                RETURNING old.permissions, c.permissions
+                "#,
+            )
+            .bind(conversation_id)
+            .bind(Json(&update))
+            .fetch_one(&mut *tx)
+            .await?;
+
+        if permissions == previous {
+            tx.commit().await?;
+            return self.get_conversation(conversation_id, user_id).await;
+        }
+
+        let message = self
+            .insert_system_message(
+                &mut tx,
+                conversation_id,
+                user_id,
+                SystemEvent::GroupPermissionsChanged { permissions },
+            )
+            .await?;
+
+        sqlx::query("UPDATE conversations SET last_message_at = NOW() WHERE id = $1")
+            .bind(conversation_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        self.notify_participants(conversation_id, user_id, &message)
+            .await?;
+
+        self.get_conversation(conversation_id, user_id).await
+    }
+
+    /// A group's administrative history, newest first (owners/admins only)
+    pub async fn get_events(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<ConversationEvent>> {
+        self.require_group_admin(conversation_id, user_id).await?;
+
+        let events = sqlx::query_as(
+            r#"
+            SELECT * FROM conversation_events
+            WHERE conversation_id = $1
+            ORDER BY created_at DESC, id
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(events)
+    }
+
+    /// Turn announcement mode on or off (owners/admins only), posting a system
+    /// message and an `announcement_mode` event
+    pub async fn set_announcement_mode(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        enabled: bool,
+    ) -> AppResult<ConversationWithDetails> {
+        self.require_group_admin(conversation_id, user_id).await?;
+
+        let mut tx = self.db.begin().await?;
+
+        let result = sqlx::query(
+            r#"
+            UPDATE conversations
+            SET announcement_only = $2, last_message_at = NOW(), updated_at = NOW()
+            WHERE id = $1 AND announcement_only != $2
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(enabled)
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            tx.commit().await?;
+            return self.get_conversation(conversation_id, user_id).await;
+        }
+
+        let message = self
+            .insert_system_message(
+                &mut tx,
+                conversation_id,
+                user_id,
+                SystemEvent::AnnouncementModeChanged { enabled },
+            )
+            .await?;
+
+        tx.commit().await?;
+
+        self.notify_participants(conversation_id, user_id, &message)
+            .await?;
+
+        // Lets clients lock or unlock the composer without parsing the system message
+        let ws_message = WsMessage {
+            msg_type: "announcement_mode".to_string(),
+            payload: serde_json::json!({
+                "conversation_id": conversation_id,
+                "enabled": enabled,
+                "actor_id": user_id,
+            }),
+        };
+        self.publish_to_participants(conversation_id, user_id, &ws_message)
+            .await?;
+
+        self.get_conversation(conversation_id, user_id).await
+    }
+
+    /// Edit group metadata, posting a system message for each change
+    pub async fn update_group(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        update: GroupUpdate,
+    ) -> AppResult<ConversationWithDetails> {
+        self.require_group_permission(conversation_id, user_id, GroupAction::ChangeInfo)
+            .await?;
+
+        let current: Conversation = sqlx::query_as("SELECT * FROM conversations WHERE id = $1")
+            .bind(conversation_id)
+            .fetch_one(&self.db)
+            .await?;
+
+        let clear_empty = |value: String| {
+            let value = value.trim().to_string();
+            (!value.is_empty()).then_some(value)
+        };
+
+        let name = update.name.map(|n| n.trim().to_string()).or(current.name.clone());
+        let description = update
+            .description
+            .map(clear_empty)
+            .unwrap_or(current.description.clone());
+        let rules = update.rules.map(clear_empty).unwrap_or(current.rules.clone());
+        let avatar_url = update.avatar_url.or(current.avatar_url.clone());
+
+        let mut events = Vec::new();
+        if name != current.name {
+            events.push(SystemEvent::GroupNameChanged { name: name.clone() });
+        }
+        if description != current.description {
+            events.push(SystemEvent::GroupDescriptionChanged {
+                description: description.clone(),
+            });
+        }
+        if rules != current.rules {
+            events.push(SystemEvent::GroupRulesChanged {
+                rules: rules.clone(),
+            });
+        }
+        if avatar_url != current.avatar_url {
+            events.push(SystemEvent::GroupAvatarChanged {
+                avatar_url: avatar_url.clone(),
+            });
+        }
+
+        if events.is_empty() {
+            return self.get_conversation(conversation_id, user_id).await;
+        }
+
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            r#"
+            UPDATE conversations
+            SET name = $2, description = $3, rules = $4, avatar_url = $5,
+                last_message_at = NOW(), updated_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(&name)
+        .bind(&description)
+        .bind(&rules)
+        .bind(&avatar_url)
+        .execute(&mut *tx)
+        .await?;
+
+        let mut system_messages = Vec::with_capacity(events.len());
+        for event in events {
+            let message = self
+                .insert_system_message(&mut tx, conversation_id, user_id, event)
+                .await?;
+            system_messages.push(message);
+        }
+
+        tx.commit().await?;
+
+        for message in &system_messages {
+            self.notify_participants(conversation_id, user_id, message)
+                .await?;
+        }
+
+        self.get_conversation(conversation_id, user_id).await
+    }
+
+    /// Add users to a group, re-admitting former members. Who may add is set
+    /// by the group's `add_members` permission.
+    pub async fn add_members(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        member_ids: Vec<Uuid>,
+    ) -> AppResult<ConversationWithDetails> {
+        self.require_group_permission(conversation_id, user_id, GroupAction::AddMembers)
+            .await?;
+
+        let mut tx = self.db.begin().await?;
+
+        let mut added = Vec::new();
+        for member_id in member_ids {
+            if member_id == user_id || added.contains(&member_id) {
+                continue;
+            }
+
+            // A no-op for users who are already active members
+            let result = sqlx::query(
+                r#"
+                INSERT INTO participants (id, conversation_id, user_id, role, joined_at)
+                SELECT $1, $2, u.id, $4, NOW() FROM users u WHERE u.id = $3
+                ON CONFLICT (conversation_id, user_id) DO UPDATE
+                SET role = EXCLUDED.role, joined_at = NOW(), left_at = NULL
+                WHERE participants.left_at IS NOT NULL
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(conversation_id)
+            .bind(member_id)
+            .bind(ParticipantRole::Member)
+            .execute(&mut *tx)
+            .await?;
+
+            if result.rows_affected() > 0 {
+                added.push(member_id);
+            }
+        }
+
+        if added.is_empty() {
+            tx.commit().await?;
+            return self.get_conversation(conversation_id, user_id).await;
+        }
+
+        let message = self
+            .insert_system_message(
+                &mut tx,
+                conversation_id,
+                user_id,
+                SystemEvent::MembersAdded { user_ids: added },
+            )
+            .await?;
+
+        sqlx::query("UPDATE conversations SET last_message_at = NOW() WHERE id = $1")
+            .bind(conversation_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        self.notify_participants(conversation_id, user_id, &message)
+            .await?;
+
+        self.get_conversation(conversation_id, user_id).await
+    }
+
+    /// Remove a member from a group, or leave it when `member_id` is the
+    /// caller. Removing others takes an owner/admin; owners cannot be removed
+    /// and cannot leave.
+    pub async fn remove_member(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        member_id: Uuid,
+    ) -> AppResult<()> {
+        let leaving = member_id == user_id;
+        if !leaving {
+            self.require_group_admin(conversation_id, user_id).await?;
+        }
+
+        let membership: Option<(ConversationType, ParticipantRole, bool)> = sqlx::query_as(
+            r#"
+            SELECT c.type, p.role, c.organization_team_id IS NOT NULL FROM conversations c
+            JOIN participants p ON p.conversation_id = c.id
+            WHERE c.id = $1 AND p.user_id = $2 AND p.left_at IS NULL
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(member_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        // Team group membership follows the organization directory
+        match membership {
+            None => return Err(AppError::NotParticipant),
+            Some((ConversationType::Direct | ConversationType::Saved, _, _)) => {
+                return Err(AppError::Validation("Not a group conversation".to_string()))
+            }
+            Some((_, ParticipantRole::Owner, _)) | Some((_, _, true)) => {
+                return Err(AppError::InsufficientPermissions)
+            }
+            Some(_) => {}
+        }
+
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            "UPDATE participants SET left_at = NOW() WHERE conversation_id = $1 AND user_id = $2",
+        )
+        .bind(conversation_id)
+        .bind(member_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let event = if leaving {
+            SystemEvent::MemberLeft {}
+        } else {
+            SystemEvent::MemberRemoved { user_id: member_id }
+        };
+        let message = self
+            .insert_system_message(&mut tx, conversation_id, user_id, event)
+            .await?;
+
+        sqlx::query("UPDATE conversations SET last_message_at = NOW() WHERE id = $1")
+            .bind(conversation_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        self.notify_participants(conversation_id, user_id, &message)
+            .await?;
+
+        // The removed member is no longer a participant but should see why
+        if !leaving {
+            let ws_message = WsMessage {
+                msg_type: "new_message".to_string(),
+                payload: serde_json::to_value(&message)?,
+            };
+            self.redis
+                .publish_message(&member_id.to_string(), &serde_json::to_string(&ws_message)?)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Create the group for an organization team. It has no owner or admins;
+    /// `sync_managed_group` sets its members.
+    pub async fn create_managed_group(
+        &self,
+        actor_id: Uuid,
+        name: &str,
+        team_id: Uuid,
+    ) -> AppResult<Uuid> {
+        let mut tx = self.db.begin().await?;
+
+        let conv_id = Uuid::new_v4();
+        sqlx::query(
+            r#"
+            INSERT INTO conversations (id, type, name, created_by, organization_team_id)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(conv_id)
+        .bind(ConversationType::Group)
+        .bind(name)
+        .bind(actor_id)
+        .bind(team_id)
+        .execute(&mut *tx)
+        .await?;
+
+        self.insert_system_message(
+            &mut tx,
+            conv_id,
+            actor_id,
+            SystemEvent::GroupCreated {
+                name: name.to_string(),
+            },
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(conv_id)
+    }
+
+    /// Make a managed group's active members exactly `member_ids`, posting the
+    /// usual system messages. Returns how many were added and removed.
+    pub async fn sync_managed_group(
+        &self,
+        conversation_id: Uuid,
+        actor_id: Uuid,
+        member_ids: &[Uuid],
+    ) -> AppResult<(usize, usize)> {
+        let current: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT user_id FROM participants WHERE conversation_id = $1 AND left_at IS NULL",
+        )
+        .bind(conversation_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut added: Vec<Uuid> = Vec::new();
+        for id in member_ids {
+            if !current.contains(id) && !added.contains(id) {
+                added.push(*id);
+            }
+        }
+        let removed: Vec<Uuid> = current
+            .into_iter()
+            .filter(|id| !member_ids.contains(id))
+            .collect();
+
+        if added.is_empty() && removed.is_empty() {
+            return Ok((0, 0));
+        }
+
+        let mut tx = self.db.begin().await?;
+        let mut system_messages = Vec::new();
+
+        if !added.is_empty() {
+            for member_id in &added {
+                sqlx::query(
+                    r#"
+                    INSERT INTO participants (id, conversation_id, user_id, role, joined_at)
+                    VALUES ($1, $2, $3, $4, NOW())
+                    ON CONFLICT (conversation_id, user_id) DO UPDATE
+                    SET role = EXCLUDED.role, joined_at = NOW(), left_at = NULL
+                    "#,
+                )
+                .bind(Uuid::new_v4())
+                .bind(conversation_id)
+                .bind(member_id)
+                .bind(ParticipantRole::Member)
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            let event = SystemEvent::MembersAdded {
+                user_ids: added.clone(),
+            };
+            system_messages.push(
+                self.insert_system_message(&mut tx, conversation_id, actor_id, event)
+                    .await?,
+            );
+        }
+
+        let mut removal_messages = Vec::new();
+        for member_id in &removed {
+            sqlx::query(
+                r#"
+                UPDATE participants SET left_at = NOW()
+                WHERE conversation_id = $1 AND user_id = $2
+                "#,
+            )
+            .bind(conversation_id)
+            .bind(member_id)
+            .execute(&mut *tx)
+            .await?;
+
+            let event = SystemEvent::MemberRemoved {
+                user_id: *member_id,
+            };
+            let message = self
+                .insert_system_message(&mut tx, conversation_id, actor_id, event)
+                .await?;
+            removal_messages.push((*member_id, message));
+        }
+
+        sqlx::query("UPDATE conversations SET last_message_at = NOW() WHERE id = $1")
+            .bind(conversation_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        for message in &system_messages {
+            self.notify_participants(conversation_id, actor_id, message)
+                .await?;
+        }
+
+        // Removed members are no longer participants but should see why
+        for (member_id, message) in &removal_messages {
+            self.notify_participants(conversation_id, actor_id, message)
+                .await?;
+
+            let ws_message = WsMessage {
+                msg_type: "new_message".to_string(),
+                payload: serde_json::to_value(message)?,
+            };
+            self.redis
+                .publish_message(&member_id.to_string(), &serde_json::to_string(&ws_message)?)
+                .await?;
+        }
+
+        Ok((added.len(), removed.len()))
+    }
+
+    /// Get user's conversations
+    pub async fn get_user_conversations(
+        &self,
+        user_id: Uuid,
+        filter: ConversationFilter,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<ConversationWithDetails>> {
+        let conversations = self
+            .list_conversations(user_id, filter, limit, offset)
+            .await?;
+
+        let mut result = Vec::with_capacity(conversations.len());
+        for conv in conversations {
+            let details = self.get_conversation(conv.id, user_id).await?;
+            result.push(details);
+        }
+
+        Ok(result)
+    }
+
+    /// User's conversations, most recently active first, without details
+    pub async fn list_conversations(
+        &self,
+        user_id: Uuid,
+        filter: ConversationFilter,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<Conversation>> {
+        let conversations: Vec<Conversation> = sqlx::query_as(
+            r#"
+            SELECT c.* FROM conversations c
+            JOIN participants p ON c.id = p.conversation_id
+            WHERE p.user_id = $1 AND p.left_at IS NULL AND p.is_request = $4
+            ORDER BY COALESCE(c.last_message_at, c.created_at) DESC, c.id
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(limit)
+        .bind(offset)
+        .bind(filter == ConversationFilter::Requests)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(conversations)
+    }
+
+    /// The user's conversations whose group name, or the other participant's
+    /// username or display name in a direct one, match, best match first
+    pub async fn search_conversations(
+        &self,
+        user_id: Uuid,
+        query: &str,
+        limit: i32,
+    ) -> AppResult<Vec<ConversationWithDetails>> {
+        let query = query.to_lowercase();
+        let search_pattern = format!("%{}%", escape_like(&query));
+
+        let conversation_ids: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT c.id FROM conversations c
+            JOIN participants p ON p.conversation_id = c.id
+            LEFT JOIN participants op ON c.type = 'direct'
+                AND op.conversation_id = c.id AND op.user_id != $1
+            LEFT JOIN users u ON u.id = op.user_id
+            WHERE p.user_id = $1 AND p.left_at IS NULL AND NOT p.is_request
+            AND (
+                LOWER(c.name) LIKE $3 OR LOWER(u.username) LIKE $3
+                OR LOWER(u.display_name) LIKE $3
+                OR LOWER(c.name) % $2 OR LOWER(u.username) % $2
+                OR LOWER(u.display_name) % $2
+            )
+            ORDER BY GREATEST(
+                similarity(LOWER(c.name), $2),
+                similarity(LOWER(u.username), $2),
+                similarity(LOWER(u.display_name), $2)
+            ) DESC, COALESCE(c.last_message_at, c.created_at) DESC, c.id
+            LIMIT $4
+            "#,
+        )
+        .bind(user_id)
+        .bind(&query)
+        .bind(&search_pattern)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut result = Vec::with_capacity(conversation_ids.len());
+        for conversation_id in conversation_ids {
+            result.push(self.get_conversation(conversation_id, user_id).await?);
+        }
+
+        Ok(result)
+    }
+
+    /// The conversations the user sends to most, recent sends weighing more,
+    /// for share sheets and shortcuts
+    pub async fn get_quick_conversations(
+        &self,
+        user_id: Uuid,
+        limit: i32,
+    ) -> AppResult<Vec<ConversationWithDetails>> {
+        let conversation_ids: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT p.conversation_id FROM participants p
+            WHERE p.user_id = $1 AND p.left_at IS NULL AND NOT p.is_request
+            AND p.interaction_score > 0
+            ORDER BY p.interaction_score
+                * POWER(0.5, EXTRACT(EPOCH FROM NOW() - p.interacted_at) / $2) DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(INTERACTION_HALF_LIFE)
+        .bind(limit)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut result = Vec::with_capacity(conversation_ids.len());
+        for conversation_id in conversation_ids {
+            result.push(self.get_conversation(conversation_id, user_id).await?);
+        }
+
+        Ok(result)
+    }
+
+    /// Send a message
+    #[allow(clippy::too_many_arguments)]
+    pub async fn send_message(
+        &self,
+        conversation_id: Uuid,
+        sender_id: Uuid,
+        message_type: MessageType,
+        payload: MessagePayload,
+        sticker_id: Option<Uuid>,
+        reply_to_id: Option<Uuid>,
+        view_once: bool,
+    ) -> AppResult<Message> {
+        let payload = message_policy::encode_payload(message_type, payload)?;
+        if view_once && !matches!(message_type, MessageType::Image | MessageType::Video) {
+            return Err(AppError::Validation(
+                "Only images and videos can be view-once".to_string(),
+            ));
+        }
+
+        self.check_content(message_type, &payload)?;
+        if let Some(attachment_id) = payload.attachment_id {
+            self.check_attachment(attachment_id, sender_id, message_type, view_once)
+                .await?;
+        }
+
+        // Check if sender is participant
+        let membership: Option<(
+            ConversationEncryption,
+            ConversationType,
+            ParticipantRole,
+            Json<GroupPermissions>,
+            bool,
+        )> = sqlx::query_as(
+            r#"
+            SELECT c.encryption, c.type, p.role, c.permissions, c.announcement_only
+            FROM participants p
+            JOIN conversations c ON c.id = p.conversation_id
+            WHERE p.conversation_id = $1 AND p.user_id = $2 AND p.left_at IS NULL
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(sender_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        let Some((encryption, conversation_type, role, Json(permissions), announcement_only)) =
+            membership
+        else {
+            return Err(AppError::NotParticipant);
+        };
+        if conversation_type == ConversationType::Group {
+            if announcement_only && role == ParticipantRole::Member {
+                return Err(AppError::AnnouncementOnly);
+            }
+            check_group_permissions(&permissions, role, message_type)?;
+        }
+        check_encryption(encryption, message_type, &payload)?;
+
+        // Replying to a message request accepts it
+        sqlx::query(
+            "UPDATE participants SET is_request = false WHERE conversation_id = $1 AND user_id = $2 AND is_request",
+        )
+        .bind(conversation_id)
+        .bind(sender_id)
+        .execute(&self.db)
+        .await?;
+
+        let shadowed = self
+            .screen_message(sender_id, conversation_id, &payload.content)
+            .await?;
+
+        self.store_message(
+            conversation_id,
+            sender_id,
+            message_type,
+            payload,
+            sticker_id,
+            reply_to_id,
+            shadowed,
+            view_once,
+        )
+        .await
+    }
+
+    /// Content within `MESSAGE_MAX_*_BYTES` for its type
+    pub fn check_content(
+        &self,
+        message_type: MessageType,
+        payload: &StoredPayload,
+    ) -> AppResult<()> {
+        message_policy::check_content(&self.config.message_limits, message_type, payload)
+    }
+
+    /// Attachments go on media messages of their type, and only their
+    /// uploader may send them
+    async fn check_attachment(
+        &self,
+        attachment_id: Uuid,
+        sender_id: Uuid,
+        message_type: MessageType,
+        view_once: bool,
+    ) -> AppResult<()> {
+        if !matches!(
+            message_type,
+            MessageType::Image | MessageType::Video | MessageType::Audio | MessageType::File
+        ) {
+            return Err(AppError::Validation(
+                "Only media messages can carry an attachment".to_string(),
+            ));
+        }
+
+        // View-once media is stored inline so it can be purged once opened
+        if view_once {
+            return Err(AppError::Validation(
+                "View-once media cannot be sent as an attachment".to_string(),
+            ));
+        }
+
+        let uploaded: Option<(String, i64)> = sqlx::query_as(
+            "SELECT content_type, size FROM attachments WHERE id = $1 AND uploader_id = $2",
+        )
+        .bind(attachment_id)
+        .bind(sender_id)
+        .fetch_optional(&self.db)
+        .await?;
+        let (content_type, size) = uploaded.ok_or(AppError::AttachmentNotFound)?;
+
+        message_policy::check_attachment(
+            &self.config.message_limits,
+            message_type,
+            &content_type,
+            size as usize,
+        )
+    }
+
+    /// Run an outgoing message through spam control and, when enabled,
+    /// content moderation. Returns whether the message should be shadowed.
+    pub async fn screen_message(
+        &self,
+        sender_id: Uuid,
+        conversation_id: Uuid,
+        content: &[u8],
+    ) -> AppResult<bool> {
+        // Enforce flood and duplicate-content limits before anything is stored
+        let verdict = SpamGuard::new(
+            self.db.clone(),
+            self.redis.clone(),
+            self.config.spam.clone(),
+        )
+        .check_message(sender_id, conversation_id, content)
+        .await?;
+        let mut shadowed = verdict == SpamVerdict::Shadow;
+
+        // Quarantined content is kept out of view like shadow-limited messages
+        if self.config.moderation.scan_messages {
+            let verdict = ModerationPipeline::new(
+                self.db.clone(),
+                self.redis.clone(),
+                &self.config.moderation,
+            )
+            .review(&ContentSubject {
+                source: ContentSource::Message,
+                uploader_id: sender_id,
+                conversation_id: Some(conversation_id),
+                content_type: None,
+                data: content,
+            })
+            .await?;
+            shadowed |= verdict.action == ModerationAction::Quarantine;
+        }
+
+        Ok(shadowed)
+    }
+
+    /// Store an already screened message and notify the other participants
+    #[allow(clippy::too_many_arguments)]
+    pub async fn store_message(
+        &self,
+        conversation_id: Uuid,
+        sender_id: Uuid,
+        message_type: MessageType,
+        payload: StoredPayload,
+        sticker_id: Option<Uuid>,
+        reply_to_id: Option<Uuid>,
+        shadowed: bool,
+        view_once: bool,
+    ) -> AppResult<Message> {
+        let mut tx = self.db.begin().await?;
+        let message = self
+            .insert_message(
+                &mut tx,
+                conversation_id,
+                sender_id,
+                message_type,
+                payload,
+                sticker_id,
+                reply_to_id,
+                shadowed,
+                view_once,
+            )
+            .await?;
+        tx.commit().await?;
+
+        self.announce_message(&message, shadowed).await?;
+        Ok(message)
+    }
+
+    /// Write an already screened message within the caller's transaction;
+    /// nobody hears of it until `announce_message` runs after the commit
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_message(
+        &self,
+        conn: &mut PgConnection,
+        conversation_id: Uuid,
+        sender_id: Uuid,
+        message_type: MessageType,
+        payload: StoredPayload,
+        sticker_id: Option<Uuid>,
+        reply_to_id: Option<Uuid>,
+        shadowed: bool,
+        view_once: bool,
+    ) -> AppResult<Message> {
+        let StoredPayload {
+            content,
+            caption,
+            encrypted,
+            attachment_id,
+            franking_tag,
+        } = payload;
+        let (content, padded) = self.pad(content);
+        let message_id = Uuid::new_v4();
+        let franking_signature = franking_tag.as_deref().map(|tag| {
+            Franking::new(&self.config).sign(message_id, conversation_id, sender_id, tag)
+        });
+
+        // Create message
+        let message: Message = sqlx::query_as(
+            r#"
+            INSERT INTO messages (id, conversation_id, sender_id, type, content, sticker_id,
+                reply_to_id, status, is_shadowed, view_once, attachment_id, padded, caption,
+                encrypted, franking_tag, franking_signature)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+            RETURNING *
+            "#,
+        )
+        .bind(message_id)
+        .bind(conversation_id)
+        .bind(sender_id)
+        .bind(message_type)
         .bind(&content)
         .bind(sticker_id)
         .bind(reply_to_id)
         .bind(MessageStatus::Sent)
-        .fetch_one(&self.db)
+        .bind(shadowed)
+        .bind(view_once)
+        .bind(attachment_id)
+        .bind(padded)
+        .bind(caption)
+        .bind(encrypted)
+        .bind(franking_tag)
+        .bind(franking_signature)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        // Shadowed messages look sent to the sender but reach no one else
+        if shadowed {
+            return Ok(self.present(message));
+        }
+
+        // Count the send toward its pack's analytics
+        if let (MessageType::Sticker, Some(sticker_id)) = (message_type, sticker_id) {
+            sqlx::query(
+                r#"
+                INSERT INTO sticker_sends_daily (sticker_id, pack_id, day, sends)
+                SELECT id, pack_id, (NOW() AT TIME ZONE 'UTC')::date, 1 FROM stickers WHERE id = $1
+                ON CONFLICT (sticker_id, day) DO UPDATE SET sends = sticker_sends_daily.sends + 1
+                "#,
+            )
+            .bind(sticker_id)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        // Update conversation last_message_at
+        sqlx::query("UPDATE conversations SET last_message_at = NOW(), updated_at = NOW() WHERE id = $1")
+            .bind(conversation_id)
+            .execute(&mut *conn)
+            .await?;
+
+        // Decay the sender's score to now, then count this message
+        sqlx::query(
+            r#"
+            UPDATE participants
+            SET interaction_score = interaction_score
+                    * POWER(0.5, EXTRACT(EPOCH FROM NOW() - COALESCE(interacted_at, NOW())) / $3)
+                    + 1,
+                interacted_at = NOW()
+            WHERE conversation_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(sender_id)
+        .bind(INTERACTION_HALF_LIFE)
+        .execute(&mut *conn)
         .await?;
 
-        // Update conversation last_message_at
-        sqlx::query("UPDATE conversations SET last_message_at = NOW(), updated_at = NOW() WHERE id = $1")
-            .bind(conversation_id)
-            .execute(&self.db)
-            .await?;
+        Ok(self.present(message))
+    }
+
+    /// Index, record and deliver a message once `insert_message` committed
+    pub async fn announce_message(&self, message: &Message, shadowed: bool) -> AppResult<()> {
+        self.update_search_index(message).await;
+
+        EventLog::new(self.db.clone(), &self.config.analytics)
+            .record(DomainEvent::MessageSent {
+                sender_id: message.sender_id,
+                conversation_id: message.conversation_id,
+                message_type: message.message_type,
+                has_attachment: message.attachment_id.is_some(),
+                is_reply: message.reply_to_id.is_some(),
+                view_once: message.view_once,
+            })
+            .await;
+
+        if shadowed {
+            return Ok(());
+        }
+
+        // Queue per-device delivery before publishing, so early acks find it
+        self.enqueue_deliveries(message).await?;
 
         // Notify participants
-        self.notify_participants(conversation_id, sender_id, &message)
+        self.notify_participants(message.conversation_id, message.sender_id, message)
             .await?;
 
-        Ok(message)
+        if rand::random::<f64>() < self.config.delivery.latency_sample_rate {
+            self.record_latency_sample(message).await;
+        }
+
+        self.spawn_auto_translate(message);
+        self.spawn_bridge_relay(message);
+
+        Ok(())
     }
 
     /// Get messages for a conversation
@@ -322,25 +1896,27 @@ impl MessagingService {
         offset: i32,
         before: Option<Uuid>,
     ) -> AppResult<Vec<Message>> {
-        // Check if user is participant
-        let is_participant: Option<(i64,)> = sqlx::query_as(
-            "SELECT 1 FROM participants WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL",
+        // Check if user is participant, and how much of the history they cleared
+        let cleared_before: Option<DateTime<Utc>> = sqlx::query_scalar(
+            r#"
+            SELECT cleared_before FROM participants
+            WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL
+            "#,
         )
         .bind(conversation_id)
         .bind(user_id)
         .fetch_optional(&self.db)
-        .await?;
-
-        if is_participant.is_none() {
-            return Err(AppError::NotParticipant);
-        }
+        .await?
+        .ok_or(AppError::NotParticipant)?;
 
         let messages: Vec<Message> = if let Some(before_id) = before {
             sqlx::query_as(
                 r#"
                 SELECT * FROM messages
                 WHERE conversation_id = $1 AND deleted_at IS NULL
+                AND (NOT is_shadowed OR sender_id = $5)
                 AND created_at < (SELECT created_at FROM messages WHERE id = $4)
+                AND ($6::timestamptz IS NULL OR created_at > $6)
                 ORDER BY created_at DESC
                 LIMIT $2 OFFSET $3
                 "#,
@@ -349,6 +1925,8 @@ impl MessagingService {
             .bind(limit)
             .bind(offset)
             .bind(before_id)
+            .bind(user_id)
+            .bind(cleared_before)
             .fetch_all(&self.db)
             .await?
         } else {
@@ -356,6 +1934,8 @@ impl MessagingService {
                 r#"
                 SELECT * FROM messages
                 WHERE conversation_id = $1 AND deleted_at IS NULL
+                AND (NOT is_shadowed OR sender_id = $4)
+                AND ($5::timestamptz IS NULL OR created_at > $5)
                 ORDER BY created_at DESC
                 LIMIT $2 OFFSET $3
                 "#,
@@ -363,80 +1943,149 @@ impl MessagingService {
             .bind(conversation_id)
             .bind(limit)
             .bind(offset)
+            .bind(user_id)
+            .bind(cleared_before)
             .fetch_all(&self.db)
             .await?
         };
 
-        Ok(messages)
+        let urls = MediaUrls::new(&self.config);
+        Ok(messages
+            .into_iter()
+            .map(|message| urls.sign_message(message.decode().redact_view_once()))
+            .collect())
     }
 
     /// Mark message as delivered
     pub async fn mark_as_delivered(&self, message_id: Uuid, user_id: Uuid) -> AppResult<()> {
+        self.mark_many_as_delivered(&[message_id], user_id).await?;
+        Ok(())
+    }
+
+    /// Mark message as read
+    pub async fn mark_as_read(&self, message_id: Uuid, user_id: Uuid) -> AppResult<()> {
+        self.mark_many_as_read(&[message_id], user_id).await?;
+        Ok(())
+    }
+
+    /// Reading any message in a conversation the user marked unread clears
+    /// the mark
+    async fn clear_marked_unread(&self, message_ids: &[Uuid], user_id: Uuid) -> AppResult<()> {
         sqlx::query(
             r#"
-            INSERT INTO receipts (id, message_id, user_id, type)
-            VALUES ($1, $2, $3, $4)
-            ON CONFLICT (message_id, user_id, type) DO NOTHING
+            UPDATE participants p SET marked_unread = false
+            FROM messages m
+            WHERE m.id = ANY($1) AND p.conversation_id = m.conversation_id
+            AND p.user_id = $2 AND p.marked_unread
             "#,
         )
-        .bind(Uuid::new_v4())
-        .bind(message_id)
+        .bind(message_ids)
         .bind(user_id)
-        .bind(ReceiptType::Delivered)
-        .execute(&self.db)
-        .await?;
-
-        // Update message status if this was the last recipient
-        sqlx::query(
-            "UPDATE messages SET status = 'delivered' WHERE id = $1 AND status = 'sent'",
-        )
-        .bind(message_id)
         .execute(&self.db)
         .await?;
 
         Ok(())
     }
 
-    /// Mark message as read
-    pub async fn mark_as_read(&self, message_id: Uuid, user_id: Uuid) -> AppResult<()> {
-        // Also mark as delivered if not already
-        sqlx::query(
+    /// Mark many messages as delivered in one statement. Messages the user
+    /// cannot see or sent themselves are skipped. Returns how many were acked.
+    pub async fn mark_many_as_delivered(
+        &self,
+        message_ids: &[Uuid],
+        user_id: Uuid,
+    ) -> AppResult<u64> {
+        self.insert_receipts(message_ids, user_id, &[ReceiptType::Delivered])
+            .await
+    }
+
+    /// Mark many messages as read (and delivered) in one statement
+    pub async fn mark_many_as_read(&self, message_ids: &[Uuid], user_id: Uuid) -> AppResult<u64> {
+        let marked = self
+            .insert_receipts(
+                message_ids,
+                user_id,
+                &[ReceiptType::Delivered, ReceiptType::Read],
+            )
+            .await?;
+
+        self.clear_marked_unread(message_ids, user_id).await?;
+
+        Ok(marked)
+    }
+
+    async fn insert_receipts(
+        &self,
+        message_ids: &[Uuid],
+        user_id: Uuid,
+        receipt_types: &[ReceiptType],
+    ) -> AppResult<u64> {
+        let mut tx = self.db.begin().await?;
+
+        let visible: Vec<(Uuid, bool, Uuid)> = sqlx::query_as(
             r#"
-            INSERT INTO receipts (id, message_id, user_id, type)
-            VALUES ($1, $2, $3, $4)
-            ON CONFLICT (message_id, user_id, type) DO NOTHING
+            SELECT m.id, p.is_request, m.sender_id FROM messages m
+            JOIN participants p ON p.conversation_id = m.conversation_id
+                AND p.user_id = $2 AND p.left_at IS NULL
+            WHERE m.id = ANY($1) AND m.sender_id != $2 AND m.deleted_at IS NULL
             "#,
         )
-        .bind(Uuid::new_v4())
-        .bind(message_id)
+        .bind(message_ids)
         .bind(user_id)
-        .bind(ReceiptType::Delivered)
-        .execute(&self.db)
+        .fetch_all(&mut *tx)
         .await?;
+        let sender_ids: Vec<Uuid> = visible.iter().map(|(_, _, sender_id)| *sender_id).collect();
+        let allowed = SocialGraphService::new(self.db.clone())
+            .interacting(&[user_id], &sender_ids)
+            .await?;
 
+        let visible_ids: Vec<Uuid> = visible.iter().map(|(id, _, _)| *id).collect();
         sqlx::query(
             r#"
-            INSERT INTO receipts (id, message_id, user_id, type)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO receipts (message_id, user_id, type)
+            SELECT m.id, $2, t.type
+            FROM UNNEST($1::uuid[]) AS m(id)
+            CROSS JOIN UNNEST($3::receipt_type[]) AS t(type)
             ON CONFLICT (message_id, user_id, type) DO NOTHING
             "#,
         )
-        .bind(Uuid::new_v4())
-        .bind(message_id)
+        .bind(&visible_ids)
         .bind(user_id)
-        .bind(ReceiptType::Read)
-        .execute(&self.db)
+        .bind(receipt_types)
+        .execute(&mut *tx)
         .await?;
 
-        // Update message status
+        let read = receipt_types.contains(&ReceiptType::Read);
+
+        // Read state isn't shown to the sender of a message request until
+        // it is accepted, nor to anyone the reader may not interact with;
+        // those messages only show as delivered, and the receipts above
+        // still count for unread badges
+        let statuses: Vec<MessageStatus> = visible
+            .iter()
+            .map(|(_, is_request, sender_id)| {
+                if read && !is_request && allowed.contains(&(user_id, *sender_id)) {
+                    MessageStatus::Read
+                } else {
+                    MessageStatus::Delivered
+                }
+            })
+            .collect();
+
         sqlx::query(
-            "UPDATE messages SET status = 'read' WHERE id = $1 AND status IN ('sent', 'delivered')",
+            r#"
+            UPDATE messages m SET status = u.status
+            FROM UNNEST($1::uuid[], $2::message_status[]) AS u(id, status)
+            WHERE m.id = u.id AND m.status IN ('sent', 'delivered') AND m.status < u.status
+            "#,
         )
-        .bind(message_id)
-        .execute(&self.db)
+        .bind(&visible_ids)
+        .bind(&statuses)
+        .execute(&mut *tx)
         .await?;
 
-        Ok(())
+        tx.commit().await?;
+
+        Ok(visible_ids.len() as u64)
     }
 
     /// Delete a message (soft delete)
@@ -453,37 +2102,183 @@ impl MessagingService {
             return Err(AppError::MessageNotFound);
         }
 
+        self.remove_from_search_index(message_id).await;
+
         Ok(())
     }
 
-    /// Broadcast typing indicator
+    /// Replace the content of one of the sender's own messages
+    pub async fn edit_message(
+        &self,
+        message_id: Uuid,
+        user_id: Uuid,
+        payload: MessagePayload,
+    ) -> AppResult<Message> {
+        let payload = message_policy::encode_payload(MessageType::Text, payload)?;
+        self.check_content(MessageType::Text, &payload)?;
+
+        let conversation: Option<(Uuid, ConversationEncryption)> = sqlx::query_as(
+            r#"
+            SELECT c.id, c.encryption FROM messages m
+            JOIN conversations c ON c.id = m.conversation_id
+            WHERE m.id = $1
+            "#,
+        )
+        .bind(message_id)
+        .fetch_optional(&self.db)
+        .await?;
+        let Some((conversation_id, encryption)) = conversation else {
+            return Err(AppError::MessageNotFound);
+        };
+        check_encryption(encryption, MessageType::Text, &payload)?;
+
+        // The edit commits to its own plaintext
+        let franking_signature = payload.franking_tag.as_deref().map(|tag| {
+            Franking::new(&self.config).sign(message_id, conversation_id, user_id, tag)
+        });
+        let (content, padded) = self.pad(payload.content);
+        let message: Option<Message> = sqlx::query_as(
+            r#"
+            UPDATE messages SET content = $3, padded = $4, encrypted = $5, franking_tag = $6,
+                franking_signature = $7, edited_at = NOW()
+            WHERE id = $1 AND sender_id = $2 AND deleted_at IS NULL AND type = 'text'
+            RETURNING *
+            "#,
+        )
+        .bind(message_id)
+        .bind(user_id)
+        .bind(&content)
+        .bind(padded)
+        .bind(payload.encrypted)
+        .bind(&payload.franking_tag)
+        .bind(franking_signature)
+        .fetch_optional(&self.db)
+        .await?;
+
+        let message = self.present(message.ok_or(AppError::MessageNotFound)?);
+
+        self.update_search_index(&message).await;
+
+        // Cached translations are of the old text
+        sqlx::query("DELETE FROM message_translations WHERE message_id = $1")
+            .bind(message_id)
+            .execute(&self.db)
+            .await?;
+
+        let shadowed: bool = sqlx::query_scalar("SELECT is_shadowed FROM messages WHERE id = $1")
+            .bind(message_id)
+            .fetch_one(&self.db)
+            .await?;
+
+        if !shadowed {
+            let ws_message = WsMessage {
+                msg_type: "message_edited".to_string(),
+                payload: serde_json::to_value(&message)?,
+            };
+            self.publish_to_participants(message.conversation_id, user_id, &ws_message)
+                .await?;
+
+            self.spawn_auto_translate(&message);
+        }
+
+        Ok(message)
+    }
+
+    /// Record that the user started or stopped typing, then send the
+    /// conversation's typers. Typing lapses after `WS_TYPING_TTL` unless sent
+    /// again.
     pub async fn broadcast_typing(
         &self,
         conversation_id: Uuid,
         user_id: Uuid,
         is_typing: bool,
     ) -> AppResult<()> {
-        let participants: Vec<(Uuid,)> = sqlx::query_as(
-            "SELECT user_id FROM participants WHERE conversation_id = $1 AND user_id != $2 AND left_at IS NULL",
+        // Typing would leak presence before a message request is accepted
+        let is_request: Option<bool> = sqlx::query_scalar(
+            "SELECT is_request FROM participants WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL",
         )
         .bind(conversation_id)
         .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        if is_request != Some(false) {
+            return Ok(());
+        }
+
+        let conversation = conversation_id.to_string();
+        let user = user_id.to_string();
+        if is_typing {
+            let ttl = self.config.websocket.typing_ttl;
+            let expires_at = Utc::now().timestamp_millis() + ttl.as_millis() as i64;
+            self.redis
+                .set_typing(&conversation, &user, expires_at, ttl)
+                .await?;
+        } else {
+            self.redis.clear_typing(&conversation, &user).await?;
+        }
+
+        self.send_typing_users(conversation_id).await
+    }
+
+    /// Send everyone in the conversation who is typing as one `typing_users`
+    /// event, at most once per `WS_TYPING_UPDATE_INTERVAL`. An update that
+    /// comes too soon is held back for `flush_typing`, which also announces
+    /// typers lapsing.
+    pub async fn send_typing_users(&self, conversation_id: Uuid) -> AppResult<()> {
+        let conversation = conversation_id.to_string();
+        let interval = self.config.websocket.typing_update_interval;
+        let now = Utc::now().timestamp_millis();
+
+        if !self
+            .redis
+            .try_throttle(&format!("typing:{}", conversation), interval)
+            .await?
+        {
+            let due = now + interval.as_millis() as i64;
+            return self.redis.schedule_typing(&conversation, due).await;
+        }
+
+        let typers = self.redis.get_typing(&conversation, now).await?;
+        if let Some(next_expiry) = typers.iter().map(|(_, expires_at)| *expires_at).min() {
+            self.redis
+                .schedule_typing(&conversation, next_expiry)
+                .await?;
+        }
+
+        let participants: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT user_id FROM participants WHERE conversation_id = $1 AND left_at IS NULL",
+        )
+        .bind(conversation_id)
         .fetch_all(&self.db)
         .await?;
 
-        let message = WsMessage {
-            msg_type: "typing".to_string(),
-            payload: serde_json::json!({
-                "conversation_id": conversation_id,
-                "user_id": user_id,
-                "is_typing": is_typing,
-                "timestamp": Utc::now().to_rfc3339()
-            }),
-        };
+        // Each participant only hears about typers who may interact with them
+        let typer_ids: Vec<Uuid> = typers
+            .iter()
+            .filter_map(|(user_id, _)| user_id.parse().ok())
+            .collect();
+        let allowed = SocialGraphService::new(self.db.clone())
+            .interacting(&typer_ids, &participants)
+            .await?;
 
-        let msg_str = serde_json::to_string(&message)?;
+        let timestamp = Utc::now().to_rfc3339();
+        for participant_id in participants {
+            let user_ids: Vec<Uuid> = typer_ids
+                .iter()
+                .filter(|typer_id| allowed.contains(&(**typer_id, participant_id)))
+                .copied()
+                .collect();
+            let message = WsMessage {
+                msg_type: "typing_users".to_string(),
+                payload: serde_json::json!({
+                    "conversation_id": conversation_id,
+                    "user_ids": user_ids,
+                    "timestamp": timestamp
+                }),
+            };
+            let msg_str = serde_json::to_string(&message)?;
 
-        for (participant_id,) in participants {
             self.redis
                 .publish_message(&participant_id.to_string(), &msg_str)
                 .await?;
@@ -492,7 +2287,25 @@ impl MessagingService {
         Ok(())
     }
 
+    /// Send the typers of conversations whose update was held back or whose
+    /// typers lapsed; run every second
+    pub async fn flush_typing(&self) -> AppResult<usize> {
+        const BATCH_SIZE: isize = 100;
+
+        let due = self
+            .redis
+            .take_due_typing(Utc::now().timestamp_millis(), BATCH_SIZE)
+            .await?;
+
+        for conversation_id in due.iter().filter_map(|id| Uuid::parse_str(id).ok()) {
+            self.send_typing_users(conversation_id).await?;
+        }
+
+        Ok(due.len())
+    }
+
     /// Update user presence
+    #[allow(dead_code)]
     pub async fn update_presence(&self, user_id: Uuid, status: &str) -> AppResult<()> {
         use std::time::Duration;
 
@@ -510,26 +2323,84 @@ impl MessagingService {
     }
 
     /// Notify participants of new message
+    /// Track delivery to every device of the participants the message is
+    /// published to; retries and push fallback happen in `DeliveryService`
+    async fn enqueue_deliveries(&self, message: &Message) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO message_deliveries (message_id, user_id, device_id, next_attempt_at)
+            SELECT $1, d.user_id, d.device_id, NOW() + make_interval(secs => $4)
+            FROM participants p
+            JOIN conversations c ON c.id = p.conversation_id
+            JOIN devices d ON d.user_id = p.user_id
+            WHERE p.conversation_id = $2 AND p.left_at IS NULL
+            AND (p.user_id != $3 OR c.type = 'saved')
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(message.id)
+        .bind(message.conversation_id)
+        .bind(message.sender_id)
+        .bind(self.config.delivery.retry_base.as_secs_f64())
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Content as it is stored, and whether it was padded. Only ciphertext is
+    /// padded; plaintext is indexed for search and would be found anyway.
+    fn pad(&self, content: Vec<u8>) -> (Vec<u8>, bool) {
+        if self.config.privacy.pad_messages && is_signal_ciphertext(&content) {
+            (pad_content(content), true)
+        } else {
+            (content, false)
+        }
+    }
+
+    /// A stored message as clients see it: unpadded, view-once media redacted
+    /// and the attachment link signed
+    fn present(&self, message: Message) -> Message {
+        MediaUrls::new(&self.config).sign_message(message.decode().redact_view_once())
+    }
+
     async fn notify_participants(
         &self,
         conversation_id: Uuid,
         sender_id: Uuid,
         message: &Message,
+    ) -> AppResult<()> {
+        let ws_message = WsMessage {
+            msg_type: "new_message".to_string(),
+            payload: serde_json::to_value(message)?,
+        };
+
+        self.publish_to_participants(conversation_id, sender_id, &ws_message)
+            .await
+    }
+
+    /// Publish an event to every other active participant. In Saved Messages
+    /// the sender is the only participant, so it goes to their other devices.
+    async fn publish_to_participants(
+        &self,
+        conversation_id: Uuid,
+        sender_id: Uuid,
+        ws_message: &WsMessage,
     ) -> AppResult<()> {
         let participants: Vec<(Uuid,)> = sqlx::query_as(
-            "SELECT user_id FROM participants WHERE conversation_id = $1 AND user_id != $2 AND left_at IS NULL",
+            r#"
+            SELECT p.user_id FROM participants p
+            JOIN conversations c ON c.id = p.conversation_id
+            WHERE p.conversation_id = $1 AND p.left_at IS NULL
+            AND (p.user_id != $2 OR c.type = 'saved')
+            "#,
         )
         .bind(conversation_id)
         .bind(sender_id)
         .fetch_all(&self.db)
         .await?;
 
-        let ws_message = WsMessage {
-            msg_type: "new_message".to_string(),
-            payload: serde_json::to_value(message)?,
-        };
-
-        let msg_str = serde_json::to_string(&ws_message)?;
+        let msg_str = serde_json::to_string(ws_message)?;
 
         for (participant_id,) in participants {
             self.redis
@@ -539,4 +2410,192 @@ impl MessagingService {
 
         Ok(())
     }
+
+    /// Index failures are logged rather than failing the write
+    async fn update_search_index(&self, message: &Message) {
+        let Some(index) = &self.search else {
+            return;
+        };
+
+        let result = match SearchDocument::from_message(message) {
+            Some(document) => index.upsert(&document).await,
+            None => index.remove(message.id).await,
+        };
+
+        if let Err(e) = result {
+            tracing::warn!("Failed to index message {}: {}", message.id, e);
+        }
+    }
+
+    fn spawn_bridge_relay(&self, message: &Message) {
+        let Some(bridge) = self.bridge.clone() else {
+            return;
+        };
+        let message = message.clone();
+        tokio::spawn(async move {
+            if let Err(e) = bridge.relay_message(&message).await {
+                tracing::warn!("Failed to relay message {} to Matrix: {}", message.id, e);
+            }
+        });
+    }
+
+    /// Translate in the background so sending never waits on the provider
+    fn spawn_auto_translate(&self, message: &Message) {
+        if message.message_type != MessageType::Text {
+            return;
+        }
+        let Ok(service) = TranslationService::new(
+            self.db.clone(),
+            self.redis.clone(),
+            self.translator.clone(),
+        ) else {
+            return;
+        };
+
+        let message = message.clone();
+        tokio::spawn(async move {
+            if let Err(e) = service.auto_translate(&message).await {
+                tracing::warn!("Failed to auto-translate message {}: {}", message.id, e);
+            }
+        });
+    }
+
+    async fn remove_from_search_index(&self, message_id: Uuid) {
+        let Some(index) = &self.search else {
+            return;
+        };
+
+        if let Err(e) = index.remove(message_id).await {
+            tracing::warn!("Failed to remove message {} from search index: {}", message_id, e);
+        }
+    }
+
+    /// Insert a system message describing a conversation event. The actor is
+    /// recorded as sender and in the JSON payload stored as content.
+    /// Note when a sampled message reached recipients' connections; the first
+    /// device ack completes the sample
+    async fn record_latency_sample(&self, message: &Message) {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO delivery_latency_samples (message_id, received_at, fanout_at)
+            VALUES ($1, $2, NOW())
+            "#,
+        )
+        .bind(message.id)
+        .bind(message.created_at)
+        .execute(&self.db)
+        .await;
+        if let Err(e) = result {
+            tracing::warn!("Failed to record delivery latency sample: {}", e);
+        }
+    }
+
+    /// Post a system message and record the change in the group's event
+    /// history
+    async fn insert_system_message(
+        &self,
+        conn: &mut PgConnection,
+        conversation_id: Uuid,
+        actor_id: Uuid,
+        event: SystemEvent,
+    ) -> AppResult<Message> {
+        let mut event = serde_json::to_value(event)?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO conversation_events (id, conversation_id, actor_id, key, params)
+            VALUES ($1, $2, $3, $4, COALESCE($5, '{}'::JSONB))
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(conversation_id)
+        .bind(actor_id)
+        .bind(event["key"].as_str())
+        .bind(event.get("params"))
+        .execute(&mut *conn)
+        .await?;
+
+        event["actor_id"] = serde_json::json!(actor_id);
+
+        let message: Message = sqlx::query_as(
+            r#"
+            INSERT INTO messages (id, conversation_id, sender_id, type, content, status)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(conversation_id)
+        .bind(actor_id)
+        .bind(MessageType::System)
+        .bind(serde_json::to_vec(&event)?)
+        .bind(MessageStatus::Sent)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        Ok(message)
+    }
+}
+
+/// Encrypted conversations only take text that is framed as ciphertext
+pub fn check_encryption(
+    encryption: ConversationEncryption,
+    message_type: MessageType,
+    payload: &StoredPayload,
+) -> AppResult<()> {
+    if encryption != ConversationEncryption::Signal {
+        return Ok(());
+    }
+
+    if message_type == MessageType::Text
+        && !(payload.encrypted && is_signal_ciphertext(&payload.content))
+    {
+        return Err(AppError::Validation(
+            "This conversation is end-to-end encrypted; text must be Signal ciphertext"
+                .to_string(),
+        ));
+    }
+    if payload.caption.is_some() {
+        return Err(AppError::Validation(
+            "This conversation is end-to-end encrypted; captions go inside the ciphertext"
+                .to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Identifies the direct conversation of a pair of users whichever of
+/// them opens it
+pub fn direct_key(user_id: Uuid, other_user_id: Uuid) -> String {
+    let (low, high) = if user_id < other_user_id {
+        (user_id, other_user_id)
+    } else {
+        (other_user_id, user_id)
+    };
+    format!("{}:{}", low, high)
+}
+
+/// Every message in a group takes `send_messages`; media and stickers also
+/// take their own permission
+pub fn check_group_permissions(
+    permissions: &GroupPermissions,
+    role: ParticipantRole,
+    message_type: MessageType,
+) -> AppResult<()> {
+    let action = match message_type {
+        MessageType::Image | MessageType::Video | MessageType::Audio | MessageType::File => {
+            Some(GroupAction::SendMedia)
+        }
+        MessageType::Sticker => Some(GroupAction::SendStickers),
+        _ => None,
+    };
+
+    if !permissions.allows(role, GroupAction::SendMessages)
+        || action.is_some_and(|action| !permissions.allows(role, action))
+    {
+        return Err(AppError::InsufficientPermissions);
+    }
+
+    Ok(())
 }
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::{migrate::Migrator, PgPool};
+
+use crate::{
+    error::AppResult,
+    models::{MigrationRecord, MigrationStatus},
+};
+
+/// Migrations embedded at build time
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+type AppliedRow = (i64, String, DateTime<Utc>, bool, Vec<u8>, i64);
+
+pub struct MigrationService {
+    db: PgPool,
+}
+
+impl MigrationService {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    /// Compare the embedded migrations with `_sqlx_migrations`
+    pub async fn status(&self) -> AppResult<MigrationStatus> {
+        let exists: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(&self.db)
+            .await?;
+        let rows: Vec<AppliedRow> = if exists {
+            sqlx::query_as(
+                r#"
+                SELECT version, description, installed_on, success, checksum, execution_time
+                FROM _sqlx_migrations ORDER BY version
+                "#,
+            )
+            .fetch_all(&self.db)
+            .await?
+        } else {
+            Vec::new()
+        };
+
+        let mut applied: HashMap<i64, AppliedRow> =
+            rows.into_iter().map(|row| (row.0, row)).collect();
+        let mut status = MigrationStatus::default();
+
+        for migration in MIGRATOR
+            .iter()
+            .filter(|m| !m.migration_type.is_down_migration())
+        {
+            let Some((version, description, installed_on, success, checksum, nanos)) =
+                applied.remove(&migration.version)
+            else {
+                status.pending.push(MigrationRecord {
+                    version: migration.version,
+                    description: migration.description.to_string(),
+                    installed_on: None,
+                    execution_ms: None,
+                });
+                continue;
+            };
+
+            let record = applied_record(version, description, installed_on, nanos);
+            if !success {
+                status.failed.push(record);
+            } else if checksum != *migration.checksum {
+                status.checksum_mismatches.push(record);
+            } else {
+                status.applied.push(record);
+            }
+        }
+
+        let mut unknown: Vec<MigrationRecord> = applied
+            .into_values()
+            .map(|(version, description, installed_on, _, _, nanos)| {
+                applied_record(version, description, installed_on, nanos)
+            })
+            .collect();
+        unknown.sort_by_key(|r| r.version);
+        status.unknown = unknown;
+
+        status.drift = !status.checksum_mismatches.is_empty()
+            || !status.unknown.is_empty()
+            || !status.failed.is_empty();
+
+        Ok(status)
+    }
+}
+
+fn applied_record(
+    version: i64,
+    description: String,
+    installed_on: DateTime<Utc>,
+    nanos: i64,
+) -> MigrationRecord {
+    MigrationRecord {
+        version,
+        description,
+        installed_on: Some(installed_on),
+        execution_ms: Some(nanos / 1_000_000),
+    }
+}
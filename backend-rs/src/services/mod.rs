@@ -1,5 +1,52 @@
+pub mod account_merge;
+pub mod admin;
+pub mod api_tokens;
+pub mod appearance;
+pub mod audit;
 pub mod auth;
+pub mod backups;
+pub mod broadcast;
+pub mod captcha;
+pub mod client_versions;
+pub mod contact_import;
 pub mod contacts;
+pub mod content_moderation;
 pub mod crypto;
+pub mod delivery;
+pub mod devices;
+pub mod digests;
+pub mod email;
+pub mod emoji;
+pub mod events;
+pub mod franking;
+pub mod gifs;
+pub mod health;
+pub mod http;
+pub mod invites;
+pub mod matrix;
+pub mod media;
+pub mod message_policy;
 pub mod messaging;
+pub mod migrations;
+pub mod moderation;
+pub mod notifications;
+pub mod organizations;
+pub mod presence;
+pub mod profiles;
+pub mod push;
+pub mod rate_limit;
+pub mod reports;
+pub mod sealed_sender;
+pub mod search;
+pub mod seed;
+pub mod social_graph;
+pub mod spam;
+pub mod sticker_preview;
 pub mod stickers;
+pub mod sync_data;
+pub mod transcoding;
+pub mod translation;
+pub mod usernames;
+pub mod view_once;
+pub mod waitlist;
+pub mod web_push;
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::ModerationAlert,
+    services::messaging::WsMessage,
+    storage::redis::RedisClient,
+};
+
+pub struct ModerationService {
+    db: PgPool,
+    redis: RedisClient,
+}
+
+impl ModerationService {
+    pub fn new(db: PgPool, redis: RedisClient) -> Self {
+        Self { db, redis }
+    }
+
+    /// Shadow-limit a user and raise a moderator alert explaining why
+    pub async fn shadow_limit(
+        &self,
+        user_id: Uuid,
+        conversation_id: Option<Uuid>,
+        reason: &str,
+        details: String,
+        duration: Duration,
+    ) -> AppResult<ModerationAlert> {
+        self.redis
+            .set_shadow_limit(&user_id.to_string(), duration)
+            .await?;
+
+        tracing::warn!("Shadow-limited user {} ({}): {}", user_id, reason, details);
+
+        self.create_alert(user_id, conversation_id, reason, Some(details))
+            .await
+    }
+
+    /// Lift a shadow limit before it expires
+    pub async fn lift_shadow_limit(&self, user_id: Uuid) -> AppResult<()> {
+        self.redis.clear_shadow_limit(&user_id.to_string()).await
+    }
+
+    /// Record an alert and push it to every connected moderator
+    pub async fn create_alert(
+        &self,
+        user_id: Uuid,
+        conversation_id: Option<Uuid>,
+        reason: &str,
+        details: Option<String>,
+    ) -> AppResult<ModerationAlert> {
+        let alert: ModerationAlert = sqlx::query_as(
+            r#"
+            INSERT INTO moderation_alerts (id, user_id, conversation_id, reason, details)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(conversation_id)
+        .bind(reason)
+        .bind(details)
+        .fetch_one(&self.db)
+        .await?;
+
+        self.notify_moderators(&alert).await?;
+
+        Ok(alert)
+    }
+
+    /// List alerts, newest first
+    pub async fn list_alerts(
+        &self,
+        include_resolved: bool,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<ModerationAlert>> {
+        let alerts: Vec<ModerationAlert> = sqlx::query_as(
+            r#"
+            SELECT * FROM moderation_alerts
+            WHERE $1 OR resolved_at IS NULL
+            ORDER BY created_at DESC
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(include_resolved)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(alerts)
+    }
+
+    /// Mark an alert as handled
+    pub async fn resolve_alert(
+        &self,
+        alert_id: Uuid,
+        resolved_by: Uuid,
+    ) -> AppResult<ModerationAlert> {
+        let alert: Option<ModerationAlert> = sqlx::query_as(
+            r#"
+            UPDATE moderation_alerts
+            SET resolved_at = COALESCE(resolved_at, NOW()), resolved_by = COALESCE(resolved_by, $2)
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(alert_id)
+        .bind(resolved_by)
+        .fetch_optional(&self.db)
+        .await?;
+
+        alert.ok_or(AppError::ModerationAlertNotFound)
+    }
+
+    /// Add a hash to the blocklist used by the content moderation pipeline
+    pub async fn add_blocked_hash(
+        &self,
+        hash: &str,
+        label: &str,
+        created_by: Uuid,
+    ) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO blocked_content_hashes (hash, label, created_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (hash) DO UPDATE SET label = EXCLUDED.label
+            "#,
+        )
+        .bind(hash)
+        .bind(label)
+        .bind(created_by)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_blocked_hash(&self, hash: &str) -> AppResult<()> {
+        sqlx::query("DELETE FROM blocked_content_hashes WHERE hash = $1")
+            .bind(hash)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn notify_moderators(&self, alert: &ModerationAlert) -> AppResult<()> {
+        let moderators: Vec<(Uuid,)> = sqlx::query_as("SELECT id FROM users WHERE is_admin")
+            .fetch_all(&self.db)
+            .await?;
+
+        let ws_message = WsMessage {
+            msg_type: "moderation_alert".to_string(),
+            payload: serde_json::to_value(alert)?,
+        };
+
+        let msg_str = serde_json::to_string(&ws_message)?;
+
+        for (moderator_id,) in moderators {
+            self.redis
+                .publish_message(&moderator_id.to_string(), &msg_str)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
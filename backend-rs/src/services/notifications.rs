@@ -0,0 +1,593 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{
+        Device, NewLoginAlert, NotificationSchedule, OtpType, PublicProfile, QuietHoursWindow,
+        User, UserSettings,
+    },
+    services::{
+        email::EmailProvider,
+        push::{PushNotification, PushProvider},
+    },
+    storage::redis::RedisClient,
+};
+
+/// Longest a user can snooze all pushes for
+pub const MAX_SNOOZE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Most quiet-hours windows in a schedule, four a day
+pub const MAX_QUIET_HOURS_WINDOWS: usize = 28;
+
+pub struct NotificationService {
+    db: PgPool,
+    redis: RedisClient,
+    push: Arc<dyn PushProvider>,
+    email: Arc<dyn EmailProvider>,
+}
+
+impl NotificationService {
+    pub fn new(
+        db: PgPool,
+        redis: RedisClient,
+        push: Arc<dyn PushProvider>,
+        email: Arc<dyn EmailProvider>,
+    ) -> Self {
+        Self {
+            db,
+            redis,
+            push,
+            email,
+        }
+    }
+
+    /// Push a notification to every device of a user that has a push token.
+    /// During the user's quiet hours it is queued for a summary instead, and
+    /// while they have pushes snoozed or the account deactivated it is dropped.
+    pub async fn push_to_user(
+        &self,
+        user_id: Uuid,
+        exclude_device_id: Option<i32>,
+        notification: &PushNotification,
+    ) -> AppResult<()> {
+        if self.snoozed_until(user_id).await?.is_some() || self.is_deactivated(user_id).await? {
+            return Ok(());
+        }
+
+        if is_quiet_at(&self.get_schedule(user_id).await?, Utc::now()) {
+            sqlx::query(
+                "INSERT INTO queued_notifications (user_id, title, body) VALUES ($1, $2, $3)",
+            )
+            .bind(user_id)
+            .bind(&notification.title)
+            .bind(&notification.body)
+            .execute(&self.db)
+            .await?;
+            return Ok(());
+        }
+
+        self.send_to_devices(user_id, exclude_device_id, notification)
+            .await?;
+        Ok(())
+    }
+
+    /// Push to one device, unless it has no push token or the user is in
+    /// quiet hours, snoozed or deactivated. Returns whether a push was sent.
+    pub async fn push_to_device(
+        &self,
+        user_id: Uuid,
+        device_id: i32,
+        notification: &PushNotification,
+    ) -> AppResult<bool> {
+        if self.pushes_paused(user_id).await? {
+            return Ok(false);
+        }
+
+        let device: Option<Device> = sqlx::query_as(
+            r#"
+            SELECT * FROM devices
+            WHERE user_id = $1 AND device_id = $2 AND push_token IS NOT NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        let Some(device) = device else {
+            return Ok(false);
+        };
+
+        let notification = self.with_badge(user_id, notification).await?;
+        self.send(&device, &notification).await
+    }
+
+    /// Total unread across the user's conversations, for the app icon badge.
+    /// Unread is counted from read receipts as in `get_conversation`; muted
+    /// conversations and message requests don't count, and a conversation
+    /// marked unread counts at least one.
+    pub async fn badge_count(&self, user_id: Uuid) -> AppResult<i64> {
+        let badge: i64 = sqlx::query_scalar(
+            r#"
+            SELECT COALESCE(SUM(GREATEST(unread, marked_unread::int)), 0)::bigint FROM (
+                SELECT p.marked_unread, COUNT(m.id) AS unread FROM participants p
+                LEFT JOIN messages m ON m.conversation_id = p.conversation_id
+                    AND m.sender_id != $1 AND m.deleted_at IS NULL AND NOT m.is_shadowed
+                    AND (p.cleared_before IS NULL OR m.created_at > p.cleared_before)
+                    AND NOT EXISTS (
+                        SELECT 1 FROM receipts r
+                        WHERE r.message_id = m.id AND r.user_id = $1 AND r.type = 'read'
+                    )
+                WHERE p.user_id = $1 AND p.left_at IS NULL AND NOT p.is_request
+                AND (p.muted_until IS NULL OR p.muted_until <= NOW())
+                GROUP BY p.conversation_id, p.marked_unread
+            ) counts
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(badge)
+    }
+
+    async fn with_badge(
+        &self,
+        user_id: Uuid,
+        notification: &PushNotification,
+    ) -> AppResult<PushNotification> {
+        Ok(PushNotification {
+            badge: Some(self.badge_count(user_id).await?),
+            ..notification.clone()
+        })
+    }
+
+    /// Send through the provider. A token the push service reports as
+    /// expired is forgotten; returns whether the push was sent.
+    async fn send(&self, device: &Device, notification: &PushNotification) -> AppResult<bool> {
+        match self.push.send(device, notification).await {
+            Ok(()) => Ok(true),
+            Err(AppError::PushSubscriptionExpired) => {
+                sqlx::query(
+                    "UPDATE devices SET push_token = NULL WHERE id = $1 AND push_token = $2",
+                )
+                .bind(device.id)
+                .bind(&device.push_token)
+                .execute(&self.db)
+                .await?;
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the user is snoozed, in quiet hours or deactivated, so pushes
+    /// to single devices are not sent
+    pub async fn pushes_paused(&self, user_id: Uuid) -> AppResult<bool> {
+        Ok(self.snoozed_until(user_id).await?.is_some()
+            || is_quiet_at(&self.get_schedule(user_id).await?, Utc::now())
+            || self.is_deactivated(user_id).await?)
+    }
+
+    async fn is_deactivated(&self, user_id: Uuid) -> AppResult<bool> {
+        let deactivated: Option<bool> =
+            sqlx::query_scalar("SELECT deactivated_at IS NOT NULL FROM users WHERE id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.db)
+                .await?;
+
+        Ok(deactivated.unwrap_or(false))
+    }
+
+    /// Push to each of the user's devices; false when every push failed, so
+    /// the caller can try again later
+    async fn send_to_devices(
+        &self,
+        user_id: Uuid,
+        exclude_device_id: Option<i32>,
+        notification: &PushNotification,
+    ) -> AppResult<bool> {
+        let devices: Vec<Device> = sqlx::query_as(
+            "SELECT * FROM devices WHERE user_id = $1 AND push_token IS NOT NULL",
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+        if devices.is_empty() {
+            return Ok(true);
+        }
+
+        let notification = &self.with_badge(user_id, notification).await?;
+        let (mut attempted, mut failed) = (0, 0);
+        for device in devices {
+            if Some(device.device_id) == exclude_device_id {
+                continue;
+            }
+
+            // One failing device should not prevent delivery to the others
+            attempted += 1;
+            if let Err(e) = self.send(&device, notification).await {
+                failed += 1;
+                tracing::warn!(
+                    "Push to {}:{} failed: {}",
+                    device.user_id,
+                    device.device_id,
+                    e
+                );
+            }
+        }
+
+        Ok(attempted == 0 || failed < attempted)
+    }
+
+    /// Warn the user's other devices and email about a login from a new device or location
+    pub async fn notify_new_login(&self, user: &User, alert: &NewLoginAlert) -> AppResult<()> {
+        let location = alert
+            .client
+            .country
+            .clone()
+            .or_else(|| alert.client.ip_address.clone())
+            .unwrap_or_else(|| "an unknown location".to_string());
+
+        let body = format!(
+            "New login to your account from {} ({}) in {}",
+            alert.device_name, alert.platform, location
+        );
+
+        let notification = PushNotification {
+            title: "New device login".to_string(),
+            body: body.clone(),
+            data: json!({
+                "type": "new_login",
+                "device_id": alert.device_id,
+                "new_device": alert.new_device,
+                "ip_address": alert.client.ip_address,
+                "country": alert.client.country,
+            }),
+            collapse_key: None,
+            thread_id: None,
+            badge: None,
+        };
+
+        self.push_to_user(user.id, Some(alert.device_id), &notification)
+            .await?;
+
+        if let Some(email) = &user.email {
+            let email_body = format!(
+                "{}.\n\nIf this wasn't you, log out all devices from the app and contact support.",
+                body
+            );
+            self.email
+                .send(email, "New login to your Ansible Talk account", &email_body)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Warn the user's other devices, and the old email address if it was the
+    /// one replaced, that the account's phone or email changed
+    pub async fn notify_identifier_changed(
+        &self,
+        user: &User,
+        otp_type: OtpType,
+        old_email: Option<&str>,
+        exclude_device_id: i32,
+    ) -> AppResult<()> {
+        let what = match otp_type {
+            OtpType::Phone => "phone number",
+            OtpType::Email => "email address",
+        };
+        let body = format!("The {} on your account was changed", what);
+
+        let notification = PushNotification {
+            title: "Account details changed".to_string(),
+            body: body.clone(),
+            data: json!({ "type": "identifier_changed", "identifier": otp_type }),
+            collapse_key: None,
+            thread_id: None,
+            badge: None,
+        };
+
+        self.push_to_user(user.id, Some(exclude_device_id), &notification)
+            .await?;
+
+        if let (OtpType::Email, Some(email)) = (otp_type, old_email) {
+            let email_body = format!(
+                "{}.\n\nIf this wasn't you, log out all devices from the app and contact support.",
+                body
+            );
+            self.email
+                .send(email, "Your Ansible Talk email was changed", &email_body)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Tell users who had a newly registered user in their address book, by a
+    /// `contact_joined` event and a push
+    pub async fn notify_contact_joined(&self, user: &User, recipients: &[Uuid]) -> AppResult<()> {
+        let event = json!({
+            "type": "contact_joined",
+            "payload": { "user": PublicProfile::from(user) },
+        })
+        .to_string();
+        let notification = PushNotification {
+            title: "New on Ansible Talk".to_string(),
+            body: format!("{} joined Ansible Talk", user.display_name),
+            data: json!({ "type": "contact_joined", "user_id": user.id }),
+            collapse_key: None,
+            thread_id: None,
+            badge: None,
+        };
+
+        for recipient in recipients {
+            self.redis
+                .publish_message(&recipient.to_string(), &event)
+                .await?;
+            self.push_to_user(*recipient, None, &notification).await?;
+        }
+
+        Ok(())
+    }
+
+    /// When the user's push snooze ends, if one is running
+    pub async fn snoozed_until(&self, user_id: Uuid) -> AppResult<Option<DateTime<Utc>>> {
+        let until = self.redis.get_push_snooze(&user_id.to_string()).await?;
+
+        Ok(until
+            .and_then(|until| DateTime::parse_from_rfc3339(&until).ok())
+            .map(|until| until.with_timezone(&Utc))
+            .filter(|until| *until > Utc::now()))
+    }
+
+    /// Suppress every push to the user for `duration`, replacing any running
+    /// snooze. WebSocket events are unaffected.
+    pub async fn snooze(&self, user_id: Uuid, duration: Duration) -> AppResult<UserSettings> {
+        if duration.is_zero() || duration > MAX_SNOOZE {
+            return Err(AppError::Validation(format!(
+                "Snooze duration must be between 1 and {} seconds",
+                MAX_SNOOZE.as_secs()
+            )));
+        }
+
+        let until = Utc::now() + chrono::Duration::seconds(duration.as_secs() as i64);
+        self.redis
+            .set_push_snooze(&user_id.to_string(), &until.to_rfc3339(), duration)
+            .await?;
+
+        self.get_settings(user_id).await
+    }
+
+    /// End a running snooze early
+    pub async fn clear_snooze(&self, user_id: Uuid) -> AppResult<UserSettings> {
+        self.redis.clear_push_snooze(&user_id.to_string()).await?;
+
+        self.get_settings(user_id).await
+    }
+
+    pub async fn get_settings(&self, user_id: Uuid) -> AppResult<UserSettings> {
+        Ok(UserSettings {
+            snoozed_until: self.snoozed_until(user_id).await?,
+            notification_schedule: self.get_schedule(user_id).await?,
+            email_digest: sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM email_digests WHERE user_id = $1)",
+            )
+            .bind(user_id)
+            .fetch_one(&self.db)
+            .await?,
+            contact_joined: sqlx::query_scalar(
+                "SELECT contact_joined_notices FROM users WHERE id = $1",
+            )
+            .bind(user_id)
+            .fetch_one(&self.db)
+            .await?,
+        })
+    }
+
+    /// Get the user's quiet-hours schedule (disabled if unset)
+    pub async fn get_schedule(&self, user_id: Uuid) -> AppResult<NotificationSchedule> {
+        let settings: Option<(bool, String, DateTime<Utc>)> = sqlx::query_as(
+            "SELECT enabled, timezone, updated_at FROM notification_schedules WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        let Some((enabled, timezone, updated_at)) = settings else {
+            return Ok(NotificationSchedule::default());
+        };
+
+        let windows: Vec<(i16, NaiveTime, NaiveTime)> = sqlx::query_as(
+            r#"
+            SELECT weekday, start_time, end_time FROM quiet_hours_windows
+            WHERE user_id = $1
+            ORDER BY weekday, start_time
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(NotificationSchedule {
+            enabled,
+            timezone,
+            windows: windows
+                .into_iter()
+                .filter_map(|(weekday, start, end)| {
+                    Some(QuietHoursWindow {
+                        weekday: Weekday::try_from(u8::try_from(weekday).ok()?).ok()?,
+                        start,
+                        end,
+                    })
+                })
+                .collect(),
+            updated_at: Some(updated_at),
+        })
+    }
+
+    /// Replace the user's quiet-hours schedule
+    pub async fn update_schedule(
+        &self,
+        user_id: Uuid,
+        enabled: bool,
+        timezone: &str,
+        windows: &[QuietHoursWindow],
+    ) -> AppResult<NotificationSchedule> {
+        let mut tx = self.db.begin().await?;
+        self.save_schedule(&mut tx, user_id, enabled, timezone, windows)
+            .await?;
+        tx.commit().await?;
+
+        self.get_schedule(user_id).await
+    }
+
+    /// Replace the schedule and its windows within the caller's transaction
+    pub async fn save_schedule(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        enabled: bool,
+        timezone: &str,
+        windows: &[QuietHoursWindow],
+    ) -> AppResult<()> {
+        if timezone.parse::<Tz>().is_err() {
+            return Err(AppError::Validation(format!("Unknown time zone '{}'", timezone)));
+        }
+        if windows.len() > MAX_QUIET_HOURS_WINDOWS {
+            return Err(AppError::Validation(format!(
+                "At most {} quiet-hours windows",
+                MAX_QUIET_HOURS_WINDOWS
+            )));
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO notification_schedules (user_id, enabled, timezone)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id) DO UPDATE SET
+                enabled = EXCLUDED.enabled,
+                timezone = EXCLUDED.timezone,
+                updated_at = NOW()
+            "#,
+        )
+        .bind(user_id)
+        .bind(enabled)
+        .bind(timezone)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query("DELETE FROM quiet_hours_windows WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await?;
+
+        for window in windows {
+            sqlx::query(
+                r#"
+                INSERT INTO quiet_hours_windows (user_id, weekday, start_time, end_time)
+                VALUES ($1, $2, $3, $4)
+                "#,
+            )
+            .bind(user_id)
+            .bind(window.weekday.num_days_from_monday() as i16)
+            .bind(window.start)
+            .bind(window.end)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send one summary push to each user whose quiet hours have ended with
+    /// notifications queued. Run periodically from a background task.
+    pub async fn flush_quiet_hours(&self) -> AppResult<()> {
+        let user_ids: Vec<Uuid> =
+            sqlx::query_scalar("SELECT DISTINCT user_id FROM queued_notifications")
+                .fetch_all(&self.db)
+                .await?;
+
+        let now = Utc::now();
+        for user_id in user_ids {
+            // A snooze holds the summary back until it ends
+            if is_quiet_at(&self.get_schedule(user_id).await?, now)
+                || self.snoozed_until(user_id).await?.is_some()
+            {
+                continue;
+            }
+
+            // Claiming the rows by deleting them keeps concurrent runs from
+            // sending the same summary twice; they are only gone once the
+            // summary has gone out
+            let mut tx = self.db.begin().await?;
+            let queued: Vec<(String, String)> = sqlx::query_as(
+                "DELETE FROM queued_notifications WHERE user_id = $1 RETURNING title, body",
+            )
+            .bind(user_id)
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let notification = match queued.as_slice() {
+                [] => continue,
+                [(title, body)] => PushNotification {
+                    title: title.clone(),
+                    body: body.clone(),
+                    data: json!({ "type": "quiet_hours_summary", "count": 1 }),
+                    collapse_key: None,
+                    thread_id: None,
+                    badge: None,
+                },
+                _ => PushNotification {
+                    title: "While Do Not Disturb was on".to_string(),
+                    body: format!("You have {} new notifications", queued.len()),
+                    data: json!({ "type": "quiet_hours_summary", "count": queued.len() }),
+                    collapse_key: None,
+                    thread_id: None,
+                    badge: None,
+                },
+            };
+
+            match self.send_to_devices(user_id, None, &notification).await {
+                Ok(true) => tx.commit().await?,
+                Ok(false) => {
+                    tracing::warn!("Quiet hours summary for {} not delivered; kept queued", user_id);
+                    tx.rollback().await?;
+                }
+                Err(e) => {
+                    tracing::warn!("Quiet hours summary for {} failed: {}", user_id, e);
+                    tx.rollback().await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `now` falls inside one of the schedule's windows, in its time zone
+fn is_quiet_at(schedule: &NotificationSchedule, now: DateTime<Utc>) -> bool {
+    if !schedule.enabled {
+        return false;
+    }
+
+    let tz: Tz = schedule.timezone.parse().unwrap_or(Tz::UTC);
+    let local = now.with_timezone(&tz);
+    let (weekday, time) = (local.weekday(), local.time());
+
+    schedule.windows.iter().any(|window| {
+        if window.start < window.end {
+            weekday == window.weekday && time >= window.start && time < window.end
+        } else {
+            (weekday == window.weekday && time >= window.start)
+                || (weekday == window.weekday.succ() && time < window.end)
+        }
+    })
+}
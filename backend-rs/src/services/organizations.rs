@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{
+        DirectoryEntry, DirectoryImportEntry, DirectoryImportSummary, DirectoryRow, Organization,
+        OrganizationWithCounts,
+    },
+    services::messaging::MessagingService,
+};
+
+/// Organization directories: admins import the member list, members see each
+/// other without adding contacts, and each team gets a managed group
+pub struct OrganizationService {
+    db: PgPool,
+    messaging: MessagingService,
+}
+
+impl OrganizationService {
+    pub fn new(db: PgPool, messaging: MessagingService) -> Self {
+        Self { db, messaging }
+    }
+
+    pub async fn create_organization(&self, name: &str) -> AppResult<Organization> {
+        let organization: Organization =
+            sqlx::query_as("INSERT INTO organizations (id, name) VALUES ($1, $2) RETURNING *")
+                .bind(Uuid::new_v4())
+                .bind(name)
+                .fetch_one(&self.db)
+                .await?;
+
+        Ok(organization)
+    }
+
+    pub async fn list_organizations(&self) -> AppResult<Vec<OrganizationWithCounts>> {
+        let organizations: Vec<OrganizationWithCounts> = sqlx::query_as(
+            r#"
+            SELECT o.*,
+                (SELECT COUNT(*) FROM organization_members m WHERE m.organization_id = o.id)
+                    AS member_count,
+                (SELECT COUNT(*) FROM organization_teams t WHERE t.organization_id = o.id)
+                    AS team_count
+            FROM organizations o
+            ORDER BY o.name
+            "#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(organizations)
+    }
+
+    /// Replace an organization's directory with `entries`. Accounts missing
+    /// from the import leave the directory and their team groups; teams left
+    /// without members keep their group, emptied.
+    pub async fn import_directory(
+        &self,
+        organization_id: Uuid,
+        actor_id: Uuid,
+        entries: Vec<DirectoryImportEntry>,
+    ) -> AppResult<DirectoryImportSummary> {
+        let exists: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM organizations WHERE id = $1)")
+                .bind(organization_id)
+                .fetch_one(&self.db)
+                .await?;
+        if !exists {
+            return Err(AppError::OrganizationNotFound);
+        }
+
+        let mut summary = DirectoryImportSummary::default();
+        let mut members: Vec<(Uuid, Option<String>)> = Vec::new();
+        // Team name -> member IDs, ordered so groups are created predictably
+        let mut teams: BTreeMap<String, Vec<Uuid>> = BTreeMap::new();
+
+        for entry in entries {
+            let Some(user_id) = self.match_account(&entry).await? else {
+                let identifier = entry
+                    .username
+                    .or(entry.email)
+                    .or(entry.phone)
+                    .unwrap_or_default();
+                summary.unmatched.push(identifier);
+                continue;
+            };
+
+            if members.iter().any(|(id, _)| *id == user_id) {
+                continue;
+            }
+            members.push((user_id, entry.title));
+
+            for team in entry.teams {
+                let team = team.trim().to_string();
+                if team.is_empty() || team.chars().count() > 100 {
+                    return Err(AppError::Validation(
+                        "Team names must be 1-100 characters".to_string(),
+                    ));
+                }
+                teams.entry(team).or_default().push(user_id);
+            }
+        }
+
+        let member_ids: Vec<Uuid> = members.iter().map(|(id, _)| *id).collect();
+
+        let mut tx = self.db.begin().await?;
+
+        let removed = sqlx::query(
+            "DELETE FROM organization_members WHERE organization_id = $1 AND user_id != ALL($2)",
+        )
+        .bind(organization_id)
+        .bind(&member_ids)
+        .execute(&mut *tx)
+        .await?;
+        summary.removed = removed.rows_affected() as usize;
+
+        for (user_id, title) in &members {
+            sqlx::query(
+                r#"
+                INSERT INTO organization_members (organization_id, user_id, title)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (organization_id, user_id) DO UPDATE SET title = EXCLUDED.title
+                "#,
+            )
+            .bind(organization_id)
+            .bind(user_id)
+            .bind(title)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for name in teams.keys() {
+            sqlx::query(
+                r#"
+                INSERT INTO organization_teams (id, organization_id, name)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (organization_id, name) DO NOTHING
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(organization_id)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query(
+            r#"
+            DELETE FROM organization_team_members
+            WHERE team_id IN (SELECT id FROM organization_teams WHERE organization_id = $1)
+            "#,
+        )
+        .bind(organization_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let all_teams: Vec<(Uuid, String, Option<Uuid>)> = sqlx::query_as(
+            r#"
+            SELECT t.id, t.name, c.id FROM organization_teams t
+            LEFT JOIN conversations c ON c.organization_team_id = t.id
+            WHERE t.organization_id = $1
+            ORDER BY t.name
+            "#,
+        )
+        .bind(organization_id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for (team_id, name, _) in &all_teams {
+            let Some(team_members) = teams.get(name) else {
+                continue;
+            };
+            sqlx::query(
+                r#"
+                INSERT INTO organization_team_members (team_id, user_id)
+                SELECT $1, UNNEST($2::uuid[])
+                ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(team_id)
+            .bind(team_members)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query("UPDATE organizations SET updated_at = NOW() WHERE id = $1")
+            .bind(organization_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        // Bring each team's group in line with the directory
+        for (team_id, name, conversation_id) in all_teams {
+            let team_members = teams.get(&name).cloned().unwrap_or_default();
+
+            let conversation_id = match conversation_id {
+                Some(id) => id,
+                None if team_members.is_empty() => continue,
+                None => {
+                    summary.team_groups_created += 1;
+                    self.messaging
+                        .create_managed_group(actor_id, &name, team_id)
+                        .await?
+                }
+            };
+
+            self.messaging
+                .sync_managed_group(conversation_id, actor_id, &team_members)
+                .await?;
+        }
+
+        summary.members = members.len();
+        summary.teams = teams.len();
+
+        Ok(summary)
+    }
+
+    /// Colleagues from every organization the user belongs to, optionally
+    /// filtered by name
+    pub async fn get_directory(
+        &self,
+        user_id: Uuid,
+        query: Option<&str>,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<DirectoryEntry>> {
+        let pattern = query.map(|q| format!("%{}%", q.trim()));
+
+        let rows: Vec<DirectoryRow> = sqlx::query_as(
+            r#"
+            SELECT u.*, colleague.organization_id, colleague.title,
+                ARRAY(
+                    SELECT t.name::text FROM organization_team_members tm
+                    JOIN organization_teams t ON t.id = tm.team_id
+                    WHERE tm.user_id = u.id AND t.organization_id = colleague.organization_id
+                    ORDER BY t.name
+                ) AS teams
+            FROM organization_members me
+            JOIN organization_members colleague
+                ON colleague.organization_id = me.organization_id
+                AND colleague.user_id != me.user_id
+            JOIN users u ON u.id = colleague.user_id
+            WHERE me.user_id = $1 AND u.banned_at IS NULL AND u.deactivated_at IS NULL
+            AND ($2::text IS NULL OR u.username ILIKE $2 OR u.display_name ILIKE $2)
+            ORDER BY u.display_name, u.id
+            LIMIT $3 OFFSET $4
+            "#,
+        )
+        .bind(user_id)
+        .bind(pattern)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows.into_iter().map(DirectoryEntry::from).collect())
+    }
+
+    async fn match_account(&self, entry: &DirectoryImportEntry) -> AppResult<Option<Uuid>> {
+        let lookups = [
+            (
+                "SELECT id FROM users WHERE LOWER(username) = LOWER($1)",
+                &entry.username,
+            ),
+            ("SELECT id FROM users WHERE email = $1", &entry.email),
+            ("SELECT id FROM users WHERE phone = $1", &entry.phone),
+        ];
+
+        for (sql, value) in lookups {
+            let Some(value) = value else {
+                continue;
+            };
+            let id: Option<Uuid> = sqlx::query_scalar(sql)
+                .bind(value.trim().trim_start_matches('@'))
+                .fetch_optional(&self.db)
+                .await?;
+            if id.is_some() {
+                return Ok(id);
+            }
+        }
+
+        Ok(None)
+    }
+}
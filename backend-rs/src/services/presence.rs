@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::AppResult, services::social_graph::SocialGraphService, storage::redis::RedisClient,
+};
+
+/// How long a status lasts without being refreshed
+pub const PRESENCE_TTL: Duration = Duration::from_secs(300);
+
+/// Online status. Changes go out on one Redis channel; each instance passes
+/// them only to connections that subscribed to the user.
+pub struct PresenceService {
+    db: PgPool,
+    redis: RedisClient,
+}
+
+impl PresenceService {
+    pub fn new(db: PgPool, redis: RedisClient) -> Self {
+        Self { db, redis }
+    }
+
+    /// Record the user's status and announce it to subscribers
+    pub async fn set_status(&self, user_id: &str, status: &str, ttl: Duration) -> AppResult<()> {
+        self.redis.set_user_presence(user_id, status, ttl).await?;
+
+        let event = json!({
+            "type": "presence",
+            "payload": { "user_id": user_id, "status": status },
+        });
+        self.redis.publish_presence(&event.to_string()).await
+    }
+
+    /// Current status of each of `user_ids`
+    pub async fn statuses(&self, user_ids: &[Uuid]) -> AppResult<Vec<(Uuid, String)>> {
+        let mut statuses = Vec::with_capacity(user_ids.len());
+        for user_id in user_ids {
+            let status = self.redis.get_user_presence(&user_id.to_string()).await?;
+            statuses.push((*user_id, status));
+        }
+
+        Ok(statuses)
+    }
+
+    /// The subset of `user_ids` whose presence `viewer_id` may follow, as
+    /// `SocialGraphService::can_interact` decides
+    pub async fn visible_to(&self, viewer_id: Uuid, user_ids: &[Uuid]) -> AppResult<Vec<Uuid>> {
+        let allowed = SocialGraphService::new(self.db.clone())
+            .interacting(user_ids, &[viewer_id])
+            .await?;
+
+        Ok(allowed
+            .into_iter()
+            .map(|(user_id, _)| user_id)
+            .filter(|user_id| *user_id != viewer_id)
+            .collect())
+    }
+}
@@ -0,0 +1,191 @@
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{PublicProfile, User},
+    storage::redis::RedisClient,
+};
+
+pub const MAX_PROFILE_LINKS: usize = 5;
+const MAX_LINK_LENGTH: usize = 200;
+
+/// Trim an emoji status; an empty one clears it
+pub fn normalize_emoji_status(raw: &str) -> AppResult<String> {
+    let status = raw.trim();
+
+    if status.chars().count() > 32 || !(status.is_empty() || is_emoji_sequence(status)) {
+        return Err(AppError::Validation(
+            "Emoji status must be a short emoji sequence".to_string(),
+        ));
+    }
+
+    Ok(status.to_string())
+}
+
+/// Only pictographs and what builds emoji out of them: joiners, variation
+/// selectors, skin tones, flags and tag sequences, and keycaps
+fn is_emoji_sequence(text: &str) -> bool {
+    let mut chars = text.chars().peekable();
+    let mut pictographs = 0;
+    while let Some(c) = chars.next() {
+        match c as u32 {
+            0x00A9 | 0x00AE | 0x203C | 0x2049 | 0x2122 | 0x2139 | 0x2194..=0x21AA
+            | 0x231A..=0x23FF | 0x24C2 | 0x25AA..=0x27BF | 0x2934..=0x2935
+            | 0x2B05..=0x2B55 | 0x3030 | 0x303D | 0x3297 | 0x3299 | 0x1F000..=0x1FAFF => {
+                pictographs += 1
+            }
+            0x200D | 0xFE0E | 0xFE0F | 0xE0020..=0xE007F => {}
+            // Keycaps: a digit, # or * followed by the combining keycap
+            _ if c.is_ascii_digit() || c == '#' || c == '*' => {
+                chars.next_if_eq(&'\u{FE0F}');
+                if chars.next_if_eq(&'\u{20E3}').is_none() {
+                    return false;
+                }
+                pictographs += 1;
+            }
+            _ => return false,
+        }
+    }
+    pictographs > 0
+}
+
+/// Trim pronouns; empty ones clear them
+pub fn normalize_pronouns(raw: &str) -> AppResult<String> {
+    let pronouns = raw.trim();
+
+    if pronouns.chars().count() > 32 {
+        return Err(AppError::Validation(
+            "Pronouns must be at most 32 characters".to_string(),
+        ));
+    }
+
+    Ok(pronouns.to_string())
+}
+
+/// Check profile links are http(s) URLs, dropping blanks and duplicates
+pub fn normalize_links(raw: Vec<String>) -> AppResult<Vec<String>> {
+    let mut links: Vec<String> = Vec::new();
+
+    for link in raw {
+        let link = link.trim();
+        if link.is_empty() || links.iter().any(|l| l == link) {
+            continue;
+        }
+
+        let valid = link.len() <= MAX_LINK_LENGTH
+            && reqwest::Url::parse(link)
+                .map(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some())
+                .unwrap_or(false);
+        if !valid {
+            return Err(AppError::Validation(format!(
+                "Invalid profile link: {}",
+                link
+            )));
+        }
+
+        links.push(link.to_string());
+    }
+
+    if links.len() > MAX_PROFILE_LINKS {
+        return Err(AppError::Validation(format!(
+            "At most {} profile links are allowed",
+            MAX_PROFILE_LINKS
+        )));
+    }
+
+    Ok(links)
+}
+
+/// Fans profile changes out as `profile_updated` events
+pub struct ProfileService {
+    db: PgPool,
+    redis: RedisClient,
+}
+
+impl ProfileService {
+    pub fn new(db: PgPool, redis: RedisClient) -> Self {
+        Self { db, redis }
+    }
+
+    /// Send the full profile to the user's own devices and the public part
+    /// to everyone who has them as a contact or shares a conversation
+    pub async fn publish_update(&self, user: &User) -> AppResult<()> {
+        let own = json!({
+            "type": "profile_updated",
+            "payload": user,
+        });
+        self.redis
+            .publish_message(&user.id.to_string(), &own.to_string())
+            .await?;
+
+        let profile_updated = json!({
+            "type": "profile_updated",
+            "payload": PublicProfile::from(user),
+        })
+        .to_string();
+        for watcher_id in self.watchers(user.id).await? {
+            self.redis
+                .publish_message(&watcher_id.to_string(), &profile_updated)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Tell everyone who has the user as a contact or shares a conversation
+    /// that the account was deactivated or is back
+    pub async fn publish_deactivation(&self, user_id: Uuid, deactivated: bool) -> AppResult<()> {
+        let event = json!({
+            "type": if deactivated { "account_deactivated" } else { "account_reactivated" },
+            "payload": { "user_id": user_id },
+        })
+        .to_string();
+        for watcher_id in self.watchers(user_id).await? {
+            self.redis
+                .publish_message(&watcher_id.to_string(), &event)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn watchers(&self, user_id: Uuid) -> AppResult<Vec<Uuid>> {
+        let watcher_ids = sqlx::query_scalar(
+            r#"
+            SELECT user_id FROM contacts WHERE contact_id = $1 AND is_blocked = false
+            UNION
+            SELECT p2.user_id FROM participants p1
+            JOIN participants p2 ON p2.conversation_id = p1.conversation_id
+            WHERE p1.user_id = $1 AND p1.left_at IS NULL AND p1.is_request = false
+            AND p2.user_id != $1 AND p2.left_at IS NULL AND p2.is_request = false
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(watcher_ids)
+    }
+
+    /// Clear emoji statuses whose expiry has passed and announce the change
+    pub async fn expire_emoji_statuses(&self) -> AppResult<usize> {
+        let users: Vec<User> = sqlx::query_as(
+            r#"
+            UPDATE users
+            SET emoji_status = NULL, emoji_status_expires_at = NULL, updated_at = NOW()
+            WHERE emoji_status_expires_at <= NOW()
+            RETURNING *
+            "#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        for user in &users {
+            self.publish_update(user).await?;
+        }
+
+        Ok(users.len())
+    }
+}
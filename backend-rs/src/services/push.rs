@@ -0,0 +1,146 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    config::NotificationConfig,
+    error::AppResult,
+    models::Device,
+    services::web_push::{WebPushProvider, WebPushSubscription},
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PushNotification {
+    pub title: String,
+    pub body: String,
+    pub data: serde_json::Value,
+    /// Pushes with the same key replace each other on the device instead of
+    /// piling up
+    pub collapse_key: Option<String>,
+    /// Groups pushes on the device, as the APNs `thread-id`, without
+    /// replacing any of them
+    pub thread_id: Option<String>,
+    /// The recipient's app icon badge; filled in when the push is sent
+    pub badge: Option<i64>,
+}
+
+/// Delivers notifications to a device's registered push token
+#[async_trait]
+pub trait PushProvider: Send + Sync {
+    async fn send(&self, device: &Device, notification: &PushNotification) -> AppResult<()>;
+}
+
+/// Development provider that only logs notifications
+pub struct LogPushProvider;
+
+#[async_trait]
+impl PushProvider for LogPushProvider {
+    async fn send(&self, device: &Device, notification: &PushNotification) -> AppResult<()> {
+        tracing::info!(
+            "Push to {}:{} ({}): {} - {}",
+            device.user_id,
+            device.device_id,
+            device.platform,
+            notification.title,
+            notification.body
+        );
+        Ok(())
+    }
+}
+
+/// Firebase Cloud Messaging provider (Android and iOS via FCM)
+pub struct FcmPushProvider {
+    http: reqwest::Client,
+    server_key: String,
+}
+
+impl FcmPushProvider {
+    pub fn new(server_key: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            server_key,
+        }
+    }
+}
+
+#[async_trait]
+impl PushProvider for FcmPushProvider {
+    async fn send(&self, device: &Device, notification: &PushNotification) -> AppResult<()> {
+        let Some(push_token) = device.push_token.as_deref() else {
+            return Ok(());
+        };
+
+        let mut body = json!({
+            "to": push_token,
+            "collapse_key": notification.collapse_key,
+            "notification": {
+                "title": notification.title,
+                "body": notification.body,
+                "tag": notification.collapse_key,
+                "badge": notification.badge.map(|badge| badge.to_string()),
+            },
+            "data": notification.data,
+        });
+        if let Some(thread_id) = &notification.thread_id {
+            body["apns"] = json!({ "payload": { "aps": { "thread-id": thread_id } } });
+        }
+
+        let response = self
+            .http
+            .post("https://fcm.googleapis.com/fcm/send")
+            .header("Authorization", format!("key={}", self.server_key))
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send push: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("FCM returned {}", response.status()).into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends to browser subscriptions through Web Push and to every other push
+/// token through the native provider
+pub struct RoutingPushProvider {
+    native: Arc<dyn PushProvider>,
+    web: Option<WebPushProvider>,
+}
+
+#[async_trait]
+impl PushProvider for RoutingPushProvider {
+    async fn send(&self, device: &Device, notification: &PushNotification) -> AppResult<()> {
+        let is_web = device
+            .push_token
+            .as_deref()
+            .and_then(WebPushSubscription::from_push_token)
+            .is_some();
+
+        match (is_web, &self.web) {
+            (true, Some(web)) => web.send(device, notification).await,
+            (true, None) => LogPushProvider.send(device, notification).await,
+            (false, _) => self.native.send(device, notification).await,
+        }
+    }
+}
+
+pub fn build_push_provider(config: &NotificationConfig) -> Arc<dyn PushProvider> {
+    let native: Arc<dyn PushProvider> =
+        match (config.push_provider.as_str(), &config.fcm_server_key) {
+            ("fcm", Some(key)) => Arc::new(FcmPushProvider::new(key.clone())),
+            ("fcm", None) => {
+                tracing::warn!("PUSH_PROVIDER=fcm but FCM_SERVER_KEY is not set, logging pushes");
+                Arc::new(LogPushProvider)
+            }
+            _ => Arc::new(LogPushProvider),
+        };
+
+    Arc::new(RoutingPushProvider {
+        native,
+        web: WebPushProvider::from_config(config),
+    })
+}
@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use crate::{
+    error::AppResult,
+    models::{VelocityCounter, VelocityKind},
+    storage::redis::RedisClient,
+};
+
+/// Registrations are counted per hour whatever `RATE_LIMIT_WINDOW` is
+const VELOCITY_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+const VELOCITY_SCOPE: &str = "register_velocity";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RateDecision {
+    Allow,
+    /// Suspicious volume: allow only with a solved CAPTCHA
+    Challenge,
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitRule {
+    pub window: Duration,
+    pub challenge_after: u32,
+    pub max: u32,
+}
+
+impl RateLimitRule {
+    /// Decision for the `count`th hit in the window
+    fn decide(&self, count: i64) -> RateDecision {
+        if count > self.max as i64 {
+            RateDecision::Deny
+        } else if count > self.challenge_after as i64 {
+            RateDecision::Challenge
+        } else {
+            RateDecision::Allow
+        }
+    }
+}
+
+pub struct RateLimiter {
+    redis: RedisClient,
+}
+
+impl RateLimiter {
+    pub fn new(redis: RedisClient) -> Self {
+        Self { redis }
+    }
+
+    /// Count a hit against every key and return the strictest decision
+    pub async fn hit(
+        &self,
+        scope: &str,
+        keys: &[&str],
+        rule: RateLimitRule,
+    ) -> AppResult<RateDecision> {
+        let mut decision = RateDecision::Allow;
+
+        for key in keys {
+            let count = self
+                .redis
+                .incr_rate_limit(&format!("{}:{}", scope, key), rule.window)
+                .await?;
+
+            decision = decision.max(rule.decide(count));
+        }
+
+        Ok(decision)
+    }
+}
+
+/// Completed registrations per client IP and device fingerprint in a fixed
+/// hourly window. Checking does not count; only a registration that went
+/// through does, so failed attempts are left to [`RateLimiter`].
+pub struct RegistrationVelocity {
+    redis: RedisClient,
+}
+
+impl RegistrationVelocity {
+    pub fn new(redis: RedisClient) -> Self {
+        Self { redis }
+    }
+
+    fn keys<'a>(
+        ip: &'a str,
+        fingerprint: Option<&'a str>,
+    ) -> impl Iterator<Item = (VelocityKind, &'a str)> {
+        std::iter::once((VelocityKind::Ip, ip))
+            .chain(fingerprint.map(|f| (VelocityKind::Device, f)))
+            .filter(|(_, value)| !value.is_empty())
+    }
+
+    fn key(kind: VelocityKind, value: &str) -> String {
+        format!("{}:{}:{}", VELOCITY_SCOPE, kind.as_str(), value)
+    }
+
+    /// The strictest decision for one more registration from this IP and
+    /// fingerprint
+    pub async fn check(
+        &self,
+        ip: &str,
+        fingerprint: Option<&str>,
+        challenge_after: u32,
+        max: u32,
+    ) -> AppResult<RateDecision> {
+        let rule = RateLimitRule {
+            window: VELOCITY_WINDOW,
+            challenge_after,
+            max,
+        };
+        let mut decision = RateDecision::Allow;
+
+        for (kind, value) in Self::keys(ip, fingerprint) {
+            let count = self
+                .redis
+                .get_rate_limit(&Self::key(kind, value))
+                .await?
+                .map_or(0, |(count, _)| count);
+            decision = decision.max(rule.decide(count + 1));
+        }
+
+        Ok(decision)
+    }
+
+    pub async fn record(&self, ip: &str, fingerprint: Option<&str>) -> AppResult<()> {
+        for (kind, value) in Self::keys(ip, fingerprint) {
+            self.redis
+                .incr_rate_limit(&Self::key(kind, value), VELOCITY_WINDOW)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Every live counter, busiest first
+    pub async fn counters(&self) -> AppResult<Vec<VelocityCounter>> {
+        let mut counters = Vec::new();
+        for key in self.redis.list_rate_limits(&format!("{}:", VELOCITY_SCOPE)).await? {
+            let (kind, value) = match key.split_once(':') {
+                Some(("ip", value)) => (VelocityKind::Ip, value),
+                Some(("device", value)) => (VelocityKind::Device, value),
+                _ => continue,
+            };
+            if let Some(counter) = self.counter(kind, value).await? {
+                counters.push(counter);
+            }
+        }
+        counters.sort_by_key(|c| std::cmp::Reverse(c.count));
+
+        Ok(counters)
+    }
+
+    pub async fn counter(
+        &self,
+        kind: VelocityKind,
+        value: &str,
+    ) -> AppResult<Option<VelocityCounter>> {
+        let counter = self
+            .redis
+            .get_rate_limit(&Self::key(kind, value))
+            .await?
+            .map(|(count, expires_in)| VelocityCounter {
+                kind,
+                value: value.to_string(),
+                count,
+                expires_in,
+            });
+
+        Ok(counter)
+    }
+
+    /// Reset a counter; returns false if there was none
+    pub async fn clear(&self, kind: VelocityKind, value: &str) -> AppResult<bool> {
+        self.redis.delete_rate_limit(&Self::key(kind, value)).await
+    }
+}
@@ -0,0 +1,257 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, AppResult},
+    models::{Message, MessageReport, ReportStatus, ReportVerification},
+    services::{
+        franking::{opens_commitment, Franking},
+        moderation::ModerationService,
+    },
+    storage::redis::RedisClient,
+};
+
+/// Reasons a message can be reported for
+pub const REPORT_REASONS: [&str; 8] = [
+    "spam",
+    "harassment",
+    "hate",
+    "violence",
+    "sexual",
+    "self_harm",
+    "illegal",
+    "other",
+];
+
+/// Longest reporter's note, in characters
+const MAX_DETAILS_CHARS: usize = 1000;
+
+/// Largest plaintext a reporter can disclose
+const MAX_DISCLOSED_BYTES: usize = 64 * 1024;
+
+/// Recipients' reports of messages and their review by moderators
+pub struct ReportService {
+    db: PgPool,
+    redis: RedisClient,
+    franking: Franking,
+}
+
+impl ReportService {
+    pub fn new(db: PgPool, redis: RedisClient, config: &Config) -> Self {
+        Self {
+            db,
+            redis,
+            franking: Franking::new(config),
+        }
+    }
+
+    /// Report a message received in a conversation the user is or was in.
+    /// Encrypted messages need their plaintext and franking key, which must
+    /// open the commitment the sender made; unencrypted ones are reported
+    /// with the server's copy.
+    pub async fn report(
+        &self,
+        reporter_id: Uuid,
+        message_id: Uuid,
+        reason: &str,
+        details: Option<String>,
+        plaintext: Option<Vec<u8>>,
+        franking_key: Option<Vec<u8>>,
+    ) -> AppResult<MessageReport> {
+        if !REPORT_REASONS.contains(&reason) {
+            return Err(AppError::Validation(format!(
+                "Reason must be one of {}",
+                REPORT_REASONS.join(", ")
+            )));
+        }
+        let details = details
+            .map(|d| d.trim().to_string())
+            .filter(|d| !d.is_empty());
+        if details
+            .as_ref()
+            .is_some_and(|d| d.chars().count() > MAX_DETAILS_CHARS)
+        {
+            return Err(AppError::Validation(format!(
+                "Details must be at most {} characters",
+                MAX_DETAILS_CHARS
+            )));
+        }
+
+        let message: Message = sqlx::query_as(
+            r#"
+            SELECT m.* FROM messages m
+            JOIN participants p ON p.conversation_id = m.conversation_id AND p.user_id = $2
+            WHERE m.id = $1 AND NOT m.is_shadowed
+            "#,
+        )
+        .bind(message_id)
+        .bind(reporter_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::MessageNotFound)?;
+        let message = message.unpad();
+        if message.sender_id == reporter_id {
+            return Err(AppError::Validation(
+                "You cannot report your own message".to_string(),
+            ));
+        }
+
+        let (content, franking_key) = if message.encrypted {
+            let Some(tag) = &message.franking_tag else {
+                return Err(AppError::Validation(
+                    "This message was sent without a franking tag and cannot be reported"
+                        .to_string(),
+                ));
+            };
+            let (Some(plaintext), Some(key)) = (plaintext, franking_key) else {
+                return Err(AppError::Validation(
+                    "Reporting an encrypted message needs its plaintext and franking_key"
+                        .to_string(),
+                ));
+            };
+            if plaintext.len() > MAX_DISCLOSED_BYTES {
+                return Err(AppError::MessageTooLarge(MAX_DISCLOSED_BYTES));
+            }
+            if !opens_commitment(tag, &key, &plaintext) {
+                return Err(AppError::Validation(
+                    "The plaintext and franking key do not match the message".to_string(),
+                ));
+            }
+            (Some(plaintext), Some(key))
+        } else {
+            (Some(message.content).filter(|c| !c.is_empty()), None)
+        };
+
+        let report: MessageReport = sqlx::query_as(
+            r#"
+            INSERT INTO message_reports (id, message_id, conversation_id, reporter_id, sender_id,
+                reason, details, content, franking_key, franking_tag, franking_signature)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (message_id, reporter_id) DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(message.id)
+        .bind(message.conversation_id)
+        .bind(reporter_id)
+        .bind(message.sender_id)
+        .bind(reason)
+        .bind(&details)
+        .bind(content)
+        .bind(franking_key)
+        .bind(&message.franking_tag)
+        .bind(&message.franking_signature)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::MessageAlreadyReported)?;
+
+        ModerationService::new(self.db.clone(), self.redis.clone())
+            .create_alert(
+                message.sender_id,
+                Some(message.conversation_id),
+                "message_report",
+                Some(format!("Report {} for {}", report.id, reason)),
+            )
+            .await?;
+
+        Ok(report)
+    }
+
+    /// Reports with a status, or all of them, newest first
+    pub async fn list(
+        &self,
+        status: Option<ReportStatus>,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<MessageReport>> {
+        let reports = sqlx::query_as(
+            r#"
+            SELECT * FROM message_reports
+            WHERE $1::report_status IS NULL OR status = $1
+            ORDER BY created_at DESC
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(status)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(reports)
+    }
+
+    /// Check a report's evidence from what the report itself holds, so it
+    /// can be checked after the message is gone
+    pub async fn verify(&self, report_id: Uuid) -> AppResult<ReportVerification> {
+        let report = self.get(report_id).await?;
+
+        let franked = report.franking_tag.is_some();
+        let commitment_opens = match (&report.franking_tag, &report.franking_key) {
+            (Some(tag), Some(key)) => {
+                opens_commitment(tag, key, report.content.as_deref().unwrap_or_default())
+            }
+            _ => false,
+        };
+        let signature_valid = match (&report.franking_tag, &report.franking_signature) {
+            (Some(tag), Some(signature)) => self.franking.verify_signature(
+                report.message_id,
+                report.conversation_id,
+                report.sender_id,
+                tag,
+                signature,
+            ),
+            _ => false,
+        };
+
+        Ok(ReportVerification {
+            report_id,
+            franked,
+            commitment_opens,
+            signature_valid,
+            verified: !franked || (commitment_opens && signature_valid),
+        })
+    }
+
+    /// Close a report as actioned or dismissed
+    pub async fn resolve(
+        &self,
+        report_id: Uuid,
+        admin_id: Uuid,
+        status: ReportStatus,
+    ) -> AppResult<MessageReport> {
+        if status == ReportStatus::Open {
+            return Err(AppError::Validation(
+                "Status must be actioned or dismissed".to_string(),
+            ));
+        }
+
+        let report = sqlx::query_as(
+            r#"
+            UPDATE message_reports SET status = $2, resolved_by = $3, resolved_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(report_id)
+        .bind(status)
+        .bind(admin_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::MessageReportNotFound)?;
+
+        Ok(report)
+    }
+
+    async fn get(&self, report_id: Uuid) -> AppResult<MessageReport> {
+        let report = sqlx::query_as("SELECT * FROM message_reports WHERE id = $1")
+            .bind(report_id)
+            .fetch_optional(&self.db)
+            .await?
+            .ok_or(AppError::MessageReportNotFound)?;
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,313 @@
+use std::{collections::HashSet, time::Duration};
+
+use base64::{
+    engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD},
+    Engine,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, AppResult},
+    models::{DeliveryCertificate, OutgoingEnvelope, SealedEnvelope, SenderCertificate},
+    services::messaging::WsMessage,
+    storage::redis::RedisClient,
+};
+
+/// Random bytes naming a delivery token in rate-limit counters
+const TOKEN_ID_LEN: usize = 16;
+
+/// Token ID, expiry (big-endian Unix seconds) and HMAC-SHA256 over both
+const TOKEN_LEN: usize = TOKEN_ID_LEN + 8 + 32;
+
+/// Recipients' unidentified access keys are 16 random bytes
+const ACCESS_KEY_LEN: usize = 16;
+
+/// Envelopes not fetched within this many days are dropped
+const RETENTION_DAYS: i32 = 30;
+
+/// Sealed-sender delivery, after Signal's. The sender proves to the server
+/// only that it holds a delivery token issued to some user and the
+/// recipient's access key; who it is travels inside the envelope as a
+/// server-signed certificate only the recipient can read.
+pub struct SealedSenderService {
+    db: PgPool,
+    redis: RedisClient,
+    config: Config,
+}
+
+impl SealedSenderService {
+    pub fn new(db: PgPool, redis: RedisClient, config: Config) -> Self {
+        Self { db, redis, config }
+    }
+
+    /// A certificate naming the device and an unlinked delivery token, both
+    /// valid for `SEALED_SENDER_CERTIFICATE_TTL`. Issuing is limited per user
+    /// since tokens are not.
+    pub async fn issue_certificate(
+        &self,
+        user_id: Uuid,
+        device_id: i32,
+    ) -> AppResult<DeliveryCertificate> {
+        let sealed_sender = &self.config.sealed_sender;
+        let issued = self
+            .redis
+            .incr_rate_limit(
+                &format!("sealed_cert:{}", user_id),
+                Duration::from_secs(24 * 60 * 60),
+            )
+            .await?;
+        if issued > sealed_sender.certificates_per_day as i64 {
+            return Err(AppError::TooManyAttempts);
+        }
+
+        let expires = Utc::now().timestamp() + sealed_sender.certificate_ttl.as_secs() as i64;
+        let certificate = serde_json::to_vec(&SenderCertificate {
+            sender_id: user_id,
+            sender_device: device_id,
+            expires,
+        })?;
+        let key = self.signing_key()?;
+
+        let mut token = vec![0u8; TOKEN_ID_LEN];
+        rand::thread_rng().fill_bytes(&mut token);
+        token.extend_from_slice(&expires.to_be_bytes());
+        let mac = self.token_mac(&token).finalize().into_bytes();
+        token.extend_from_slice(&mac);
+
+        Ok(DeliveryCertificate {
+            certificate: BASE64.encode(&certificate),
+            signature: BASE64.encode(key.sign(&certificate)),
+            server_key: BASE64.encode(key.public_key()),
+            delivery_token: URL_SAFE_NO_PAD.encode(&token),
+            expires_at: DateTime::from_timestamp(expires, 0).unwrap_or_default(),
+        })
+    }
+
+    /// Set the key senders must present, or `None` to refuse sealed messages
+    pub async fn set_access_key(&self, user_id: Uuid, key: Option<&str>) -> AppResult<()> {
+        let hash = key
+            .map(|key| {
+                BASE64
+                    .decode(key)
+                    .ok()
+                    .filter(|key| key.len() == ACCESS_KEY_LEN)
+                    .map(|key| Sha256::digest(key).to_vec())
+                    .ok_or_else(|| {
+                        AppError::Validation(format!(
+                            "Access key must be {} bytes, base64",
+                            ACCESS_KEY_LEN
+                        ))
+                    })
+            })
+            .transpose()?;
+
+        sqlx::query("UPDATE users SET unidentified_access_key = $2 WHERE id = $1")
+            .bind(user_id)
+            .bind(hash)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Queue one envelope per recipient device and hand each to its device if
+    /// connected. Nothing about the sender is checked or kept beyond the
+    /// token's rate-limit counter.
+    pub async fn submit(
+        &self,
+        recipient_id: Uuid,
+        delivery_token: &str,
+        access_key: &str,
+        envelopes: Vec<OutgoingEnvelope>,
+    ) -> AppResult<Vec<Uuid>> {
+        let token_id = self.verify_token(delivery_token)?;
+
+        // Unknown recipients look the same as a wrong key
+        let stored: Option<Option<Vec<u8>>> =
+            sqlx::query_scalar("SELECT unidentified_access_key FROM users WHERE id = $1")
+                .bind(recipient_id)
+                .fetch_optional(&self.db)
+                .await?;
+        let presented = BASE64
+            .decode(access_key)
+            .map(|key| Sha256::digest(key).to_vec());
+        match (stored.flatten(), presented) {
+            (Some(stored), Ok(presented)) if stored == presented => {}
+            _ => return Err(AppError::Unauthorized),
+        }
+
+        let spam = &self.config.spam;
+        let count = self
+            .redis
+            .incr_rate_limit(&format!("sealed:{}", token_id), spam.message_window)
+            .await?;
+        if count > spam.message_max as i64 {
+            return Err(AppError::MessageRateLimited);
+        }
+
+        let devices: HashSet<i32> =
+            sqlx::query_scalar("SELECT device_id FROM devices WHERE user_id = $1")
+                .bind(recipient_id)
+                .fetch_all(&self.db)
+                .await?
+                .into_iter()
+                .collect();
+        let mut seen = HashSet::new();
+        if envelopes.is_empty()
+            || envelopes
+                .iter()
+                .any(|e| e.content.is_empty() || !seen.insert(e.device_id))
+        {
+            return Err(AppError::Validation(
+                "One non-empty envelope per device is required".to_string(),
+            ));
+        }
+        if let Some(envelope) = envelopes.iter().find(|e| !devices.contains(&e.device_id)) {
+            return Err(AppError::Validation(format!(
+                "Recipient has no device {}",
+                envelope.device_id
+            )));
+        }
+
+        let mut tx = self.db.begin().await?;
+        let mut stored = Vec::with_capacity(envelopes.len());
+        for envelope in envelopes {
+            let envelope: SealedEnvelope = sqlx::query_as(
+                r#"
+                INSERT INTO sealed_envelopes (id, recipient_id, device_id, content)
+                VALUES ($1, $2, $3, $4)
+                RETURNING id, device_id, content, created_at
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(recipient_id)
+            .bind(envelope.device_id)
+            .bind(&envelope.content)
+            .fetch_one(&mut *tx)
+            .await?;
+            stored.push(envelope);
+        }
+        tx.commit().await?;
+
+        let recipient = recipient_id.to_string();
+        for envelope in &stored {
+            let ws_message = WsMessage {
+                msg_type: "sealed_message".to_string(),
+                payload: serde_json::to_value(envelope)?,
+            };
+            self.redis
+                .publish_device_message(
+                    &recipient,
+                    envelope.device_id,
+                    &serde_json::to_string(&ws_message)?,
+                )
+                .await?;
+        }
+
+        Ok(stored.into_iter().map(|e| e.id).collect())
+    }
+
+    /// Envelopes waiting for a device, oldest first
+    pub async fn pending(&self, user_id: Uuid, device_id: i32) -> AppResult<Vec<SealedEnvelope>> {
+        let envelopes = sqlx::query_as(
+            r#"
+            SELECT id, device_id, content, created_at FROM sealed_envelopes
+            WHERE recipient_id = $1 AND device_id = $2
+            ORDER BY created_at, id
+            "#,
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(envelopes)
+    }
+
+    /// Drop envelopes the device has decrypted
+    pub async fn ack(
+        &self,
+        user_id: Uuid,
+        device_id: i32,
+        envelope_ids: &[Uuid],
+    ) -> AppResult<u64> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM sealed_envelopes
+            WHERE id = ANY($1) AND recipient_id = $2 AND device_id = $3
+            "#,
+        )
+        .bind(envelope_ids)
+        .bind(user_id)
+        .bind(device_id)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn purge_expired(&self) -> AppResult<u64> {
+        let result = sqlx::query(
+            "DELETE FROM sealed_envelopes WHERE created_at < NOW() - make_interval(days => $1)",
+        )
+        .bind(RETENTION_DAYS)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// The token's ID if it was issued here and has not expired
+    fn verify_token(&self, token: &str) -> AppResult<String> {
+        let token = URL_SAFE_NO_PAD
+            .decode(token)
+            .ok()
+            .filter(|t| t.len() == TOKEN_LEN)
+            .ok_or(AppError::DeliveryTokenInvalid)?;
+        let (payload, mac) = token.split_at(TOKEN_ID_LEN + 8);
+        self.token_mac(payload)
+            .verify_slice(mac)
+            .map_err(|_| AppError::DeliveryTokenInvalid)?;
+
+        let (id, expires) = payload.split_at(TOKEN_ID_LEN);
+        let expires = i64::from_be_bytes(expires.try_into().unwrap_or_default());
+        if expires < Utc::now().timestamp() {
+            return Err(AppError::DeliveryTokenInvalid);
+        }
+
+        Ok(URL_SAFE_NO_PAD.encode(id))
+    }
+
+    fn token_mac(&self, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.derive_key(b"delivery-token"))
+            .expect("HMAC accepts keys of any length");
+        mac.update(payload);
+        mac
+    }
+
+    /// Every instance derives the same key pair from the secret
+    fn signing_key(&self) -> AppResult<Ed25519KeyPair> {
+        Ed25519KeyPair::from_seed_unchecked(&self.derive_key(b"sender-certificate"))
+            .map_err(|e| anyhow::anyhow!("Failed to derive certificate key: {}", e).into())
+    }
+
+    fn derive_key(&self, label: &[u8]) -> [u8; 32] {
+        let secret = self
+            .config
+            .sealed_sender
+            .secret
+            .as_deref()
+            .unwrap_or(&self.config.jwt.secret);
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(label);
+        mac.finalize().into_bytes().into()
+    }
+}
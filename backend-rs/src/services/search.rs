@@ -0,0 +1,471 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::SearchConfig,
+    error::{AppError, AppResult},
+    models::{Message, MessageType},
+};
+
+/// Escape `%`, `_`, and `\` so user input matches literally inside a LIKE pattern
+pub fn escape_like(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The searchable form of a message
+#[derive(Debug, Clone)]
+pub struct SearchDocument {
+    pub message_id: Uuid,
+    pub conversation_id: Uuid,
+    pub sender_id: Uuid,
+    pub text: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SearchDocument {
+    /// Only plaintext text messages can be indexed; encrypted payloads and
+    /// media are skipped
+    pub fn from_message(message: &Message) -> Option<Self> {
+        if message.message_type != MessageType::Text
+            || message.encrypted
+            || message.deleted_at.is_some()
+        {
+            return None;
+        }
+
+        let text = std::str::from_utf8(&message.content).ok()?.trim();
+        if text.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            message_id: message.id,
+            conversation_id: message.conversation_id,
+            sender_id: message.sender_id,
+            text: text.to_string(),
+            created_at: message.created_at,
+        })
+    }
+}
+
+/// Backing store for in-conversation message search
+#[async_trait]
+pub trait SearchIndex: Send + Sync {
+    /// One-time setup at startup (index settings, etc.)
+    async fn prepare(&self) -> AppResult<()> {
+        Ok(())
+    }
+
+    async fn upsert(&self, document: &SearchDocument) -> AppResult<()>;
+
+    async fn remove(&self, message_id: Uuid) -> AppResult<()>;
+
+    /// Drop every document, or only those of one conversation
+    async fn clear(&self, conversation_id: Option<Uuid>) -> AppResult<()>;
+
+    /// Matching message IDs in any of the conversations, best match first
+    async fn search(
+        &self,
+        conversation_ids: &[Uuid],
+        query: &str,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<Uuid>>;
+}
+
+/// Postgres full-text search over the `message_search_index` table
+pub struct PostgresSearchIndex {
+    db: PgPool,
+}
+
+impl PostgresSearchIndex {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl SearchIndex for PostgresSearchIndex {
+    async fn upsert(&self, document: &SearchDocument) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO message_search_index (message_id, conversation_id, document, created_at)
+            VALUES ($1, $2, to_tsvector('simple', $3), $4)
+            ON CONFLICT (message_id) DO UPDATE SET document = EXCLUDED.document
+            "#,
+        )
+        .bind(document.message_id)
+        .bind(document.conversation_id)
+        .bind(&document.text)
+        .bind(document.created_at)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove(&self, message_id: Uuid) -> AppResult<()> {
+        sqlx::query("DELETE FROM message_search_index WHERE message_id = $1")
+            .bind(message_id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn clear(&self, conversation_id: Option<Uuid>) -> AppResult<()> {
+        sqlx::query(
+            "DELETE FROM message_search_index WHERE $1::uuid IS NULL OR conversation_id = $1",
+        )
+        .bind(conversation_id)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        conversation_ids: &[Uuid],
+        query: &str,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<Uuid>> {
+        let ids: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT message_id FROM message_search_index
+            WHERE conversation_id = ANY($1) AND document @@ websearch_to_tsquery('simple', $2)
+            ORDER BY ts_rank(document, websearch_to_tsquery('simple', $2)) DESC, created_at DESC
+            LIMIT $3 OFFSET $4
+            "#,
+        )
+        .bind(conversation_ids)
+        .bind(query)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(ids)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MeilisearchHit {
+    id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+struct MeilisearchResponse {
+    hits: Vec<MeilisearchHit>,
+}
+
+/// External Meilisearch index
+pub struct MeilisearchIndex {
+    http: reqwest::Client,
+    url: String,
+    index: String,
+    api_key: Option<String>,
+}
+
+impl MeilisearchIndex {
+    pub fn new(url: String, index: String, api_key: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            index,
+            api_key,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let url = format!("{}/indexes/{}{}", self.url, self.index, path);
+        let request = self.http.request(method, url);
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> AppResult<reqwest::Response> {
+        let response = request
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Meilisearch request failed: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("Meilisearch returned {}", response.status()).into());
+        }
+
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl SearchIndex for MeilisearchIndex {
+    async fn prepare(&self) -> AppResult<()> {
+        let settings = serde_json::json!({
+            "filterableAttributes": ["conversation_id"],
+            "sortableAttributes": ["created_at"],
+        });
+        self.send(
+            self.request(reqwest::Method::PATCH, "/settings")
+                .json(&settings),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn upsert(&self, document: &SearchDocument) -> AppResult<()> {
+        let body = serde_json::json!([{
+            "id": document.message_id,
+            "conversation_id": document.conversation_id,
+            "sender_id": document.sender_id,
+            "text": document.text,
+            "created_at": document.created_at.timestamp(),
+        }]);
+        self.send(
+            self.request(reqwest::Method::POST, "/documents?primaryKey=id")
+                .json(&body),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove(&self, message_id: Uuid) -> AppResult<()> {
+        self.send(self.request(
+            reqwest::Method::DELETE,
+            &format!("/documents/{}", message_id),
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn clear(&self, conversation_id: Option<Uuid>) -> AppResult<()> {
+        let request = match conversation_id {
+            Some(id) => self
+                .request(reqwest::Method::POST, "/documents/delete")
+                .json(&serde_json::json!({ "filter": format!("conversation_id = '{}'", id) })),
+            None => self.request(reqwest::Method::DELETE, "/documents"),
+        };
+        self.send(request).await?;
+
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        conversation_ids: &[Uuid],
+        query: &str,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<Uuid>> {
+        let conversation_ids: Vec<String> = conversation_ids
+            .iter()
+            .map(|id| format!("'{}'", id))
+            .collect();
+        let body = serde_json::json!({
+            "q": query,
+            "filter": format!("conversation_id IN [{}]", conversation_ids.join(", ")),
+            "limit": limit,
+            "offset": offset,
+            "attributesToRetrieve": ["id"],
+        });
+        let response: MeilisearchResponse = self
+            .send(self.request(reqwest::Method::POST, "/search").json(&body))
+            .await?
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("Invalid Meilisearch response: {}", e))?;
+
+        Ok(response.hits.into_iter().map(|hit| hit.id).collect())
+    }
+}
+
+pub fn build_search_index(db: &PgPool, config: &SearchConfig) -> Option<Arc<dyn SearchIndex>> {
+    match config.provider.as_str() {
+        "none" => None,
+        "postgres" => Some(Arc::new(PostgresSearchIndex::new(db.clone()))),
+        "meilisearch" => match &config.meilisearch_url {
+            Some(url) => Some(Arc::new(MeilisearchIndex::new(
+                url.clone(),
+                config.meilisearch_index.clone(),
+                config.meilisearch_api_key.clone(),
+            ))),
+            None => {
+                tracing::warn!("SEARCH_PROVIDER=meilisearch but MEILISEARCH_URL is not set");
+                None
+            }
+        },
+        other => {
+            tracing::warn!("Unknown search provider: {}", other);
+            None
+        }
+    }
+}
+
+/// Message search and index maintenance
+pub struct SearchService {
+    db: PgPool,
+    index: Arc<dyn SearchIndex>,
+}
+
+impl SearchService {
+    pub fn new(db: PgPool, index: Option<Arc<dyn SearchIndex>>) -> AppResult<Self> {
+        let index = index.ok_or(AppError::SearchDisabled)?;
+        Ok(Self { db, index })
+    }
+
+    /// Search one conversation's messages, best match first
+    pub async fn search_messages(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        query: &str,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<Message>> {
+        let cleared_before: Option<DateTime<Utc>> = sqlx::query_scalar(
+            r#"
+            SELECT cleared_before FROM participants
+            WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::NotParticipant)?;
+
+        let ids = self
+            .index
+            .search(&[conversation_id], query, limit, offset)
+            .await?;
+
+        let mut messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT * FROM messages
+            WHERE id = ANY($1) AND conversation_id = $2 AND deleted_at IS NULL
+            AND (NOT is_shadowed OR sender_id = $3)
+            AND ($4::timestamptz IS NULL OR created_at > $4)
+            "#,
+        )
+        .bind(&ids)
+        .bind(conversation_id)
+        .bind(user_id)
+        .bind(cleared_before)
+        .fetch_all(&self.db)
+        .await?;
+
+        // Keep the index's relevance order
+        messages.sort_by_key(|m| ids.iter().position(|id| *id == m.id));
+
+        Ok(messages)
+    }
+
+    /// Search the messages of every conversation the user is in, best match
+    /// first. Message requests are left out until accepted.
+    pub async fn search_all_messages(
+        &self,
+        user_id: Uuid,
+        query: &str,
+        limit: i32,
+    ) -> AppResult<Vec<Message>> {
+        let conversation_ids: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT conversation_id FROM participants
+            WHERE user_id = $1 AND left_at IS NULL AND NOT is_request
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+        if conversation_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let ids = self
+            .index
+            .search(&conversation_ids, query, limit, 0)
+            .await?;
+
+        let mut messages: Vec<Message> = sqlx::query_as(
+            r#"
+            SELECT m.* FROM messages m
+            JOIN participants p ON p.conversation_id = m.conversation_id
+                AND p.user_id = $2 AND p.left_at IS NULL AND NOT p.is_request
+            WHERE m.id = ANY($1) AND m.deleted_at IS NULL
+            AND (NOT m.is_shadowed OR m.sender_id = $2)
+            AND (p.cleared_before IS NULL OR m.created_at > p.cleared_before)
+            "#,
+        )
+        .bind(&ids)
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        messages.sort_by_key(|m| ids.iter().position(|id| *id == m.id));
+
+        Ok(messages)
+    }
+
+    /// Re-index every message, or only one conversation's, from the database
+    pub async fn rebuild(&self, conversation_id: Option<Uuid>) -> AppResult<i64> {
+        const BATCH_SIZE: i64 = 500;
+
+        self.index.clear(conversation_id).await?;
+
+        let mut indexed = 0i64;
+        let mut cursor: Option<(DateTime<Utc>, Uuid)> = None;
+
+        loop {
+            let batch: Vec<Message> = sqlx::query_as(
+                r#"
+                SELECT * FROM messages
+                WHERE type = 'text' AND deleted_at IS NULL
+                AND ($1::uuid IS NULL OR conversation_id = $1)
+                AND ($2::timestamptz IS NULL OR (created_at, id) > ($2, $3))
+                ORDER BY created_at, id
+                LIMIT $4
+                "#,
+            )
+            .bind(conversation_id)
+            .bind(cursor.map(|(created_at, _)| created_at))
+            .bind(cursor.map(|(_, id)| id))
+            .bind(BATCH_SIZE)
+            .fetch_all(&self.db)
+            .await?;
+
+            for document in batch.iter().filter_map(SearchDocument::from_message) {
+                self.index.upsert(&document).await?;
+                indexed += 1;
+            }
+
+            match batch.last() {
+                Some(last) if batch.len() as i64 == BATCH_SIZE => {
+                    cursor = Some((last.created_at, last.id));
+                }
+                _ => break,
+            }
+        }
+
+        tracing::info!("Rebuilt search index: {} messages indexed", indexed);
+
+        Ok(indexed)
+    }
+}
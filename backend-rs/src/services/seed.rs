@@ -0,0 +1,377 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{ConversationEncryption, MessageType},
+    services::{
+        contacts::ContactsService,
+        message_policy::StoredPayload,
+        messaging::{direct_key, MessagingService},
+        stickers::StickersService,
+    },
+};
+
+/// Declarative demo data, loaded with `server --seed <file>`
+#[derive(Debug, Deserialize)]
+pub struct Fixture {
+    #[serde(default)]
+    pub users: Vec<FixtureUser>,
+    #[serde(default)]
+    pub conversations: Vec<FixtureConversation>,
+    #[serde(default)]
+    pub sticker_packs: Vec<FixtureStickerPack>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FixtureUser {
+    pub username: String,
+    pub display_name: String,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub bio: Option<String>,
+    #[serde(default)]
+    pub is_admin: bool,
+    /// Usernames added to this user's contacts
+    #[serde(default)]
+    pub contacts: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FixtureConversationType {
+    Direct,
+    Group,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FixtureConversation {
+    #[serde(rename = "type")]
+    pub conversation_type: FixtureConversationType,
+    /// Group name; ignored for direct conversations
+    pub name: Option<String>,
+    /// Usernames; the first one creates the conversation
+    pub members: Vec<String>,
+    /// Sent in order when the conversation is first created
+    #[serde(default)]
+    pub messages: Vec<FixtureMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FixtureMessage {
+    pub from: String,
+    pub text: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FixtureStickerPack {
+    pub name: String,
+    pub author: String,
+    pub description: Option<String>,
+    #[serde(default = "default_official")]
+    pub official: bool,
+    #[serde(default)]
+    pub animated: bool,
+    pub stickers: Vec<FixtureSticker>,
+}
+
+fn default_official() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FixtureSticker {
+    pub emoji: String,
+    /// Image path, relative to the fixture file
+    pub image: PathBuf,
+}
+
+/// What a seed run created; anything already present is left alone
+#[derive(Debug, Default)]
+pub struct SeedSummary {
+    pub users: usize,
+    pub contacts: usize,
+    pub conversations: usize,
+    pub messages: usize,
+    pub sticker_packs: usize,
+}
+
+/// Loads fixtures through the regular services. Re-running a fixture is safe:
+/// users, conversations and packs that already exist are skipped.
+pub struct SeedService {
+    db: PgPool,
+    messaging: MessagingService,
+    contacts: ContactsService,
+    stickers: StickersService,
+}
+
+impl SeedService {
+    pub fn new(db: PgPool, messaging: MessagingService, stickers: StickersService) -> Self {
+        Self {
+            contacts: ContactsService::new(db.clone()),
+            db,
+            messaging,
+            stickers,
+        }
+    }
+
+    pub fn load(path: &Path) -> AppResult<Fixture> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read fixture {}: {}", path.display(), e))?;
+        let fixture = serde_json::from_str(&data)
+            .map_err(|e| anyhow::anyhow!("Invalid fixture {}: {}", path.display(), e))?;
+        Ok(fixture)
+    }
+
+    /// Apply a fixture; `base_dir` resolves sticker image paths
+    pub async fn apply(&self, fixture: &Fixture, base_dir: &Path) -> AppResult<SeedSummary> {
+        let mut summary = SeedSummary::default();
+        let mut user_ids = HashMap::new();
+
+        for user in &fixture.users {
+            let (id, created) = self.ensure_user(user).await?;
+            if created {
+                summary.users += 1;
+            }
+            user_ids.insert(user.username.clone(), id);
+        }
+
+        let lookup = |username: &str| -> AppResult<Uuid> {
+            user_ids.get(username).copied().ok_or_else(|| {
+                AppError::Validation(format!("Fixture references unknown user {}", username))
+            })
+        };
+
+        for user in &fixture.users {
+            let user_id = lookup(&user.username)?;
+            for contact in &user.contacts {
+                match self
+                    .contacts
+                    .add_contact(user_id, lookup(contact)?, None)
+                    .await
+                {
+                    Ok(_) => summary.contacts += 1,
+                    Err(AppError::ContactAlreadyExists) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        for conversation in &fixture.conversations {
+            let members = conversation
+                .members
+                .iter()
+                .map(|username| lookup(username))
+                .collect::<AppResult<Vec<_>>>()?;
+
+            let Some(conversation_id) = self.create_conversation(conversation, &members).await?
+            else {
+                continue;
+            };
+            summary.conversations += 1;
+
+            for message in &conversation.messages {
+                self.messaging
+                    .store_message(
+                        conversation_id,
+                        lookup(&message.from)?,
+                        MessageType::Text,
+                        StoredPayload::text(message.text.clone()),
+                        None,
+                        None,
+                        false,
+                        false,
+                    )
+                    .await?;
+                summary.messages += 1;
+            }
+        }
+
+        for pack in &fixture.sticker_packs {
+            if self.create_sticker_pack(pack, base_dir).await? {
+                summary.sticker_packs += 1;
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Existing user ID by username, or a newly created user
+    async fn ensure_user(&self, user: &FixtureUser) -> AppResult<(Uuid, bool)> {
+        let existing: Option<Uuid> = sqlx::query_scalar("SELECT id FROM users WHERE username = $1")
+            .bind(&user.username)
+            .fetch_optional(&self.db)
+            .await?;
+
+        if let Some(id) = existing {
+            return Ok((id, false));
+        }
+
+        if user.email.is_none() && user.phone.is_none() {
+            return Err(AppError::Validation(format!(
+                "Fixture user {} needs an email or phone",
+                user.username
+            )));
+        }
+
+        let id: Uuid = sqlx::query_scalar(
+            r#"
+            INSERT INTO users (id, username, display_name, email, phone, bio, is_admin)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(&user.username)
+        .bind(&user.display_name)
+        .bind(&user.email)
+        .bind(&user.phone)
+        .bind(&user.bio)
+        .bind(user.is_admin)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok((id, true))
+    }
+
+    /// Create the conversation unless it already exists; `None` means skipped
+    async fn create_conversation(
+        &self,
+        conversation: &FixtureConversation,
+        members: &[Uuid],
+    ) -> AppResult<Option<Uuid>> {
+        let Some((&creator, others)) = members.split_first() else {
+            return Err(AppError::Validation(
+                "Fixture conversation has no members".to_string(),
+            ));
+        };
+
+        match conversation.conversation_type {
+            FixtureConversationType::Direct => {
+                let [other] = others else {
+                    return Err(AppError::Validation(
+                        "Fixture direct conversation needs exactly two members".to_string(),
+                    ));
+                };
+
+                let existing: Option<Uuid> =
+                    sqlx::query_scalar("SELECT id FROM conversations WHERE direct_key = $1")
+                        .bind(direct_key(creator, *other))
+                        .fetch_optional(&self.db)
+                        .await?;
+
+                if existing.is_some() {
+                    return Ok(None);
+                }
+
+                let id = self
+                    .messaging
+                    .direct_conversation_id(creator, *other, ConversationEncryption::None)
+                    .await?;
+                Ok(Some(id))
+            }
+            FixtureConversationType::Group => {
+                let name = conversation.name.as_deref().ok_or_else(|| {
+                    AppError::Validation("Fixture group conversation needs a name".to_string())
+                })?;
+
+                let existing: Option<Uuid> = sqlx::query_scalar(
+                    r#"
+                    SELECT id FROM conversations
+                    WHERE type = 'group' AND name = $1 AND created_by = $2
+                    "#,
+                )
+                .bind(name)
+                .bind(creator)
+                .fetch_optional(&self.db)
+                .await?;
+
+                if existing.is_some() {
+                    return Ok(None);
+                }
+
+                let group = self
+                    .messaging
+                    .create_group_conversation(
+                        creator,
+                        name,
+                        others.to_vec(),
+                        ConversationEncryption::None,
+                    )
+                    .await?;
+                Ok(Some(group.conversation.id))
+            }
+        }
+    }
+
+    /// Create a sticker pack and upload its images unless one with the same name exists
+    async fn create_sticker_pack(
+        &self,
+        pack: &FixtureStickerPack,
+        base_dir: &Path,
+    ) -> AppResult<bool> {
+        let exists: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM sticker_packs WHERE name = $1)")
+                .bind(&pack.name)
+                .fetch_one(&self.db)
+                .await?;
+
+        if exists {
+            return Ok(false);
+        }
+
+        let created = self
+            .stickers
+            .create_pack(
+                None,
+                &pack.name,
+                &pack.author,
+                pack.description.as_deref(),
+                pack.official,
+                pack.animated,
+            )
+            .await?;
+
+        for (position, sticker) in pack.stickers.iter().enumerate() {
+            let path = base_dir.join(&sticker.image);
+            let data = std::fs::read(&path)
+                .map_err(|e| anyhow::anyhow!("Failed to read sticker {}: {}", path.display(), e))?;
+            let content_type = image_content_type(&path);
+
+            if position == 0 {
+                self.stickers
+                    .upload_pack_cover(created.id, Bytes::from(data.clone()), content_type)
+                    .await?;
+            }
+
+            self.stickers
+                .add_sticker(
+                    created.id,
+                    &sticker.emoji,
+                    position as i32,
+                    Bytes::from(data),
+                    content_type,
+                )
+                .await?;
+        }
+
+        Ok(true)
+    }
+}
+
+fn image_content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashSet;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{error::AppResult, models::User};
+
+/// Who may see whose activity. Typing, presence, read receipts and the
+/// online status on profiles all ask `can_interact`, so blocks and message
+/// requests apply the same way everywhere.
+pub struct SocialGraphService {
+    db: PgPool,
+}
+
+impl SocialGraphService {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+
+    /// Whether `recipient_id` may see `sender_id`'s activity. The sender
+    /// must have let the recipient in: kept them as a contact, shared an
+    /// organization directory, or accepted a conversation with them that
+    /// the recipient hasn't muted. A block either way, or a deactivated
+    /// sender, rules it out.
+    pub async fn can_interact(&self, sender_id: Uuid, recipient_id: Uuid) -> AppResult<bool> {
+        let allowed = self.interacting(&[sender_id], &[recipient_id]).await?;
+
+        Ok(allowed.contains(&(sender_id, recipient_id)))
+    }
+
+    /// The `(sender, recipient)` pairs among `sender_ids` and
+    /// `recipient_ids` that `can_interact` allows, in one query
+    pub async fn interacting(
+        &self,
+        sender_ids: &[Uuid],
+        recipient_ids: &[Uuid],
+    ) -> AppResult<HashSet<(Uuid, Uuid)>> {
+        if sender_ids.is_empty() || recipient_ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let pairs: Vec<(Uuid, Uuid)> = sqlx::query_as(
+            r#"
+            SELECT s.id, r.id
+            FROM (SELECT DISTINCT id FROM UNNEST($1::uuid[]) AS s(id)) s
+            CROSS JOIN (SELECT DISTINCT id FROM UNNEST($2::uuid[]) AS r(id)) r
+            WHERE s.id = r.id OR (
+                NOT EXISTS (
+                    SELECT 1 FROM users u WHERE u.id = s.id AND u.deactivated_at IS NOT NULL
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM contacts b
+                    WHERE b.is_blocked AND (
+                        (b.user_id = s.id AND b.contact_id = r.id)
+                        OR (b.user_id = r.id AND b.contact_id = s.id)
+                    )
+                )
+                AND (
+                    EXISTS (
+                        SELECT 1 FROM contacts c WHERE c.user_id = s.id AND c.contact_id = r.id
+                    )
+                    OR EXISTS (
+                        SELECT 1 FROM organization_members a
+                        JOIN organization_members b ON a.organization_id = b.organization_id
+                        WHERE a.user_id = s.id AND b.user_id = r.id
+                    )
+                    OR EXISTS (
+                        SELECT 1 FROM participants ps
+                        JOIN participants pr ON pr.conversation_id = ps.conversation_id
+                        WHERE ps.user_id = s.id AND ps.left_at IS NULL AND NOT ps.is_request
+                        AND pr.user_id = r.id AND pr.left_at IS NULL
+                        AND (pr.muted_until IS NULL OR pr.muted_until <= NOW())
+                    )
+                )
+            )
+            "#,
+        )
+        .bind(sender_ids)
+        .bind(recipient_ids)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(pairs.into_iter().collect())
+    }
+
+    /// Hide the online status and last seen time of the users `viewer_id`
+    /// may not see the activity of
+    pub async fn redact_presence<'a>(
+        &self,
+        viewer_id: Uuid,
+        users: impl IntoIterator<Item = &'a mut User>,
+    ) -> AppResult<()> {
+        let mut users: Vec<&mut User> = users.into_iter().collect();
+        let user_ids: Vec<Uuid> = users.iter().map(|u| u.id).collect();
+        let allowed = self.interacting(&user_ids, &[viewer_id]).await?;
+
+        for user in users.iter_mut() {
+            if !allowed.contains(&(user.id, viewer_id)) {
+                user.hide_presence();
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::SpamConfig,
+    error::{AppError, AppResult},
+    services::moderation::ModerationService,
+    storage::redis::RedisClient,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpamVerdict {
+    Deliver,
+    /// Store the message but show it only to its sender
+    Shadow,
+}
+
+pub struct SpamGuard {
+    db: PgPool,
+    redis: RedisClient,
+    config: SpamConfig,
+}
+
+impl SpamGuard {
+    pub fn new(db: PgPool, redis: RedisClient, config: SpamConfig) -> Self {
+        Self { db, redis, config }
+    }
+
+    /// Check an outgoing message against flood and duplicate-content limits.
+    ///
+    /// Returns `MessageRateLimited` while the sender is over the per-window
+    /// limit. Repeatedly hitting the limit, or posting the same content to too
+    /// many conversations, shadow-limits the sender and alerts moderators.
+    pub async fn check_message(
+        &self,
+        sender_id: Uuid,
+        conversation_id: Uuid,
+        content: &[u8],
+    ) -> AppResult<SpamVerdict> {
+        let sender = sender_id.to_string();
+
+        let count = self
+            .redis
+            .incr_rate_limit(&format!("msg:{}", sender), self.config.message_window)
+            .await?;
+
+        if count > self.config.message_max as i64 {
+            // Only the first rejection in a window counts as a strike
+            if count == self.config.message_max as i64 + 1 {
+                self.record_strike(sender_id).await?;
+            }
+            return Err(AppError::MessageRateLimited);
+        }
+
+        if self.redis.is_shadow_limited(&sender).await? {
+            return Ok(SpamVerdict::Shadow);
+        }
+
+        if content.len() >= self.config.duplicate_min_bytes {
+            let content_hash = format!("{:x}", Sha256::digest(content));
+            let conversations = self
+                .redis
+                .track_duplicate_content(
+                    &sender,
+                    &content_hash,
+                    &conversation_id.to_string(),
+                    self.config.duplicate_window,
+                )
+                .await?;
+
+            if conversations > self.config.duplicate_max_conversations as i64 {
+                ModerationService::new(self.db.clone(), self.redis.clone())
+                    .shadow_limit(
+                        sender_id,
+                        Some(conversation_id),
+                        "duplicate_content",
+                        format!(
+                            "Identical content sent to {} conversations within {}s",
+                            conversations,
+                            self.config.duplicate_window.as_secs()
+                        ),
+                        self.config.shadow_duration,
+                    )
+                    .await?;
+                return Ok(SpamVerdict::Shadow);
+            }
+        }
+
+        Ok(SpamVerdict::Deliver)
+    }
+
+    async fn record_strike(&self, sender_id: Uuid) -> AppResult<()> {
+        let strikes = self
+            .redis
+            .incr_rate_limit(
+                &format!("msg-strikes:{}", sender_id),
+                self.config.shadow_duration,
+            )
+            .await?;
+
+        if strikes == self.config.strikes_before_shadow as i64 {
+            ModerationService::new(self.db.clone(), self.redis.clone())
+                .shadow_limit(
+                    sender_id,
+                    None,
+                    "flood",
+                    format!(
+                        "Exceeded {} messages per {}s {} times",
+                        self.config.message_max,
+                        self.config.message_window.as_secs(),
+                        strikes
+                    ),
+                    self.config.shadow_duration,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
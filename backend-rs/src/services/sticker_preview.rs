@@ -0,0 +1,158 @@
+use std::{io::Read, sync::Arc};
+
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+
+use crate::{config::StickerConfig, error::AppResult};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// APNG chunks describing the animation; a PNG without them is its default image
+const ANIMATION_CHUNKS: [&[u8; 4]; 3] = [b"acTL", b"fcTL", b"fdAT"];
+
+/// Decompressed Lottie animations larger than this are not rendered
+const MAX_LOTTIE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// The fields every Lottie animation has; the rest is left to the renderer
+#[derive(Debug, Deserialize)]
+struct LottieHeader {
+    /// In-point: the first frame
+    ip: f64,
+    op: f64,
+    w: u32,
+    h: u32,
+}
+
+/// A self-hosted renderer: `POST {url}/render?frame=N` with the animation as
+/// JSON returns that frame as a PNG
+pub struct LottieRenderer {
+    http: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+}
+
+impl LottieRenderer {
+    pub fn new(url: String, api_key: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            api_key,
+        }
+    }
+
+    async fn render(&self, animation: Vec<u8>, frame: u32) -> AppResult<Vec<u8>> {
+        let request = self
+            .http
+            .post(format!("{}/render", self.url))
+            .query(&[("frame", frame)])
+            .header("content-type", "application/json")
+            .body(animation);
+        let request = match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        };
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Lottie renderer request failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("Lottie renderer returned {}", response.status()).into());
+        }
+
+        let png = response
+            .bytes()
+            .await
+            .map_err(|e| anyhow::anyhow!("Invalid Lottie renderer response: {}", e))?;
+        if !png.starts_with(PNG_SIGNATURE) {
+            return Err(anyhow::anyhow!("Lottie renderer did not return a PNG").into());
+        }
+
+        Ok(png.to_vec())
+    }
+}
+
+/// Returns `None` when no renderer is configured
+pub fn build_lottie_renderer(config: &StickerConfig) -> Option<Arc<LottieRenderer>> {
+    config.renderer_url.as_ref().map(|url| {
+        Arc::new(LottieRenderer::new(
+            url.clone(),
+            config.renderer_api_key.clone(),
+        ))
+    })
+}
+
+/// A static PNG of an animated sticker's first frame: cut from an APNG,
+/// or rendered from Lottie (`.json`, or gzipped `.tgs`) when a renderer is
+/// configured. `None` for stickers that are not animated or cannot be
+/// previewed.
+pub async fn render_preview(
+    data: &[u8],
+    content_type: &str,
+    renderer: Option<&LottieRenderer>,
+) -> AppResult<Option<Vec<u8>>> {
+    if data.starts_with(PNG_SIGNATURE) {
+        return Ok(apng_default_image(data));
+    }
+
+    let (Some(renderer), Some((animation, header))) = (renderer, lottie(data, content_type))
+    else {
+        return Ok(None);
+    };
+    if header.w == 0 || header.h == 0 || header.op <= header.ip {
+        return Ok(None);
+    }
+
+    let png = renderer.render(animation, header.ip.max(0.0) as u32).await?;
+    Ok(Some(png))
+}
+
+/// An APNG's default image, a plain PNG with the animation chunks left out;
+/// `None` for a PNG that is not animated or is malformed
+fn apng_default_image(data: &[u8]) -> Option<Vec<u8>> {
+    let mut image = PNG_SIGNATURE.to_vec();
+    let mut animated = false;
+    let mut rest = &data[PNG_SIGNATURE.len()..];
+
+    while !rest.is_empty() {
+        let length = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        // Length, type, data and CRC
+        let chunk = rest.get(..12usize.checked_add(length)?)?;
+        let chunk_type = &chunk[4..8];
+
+        if ANIMATION_CHUNKS.iter().any(|t| t.as_slice() == chunk_type) {
+            animated = true;
+        } else {
+            image.extend_from_slice(chunk);
+        }
+        rest = &rest[chunk.len()..];
+
+        if chunk_type == b"IEND" {
+            break;
+        }
+    }
+
+    animated.then_some(image)
+}
+
+/// A Lottie animation's JSON and header, gunzipping `.tgs` stickers
+fn lottie(data: &[u8], content_type: &str) -> Option<(Vec<u8>, LottieHeader)> {
+    let json = match content_type {
+        "application/json" => data.to_vec(),
+        "application/x-tgsticker" | "application/gzip" => {
+            let mut json = Vec::new();
+            GzDecoder::new(data)
+                .take(MAX_LOTTIE_BYTES + 1)
+                .read_to_end(&mut json)
+                .ok()?;
+            json
+        }
+        _ => return None,
+    };
+    if json.len() as u64 > MAX_LOTTIE_BYTES {
+        return None;
+    }
+
+    let header = serde_json::from_slice(&json).ok()?;
+    Some((json, header))
+}
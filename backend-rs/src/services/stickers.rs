@@ -1,75 +1,128 @@
+use std::sync::Arc;
+
 use bytes::Bytes;
-use sqlx::PgPool;
+use chrono::{Duration, Utc};
+use sqlx::{PgConnection, PgPool};
 use uuid::Uuid;
 
 use crate::{
     error::{AppError, AppResult},
-    models::{Sticker, StickerPack, StickerPackWithStickers, UserStickerPack},
-    storage::minio::MinioClient,
+    models::{
+        CatalogSection, DailyCount, Sticker, StickerPack, StickerPackAnalytics,
+        StickerPackSearchResult, StickerPackWithStickers, StickerSends, UserStickerPack,
+    },
+    services::{
+        search::escape_like,
+        sticker_preview::{render_preview, LottieRenderer},
+    },
+    storage::blob::BlobStorage,
 };
 
+/// Tags per pack and characters per tag
+const MAX_TAGS: usize = 10;
+const MAX_TAG_LEN: usize = 32;
+
+/// Days of activity the trending score looks back over
+const TRENDING_WINDOW_DAYS: i32 = 14;
+
+/// Days after which a send or install counts for half as much
+const TRENDING_HALF_LIFE_DAYS: f64 = 3.0;
+
+/// An install weighs as much as this many sends
+const TRENDING_INSTALL_WEIGHT: f64 = 5.0;
+
 pub struct StickersService {
     db: PgPool,
-    minio: MinioClient,
+    storage: Arc<dyn BlobStorage>,
+    renderer: Option<Arc<LottieRenderer>>,
 }
 
 impl StickersService {
-    pub fn new(db: PgPool, minio: MinioClient) -> Self {
-        Self { db, minio }
+    pub fn new(db: PgPool, storage: Arc<dyn BlobStorage>) -> Self {
+        Self {
+            db,
+            storage,
+            renderer: None,
+        }
+    }
+
+    /// Render previews of Lottie stickers as they are added
+    pub fn with_renderer(mut self, renderer: Option<Arc<LottieRenderer>>) -> Self {
+        self.renderer = renderer;
+        self
     }
 
-    /// Get sticker pack catalog
+    /// Get sticker pack catalog, a curated section of it, or the packs with a tag
     pub async fn get_catalog(
         &self,
         limit: i32,
         offset: i32,
         official: Option<bool>,
+        section: Option<CatalogSection>,
+        tag: Option<&str>,
     ) -> AppResult<Vec<StickerPack>> {
-        let packs: Vec<StickerPack> = if let Some(is_official) = official {
-            sqlx::query_as(
-                r#"
-                SELECT * FROM sticker_packs
-                WHERE is_official = $1
-                ORDER BY downloads DESC, created_at DESC
-                LIMIT $2 OFFSET $3
-                "#,
-            )
-            .bind(is_official)
-            .bind(limit)
-            .bind(offset)
-            .fetch_all(&self.db)
-            .await?
-        } else {
-            sqlx::query_as(
-                r#"
-                SELECT * FROM sticker_packs
-                ORDER BY downloads DESC, created_at DESC
-                LIMIT $1 OFFSET $2
-                "#,
-            )
-            .bind(limit)
-            .bind(offset)
-            .fetch_all(&self.db)
-            .await?
+        let (filter, order) = match section {
+            Some(CatalogSection::Featured) => {
+                ("featured", "featured_order ASC NULLS LAST, created_at DESC")
+            }
+            Some(CatalogSection::Trending) => {
+                ("trending_score > 0", "trending_score DESC, downloads DESC")
+            }
+            Some(CatalogSection::New) => ("TRUE", "created_at DESC"),
+            None => ("TRUE", "downloads DESC, created_at DESC"),
         };
 
+        let packs: Vec<StickerPack> = sqlx::query_as(&format!(
+            r#"
+            SELECT * FROM sticker_packs
+            WHERE {}
+            AND ($1::BOOLEAN IS NULL OR is_official = $1)
+            AND ($2::TEXT IS NULL OR $2 = ANY(tags))
+            ORDER BY {}, id
+            LIMIT $3 OFFSET $4
+            "#,
+            filter, order
+        ))
+        .bind(official)
+        .bind(tag.map(|t| t.trim().to_lowercase()))
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.db)
+        .await?;
+
         Ok(packs)
     }
 
     /// Search sticker packs
-    pub async fn search_packs(&self, query: &str, limit: i32) -> AppResult<Vec<StickerPack>> {
-        let search_pattern = format!("%{}%", query.to_lowercase());
+    pub async fn search_packs(
+        &self,
+        query: &str,
+        limit: i32,
+        offset: i32,
+    ) -> AppResult<Vec<StickerPackSearchResult>> {
+        let query = query.to_lowercase();
+        let search_pattern = format!("%{}%", escape_like(&query));
 
-        let packs: Vec<StickerPack> = sqlx::query_as(
+        let packs: Vec<StickerPackSearchResult> = sqlx::query_as(
             r#"
-            SELECT * FROM sticker_packs
-            WHERE LOWER(name) LIKE $1 OR LOWER(description) LIKE $1 OR LOWER(author) LIKE $1
-            ORDER BY downloads DESC
-            LIMIT $2
+            SELECT p.*,
+                GREATEST(
+                    similarity(LOWER(p.name), $1),
+                    similarity(LOWER(p.author), $1),
+                    similarity(LOWER(COALESCE(p.description, '')), $1)
+                ) AS score
+            FROM sticker_packs p
+            WHERE LOWER(p.name) LIKE $2 OR LOWER(p.author) LIKE $2
+            OR LOWER(COALESCE(p.description, '')) LIKE $2
+            OR LOWER(p.name) % $1 OR LOWER(p.author) % $1
+            ORDER BY score DESC, p.downloads DESC
+            LIMIT $3 OFFSET $4
             "#,
         )
+        .bind(&query)
         .bind(&search_pattern)
         .bind(limit)
+        .bind(offset)
         .fetch_all(&self.db)
         .await?;
 
@@ -99,7 +152,7 @@ impl StickersService {
     /// Download (add) a sticker pack to user's collection
     pub async fn download_pack(&self, user_id: Uuid, pack_id: Uuid) -> AppResult<()> {
         // Check if pack exists
-        let pack_exists: Option<(i64,)> =
+        let pack_exists: Option<(i32,)> =
             sqlx::query_as("SELECT 1 FROM sticker_packs WHERE id = $1")
                 .bind(pack_id)
                 .fetch_optional(&self.db)
@@ -110,7 +163,7 @@ impl StickersService {
         }
 
         // Check if already owned
-        let already_owned: Option<(i64,)> = sqlx::query_as(
+        let already_owned: Option<(i32,)> = sqlx::query_as(
             "SELECT 1 FROM user_sticker_packs WHERE user_id = $1 AND pack_id = $2",
         )
         .bind(user_id)
@@ -190,10 +243,38 @@ impl StickersService {
         Ok(result)
     }
 
+    /// User's sticker packs in their chosen order, without the stickers
+    pub async fn list_user_packs(&self, user_id: Uuid) -> AppResult<Vec<StickerPack>> {
+        let packs: Vec<StickerPack> = sqlx::query_as(
+            r#"
+            SELECT p.* FROM sticker_packs p
+            JOIN user_sticker_packs up ON up.pack_id = p.id
+            WHERE up.user_id = $1
+            ORDER BY up.position ASC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(packs)
+    }
+
     /// Reorder user's sticker packs
     pub async fn reorder_packs(&self, user_id: Uuid, pack_ids: Vec<Uuid>) -> AppResult<()> {
         let mut tx = self.db.begin().await?;
+        self.save_pack_order(&mut tx, user_id, &pack_ids).await?;
+        tx.commit().await?;
+        Ok(())
+    }
 
+    /// Reorder user's sticker packs within the caller's transaction
+    pub async fn save_pack_order(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        pack_ids: &[Uuid],
+    ) -> AppResult<()> {
         for (position, pack_id) in pack_ids.iter().enumerate() {
             sqlx::query(
                 "UPDATE user_sticker_packs SET position = $1 WHERE user_id = $2 AND pack_id = $3",
@@ -201,17 +282,17 @@ impl StickersService {
             .bind(position as i32)
             .bind(user_id)
             .bind(pack_id)
-            .execute(&mut *tx)
+            .execute(&mut *conn)
             .await?;
         }
 
-        tx.commit().await?;
         Ok(())
     }
 
-    /// Create a new sticker pack (admin)
+    /// Create a new sticker pack (admin); `creator_id` may see its analytics
     pub async fn create_pack(
         &self,
+        creator_id: Option<Uuid>,
         name: &str,
         author: &str,
         description: Option<&str>,
@@ -220,8 +301,9 @@ impl StickersService {
     ) -> AppResult<StickerPack> {
         let pack: StickerPack = sqlx::query_as(
             r#"
-            INSERT INTO sticker_packs (id, name, author, description, is_official, is_animated, price, downloads)
-            VALUES ($1, $2, $3, $4, $5, $6, 0, 0)
+            INSERT INTO sticker_packs (id, name, author, description, is_official, is_animated,
+                price, downloads, creator_id)
+            VALUES ($1, $2, $3, $4, $5, $6, 0, 0, $7)
             RETURNING *
             "#,
         )
@@ -231,12 +313,188 @@ impl StickersService {
         .bind(description)
         .bind(is_official)
         .bind(is_animated)
+        .bind(creator_id)
         .fetch_one(&self.db)
         .await?;
 
         Ok(pack)
     }
 
+    /// Sends and installs of a pack over the last `days` days
+    pub async fn pack_analytics(
+        &self,
+        pack_id: Uuid,
+        days: i64,
+    ) -> AppResult<StickerPackAnalytics> {
+        let downloads: i64 = sqlx::query_scalar("SELECT downloads FROM sticker_packs WHERE id = $1")
+            .bind(pack_id)
+            .fetch_optional(&self.db)
+            .await?
+            .ok_or(AppError::StickerPackNotFound)?;
+
+        let since = (Utc::now() - Duration::days(days - 1)).date_naive();
+
+        let sends_per_day: Vec<DailyCount> = sqlx::query_as(
+            r#"
+            SELECT day, SUM(sends)::BIGINT AS count FROM sticker_sends_daily
+            WHERE pack_id = $1 AND day >= $2
+            GROUP BY day
+            ORDER BY day ASC
+            "#,
+        )
+        .bind(pack_id)
+        .bind(since)
+        .fetch_all(&self.db)
+        .await?;
+
+        let installs_per_day: Vec<DailyCount> = sqlx::query_as(
+            r#"
+            SELECT (created_at AT TIME ZONE 'UTC')::date AS day, COUNT(*) AS count
+            FROM user_sticker_packs
+            WHERE pack_id = $1 AND (created_at AT TIME ZONE 'UTC')::date >= $2
+            GROUP BY day
+            ORDER BY day ASC
+            "#,
+        )
+        .bind(pack_id)
+        .bind(since)
+        .fetch_all(&self.db)
+        .await?;
+
+        let stickers: Vec<StickerSends> = sqlx::query_as(
+            r#"
+            SELECT s.id AS sticker_id, s.emoji, COALESCE(SUM(d.sends), 0)::BIGINT AS sends
+            FROM stickers s
+            LEFT JOIN sticker_sends_daily d ON d.sticker_id = s.id AND d.day >= $2
+            WHERE s.pack_id = $1
+            GROUP BY s.id, s.emoji, s.position
+            ORDER BY sends DESC, s.position ASC
+            "#,
+        )
+        .bind(pack_id)
+        .bind(since)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(StickerPackAnalytics {
+            pack_id,
+            days,
+            downloads,
+            sends: sends_per_day.iter().map(|d| d.count).sum(),
+            sends_per_day,
+            installs_per_day,
+            stickers,
+        })
+    }
+
+    /// `pack_analytics` for the user who created the pack
+    pub async fn creator_pack_analytics(
+        &self,
+        user_id: Uuid,
+        pack_id: Uuid,
+        days: i64,
+    ) -> AppResult<StickerPackAnalytics> {
+        let creator_id: Option<Uuid> =
+            sqlx::query_scalar("SELECT creator_id FROM sticker_packs WHERE id = $1")
+                .bind(pack_id)
+                .fetch_optional(&self.db)
+                .await?
+                .ok_or(AppError::StickerPackNotFound)?;
+
+        if creator_id != Some(user_id) {
+            return Err(AppError::InsufficientPermissions);
+        }
+
+        self.pack_analytics(pack_id, days).await
+    }
+
+    /// Set a pack's featured placement and tags (admin). Tags are
+    /// lowercased and deduplicated.
+    pub async fn curate_pack(
+        &self,
+        pack_id: Uuid,
+        featured: bool,
+        featured_order: Option<i32>,
+        tags: Vec<String>,
+    ) -> AppResult<StickerPack> {
+        let mut normalized: Vec<String> = Vec::new();
+        for tag in tags {
+            let tag = tag.trim().to_lowercase();
+            if tag.is_empty() || tag.chars().count() > MAX_TAG_LEN {
+                return Err(AppError::Validation(format!(
+                    "Tags must be 1 to {} characters",
+                    MAX_TAG_LEN
+                )));
+            }
+            if !normalized.contains(&tag) {
+                normalized.push(tag);
+            }
+        }
+        if normalized.len() > MAX_TAGS {
+            return Err(AppError::Validation(format!(
+                "At most {} tags per pack",
+                MAX_TAGS
+            )));
+        }
+
+        let pack: StickerPack = sqlx::query_as(
+            r#"
+            UPDATE sticker_packs
+            SET featured = $2, featured_order = $3, tags = $4, updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(pack_id)
+        .bind(featured)
+        .bind(featured.then_some(featured_order).flatten())
+        .bind(&normalized)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::StickerPackNotFound)?;
+
+        Ok(pack)
+    }
+
+    /// Recompute every pack's trending score from the last
+    /// `TRENDING_WINDOW_DAYS` of sends and installs, each day's activity
+    /// counting half as much every `TRENDING_HALF_LIFE_DAYS`. Returns how
+    /// many packs changed.
+    pub async fn refresh_trending(&self) -> AppResult<u64> {
+        let result = sqlx::query(
+            r#"
+            WITH activity AS (
+                SELECT pack_id, day, sends::DOUBLE PRECISION AS weight
+                FROM sticker_sends_daily
+                WHERE day >= CURRENT_DATE - $1::INTEGER
+                UNION ALL
+                SELECT pack_id, (created_at AT TIME ZONE 'UTC')::date, $3
+                FROM user_sticker_packs
+                WHERE created_at >= NOW() - make_interval(days => $1)
+            ),
+            scores AS (
+                SELECT pack_id,
+                    SUM(weight * POWER(0.5, (CURRENT_DATE - day) / $2::DOUBLE PRECISION))
+                        AS score
+                FROM activity
+                GROUP BY pack_id
+            )
+            UPDATE sticker_packs p
+            SET trending_score = COALESCE(s.score, 0)
+            FROM sticker_packs q
+            LEFT JOIN scores s ON s.pack_id = q.id
+            WHERE p.id = q.id AND p.trending_score IS DISTINCT FROM COALESCE(s.score, 0)
+            "#,
+        )
+        .bind(TRENDING_WINDOW_DAYS)
+        .bind(TRENDING_HALF_LIFE_DAYS)
+        .bind(TRENDING_INSTALL_WEIGHT)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
     /// Upload pack cover image (admin)
     pub async fn upload_pack_cover(
         &self,
@@ -248,13 +506,18 @@ impl StickersService {
         let key = format!("packs/{}/cover.{}", pack_id, extension);
 
         let url = self
-            .minio
-            .upload_file(self.minio.stickers_bucket(), &key, data, content_type)
+            .storage
+            .upload_file(self.storage.stickers_bucket(), &key, data, content_type)
             .await?;
 
+        // Version the reused key so cached covers are replaced
+        let updated_at = Utc::now();
+        let url = format!("{}?v={}", url, updated_at.timestamp());
+
         // Update pack
-        sqlx::query("UPDATE sticker_packs SET cover_url = $1, updated_at = NOW() WHERE id = $2")
+        sqlx::query("UPDATE sticker_packs SET cover_url = $1, updated_at = $2 WHERE id = $3")
             .bind(&url)
+            .bind(updated_at)
             .bind(pack_id)
             .execute(&self.db)
             .await?;
@@ -275,15 +538,35 @@ impl StickersService {
         let extension = get_extension_from_content_type(content_type);
         let key = format!("packs/{}/{}.{}", pack_id, sticker_id, extension);
 
+        // A sticker that cannot be previewed is still added
+        let preview = render_preview(&data, content_type, self.renderer.as_deref())
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Failed to render preview of sticker {}: {}", sticker_id, e);
+                None
+            });
+
         let url = self
-            .minio
-            .upload_file(self.minio.stickers_bucket(), &key, data, content_type)
+            .storage
+            .upload_file(self.storage.stickers_bucket(), &key, data, content_type)
             .await?;
 
+        let preview_url = match preview {
+            Some(png) => {
+                let key = format!("packs/{}/{}_preview.png", pack_id, sticker_id);
+                let url = self
+                    .storage
+                    .upload_file(self.storage.stickers_bucket(), &key, png.into(), "image/png")
+                    .await?;
+                Some(url)
+            }
+            None => None,
+        };
+
         let sticker: Sticker = sqlx::query_as(
             r#"
-            INSERT INTO stickers (id, pack_id, emoji, image_url, position)
-            VALUES ($1, $2, $3, $4, $5)
+            INSERT INTO stickers (id, pack_id, emoji, image_url, preview_url, position)
+            VALUES ($1, $2, $3, $4, $5, $6)
             RETURNING *
             "#,
         )
@@ -291,14 +574,22 @@ impl StickersService {
         .bind(pack_id)
         .bind(emoji)
         .bind(&url)
+        .bind(&preview_url)
         .bind(position)
         .fetch_one(&self.db)
         .await?;
 
+        // Invalidate cached pack metadata
+        sqlx::query("UPDATE sticker_packs SET updated_at = NOW() WHERE id = $1")
+            .bind(pack_id)
+            .execute(&self.db)
+            .await?;
+
         Ok(sticker)
     }
 
     /// Get a single sticker
+    #[allow(dead_code)]
     pub async fn get_sticker(&self, sticker_id: Uuid) -> AppResult<Sticker> {
         let sticker: Option<Sticker> = sqlx::query_as("SELECT * FROM stickers WHERE id = $1")
             .bind(sticker_id)
@@ -316,6 +607,7 @@ fn get_extension_from_content_type(content_type: &str) -> &str {
         "image/gif" => "gif",
         "image/webp" => "webp",
         "application/json" => "json",
+        "application/x-tgsticker" | "application/gzip" => "tgs",
         _ => "bin",
     }
 }
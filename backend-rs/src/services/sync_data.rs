@@ -0,0 +1,273 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{types::Json, FromRow, PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{ChatFolder, SyncData, SyncedNotifications, SYNC_DATA_VERSION},
+    services::{
+        contacts::ContactsService, digests::DigestService, messaging::WsMessage,
+        notifications::NotificationService, stickers::StickersService,
+    },
+    storage::redis::RedisClient,
+};
+
+/// Most chat folders a user can keep
+const MAX_FOLDERS: usize = 20;
+
+/// Longest folder name, in characters
+const MAX_FOLDER_NAME_CHARS: usize = 32;
+
+/// How far ahead of the server clock a device's `updated_at` may be
+const MAX_CLOCK_SKEW: Duration = Duration::minutes(5);
+
+#[derive(FromRow)]
+struct StoredSyncData {
+    folders: Json<Vec<ChatFolder>>,
+    updated_at: DateTime<Utc>,
+    updated_by_device: Option<i32>,
+}
+
+/// Settings a fresh install restores in one go: notification preferences,
+/// sticker pack order and chat folders
+pub struct SyncDataService {
+    db: PgPool,
+    redis: RedisClient,
+    notifications: NotificationService,
+    digests: DigestService,
+    stickers: StickersService,
+}
+
+impl SyncDataService {
+    pub fn new(
+        db: PgPool,
+        redis: RedisClient,
+        notifications: NotificationService,
+        digests: DigestService,
+        stickers: StickersService,
+    ) -> Self {
+        Self {
+            db,
+            redis,
+            notifications,
+            digests,
+            stickers,
+        }
+    }
+
+    /// The user's current settings; never-synced users get their live
+    /// settings with no folders and no `updated_at`
+    pub async fn get(&self, user_id: Uuid) -> AppResult<SyncData> {
+        let stored: Option<StoredSyncData> = sqlx::query_as(
+            r#"
+            SELECT folders, updated_at, updated_by_device FROM user_sync_data
+            WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+        let (folders, updated_at, updated_by_device) = match stored {
+            Some(stored) => (
+                stored.folders.0,
+                Some(stored.updated_at),
+                stored.updated_by_device,
+            ),
+            None => (Vec::new(), None, None),
+        };
+
+        let settings = self.notifications.get_settings(user_id).await?;
+        let sticker_packs = self
+            .stickers
+            .list_user_packs(user_id)
+            .await?
+            .into_iter()
+            .map(|pack| pack.id)
+            .collect();
+
+        Ok(SyncData {
+            version: SYNC_DATA_VERSION,
+            updated_at,
+            updated_by_device,
+            notifications: SyncedNotifications {
+                notification_schedule: settings.notification_schedule,
+                email_digest: settings.email_digest,
+                contact_joined: settings.contact_joined,
+            },
+            sticker_packs,
+            folders: self.visible_folders(user_id, folders).await?,
+        })
+    }
+
+    /// Apply settings sent by a device. The newest `updated_at` wins: a save
+    /// older than the stored one fails with the stored settings, for the
+    /// device to merge and send again.
+    pub async fn put(&self, user_id: Uuid, device_id: i32, data: SyncData) -> AppResult<SyncData> {
+        if !(1..=SYNC_DATA_VERSION).contains(&data.version) {
+            return Err(AppError::Validation(format!(
+                "Unsupported sync data version {}; this server reads up to {}",
+                data.version, SYNC_DATA_VERSION
+            )));
+        }
+        let updated_at = data
+            .updated_at
+            .ok_or_else(|| AppError::Validation("updated_at is required".to_string()))?;
+        if updated_at > Utc::now() + MAX_CLOCK_SKEW {
+            return Err(AppError::Validation(
+                "updated_at is in the future".to_string(),
+            ));
+        }
+        if data.folders.len() > MAX_FOLDERS {
+            return Err(AppError::Validation(format!(
+                "At most {} folders",
+                MAX_FOLDERS
+            )));
+        }
+        let mut folders = Vec::with_capacity(data.folders.len());
+        for folder in data.folders {
+            let name = folder.name.trim();
+            if name.is_empty() || name.chars().count() > MAX_FOLDER_NAME_CHARS {
+                return Err(AppError::Validation(format!(
+                    "Folder names must be 1-{} characters",
+                    MAX_FOLDER_NAME_CHARS
+                )));
+            }
+            folders.push(ChatFolder {
+                name: name.to_string(),
+                conversation_ids: folder.conversation_ids,
+            });
+        }
+        let folders = self.visible_folders(user_id, folders).await?;
+
+        // The newest save wins even when two devices save at once, including
+        // the very first save; the settings below roll back with a losing one
+        let mut tx = self.db.begin().await?;
+        let saved = sqlx::query(
+            r#"
+            INSERT INTO user_sync_data (user_id, version, folders, updated_at, updated_by_device)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id) DO UPDATE SET
+                version = EXCLUDED.version,
+                folders = EXCLUDED.folders,
+                updated_at = EXCLUDED.updated_at,
+                updated_by_device = EXCLUDED.updated_by_device
+            WHERE user_sync_data.updated_at <= EXCLUDED.updated_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(SYNC_DATA_VERSION)
+        .bind(Json(&folders))
+        .bind(updated_at)
+        .bind(device_id)
+        .execute(&mut *tx)
+        .await?;
+        if saved.rows_affected() == 0 {
+            tx.rollback().await?;
+            let current = self.get(user_id).await?;
+            return Err(AppError::SyncDataConflict {
+                current: serde_json::to_value(current)?,
+            });
+        }
+
+        let notifications = &data.notifications;
+        let schedule = &notifications.notification_schedule;
+        self.notifications
+            .save_schedule(
+                &mut tx,
+                user_id,
+                schedule.enabled,
+                &schedule.timezone,
+                &schedule.windows,
+            )
+            .await?;
+        self.digests
+            .set_enabled(&mut tx, user_id, notifications.email_digest)
+            .await?;
+        ContactsService::save_contact_joined_notices(&mut tx, user_id, notifications.contact_joined)
+            .await?;
+        self.reorder_sticker_packs(&mut tx, user_id, &data.sticker_packs)
+            .await?;
+        tx.commit().await?;
+
+        let data = self.get(user_id).await?;
+        self.sync_to_devices(user_id, &data).await?;
+
+        Ok(data)
+    }
+
+    /// Put the listed packs first, in order; installed packs missing from the
+    /// list keep their order after them, and packs not installed are ignored
+    async fn reorder_sticker_packs(
+        &self,
+        conn: &mut PgConnection,
+        user_id: Uuid,
+        order: &[Uuid],
+    ) -> AppResult<()> {
+        let installed: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT pack_id FROM user_sticker_packs WHERE user_id = $1 ORDER BY position",
+        )
+        .bind(user_id)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let mut pack_ids: Vec<Uuid> = Vec::with_capacity(installed.len());
+        for pack_id in order.iter().chain(&installed) {
+            if installed.contains(pack_id) && !pack_ids.contains(pack_id) {
+                pack_ids.push(*pack_id);
+            }
+        }
+
+        self.stickers.save_pack_order(conn, user_id, &pack_ids).await
+    }
+
+    /// Drop conversations the user is no longer in from each folder
+    async fn visible_folders(
+        &self,
+        user_id: Uuid,
+        folders: Vec<ChatFolder>,
+    ) -> AppResult<Vec<ChatFolder>> {
+        let listed: Vec<Uuid> = folders
+            .iter()
+            .flat_map(|folder| folder.conversation_ids.iter().copied())
+            .collect();
+        if listed.is_empty() {
+            return Ok(folders);
+        }
+
+        let joined: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT conversation_id FROM participants
+            WHERE user_id = $1 AND conversation_id = ANY($2) AND left_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(&listed)
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(folders
+            .into_iter()
+            .map(|mut folder| {
+                let mut seen = HashSet::new();
+                folder
+                    .conversation_ids
+                    .retain(|id| joined.contains(id) && seen.insert(*id));
+                folder
+            })
+            .collect())
+    }
+
+    /// Tell the user's other devices to fetch the new settings
+    async fn sync_to_devices(&self, user_id: Uuid, data: &SyncData) -> AppResult<()> {
+        let ws_message = WsMessage {
+            msg_type: "sync_data_updated".to_string(),
+            payload: serde_json::to_value(data)?,
+        };
+
+        self.redis
+            .publish_message(&user_id.to_string(), &serde_json::to_string(&ws_message)?)
+            .await
+    }
+}
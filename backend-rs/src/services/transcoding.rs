@@ -0,0 +1,356 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use sqlx::PgPool;
+use tokio::process::Command;
+use uuid::Uuid;
+
+use crate::{
+    config::{Config, TranscodeConfig},
+    error::AppResult,
+    models::{Attachment, AttachmentRendition, RenditionKind, TranscodeStatus},
+    services::{media::MediaUrls, messaging::WsMessage},
+    storage::{blob::BlobStorage, redis::RedisClient},
+};
+
+/// Attachments transcoded per run
+const TRANSCODE_BATCH: i64 = 2;
+
+/// Attempts before an attachment is marked failed
+const MAX_ATTEMPTS: i32 = 3;
+
+/// Converts GIFs and videos into renditions
+#[async_trait]
+pub trait Transcoder: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// `input` as `kind`, or `None` when it cannot be made within `max_bytes`
+    async fn render(
+        &self,
+        input: &[u8],
+        kind: RenditionKind,
+        max_bytes: usize,
+    ) -> AppResult<Option<Bytes>>;
+}
+
+/// Runs the `ffmpeg` binary on temporary files
+pub struct FfmpegTranscoder {
+    binary: String,
+    max_dimension: u32,
+    timeout: Duration,
+}
+
+impl FfmpegTranscoder {
+    pub fn new(config: &TranscodeConfig) -> Self {
+        Self {
+            binary: config.ffmpeg_path.clone(),
+            max_dimension: config.max_dimension,
+            timeout: config.timeout,
+        }
+    }
+
+    /// Encoder arguments for each try, smaller output last
+    fn attempts(kind: RenditionKind) -> Vec<Vec<&'static str>> {
+        match kind {
+            RenditionKind::Mp4 => ["28", "34"]
+                .into_iter()
+                .map(|crf| {
+                    vec![
+                        "-c:v", "libx264", "-preset", "veryfast", "-crf", crf, "-pix_fmt",
+                        "yuv420p", "-movflags", "+faststart", "-c:a", "aac", "-b:a", "96k",
+                    ]
+                })
+                .collect(),
+            RenditionKind::Webm => ["36", "44"]
+                .into_iter()
+                .map(|crf| {
+                    vec![
+                        "-c:v", "libvpx-vp9", "-b:v", "0", "-crf", crf, "-deadline", "realtime",
+                        "-cpu-used", "8", "-row-mt", "1", "-c:a", "libopus", "-b:a", "64k",
+                    ]
+                })
+                .collect(),
+            RenditionKind::Poster => vec![vec!["-frames:v", "1", "-q:v", "4"]],
+        }
+    }
+
+    async fn render_in(
+        &self,
+        dir: &Path,
+        input: &[u8],
+        kind: RenditionKind,
+        max_bytes: usize,
+    ) -> AppResult<Option<Bytes>> {
+        let input_path = dir.join("input");
+        let output_path = dir.join(match kind {
+            RenditionKind::Mp4 => "output.mp4",
+            RenditionKind::Webm => "output.webm",
+            RenditionKind::Poster => "output.jpg",
+        });
+        tokio::fs::write(&input_path, input)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to write transcoder input: {}", e))?;
+
+        // Fit within the longest side, keeping even dimensions for the encoders
+        let scale = format!(
+            "scale='min({0},iw)':'min({0},ih)'\
+             :force_original_aspect_ratio=decrease:force_divisible_by=2",
+            self.max_dimension
+        );
+
+        for args in Self::attempts(kind) {
+            let run = Command::new(&self.binary)
+                .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+                .arg(&input_path)
+                .args(["-vf", &scale])
+                .args(args)
+                .arg(&output_path)
+                .kill_on_drop(true)
+                .output();
+            let output = tokio::time::timeout(self.timeout, run)
+                .await
+                .map_err(|_| anyhow::anyhow!("ffmpeg timed out rendering {}", kind.as_str()))?
+                .map_err(|e| anyhow::anyhow!("Failed to run ffmpeg: {}", e))?;
+            if !output.status.success() {
+                return Err(anyhow::anyhow!(
+                    "ffmpeg failed rendering {}: {}",
+                    kind.as_str(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+                .into());
+            }
+
+            let rendered = tokio::fs::read(&output_path)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to read ffmpeg output: {}", e))?;
+            if rendered.len() <= max_bytes {
+                return Ok(Some(rendered.into()));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl Transcoder for FfmpegTranscoder {
+    fn name(&self) -> &'static str {
+        "ffmpeg"
+    }
+
+    async fn render(
+        &self,
+        input: &[u8],
+        kind: RenditionKind,
+        max_bytes: usize,
+    ) -> AppResult<Option<Bytes>> {
+        let dir = std::env::temp_dir().join(format!("ansible-transcode-{}", Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to create transcoder directory: {}", e))?;
+
+        let result = self.render_in(&dir, input, kind, max_bytes).await;
+
+        if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+            tracing::warn!("Failed to remove {}: {}", dir.display(), e);
+        }
+        result
+    }
+}
+
+/// Returns `None` when transcoding is disabled
+pub fn build_transcoder(config: &TranscodeConfig) -> Option<Arc<dyn Transcoder>> {
+    match config.provider.as_str() {
+        "none" => None,
+        "ffmpeg" => Some(Arc::new(FfmpegTranscoder::new(config))),
+        other => {
+            tracing::warn!("Unknown transcode provider: {}", other);
+            None
+        }
+    }
+}
+
+/// Whether an upload is queued for transcoding: GIFs, and videos of at
+/// least `TRANSCODE_VIDEO_MIN_BYTES`
+pub fn wants_transcode(config: &TranscodeConfig, content_type: &str, size: usize) -> bool {
+    config.provider != "none"
+        && (content_type == "image/gif"
+            || (content_type.starts_with("video/") && size >= config.video_min_bytes))
+}
+
+/// Background transcoding of queued attachments. Each instance claims a few
+/// at a time; a claim older than the time its renditions may take is
+/// picked up again, so a crashed instance's work is retried.
+pub struct TranscodeService {
+    db: PgPool,
+    redis: RedisClient,
+    storage: Arc<dyn BlobStorage>,
+    transcoder: Arc<dyn Transcoder>,
+    config: Config,
+}
+
+impl TranscodeService {
+    pub fn new(
+        db: PgPool,
+        redis: RedisClient,
+        storage: Arc<dyn BlobStorage>,
+        transcoder: Arc<dyn Transcoder>,
+        config: Config,
+    ) -> Self {
+        Self {
+            db,
+            redis,
+            storage,
+            transcoder,
+            config,
+        }
+    }
+
+    /// Transcode a batch of queued attachments; returns how many finished
+    pub async fn run_pending(&self) -> AppResult<usize> {
+        let stale_after = self.config.transcode.timeout.as_secs_f64()
+            * (RenditionKind::ALL.len() * 2 + 1) as f64;
+        let claimed: Vec<Attachment> = sqlx::query_as(
+            r#"
+            UPDATE attachments
+            SET transcode_attempts = transcode_attempts + 1, transcode_started_at = NOW()
+            WHERE id IN (
+                SELECT id FROM attachments
+                WHERE transcode_status = 'pending'
+                AND (transcode_started_at IS NULL
+                    OR transcode_started_at < NOW() - make_interval(secs => $1))
+                ORDER BY created_at
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(stale_after)
+        .bind(TRANSCODE_BATCH)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut finished = 0;
+        for attachment in claimed {
+            let status = if attachment.transcode_attempts > MAX_ATTEMPTS {
+                TranscodeStatus::Failed
+            } else {
+                match self.transcode(&attachment).await {
+                    Ok(status) => status,
+                    Err(e) => {
+                        tracing::warn!("Failed to transcode attachment {}: {}", attachment.id, e);
+                        // Retried once the claim goes stale
+                        continue;
+                    }
+                }
+            };
+
+            sqlx::query("UPDATE attachments SET transcode_status = $2 WHERE id = $1")
+                .bind(attachment.id)
+                .bind(status)
+                .execute(&self.db)
+                .await?;
+            self.notify(&attachment, status).await?;
+            finished += 1;
+        }
+
+        Ok(finished)
+    }
+
+    /// Store each rendition the transcoder can make within the size cap
+    async fn transcode(&self, attachment: &Attachment) -> AppResult<TranscodeStatus> {
+        let bucket = self.storage.attachments_bucket();
+        let original = self
+            .storage
+            .download_file(bucket, &attachment.storage_key)
+            .await?;
+
+        let mut made = 0;
+        for kind in RenditionKind::ALL {
+            let Some(data) = self
+                .transcoder
+                .render(&original, kind, self.config.transcode.max_bytes)
+                .await?
+            else {
+                continue;
+            };
+
+            let key = format!("{}.{}", attachment.storage_key, kind.as_str());
+            let size = data.len() as i64;
+            self.storage
+                .upload_private_file(bucket, &key, data, kind.content_type())
+                .await?;
+
+            sqlx::query(
+                r#"
+                INSERT INTO attachment_renditions
+                    (attachment_id, kind, storage_key, content_type, size)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (attachment_id, kind) DO UPDATE SET
+                    storage_key = EXCLUDED.storage_key,
+                    content_type = EXCLUDED.content_type,
+                    size = EXCLUDED.size,
+                    created_at = NOW()
+                "#,
+            )
+            .bind(attachment.id)
+            .bind(kind)
+            .bind(&key)
+            .bind(kind.content_type())
+            .bind(size)
+            .execute(&self.db)
+            .await?;
+            made += 1;
+        }
+
+        Ok(if made > 0 {
+            TranscodeStatus::Ready
+        } else {
+            TranscodeStatus::Failed
+        })
+    }
+
+    /// Tell the uploader and everyone holding a message with the attachment
+    async fn notify(&self, attachment: &Attachment, status: TranscodeStatus) -> AppResult<()> {
+        let renditions: Vec<AttachmentRendition> =
+            sqlx::query_as("SELECT * FROM attachment_renditions WHERE attachment_id = $1")
+                .bind(attachment.id)
+                .fetch_all(&self.db)
+                .await?;
+        let link = MediaUrls::new(&self.config).sign_with_renditions(attachment.id, renditions);
+
+        let recipients: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT $2
+            UNION
+            SELECT p.user_id FROM messages m
+            JOIN participants p ON p.conversation_id = m.conversation_id
+            WHERE m.attachment_id = $1 AND p.left_at IS NULL AND m.deleted_at IS NULL
+            AND (NOT m.is_shadowed OR p.user_id = m.sender_id)
+            "#,
+        )
+        .bind(attachment.id)
+        .bind(attachment.uploader_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let ws_message = WsMessage {
+            msg_type: "attachment_transcoded".to_string(),
+            payload: serde_json::json!({
+                "attachment_id": attachment.id,
+                "transcode_status": status,
+                "renditions": link.renditions,
+            }),
+        };
+        let msg_str = serde_json::to_string(&ws_message)?;
+        for user_id in recipients {
+            self.redis
+                .publish_message(&user_id.to_string(), &msg_str)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,476 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::TranslationConfig,
+    error::{AppError, AppResult},
+    models::{ConversationTranslationSettings, Message, MessageTranslation, MessageType},
+    services::{http::send_json, messaging::WsMessage},
+    storage::redis::RedisClient,
+};
+
+/// Whether `code` looks like a language tag such as `en`, `zh-TW`, or `pt-BR`
+pub fn is_valid_language(code: &str) -> bool {
+    let mut parts = code.split('-');
+    let primary = parts.next().unwrap_or("");
+    let region = parts.next();
+
+    (2..=3).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && region.is_none_or(|r| {
+            (2..=4).contains(&r.len()) && r.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+        && parts.next().is_none()
+}
+
+#[derive(Debug, Clone)]
+pub struct Translation {
+    pub text: String,
+    pub detected_language: Option<String>,
+}
+
+/// Machine translation backend
+#[async_trait]
+pub trait TranslationProvider: Send + Sync {
+    /// Short name stored alongside cached translations
+    fn name(&self) -> &'static str;
+
+    async fn translate(&self, text: &str, target_language: &str) -> AppResult<Translation>;
+}
+
+#[derive(Debug, Deserialize)]
+struct DeepLTranslation {
+    detected_source_language: Option<String>,
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeepLResponse {
+    translations: Vec<DeepLTranslation>,
+}
+
+pub struct DeepLTranslator {
+    http: reqwest::Client,
+    url: String,
+    api_key: String,
+}
+
+impl DeepLTranslator {
+    pub fn new(api_key: String, url: Option<String>) -> Self {
+        let url = url.unwrap_or_else(|| "https://api-free.deepl.com".to_string());
+        Self {
+            http: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            api_key,
+        }
+    }
+}
+
+#[async_trait]
+impl TranslationProvider for DeepLTranslator {
+    fn name(&self) -> &'static str {
+        "deepl"
+    }
+
+    async fn translate(&self, text: &str, target_language: &str) -> AppResult<Translation> {
+        let body = serde_json::json!({
+            "text": [text],
+            "target_lang": target_language.to_uppercase(),
+        });
+        let response: DeepLResponse = send_json(
+            "DeepL",
+            self.http
+                .post(format!("{}/v2/translate", self.url))
+                .header("Authorization", format!("DeepL-Auth-Key {}", self.api_key))
+                .json(&body),
+        )
+        .await?;
+
+        let translation = response
+            .translations
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("DeepL returned no translation"))?;
+
+        Ok(Translation {
+            text: translation.text,
+            detected_language: translation
+                .detected_source_language
+                .map(|l| l.to_lowercase()),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleTranslation {
+    translated_text: String,
+    detected_source_language: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleData {
+    translations: Vec<GoogleTranslation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleResponse {
+    data: GoogleData,
+}
+
+/// Google Cloud Translation (basic, v2)
+pub struct GoogleTranslator {
+    http: reqwest::Client,
+    api_key: String,
+}
+
+impl GoogleTranslator {
+    pub fn new(api_key: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            api_key,
+        }
+    }
+}
+
+#[async_trait]
+impl TranslationProvider for GoogleTranslator {
+    fn name(&self) -> &'static str {
+        "google"
+    }
+
+    async fn translate(&self, text: &str, target_language: &str) -> AppResult<Translation> {
+        let body = serde_json::json!({
+            "q": text,
+            "target": target_language,
+            "format": "text",
+        });
+        let response: GoogleResponse = send_json(
+            "Google Translate",
+            self.http
+                .post("https://translation.googleapis.com/language/translate/v2")
+                .query(&[("key", &self.api_key)])
+                .json(&body),
+        )
+        .await?;
+
+        let translation = response
+            .data
+            .translations
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Google Translate returned no translation"))?;
+
+        Ok(Translation {
+            text: translation.translated_text,
+            detected_language: translation.detected_source_language,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NllbResponse {
+    translation: String,
+    detected_language: Option<String>,
+}
+
+/// A self-hosted NLLB server exposing `POST /translate` with
+/// `{"text", "target_lang"}` and answering `{"translation", "detected_language"}`
+pub struct NllbTranslator {
+    http: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+}
+
+impl NllbTranslator {
+    pub fn new(url: String, api_key: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            api_key,
+        }
+    }
+}
+
+#[async_trait]
+impl TranslationProvider for NllbTranslator {
+    fn name(&self) -> &'static str {
+        "nllb"
+    }
+
+    async fn translate(&self, text: &str, target_language: &str) -> AppResult<Translation> {
+        let body = serde_json::json!({
+            "text": text,
+            "target_lang": target_language,
+        });
+        let request = self
+            .http
+            .post(format!("{}/translate", self.url))
+            .json(&body);
+        let request = match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        };
+        let response: NllbResponse = send_json("NLLB", request).await?;
+
+        Ok(Translation {
+            text: response.translation,
+            detected_language: response.detected_language,
+        })
+    }
+}
+
+/// Returns `None` when translation is disabled or misconfigured
+pub fn build_translation_provider(
+    config: &TranslationConfig,
+) -> Option<Arc<dyn TranslationProvider>> {
+    match (config.provider.as_str(), &config.api_key, &config.url) {
+        ("none", _, _) => None,
+        ("deepl", Some(key), url) => Some(Arc::new(DeepLTranslator::new(key.clone(), url.clone()))),
+        ("google", Some(key), _) => Some(Arc::new(GoogleTranslator::new(key.clone()))),
+        ("nllb", key, Some(url)) => Some(Arc::new(NllbTranslator::new(url.clone(), key.clone()))),
+        ("nllb", _, None) => {
+            tracing::warn!("TRANSLATION_PROVIDER=nllb but TRANSLATION_URL is not set");
+            None
+        }
+        ("deepl" | "google", None, _) => {
+            tracing::warn!(
+                "TRANSLATION_PROVIDER={} but TRANSLATION_API_KEY is not set",
+                config.provider
+            );
+            None
+        }
+        (other, _, _) => {
+            tracing::warn!("Unknown translation provider: {}", other);
+            None
+        }
+    }
+}
+
+/// On-demand and automatic message translation with a per-(message, language) cache
+pub struct TranslationService {
+    db: PgPool,
+    redis: RedisClient,
+    provider: Arc<dyn TranslationProvider>,
+}
+
+impl TranslationService {
+    pub fn new(
+        db: PgPool,
+        redis: RedisClient,
+        provider: Option<Arc<dyn TranslationProvider>>,
+    ) -> AppResult<Self> {
+        let provider = provider.ok_or(AppError::TranslationDisabled)?;
+        Ok(Self {
+            db,
+            redis,
+            provider,
+        })
+    }
+
+    /// Translate a message the user can see into `language`
+    pub async fn translate_message(
+        &self,
+        message_id: Uuid,
+        user_id: Uuid,
+        language: &str,
+    ) -> AppResult<MessageTranslation> {
+        let message: Message = sqlx::query_as(
+            r#"
+            SELECT m.* FROM messages m
+            JOIN participants p ON p.conversation_id = m.conversation_id
+            WHERE m.id = $1 AND p.user_id = $2 AND p.left_at IS NULL
+            AND m.deleted_at IS NULL AND (NOT m.is_shadowed OR m.sender_id = $2)
+            AND (p.cleared_before IS NULL OR m.created_at > p.cleared_before)
+            "#,
+        )
+        .bind(message_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::MessageNotFound)?;
+
+        self.translate(&message, language).await
+    }
+
+    /// Cached translation of a text message, fetching it from the provider on a miss
+    async fn translate(&self, message: &Message, language: &str) -> AppResult<MessageTranslation> {
+        let cached: Option<MessageTranslation> = sqlx::query_as(
+            "SELECT * FROM message_translations WHERE message_id = $1 AND language = $2",
+        )
+        .bind(message.id)
+        .bind(language)
+        .fetch_optional(&self.db)
+        .await?;
+
+        if let Some(translation) = cached {
+            return Ok(translation);
+        }
+
+        let text = match message.message_type {
+            MessageType::Text if !message.encrypted => std::str::from_utf8(&message.content).ok(),
+            _ => None,
+        }
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .ok_or_else(|| AppError::Validation("Only text messages can be translated".to_string()))?;
+
+        let translation = self.provider.translate(text, language).await?;
+
+        let translation: MessageTranslation = sqlx::query_as(
+            r#"
+            INSERT INTO message_translations (message_id, language, text, source_language, provider)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (message_id, language) DO UPDATE SET
+                text = EXCLUDED.text,
+                source_language = EXCLUDED.source_language,
+                provider = EXCLUDED.provider,
+                created_at = NOW()
+            RETURNING *
+            "#,
+        )
+        .bind(message.id)
+        .bind(language)
+        .bind(&translation.text)
+        .bind(&translation.detected_language)
+        .bind(self.provider.name())
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(translation)
+    }
+
+    /// Translate a new or edited message for every participant with
+    /// auto-translate on, once per target language, and push the result to
+    /// them as a `message_translated` event
+    pub async fn auto_translate(&self, message: &Message) -> AppResult<()> {
+        let targets: Vec<(Uuid, String)> = sqlx::query_as(
+            r#"
+            SELECT s.user_id, s.target_language FROM conversation_translation_settings s
+            JOIN participants p ON p.conversation_id = s.conversation_id AND p.user_id = s.user_id
+            WHERE s.conversation_id = $1 AND s.auto_translate AND s.target_language IS NOT NULL
+            AND s.user_id != $2 AND p.left_at IS NULL
+            "#,
+        )
+        .bind(message.conversation_id)
+        .bind(message.sender_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut languages: Vec<&str> = targets.iter().map(|(_, l)| l.as_str()).collect();
+        languages.sort_unstable();
+        languages.dedup();
+
+        for language in languages {
+            let translation = self.translate(message, language).await?;
+
+            // Nothing to show when the message is already in that language
+            if translation.source_language.as_deref() == Some(language) {
+                continue;
+            }
+
+            let ws_message = WsMessage {
+                msg_type: "message_translated".to_string(),
+                payload: serde_json::json!({
+                    "conversation_id": message.conversation_id,
+                    "translation": translation,
+                }),
+            };
+            let msg_str = serde_json::to_string(&ws_message)?;
+
+            for (user_id, _) in targets.iter().filter(|(_, l)| l == language) {
+                self.redis
+                    .publish_message(&user_id.to_string(), &msg_str)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the user's auto-translate settings for a conversation (off if unset)
+    pub async fn get_settings(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+    ) -> AppResult<ConversationTranslationSettings> {
+        self.ensure_participant(conversation_id, user_id).await?;
+
+        let settings: Option<ConversationTranslationSettings> = sqlx::query_as(
+            r#"
+            SELECT conversation_id, auto_translate, target_language, updated_at
+            FROM conversation_translation_settings
+            WHERE conversation_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(settings.unwrap_or(ConversationTranslationSettings {
+            conversation_id,
+            ..Default::default()
+        }))
+    }
+
+    /// Update auto-translate settings; `None` leaves a field unchanged
+    pub async fn update_settings(
+        &self,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        auto_translate: Option<bool>,
+        target_language: Option<String>,
+    ) -> AppResult<ConversationTranslationSettings> {
+        let current = self.get_settings(conversation_id, user_id).await?;
+        let auto_translate = auto_translate.unwrap_or(current.auto_translate);
+        let target_language = target_language.or(current.target_language);
+
+        if auto_translate && target_language.is_none() {
+            return Err(AppError::Validation(
+                "target_language is required to enable auto-translate".to_string(),
+            ));
+        }
+
+        let settings: ConversationTranslationSettings = sqlx::query_as(
+            r#"
+            INSERT INTO conversation_translation_settings
+                (conversation_id, user_id, auto_translate, target_language)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (conversation_id, user_id) DO UPDATE SET
+                auto_translate = EXCLUDED.auto_translate,
+                target_language = EXCLUDED.target_language,
+                updated_at = NOW()
+            RETURNING conversation_id, auto_translate, target_language, updated_at
+            "#,
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .bind(auto_translate)
+        .bind(target_language)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(settings)
+    }
+
+    async fn ensure_participant(&self, conversation_id: Uuid, user_id: Uuid) -> AppResult<()> {
+        let is_participant: Option<(i32,)> = sqlx::query_as(
+            "SELECT 1 FROM participants WHERE conversation_id = $1 AND user_id = $2 AND left_at IS NULL",
+        )
+        .bind(conversation_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+
+        if is_participant.is_none() {
+            return Err(AppError::NotParticipant);
+        }
+
+        Ok(())
+    }
+}
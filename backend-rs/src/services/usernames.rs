@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    storage::redis::RedisClient,
+};
+
+/// How long a checked username is held for a registration in progress
+const RESERVATION_TTL: Duration = Duration::from_secs(600);
+
+/// Handles nobody can register: they impersonate the service or clash with
+/// client routes and mentions
+const RESERVED_USERNAMES: &[&str] = &[
+    "admin",
+    "administrator",
+    "ansible",
+    "ansibletalk",
+    "ansible_talk",
+    "api",
+    "everyone",
+    "help",
+    "here",
+    "me",
+    "moderator",
+    "null",
+    "official",
+    "root",
+    "saved",
+    "security",
+    "self",
+    "staff",
+    "support",
+    "system",
+    "undefined",
+];
+
+/// Lowercase a username and check its format: 3-32 characters of `a-z`,
+/// `0-9`, `_` and `.`, not starting or ending with `.`. A leading `@` is
+/// dropped.
+pub fn normalize_username(raw: &str) -> AppResult<String> {
+    let username = raw.trim().trim_start_matches('@').to_lowercase();
+
+    let valid_chars = username
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '.');
+
+    if !(3..=32).contains(&username.len())
+        || !valid_chars
+        || username.starts_with('.')
+        || username.ends_with('.')
+    {
+        return Err(AppError::Validation(
+            "Username must be 3-32 characters of letters, digits, '_' or '.'".to_string(),
+        ));
+    }
+
+    Ok(username)
+}
+
+pub fn is_reserved(username: &str) -> bool {
+    RESERVED_USERNAMES.contains(&username)
+}
+
+/// Map a unique-index violation on usernames to `UsernameTaken`
+pub fn map_username_conflict(e: sqlx::Error) -> AppError {
+    match &e {
+        sqlx::Error::Database(db) if db.constraint() == Some("idx_users_username_lower") => {
+            AppError::UsernameTaken
+        }
+        _ => e.into(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsernameAvailability {
+    pub username: String,
+    pub available: bool,
+    /// Why it is unavailable: reserved or taken
+    pub reason: Option<String>,
+    /// Whether it is now held for the caller's registration
+    pub reserved: bool,
+}
+
+pub struct UsernameService {
+    db: PgPool,
+    redis: RedisClient,
+}
+
+impl UsernameService {
+    pub fn new(db: PgPool, redis: RedisClient) -> Self {
+        Self { db, redis }
+    }
+
+    /// Check a username and, for a registration whose phone/email has a
+    /// verified OTP, hold it briefly so nobody else can take it meanwhile
+    pub async fn check_availability(
+        &self,
+        raw: &str,
+        registration_target: Option<&str>,
+    ) -> AppResult<UsernameAvailability> {
+        let username = normalize_username(raw)?;
+
+        let unavailable = |reason: &str| UsernameAvailability {
+            username: username.clone(),
+            available: false,
+            reason: Some(reason.to_string()),
+            reserved: false,
+        };
+
+        if is_reserved(&username) {
+            return Ok(unavailable("reserved"));
+        }
+
+        if self.is_registered(&username, None).await? {
+            return Ok(unavailable("taken"));
+        }
+
+        let holder = match registration_target {
+            Some(target) if self.has_verified_otp(target).await? => Some(target),
+            _ => None,
+        };
+
+        let reserved = match holder {
+            Some(holder) => {
+                if !self
+                    .redis
+                    .reserve_username(&username, holder, RESERVATION_TTL)
+                    .await?
+                {
+                    return Ok(unavailable("taken"));
+                }
+                true
+            }
+            None => {
+                if self
+                    .redis
+                    .get_username_reservation(&username)
+                    .await?
+                    .is_some()
+                {
+                    return Ok(unavailable("taken"));
+                }
+                false
+            }
+        };
+
+        Ok(UsernameAvailability {
+            username,
+            available: true,
+            reason: None,
+            reserved,
+        })
+    }
+
+    /// Normalize a username being claimed and make sure it is free for
+    /// `holder` (the registration target) or for an existing `user_id`
+    pub async fn ensure_available(
+        &self,
+        raw: &str,
+        holder: Option<&str>,
+        user_id: Option<Uuid>,
+    ) -> AppResult<String> {
+        let username = normalize_username(raw)?;
+
+        if is_reserved(&username) {
+            return Err(AppError::Validation("Username is reserved".to_string()));
+        }
+
+        if self.is_registered(&username, user_id).await? {
+            return Err(AppError::UsernameTaken);
+        }
+
+        if let Some(reserved_for) = self.redis.get_username_reservation(&username).await? {
+            if Some(reserved_for.as_str()) != holder {
+                return Err(AppError::UsernameTaken);
+            }
+        }
+
+        Ok(username)
+    }
+
+    /// Drop the reservation once the username is stored
+    pub async fn release(&self, username: &str) -> AppResult<()> {
+        self.redis.delete_username_reservation(username).await
+    }
+
+    async fn is_registered(&self, username: &str, except: Option<Uuid>) -> AppResult<bool> {
+        let taken: Option<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM users WHERE LOWER(username) = $1 AND id IS DISTINCT FROM $2",
+        )
+        .bind(username)
+        .bind(except)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(taken.is_some())
+    }
+
+    async fn has_verified_otp(&self, target: &str) -> AppResult<bool> {
+        let verified: Option<bool> = sqlx::query_scalar(
+            "SELECT verified FROM otps WHERE target = $1 AND verified = true LIMIT 1",
+        )
+        .bind(target)
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(verified.is_some())
+    }
+}
@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::Message,
+    services::messaging::WsMessage,
+    storage::redis::RedisClient,
+};
+
+/// One-time retrieval of view-once media. The media is wiped once every
+/// recipient has opened it, or when it expires unopened.
+pub struct ViewOnceService {
+    db: PgPool,
+    redis: RedisClient,
+    ttl: Duration,
+}
+
+impl ViewOnceService {
+    pub fn new(db: PgPool, redis: RedisClient, ttl: Duration) -> Self {
+        Self { db, redis, ttl }
+    }
+
+    /// Hand the media to a recipient the first time they ask, and refuse
+    /// every later request
+    pub async fn open(&self, message_id: Uuid, user_id: Uuid) -> AppResult<Vec<u8>> {
+        let message: Message = sqlx::query_as(
+            r#"
+            SELECT m.* FROM messages m
+            JOIN participants p ON p.conversation_id = m.conversation_id
+            WHERE m.id = $1 AND p.user_id = $2 AND p.left_at IS NULL
+            AND m.view_once AND m.deleted_at IS NULL AND NOT m.is_shadowed
+            AND (p.cleared_before IS NULL OR m.created_at > p.cleared_before)
+            "#,
+        )
+        .bind(message_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::MessageNotFound)?;
+
+        // The sender already has the media; only recipients get their one view
+        if message.sender_id == user_id {
+            return Err(AppError::MediaGone);
+        }
+
+        let expired: Option<DateTime<Utc>> =
+            sqlx::query_scalar("SELECT media_deleted_at FROM messages WHERE id = $1")
+                .bind(message_id)
+                .fetch_one(&self.db)
+                .await?;
+        if expired.is_some() {
+            return Err(AppError::MediaGone);
+        }
+
+        let viewed_at: Option<DateTime<Utc>> = sqlx::query_scalar(
+            r#"
+            INSERT INTO view_once_views (message_id, user_id) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            RETURNING viewed_at
+            "#,
+        )
+        .bind(message_id)
+        .bind(user_id)
+        .fetch_optional(&self.db)
+        .await?;
+        let viewed_at = viewed_at.ok_or(AppError::MediaGone)?;
+
+        self.notify_sender(&message, user_id, viewed_at).await?;
+        self.delete_if_all_viewed(&message).await?;
+
+        Ok(message.unpad().content)
+    }
+
+    /// Wipe view-once media nobody opened within the TTL
+    pub async fn purge_expired(&self) -> AppResult<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE messages SET content = '', media_deleted_at = NOW()
+            WHERE view_once AND media_deleted_at IS NULL
+            AND created_at < NOW() - make_interval(secs => $1)
+            "#,
+        )
+        .bind(self.ttl.as_secs() as f64)
+        .execute(&self.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_if_all_viewed(&self, message: &Message) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            UPDATE messages SET content = '', media_deleted_at = NOW()
+            WHERE id = $1 AND media_deleted_at IS NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM participants p
+                WHERE p.conversation_id = $2 AND p.left_at IS NULL AND p.user_id != $3
+                AND NOT EXISTS (
+                    SELECT 1 FROM view_once_views v
+                    WHERE v.message_id = $1 AND v.user_id = p.user_id
+                )
+            )
+            "#,
+        )
+        .bind(message.id)
+        .bind(message.conversation_id)
+        .bind(message.sender_id)
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// `media_viewed` receipt for the sender's devices
+    async fn notify_sender(
+        &self,
+        message: &Message,
+        viewer_id: Uuid,
+        viewed_at: DateTime<Utc>,
+    ) -> AppResult<()> {
+        let ws_message = WsMessage {
+            msg_type: "media_viewed".to_string(),
+            payload: serde_json::json!({
+                "message_id": message.id,
+                "conversation_id": message.conversation_id,
+                "user_id": viewer_id,
+                "viewed_at": viewed_at,
+            }),
+        };
+
+        self.redis
+            .publish_message(
+                &message.sender_id.to_string(),
+                &serde_json::to_string(&ws_message)?,
+            )
+            .await
+    }
+}
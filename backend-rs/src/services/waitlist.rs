@@ -0,0 +1,202 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{AppError, AppResult},
+    models::{ClientInfo, OtpType, SignupMode, WaitlistEntry, WaitlistPosition, WaitlistStatus},
+    services::auth::AuthService,
+    storage::redis::RedisClient,
+};
+
+/// Most entries approved at once
+const MAX_APPROVE_BATCH: i64 = 500;
+
+pub struct WaitlistService {
+    db: PgPool,
+    redis: RedisClient,
+    config: Config,
+}
+
+impl WaitlistService {
+    pub fn new(db: PgPool, redis: RedisClient, config: Config) -> Self {
+        Self { db, redis, config }
+    }
+
+    /// Join the waitlist with a phone or email whose OTP was verified, or
+    /// see where an earlier join stands
+    pub async fn join(
+        &self,
+        target: &str,
+        target_type: OtpType,
+        client: &ClientInfo,
+    ) -> AppResult<WaitlistPosition> {
+        if SignupMode::from_config(&self.config.signup.mode) != SignupMode::Waitlist {
+            return Err(AppError::WaitlistClosed);
+        }
+
+        let verified: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM otps WHERE target = $1 AND type = $2 AND verified = true)",
+        )
+        .bind(target)
+        .bind(target_type)
+        .fetch_one(&self.db)
+        .await?;
+        if !verified {
+            return Err(AppError::OtpNotVerified);
+        }
+
+        let registered: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM users WHERE phone = $1 OR email = $1)")
+                .bind(target)
+                .fetch_one(&self.db)
+                .await?;
+        if registered {
+            return Err(AppError::UserAlreadyExists);
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO waitlist (id, target, target_type, ip_address, country)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (target, target_type) DO NOTHING
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(target)
+        .bind(target_type)
+        .bind(&client.ip_address)
+        .bind(&client.country)
+        .execute(&self.db)
+        .await?;
+
+        let entry: WaitlistEntry =
+            sqlx::query_as("SELECT * FROM waitlist WHERE target = $1 AND target_type = $2")
+                .bind(target)
+                .bind(target_type)
+                .fetch_one(&self.db)
+                .await?;
+        let position = match entry.status {
+            WaitlistStatus::Pending => Some(
+                sqlx::query_scalar(
+                    "SELECT COUNT(*) + 1 FROM waitlist WHERE status = 'pending' AND created_at < $1",
+                )
+                .bind(entry.created_at)
+                .fetch_one(&self.db)
+                .await?,
+            ),
+            _ => None,
+        };
+
+        Ok(WaitlistPosition {
+            status: entry.status,
+            position,
+            joined_at: entry.created_at,
+        })
+    }
+
+    /// Entries with a status, or all of them, oldest first
+    pub async fn list(
+        &self,
+        status: Option<WaitlistStatus>,
+        limit: i64,
+        offset: i64,
+    ) -> AppResult<Vec<WaitlistEntry>> {
+        let entries = sqlx::query_as(
+            r#"
+            SELECT * FROM waitlist
+            WHERE $1::waitlist_status IS NULL OR status = $1
+            ORDER BY created_at
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(status)
+        .bind(limit.clamp(1, 500))
+        .bind(offset.max(0))
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(entries)
+    }
+
+    /// Approve one pending or rejected entry and send it a sign-up OTP
+    pub async fn approve(&self, admin_id: Uuid, entry_id: Uuid) -> AppResult<WaitlistEntry> {
+        let entry: WaitlistEntry = sqlx::query_as(
+            r#"
+            UPDATE waitlist SET status = 'approved', reviewed_by = $2, reviewed_at = NOW()
+            WHERE id = $1 AND status IN ('pending', 'rejected')
+            RETURNING *
+            "#,
+        )
+        .bind(entry_id)
+        .bind(admin_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::WaitlistEntryNotFound)?;
+
+        self.notify(std::slice::from_ref(&entry)).await;
+
+        Ok(entry)
+    }
+
+    /// Approve the `count` longest-waiting entries, for rolling out in batches
+    pub async fn approve_next(&self, admin_id: Uuid, count: i64) -> AppResult<Vec<WaitlistEntry>> {
+        if !(1..=MAX_APPROVE_BATCH).contains(&count) {
+            return Err(AppError::Validation(format!(
+                "Approve 1-{} entries at a time",
+                MAX_APPROVE_BATCH
+            )));
+        }
+
+        let entries: Vec<WaitlistEntry> = sqlx::query_as(
+            r#"
+            UPDATE waitlist SET status = 'approved', reviewed_by = $2, reviewed_at = NOW()
+            WHERE id IN (
+                SELECT id FROM waitlist
+                WHERE status = 'pending'
+                ORDER BY created_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(count)
+        .bind(admin_id)
+        .fetch_all(&self.db)
+        .await?;
+
+        self.notify(&entries).await;
+
+        Ok(entries)
+    }
+
+    /// Reject a pending or approved entry; approved ones can no longer register
+    pub async fn reject(&self, admin_id: Uuid, entry_id: Uuid) -> AppResult<WaitlistEntry> {
+        let entry = sqlx::query_as(
+            r#"
+            UPDATE waitlist SET status = 'rejected', reviewed_by = $2, reviewed_at = NOW()
+            WHERE id = $1 AND status IN ('pending', 'approved')
+            RETURNING *
+            "#,
+        )
+        .bind(entry_id)
+        .bind(admin_id)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(AppError::WaitlistEntryNotFound)?;
+
+        Ok(entry)
+    }
+
+    /// Send approved entries an OTP so they can go straight to registering.
+    /// Failures are only logged; they can ask for another code.
+    async fn notify(&self, entries: &[WaitlistEntry]) {
+        let auth = AuthService::new(self.db.clone(), self.redis.clone(), self.config.clone());
+        for entry in entries {
+            if let Err(e) = auth.send_otp(&entry.target, entry.target_type).await {
+                tracing::warn!("Failed to send waitlist OTP for entry {}: {}", entry.id, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use reqwest::{StatusCode, Url};
+use ring::{
+    aead, agreement, hkdf,
+    rand::{SecureRandom, SystemRandom},
+    signature::{self, EcdsaKeyPair},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::NotificationConfig,
+    error::{AppError, AppResult},
+    models::Device,
+    services::push::{PushNotification, PushProvider},
+};
+
+/// Record size advertised in the encrypted payload's header
+const RECORD_SIZE: u32 = 4096;
+
+/// How long the push service holds a notification for an offline browser
+const PUSH_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// VAPID tokens may be valid for at most a day
+const VAPID_TOKEN_TTL: i64 = 12 * 60 * 60;
+
+/// A browser's push subscription, as `PushManager.subscribe()` returns it.
+/// Stored serialized as the device's push token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebPushSubscription {
+    pub endpoint: String,
+    pub keys: WebPushKeys,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebPushKeys {
+    /// The browser's P-256 public key, uncompressed, base64url
+    pub p256dh: String,
+    /// 16-byte authentication secret, base64url
+    pub auth: String,
+}
+
+impl WebPushSubscription {
+    /// The subscription a device's push token holds, if it is one
+    pub fn from_push_token(push_token: &str) -> Option<Self> {
+        serde_json::from_str(push_token).ok()
+    }
+
+    pub fn validate(&self) -> AppResult<()> {
+        if !self.endpoint.starts_with("https://")
+            || self.endpoint.len() > 2048
+            || Url::parse(&self.endpoint).is_err()
+        {
+            return Err(AppError::Validation(
+                "Push endpoint must be an https URL".to_string(),
+            ));
+        }
+
+        let p256dh = URL_SAFE_NO_PAD.decode(self.keys.p256dh.trim_end_matches('='));
+        if !p256dh.is_ok_and(|key| key.len() == 65 && key[0] == 4) {
+            return Err(AppError::Validation("Invalid p256dh key".to_string()));
+        }
+
+        let auth = URL_SAFE_NO_PAD.decode(self.keys.auth.trim_end_matches('='));
+        if !auth.is_ok_and(|secret| secret.len() == 16) {
+            return Err(AppError::Validation("Invalid auth secret".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Web Push provider (RFC 8030) for browsers, authenticated with VAPID
+/// (RFC 8292). Payloads are encrypted for the subscription (RFC 8291), so
+/// the push service never sees them.
+pub struct WebPushProvider {
+    http: reqwest::Client,
+    key_pair: EcdsaKeyPair,
+    public_key: String,
+    subject: String,
+    rng: SystemRandom,
+}
+
+impl WebPushProvider {
+    /// `None` unless a valid VAPID key pair is configured
+    pub fn from_config(config: &NotificationConfig) -> Option<Self> {
+        let public_key = config.vapid_public_key.as_ref()?;
+        let private_key = config.vapid_private_key.as_ref()?;
+        let rng = SystemRandom::new();
+
+        let key_pair = URL_SAFE_NO_PAD
+            .decode(public_key)
+            .ok()
+            .zip(URL_SAFE_NO_PAD.decode(private_key).ok())
+            .and_then(|(public, private)| {
+                EcdsaKeyPair::from_private_key_and_public_key(
+                    &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                    &private,
+                    &public,
+                    &rng,
+                )
+                .ok()
+            });
+        let Some(key_pair) = key_pair else {
+            tracing::warn!("VAPID_PUBLIC_KEY or VAPID_PRIVATE_KEY is invalid, Web Push is off");
+            return None;
+        };
+
+        Some(Self {
+            http: reqwest::Client::new(),
+            key_pair,
+            public_key: public_key.clone(),
+            subject: config.vapid_subject.clone(),
+            rng,
+        })
+    }
+
+    /// `Authorization` header for a push to `endpoint`: a VAPID token for its
+    /// origin, signed with ES256, plus the public key
+    fn authorization(&self, endpoint: &str) -> AppResult<String> {
+        let audience = Url::parse(endpoint)
+            .map_err(|_| AppError::PushSubscriptionExpired)?
+            .origin()
+            .ascii_serialization();
+
+        let header = URL_SAFE_NO_PAD.encode(json!({ "typ": "JWT", "alg": "ES256" }).to_string());
+        let claims = URL_SAFE_NO_PAD.encode(
+            json!({
+                "aud": audience,
+                "exp": Utc::now().timestamp() + VAPID_TOKEN_TTL,
+                "sub": self.subject,
+            })
+            .to_string(),
+        );
+        let signing_input = format!("{}.{}", header, claims);
+        let signature = self
+            .key_pair
+            .sign(&self.rng, signing_input.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Failed to sign VAPID token"))?;
+
+        Ok(format!(
+            "vapid t={}.{}, k={}",
+            signing_input,
+            URL_SAFE_NO_PAD.encode(signature.as_ref()),
+            self.public_key
+        ))
+    }
+}
+
+#[async_trait]
+impl PushProvider for WebPushProvider {
+    async fn send(&self, device: &Device, notification: &PushNotification) -> AppResult<()> {
+        let Some(subscription) = device
+            .push_token
+            .as_deref()
+            .and_then(WebPushSubscription::from_push_token)
+        else {
+            return Ok(());
+        };
+
+        // What the client's service worker gets to display
+        let payload = json!({
+            "title": notification.title,
+            "body": notification.body,
+            "tag": notification.collapse_key,
+            "badge": notification.badge,
+            "data": notification.data,
+        });
+        let body = encrypt(&subscription, payload.to_string().as_bytes(), &self.rng)?;
+
+        let mut request = self
+            .http
+            .post(&subscription.endpoint)
+            .header("Authorization", self.authorization(&subscription.endpoint)?)
+            .header("Content-Encoding", "aes128gcm")
+            .header("Content-Type", "application/octet-stream")
+            .header("TTL", PUSH_TTL.as_secs().to_string())
+            .body(body);
+        if let Some(collapse_key) = &notification.collapse_key {
+            request = request.header("Topic", topic(collapse_key));
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send push: {}", e))?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::NOT_FOUND | StatusCode::GONE => Err(AppError::PushSubscriptionExpired),
+            status => Err(anyhow::anyhow!("Push service returned {}", status).into()),
+        }
+    }
+}
+
+/// A waiting push is replaced by a newer one with the same topic; topics are
+/// at most 32 base64url characters
+fn topic(collapse_key: &str) -> String {
+    let digest = URL_SAFE_NO_PAD.encode(Sha256::digest(collapse_key.as_bytes()));
+    digest[..32].to_string()
+}
+
+/// Output length for an HKDF expansion
+struct OutputLen(usize);
+
+impl hkdf::KeyType for OutputLen {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+fn hkdf_sha256(salt: &[u8], ikm: &[u8], info: &[u8], len: usize) -> AppResult<Vec<u8>> {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(ikm);
+    let mut out = vec![0; len];
+    prk.expand(&[info], OutputLen(len))
+        .and_then(|okm| okm.fill(&mut out))
+        .map_err(|_| anyhow::anyhow!("HKDF expansion failed"))?;
+    Ok(out)
+}
+
+/// Encrypt a payload for one subscription (RFC 8291) as a single
+/// `aes128gcm` record (RFC 8188), header included
+fn encrypt(
+    subscription: &WebPushSubscription,
+    plaintext: &[u8],
+    rng: &SystemRandom,
+) -> AppResult<Vec<u8>> {
+    let crypto_failed = |_| anyhow::anyhow!("Web Push encryption failed");
+
+    // Keys were checked when the subscription was stored
+    let browser_key = URL_SAFE_NO_PAD
+        .decode(subscription.keys.p256dh.trim_end_matches('='))
+        .map_err(|_| AppError::PushSubscriptionExpired)?;
+    let auth_secret = URL_SAFE_NO_PAD
+        .decode(subscription.keys.auth.trim_end_matches('='))
+        .map_err(|_| AppError::PushSubscriptionExpired)?;
+
+    let ephemeral = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, rng)
+        .map_err(crypto_failed)?;
+    let server_key = ephemeral.compute_public_key().map_err(crypto_failed)?;
+    let shared_secret = agreement::agree_ephemeral(
+        ephemeral,
+        &agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, &browser_key),
+        |secret| secret.to_vec(),
+    )
+    .map_err(|_| AppError::PushSubscriptionExpired)?;
+
+    let mut key_info = b"WebPush: info\0".to_vec();
+    key_info.extend_from_slice(&browser_key);
+    key_info.extend_from_slice(server_key.as_ref());
+    let ikm = hkdf_sha256(&auth_secret, &shared_secret, &key_info, 32)?;
+
+    let mut salt = [0u8; 16];
+    rng.fill(&mut salt).map_err(crypto_failed)?;
+    let content_key = hkdf_sha256(&salt, &ikm, b"Content-Encoding: aes128gcm\0", 16)?;
+    let nonce = hkdf_sha256(&salt, &ikm, b"Content-Encoding: nonce\0", 12)?;
+
+    // The padding delimiter marks the only record as the last
+    let mut record = plaintext.to_vec();
+    record.push(2);
+    let key = aead::LessSafeKey::new(
+        aead::UnboundKey::new(&aead::AES_128_GCM, &content_key).map_err(crypto_failed)?,
+    );
+    let nonce = aead::Nonce::try_assume_unique_for_key(&nonce).map_err(crypto_failed)?;
+    key.seal_in_place_append_tag(nonce, aead::Aad::empty(), &mut record)
+        .map_err(crypto_failed)?;
+
+    let mut body = Vec::with_capacity(21 + server_key.as_ref().len() + record.len());
+    body.extend_from_slice(&salt);
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(server_key.as_ref().len() as u8);
+    body.extend_from_slice(server_key.as_ref());
+    body.extend_from_slice(&record);
+
+    Ok(body)
+}
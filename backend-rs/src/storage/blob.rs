@@ -0,0 +1,212 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::{config::Config, error::AppResult, models::StorageCallStats};
+
+use super::{gcs::GcsStorage, local::LocalStorage, minio::MinioClient};
+
+/// Object storage for stickers, avatars and attachments
+#[async_trait]
+pub trait BlobStorage: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Check the configured buckets exist, creating them where the backend can
+    async fn ensure_buckets(&self) -> AppResult<()>;
+
+    /// Upload a publicly readable object. Objects are served with a long-lived
+    /// Cache-Control, so callers that overwrite a key must version its URL.
+    async fn upload_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Bytes,
+        content_type: &str,
+    ) -> AppResult<String>;
+
+    /// Upload an object that is not publicly readable (quarantined or otherwise
+    /// restricted content)
+    async fn upload_private_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Bytes,
+        content_type: &str,
+    ) -> AppResult<()>;
+
+    async fn download_file(&self, bucket: &str, key: &str) -> AppResult<Bytes>;
+
+    async fn delete_file(&self, bucket: &str, key: &str) -> AppResult<()>;
+
+    async fn file_exists(&self, bucket: &str, key: &str) -> AppResult<bool>;
+
+    fn get_file_url(&self, bucket: &str, key: &str) -> String;
+
+    async fn list_files(&self, bucket: &str, prefix: &str) -> AppResult<Vec<String>>;
+
+    /// Total object count and size of a bucket
+    async fn bucket_usage(&self, bucket: &str) -> AppResult<(i64, i64)>;
+
+    fn stickers_bucket(&self) -> &str;
+
+    fn avatars_bucket(&self) -> &str;
+
+    fn attachments_bucket(&self) -> &str;
+
+    /// Calls made and failed so far; only counted by [`MeteredStorage`]
+    fn call_stats(&self) -> StorageCallStats {
+        StorageCallStats {
+            backend: self.name().to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Select the storage backend named by `STORAGE_PROVIDER`
+pub async fn build_blob_storage(config: &Config) -> AppResult<Arc<dyn BlobStorage>> {
+    let storage: Arc<dyn BlobStorage> = match config.storage.provider.as_str() {
+        "minio" => Arc::new(MinioClient::new(&config.minio, &config.storage).await?),
+        "s3" => Arc::new(MinioClient::aws(&config.storage).await?),
+        "gcs" => Arc::new(GcsStorage::new(&config.storage)),
+        "local" => Arc::new(LocalStorage::new(config)),
+        other => {
+            return Err(anyhow::anyhow!("Unknown storage provider: {}", other).into());
+        }
+    };
+
+    Ok(Arc::new(MeteredStorage::new(storage)))
+}
+
+/// Number of recent calls the error rate is taken over
+const RECENT_CALLS: usize = 100;
+
+/// Counts calls to a backend and how many of them failed
+pub struct MeteredStorage {
+    inner: Arc<dyn BlobStorage>,
+    requests: AtomicU64,
+    errors: AtomicU64,
+    /// Outcome of each recent call, `true` for a failure
+    recent: Mutex<VecDeque<bool>>,
+}
+
+impl MeteredStorage {
+    pub fn new(inner: Arc<dyn BlobStorage>) -> Self {
+        Self {
+            inner,
+            requests: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_CALLS)),
+        }
+    }
+
+    fn record<T>(&self, result: AppResult<T>) -> AppResult<T> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if result.is_err() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Ok(mut recent) = self.recent.lock() {
+            if recent.len() == RECENT_CALLS {
+                recent.pop_front();
+            }
+            recent.push_back(result.is_err());
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl BlobStorage for MeteredStorage {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn ensure_buckets(&self) -> AppResult<()> {
+        self.record(self.inner.ensure_buckets().await)
+    }
+
+    async fn upload_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Bytes,
+        content_type: &str,
+    ) -> AppResult<String> {
+        self.record(self.inner.upload_file(bucket, key, data, content_type).await)
+    }
+
+    async fn upload_private_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Bytes,
+        content_type: &str,
+    ) -> AppResult<()> {
+        self.record(
+            self.inner
+                .upload_private_file(bucket, key, data, content_type)
+                .await,
+        )
+    }
+
+    async fn download_file(&self, bucket: &str, key: &str) -> AppResult<Bytes> {
+        self.record(self.inner.download_file(bucket, key).await)
+    }
+
+    async fn delete_file(&self, bucket: &str, key: &str) -> AppResult<()> {
+        self.record(self.inner.delete_file(bucket, key).await)
+    }
+
+    async fn file_exists(&self, bucket: &str, key: &str) -> AppResult<bool> {
+        self.record(self.inner.file_exists(bucket, key).await)
+    }
+
+    fn get_file_url(&self, bucket: &str, key: &str) -> String {
+        self.inner.get_file_url(bucket, key)
+    }
+
+    async fn list_files(&self, bucket: &str, prefix: &str) -> AppResult<Vec<String>> {
+        self.record(self.inner.list_files(bucket, prefix).await)
+    }
+
+    async fn bucket_usage(&self, bucket: &str) -> AppResult<(i64, i64)> {
+        self.record(self.inner.bucket_usage(bucket).await)
+    }
+
+    fn stickers_bucket(&self) -> &str {
+        self.inner.stickers_bucket()
+    }
+
+    fn avatars_bucket(&self) -> &str {
+        self.inner.avatars_bucket()
+    }
+
+    fn attachments_bucket(&self) -> &str {
+        self.inner.attachments_bucket()
+    }
+
+    fn call_stats(&self) -> StorageCallStats {
+        let recent_error_rate = self
+            .recent
+            .lock()
+            .ok()
+            .filter(|recent| !recent.is_empty())
+            .map(|recent| {
+                recent.iter().filter(|failed| **failed).count() as f64 / recent.len() as f64
+            })
+            .unwrap_or(0.0);
+
+        StorageCallStats {
+            backend: self.name().to_string(),
+            requests: self.requests.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            recent_error_rate,
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::{future, stream::BoxStream, StreamExt};
+use redis::{aio::MultiplexedConnection, AsyncCommands, Client};
+
+use crate::{config::Config, error::AppResult};
+
+/// One event as received from the bus
+#[derive(Debug, Clone)]
+pub struct BusMessage {
+    /// e.g. `messages:{user_id}`
+    pub channel: String,
+    pub payload: String,
+}
+
+/// Events on the subscribed channels until the connection drops
+pub type BusStream = BoxStream<'static, BusMessage>;
+
+/// Cross-instance fanout of WebSocket, presence and broadcast events.
+/// Channels are named the Redis way; a channel ending in `*` subscribes to
+/// every channel with that prefix.
+#[async_trait]
+pub trait EventBus: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn publish(&self, channel: &str, payload: &str) -> AppResult<()>;
+
+    async fn subscribe(&self, channels: &[String]) -> AppResult<BusStream>;
+}
+
+/// Redis pub/sub: fire and forget, the default
+pub struct RedisEventBus {
+    client: Client,
+    conn: MultiplexedConnection,
+}
+
+impl RedisEventBus {
+    pub fn new(client: Client, conn: MultiplexedConnection) -> Self {
+        Self { client, conn }
+    }
+}
+
+#[async_trait]
+impl EventBus for RedisEventBus {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn publish(&self, channel: &str, payload: &str) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        conn.publish::<_, _, ()>(channel, payload).await?;
+        Ok(())
+    }
+
+    async fn subscribe(&self, channels: &[String]) -> AppResult<BusStream> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        for channel in channels {
+            if channel.ends_with('*') {
+                pubsub.psubscribe(channel).await?;
+            } else {
+                pubsub.subscribe(channel).await?;
+            }
+        }
+
+        let stream = pubsub.into_on_message().filter_map(|msg| {
+            let message = msg.get_payload::<String>().ok().map(|payload| BusMessage {
+                channel: msg.get_channel_name().to_string(),
+                payload,
+            });
+            future::ready(message)
+        });
+        Ok(stream.boxed())
+    }
+}
+
+/// The bus named by `EVENT_BUS`; `None` keeps Redis pub/sub
+pub async fn build_event_bus(config: &Config) -> AppResult<Option<Arc<dyn EventBus>>> {
+    match config.event_bus.backend.as_str() {
+        "redis" => Ok(None),
+        #[cfg(feature = "nats")]
+        "nats" => Ok(Some(Arc::new(
+            nats::NatsEventBus::connect(&config.event_bus).await?,
+        ))),
+        #[cfg(feature = "kafka")]
+        "kafka" => Ok(Some(Arc::new(
+            kafka::KafkaEventBus::connect(&config.event_bus).await?,
+        ))),
+        #[cfg(not(feature = "nats"))]
+        "nats" => {
+            Err(anyhow::anyhow!("EVENT_BUS=nats needs a build with `--features nats`").into())
+        }
+        #[cfg(not(feature = "kafka"))]
+        "kafka" => {
+            Err(anyhow::anyhow!("EVENT_BUS=kafka needs a build with `--features kafka`").into())
+        }
+        other => Err(anyhow::anyhow!("Unknown event bus: {}", other).into()),
+    }
+}
+
+/// Whether `channel` is one of `channels` or matches one of its patterns
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+fn matches(channels: &[String], channel: &str) -> bool {
+    channels.iter().any(|c| match c.strip_suffix('*') {
+        Some(prefix) => channel.starts_with(prefix),
+        None => c == channel,
+    })
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use async_nats::Client;
+
+    use super::*;
+    use crate::config::EventBusConfig;
+
+    /// Core NATS subjects, one per channel: `messages:{user}` is published as
+    /// `{prefix}.messages.{user}`
+    pub struct NatsEventBus {
+        client: Client,
+        prefix: String,
+    }
+
+    impl NatsEventBus {
+        pub async fn connect(config: &EventBusConfig) -> AppResult<Self> {
+            let client = async_nats::connect(config.nats_url.as_str())
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to connect to NATS: {}", e))?;
+
+            Ok(Self {
+                client,
+                prefix: config.nats_subject_prefix.clone(),
+            })
+        }
+
+        /// Channel segments never contain `.`, so the mapping reverses
+        fn subject(&self, channel: &str) -> String {
+            format!("{}.{}", self.prefix, channel.replace(':', "."))
+        }
+    }
+
+    #[async_trait]
+    impl EventBus for NatsEventBus {
+        fn name(&self) -> &'static str {
+            "nats"
+        }
+
+        async fn publish(&self, channel: &str, payload: &str) -> AppResult<()> {
+            self.client
+                .publish(self.subject(channel), payload.to_string().into())
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to publish to NATS: {}", e))?;
+            Ok(())
+        }
+
+        async fn subscribe(&self, channels: &[String]) -> AppResult<BusStream> {
+            let mut subscribers = Vec::with_capacity(channels.len());
+            for channel in channels {
+                // A trailing `*` is also NATS's single-token wildcard
+                let subscriber = self
+                    .client
+                    .subscribe(self.subject(channel))
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to subscribe to NATS: {}", e))?;
+                subscribers.push(subscriber);
+            }
+
+            let prefix = format!("{}.", self.prefix);
+            let stream = futures::stream::select_all(subscribers).filter_map(move |msg| {
+                let channel = msg
+                    .subject
+                    .strip_prefix(prefix.as_str())
+                    .map(|subject| subject.replace('.', ":"));
+                let message = channel.zip(String::from_utf8(msg.payload.to_vec()).ok());
+                future::ready(message.map(|(channel, payload)| BusMessage { channel, payload }))
+            });
+            Ok(stream.boxed())
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use chrono::Utc;
+    use rskafka::{
+        client::{
+            consumer::{StartOffset, StreamConsumerBuilder},
+            partition::{Compression, PartitionClient, UnknownTopicHandling},
+            ClientBuilder,
+        },
+        record::Record,
+    };
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::config::EventBusConfig;
+
+    /// Events buffered for local subscribers before the slowest one misses some
+    const LOCAL_BUFFER: usize = 4096;
+
+    /// One topic keyed by channel. Each instance reads every partition from
+    /// where it joined and hands events to its local subscribers; after a
+    /// broker hiccup it resumes from the last offset it read.
+    pub struct KafkaEventBus {
+        partitions: Vec<Arc<PartitionClient>>,
+        local: broadcast::Sender<BusMessage>,
+    }
+
+    impl KafkaEventBus {
+        pub async fn connect(config: &EventBusConfig) -> AppResult<Self> {
+            let client = ClientBuilder::new(config.kafka_brokers.clone())
+                .build()
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to connect to Kafka: {}", e))?;
+            let topic = client
+                .list_topics()
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to list Kafka topics: {}", e))?
+                .into_iter()
+                .find(|t| t.name == config.kafka_topic)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Kafka topic {} does not exist", config.kafka_topic)
+                })?;
+
+            let mut partitions = Vec::with_capacity(topic.partitions.len());
+            for partition in topic.partitions {
+                let client = client
+                    .partition_client(&topic.name, partition, UnknownTopicHandling::Retry)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to open Kafka partition: {}", e))?;
+                partitions.push(Arc::new(client));
+            }
+
+            let (local, _) = broadcast::channel(LOCAL_BUFFER);
+            for partition in &partitions {
+                tokio::spawn(consume(partition.clone(), local.clone()));
+            }
+
+            Ok(Self { partitions, local })
+        }
+    }
+
+    async fn consume(partition: Arc<PartitionClient>, local: broadcast::Sender<BusMessage>) {
+        let mut start = StartOffset::Latest;
+        loop {
+            let mut consumer = StreamConsumerBuilder::new(partition.clone(), start)
+                .with_max_wait_ms(100)
+                .build();
+            while let Some(result) = consumer.next().await {
+                match result {
+                    Ok((record, _)) => {
+                        start = StartOffset::At(record.offset + 1);
+                        let Record { key, value, .. } = record.record;
+                        let channel = key.and_then(|k| String::from_utf8(k).ok());
+                        let payload = value.and_then(|v| String::from_utf8(v).ok());
+                        if let (Some(channel), Some(payload)) = (channel, payload) {
+                            // No receivers just means nobody here is listening
+                            let _ = local.send(BusMessage { channel, payload });
+                        }
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            "Kafka partition {} consumer failed: {}",
+                            partition.partition(),
+                            e
+                        );
+                        break;
+                    }
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    #[async_trait]
+    impl EventBus for KafkaEventBus {
+        fn name(&self) -> &'static str {
+            "kafka"
+        }
+
+        async fn publish(&self, channel: &str, payload: &str) -> AppResult<()> {
+            // The same channel always lands on the same partition, keeping
+            // a user's events in order
+            let hash = channel
+                .bytes()
+                .fold(0_u32, |h, b| h.wrapping_mul(31).wrapping_add(b as u32));
+            let partition = &self.partitions[hash as usize % self.partitions.len()];
+
+            let record = Record {
+                key: Some(channel.as_bytes().to_vec()),
+                value: Some(payload.as_bytes().to_vec()),
+                headers: BTreeMap::new(),
+                timestamp: Utc::now(),
+            };
+            partition
+                .produce(vec![record], Compression::NoCompression)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to publish to Kafka: {}", e))?;
+            Ok(())
+        }
+
+        async fn subscribe(&self, channels: &[String]) -> AppResult<BusStream> {
+            let channels = channels.to_vec();
+            let receiver = self.local.subscribe();
+            let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(message) => return Some((message, receiver)),
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            tracing::warn!(
+                                "Subscriber fell behind Kafka, {} events missed",
+                                missed
+                            );
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            })
+            .filter(move |message| future::ready(matches(&channels, &message.channel)));
+            Ok(stream.boxed())
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::{config::StorageConfig, error::AppResult};
+
+use super::blob::BlobStorage;
+
+const API_BASE: &str = "https://storage.googleapis.com/storage/v1/b";
+const UPLOAD_BASE: &str = "https://storage.googleapis.com/upload/storage/v1/b";
+const TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+const MULTIPART_BOUNDARY: &str = "ansible_talk_gcs_upload";
+
+#[derive(Debug, Deserialize)]
+struct AccessToken {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ObjectList {
+    #[serde(default)]
+    items: Vec<ObjectEntry>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ObjectEntry {
+    #[serde(default)]
+    name: String,
+    /// The JSON API encodes 64-bit sizes as strings
+    #[serde(default)]
+    size: String,
+}
+
+/// Google Cloud Storage through the JSON API, authenticated as the service
+/// account attached to the instance (Cloud Run, GCE, GKE workload identity).
+///
+/// Objects are written without ACLs, which buckets with uniform bucket-level
+/// access reject; public reads are granted on the bucket through IAM.
+pub struct GcsStorage {
+    http: reqwest::Client,
+    config: StorageConfig,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl GcsStorage {
+    pub fn new(config: &StorageConfig) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            config: config.clone(),
+            token: Mutex::new(None),
+        }
+    }
+
+    /// Access token from the metadata server, cached until shortly before expiry
+    async fn access_token(&self) -> AppResult<String> {
+        let mut cached = self.token.lock().await;
+        if let Some((token, expires_at)) = cached.as_ref() {
+            if *expires_at > Instant::now() {
+                return Ok(token.clone());
+            }
+        }
+
+        let response = self
+            .http
+            .get(TOKEN_URL)
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("GCS token request failed: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("GCS token request returned {}", response.status()).into());
+        }
+
+        let token: AccessToken = response
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("Invalid GCS token response: {}", e))?;
+
+        let expires_at = Instant::now() + Duration::from_secs(token.expires_in.saturating_sub(60));
+        *cached = Some((token.access_token.clone(), expires_at));
+
+        Ok(token.access_token)
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> AppResult<reqwest::Response> {
+        let token = self.access_token().await?;
+
+        let response = request
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("GCS request failed: {}", e))?;
+
+        Ok(response)
+    }
+
+    /// `base/{bucket}/{segments...}` with each segment percent-encoded, since
+    /// object names may contain slashes
+    fn url(base: &str, bucket: &str, segments: &[&str]) -> AppResult<Url> {
+        let mut url = Url::parse(base).map_err(|e| anyhow::anyhow!("Invalid GCS URL: {}", e))?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid GCS URL"))?
+            .push(bucket)
+            .extend(segments);
+        Ok(url)
+    }
+
+    async fn upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Bytes,
+        content_type: &str,
+        cache_control: Option<&str>,
+    ) -> AppResult<()> {
+        let mut metadata = serde_json::json!({
+            "name": key,
+            "contentType": content_type,
+        });
+        if let Some(cache_control) = cache_control {
+            metadata["cacheControl"] = cache_control.into();
+        }
+
+        // Multipart upload carries the metadata and media in one request
+        let mut body = Vec::with_capacity(data.len() + 512);
+        body.extend_from_slice(
+            format!(
+                "--{b}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{m}\r\n\
+                 --{b}\r\nContent-Type: {t}\r\n\r\n",
+                b = MULTIPART_BOUNDARY,
+                m = metadata,
+                t = content_type
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&data);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", MULTIPART_BOUNDARY).as_bytes());
+
+        let url = Self::url(UPLOAD_BASE, bucket, &["o"])?;
+        let response = self
+            .send(
+                self.http
+                    .post(url)
+                    .query(&[("uploadType", "multipart")])
+                    .header(
+                        reqwest::header::CONTENT_TYPE,
+                        format!("multipart/related; boundary={}", MULTIPART_BOUNDARY),
+                    )
+                    .body(body),
+            )
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Failed to upload file: GCS returned {}",
+                response.status()
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Page through a bucket listing, handing each page's objects to `visit`
+    async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        mut visit: impl FnMut(Vec<ObjectEntry>),
+    ) -> AppResult<()> {
+        let url = Self::url(API_BASE, bucket, &["o"])?;
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let mut request = self.http.get(url.clone()).query(&[
+                ("prefix", prefix),
+                ("fields", "items(name,size),nextPageToken"),
+            ]);
+            if let Some(token) = &page_token {
+                request = request.query(&[("pageToken", token)]);
+            }
+
+            let response = self.send(request).await?;
+            if !response.status().is_success() {
+                return Err(anyhow::anyhow!(
+                    "Failed to list files: GCS returned {}",
+                    response.status()
+                )
+                .into());
+            }
+
+            let page: ObjectList = response
+                .json()
+                .await
+                .map_err(|e| anyhow::anyhow!("Invalid GCS list response: {}", e))?;
+            visit(page.items);
+
+            match page.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlobStorage for GcsStorage {
+    fn name(&self) -> &'static str {
+        "gcs"
+    }
+
+    /// GCS bucket names are global and created with the project, so they are
+    /// only checked here
+    async fn ensure_buckets(&self) -> AppResult<()> {
+        let buckets = [
+            &self.config.stickers_bucket,
+            &self.config.avatars_bucket,
+            &self.config.attachments_bucket,
+        ];
+
+        for bucket in buckets.into_iter().chain(&self.config.backup_bucket) {
+            let url = Self::url(API_BASE, bucket, &[])?;
+            let response = self.send(self.http.get(url)).await?;
+            if !response.status().is_success() {
+                return Err(anyhow::anyhow!(
+                    "Bucket {} is not accessible: GCS returned {}",
+                    bucket,
+                    response.status()
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn upload_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Bytes,
+        content_type: &str,
+    ) -> AppResult<String> {
+        self.upload(
+            bucket,
+            key,
+            data,
+            content_type,
+            Some("public, max-age=31536000, immutable"),
+        )
+        .await?;
+
+        Ok(self.get_file_url(bucket, key))
+    }
+
+    async fn upload_private_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Bytes,
+        content_type: &str,
+    ) -> AppResult<()> {
+        self.upload(bucket, key, data, content_type, Some("private, no-store"))
+            .await
+    }
+
+    async fn download_file(&self, bucket: &str, key: &str) -> AppResult<Bytes> {
+        let url = Self::url(API_BASE, bucket, &["o", key])?;
+        let response = self
+            .send(self.http.get(url).query(&[("alt", "media")]))
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Failed to download file: GCS returned {}",
+                response.status()
+            )
+            .into());
+        }
+
+        let data = response
+            .bytes()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read file body: {}", e))?;
+
+        Ok(data)
+    }
+
+    async fn delete_file(&self, bucket: &str, key: &str) -> AppResult<()> {
+        let url = Self::url(API_BASE, bucket, &["o", key])?;
+        let response = self.send(self.http.delete(url)).await?;
+
+        // Deleting a missing object succeeds, as it does on S3
+        let status = response.status();
+        if !status.is_success() && status != StatusCode::NOT_FOUND {
+            return Err(anyhow::anyhow!("Failed to delete file: GCS returned {}", status).into());
+        }
+
+        Ok(())
+    }
+
+    async fn file_exists(&self, bucket: &str, key: &str) -> AppResult<bool> {
+        let url = Self::url(API_BASE, bucket, &["o", key])?;
+        let response = self
+            .send(self.http.get(url).query(&[("fields", "name")]))
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(anyhow::anyhow!("GCS returned {}", status).into()),
+        }
+    }
+
+    fn get_file_url(&self, bucket: &str, key: &str) -> String {
+        match &self.config.public_url {
+            Some(public_url) => format!("{}/{}/{}", public_url, bucket, key),
+            None => format!("https://storage.googleapis.com/{}/{}", bucket, key),
+        }
+    }
+
+    async fn list_files(&self, bucket: &str, prefix: &str) -> AppResult<Vec<String>> {
+        let mut keys = Vec::new();
+        self.list_objects(bucket, prefix, |items| {
+            keys.extend(items.into_iter().map(|item| item.name));
+        })
+        .await?;
+
+        Ok(keys)
+    }
+
+    async fn bucket_usage(&self, bucket: &str) -> AppResult<(i64, i64)> {
+        let mut objects = 0i64;
+        let mut bytes = 0i64;
+        self.list_objects(bucket, "", |items| {
+            for item in items {
+                objects += 1;
+                bytes += item.size.parse::<i64>().unwrap_or(0);
+            }
+        })
+        .await?;
+
+        Ok((objects, bytes))
+    }
+
+    fn stickers_bucket(&self) -> &str {
+        &self.config.stickers_bucket
+    }
+
+    fn avatars_bucket(&self) -> &str {
+        &self.config.avatars_bucket
+    }
+
+    fn attachments_bucket(&self) -> &str {
+        &self.config.attachments_bucket
+    }
+}
@@ -0,0 +1,240 @@
+use std::path::{Component, Path, PathBuf};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::{
+    config::Config,
+    error::{AppError, AppResult},
+};
+
+use super::blob::BlobStorage;
+
+/// Route the public directory is served under, relative to the server root
+pub const LOCAL_FILES_ROUTE: &str = "/files";
+
+/// Files on local disk for development, so the backend runs without MinIO.
+///
+/// Public objects live under `{dir}/public/{bucket}/{key}` and are served by
+/// the static `/files` route; private objects live under `{dir}/private` and
+/// are never served.
+pub struct LocalStorage {
+    root: PathBuf,
+    base_url: String,
+    stickers_bucket: String,
+    avatars_bucket: String,
+    attachments_bucket: String,
+}
+
+impl LocalStorage {
+    pub fn new(config: &Config) -> Self {
+        let base_url = config.storage.public_url.clone().unwrap_or_else(|| {
+            format!("http://localhost:{}{}", config.server.port, LOCAL_FILES_ROUTE)
+        });
+
+        Self {
+            root: config.storage.local_dir.clone(),
+            base_url,
+            stickers_bucket: config.storage.stickers_bucket.clone(),
+            avatars_bucket: config.storage.avatars_bucket.clone(),
+            attachments_bucket: config.storage.attachments_bucket.clone(),
+        }
+    }
+
+    /// Directory served by the static route
+    pub fn public_dir(dir: &Path) -> PathBuf {
+        dir.join("public")
+    }
+
+    /// Resolve an object path, refusing keys that would escape the bucket
+    fn path(&self, visibility: &str, bucket: &str, key: &str) -> AppResult<PathBuf> {
+        let relative = Path::new(key);
+        let safe = !key.is_empty()
+            && relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+        if !safe || bucket.contains(['/', '\\']) || bucket.starts_with('.') {
+            return Err(AppError::Validation(format!("Invalid object key: {}", key)));
+        }
+
+        Ok(self.root.join(visibility).join(bucket).join(relative))
+    }
+
+    async fn write(&self, path: PathBuf, data: Bytes) -> AppResult<()> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to create directory: {}", e))?;
+        }
+
+        tokio::fs::write(&path, &data)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to upload file: {}", e))?;
+
+        Ok(())
+    }
+
+    /// The public path if it exists, else the private one
+    async fn existing_path(&self, bucket: &str, key: &str) -> AppResult<Option<PathBuf>> {
+        for visibility in ["public", "private"] {
+            let path = self.path(visibility, bucket, key)?;
+            if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+                return Ok(Some(path));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Every file below a bucket as `(key, size)`, public and private
+    async fn walk(&self, bucket: &str) -> AppResult<Vec<(String, u64)>> {
+        let mut files = Vec::new();
+
+        for visibility in ["public", "private"] {
+            let bucket_dir = self.root.join(visibility).join(bucket);
+            let mut pending = vec![bucket_dir.clone()];
+
+            while let Some(dir) = pending.pop() {
+                let mut entries = match tokio::fs::read_dir(&dir).await {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(anyhow::anyhow!("Failed to list files: {}", e).into()),
+                };
+
+                while let Some(entry) = entries
+                    .next_entry()
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to list files: {}", e))?
+                {
+                    let metadata = entry
+                        .metadata()
+                        .await
+                        .map_err(|e| anyhow::anyhow!("Failed to list files: {}", e))?;
+                    let path = entry.path();
+
+                    if metadata.is_dir() {
+                        pending.push(path);
+                    } else if let Ok(relative) = path.strip_prefix(&bucket_dir) {
+                        let key = relative
+                            .components()
+                            .map(|c| c.as_os_str().to_string_lossy())
+                            .collect::<Vec<_>>()
+                            .join("/");
+                        files.push((key, metadata.len()));
+                    }
+                }
+            }
+        }
+
+        Ok(files)
+    }
+}
+
+#[async_trait]
+impl BlobStorage for LocalStorage {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn ensure_buckets(&self) -> AppResult<()> {
+        let buckets = [
+            &self.stickers_bucket,
+            &self.avatars_bucket,
+            &self.attachments_bucket,
+        ];
+
+        for visibility in ["public", "private"] {
+            for bucket in buckets {
+                tokio::fs::create_dir_all(self.root.join(visibility).join(bucket))
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to create bucket: {}", e))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn upload_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Bytes,
+        _content_type: &str,
+    ) -> AppResult<String> {
+        self.write(self.path("public", bucket, key)?, data).await?;
+
+        Ok(self.get_file_url(bucket, key))
+    }
+
+    async fn upload_private_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Bytes,
+        _content_type: &str,
+    ) -> AppResult<()> {
+        self.write(self.path("private", bucket, key)?, data).await
+    }
+
+    async fn download_file(&self, bucket: &str, key: &str) -> AppResult<Bytes> {
+        let path = self
+            .existing_path(bucket, key)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Failed to download file: {} not found", key))?;
+
+        let data = tokio::fs::read(&path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to download file: {}", e))?;
+
+        Ok(Bytes::from(data))
+    }
+
+    async fn delete_file(&self, bucket: &str, key: &str) -> AppResult<()> {
+        if let Some(path) = self.existing_path(bucket, key).await? {
+            tokio::fs::remove_file(&path)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to delete file: {}", e))?;
+        }
+
+        Ok(())
+    }
+
+    async fn file_exists(&self, bucket: &str, key: &str) -> AppResult<bool> {
+        Ok(self.existing_path(bucket, key).await?.is_some())
+    }
+
+    fn get_file_url(&self, bucket: &str, key: &str) -> String {
+        format!("{}/{}/{}", self.base_url, bucket, key)
+    }
+
+    async fn list_files(&self, bucket: &str, prefix: &str) -> AppResult<Vec<String>> {
+        let keys = self
+            .walk(bucket)
+            .await?
+            .into_iter()
+            .map(|(key, _)| key)
+            .filter(|key| key.starts_with(prefix))
+            .collect();
+
+        Ok(keys)
+    }
+
+    async fn bucket_usage(&self, bucket: &str) -> AppResult<(i64, i64)> {
+        let files = self.walk(bucket).await?;
+        let bytes = files.iter().map(|(_, size)| *size as i64).sum();
+
+        Ok((files.len() as i64, bytes))
+    }
+
+    fn stickers_bucket(&self) -> &str {
+        &self.stickers_bucket
+    }
+
+    fn avatars_bucket(&self) -> &str {
+        &self.avatars_bucket
+    }
+
+    fn attachments_bucket(&self) -> &str {
+        &self.attachments_bucket
+    }
+}
@@ -1,4 +1,5 @@
-use aws_config::Region;
+use async_trait::async_trait;
+use aws_config::{BehaviorVersion, Region};
 use aws_sdk_s3::{
     config::Credentials,
     primitives::ByteStream,
@@ -7,27 +8,32 @@ use aws_sdk_s3::{
 };
 use bytes::Bytes;
 
-use crate::{config::MinioConfig, error::AppResult};
+use crate::{
+    config::{MinioConfig, StorageConfig},
+    error::AppResult,
+};
+
+use super::blob::BlobStorage;
 
+/// S3-compatible storage: MinIO with static keys, or AWS S3 with the default
+/// credential chain
 #[derive(Clone)]
 pub struct MinioClient {
     client: Client,
-    config: MinioConfig,
+    config: StorageConfig,
+    /// Path-style endpoint; `None` for AWS S3
+    endpoint: Option<String>,
+    region: String,
 }
 
 impl MinioClient {
-    pub async fn new(config: &MinioConfig) -> AppResult<Self> {
-        let creds = Credentials::new(
-            &config.access_key,
-            &config.secret_key,
-            None,
-            None,
-            "minio",
-        );
+    pub async fn new(minio: &MinioConfig, config: &StorageConfig) -> AppResult<Self> {
+        let creds = Credentials::new(&minio.access_key, &minio.secret_key, None, None, "minio");
 
         let s3_config = Config::builder()
-            .region(Region::new(config.region.clone()))
-            .endpoint_url(&config.endpoint)
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new(minio.region.clone()))
+            .endpoint_url(&minio.endpoint)
             .credentials_provider(creds)
             .force_path_style(true)
             .build();
@@ -37,31 +43,45 @@ impl MinioClient {
         Ok(Self {
             client,
             config: config.clone(),
+            endpoint: Some(minio.endpoint.clone()),
+            region: minio.region.clone(),
         })
     }
 
-    pub async fn ensure_buckets(&self) -> AppResult<()> {
-        let buckets = [
-            &self.config.stickers_bucket,
-            &self.config.avatars_bucket,
-            &self.config.attachments_bucket,
-        ];
-
-        for bucket in buckets {
-            self.create_bucket_if_not_exists(bucket).await?;
-        }
+    /// AWS S3 with credentials and region from the environment, instance
+    /// profile, ECS task role or web identity (IRSA)
+    pub async fn aws(config: &StorageConfig) -> AppResult<Self> {
+        let sdk_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        let region = sdk_config
+            .region()
+            .map(|r| r.to_string())
+            .unwrap_or_else(|| "us-east-1".to_string());
 
-        Ok(())
+        Ok(Self {
+            client: Client::new(&sdk_config),
+            config: config.clone(),
+            endpoint: None,
+            region,
+        })
     }
 
-    async fn create_bucket_if_not_exists(&self, bucket: &str) -> AppResult<()> {
+    async fn create_bucket_if_not_exists(
+        &self,
+        bucket: &str,
+        acl: BucketCannedAcl,
+    ) -> AppResult<()> {
         let result = self.client.head_bucket().bucket(bucket).send().await;
 
-        if result.is_err() {
+        if let Err(e) = result {
+            // AWS buckets are provisioned with their policies out of band
+            if self.endpoint.is_none() {
+                return Err(anyhow::anyhow!("Bucket {} is not accessible: {}", bucket, e).into());
+            }
+
             self.client
                 .create_bucket()
                 .bucket(bucket)
-                .acl(BucketCannedAcl::PublicRead)
+                .acl(acl)
                 .send()
                 .await
                 .map_err(|e| anyhow::anyhow!("Failed to create bucket: {}", e))?;
@@ -71,7 +91,43 @@ impl MinioClient {
         Ok(())
     }
 
-    pub async fn upload_file(
+    /// New AWS buckets enforce bucket-owner ownership and reject ACLs, so
+    /// public reads there come from the bucket policy instead
+    fn object_acl(&self, acl: ObjectCannedAcl) -> Option<ObjectCannedAcl> {
+        self.endpoint.is_some().then_some(acl)
+    }
+}
+
+#[async_trait]
+impl BlobStorage for MinioClient {
+    fn name(&self) -> &'static str {
+        if self.endpoint.is_some() {
+            "minio"
+        } else {
+            "s3"
+        }
+    }
+
+    async fn ensure_buckets(&self) -> AppResult<()> {
+        let buckets = [
+            &self.config.stickers_bucket,
+            &self.config.avatars_bucket,
+            &self.config.attachments_bucket,
+        ];
+
+        for bucket in buckets {
+            self.create_bucket_if_not_exists(bucket, BucketCannedAcl::PublicRead)
+                .await?;
+        }
+        if let Some(bucket) = &self.config.backup_bucket {
+            self.create_bucket_if_not_exists(bucket, BucketCannedAcl::Private)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn upload_file(
         &self,
         bucket: &str,
         key: &str,
@@ -84,7 +140,8 @@ impl MinioClient {
             .key(key)
             .body(ByteStream::from(data))
             .content_type(content_type)
-            .acl(ObjectCannedAcl::PublicRead)
+            .cache_control("public, max-age=31536000, immutable")
+            .set_acl(self.object_acl(ObjectCannedAcl::PublicRead))
             .send()
             .await
             .map_err(|e| anyhow::anyhow!("Failed to upload file: {}", e))?;
@@ -92,7 +149,28 @@ impl MinioClient {
         Ok(self.get_file_url(bucket, key))
     }
 
-    pub async fn download_file(&self, bucket: &str, key: &str) -> AppResult<Bytes> {
+    async fn upload_private_file(
+        &self,
+        bucket: &str,
+        key: &str,
+        data: Bytes,
+        content_type: &str,
+    ) -> AppResult<()> {
+        self.client
+            .put_object()
+            .bucket(bucket)
+            .key(key)
+            .body(ByteStream::from(data))
+            .content_type(content_type)
+            .set_acl(self.object_acl(ObjectCannedAcl::Private))
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to upload file: {}", e))?;
+
+        Ok(())
+    }
+
+    async fn download_file(&self, bucket: &str, key: &str) -> AppResult<Bytes> {
         let result = self
             .client
             .get_object()
@@ -111,7 +189,7 @@ impl MinioClient {
         Ok(data.into_bytes())
     }
 
-    pub async fn delete_file(&self, bucket: &str, key: &str) -> AppResult<()> {
+    async fn delete_file(&self, bucket: &str, key: &str) -> AppResult<()> {
         self.client
             .delete_object()
             .bucket(bucket)
@@ -123,20 +201,30 @@ impl MinioClient {
         Ok(())
     }
 
-    pub async fn file_exists(&self, bucket: &str, key: &str) -> AppResult<bool> {
-        let result = self.client.head_object().bucket(bucket).key(key).send().await;
+    async fn file_exists(&self, bucket: &str, key: &str) -> AppResult<bool> {
+        let result = self
+            .client
+            .head_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await;
 
         Ok(result.is_ok())
     }
 
-    pub fn get_file_url(&self, bucket: &str, key: &str) -> String {
-        match &self.config.public_url {
-            Some(public_url) => format!("{}/{}/{}", public_url, bucket, key),
-            None => format!("{}/{}/{}", self.config.endpoint, bucket, key),
+    fn get_file_url(&self, bucket: &str, key: &str) -> String {
+        match (&self.config.public_url, &self.endpoint) {
+            (Some(public_url), _) => format!("{}/{}/{}", public_url, bucket, key),
+            (None, Some(endpoint)) => format!("{}/{}/{}", endpoint, bucket, key),
+            (None, None) => format!(
+                "https://{}.s3.{}.amazonaws.com/{}",
+                bucket, self.region, key
+            ),
         }
     }
 
-    pub async fn list_files(&self, bucket: &str, prefix: &str) -> AppResult<Vec<String>> {
+    async fn list_files(&self, bucket: &str, prefix: &str) -> AppResult<Vec<String>> {
         let result = self
             .client
             .list_objects_v2()
@@ -155,16 +243,46 @@ impl MinioClient {
         Ok(keys)
     }
 
-    // Bucket accessors
-    pub fn stickers_bucket(&self) -> &str {
+    async fn bucket_usage(&self, bucket: &str) -> AppResult<(i64, i64)> {
+        let mut objects = 0i64;
+        let mut bytes = 0i64;
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let result = self
+                .client
+                .list_objects_v2()
+                .bucket(bucket)
+                .set_continuation_token(continuation_token.take())
+                .send()
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to list files: {}", e))?;
+
+            for obj in result.contents() {
+                objects += 1;
+                bytes += obj.size().unwrap_or(0);
+            }
+
+            match result.next_continuation_token() {
+                Some(token) if result.is_truncated().unwrap_or(false) => {
+                    continuation_token = Some(token.to_string());
+                }
+                _ => break,
+            }
+        }
+
+        Ok((objects, bytes))
+    }
+
+    fn stickers_bucket(&self) -> &str {
         &self.config.stickers_bucket
     }
 
-    pub fn avatars_bucket(&self) -> &str {
+    fn avatars_bucket(&self) -> &str {
         &self.config.avatars_bucket
     }
 
-    pub fn attachments_bucket(&self) -> &str {
+    fn attachments_bucket(&self) -> &str {
         &self.config.attachments_bucket
     }
 }
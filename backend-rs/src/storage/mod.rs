@@ -1,2 +1,6 @@
+pub mod blob;
+pub mod event_bus;
+pub mod gcs;
+pub mod local;
 pub mod minio;
 pub mod redis;
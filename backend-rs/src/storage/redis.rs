@@ -1,25 +1,102 @@
+use chrono::{DateTime, Utc};
 use redis::{aio::MultiplexedConnection, AsyncCommands, Client};
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
 
+use super::event_bus::{BusStream, EventBus, RedisEventBus};
 use crate::error::AppResult;
 
+/// Numbered events stay replayable this long, and a resume token stays
+/// usable this long after its connection drops
+pub const EVENT_LOG_TTL: Duration = Duration::from_secs(300);
+
+/// A user's event sequence outlives any resume window by a wide margin
+const EVENT_SEQ_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Events a reconnecting client would discard anyway; never numbered
+const EPHEMERAL_EVENTS: [&str; 3] = ["typing_users", "presence", "pong"];
+
+/// An instance that has not sent a heartbeat for this long is presumed gone,
+/// and routes to its devices are ignored
+pub const INSTANCE_TTL: Duration = Duration::from_secs(30);
+
+/// Hold the reservation `KEYS[1]` for the holder `ARGV[1]` for `ARGV[2]`
+/// seconds. `KEYS[2]` remembers the one reservation each holder may have, and
+/// the one held before is let go. Returns 0 if someone else holds it.
+const RESERVE_USERNAME: &str = r#"
+local current = redis.call('GET', KEYS[1])
+if current and current ~= ARGV[1] then
+    return 0
+end
+local previous = redis.call('GET', KEYS[2])
+if previous and previous ~= KEYS[1] and redis.call('GET', previous) == ARGV[1] then
+    redis.call('DEL', previous)
+end
+redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[2])
+redis.call('SET', KEYS[2], KEYS[1], 'EX', ARGV[2])
+return 1
+"#;
+
+/// Set `KEYS[1]` to `ARGV[2]`, keeping its expiry, only while it still holds
+/// `ARGV[1]`. Returns 0 if it changed or expired in the meantime.
+const REPLACE_IF_UNCHANGED: &str = r#"
+if redis.call('GET', KEYS[1]) ~= ARGV[1] then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[2], 'KEEPTTL')
+return 1
+"#;
+
+/// Where and since when a device is connected, for `GET /users/me/connections`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConnectionRecord {
+    pub instance_id: String,
+    pub connected_at: DateTime<Utc>,
+    pub last_ping_at: DateTime<Utc>,
+}
+
+/// An event for one device, sent to the instance holding its connection
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoutedEvent {
+    pub user_id: String,
+    pub device_id: i32,
+    pub event: String,
+}
+
 #[derive(Clone)]
 pub struct RedisClient {
     client: Client,
     conn: MultiplexedConnection,
+    /// Carries pub/sub; Redis itself unless `EVENT_BUS` names a broker
+    bus: Arc<dyn EventBus>,
 }
 
 impl RedisClient {
     pub async fn new(url: &str) -> AppResult<Self> {
         let client = Client::open(url)?;
         let conn = client.get_multiplexed_async_connection().await?;
-        Ok(Self { client, conn })
+        let bus = Arc::new(RedisEventBus::new(client.clone(), conn.clone()));
+        Ok(Self { client, conn, bus })
+    }
+
+    /// Fan events out over another bus. Sequence numbers and the replay log
+    /// stay in Redis.
+    pub fn with_event_bus(mut self, bus: Arc<dyn EventBus>) -> Self {
+        self.bus = bus;
+        self
     }
 
     pub fn client(&self) -> &Client {
         &self.client
     }
 
+    /// Round trip to the server, for health checks
+    pub async fn ping(&self) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        redis::cmd("PING").query_async::<_, ()>(&mut conn).await?;
+        Ok(())
+    }
+
     // Session management
     pub async fn set_session(
         &self,
@@ -29,7 +106,7 @@ impl RedisClient {
     ) -> AppResult<()> {
         let mut conn = self.conn.clone();
         let key = format!("session:{}", session_id);
-        conn.set_ex(&key, user_id, ttl.as_secs()).await?;
+        conn.set_ex::<_, _, ()>(&key, user_id, ttl.as_secs()).await?;
         Ok(())
     }
 
@@ -43,7 +120,7 @@ impl RedisClient {
     pub async fn delete_session(&self, session_id: &str) -> AppResult<()> {
         let mut conn = self.conn.clone();
         let key = format!("session:{}", session_id);
-        conn.del(&key).await?;
+        conn.del::<_, ()>(&key).await?;
         Ok(())
     }
 
@@ -52,7 +129,7 @@ impl RedisClient {
         let pattern = format!("session:{}:*", user_id);
         let keys: Vec<String> = conn.keys(&pattern).await?;
         if !keys.is_empty() {
-            conn.del(keys).await?;
+            conn.del::<_, ()>(keys).await?;
         }
         Ok(())
     }
@@ -61,7 +138,7 @@ impl RedisClient {
     pub async fn set_otp(&self, target: &str, code: &str, ttl: Duration) -> AppResult<()> {
         let mut conn = self.conn.clone();
         let key = format!("otp:{}", target);
-        conn.set_ex(&key, code, ttl.as_secs()).await?;
+        conn.set_ex::<_, _, ()>(&key, code, ttl.as_secs()).await?;
         Ok(())
     }
 
@@ -75,7 +152,102 @@ impl RedisClient {
     pub async fn delete_otp(&self, target: &str) -> AppResult<()> {
         let mut conn = self.conn.clone();
         let key = format!("otp:{}", target);
-        conn.del(&key).await?;
+        conn.del::<_, ()>(&key).await?;
+        Ok(())
+    }
+
+    // Rate limiting
+    /// Increment a fixed-window counter, starting the window on first hit
+    pub async fn incr_rate_limit(&self, key: &str, window: Duration) -> AppResult<i64> {
+        let mut conn = self.conn.clone();
+        let key = format!("ratelimit:{}", key);
+        let count: i64 = conn.incr(&key, 1).await?;
+        if count == 1 {
+            conn.expire::<_, ()>(&key, window.as_secs() as i64).await?;
+        }
+        Ok(count)
+    }
+
+    /// A fixed-window counter's count and seconds left, without counting a hit
+    pub async fn get_rate_limit(&self, key: &str) -> AppResult<Option<(i64, i64)>> {
+        let mut conn = self.conn.clone();
+        let key = format!("ratelimit:{}", key);
+        let count: Option<i64> = conn.get(&key).await?;
+        let Some(count) = count else {
+            return Ok(None);
+        };
+        let ttl: i64 = conn.ttl(&key).await?;
+        Ok(Some((count, ttl)))
+    }
+
+    /// Keys of live counters under `prefix`, with the prefix stripped
+    pub async fn list_rate_limits(&self, prefix: &str) -> AppResult<Vec<String>> {
+        let mut conn = self.conn.clone();
+        let full = format!("ratelimit:{}", prefix);
+        let keys: Vec<String> = conn.keys(format!("{}*", full)).await?;
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&full).map(str::to_string))
+            .collect())
+    }
+
+    pub async fn delete_rate_limit(&self, key: &str) -> AppResult<bool> {
+        let mut conn = self.conn.clone();
+        let removed: i64 = conn.del(format!("ratelimit:{}", key)).await?;
+        Ok(removed > 0)
+    }
+
+    /// Claim a throttle slot; returns false if the key was claimed within `ttl`
+    pub async fn try_throttle(&self, key: &str, ttl: Duration) -> AppResult<bool> {
+        let mut conn = self.conn.clone();
+        let key = format!("throttle:{}", key);
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(&key)
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl.as_secs().max(1))
+            .query_async(&mut conn)
+            .await?;
+        Ok(claimed.is_some())
+    }
+
+    // Spam control
+    /// Record a content hash in a conversation and return how many distinct
+    /// conversations the sender has posted it to within the window
+    pub async fn track_duplicate_content(
+        &self,
+        user_id: &str,
+        content_hash: &str,
+        conversation_id: &str,
+        window: Duration,
+    ) -> AppResult<i64> {
+        let mut conn = self.conn.clone();
+        let key = format!("spam:dup:{}:{}", user_id, content_hash);
+        conn.sadd::<_, _, ()>(&key, conversation_id).await?;
+        conn.expire::<_, ()>(&key, window.as_secs() as i64).await?;
+        let count: i64 = conn.scard(&key).await?;
+        Ok(count)
+    }
+
+    pub async fn set_shadow_limit(&self, user_id: &str, ttl: Duration) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("spam:shadow:{}", user_id);
+        conn.set_ex::<_, _, ()>(&key, "1", ttl.as_secs()).await?;
+        Ok(())
+    }
+
+    pub async fn is_shadow_limited(&self, user_id: &str) -> AppResult<bool> {
+        let mut conn = self.conn.clone();
+        let key = format!("spam:shadow:{}", user_id);
+        let exists: bool = conn.exists(&key).await?;
+        Ok(exists)
+    }
+
+    pub async fn clear_shadow_limit(&self, user_id: &str) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("spam:shadow:{}", user_id);
+        conn.del::<_, ()>(&key).await?;
         Ok(())
     }
 
@@ -88,7 +260,7 @@ impl RedisClient {
     ) -> AppResult<()> {
         let mut conn = self.conn.clone();
         let key = format!("presence:{}", user_id);
-        conn.set_ex(&key, status, ttl.as_secs()).await?;
+        conn.set_ex::<_, _, ()>(&key, status, ttl.as_secs()).await?;
         Ok(())
     }
 
@@ -99,18 +271,517 @@ impl RedisClient {
         Ok(value.unwrap_or_else(|| "offline".to_string()))
     }
 
+    // Typing indicators
+    /// Record the user as typing in the conversation until `expires_at`
+    /// (Unix milliseconds)
+    pub async fn set_typing(
+        &self,
+        conversation_id: &str,
+        user_id: &str,
+        expires_at: i64,
+        ttl: Duration,
+    ) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("typing:{}", conversation_id);
+        conn.zadd::<_, _, _, ()>(&key, user_id, expires_at).await?;
+        conn.expire::<_, ()>(&key, ttl.as_secs() as i64 + 1).await?;
+        Ok(())
+    }
+
+    pub async fn clear_typing(&self, conversation_id: &str, user_id: &str) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("typing:{}", conversation_id);
+        conn.zrem::<_, _, ()>(&key, user_id).await?;
+        Ok(())
+    }
+
+    /// Users still typing in the conversation at `now`, with when each
+    /// stops counting as typing; expired ones are dropped
+    pub async fn get_typing(
+        &self,
+        conversation_id: &str,
+        now: i64,
+    ) -> AppResult<Vec<(String, i64)>> {
+        let mut conn = self.conn.clone();
+        let key = format!("typing:{}", conversation_id);
+        conn.zrembyscore::<_, _, _, ()>(&key, "-inf", now).await?;
+        let typers: Vec<(String, f64)> = conn.zrange_withscores(&key, 0, -1).await?;
+        Ok(typers
+            .into_iter()
+            .map(|(user_id, expires_at)| (user_id, expires_at as i64))
+            .collect())
+    }
+
+    /// Have the conversation's typers sent again at `due` (Unix
+    /// milliseconds), unless they are already due sooner
+    pub async fn schedule_typing(&self, conversation_id: &str, due: i64) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        redis::cmd("ZADD")
+            .arg("typing_due")
+            .arg("LT")
+            .arg(due)
+            .arg(conversation_id)
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Claim up to `limit` conversations whose typers are due to be sent.
+    /// Each is claimed by one instance only.
+    pub async fn take_due_typing(&self, now: i64, limit: isize) -> AppResult<Vec<String>> {
+        let mut conn = self.conn.clone();
+        let due: Vec<String> = conn
+            .zrangebyscore_limit("typing_due", "-inf", now, 0, limit)
+            .await?;
+
+        let mut claimed = Vec::with_capacity(due.len());
+        for conversation_id in due {
+            let removed: i64 = conn.zrem("typing_due", &conversation_id).await?;
+            if removed > 0 {
+                claimed.push(conversation_id);
+            }
+        }
+        Ok(claimed)
+    }
+
+    /// Route one device to the instance holding its open WebSocket
+    pub async fn set_device_connected(
+        &self,
+        user_id: &str,
+        device_id: i32,
+        instance_id: &str,
+        connected_at: DateTime<Utc>,
+        ttl: Duration,
+    ) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("ws_device:{}:{}", user_id, device_id);
+        conn.set_ex::<_, _, ()>(&key, instance_id, ttl.as_secs())
+            .await?;
+
+        let record = serde_json::to_string(&ConnectionRecord {
+            instance_id: instance_id.to_string(),
+            connected_at,
+            last_ping_at: Utc::now(),
+        })?;
+        let key = format!("ws_connection:{}:{}", user_id, device_id);
+        conn.set_ex::<_, _, ()>(&key, record, ttl.as_secs()).await?;
+        Ok(())
+    }
+
+    /// Drop the device's route unless a connection on another instance has
+    /// taken it over since
+    pub async fn clear_device_connected(
+        &self,
+        user_id: &str,
+        device_id: i32,
+        instance_id: &str,
+    ) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("ws_device:{}:{}", user_id, device_id);
+        let current: Option<String> = conn.get(&key).await?;
+        if current.as_deref() == Some(instance_id) {
+            conn.del::<_, ()>(&[key, format!("ws_connection:{}:{}", user_id, device_id)])
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// The device's connection record, and whether the instance holding it
+    /// is still alive
+    pub async fn get_device_connection(
+        &self,
+        user_id: &str,
+        device_id: i32,
+    ) -> AppResult<Option<(ConnectionRecord, bool)>> {
+        let mut conn = self.conn.clone();
+        let key = format!("ws_connection:{}:{}", user_id, device_id);
+        let Some(record) = conn.get::<_, Option<String>>(&key).await? else {
+            return Ok(None);
+        };
+        let Ok(record) = serde_json::from_str::<ConnectionRecord>(&record) else {
+            return Ok(None);
+        };
+
+        let alive: bool = conn
+            .exists(format!("ws_instance:{}", record.instance_id))
+            .await?;
+        Ok(Some((record, alive)))
+    }
+
+    /// The live instance holding the device's WebSocket, if any
+    pub async fn get_device_route(
+        &self,
+        user_id: &str,
+        device_id: i32,
+    ) -> AppResult<Option<String>> {
+        let mut conn = self.conn.clone();
+        let key = format!("ws_device:{}:{}", user_id, device_id);
+        let Some(instance_id) = conn.get::<_, Option<String>>(&key).await? else {
+            return Ok(None);
+        };
+
+        let alive: bool = conn.exists(format!("ws_instance:{}", instance_id)).await?;
+        Ok(alive.then_some(instance_id))
+    }
+
+    pub async fn is_device_connected(&self, user_id: &str, device_id: i32) -> AppResult<bool> {
+        Ok(self.get_device_route(user_id, device_id).await?.is_some())
+    }
+
+    /// Keep this instance's routes valid for another `INSTANCE_TTL`
+    pub async fn heartbeat_instance(&self, instance_id: &str) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("ws_instance:{}", instance_id);
+        conn.set_ex::<_, _, ()>(&key, "1", INSTANCE_TTL.as_secs())
+            .await?;
+        Ok(())
+    }
+
+    /// Suppress the user's pushes until the key expires; the value is the
+    /// expiry, for display
+    pub async fn set_push_snooze(
+        &self,
+        user_id: &str,
+        until: &str,
+        ttl: Duration,
+    ) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("push_snooze:{}", user_id);
+        conn.set_ex::<_, _, ()>(&key, until, ttl.as_secs()).await?;
+        Ok(())
+    }
+
+    pub async fn get_push_snooze(&self, user_id: &str) -> AppResult<Option<String>> {
+        let mut conn = self.conn.clone();
+        let key = format!("push_snooze:{}", user_id);
+        let value: Option<String> = conn.get(&key).await?;
+        Ok(value)
+    }
+
+    pub async fn clear_push_snooze(&self, user_id: &str) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("push_snooze:{}", user_id);
+        conn.del::<_, ()>(&key).await?;
+        Ok(())
+    }
+
+    /// Count a message push to a device for grouping. Returns the pushes in
+    /// the device's current window, the conversations they came from, and
+    /// the pushes for this conversation since it last went quiet for `window`.
+    pub async fn track_message_push(
+        &self,
+        user_id: &str,
+        device_id: i32,
+        conversation_id: &str,
+        window: Duration,
+    ) -> AppResult<(i64, i64, i64)> {
+        let mut conn = self.conn.clone();
+        let burst_key = format!("push_burst:{}:{}", user_id, device_id);
+        let chats_key = format!("push_burst_chats:{}:{}", user_id, device_id);
+        let thread_key = format!("push_thread:{}:{}:{}", user_id, device_id, conversation_id);
+        let window = window.as_secs() as i64;
+
+        let pushes: i64 = conn.incr(&burst_key, 1).await?;
+        if pushes == 1 {
+            conn.del::<_, ()>(&chats_key).await?;
+            conn.expire::<_, ()>(&burst_key, window).await?;
+        }
+        conn.sadd::<_, _, ()>(&chats_key, conversation_id).await?;
+        conn.expire::<_, ()>(&chats_key, window).await?;
+        let conversations: i64 = conn.scard(&chats_key).await?;
+
+        let in_conversation: i64 = conn.incr(&thread_key, 1).await?;
+        conn.expire::<_, ()>(&thread_key, window).await?;
+
+        Ok((pushes, conversations, in_conversation))
+    }
+
+    /// Start push grouping over once the device has caught up on these
+    /// conversations
+    pub async fn clear_message_pushes(
+        &self,
+        user_id: &str,
+        device_id: i32,
+        conversation_ids: &[String],
+    ) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let mut keys = vec![
+            format!("push_burst:{}:{}", user_id, device_id),
+            format!("push_burst_chats:{}:{}", user_id, device_id),
+        ];
+        keys.extend(
+            conversation_ids
+                .iter()
+                .map(|id| format!("push_thread:{}:{}:{}", user_id, device_id, id)),
+        );
+        conn.del::<_, ()>(keys).await?;
+        Ok(())
+    }
+
+    // Generic cache
+    pub async fn get_cached(&self, key: &str) -> AppResult<Option<String>> {
+        let mut conn = self.conn.clone();
+        let key = format!("cache:{}", key);
+        let value: Option<String> = conn.get(&key).await?;
+        Ok(value)
+    }
+
+    pub async fn set_cached(&self, key: &str, value: &str, ttl: Duration) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("cache:{}", key);
+        conn.set_ex::<_, _, ()>(&key, value, ttl.as_secs()).await?;
+        Ok(())
+    }
+
+    pub async fn delete_cached(&self, key: &str) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("cache:{}", key);
+        conn.del::<_, ()>(&key).await?;
+        Ok(())
+    }
+
+    /// Read and delete in one step, so only one caller gets the value
+    pub async fn take_cached(&self, key: &str) -> AppResult<Option<String>> {
+        let mut conn = self.conn.clone();
+        let key = format!("cache:{}", key);
+        let value: Option<String> = redis::cmd("GETDEL").arg(&key).query_async(&mut conn).await?;
+        Ok(value)
+    }
+
+    /// Overwrite a cached value read as `current`, unless another writer got
+    /// there first; returns whether it was replaced
+    pub async fn replace_cached(&self, key: &str, current: &str, value: &str) -> AppResult<bool> {
+        let mut conn = self.conn.clone();
+        let replaced: i32 = redis::Script::new(REPLACE_IF_UNCHANGED)
+            .key(format!("cache:{}", key))
+            .arg(current)
+            .arg(value)
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(replaced == 1)
+    }
+
+    // Username reservations
+    /// Hold a username for `holder`; returns false if someone else holds it.
+    /// A holder has one username at a time, so holding another lets the
+    /// previous one go.
+    pub async fn reserve_username(
+        &self,
+        username: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> AppResult<bool> {
+        let mut conn = self.conn.clone();
+        let reserved: i32 = redis::Script::new(RESERVE_USERNAME)
+            .key(format!("username_reservation:{}", username))
+            .key(format!("username_holder:{}", holder))
+            .arg(holder)
+            .arg(ttl.as_secs())
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(reserved == 1)
+    }
+
+    pub async fn get_username_reservation(&self, username: &str) -> AppResult<Option<String>> {
+        let mut conn = self.conn.clone();
+        let key = format!("username_reservation:{}", username);
+        let value: Option<String> = conn.get(&key).await?;
+        Ok(value)
+    }
+
+    pub async fn delete_username_reservation(&self, username: &str) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("username_reservation:{}", username);
+        conn.del::<_, ()>(&key).await?;
+        Ok(())
+    }
+
+    // Maintenance mode
+    pub async fn get_maintenance(&self) -> AppResult<Option<String>> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn.get("maintenance").await?;
+        Ok(value)
+    }
+
+    pub async fn set_maintenance(&self, state: &str) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        conn.set::<_, _, ()>("maintenance", state).await?;
+        Ok(())
+    }
+
+    pub async fn clear_maintenance(&self) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        conn.del::<_, ()>("maintenance").await?;
+        Ok(())
+    }
+
     // Pub/Sub for messaging
     pub async fn publish_message(&self, user_id: &str, message: &str) -> AppResult<()> {
+        let message = self.log_event(user_id, message).await?;
+        self.bus
+            .publish(&format!("messages:{}", user_id), &message)
+            .await
+    }
+
+    /// Number a user event with the next `seq` in the user's stream and keep
+    /// it for reconnecting devices to replay. Ephemeral events pass through
+    /// unnumbered.
+    async fn log_event(&self, user_id: &str, message: &str) -> AppResult<String> {
+        let Ok(serde_json::Value::Object(mut event)) = serde_json::from_str(message) else {
+            return Ok(message.to_string());
+        };
+        let msg_type = event.get("type").and_then(|t| t.as_str()).unwrap_or_default();
+        if EPHEMERAL_EVENTS.contains(&msg_type) {
+            return Ok(message.to_string());
+        }
+
+        let mut conn = self.conn.clone();
+        let seq_key = format!("ws_seq:{}", user_id);
+        let seq: u64 = conn.incr(&seq_key, 1).await?;
+        event.insert("seq".to_string(), seq.into());
+        let logged = serde_json::Value::Object(event).to_string();
+
+        redis::pipe()
+            .set_ex(
+                format!("ws_event:{}:{}", user_id, seq),
+                &logged,
+                EVENT_LOG_TTL.as_secs(),
+            )
+            .ignore()
+            .expire(&seq_key, EVENT_SEQ_TTL.as_secs() as i64)
+            .ignore()
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+
+        Ok(logged)
+    }
+
+    /// The last `seq` handed out in the user's event stream
+    pub async fn current_event_seq(&self, user_id: &str) -> AppResult<u64> {
+        let mut conn = self.conn.clone();
+        let seq: Option<u64> = conn.get(format!("ws_seq:{}", user_id)).await?;
+        Ok(seq.unwrap_or(0))
+    }
+
+    /// Logged events after `after` up to and including `through`, in order;
+    /// `None` where an event has already expired
+    pub async fn get_logged_events(
+        &self,
+        user_id: &str,
+        after: u64,
+        through: u64,
+    ) -> AppResult<Vec<Option<String>>> {
+        if through <= after {
+            return Ok(Vec::new());
+        }
+
         let mut conn = self.conn.clone();
-        let channel = format!("messages:{}", user_id);
-        conn.publish(&channel, message).await?;
+        let mut pipe = redis::pipe();
+        for seq in after + 1..=through {
+            pipe.get(format!("ws_event:{}:{}", user_id, seq));
+        }
+        let events: Vec<Option<String>> = pipe.query_async(&mut conn).await?;
+        Ok(events)
+    }
+
+    // WebSocket resume sessions
+    pub async fn set_resume_session(&self, token: &str, session: &str) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("ws_resume:{}", token);
+        conn.set_ex::<_, _, ()>(&key, session, EVENT_LOG_TTL.as_secs())
+            .await?;
         Ok(())
     }
 
-    pub async fn subscribe_messages(&self, user_id: &str) -> AppResult<redis::aio::PubSub> {
-        let mut pubsub = self.client.get_async_pubsub().await?;
-        let channel = format!("messages:{}", user_id);
-        pubsub.subscribe(&channel).await?;
-        Ok(pubsub)
+    pub async fn get_resume_session(&self, token: &str) -> AppResult<Option<String>> {
+        let mut conn = self.conn.clone();
+        let key = format!("ws_resume:{}", token);
+        let value: Option<String> = conn.get(&key).await?;
+        Ok(value)
+    }
+
+    /// Restart the session's resume window, if it still exists
+    pub async fn touch_resume_session(&self, token: &str) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("ws_resume:{}", token);
+        conn.expire::<_, ()>(&key, EVENT_LOG_TTL.as_secs() as i64)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_resume_session(&self, token: &str) -> AppResult<()> {
+        let mut conn = self.conn.clone();
+        let key = format!("ws_resume:{}", token);
+        conn.del::<_, ()>(&key).await?;
+        Ok(())
+    }
+
+    pub async fn subscribe_messages(&self, user_id: &str) -> AppResult<BusStream> {
+        self.bus.subscribe(&[format!("messages:{}", user_id)]).await
+    }
+
+    /// Publish to one device of a user, such as a delivery retry. Only the
+    /// instance holding the device's connection receives it; nothing is sent
+    /// when the device is not connected.
+    pub async fn publish_device_message(
+        &self,
+        user_id: &str,
+        device_id: i32,
+        message: &str,
+    ) -> AppResult<()> {
+        let Some(instance_id) = self.get_device_route(user_id, device_id).await? else {
+            return Ok(());
+        };
+
+        let routed = RoutedEvent {
+            user_id: user_id.to_string(),
+            device_id,
+            event: message.to_string(),
+        };
+        self.bus
+            .publish(
+                &format!("instance:{}", instance_id),
+                &serde_json::to_string(&routed)?,
+            )
+            .await
+    }
+
+    /// Subscribe to the events routed to one instance's devices
+    pub async fn subscribe_instance(&self, instance_id: &str) -> AppResult<BusStream> {
+        self.bus
+            .subscribe(&[format!("instance:{}", instance_id)])
+            .await
+    }
+
+    /// Subscribe to the given users' messages, or to every user's when
+    /// `user_ids` is empty, plus server-wide broadcasts
+    pub async fn subscribe_events(&self, user_ids: &[String]) -> AppResult<BusStream> {
+        let mut channels: Vec<String> = user_ids
+            .iter()
+            .map(|user_id| format!("messages:{}", user_id))
+            .collect();
+        if channels.is_empty() {
+            channels.push("messages:*".to_string());
+        }
+        channels.push("broadcast".to_string());
+        self.bus.subscribe(&channels).await
+    }
+
+    // Pub/Sub for presence changes, which every instance filters locally
+    pub async fn publish_presence(&self, message: &str) -> AppResult<()> {
+        self.bus.publish("presence", message).await
+    }
+
+    pub async fn subscribe_presence(&self) -> AppResult<BusStream> {
+        self.bus.subscribe(&["presence".to_string()]).await
+    }
+
+    // Pub/Sub for server-wide broadcasts
+    pub async fn publish_broadcast(&self, message: &str) -> AppResult<()> {
+        self.bus.publish("broadcast", message).await
+    }
+
+    pub async fn subscribe_broadcast(&self) -> AppResult<BusStream> {
+        self.bus.subscribe(&["broadcast".to_string()]).await
     }
 }
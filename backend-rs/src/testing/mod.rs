@@ -0,0 +1,300 @@
+//! Self-contained `AppState` construction for end-to-end handler tests,
+//! enabled by the `test-harness` feature.
+//!
+//! `TestApp::spawn` starts an embedded Postgres (or creates a throwaway
+//! database on `TEST_DATABASE_URL`), an in-process mock Redis and the local
+//! blob store under a temp directory, and builds the same router the server
+//! runs. Requests go straight to the router without a listening socket.
+
+pub mod redis;
+
+use std::{net::SocketAddr, path::PathBuf, str::FromStr, sync::Arc};
+
+use axum::{
+    body::Body,
+    extract::connect_info::MockConnectInfo,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use postgresql_embedded::{PostgreSQL, Settings};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    ConnectOptions, Executor, PgPool,
+};
+use tokio::task::JoinHandle;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::{
+    api::websocket::WsHub,
+    build_app,
+    config::Config,
+    logging::SlowLog,
+    models::{ClientInfo, OtpType},
+    services::{auth::AuthService, email::build_email_provider, push::build_push_provider},
+    storage::{blob::build_blob_storage, redis::RedisClient},
+    AppState,
+};
+
+use self::redis::MockRedis;
+
+/// A running app with its backing services; they shut down when it is dropped
+pub struct TestApp {
+    pub state: AppState,
+    router: Router,
+    _postgres: Option<PostgreSQL>,
+    _redis: MockRedis,
+    hub: JoinHandle<()>,
+    storage_dir: PathBuf,
+}
+
+/// A registered user and an access token for its first device
+#[derive(Debug, Clone)]
+pub struct TestUser {
+    pub id: Uuid,
+    pub username: String,
+    pub access_token: String,
+}
+
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    /// Parsed JSON, a string for non-JSON bodies, or null when empty
+    pub body: serde_json::Value,
+}
+
+impl TestApp {
+    pub async fn spawn() -> anyhow::Result<Self> {
+        let mut config = Config::load();
+
+        let (db, postgres) = match std::env::var("TEST_DATABASE_URL") {
+            Ok(url) => (throwaway_database(&url).await?, None),
+            Err(_) => {
+                let (db, postgres) = embedded_database(&mut config).await?;
+                (db, Some(postgres))
+            }
+        };
+        sqlx::migrate!("./migrations").run(&db).await?;
+
+        let redis_server = MockRedis::start().await?;
+        config.redis.host = redis_server.addr().ip().to_string();
+        config.redis.port = redis_server.addr().port();
+        config.redis.password = None;
+        config.redis.db = 0;
+        let redis = RedisClient::new(&redis_server.url()).await?;
+
+        let storage_dir =
+            std::env::temp_dir().join(format!("ansible-talk-test-{}", Uuid::new_v4().simple()));
+        config.storage.provider = "local".to_string();
+        config.storage.local_dir = storage_dir.clone();
+        config.storage.public_url = None;
+        let storage = build_blob_storage(&config).await?;
+        storage.ensure_buckets().await?;
+
+        // Keep third-party providers out of tests
+        config.notifications.push_provider = "log".to_string();
+        config.notifications.email_provider = "log".to_string();
+        config.captcha.provider = "none".to_string();
+        config.moderation.hash_provider = "none".to_string();
+        config.search.provider = "none".to_string();
+        config.translation.provider = "none".to_string();
+        config.gifs.provider = "none".to_string();
+        config.grpc.addr = None;
+        config.matrix.homeserver_url = None;
+
+        let ws_hub = Arc::new(WsHub::new(redis.clone(), config.websocket.clone()));
+        let hub = {
+            let ws_hub = ws_hub.clone();
+            tokio::spawn(async move { ws_hub.run().await })
+        };
+
+        let state = AppState {
+            db,
+            redis,
+            storage,
+            push: build_push_provider(&config.notifications),
+            email: build_email_provider(&config.notifications),
+            config: Arc::new(config),
+            ws_hub,
+            captcha: None,
+            search: None,
+            translator: None,
+            gifs: None,
+            sticker_renderer: None,
+            matrix: None,
+            slow_log: Arc::new(SlowLog::default()),
+        };
+
+        // Handlers that read the peer address see a loopback client
+        let router =
+            build_app(state.clone()).layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))));
+
+        Ok(Self {
+            state,
+            router,
+            _postgres: postgres,
+            _redis: redis_server,
+            hub,
+            storage_dir,
+        })
+    }
+
+    /// Send a request through the router, with an optional bearer token and JSON body
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<serde_json::Value>,
+    ) -> TestResponse {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .expect("valid test request");
+
+        let response = self
+            .router
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("router is infallible");
+
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("readable response body");
+
+        let body = if bytes.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_slice(&bytes).unwrap_or_else(|_| {
+                serde_json::Value::String(String::from_utf8_lossy(&bytes).into_owned())
+            })
+        };
+
+        TestResponse { status, body }
+    }
+
+    pub async fn get(&self, uri: &str, token: Option<&str>) -> TestResponse {
+        self.request(Method::GET, uri, token, None).await
+    }
+
+    pub async fn post(
+        &self,
+        uri: &str,
+        token: Option<&str>,
+        body: serde_json::Value,
+    ) -> TestResponse {
+        self.request(Method::POST, uri, token, Some(body)).await
+    }
+
+    pub async fn put(
+        &self,
+        uri: &str,
+        token: Option<&str>,
+        body: serde_json::Value,
+    ) -> TestResponse {
+        self.request(Method::PUT, uri, token, Some(body)).await
+    }
+
+    pub async fn delete(&self, uri: &str, token: Option<&str>) -> TestResponse {
+        self.request(Method::DELETE, uri, token, None).await
+    }
+
+    /// Register a user by email through the normal OTP flow, reading the code
+    /// back from Redis
+    pub async fn create_user(&self, username: &str) -> anyhow::Result<TestUser> {
+        let auth = AuthService::new(
+            self.state.db.clone(),
+            self.state.redis.clone(),
+            (*self.state.config).clone(),
+        );
+        let email = format!("{}@test.invalid", username);
+
+        auth.send_otp(&email, OtpType::Email).await?;
+        let code = self
+            .state
+            .redis
+            .get_otp(&email)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("OTP for {} was not stored", email))?;
+        auth.verify_otp(&email, OtpType::Email, &code).await?;
+
+        let (user, tokens) = auth
+            .register(
+                None,
+                Some(&email),
+                username,
+                username,
+                "test",
+                "test",
+                None,
+                &ClientInfo::default(),
+            )
+            .await?;
+
+        Ok(TestUser {
+            id: user.id,
+            username: user.username,
+            access_token: tokens.access_token,
+        })
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        self.hub.abort();
+        let _ = std::fs::remove_dir_all(&self.storage_dir);
+    }
+}
+
+/// Start a temporary Postgres and point the config at it
+async fn embedded_database(config: &mut Config) -> anyhow::Result<(PgPool, PostgreSQL)> {
+    let mut postgres = PostgreSQL::new(Settings::default());
+    postgres.setup().await?;
+    postgres.start().await?;
+
+    let database = "ansible_talk";
+    postgres.create_database(database).await?;
+
+    let settings = postgres.settings();
+    config.database.host = settings.host.clone();
+    config.database.port = settings.port;
+    config.database.user = settings.username.clone();
+    config.database.password = settings.password.clone();
+    config.database.database = database.to_string();
+    config.database.ssl_mode = "disable".to_string();
+
+    let db = PgPoolOptions::new()
+        .max_connections(5)
+        .connect(&config.database_url())
+        .await?;
+
+    Ok((db, postgres))
+}
+
+/// Create a uniquely named database on an existing server. It is left in place
+/// for inspection after the test.
+async fn throwaway_database(url: &str) -> anyhow::Result<PgPool> {
+    let options = PgConnectOptions::from_str(url)?;
+    let name = format!("ansible_talk_test_{}", Uuid::new_v4().simple());
+
+    let mut admin = options.connect().await?;
+    admin
+        .execute(format!("CREATE DATABASE \"{}\"", name).as_str())
+        .await?;
+
+    let db = PgPoolOptions::new()
+        .max_connections(5)
+        .connect_with(options.database(&name))
+        .await?;
+
+    Ok(db)
+}
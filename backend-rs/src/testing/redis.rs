@@ -0,0 +1,700 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+/// An in-process Redis speaking RESP2, covering the commands `RedisClient`
+/// issues (strings with expiry, counters, sets, sorted sets, key patterns
+/// and pub/sub). Lua scripts are not run.
+/// State lives for as long as the server does.
+pub struct MockRedis {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl MockRedis {
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let store = Arc::new(Mutex::new(Store::default()));
+
+        let task = tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve(socket, store.clone()));
+            }
+        });
+
+        Ok(Self { addr, task })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn url(&self) -> String {
+        format!("redis://{}/0", self.addr)
+    }
+}
+
+impl Drop for MockRedis {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+enum Value {
+    String(Vec<u8>),
+    Set(HashSet<Vec<u8>>),
+    SortedSet(HashMap<Vec<u8>, f64>),
+}
+
+struct Entry {
+    value: Value,
+    expires_at: Option<Instant>,
+}
+
+struct Subscriber {
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct Store {
+    entries: HashMap<String, Entry>,
+    subscribers: HashMap<u64, Subscriber>,
+    next_subscriber: u64,
+}
+
+impl Store {
+    /// The entry for a key, dropping it first if it has expired
+    fn live(&mut self, key: &str) -> Option<&mut Entry> {
+        let expired = self
+            .entries
+            .get(key)
+            .and_then(|e| e.expires_at)
+            .is_some_and(|at| at <= Instant::now());
+        if expired {
+            self.entries.remove(key);
+        }
+        self.entries.get_mut(key)
+    }
+
+    fn set(&mut self, key: String, value: Vec<u8>, ttl: Option<Duration>) {
+        self.entries.insert(
+            key,
+            Entry {
+                value: Value::String(value),
+                expires_at: ttl.map(|ttl| Instant::now() + ttl),
+            },
+        );
+    }
+}
+
+async fn serve(socket: TcpStream, store: Arc<Mutex<Store>>) {
+    let (read, mut write) = socket.into_split();
+    let mut reader = BufReader::new(read);
+
+    // Replies and pub/sub pushes share one ordered writer
+    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let writer = tokio::spawn(async move {
+        while let Some(frame) = rx.recv().await {
+            if write.write_all(&frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let subscriber_id = {
+        let mut store = store.lock().unwrap();
+        store.next_subscriber += 1;
+        store.next_subscriber
+    };
+
+    while let Ok(Some(args)) = read_command(&mut reader).await {
+        if args.is_empty() {
+            continue;
+        }
+        let reply = execute(&store, subscriber_id, &tx, &args);
+        if tx.send(reply).is_err() {
+            break;
+        }
+    }
+
+    store.lock().unwrap().subscribers.remove(&subscriber_id);
+    drop(tx);
+    let _ = writer.await;
+}
+
+/// Read one RESP array of bulk strings; `None` at end of stream
+async fn read_command<R: AsyncBufReadExt + Unpin>(
+    reader: &mut R,
+) -> std::io::Result<Option<Vec<Vec<u8>>>> {
+    let header = match read_line(reader).await? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    let count: usize = match header.strip_prefix('*') {
+        Some(count) => count.parse().unwrap_or(0),
+        // Inline command
+        None => {
+            return Ok(Some(
+                header
+                    .split_whitespace()
+                    .map(|s| s.as_bytes().to_vec())
+                    .collect(),
+            ))
+        }
+    };
+
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len: usize = read_line(reader)
+            .await?
+            .and_then(|line| line.strip_prefix('$').and_then(|l| l.parse().ok()))
+            .ok_or_else(|| std::io::Error::other("expected bulk string"))?;
+
+        let mut data = vec![0; len + 2];
+        reader.read_exact(&mut data).await?;
+        data.truncate(len);
+        args.push(data);
+    }
+
+    Ok(Some(args))
+}
+
+async fn read_line<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn simple(s: &str) -> Vec<u8> {
+    format!("+{}\r\n", s).into_bytes()
+}
+
+fn error(s: &str) -> Vec<u8> {
+    format!("-{}\r\n", s).into_bytes()
+}
+
+fn integer(n: i64) -> Vec<u8> {
+    format!(":{}\r\n", n).into_bytes()
+}
+
+fn bulk(data: Option<&[u8]>) -> Vec<u8> {
+    match data {
+        Some(data) => {
+            let mut frame = format!("${}\r\n", data.len()).into_bytes();
+            frame.extend_from_slice(data);
+            frame.extend_from_slice(b"\r\n");
+            frame
+        }
+        None => b"$-1\r\n".to_vec(),
+    }
+}
+
+fn array(items: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut frame = format!("*{}\r\n", items.len()).into_bytes();
+    for item in items {
+        frame.extend(item);
+    }
+    frame
+}
+
+const WRONG_TYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+fn execute(
+    store: &Mutex<Store>,
+    subscriber_id: u64,
+    tx: &mpsc::UnboundedSender<Vec<u8>>,
+    args: &[Vec<u8>],
+) -> Vec<u8> {
+    let mut store = store.lock().unwrap();
+    let command = String::from_utf8_lossy(&args[0]).to_uppercase();
+    let arg = |i: usize| String::from_utf8_lossy(&args[i]).into_owned();
+    let int_arg = |i: usize| arg(i).parse::<i64>().ok();
+
+    match (command.as_str(), args.len()) {
+        ("PING", _) => simple("PONG"),
+        ("SELECT", 2) | ("CLIENT", _) => simple("OK"),
+        ("GET", 2) => match store.live(&arg(1)) {
+            Some(Entry {
+                value: Value::String(data),
+                ..
+            }) => bulk(Some(data)),
+            Some(_) => error(WRONG_TYPE),
+            None => bulk(None),
+        },
+        ("GETDEL", 2) => match store.live(&arg(1)) {
+            Some(Entry {
+                value: Value::String(data),
+                ..
+            }) => {
+                let data = data.clone();
+                store.entries.remove(&arg(1));
+                bulk(Some(&data))
+            }
+            Some(_) => error(WRONG_TYPE),
+            None => bulk(None),
+        },
+        ("SET", n) if n >= 3 => {
+            let key = arg(1);
+            let mut ttl = None;
+            let mut nx = false;
+            let mut i = 3;
+            while i < n {
+                match arg(i).to_uppercase().as_str() {
+                    "NX" => nx = true,
+                    "EX" | "PX" => {
+                        let Some(amount) = args.get(i + 1).and_then(|_| int_arg(i + 1)) else {
+                            return error("ERR syntax error");
+                        };
+                        let amount = amount.max(0) as u64;
+                        ttl = Some(if arg(i).eq_ignore_ascii_case("EX") {
+                            Duration::from_secs(amount)
+                        } else {
+                            Duration::from_millis(amount)
+                        });
+                        i += 1;
+                    }
+                    _ => return error("ERR syntax error"),
+                }
+                i += 1;
+            }
+
+            if nx && store.live(&key).is_some() {
+                return bulk(None);
+            }
+            store.set(key, args[2].clone(), ttl);
+            simple("OK")
+        }
+        ("SETEX", 4) => match int_arg(2) {
+            Some(secs) => {
+                store.set(
+                    arg(1),
+                    args[3].clone(),
+                    Some(Duration::from_secs(secs.max(0) as u64)),
+                );
+                simple("OK")
+            }
+            None => error("ERR value is not an integer or out of range"),
+        },
+        ("DEL", n) | ("EXISTS", n) if n >= 2 => {
+            let mut count = 0;
+            for i in 1..n {
+                let key = arg(i);
+                if store.live(&key).is_some() {
+                    count += 1;
+                    if command == "DEL" {
+                        store.entries.remove(&key);
+                    }
+                }
+            }
+            integer(count)
+        }
+        ("KEYS", 2) => {
+            let pattern = arg(1);
+            let now = Instant::now();
+            store
+                .entries
+                .retain(|_, e| e.expires_at.is_none_or(|at| at > now));
+            let keys = store
+                .entries
+                .keys()
+                .filter(|key| glob_match(&pattern, key))
+                .map(|key| bulk(Some(key.as_bytes())))
+                .collect();
+            array(keys)
+        }
+        ("INCR", 2) | ("INCRBY", 3) => {
+            let by = if args.len() == 3 { int_arg(2) } else { Some(1) };
+            let Some(by) = by else {
+                return error("ERR value is not an integer or out of range");
+            };
+            let key = arg(1);
+            let (current, expires_at) = match store.live(&key) {
+                Some(Entry {
+                    value: Value::String(data),
+                    expires_at,
+                }) => match String::from_utf8_lossy(data).parse::<i64>() {
+                    Ok(n) => (n, *expires_at),
+                    Err(_) => return error("ERR value is not an integer or out of range"),
+                },
+                Some(_) => return error(WRONG_TYPE),
+                None => (0, None),
+            };
+            let next = current + by;
+            store.entries.insert(
+                key,
+                Entry {
+                    value: Value::String(next.to_string().into_bytes()),
+                    expires_at,
+                },
+            );
+            integer(next)
+        }
+        ("EXPIRE", 3) => {
+            let Some(secs) = int_arg(2) else {
+                return error("ERR value is not an integer or out of range");
+            };
+            let key = arg(1);
+            if secs <= 0 {
+                return integer(store.entries.remove(&key).is_some() as i64);
+            }
+            match store.live(&key) {
+                Some(entry) => {
+                    entry.expires_at = Some(Instant::now() + Duration::from_secs(secs as u64));
+                    integer(1)
+                }
+                None => integer(0),
+            }
+        }
+        ("TTL", 2) => match store.live(&arg(1)) {
+            Some(Entry {
+                expires_at: Some(at),
+                ..
+            }) => integer(at.saturating_duration_since(Instant::now()).as_secs() as i64),
+            Some(_) => integer(-1),
+            None => integer(-2),
+        },
+        ("SADD", n) if n >= 3 => {
+            let key = arg(1);
+            if store.live(&key).is_none() {
+                store.entries.insert(
+                    key.clone(),
+                    Entry {
+                        value: Value::Set(HashSet::new()),
+                        expires_at: None,
+                    },
+                );
+            }
+            match store.live(&key) {
+                Some(Entry {
+                    value: Value::Set(members),
+                    ..
+                }) => {
+                    let added = args[2..]
+                        .iter()
+                        .filter(|member| members.insert(member.to_vec()))
+                        .count();
+                    integer(added as i64)
+                }
+                _ => error(WRONG_TYPE),
+            }
+        }
+        ("SCARD", 2) | ("SMEMBERS", 2) => match store.live(&arg(1)) {
+            Some(Entry {
+                value: Value::Set(members),
+                ..
+            }) => {
+                if command == "SCARD" {
+                    integer(members.len() as i64)
+                } else {
+                    array(members.iter().map(|m| bulk(Some(m))).collect())
+                }
+            }
+            Some(_) => error(WRONG_TYPE),
+            None if command == "SCARD" => integer(0),
+            None => array(Vec::new()),
+        },
+        ("ZADD", n) if n >= 4 => {
+            let key = arg(1);
+            let mut i = 2;
+            let (mut nx, mut xx, mut lt, mut gt) = (false, false, false, false);
+            while i < n {
+                match arg(i).to_uppercase().as_str() {
+                    "NX" => nx = true,
+                    "XX" => xx = true,
+                    "LT" => lt = true,
+                    "GT" => gt = true,
+                    _ => break,
+                }
+                i += 1;
+            }
+            if i == n || (n - i) % 2 != 0 {
+                return error("ERR syntax error");
+            }
+            let mut pairs = Vec::with_capacity((n - i) / 2);
+            for j in (i..n).step_by(2) {
+                let Some(score) = score_arg(&arg(j)) else {
+                    return error("ERR value is not a valid float");
+                };
+                pairs.push((args[j + 1].to_vec(), score));
+            }
+
+            if store.live(&key).is_none() {
+                store.entries.insert(
+                    key.clone(),
+                    Entry {
+                        value: Value::SortedSet(HashMap::new()),
+                        expires_at: None,
+                    },
+                );
+            }
+            let Some(Entry {
+                value: Value::SortedSet(members),
+                ..
+            }) = store.live(&key)
+            else {
+                return error(WRONG_TYPE);
+            };
+            let mut added = 0;
+            for (member, score) in pairs {
+                match members.get(&member) {
+                    Some(_) if nx => {}
+                    Some(&current) if (lt && score >= current) || (gt && score <= current) => {}
+                    Some(_) => {
+                        members.insert(member, score);
+                    }
+                    None if xx => {}
+                    None => {
+                        members.insert(member, score);
+                        added += 1;
+                    }
+                }
+            }
+            integer(added)
+        }
+        ("ZREM", n) if n >= 3 => match store.live(&arg(1)) {
+            Some(Entry {
+                value: Value::SortedSet(members),
+                ..
+            }) => {
+                let removed = args[2..]
+                    .iter()
+                    .filter(|member| members.remove(*member).is_some())
+                    .count();
+                integer(removed as i64)
+            }
+            Some(_) => error(WRONG_TYPE),
+            None => integer(0),
+        },
+        ("ZREMRANGEBYSCORE", 4) => {
+            let (Some(min), Some(max)) = (score_arg(&arg(2)), score_arg(&arg(3))) else {
+                return error("ERR min or max is not a float");
+            };
+            match store.live(&arg(1)) {
+                Some(Entry {
+                    value: Value::SortedSet(members),
+                    ..
+                }) => {
+                    let before = members.len();
+                    members.retain(|_, score| *score < min || *score > max);
+                    integer((before - members.len()) as i64)
+                }
+                Some(_) => error(WRONG_TYPE),
+                None => integer(0),
+            }
+        }
+        ("ZRANGE", n) | ("ZRANGEBYSCORE", n) if n >= 4 => {
+            let mut with_scores = false;
+            let mut limit: Option<(usize, Option<usize>)> = None;
+            let mut i = 4;
+            while i < n {
+                match arg(i).to_uppercase().as_str() {
+                    "WITHSCORES" => with_scores = true,
+                    "LIMIT" if i + 2 < n => {
+                        let (Some(offset), Some(count)) = (int_arg(i + 1), int_arg(i + 2)) else {
+                            return error("ERR value is not an integer or out of range");
+                        };
+                        limit = Some((offset.max(0) as usize, usize::try_from(count).ok()));
+                        i += 2;
+                    }
+                    _ => return error("ERR syntax error"),
+                }
+                i += 1;
+            }
+
+            let members = match store.live(&arg(1)) {
+                Some(Entry {
+                    value: Value::SortedSet(members),
+                    ..
+                }) => sorted_members(members),
+                Some(_) => return error(WRONG_TYPE),
+                None => Vec::new(),
+            };
+            let selected: Vec<(Vec<u8>, f64)> = if command == "ZRANGE" {
+                let (Some(start), Some(stop)) = (int_arg(2), int_arg(3)) else {
+                    return error("ERR value is not an integer or out of range");
+                };
+                let len = members.len() as i64;
+                let start = if start < 0 { len + start } else { start }.max(0);
+                let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+                if start > stop {
+                    Vec::new()
+                } else {
+                    members[start as usize..=stop as usize].to_vec()
+                }
+            } else {
+                let (Some(min), Some(max)) = (score_arg(&arg(2)), score_arg(&arg(3))) else {
+                    return error("ERR min or max is not a float");
+                };
+                let in_range = members
+                    .into_iter()
+                    .filter(|(_, score)| *score >= min && *score <= max);
+                match limit {
+                    Some((offset, Some(count))) => in_range.skip(offset).take(count).collect(),
+                    Some((offset, None)) => in_range.skip(offset).collect(),
+                    None => in_range.collect(),
+                }
+            };
+
+            let mut items = Vec::new();
+            for (member, score) in selected {
+                items.push(bulk(Some(&member)));
+                if with_scores {
+                    items.push(bulk(Some(score.to_string().as_bytes())));
+                }
+            }
+            array(items)
+        }
+        ("PUBLISH", 3) => {
+            let channel = arg(1);
+            let mut receivers = 0;
+            for subscriber in store.subscribers.values() {
+                if subscriber.channels.contains(&channel) {
+                    receivers += 1;
+                    let _ = subscriber.tx.send(array(vec![
+                        bulk(Some(b"message")),
+                        bulk(Some(channel.as_bytes())),
+                        bulk(Some(&args[2])),
+                    ]));
+                }
+                for pattern in &subscriber.patterns {
+                    if glob_match(pattern, &channel) {
+                        receivers += 1;
+                        let _ = subscriber.tx.send(array(vec![
+                            bulk(Some(b"pmessage")),
+                            bulk(Some(pattern.as_bytes())),
+                            bulk(Some(channel.as_bytes())),
+                            bulk(Some(&args[2])),
+                        ]));
+                    }
+                }
+            }
+            integer(receivers)
+        }
+        ("SUBSCRIBE", n) | ("PSUBSCRIBE", n) if n >= 2 => {
+            let kind = command.to_lowercase();
+            let subscriber = store
+                .subscribers
+                .entry(subscriber_id)
+                .or_insert_with(|| Subscriber {
+                    channels: HashSet::new(),
+                    patterns: HashSet::new(),
+                    tx: tx.clone(),
+                });
+
+            // Each name gets its own confirmation; all but the last are pushed
+            // ahead of the reply
+            let mut confirmations = Vec::with_capacity(n - 1);
+            for i in 1..n {
+                let name = arg(i);
+                if command == "SUBSCRIBE" {
+                    subscriber.channels.insert(name.clone());
+                } else {
+                    subscriber.patterns.insert(name.clone());
+                }
+                let count = subscriber.channels.len() + subscriber.patterns.len();
+                confirmations.push(array(vec![
+                    bulk(Some(kind.as_bytes())),
+                    bulk(Some(name.as_bytes())),
+                    integer(count as i64),
+                ]));
+            }
+            let last = confirmations.pop().unwrap_or_default();
+            for confirmation in confirmations {
+                let _ = tx.send(confirmation);
+            }
+            last
+        }
+        ("UNSUBSCRIBE", n) | ("PUNSUBSCRIBE", n) => {
+            let kind = command.to_lowercase();
+            let Some(subscriber) = store.subscribers.get_mut(&subscriber_id) else {
+                return array(vec![bulk(Some(kind.as_bytes())), bulk(None), integer(0)]);
+            };
+            let set = if command == "UNSUBSCRIBE" {
+                &mut subscriber.channels
+            } else {
+                &mut subscriber.patterns
+            };
+            let names: Vec<String> = if n > 1 {
+                (1..n).map(arg).collect()
+            } else {
+                set.iter().cloned().collect()
+            };
+            for name in &names {
+                set.remove(name);
+            }
+            let count = subscriber.channels.len() + subscriber.patterns.len();
+
+            let mut confirmations: Vec<Vec<u8>> = names
+                .iter()
+                .map(|name| {
+                    array(vec![
+                        bulk(Some(kind.as_bytes())),
+                        bulk(Some(name.as_bytes())),
+                        integer(count as i64),
+                    ])
+                })
+                .collect();
+            let last = confirmations.pop().unwrap_or_else(|| {
+                array(vec![bulk(Some(kind.as_bytes())), bulk(None), integer(0)])
+            });
+            for confirmation in confirmations {
+                let _ = tx.send(confirmation);
+            }
+            last
+        }
+        _ => error(&format!("ERR unknown command '{}'", command)),
+    }
+}
+
+/// A sorted set score bound: a float, `-inf` or `+inf`
+fn score_arg(arg: &str) -> Option<f64> {
+    match arg.to_lowercase().as_str() {
+        "-inf" => Some(f64::NEG_INFINITY),
+        "+inf" | "inf" => Some(f64::INFINITY),
+        arg => arg.parse().ok(),
+    }
+}
+
+/// Members by score, then by member, as Redis orders them
+fn sorted_members(members: &HashMap<Vec<u8>, f64>) -> Vec<(Vec<u8>, f64)> {
+    let mut sorted: Vec<(Vec<u8>, f64)> = members
+        .iter()
+        .map(|(member, score)| (member.clone(), *score))
+        .collect();
+    sorted.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    sorted
+}
+
+/// Redis glob patterns: `*`, `?` and `\` escapes
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    fn matches(p: &[char], t: &[char]) -> bool {
+        match p.first() {
+            None => t.is_empty(),
+            Some('*') => (0..=t.len()).any(|i| matches(&p[1..], &t[i..])),
+            Some('?') => !t.is_empty() && matches(&p[1..], &t[1..]),
+            Some('\\') if p.len() > 1 => t.first() == Some(&p[1]) && matches(&p[2..], &t[1..]),
+            Some(c) => t.first() == Some(c) && matches(&p[1..], &t[1..]),
+        }
+    }
+
+    matches(&pattern, &text)
+}
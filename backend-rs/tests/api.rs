@@ -0,0 +1,113 @@
+//! End-to-end handler tests through the real router; run with
+//! `cargo test --features test-harness`
+
+#![cfg(feature = "test-harness")]
+
+use ansible_talk_backend::testing::TestApp;
+use axum::http::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn health_check_responds() -> anyhow::Result<()> {
+    let app = TestApp::spawn().await?;
+
+    let health = app.get("/health", None).await;
+    assert_eq!(health.status, StatusCode::OK);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn protected_routes_need_a_token() -> anyhow::Result<()> {
+    let app = TestApp::spawn().await?;
+
+    let me = app.get("/api/v1/users/me", None).await;
+    assert_eq!(me.status, StatusCode::UNAUTHORIZED);
+
+    let me = app.get("/api/v1/users/me", Some("not-a-token")).await;
+    assert_eq!(me.status, StatusCode::UNAUTHORIZED);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn registered_user_reads_own_profile() -> anyhow::Result<()> {
+    let app = TestApp::spawn().await?;
+    let alice = app.create_user("alice").await?;
+
+    let me = app.get("/api/v1/users/me", Some(&alice.access_token)).await;
+    assert_eq!(me.status, StatusCode::OK);
+    assert_eq!(me.body["id"], json!(alice.id));
+    assert_eq!(me.body["username"], "alice");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn direct_message_reaches_the_other_participant() -> anyhow::Result<()> {
+    let app = TestApp::spawn().await?;
+    let alice = app.create_user("alice").await?;
+    let bob = app.create_user("bob").await?;
+
+    let conversation = app
+        .post(
+            "/api/v1/conversations/direct",
+            Some(&alice.access_token),
+            json!({ "user_id": bob.id }),
+        )
+        .await;
+    assert_eq!(conversation.status, StatusCode::OK);
+    let conversation_id = conversation.body["id"]
+        .as_str()
+        .expect("conversation id")
+        .to_string();
+
+    let sent = app
+        .post(
+            &format!("/api/v1/conversations/{}/messages", conversation_id),
+            Some(&alice.access_token),
+            json!({ "type": "text", "payload": { "v": 1, "body": "hello bob" } }),
+        )
+        .await;
+    assert_eq!(sent.status, StatusCode::OK, "{}", sent.body);
+
+    let messages = app
+        .get(
+            &format!("/api/v1/conversations/{}/messages", conversation_id),
+            Some(&bob.access_token),
+        )
+        .await;
+    assert_eq!(messages.status, StatusCode::OK);
+    let items = messages.body["items"].as_array().expect("message page");
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["payload"]["body"], "hello bob");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn outsiders_cannot_read_a_conversation() -> anyhow::Result<()> {
+    let app = TestApp::spawn().await?;
+    let alice = app.create_user("alice").await?;
+    let bob = app.create_user("bob").await?;
+    let eve = app.create_user("eve").await?;
+
+    let conversation = app
+        .post(
+            "/api/v1/conversations/direct",
+            Some(&alice.access_token),
+            json!({ "user_id": bob.id }),
+        )
+        .await;
+    let conversation_id = conversation.body["id"].as_str().expect("conversation id");
+
+    let messages = app
+        .get(
+            &format!("/api/v1/conversations/{}/messages", conversation_id),
+            Some(&eve.access_token),
+        )
+        .await;
+    assert!(messages.status.is_client_error(), "{}", messages.status);
+
+    Ok(())
+}
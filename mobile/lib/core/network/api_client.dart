@@ -1,6 +1,8 @@
 import 'package:dio/dio.dart';
+import 'package:flutter/foundation.dart';
 import 'package:flutter_riverpod/flutter_riverpod.dart';
 
+import '../../shared/models/message.dart';
 import '../storage/secure_storage.dart';
 
 class ApiClient {
@@ -9,6 +11,9 @@ class ApiClient {
 
   static const String baseUrl = 'http://localhost:8080/api/v1';
 
+  /// Sent as `X-Client-Version`; keep in step with `version` in pubspec.yaml
+  static const String clientVersion = '1.0.0';
+
   ApiClient(this._storage) {
     _dio = Dio(BaseOptions(
       baseUrl: baseUrl,
@@ -16,6 +21,8 @@ class ApiClient {
       receiveTimeout: const Duration(seconds: 30),
       headers: {
         'Content-Type': 'application/json',
+        'X-Client-Platform': _clientPlatform(),
+        'X-Client-Version': clientVersion,
       },
     ));
 
@@ -44,6 +51,19 @@ class ApiClient {
     ));
   }
 
+  /// The platform name the server checks minimum versions against
+  static String _clientPlatform() {
+    if (kIsWeb) return 'web';
+    switch (defaultTargetPlatform) {
+      case TargetPlatform.iOS:
+        return 'ios';
+      case TargetPlatform.android:
+        return 'android';
+      default:
+        return 'desktop';
+    }
+  }
+
   Future<bool> _refreshToken() async {
     try {
       final refreshToken = await _storage.getRefreshToken();
@@ -105,12 +125,14 @@ class ApiClient {
     required String type,
     required String deviceName,
     required String platform,
+    String? deviceToken,
   }) async {
     return _dio.post('/auth/login', data: {
       'target': target,
       'type': type,
       'device_name': deviceName,
       'platform': platform,
+      if (deviceToken != null) 'device_token': deviceToken,
     });
   }
 
@@ -135,9 +157,15 @@ class ApiClient {
   }
 
   // Contact endpoints
-  Future<Response> getContacts({bool includeBlocked = false}) async {
+  Future<Response> getContacts({
+    bool includeBlocked = false,
+    int limit = 100,
+    String? cursor,
+  }) async {
     return _dio.get('/contacts', queryParameters: {
       'include_blocked': includeBlocked,
+      'limit': limit,
+      if (cursor != null) 'cursor': cursor,
     });
   }
 
@@ -179,10 +207,10 @@ class ApiClient {
   }
 
   // Conversation endpoints
-  Future<Response> getConversations({int limit = 20, int offset = 0}) async {
+  Future<Response> getConversations({int limit = 20, String? cursor}) async {
     return _dio.get('/conversations', queryParameters: {
       'limit': limit,
-      'offset': offset,
+      if (cursor != null) 'cursor': cursor,
     });
   }
 
@@ -206,24 +234,24 @@ class ApiClient {
   Future<Response> getMessages(
     String conversationId, {
     int limit = 50,
-    int offset = 0,
+    String? cursor,
   }) async {
     return _dio.get('/conversations/$conversationId/messages', queryParameters: {
       'limit': limit,
-      'offset': offset,
+      if (cursor != null) 'cursor': cursor,
     });
   }
 
   Future<Response> sendMessage(
     String conversationId, {
     required String type,
-    required List<int> content,
+    required MessagePayload payload,
     String? stickerId,
     String? replyToId,
   }) async {
     return _dio.post('/conversations/$conversationId/messages', data: {
       'type': type,
-      'content': content,
+      'payload': payload.toJson()..removeWhere((_, value) => value == null),
       if (stickerId != null) 'sticker_id': stickerId,
       if (replyToId != null) 'reply_to_id': replyToId,
     });
@@ -275,10 +303,10 @@ class ApiClient {
   }
 
   // Sticker endpoints
-  Future<Response> getStickerCatalog({int limit = 20, int offset = 0, bool? official}) async {
+  Future<Response> getStickerCatalog({int limit = 20, String? cursor, bool? official}) async {
     return _dio.get('/stickers/catalog', queryParameters: {
       'limit': limit,
-      'offset': offset,
+      if (cursor != null) 'cursor': cursor,
       if (official != null) 'official': official,
     });
   }
@@ -8,6 +8,7 @@ class SecureStorage {
   static const _refreshTokenKey = 'refresh_token';
   static const _userIdKey = 'user_id';
   static const _deviceIdKey = 'device_id';
+  static const _deviceTokenKey = 'device_token';
   static const _signalIdentityKeyKey = 'signal_identity_key';
   static const _signalRegistrationIdKey = 'signal_registration_id';
   static const _signalKeysInitializedKey = 'signal_keys_initialized';
@@ -60,6 +61,16 @@ class SecureStorage {
     return _storage.read(key: _deviceIdKey);
   }
 
+  // Issued when the server creates this device; sent on login to sign the
+  // same device back in
+  Future<void> saveDeviceToken(String deviceToken) async {
+    await _storage.write(key: _deviceTokenKey, value: deviceToken);
+  }
+
+  Future<String?> getDeviceToken() async {
+    return _storage.read(key: _deviceTokenKey);
+  }
+
   // Signal protocol keys
   Future<void> saveSignalIdentityKey(List<int> privateKey, List<int> publicKey) async {
     final data = jsonEncode({
@@ -133,6 +133,10 @@ class AuthNotifier extends StateNotifier<AuthState> {
       // Save tokens
       await _storage.saveTokens(tokens.accessToken, tokens.refreshToken);
       await _storage.saveUserId(user.id);
+      final deviceToken = response.data['tokens']['device_token'];
+      if (deviceToken != null) {
+        await _storage.saveDeviceToken(deviceToken);
+      }
 
       // Initialize Signal keys
       await _signalClient.initializeKeys();
@@ -163,6 +167,7 @@ class AuthNotifier extends StateNotifier<AuthState> {
         type: type,
         deviceName: _getDeviceName(),
         platform: _getPlatform(),
+        deviceToken: await _storage.getDeviceToken(),
       );
 
       final user = User.fromJson(response.data['user']);
@@ -171,6 +176,10 @@ class AuthNotifier extends StateNotifier<AuthState> {
       // Save tokens
       await _storage.saveTokens(tokens.accessToken, tokens.refreshToken);
       await _storage.saveUserId(user.id);
+      final deviceToken = response.data['tokens']['device_token'];
+      if (deviceToken != null) {
+        await _storage.saveDeviceToken(deviceToken);
+      }
 
       // Initialize Signal keys if needed
       await _signalClient.initializeKeys();
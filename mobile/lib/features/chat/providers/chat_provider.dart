@@ -1,5 +1,3 @@
-import 'dart:convert';
-
 import 'package:flutter_riverpod/flutter_riverpod.dart';
 
 import '../../../core/network/api_client.dart';
@@ -88,7 +86,7 @@ class ConversationsNotifier extends StateNotifier<ConversationsState> {
 
     try {
       final response = await _apiClient.getConversations();
-      final conversations = (response.data as List)
+      final conversations = (response.data['items'] as List)
           .map((json) => Conversation.fromJson(json))
           .toList();
 
@@ -144,12 +142,14 @@ class MessagesState {
   final List<Message> messages;
   final bool isLoading;
   final bool hasMore;
+  final String? nextCursor;
   final String? error;
 
   const MessagesState({
     this.messages = const [],
     this.isLoading = false,
     this.hasMore = true,
+    this.nextCursor,
     this.error,
   });
 
@@ -157,12 +157,14 @@ class MessagesState {
     List<Message>? messages,
     bool? isLoading,
     bool? hasMore,
+    String? nextCursor,
     String? error,
   }) {
     return MessagesState(
       messages: messages ?? this.messages,
       isLoading: isLoading ?? this.isLoading,
       hasMore: hasMore ?? this.hasMore,
+      nextCursor: nextCursor ?? this.nextCursor,
       error: error,
     );
   }
@@ -220,14 +222,14 @@ class MessagesNotifier extends StateNotifier<MessagesState> {
     state = state.copyWith(isLoading: true, error: null);
 
     try {
-      final offset = loadMore ? state.messages.length : 0;
       final response = await _apiClient.getMessages(
         conversationId,
         limit: 50,
-        offset: offset,
+        cursor: loadMore ? state.nextCursor : null,
       );
+      final nextCursor = response.data['next_cursor'] as String?;
 
-      var messages = (response.data as List)
+      var messages = (response.data['items'] as List)
           .map((json) => Message.fromJson(json))
           .toList();
 
@@ -239,7 +241,8 @@ class MessagesNotifier extends StateNotifier<MessagesState> {
       state = state.copyWith(
         messages: loadMore ? [...state.messages, ...messages] : messages,
         isLoading: false,
-        hasMore: messages.length >= 50,
+        hasMore: nextCursor != null,
+        nextCursor: nextCursor,
       );
     } catch (e) {
       state = state.copyWith(
@@ -259,7 +262,7 @@ class MessagesNotifier extends StateNotifier<MessagesState> {
       conversationId: conversationId,
       senderId: currentUser!.id,
       type: MessageType.text,
-      content: utf8.encode(text),
+      payload: MessagePayload(body: text),
       replyToId: replyToId,
       status: MessageStatus.sending,
       createdAt: DateTime.now(),
@@ -271,14 +274,11 @@ class MessagesNotifier extends StateNotifier<MessagesState> {
     state = state.copyWith(messages: [localMessage, ...state.messages]);
 
     try {
-      // Encrypt message (for now, just encode as we'd need the recipient's keys)
-      final encryptedContent = utf8.encode(text);
-
-      // Send to server
+      // Send to server (as plaintext for now, as we'd need the recipient's keys)
       final response = await _apiClient.sendMessage(
         conversationId,
         type: 'text',
-        content: encryptedContent,
+        payload: localMessage.payload,
         replyToId: replyToId,
       );
 
@@ -293,7 +293,7 @@ class MessagesNotifier extends StateNotifier<MessagesState> {
               conversationId: sentMessage.conversationId,
               senderId: sentMessage.senderId,
               type: sentMessage.type,
-              content: sentMessage.content,
+              payload: sentMessage.payload,
               replyToId: sentMessage.replyToId,
               status: MessageStatus.sent,
               createdAt: sentMessage.createdAt,
@@ -314,7 +314,7 @@ class MessagesNotifier extends StateNotifier<MessagesState> {
               conversationId: m.conversationId,
               senderId: m.senderId,
               type: m.type,
-              content: m.content,
+              payload: m.payload,
               replyToId: m.replyToId,
               status: MessageStatus.failed,
               createdAt: m.createdAt,
@@ -335,7 +335,7 @@ class MessagesNotifier extends StateNotifier<MessagesState> {
       final response = await _apiClient.sendMessage(
         conversationId,
         type: 'sticker',
-        content: [],
+        payload: const MessagePayload(),
         stickerId: stickerId,
       );
 
@@ -347,7 +347,7 @@ class MessagesNotifier extends StateNotifier<MessagesState> {
             conversationId: sentMessage.conversationId,
             senderId: sentMessage.senderId,
             type: sentMessage.type,
-            content: sentMessage.content,
+            payload: sentMessage.payload,
             stickerId: sentMessage.stickerId,
             status: MessageStatus.sent,
             createdAt: sentMessage.createdAt,
@@ -371,29 +371,9 @@ class MessagesNotifier extends StateNotifier<MessagesState> {
   }
 
   Future<Message> _decryptMessage(Message message) async {
-    // For now, just decode the content
-    // In production, use Signal protocol decryption
-    try {
-      final decryptedText = utf8.decode(message.content);
-      return Message(
-        id: message.id,
-        conversationId: message.conversationId,
-        senderId: message.senderId,
-        type: message.type,
-        content: utf8.encode(decryptedText),
-        stickerId: message.stickerId,
-        replyToId: message.replyToId,
-        status: message.status,
-        createdAt: message.createdAt,
-        updatedAt: message.updatedAt,
-        deletedAt: message.deletedAt,
-        sender: message.sender,
-        replyTo: message.replyTo,
-        receipts: message.receipts,
-      );
-    } catch (e) {
-      return message;
-    }
+    // Plaintext bodies need no decryption; ciphertext shows as undecryptable
+    // until Signal protocol decryption is wired in
+    return message;
   }
 }
 
@@ -1,5 +1,3 @@
-import 'dart:convert';
-
 import 'package:flutter/material.dart';
 import 'package:flutter_riverpod/flutter_riverpod.dart';
 import 'package:cached_network_image/cached_network_image.dart';
@@ -292,12 +290,7 @@ class _ChatScreenState extends ConsumerState<ChatScreen> {
         errorWidget: (_, __, ___) => const Icon(Icons.broken_image),
       );
     } else {
-      String text;
-      try {
-        text = utf8.decode(message.content);
-      } catch (e) {
-        text = 'Unable to decrypt message';
-      }
+      final text = message.payload.body ?? 'Unable to decrypt message';
 
       content = Container(
         padding: const EdgeInsets.symmetric(horizontal: 12, vertical: 8),
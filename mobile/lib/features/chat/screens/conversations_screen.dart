@@ -1,5 +1,3 @@
-import 'dart:convert';
-
 import 'package:flutter/material.dart';
 import 'package:flutter_riverpod/flutter_riverpod.dart';
 import 'package:go_router/go_router.dart';
@@ -135,11 +133,7 @@ class ConversationsScreen extends ConsumerWidget {
     if (conversation.lastMessage != null) {
       final msg = conversation.lastMessage!;
       if (msg.type == MessageType.text) {
-        try {
-          lastMessageText = utf8.decode(msg.content);
-        } catch (e) {
-          lastMessageText = 'Message';
-        }
+        lastMessageText = msg.payload.body ?? 'Message';
       } else if (msg.type == MessageType.sticker) {
         lastMessageText = 'Sticker';
       } else if (msg.type == MessageType.image) {
@@ -44,10 +44,18 @@ class ContactsNotifier extends StateNotifier<ContactsState> {
     state = state.copyWith(isLoading: true, error: null);
 
     try {
-      final response = await _apiClient.getContacts(includeBlocked: includeBlocked);
-      final contacts = (response.data as List)
-          .map((json) => Contact.fromJson(json))
-          .toList();
+      // The list is paginated; the contacts screen shows all of them
+      final contacts = <Contact>[];
+      String? cursor;
+      do {
+        final response = await _apiClient.getContacts(
+          includeBlocked: includeBlocked,
+          cursor: cursor,
+        );
+        contacts.addAll((response.data['items'] as List)
+            .map((json) => Contact.fromJson(json)));
+        cursor = response.data['next_cursor'] as String?;
+      } while (cursor != null);
 
       state = state.copyWith(contacts: contacts, isLoading: false);
     } catch (e) {
@@ -96,12 +96,14 @@ class StickerCatalogState {
   final List<StickerPack> packs;
   final bool isLoading;
   final bool hasMore;
+  final String? nextCursor;
   final String? error;
 
   const StickerCatalogState({
     this.packs = const [],
     this.isLoading = false,
     this.hasMore = true,
+    this.nextCursor,
     this.error,
   });
 
@@ -109,12 +111,14 @@ class StickerCatalogState {
     List<StickerPack>? packs,
     bool? isLoading,
     bool? hasMore,
+    String? nextCursor,
     String? error,
   }) {
     return StickerCatalogState(
       packs: packs ?? this.packs,
       isLoading: isLoading ?? this.isLoading,
       hasMore: hasMore ?? this.hasMore,
+      nextCursor: nextCursor ?? this.nextCursor,
       error: error,
     );
   }
@@ -134,20 +138,21 @@ class StickerCatalogNotifier extends StateNotifier<StickerCatalogState> {
     state = state.copyWith(isLoading: true, error: null);
 
     try {
-      final offset = loadMore ? state.packs.length : 0;
       final response = await _apiClient.getStickerCatalog(
         limit: 20,
-        offset: offset,
+        cursor: loadMore ? state.nextCursor : null,
       );
+      final nextCursor = response.data['next_cursor'] as String?;
 
-      final packs = (response.data as List)
+      final packs = (response.data['items'] as List)
           .map((json) => StickerPack.fromJson(json))
           .toList();
 
       state = state.copyWith(
         packs: loadMore ? [...state.packs, ...packs] : packs,
         isLoading: false,
-        hasMore: packs.length >= 20,
+        hasMore: nextCursor != null,
+        nextCursor: nextCursor,
       );
     } catch (e) {
       state = state.copyWith(
@@ -53,7 +53,7 @@ class Message with _$Message {
     required String conversationId,
     required String senderId,
     required MessageType type,
-    required List<int> content, // Encrypted content
+    @Default(MessagePayload()) MessagePayload payload,
     String? stickerId,
     String? replyToId,
     @Default(MessageStatus.sending) MessageStatus status,
@@ -68,6 +68,23 @@ class Message with _$Message {
   factory Message.fromJson(Map<String, dynamic> json) => _$MessageFromJson(json);
 }
 
+// Typed message body; see the server's message payload format
+@freezed
+class MessagePayload with _$MessagePayload {
+  const factory MessagePayload({
+    @Default(1) int v,
+    String? body, // Plaintext text message
+    String? caption,
+    String? attachmentId,
+    String? ciphertext, // Base64 Signal message
+    String? media, // Base64 inline media
+    Map<String, dynamic>? event, // System message event
+  }) = _MessagePayload;
+
+  factory MessagePayload.fromJson(Map<String, dynamic> json) =>
+      _$MessagePayloadFromJson(json);
+}
+
 @freezed
 class Receipt with _$Receipt {
   const factory Receipt({